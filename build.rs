@@ -0,0 +1,64 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Provides the dex of the Java support classes for the `android-embedded-dex` feature.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The environment variable pointing to a prebuilt dex.
+const DEX_ENV: &str = "BTLEPLUG_DEX";
+
+/// Where `./gradlew embeddedDex` puts the dex, relative to the crate root.
+const IN_TREE_DEX: &str = "src/droidplug/java/btleplug.dex";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_ANDROID_EMBEDDED_DEX").is_none()
+        || env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("android")
+    {
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed={}", DEX_ENV);
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let in_tree = manifest_dir.join(IN_TREE_DEX);
+    println!("cargo:rerun-if-changed={}", in_tree.display());
+    let dex = match env::var_os(DEX_ENV) {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            if !path.is_file() {
+                fail(&format!(
+                    "{} is set to {}, which is not a file.",
+                    DEX_ENV,
+                    path.display()
+                ));
+            }
+            path
+        }
+        None if in_tree.is_file() => in_tree,
+        None => fail(&format!(
+            "{} is not set, and there is no {} in the crate.",
+            DEX_ENV, IN_TREE_DEX
+        )),
+    };
+
+    let out = Path::new(&env::var_os("OUT_DIR").unwrap()).join("btleplug.dex");
+    if let Err(e) = std::fs::copy(&dex, &out) {
+        fail(&format!("Failed to copy {}: {}", dex.display(), e));
+    }
+}
+
+fn fail(reason: &str) -> ! {
+    panic!(
+        "The android-embedded-dex feature needs the dex of btleplug's Java classes. {}\n\
+         Either set {} to the path of a prebuilt btleplug.dex, or build it with \
+         `./gradlew embeddedDex` in src/droidplug/java of a btleplug checkout (which needs the \
+         Android SDK and jni-utils) and point {} at the result.",
+        reason, DEX_ENV, DEX_ENV
+    );
+}
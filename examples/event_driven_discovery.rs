@@ -42,7 +42,7 @@ async fn main() -> anyhow::Result<()> {
                 let peripheral = central.peripheral(&id).await?;
                 let properties = peripheral.properties().await?;
                 let name = properties
-                    .and_then(|p| p.local_name)
+                    .and_then(|p| p.local_name.clone())
                     .map(|local_name| format!("Name: {local_name}"))
                     .unwrap_or_default();
                 println!("DeviceDiscovered: {:?} {}", id, name);
@@ -42,6 +42,7 @@ async fn main() -> anyhow::Result<()> {
                 let local_name = properties
                     .unwrap()
                     .local_name
+                    .clone()
                     .unwrap_or(String::from("(peripheral name unknown)"));
                 println!(
                     "Peripheral {:?} is connected: {:?}",
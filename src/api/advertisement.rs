@@ -0,0 +1,597 @@
+//! Parsing of raw advertising data into AD structures.
+//!
+//! Most platforms hand btleplug advertisements which were already parsed by the OS, but some
+//! (currently Android) only provide the raw bytes. The parser here splits those bytes into
+//! [`AdStructure`]s and reports any malformed structure as an [`AdParseError`] carrying its byte
+//! offset.
+
+use super::bleuuid::{uuid_from_u16, uuid_from_u32};
+use super::BDAddr;
+use bitflags::bitflags;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// AD type of Flags.
+pub const FLAGS: u8 = 0x01;
+/// AD type of an incomplete list of 16-bit service UUIDs.
+pub const INCOMPLETE_LIST_16BIT_SERVICE_UUIDS: u8 = 0x02;
+/// AD type of a complete list of 16-bit service UUIDs.
+pub const COMPLETE_LIST_16BIT_SERVICE_UUIDS: u8 = 0x03;
+/// AD type of an incomplete list of 32-bit service UUIDs.
+pub const INCOMPLETE_LIST_32BIT_SERVICE_UUIDS: u8 = 0x04;
+/// AD type of a complete list of 32-bit service UUIDs.
+pub const COMPLETE_LIST_32BIT_SERVICE_UUIDS: u8 = 0x05;
+/// AD type of an incomplete list of 128-bit service UUIDs.
+pub const INCOMPLETE_LIST_128BIT_SERVICE_UUIDS: u8 = 0x06;
+/// AD type of a complete list of 128-bit service UUIDs.
+pub const COMPLETE_LIST_128BIT_SERVICE_UUIDS: u8 = 0x07;
+/// AD type of Shortened Local Name.
+pub const SHORTENED_LOCAL_NAME: u8 = 0x08;
+/// AD type of Complete Local Name.
+pub const COMPLETE_LOCAL_NAME: u8 = 0x09;
+/// AD type of TX Power Level.
+pub const TX_POWER_LEVEL: u8 = 0x0A;
+/// AD type of Service Data with a 16-bit UUID.
+pub const SERVICE_DATA_16BIT_UUID: u8 = 0x16;
+/// AD type of Public Target Address.
+pub const PUBLIC_TARGET_ADDRESS: u8 = 0x17;
+/// AD type of Appearance.
+pub const APPEARANCE: u8 = 0x19;
+/// AD type of Advertising Interval.
+pub const ADVERTISING_INTERVAL: u8 = 0x1A;
+/// AD type of Service Data with a 32-bit UUID.
+pub const SERVICE_DATA_32BIT_UUID: u8 = 0x20;
+/// AD type of Service Data with a 128-bit UUID.
+pub const SERVICE_DATA_128BIT_UUID: u8 = 0x21;
+/// AD type of URI.
+pub const URI: u8 = 0x24;
+/// AD type of LE Supported Features.
+pub const LE_SUPPORTED_FEATURES: u8 = 0x27;
+/// AD type of Advertising Interval - long.
+pub const ADVERTISING_INTERVAL_LONG: u8 = 0x2F;
+
+/// The unit of advertising intervals, 0.625ms.
+const ADVERTISING_INTERVAL_UNIT: Duration = Duration::from_micros(625);
+/// AD type of Manufacturer Specific Data.
+pub const MANUFACTURER_SPECIFIC_DATA: u8 = 0xFF;
+
+/// How malformed advertising data is handled. See
+/// [`Central::set_advertisement_parse_mode`](crate::api::Central::set_advertisement_parse_mode).
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ParseMode {
+    /// Malformed AD structures are silently skipped.
+    #[default]
+    Lenient,
+    /// Malformed AD structures are skipped, and reported through
+    /// [`CentralEvent::MalformedAdvertisement`](crate::api::CentralEvent::MalformedAdvertisement).
+    Strict,
+}
+
+bitflags! {
+    /// The Flags AD structure, which tells how a device is discoverable and which transports it
+    /// supports.
+    #[cfg_attr(
+        feature = "serde",
+        derive(Serialize, Deserialize),
+        serde(crate = "serde_cr")
+    )]
+    #[derive(Default, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+    pub struct AdFlags: u8 {
+        const LE_LIMITED_DISCOVERABLE = 1 << 0;
+        const LE_GENERAL_DISCOVERABLE = 1 << 1;
+        const BR_EDR_NOT_SUPPORTED = 1 << 2;
+        const SIMULTANEOUS_LE_BR_EDR_CONTROLLER = 1 << 3;
+        const _ = !0;
+    }
+}
+
+/// A single AD structure: a length-prefixed AD type and its data.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdStructure {
+    /// The AD type, as assigned in the Bluetooth Assigned Numbers document.
+    pub ad_type: u8,
+    /// The data following the AD type.
+    pub data: Vec<u8>,
+}
+
+/// The fields btleplug reports from advertising data, extracted by [`parse_advertisement`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ParsedAdvertisement {
+    /// The flags, including bits which aren't assigned yet.
+    pub flags: Option<AdFlags>,
+    /// The complete local name, or the shortened one if only that was advertised.
+    pub local_name: Option<String>,
+    pub tx_power_level: Option<i16>,
+    /// Manufacturer specific data keyed by company identifier. Multiple structures for the same
+    /// company are concatenated.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// The advertised service UUIDs, from both complete and incomplete lists.
+    pub services: Vec<Uuid>,
+    pub appearance: Option<u16>,
+    /// The URI, with its scheme expanded. URIs with a scheme btleplug doesn't know, or which aren't
+    /// valid UTF-8, are skipped.
+    pub uri: Option<String>,
+    /// The LE supported features bit mask, in little endian byte order.
+    pub le_supported_features: Option<Vec<u8>>,
+    /// The advertising interval, from either the short or the long form of the structure.
+    pub advertising_interval: Option<Duration>,
+    /// The public target addresses, from all Public Target Address structures.
+    pub public_target_addresses: Vec<BDAddr>,
+    /// The malformed structures which were skipped, in the order they appear in the data.
+    pub errors: Vec<AdParseError>,
+}
+
+/// A malformed AD structure found in advertising data.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("{kind} at offset {offset}")]
+pub struct AdParseError {
+    /// Offset of the length byte of the offending AD structure within the advertising data.
+    pub offset: usize,
+    /// What is wrong with the structure.
+    pub kind: AdParseErrorKind,
+}
+
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum AdParseErrorKind {
+    /// The length byte claims more data than is left in the advertisement.
+    #[error("AD structure of length {length} overruns the {remaining} remaining bytes")]
+    Truncated { length: usize, remaining: usize },
+    /// The structure is too short to hold the fields required by its AD type.
+    #[error(
+        "AD structure of type {ad_type:#04x} has {length} data bytes, expected at least {minimum}"
+    )]
+    TooShort {
+        ad_type: u8,
+        length: usize,
+        minimum: usize,
+    },
+}
+
+/// Splits advertising data into AD structures.
+///
+/// Parsing stops at the first zero length byte, which marks the start of padding. Structures which
+/// run past the end of `data` are dropped, along with everything after them, and reported as
+/// errors.
+pub fn parse(data: &[u8]) -> (Vec<AdStructure>, Vec<AdParseError>) {
+    let mut structures = Vec::new();
+    let mut errors = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let length = data[offset] as usize;
+        if length == 0 {
+            break;
+        }
+        let remaining = data.len() - offset - 1;
+        if length > remaining {
+            errors.push(AdParseError {
+                offset,
+                kind: AdParseErrorKind::Truncated { length, remaining },
+            });
+            break;
+        }
+        structures.push(AdStructure {
+            ad_type: data[offset + 1],
+            data: data[offset + 2..offset + 1 + length].to_vec(),
+        });
+        offset += length + 1;
+    }
+    (structures, errors)
+}
+
+/// Lays out AD structures back to back, as they are sent over the air. Structures with more than
+/// 254 bytes of data can't be encoded, and are cut short.
+pub fn encode<'a>(structures: impl IntoIterator<Item = &'a AdStructure>) -> Vec<u8> {
+    let mut data = Vec::new();
+    for structure in structures {
+        let length = structure.data.len().min(254);
+        data.push(length as u8 + 1);
+        data.push(structure.ad_type);
+        data.extend_from_slice(&structure.data[..length]);
+    }
+    data
+}
+
+/// Extracts everything btleplug reports from advertising data in a single pass.
+pub fn parse_advertisement(data: &[u8]) -> ParsedAdvertisement {
+    let (structures, errors) = parse(data);
+    let mut advertisement = parse_structures(structures);
+    advertisement.errors.extend(errors);
+    advertisement.errors.sort_by_key(|error| error.offset);
+    advertisement
+}
+
+/// Extracts everything btleplug reports from AD structures which were already split up, e.g. by
+/// the platform. The offsets of errors assume the structures were laid out back to back.
+pub fn parse_structures(structures: impl IntoIterator<Item = AdStructure>) -> ParsedAdvertisement {
+    let mut advertisement = ParsedAdvertisement::default();
+    let mut offset = 0;
+    for structure in structures {
+        let structure_offset = offset;
+        offset += structure.data.len() + 2;
+        let minimum = match structure.ad_type {
+            FLAGS | TX_POWER_LEVEL | URI | LE_SUPPORTED_FEATURES => 1,
+            APPEARANCE
+            | MANUFACTURER_SPECIFIC_DATA
+            | SERVICE_DATA_16BIT_UUID
+            | ADVERTISING_INTERVAL => 2,
+            ADVERTISING_INTERVAL_LONG => 3,
+            SERVICE_DATA_32BIT_UUID => 4,
+            PUBLIC_TARGET_ADDRESS => 6,
+            SERVICE_DATA_128BIT_UUID => 16,
+            _ => 0,
+        };
+        if structure.data.len() < minimum {
+            advertisement.errors.push(AdParseError {
+                offset: structure_offset,
+                kind: AdParseErrorKind::TooShort {
+                    ad_type: structure.ad_type,
+                    length: structure.data.len(),
+                    minimum,
+                },
+            });
+            continue;
+        }
+        let data = structure.data;
+        match structure.ad_type {
+            FLAGS => advertisement.flags = Some(AdFlags::from_bits_retain(data[0])),
+            INCOMPLETE_LIST_16BIT_SERVICE_UUIDS | COMPLETE_LIST_16BIT_SERVICE_UUIDS => {
+                advertisement.services.extend(
+                    data.chunks_exact(2)
+                        .map(|uuid| uuid_from_u16(u16::from_le_bytes([uuid[0], uuid[1]]))),
+                );
+            }
+            INCOMPLETE_LIST_32BIT_SERVICE_UUIDS | COMPLETE_LIST_32BIT_SERVICE_UUIDS => {
+                advertisement
+                    .services
+                    .extend(data.chunks_exact(4).map(|uuid| {
+                        uuid_from_u32(u32::from_le_bytes([uuid[0], uuid[1], uuid[2], uuid[3]]))
+                    }));
+            }
+            INCOMPLETE_LIST_128BIT_SERVICE_UUIDS | COMPLETE_LIST_128BIT_SERVICE_UUIDS => {
+                advertisement
+                    .services
+                    .extend(data.chunks_exact(16).map(uuid_from_le_slice));
+            }
+            SHORTENED_LOCAL_NAME if advertisement.local_name.is_some() => {}
+            SHORTENED_LOCAL_NAME | COMPLETE_LOCAL_NAME => {
+                advertisement.local_name = Some(local_name(&data));
+            }
+            TX_POWER_LEVEL => advertisement.tx_power_level = Some(i16::from(data[0] as i8)),
+            APPEARANCE => advertisement.appearance = Some(u16::from_le_bytes([data[0], data[1]])),
+            SERVICE_DATA_16BIT_UUID => {
+                let uuid = uuid_from_u16(u16::from_le_bytes([data[0], data[1]]));
+                advertisement.service_data.insert(uuid, data[2..].to_vec());
+            }
+            SERVICE_DATA_32BIT_UUID => {
+                let uuid = uuid_from_u32(u32::from_le_bytes([data[0], data[1], data[2], data[3]]));
+                advertisement.service_data.insert(uuid, data[4..].to_vec());
+            }
+            SERVICE_DATA_128BIT_UUID => {
+                let uuid = uuid_from_le_slice(&data[..16]);
+                advertisement.service_data.insert(uuid, data[16..].to_vec());
+            }
+            MANUFACTURER_SPECIFIC_DATA => {
+                let company_id = u16::from_le_bytes([data[0], data[1]]);
+                advertisement
+                    .manufacturer_data
+                    .entry(company_id)
+                    .or_default()
+                    .extend_from_slice(&data[2..]);
+            }
+            URI => {
+                if let Some(uri) = uri(&data) {
+                    advertisement.uri = Some(uri);
+                }
+            }
+            LE_SUPPORTED_FEATURES => advertisement.le_supported_features = Some(data),
+            ADVERTISING_INTERVAL | ADVERTISING_INTERVAL_LONG => {
+                // The long form has either 3 or 4 bytes.
+                let mut units = [0; 4];
+                let length = data.len().min(4);
+                units[..length].copy_from_slice(&data[..length]);
+                advertisement.advertising_interval =
+                    Some(ADVERTISING_INTERVAL_UNIT * u32::from_le_bytes(units));
+            }
+            PUBLIC_TARGET_ADDRESS => {
+                advertisement.public_target_addresses.extend(
+                    data.chunks_exact(6)
+                        .map(|address| BDAddr::from_le_bytes(address.try_into().unwrap())),
+                );
+            }
+            _ => {}
+        }
+    }
+    advertisement.errors.sort_by_key(|error| error.offset);
+    advertisement
+}
+
+/// Decodes a URI structure, which starts with a code point standing for the scheme of the URI.
+/// Returns `None` if the scheme isn't known or the data isn't valid UTF-8.
+fn uri(data: &[u8]) -> Option<String> {
+    let uri = std::str::from_utf8(data).ok()?;
+    let mut chars = uri.chars();
+    let scheme = match chars.next()? {
+        // The URI includes its scheme.
+        '\u{01}' => "",
+        '\u{16}' => "http:",
+        '\u{17}' => "https:",
+        _ => return None,
+    };
+    Some(format!("{}{}", scheme, chars.as_str()))
+}
+
+/// Converts a 128-bit UUID in the little endian byte order used over the air.
+fn uuid_from_le_slice(bytes: &[u8]) -> Uuid {
+    let mut bytes: [u8; 16] = bytes.try_into().unwrap();
+    bytes.reverse();
+    Uuid::from_bytes(bytes)
+}
+
+/// Decodes a local name. Names aren't always valid UTF-8, and some devices pad them with NULs.
+pub(crate) fn local_name(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .chars()
+        .filter(|&c| c != '\u{fffd}' && c != '\0')
+        .collect()
+}
+
+/// Extracts manufacturer specific data from advertising data, keyed by company identifier.
+/// Multiple structures for the same company are concatenated.
+pub fn parse_manufacturer_data(data: &[u8]) -> (HashMap<u16, Vec<u8>>, Vec<AdParseError>) {
+    let (structures, mut errors) = parse(data);
+    let mut manufacturer_data: HashMap<u16, Vec<u8>> = HashMap::new();
+    let mut offset = 0;
+    for structure in structures {
+        let structure_offset = offset;
+        offset += structure.data.len() + 2;
+        if structure.ad_type != MANUFACTURER_SPECIFIC_DATA {
+            continue;
+        }
+        if structure.data.len() < 2 {
+            errors.push(AdParseError {
+                offset: structure_offset,
+                kind: AdParseErrorKind::TooShort {
+                    ad_type: structure.ad_type,
+                    length: structure.data.len(),
+                    minimum: 2,
+                },
+            });
+            continue;
+        }
+        let company_id = u16::from_le_bytes([structure.data[0], structure.data[1]]);
+        manufacturer_data
+            .entry(company_id)
+            .or_default()
+            .extend_from_slice(&structure.data[2..]);
+    }
+    errors.sort_by_key(|error| error.offset);
+    (manufacturer_data, errors)
+}
+
+/// Extracts the GAP appearance from advertising data, if present.
+pub fn parse_appearance(data: &[u8]) -> Option<u16> {
+    parse(data)
+        .0
+        .into_iter()
+        .find(|structure| structure.ad_type == APPEARANCE && structure.data.len() >= 2)
+        .map(|structure| u16::from_le_bytes([structure.data[0], structure.data[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_valid() {
+        let data = [0x02, 0x01, 0x06, 0x03, 0xFF, 0x4C, 0x00, 0x00, 0x00];
+        let (structures, errors) = parse(&data);
+        assert_eq!(
+            structures,
+            vec![
+                AdStructure {
+                    ad_type: 0x01,
+                    data: vec![0x06]
+                },
+                AdStructure {
+                    ad_type: 0xFF,
+                    data: vec![0x4C, 0x00]
+                },
+            ]
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let data = [0x02, 0x01, 0x06, 0x03, 0xFF, 0x4C, 0x00, 0x01, 0x08];
+        let (structures, errors) = parse(&data);
+        assert!(errors.is_empty());
+        assert_eq!(encode(&structures), data);
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let data = [0x02, 0x01, 0x06, 0x05, 0xFF, 0x4C];
+        let (structures, errors) = parse(&data);
+        assert_eq!(structures.len(), 1);
+        assert_eq!(
+            errors,
+            vec![AdParseError {
+                offset: 3,
+                kind: AdParseErrorKind::Truncated {
+                    length: 5,
+                    remaining: 2
+                }
+            }]
+        );
+    }
+
+    #[test]
+    fn manufacturer_data_too_short() {
+        let data = [0x02, 0xFF, 0x4C, 0x04, 0xFF, 0x59, 0x00, 0x01];
+        let (manufacturer_data, errors) = parse_manufacturer_data(&data);
+        assert_eq!(manufacturer_data.get(&0x0059), Some(&vec![0x01]));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].offset, 0);
+    }
+
+    #[test]
+    fn appearance() {
+        let data = [0x02, 0x01, 0x06, 0x03, 0x19, 0x41, 0x03];
+        assert_eq!(parse_appearance(&data), Some(0x0341));
+        assert_eq!(parse_appearance(&data[..3]), None);
+    }
+
+    #[test]
+    fn parse_full_advertisement() {
+        let data = [
+            0x02, 0x01, 0x06, // Flags
+            0x05, 0x03, 0x0F, 0x18, 0x0A, 0x18, // Complete list of 16-bit UUIDs
+            0x04, 0x08, b'b', b't', b'l', // Shortened local name
+            0x02, 0x0A, 0xF4, // TX power level: -12 dBm
+            0x05, 0x16, 0x0F, 0x18, 0x64, 0x00, // Service data for 0x180F
+            0x03, 0x19, 0x41, 0x03, // Appearance
+            0x04, 0xFF, 0x4C, 0x00, 0x01, // Manufacturer data
+        ];
+        let advertisement = parse_advertisement(&data);
+        assert_eq!(
+            advertisement.flags,
+            Some(AdFlags::LE_GENERAL_DISCOVERABLE | AdFlags::BR_EDR_NOT_SUPPORTED)
+        );
+        assert_eq!(
+            advertisement.services,
+            vec![uuid_from_u16(0x180F), uuid_from_u16(0x180A)]
+        );
+        assert_eq!(advertisement.local_name.as_deref(), Some("btl"));
+        assert_eq!(advertisement.tx_power_level, Some(-12));
+        assert_eq!(
+            advertisement.service_data.get(&uuid_from_u16(0x180F)),
+            Some(&vec![0x64, 0x00])
+        );
+        assert_eq!(advertisement.appearance, Some(0x0341));
+        assert_eq!(
+            advertisement.manufacturer_data.get(&0x004C),
+            Some(&vec![0x01])
+        );
+        assert!(advertisement.errors.is_empty());
+    }
+
+    #[test]
+    fn parse_advertisement_names_and_uuids() {
+        let mut data = vec![0x05, 0x09, b'a', b'b', 0x00, 0xFF];
+        // A shortened name doesn't replace the complete one.
+        data.extend_from_slice(&[0x02, 0x08, b'a']);
+        data.push(17);
+        data.push(COMPLETE_LIST_128BIT_SERVICE_UUIDS);
+        let uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+        data.extend(uuid.as_bytes().iter().rev());
+        let advertisement = parse_advertisement(&data);
+        assert_eq!(advertisement.local_name.as_deref(), Some("ab"));
+        assert_eq!(advertisement.services, vec![uuid]);
+    }
+
+    #[test]
+    fn parse_advertisement_other_types() {
+        let mut data = vec![0x0A, URI, 0x17];
+        data.extend_from_slice(b"//a.io/x");
+        data.extend_from_slice(&[
+            0x03, 0x27, 0x01, 0x01, // LE supported features
+            0x03, 0x1A, 0xA0, 0x00, // Advertising interval: 100ms
+            0x07, 0x17, 0x00, 0xFA, 0x34, 0x00, 0xCC, 0x2A, // Public target address
+        ]);
+        let advertisement = parse_advertisement(&data);
+        assert_eq!(advertisement.uri.as_deref(), Some("https://a.io/x"));
+        assert_eq!(advertisement.le_supported_features, Some(vec![0x01, 0x01]));
+        assert_eq!(
+            advertisement.advertising_interval,
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            advertisement.public_target_addresses,
+            vec![BDAddr::from([0x2A, 0xCC, 0x00, 0x34, 0xFA, 0x00])]
+        );
+        assert!(advertisement.errors.is_empty());
+
+        // The long form of the advertising interval, and a URI with an unknown scheme.
+        let advertisement = parse_advertisement(&[0x04, 0x2F, 0x00, 0x00, 0x01, 0x02, URI, 0x7F]);
+        assert_eq!(
+            advertisement.advertising_interval,
+            Some(Duration::from_micros(625 * 0x010000))
+        );
+        assert_eq!(advertisement.uri, None);
+    }
+
+    #[test]
+    fn parse_advertisement_errors() {
+        let data = [0x02, 0x16, 0x0F, 0x02, 0xFF, 0x4C, 0x05, 0x01];
+        let advertisement = parse_advertisement(&data);
+        assert!(advertisement.service_data.is_empty());
+        assert!(advertisement.manufacturer_data.is_empty());
+        assert_eq!(
+            advertisement
+                .errors
+                .iter()
+                .map(|error| error.offset)
+                .collect::<Vec<_>>(),
+            vec![0, 3, 6]
+        );
+    }
+
+    #[test]
+    fn parse_beacons() {
+        // An iBeacon, with major 1, minor 2 and a measured power of -59 dBm.
+        let ibeacon = [
+            0x02, 0x01, 0x1A, 0x1A, 0xFF, 0x4C, 0x00, 0x02, 0x15, 0xE2, 0xC5, 0x6D, 0xB5, 0xDF,
+            0xFB, 0x48, 0xD2, 0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10, 0x96, 0xE0, 0x00, 0x01, 0x00,
+            0x02, 0xC5,
+        ];
+        let advertisement = parse_advertisement(&ibeacon);
+        assert_eq!(advertisement.flags, Some(AdFlags::from_bits_retain(0x1A)));
+        let data = &advertisement.manufacturer_data[&0x004C];
+        assert_eq!(data.len(), 23);
+        assert_eq!(data[..2], [0x02, 0x15]);
+        assert_eq!(data[22], 0xC5);
+        assert!(advertisement.errors.is_empty());
+
+        // An Eddystone-URL frame for https://www.google.com/, padded like some beacons do.
+        let eddystone = [
+            0x02, 0x01, 0x06, 0x03, 0x03, 0xAA, 0xFE, 0x0D, 0x16, 0xAA, 0xFE, 0x10, 0xEB, 0x01,
+            b'g', b'o', b'o', b'g', b'l', b'e', 0x00, 0x00, 0x00,
+        ];
+        let advertisement = parse_advertisement(&eddystone);
+        let eddystone_uuid = uuid_from_u16(0xFEAA);
+        assert_eq!(advertisement.services, vec![eddystone_uuid]);
+        assert_eq!(
+            advertisement.service_data[&eddystone_uuid],
+            [0x10, 0xEB, 0x01, b'g', b'o', b'o', b'g', b'l', b'e', 0x00]
+        );
+        assert!(advertisement.errors.is_empty());
+    }
+}
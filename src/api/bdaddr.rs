@@ -76,6 +76,12 @@ impl From<[u8; 6]> for BDAddr {
     }
 }
 
+impl From<BDAddr> for [u8; 6] {
+    fn from(addr: BDAddr) -> Self {
+        addr.address
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for BDAddr {
     type Error = ParseBDAddrError;
 
@@ -133,6 +139,65 @@ impl BDAddr {
         self.address
     }
 
+    /// Build an address from bytes in display order, most significant byte first. This is the same
+    /// as `BDAddr::from(bytes)`.
+    pub const fn from_be_bytes(bytes: [u8; 6]) -> Self {
+        Self { address: bytes }
+    }
+
+    /// Build an address from bytes in the least significant byte first order used on the air and
+    /// in HCI packets.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use btleplug::api::BDAddr;
+    /// let addr = BDAddr::from_le_bytes([0x00, 0xFA, 0x34, 0x00, 0xCC, 0x2A]);
+    /// assert_eq!("2A:CC:00:34:FA:00", addr.to_string());
+    /// ```
+    pub const fn from_le_bytes(bytes: [u8; 6]) -> Self {
+        Self {
+            address: [bytes[5], bytes[4], bytes[3], bytes[2], bytes[1], bytes[0]],
+        }
+    }
+
+    /// Returns the address bytes most significant byte first. This is the same as
+    /// [`into_inner`](BDAddr::into_inner).
+    pub const fn to_be_bytes(self) -> [u8; 6] {
+        self.address
+    }
+
+    /// Returns the address bytes least significant byte first, as used on the air and in HCI
+    /// packets.
+    pub const fn to_le_bytes(self) -> [u8; 6] {
+        let a = self.address;
+        [a[5], a[4], a[3], a[2], a[1], a[0]]
+    }
+
+    /// Build an address from a `u64` holding its bytes in reverse, least significant byte first,
+    /// the way [`to_u64_le`](BDAddr::to_u64_le) returns it. The big endian counterpart is
+    /// `BDAddr::try_from(u64)`. Fails if any of the two most significant bytes are set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use btleplug::api::BDAddr;
+    /// let addr = BDAddr::from_u64_le(0x00_FA_34_00_CC_2A)?;
+    /// assert_eq!("2A:CC:00:34:FA:00", addr.to_string());
+    /// # Ok::<(), btleplug::api::ParseBDAddrError>(())
+    /// ```
+    pub fn from_u64_le(int: u64) -> Result<Self, ParseBDAddrError> {
+        Self::try_from(int).map(|addr| Self::from_le_bytes(addr.address))
+    }
+
+    /// Returns the address as a `u64` holding its bytes in reverse, least significant byte first,
+    /// so `1F:2A:00:CC:22:F1` becomes `0xF122CC002A1F`. The big endian counterpart is
+    /// `u64::from(addr)`.
+    pub const fn to_u64_le(self) -> u64 {
+        let a = self.address;
+        u64::from_be_bytes([0, 0, a[5], a[4], a[3], a[2], a[1], a[0]])
+    }
+
     /// Check if this address is a randomly generated.
     pub fn is_random_static(&self) -> bool {
         self.address[5] & 0b11 == 0b11
@@ -192,6 +257,17 @@ impl BDAddr {
             .expect("A String-Writer never fails");
         s
     }
+
+    /// Create a `String` with the address with no delimiters, using uppercase hex-digits.
+    pub fn to_string_no_delim_uppercase(&self) -> String {
+        self.to_string_no_delim().to_uppercase()
+    }
+
+    /// Create a `String` with the address with colons as delimiters, using lowercase hex-digits.
+    /// This is the same as `format!("{:x}", addr)`.
+    pub fn to_string_lowercase(&self) -> String {
+        format!("{:x}", self)
+    }
 }
 
 /// Different de-/serialization formats for [`BDAddr`].
@@ -377,6 +453,103 @@ pub mod serde {
         }
     }
 
+    /// De-/Serialization of [`BDAddr`] as a colon delimited string in human readable formats (like
+    /// JSON or TOML) and as an array of bytes in binary formats (like bincode).
+    ///
+    /// Deserialization accepts either representation in human readable formats.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use serde_cr as serde;
+    /// use btleplug::api::BDAddr;
+    /// use serde::{Serialize, Deserialize};
+    ///
+    /// #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+    /// # #[serde(crate = "serde_cr")]
+    /// struct S {
+    ///     #[serde(with = "btleplug::serde::bdaddr::human_readable")]
+    ///     addr: BDAddr,
+    /// }
+    ///
+    /// let s = S { addr: [0x00, 0xDE, 0xAD, 0xBE, 0xEF, 0x00].into() };
+    /// assert_eq!(serde_json::to_string(&s)?, r#"{"addr":"00:DE:AD:BE:EF:00"}"#);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub mod human_readable {
+        use super::*;
+
+        struct HumanReadableVisitor;
+
+        pub fn serialize<S>(addr: &BDAddr, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            if serializer.is_human_readable() {
+                colon_delim::serialize(addr, serializer)
+            } else {
+                bytes::serialize(addr, serializer)
+            }
+        }
+
+        pub fn deserialize<'de, D>(d: D) -> Result<BDAddr, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            if d.is_human_readable() {
+                d.deserialize_any(HumanReadableVisitor)
+            } else {
+                bytes::deserialize(d)
+            }
+        }
+
+        impl<'de> Visitor<'de> for HumanReadableVisitor {
+            type Value = BDAddr;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    formatter,
+                    "A Bluetooth address as a string, like `00:11:22:33:44:55`, or an array of 6 bytes"
+                )
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: DeError,
+            {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                <[u8; 6]>::deserialize(serde::de::value::SeqAccessDeserializer::new(seq))
+                    .map(BDAddr::from)
+            }
+        }
+    }
+
+    /// De-/Serialization of [`BDAddr`] as a string of lowercase hex-digits with colons as
+    /// delimiters, like `00:de:ad:be:ef:00`. Deserialization accepts either case.
+    pub mod lowercase {
+        use super::*;
+
+        pub fn serialize<S>(addr: &BDAddr, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.serialize_str(&addr.to_string_lowercase())
+        }
+
+        pub fn deserialize<'de, D>(d: D) -> Result<BDAddr, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            colon_delim::deserialize(d)
+        }
+    }
+
     /// De-/Serialization of [`BDAddr`] as an array of bytes.
     ///
     /// # Example
@@ -453,6 +626,21 @@ mod tests {
         assert_eq!(format!("{}", ADDR.to_string_no_delim()), "1f2a00cc22f1");
     }
 
+    #[test]
+    fn display_addr_variants() {
+        assert_eq!(ADDR.to_string_lowercase(), "1f:2a:00:cc:22:f1");
+        assert_eq!(ADDR.to_string_no_delim_uppercase(), "1F2A00CC22F1");
+    }
+
+    #[test]
+    fn addr_byte_order() {
+        assert_eq!(ADDR.to_be_bytes(), [0x1f, 0x2a, 0x00, 0xcc, 0x22, 0xf1]);
+        assert_eq!(ADDR.to_le_bytes(), [0xf1, 0x22, 0xcc, 0x00, 0x2a, 0x1f]);
+        assert_eq!(BDAddr::from_le_bytes(ADDR.to_le_bytes()), ADDR);
+        assert_eq!(BDAddr::from_be_bytes(ADDR.to_be_bytes()), ADDR);
+        assert_eq!(<[u8; 6]>::from(ADDR), ADDR.into_inner());
+    }
+
     #[test]
     fn u64_to_addr() {
         let hex_addr: BDAddr = HEX.try_into().unwrap();
@@ -470,6 +658,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn u64_le_conversion() {
+        const HEX_LE: u64 = 0x00_00_f1_22_cc_00_2a_1f;
+        assert_eq!(ADDR.to_u64_le(), HEX_LE);
+        assert_eq!(BDAddr::from_u64_le(HEX_LE), Ok(ADDR));
+        assert_eq!(u64::from(BDAddr::from(ADDR.to_le_bytes())), HEX_LE);
+        assert_eq!(
+            BDAddr::from_u64_le(0x1122334455667788),
+            Err(ParseBDAddrError::IncorrectByteCount)
+        );
+    }
+
     #[test]
     fn addr_to_u64() {
         let addr_as_hex: u64 = ADDR.into();
@@ -500,6 +700,30 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_human_readable_bdaddr_with_struct() {
+        use serde_cr::Deserialize;
+
+        #[derive(Deserialize, PartialEq, Copy, Clone, Debug)]
+        #[serde(crate = "serde_cr")]
+        struct Data {
+            #[serde(with = "crate::serde::bdaddr::human_readable")]
+            addr: BDAddr,
+        }
+
+        let data = Data {
+            addr: BDAddr::from([0xff, 0x00, 0xff, 0x00, 0xff, 0x00]),
+        };
+
+        assert_eq!(toml::from_str(r#"addr = "ff:00:ff:00:ff:00""#), Ok(data));
+        assert_eq!(toml::from_str(r#"addr = "ff00ff00ff00""#), Ok(data));
+        assert_eq!(
+            toml::from_str(r#"addr = [255, 0, 255, 0, 255, 0]"#),
+            Ok(data)
+        );
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn deserialize_toml_nodelim_bdaddr_with_struct() {
@@ -21,10 +21,17 @@
 //! use btleplug::platform::{Adapter, Manager, Peripheral};
 //! ```
 
+pub mod advertisement;
 pub(crate) mod bdaddr;
 pub mod bleuuid;
+mod poll;
+pub mod server;
+pub mod watch;
 
-use crate::Result;
+use crate::duty_cycle::{DutyCycle, DutyCycledScan};
+use crate::interceptor::GattInterceptor;
+use crate::scan_session::ScanSession;
+use crate::{Error, Result};
 use async_trait::async_trait;
 use bitflags::bitflags;
 use futures::stream::Stream;
@@ -35,11 +42,17 @@ use serde_cr as serde;
 use std::{
     collections::{BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
+    future::Future,
     pin::Pin,
+    sync::Arc,
+    time::Duration,
 };
 use uuid::Uuid;
 
+use self::advertisement::{AdParseError, AdStructure, ParseMode};
 pub use self::bdaddr::{BDAddr, ParseBDAddrError};
+pub use self::server::{AdvertisementData, GattServer};
+pub use self::watch::WatchEvent;
 
 use crate::platform::PeripheralId;
 
@@ -86,12 +99,29 @@ impl AddressType {
 pub struct ValueNotification {
     /// UUID of the characteristic that fired the notification.
     pub uuid: Uuid,
+    /// UUID of the service the characteristic belongs to.
+    pub service_uuid: Uuid,
+    /// The [handle](Characteristic::handle) of the characteristic, which tells apart
+    /// characteristics with the same UUIDs. Only set on platforms which report handles.
+    pub handle: Option<u16>,
     /// The new value of the characteristic.
     pub value: Vec<u8>,
+    /// Whether the value was presumably sent as an indication rather than as a notification.
+    ///
+    /// This is a heuristic: no platform reports which of the two a value arrived as, so it's
+    /// derived from the properties of the characteristic, as what subscribing enables on the
+    /// platform. That's indications if the characteristic only supports those, and on Windows
+    /// whenever it supports them. The platforms confirm indications by themselves.
+    pub is_indication: bool,
 }
 
 bitflags! {
     /// A set of properties that indicate what operations are supported by a Characteristic.
+    #[cfg_attr(
+        feature = "serde",
+        derive(Serialize, Deserialize),
+        serde(crate = "serde_cr")
+    )]
     #[derive(Default, Debug, PartialEq, Eq, Ord, PartialOrd, Clone, Copy)]
     pub struct CharPropFlags: u8 {
         const BROADCAST = 0x01;
@@ -105,12 +135,243 @@ bitflags! {
     }
 }
 
+impl CharPropFlags {
+    /// Whether subscribing to a characteristic with these properties enables indications, on
+    /// platforms which prefer notifications when a characteristic supports both.
+    pub(crate) fn subscribes_to_indications(self) -> bool {
+        self.contains(CharPropFlags::INDICATE) && !self.contains(CharPropFlags::NOTIFY)
+    }
+}
+
+bitflags! {
+    /// The permissions of a descriptor, with the values of the `PERMISSION_*` constants of
+    /// Android's `BluetoothGattDescriptor`.
+    #[cfg_attr(
+        feature = "serde",
+        derive(Serialize, Deserialize),
+        serde(crate = "serde_cr")
+    )]
+    #[derive(Default, Debug, PartialEq, Eq, Ord, PartialOrd, Clone, Copy)]
+    pub struct DescriptorPermissions: u16 {
+        const READ = 0x01;
+        const READ_ENCRYPTED = 0x02;
+        const READ_ENCRYPTED_MITM = 0x04;
+        const WRITE = 0x10;
+        const WRITE_ENCRYPTED = 0x20;
+        const WRITE_ENCRYPTED_MITM = 0x40;
+        const WRITE_SIGNED = 0x80;
+        const WRITE_SIGNED_MITM = 0x100;
+    }
+}
+
+bitflags! {
+    /// The optional features supported by the backend of a [`Central`], see
+    /// [`Central::capabilities`]. This lets applications decide at runtime which features to
+    /// offer, rather than by target platform.
+    #[cfg_attr(
+        feature = "serde",
+        derive(Serialize, Deserialize),
+        serde(crate = "serde_cr")
+    )]
+    #[derive(Default, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+    pub struct Capabilities: u32 {
+        /// Receiving extended (Bluetooth 5) advertisements.
+        const EXTENDED_ADVERTISING = 1 << 0;
+        /// Connecting over the LE Coded (long range) PHY.
+        const CODED_PHY = 1 << 1;
+        /// Pairing and bonding with peripherals through btleplug.
+        const PAIRING = 1 << 2;
+        /// L2CAP connection oriented channels.
+        const L2CAP = 1 << 3;
+        /// Publishing a local GATT server.
+        const GATT_SERVER = 1 << 4;
+        /// Scanning keeps running while the application is in the background. On iOS this also
+        /// needs the `bluetooth-central` background mode and a scan filter with services.
+        const BACKGROUND_SCAN = 1 << 5;
+        /// Enumerating paired peripherals with [`Central::paired_peripherals`].
+        const PAIRED_PERIPHERALS = 1 << 6;
+        /// The [`ScanSettings`] given to [`Central::start_scan_with_settings`].
+        const SCAN_SETTINGS = 1 << 7;
+        /// [`ParseMode::Strict`], see [`Central::set_advertisement_parse_mode`].
+        const STRICT_ADVERTISEMENT_PARSING = 1 << 8;
+        /// Replaying events to late subscribers, see [`Central::set_event_replay_capacity`].
+        const EVENT_REPLAY = 1 << 9;
+        /// Connection parameters from [`Peripheral::connection_info`].
+        const CONNECTION_INFO = 1 << 10;
+        /// Adding peripherals which weren't discovered with [`Central::add_peripheral`].
+        const ADD_PERIPHERAL = 1 << 11;
+        /// Adapter hotplug events from [`Manager::events`].
+        const MANAGER_EVENTS = 1 << 12;
+        /// The negotiated ATT MTU from [`Peripheral::mtu`].
+        const MTU = 1 << 13;
+        /// Sharing the scan between consumers with [`Central::start_scan_session`].
+        const SCAN_SESSIONS = 1 << 14;
+        /// Signed Write Commands with [`WriteType::Signed`].
+        const SIGNED_WRITE = 1 << 15;
+        /// Choosing the transport of a connection with [`ConnectOptions::transport`], including
+        /// GATT over BR/EDR.
+        const CONNECT_TRANSPORT = 1 << 16;
+        /// Looking up whether peripherals are paired with [`Peripheral::is_paired`].
+        const PAIRING_STATE = 1 << 17;
+        /// Running [`GattInterceptor`]s around GATT operations.
+        const GATT_INTERCEPTORS = 1 << 18;
+        /// Advertising this device with [`Central::start_advertising`].
+        const ADVERTISING = 1 << 19;
+        /// Requesting connection parameters with [`Peripheral::update_connection_parameters`].
+        const CONNECTION_PARAMETERS = 1 << 20;
+        /// Choosing the PHYs to connect on with [`ConnectOptions::phy`].
+        const CONNECT_PHY = 1 << 21;
+        /// Waiting for a peripheral to come into range with [`ConnectOptions::auto_connect`].
+        const AUTO_CONNECT = 1 << 22;
+        /// [`CentralEvent::BondStateChanged`] events.
+        const BOND_STATE_EVENTS = 1 << 23;
+        /// Pairing request events, answered with [`Peripheral::respond_to_pairing`].
+        const PAIRING_REQUESTS = 1 << 24;
+        /// Choosing and reading the PHYs of a connection with [`Peripheral::set_preferred_phy`]
+        /// and [`Peripheral::read_phy`].
+        const PHY_SELECTION = 1 << 25;
+        /// Reliable write transactions with [`Peripheral::begin_reliable_write`].
+        const RELIABLE_WRITE = 1 << 26;
+        /// Reading long values in chunks with [`Peripheral::set_long_value_chunking`].
+        const LONG_VALUE_CHUNKING = 1 << 27;
+    }
+}
+
+impl Capabilities {
+    /// Whether these capabilities include `feature`.
+    pub fn supports(self, feature: Feature) -> bool {
+        self.contains(feature.capability())
+    }
+}
+
+/// An optional feature, which not all backends support. Each feature has a flag in
+/// [`Capabilities`]; using a feature the backend doesn't support fails with
+/// [`Error::NotSupported`](crate::Error::NotSupported) naming it.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Feature {
+    ExtendedAdvertising,
+    CodedPhy,
+    Pairing,
+    L2cap,
+    GattServer,
+    BackgroundScan,
+    PairedPeripherals,
+    ScanSettings,
+    StrictAdvertisementParsing,
+    EventReplay,
+    ConnectionInfo,
+    AddPeripheral,
+    ManagerEvents,
+    Mtu,
+    ScanSessions,
+    SignedWrite,
+    ConnectTransport,
+    PairingState,
+    GattInterceptors,
+    Advertising,
+    ConnectionParameters,
+    ConnectPhy,
+    AutoConnect,
+    BondStateEvents,
+    PairingRequests,
+    PhySelection,
+    ReliableWrite,
+    LongValueChunking,
+}
+
+impl Feature {
+    /// The flag of this feature in [`Capabilities`].
+    pub fn capability(self) -> Capabilities {
+        match self {
+            Feature::ExtendedAdvertising => Capabilities::EXTENDED_ADVERTISING,
+            Feature::CodedPhy => Capabilities::CODED_PHY,
+            Feature::Pairing => Capabilities::PAIRING,
+            Feature::L2cap => Capabilities::L2CAP,
+            Feature::GattServer => Capabilities::GATT_SERVER,
+            Feature::BackgroundScan => Capabilities::BACKGROUND_SCAN,
+            Feature::PairedPeripherals => Capabilities::PAIRED_PERIPHERALS,
+            Feature::ScanSettings => Capabilities::SCAN_SETTINGS,
+            Feature::StrictAdvertisementParsing => Capabilities::STRICT_ADVERTISEMENT_PARSING,
+            Feature::EventReplay => Capabilities::EVENT_REPLAY,
+            Feature::ConnectionInfo => Capabilities::CONNECTION_INFO,
+            Feature::AddPeripheral => Capabilities::ADD_PERIPHERAL,
+            Feature::ManagerEvents => Capabilities::MANAGER_EVENTS,
+            Feature::Mtu => Capabilities::MTU,
+            Feature::ScanSessions => Capabilities::SCAN_SESSIONS,
+            Feature::SignedWrite => Capabilities::SIGNED_WRITE,
+            Feature::ConnectTransport => Capabilities::CONNECT_TRANSPORT,
+            Feature::PairingState => Capabilities::PAIRING_STATE,
+            Feature::GattInterceptors => Capabilities::GATT_INTERCEPTORS,
+            Feature::Advertising => Capabilities::ADVERTISING,
+            Feature::ConnectionParameters => Capabilities::CONNECTION_PARAMETERS,
+            Feature::ConnectPhy => Capabilities::CONNECT_PHY,
+            Feature::AutoConnect => Capabilities::AUTO_CONNECT,
+            Feature::BondStateEvents => Capabilities::BOND_STATE_EVENTS,
+            Feature::PairingRequests => Capabilities::PAIRING_REQUESTS,
+            Feature::PhySelection => Capabilities::PHY_SELECTION,
+            Feature::ReliableWrite => Capabilities::RELIABLE_WRITE,
+            Feature::LongValueChunking => Capabilities::LONG_VALUE_CHUNKING,
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Feature::ExtendedAdvertising => "extended advertising",
+            Feature::CodedPhy => "LE Coded PHY",
+            Feature::Pairing => "pairing",
+            Feature::L2cap => "L2CAP channels",
+            Feature::GattServer => "GATT server",
+            Feature::BackgroundScan => "background scanning",
+            Feature::PairedPeripherals => "enumerating paired peripherals",
+            Feature::ScanSettings => "scan settings",
+            Feature::StrictAdvertisementParsing => "strict advertisement parsing",
+            Feature::EventReplay => "replaying central events",
+            Feature::ConnectionInfo => "connection info",
+            Feature::AddPeripheral => "adding peripherals without a scan",
+            Feature::ManagerEvents => "manager events",
+            Feature::Mtu => "MTU",
+            Feature::ScanSessions => "scan sessions",
+            Feature::SignedWrite => "signed writes",
+            Feature::ConnectTransport => "choosing the connection transport",
+            Feature::PairingState => "pairing state",
+            Feature::GattInterceptors => "GATT interceptors",
+            Feature::Advertising => "advertising",
+            Feature::ConnectionParameters => "requesting connection parameters",
+            Feature::ConnectPhy => "choosing the PHY of a connection",
+            Feature::AutoConnect => "auto-connect",
+            Feature::BondStateEvents => "bond state events",
+            Feature::PairingRequests => "handling pairing requests",
+            Feature::PhySelection => "choosing the connection PHY",
+            Feature::ReliableWrite => "reliable writes",
+            Feature::LongValueChunking => "long value chunking",
+        })
+    }
+}
+
 /// A GATT service. Services are groups of characteristics, which may be standard or
 /// device-specific.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
 pub struct Service {
     /// The UUID for this service.
     pub uuid: Uuid,
+    /// The ATT handle of the declaration of this service, which tells apart several instances of
+    /// a service with the same UUID, and orders them. Reported on BlueZ and Android, where it is
+    /// the instance ID Android gives the service.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub handle: Option<u16>,
     /// Whether this is a primary service.
     pub primary: bool,
     /// The characteristics of this service.
@@ -125,6 +386,11 @@ pub struct Service {
 ///
 /// A characteristic may be interacted with in various ways depending on its properties. You may be
 /// able to write to it, read from it, set its notify or indicate status, or send a command to it.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
 pub struct Characteristic {
     /// The UUID for this characteristic. This uniquely identifies its behavior.
@@ -137,6 +403,11 @@ pub struct Characteristic {
     pub properties: CharPropFlags,
     /// The descriptors of this characteristic.
     pub descriptors: BTreeSet<Descriptor>,
+    /// The ATT handle of the value of this characteristic, which tells apart characteristics with
+    /// the same UUID, see [`Peripheral::read_by_handle`]. Reported on BlueZ and Android, where it
+    /// is the instance ID Android gives the characteristic, which is its handle.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub handle: Option<u16>,
 }
 
 impl Display for Characteristic {
@@ -150,6 +421,11 @@ impl Display for Characteristic {
 }
 
 /// Add doc
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
 pub struct Descriptor {
     /// The UUID for this descriptor. This uniquely identifies its behavior.
@@ -158,6 +434,16 @@ pub struct Descriptor {
     pub service_uuid: Uuid,
     /// The UUID of the characteristic this descriptor belongs to.
     pub characteristic_uuid: Uuid,
+    /// The ATT handle of this descriptor. Reported on BlueZ and Android, where it is the instance
+    /// ID Android gives the descriptor, which is its handle.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub handle: Option<u16>,
+    /// The permissions of this descriptor, if the platform knows them. The ATT protocol doesn't
+    /// tell clients the permissions of attributes, so only Android reports them, and only for
+    /// descriptors it has permissions for; it leaves them empty for most descriptors of remote
+    /// devices.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub permissions: Option<DescriptorPermissions>,
 }
 
 impl Display for Descriptor {
@@ -181,6 +467,9 @@ pub struct PeripheralProperties {
     pub address_type: Option<AddressType>,
     /// The local name. This is generally a human-readable string that identifies the type of device.
     pub local_name: Option<String>,
+    /// Where [`local_name`](Self::local_name) came from, since advertised names, scan response
+    /// names and the GAP device name frequently disagree.
+    pub local_name_source: Option<NameSource>,
     /// The transmission power level for the device
     pub tx_power_level: Option<i16>,
     /// The most recent Received Signal Strength Indicator for the device
@@ -193,7 +482,134 @@ pub struct PeripheralProperties {
     pub service_data: HashMap<Uuid, Vec<u8>>,
     /// Advertised services for this device
     pub services: Vec<Uuid>,
+    /// The raw class of device of a BR/EDR (classic) or dual-mode device, describing its major and
+    /// minor device class and the service classes it offers. BlueZ learns it from inquiry results
+    /// and extended inquiry responses, which are only received while scanning with a
+    /// [`ScanSettings::transport`] including BR/EDR. Android and WinRT report the class the system
+    /// cached for the device, e.g. because it was found by a classic inquiry or paired.
+    /// CoreBluetooth doesn't expose it.
     pub class: Option<u32>,
+    /// The GAP appearance of the device, describing its external form (e.g. `0x0341` for a
+    /// heart rate belt), if it was advertised.
+    pub appearance: Option<u16>,
+    /// Whether the device is paired (bonded) with this host, or `None` if the platform doesn't
+    /// report pairing state.
+    pub is_paired: Option<bool>,
+    /// The advertising interval of the device, estimated from when its advertisements were
+    /// received during scans. `None` until a few advertisements were received, and on Linux,
+    /// where BlueZ doesn't report each advertisement.
+    pub advertising_interval: Option<AdvertisingInterval>,
+    /// The URI the device advertised, with its scheme expanded. Only URIs with an `http:` or
+    /// `https:` scheme, or which include their scheme, are decoded.
+    pub uri: Option<String>,
+    /// The LE features the controller of the device advertised as supported: a little endian bit
+    /// mask in which bit n stands for the feature with bit number n in the Core Specification.
+    pub le_supported_features: Option<Vec<u8>>,
+    /// The advertising interval the device advertised for itself. Unlike
+    /// [`advertising_interval`](Self::advertising_interval) this isn't estimated.
+    pub advertised_interval: Option<Duration>,
+    /// The public addresses of the devices the advertisements are meant for, if the device
+    /// advertised any.
+    pub public_target_addresses: Vec<BDAddr>,
+    /// The AD structures of the last advertisement received, as opposed to the scan response. The
+    /// fields above merge both; this tells them apart for devices which e.g. advertise their
+    /// identity and send telemetry in the scan response. Only reported on Windows, the other
+    /// platforms merge both packets before btleplug gets to see them.
+    pub advertisement_data: Option<Vec<AdStructure>>,
+    /// The AD structures of the last scan response received, see
+    /// [`advertisement_data`](Self::advertisement_data). Only reported on Windows.
+    pub scan_response_data: Option<Vec<AdStructure>>,
+    /// The advertising data of the last advertising report, untouched, for parsing AD types
+    /// btleplug doesn't know with [`advertisement::parse`]. On Android this is the scan record,
+    /// which includes the scan response, and may be followed by zero padding. On Windows it's
+    /// rebuilt from [`advertisement_data`](Self::advertisement_data) and
+    /// [`scan_response_data`](Self::scan_response_data). Not reported on Linux, macOS and iOS,
+    /// where the OS doesn't hand over the raw data.
+    pub raw_data: Option<Vec<u8>>,
+    /// The PHY the last extended (Bluetooth 5) advertisement carried its data on, or `None` if
+    /// the last advertisement was a legacy one. Only reported on Android 8.0 and later.
+    pub secondary_phy: Option<Phy>,
+    /// The advertising set ID (SID) of the last extended advertisement, which tells apart the
+    /// advertising sets of a device. Only reported on Android 8.0 and later.
+    pub advertising_sid: Option<u8>,
+    /// The interval of the periodic advertising announced by the last extended advertisement, if
+    /// the device advertises periodically. Only reported on Android 8.0 and later.
+    pub periodic_advertising_interval: Option<Duration>,
+}
+
+impl PeripheralProperties {
+    /// Returns which packet the last AD structure of type `ad_type` (one of the constants in the
+    /// [`advertisement`] module) was received in, or `None` if it wasn't received or the platform
+    /// doesn't tell the packets apart. If both packets contained it, the advertisement is
+    /// returned.
+    pub fn packet_of(&self, ad_type: u8) -> Option<AdvertisingPacket> {
+        let contains = |structures: &Option<Vec<AdStructure>>| {
+            structures
+                .iter()
+                .flatten()
+                .any(|structure| structure.ad_type == ad_type)
+        };
+        if contains(&self.advertisement_data) {
+            Some(AdvertisingPacket::Advertisement)
+        } else if contains(&self.scan_response_data) {
+            Some(AdvertisingPacket::ScanResponse)
+        } else {
+            None
+        }
+    }
+}
+
+/// The packets advertising data is sent in, see [`PeripheralProperties::packet_of`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AdvertisingPacket {
+    /// The advertisement, which every scanner receives.
+    Advertisement,
+    /// The scan response, which a device sends to active scanners asking for more data.
+    ScanResponse,
+}
+
+/// Where the [`local_name`](PeripheralProperties::local_name) of a peripheral came from.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NameSource {
+    /// Advertising data. On platforms which don't tell advertisements and scan responses apart,
+    /// this includes names from scan responses.
+    Advertisement,
+    /// A scan response. Only reported on Windows.
+    ScanResponse,
+    /// The GAP Device Name characteristic, read with [`Peripheral::read_device_name`].
+    DeviceName,
+    /// A name the platform keeps for the device, which it may have taken from any of the above or
+    /// which the user gave the device. BlueZ only reports names like this, and CoreBluetooth and
+    /// Windows report them for devices they already know.
+    Platform,
+}
+
+/// An advertising interval estimated from the times advertisements of a device were received,
+/// see [`PeripheralProperties::advertising_interval`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdvertisingInterval {
+    /// The estimated time between two advertising events, including the random delay of up to
+    /// 10ms an advertiser adds to each event.
+    pub interval: Duration,
+    /// How well the received advertisements fit the estimate, from 0.0 to 1.0. The confidence is
+    /// low while only a few advertisements were received, or if they arrived irregularly, e.g.
+    /// because the device changes its interval.
+    pub confidence: f32,
 }
 
 #[cfg_attr(
@@ -202,13 +618,462 @@ pub struct PeripheralProperties {
     serde(crate = "serde_cr")
 )]
 /// The filter used when scanning for BLE devices.
+///
+/// A device passes the filter if it matches one entry of each list which isn't empty. Each
+/// platform applies what it can natively, which saves radio and CPU time, and btleplug checks the
+/// rest against the advertisements it receives. The [`CentralEvent::DeviceDiscovered`] event of a
+/// device which only passes once later advertisements filled in its properties comes right
+/// before its first event which passes. The filter applies until the scan is stopped.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct ScanFilter {
     /// If the filter contains at least one service UUID, only devices supporting at least one of
     /// the given services will be available.
     pub services: Vec<Uuid>,
+    /// If the filter contains at least one GAP appearance value, advertisement events are only
+    /// emitted for devices advertising one of the given appearances. Devices which don't advertise
+    /// an appearance at all are filtered out.
+    ///
+    /// No platform supports filtering on appearance natively, so this is applied by btleplug to
+    /// the advertisements it receives and doesn't save any radio time.
+    pub appearances: Vec<u16>,
+    /// If the filter contains at least one entry, only devices advertising manufacturer specific
+    /// data matching one of them are reported. Applied natively on Android and Windows.
+    pub manufacturer_data: Vec<ManufacturerDataFilter>,
+    /// If the filter contains at least one prefix, only devices whose local name starts with one
+    /// of them are reported. BlueZ applies a single prefix natively, if
+    /// [`ScanSettings::pattern`] isn't set.
+    pub name_prefixes: Vec<String>,
+    /// If the filter contains at least one address, only the devices with one of these addresses
+    /// are reported. Applied natively on Android. macOS and iOS don't tell the addresses of
+    /// devices, so no device passes an address filter there.
+    pub addresses: Vec<BDAddr>,
+}
+
+/// Matches the manufacturer specific data a device advertises for one company, see
+/// [`ScanFilter::manufacturer_data`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ManufacturerDataFilter {
+    /// The company identifier the data is advertised with.
+    pub company_id: u16,
+    /// The bytes the data has to start with. Empty to match any data of the company.
+    pub data: Vec<u8>,
+    /// Which bits of `data` have to match, byte by byte. Empty to compare all of `data`; otherwise
+    /// it should be as long as `data`, and missing bytes are compared fully.
+    pub mask: Vec<u8>,
+}
+
+impl ManufacturerDataFilter {
+    /// Returns whether `data`, advertised for [`company_id`](Self::company_id), matches the
+    /// filter.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.data.len()
+            && self
+                .data
+                .iter()
+                .zip(data)
+                .enumerate()
+                .all(|(i, (expected, actual))| {
+                    let mask = self.mask.get(i).copied().unwrap_or(0xff);
+                    expected & mask == actual & mask
+                })
+    }
+}
+
+impl ScanFilter {
+    /// Returns whether a device with the given appearance passes the
+    /// [`appearances`](ScanFilter::appearances) filter.
+    pub fn matches_appearance(&self, appearance: Option<u16>) -> bool {
+        self.appearances.is_empty()
+            || appearance.is_some_and(|appearance| self.appearances.contains(&appearance))
+    }
+
+    /// Returns whether a device with the given properties passes the filter: it advertises one
+    /// of the [`services`](ScanFilter::services), as a service UUID or with service data, and
+    /// matches one entry of each of the other lists.
+    pub fn matches(&self, properties: &PeripheralProperties) -> bool {
+        let matches_services = self.services.is_empty()
+            || self.services.iter().any(|service| {
+                properties.services.contains(service)
+                    || properties.service_data.contains_key(service)
+            });
+        matches_services && self.matches_except_services(properties)
+    }
+
+    /// Returns whether the filter has criteria besides [`services`](ScanFilter::services), which
+    /// all platforms apply natively.
+    pub(crate) fn filters_beyond_services(&self) -> bool {
+        !(self.appearances.is_empty()
+            && self.manufacturer_data.is_empty()
+            && self.name_prefixes.is_empty()
+            && self.addresses.is_empty())
+    }
+
+    /// Like [`matches`](Self::matches), but ignoring the services.
+    pub(crate) fn matches_except_services(&self, properties: &PeripheralProperties) -> bool {
+        let matches_manufacturer_data = self.manufacturer_data.is_empty()
+            || self.manufacturer_data.iter().any(|filter| {
+                properties
+                    .manufacturer_data
+                    .get(&filter.company_id)
+                    .is_some_and(|data| filter.matches(data))
+            });
+        let matches_name = self.name_prefixes.is_empty()
+            || properties.local_name.as_ref().is_some_and(|name| {
+                self.name_prefixes
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix.as_str()))
+            });
+        let matches_address =
+            self.addresses.is_empty() || self.addresses.contains(&properties.address);
+        self.matches_appearance(properties.appearance)
+            && matches_manufacturer_data
+            && matches_name
+            && matches_address
+    }
+}
+
+/// The kind of Bluetooth devices to scan for.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScanTransport {
+    /// Scan for both LE and BR/EDR (classic) devices, if the adapter supports both.
+    Auto,
+    /// Only scan for LE devices.
+    Le,
+    /// Only scan for BR/EDR (classic) devices.
+    BrEdr,
+}
+
+/// Settings controlling how a scan is performed, as opposed to which devices it reports (see
+/// [`ScanFilter`]). Settings which aren't set keep the platform's defaults, and settings a
+/// platform doesn't support are ignored.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScanSettings {
+    /// The kind of devices to scan for. btleplug scans for both LE and BR/EDR devices by
+    /// default. Only supported on Linux.
+    pub transport: Option<ScanTransport>,
+    /// Whether every advertisement is reported, rather than only those whose data changed.
+    /// Defaults to `true`. Only supported on Linux.
+    pub duplicate_data: Option<bool>,
+    /// Whether the adapter is made discoverable while scanning. Only supported on Linux.
+    pub discoverable: Option<bool>,
+    /// Only report devices whose address or name starts with this pattern. Only supported on
+    /// Linux.
+    pub pattern: Option<String>,
+    /// How much power the scan may use to find devices quickly. Only supported on Android, which
+    /// scans in [`ScanMode::LowPower`] by default.
+    pub mode: Option<ScanMode>,
+    /// Lets the controller collect advertisements and hand them over in batches, at most this
+    /// long after they were received, so the host can sleep in between. Only supported on
+    /// Android, on hardware which supports offloaded batching.
+    pub report_delay: Option<Duration>,
+    /// Whether extended (Bluetooth 5) advertisements are reported, rather than only legacy ones.
+    /// btleplug reports them by default. Only supported on Android 8.0 and later, and Windows.
+    pub extended_advertising: Option<bool>,
+    /// Whether to scan on the LE Coded (long range) PHY too, for extended advertisements sent
+    /// over it. Android scans on all the PHYs the adapter supports by default. Only supported on
+    /// Android 8.0 and later.
+    pub coded_phy: Option<bool>,
+}
+
+/// The trade-off between power consumption and latency of a scan, see [`ScanSettings::mode`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScanMode {
+    /// Scan in short windows with long pauses, finding devices slowly.
+    LowPower,
+    /// Balance power consumption and latency.
+    Balanced,
+    /// Scan continuously, finding devices as quickly as possible.
+    LowLatency,
+}
+
+/// The transport a connection to a dual-mode device is made over.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectTransport {
+    /// Let the platform choose the transport.
+    Auto,
+    /// Connect over LE.
+    Le,
+    /// Connect over BR/EDR (classic), for devices which only expose their GATT services over a
+    /// classic link.
+    BrEdr,
+}
+
+/// An LE physical layer, which trades range against throughput.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Phy {
+    /// The 1M PHY, which every LE device supports.
+    Le1M,
+    /// The 2M PHY, with twice the throughput of 1M at a slightly shorter range.
+    Le2M,
+    /// The Coded PHY, with up to four times the range of 1M at a fraction of its throughput.
+    LeCoded,
+}
+
+/// The coding preferred for sending over the LE Coded PHY, see [`Peripheral::set_preferred_phy`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum PhyCoding {
+    /// Let the controller choose.
+    #[default]
+    NoPreference,
+    /// Two symbols per bit, with twice the range of 1M at a quarter of its throughput.
+    S2,
+    /// Eight symbols per bit, with four times the range of 1M at an eighth of its throughput.
+    S8,
+}
+
+/// The PHYs a connection uses, as reported by [`Peripheral::read_phy`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ConnectionPhy {
+    /// The PHY used for sending to the peripheral.
+    pub tx: Phy,
+    /// The PHY used for receiving from the peripheral.
+    pub rx: Phy,
+}
+
+/// Options for connecting to a peripheral with [`Peripheral::connect_with`]. Options which aren't
+/// set keep the platform's defaults.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConnectOptions {
+    /// The transport to connect over. Only supported on Android. BlueZ picks the transport of
+    /// dual-mode devices by itself, so it only accepts [`ConnectTransport::Auto`]. CoreBluetooth
+    /// and WinRT always connect over LE, so they accept [`ConnectTransport::Le`] as well.
+    pub transport: Option<ConnectTransport>,
+    /// How long to try connecting before giving up with
+    /// [`Error::TimedOut`](crate::Error::TimedOut). Supported on all platforms. Without it,
+    /// connecting gives up after a platform-specific time, or, with `auto_connect` or on
+    /// CoreBluetooth, never.
+    pub timeout: Option<Duration>,
+    /// Whether to wait for the peripheral to come into range rather than failing when it isn't
+    /// advertising. This is Android's `autoConnect` flag, which connects in the background with a
+    /// low scan duty cycle. BlueZ retries connecting until it succeeds, and CoreBluetooth always
+    /// waits. Not supported on WinRT.
+    pub auto_connect: Option<bool>,
+    /// The PHYs the connection may use. Only supported on Android 8 and later.
+    pub phy: Option<Vec<Phy>>,
+}
+
+/// The parameters negotiated for an LE connection, which bound its throughput and latency.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConnectionInfo {
+    /// The time between two connection events.
+    pub interval: Duration,
+    /// The number of consecutive connection events the peripheral may skip (the peripheral or
+    /// slave latency).
+    pub latency: u16,
+    /// The time without a received packet after which the connection is considered lost.
+    pub supervision_timeout: Duration,
 }
 
+impl ConnectionInfo {
+    /// Creates connection info from the raw values used by HCI, where the interval is given in
+    /// units of 1.25 ms and the supervision timeout in units of 10 ms.
+    pub fn from_hci_units(interval: u16, latency: u16, supervision_timeout: u16) -> Self {
+        ConnectionInfo {
+            interval: Duration::from_micros(interval as u64 * 1250),
+            latency,
+            supervision_timeout: Duration::from_millis(supervision_timeout as u64 * 10),
+        }
+    }
+}
+
+/// A preset of connection parameters, trading throughput and latency against power. These are the
+/// presets of Android's `BluetoothGatt.requestConnectionPriority`.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ConnectionPriority {
+    /// A 30 to 50 ms connection interval, the default of most platforms.
+    #[default]
+    Balanced,
+    /// A 11.25 to 15 ms connection interval, for transferring a lot of data like firmware updates.
+    High,
+    /// A 100 to 125 ms connection interval, where the peripheral may skip two connection events.
+    LowPower,
+}
+
+/// The connection parameters to request with [`Peripheral::update_connection_parameters`]. The
+/// peripheral may not accept them, and platforms which only take presets use the
+/// [`ConnectionPriority`] closest to them.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConnectionParams {
+    /// The shortest acceptable time between two connection events, at least 7.5 ms.
+    pub min_interval: Duration,
+    /// The longest acceptable time between two connection events, at most 4 s.
+    pub max_interval: Duration,
+    /// The number of consecutive connection events the peripheral may skip.
+    pub latency: u16,
+    /// The time without a received packet after which the connection is considered lost.
+    pub supervision_timeout: Duration,
+}
+
+impl ConnectionParams {
+    /// The preset closest to these parameters.
+    pub fn priority(&self) -> ConnectionPriority {
+        if self.max_interval <= Duration::from_millis(15) {
+            ConnectionPriority::High
+        } else if self.min_interval >= Duration::from_millis(100) {
+            ConnectionPriority::LowPower
+        } else {
+            ConnectionPriority::Balanced
+        }
+    }
+}
+
+impl From<ConnectionPriority> for ConnectionParams {
+    fn from(priority: ConnectionPriority) -> Self {
+        let (min_interval, max_interval, latency) = match priority {
+            ConnectionPriority::Balanced => (30_000, 50_000, 0),
+            ConnectionPriority::High => (11_250, 15_000, 0),
+            ConnectionPriority::LowPower => (100_000, 125_000, 2),
+        };
+        ConnectionParams {
+            min_interval: Duration::from_micros(min_interval),
+            max_interval: Duration::from_micros(max_interval),
+            latency,
+            supervision_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// How [`Peripheral::write_with_retry`] retries failed writes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of write attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the second attempt. Each further attempt doubles it.
+    pub initial_backoff: Duration,
+    /// The upper bound of the delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait after the given failed attempt, counting from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+
+    /// Runs the operation returned by `f` until it succeeds, fails with an error which isn't
+    /// [transient](Error::is_transient), or the policy runs out of attempts. Before each retry
+    /// `is_connected` is checked, and [`Error::NotConnected`] returned if it's false.
+    pub(crate) async fn retry<T, F, Fut, C, CFut>(
+        &self,
+        operation: impl Display + Send,
+        mut f: F,
+        mut is_connected: C,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+        C: FnMut() -> CFut,
+        CFut: Future<Output = Result<bool>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(e) if e.is_transient() && attempt < self.max_attempts => {
+                    log::debug!(
+                        "{} failed on attempt {}, retrying: {}",
+                        operation,
+                        attempt,
+                        e
+                    );
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    if !is_connected().await? {
+                        return Err(Error::NotConnected);
+                    }
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The largest value an attribute can hold.
+pub(crate) const MAX_ATTRIBUTE_VALUE_LEN: usize = 512;
+
+/// The size of the header of an ATT Write Command, which has to fit in the MTU with the value.
+const ATT_WRITE_HEADER_LEN: usize = 3;
+
+/// The size of the authentication signature appended to a Signed Write Command.
+const ATT_SIGNATURE_LEN: usize = 12;
+
+/// The GAP (Generic Access) service.
+const GENERIC_ACCESS_SERVICE: Uuid = bleuuid::uuid_from_u16(0x1800);
+
+/// The Device Name characteristic of the GAP service.
+const DEVICE_NAME_CHARACTERISTIC: Uuid = bleuuid::uuid_from_u16(0x2A00);
+
 /// The type of write operation to use.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum WriteType {
@@ -217,6 +1082,12 @@ pub enum WriteType {
     WithResponse,
     /// A write-without-response, also known as a command.
     WithoutResponse,
+    /// A Signed Write Command: a write-without-response authenticated with the signature key
+    /// (CSRK) exchanged when bonding, for characteristics with the
+    /// [`AUTHENTICATED_SIGNED_WRITES`](CharPropFlags::AUTHENTICATED_SIGNED_WRITES) property on an
+    /// unencrypted link. Only supported where the platform can sign writes, see
+    /// [`Feature::SignedWrite`].
+    Signed,
 }
 
 /// Peripheral is the device that you would like to communicate with (the "server" of BLE). This
@@ -232,7 +1103,16 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
 
     /// Returns the set of properties associated with the peripheral. These may be updated over time
     /// as additional advertising reports are received.
-    async fn properties(&self) -> Result<Option<PeripheralProperties>>;
+    ///
+    /// The properties are shared rather than copied: the returned `Arc` is a snapshot which later
+    /// updates don't change.
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>>;
+
+    /// Returns the last known properties of the peripheral without talking to the OS, so it can be
+    /// used from non-async contexts such as UI callbacks or `Drop` implementations. The result may
+    /// lag behind [`properties`](Peripheral::properties); on Linux it reflects the state when the
+    /// peripheral was obtained from the adapter or `properties` was last called.
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>>;
 
     /// The set of services we've discovered for this device. This will be empty until
     /// `discover_services` is called.
@@ -247,18 +1127,219 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
             .collect()
     }
 
+    /// The set of descriptors of all the characteristics we've discovered for this device, e.g. to
+    /// find the Client Characteristic Configuration or Characteristic User Description
+    /// descriptors. Like [`characteristics`](Peripheral::characteristics) this doesn't talk to
+    /// the device, and is empty until [`discover_services`](Peripheral::discover_services) was
+    /// called.
+    fn descriptors(&self) -> BTreeSet<Descriptor> {
+        self.characteristics()
+            .into_iter()
+            .flat_map(|characteristic| characteristic.descriptors)
+            .collect()
+    }
+
+    /// Looks up a discovered characteristic by its UUID and the UUID of the service it belongs to.
+    /// Returns [`Error::NoSuchService`] or [`Error::NoSuchCharacteristic`] if either wasn't
+    /// discovered. If the device has several instances of the service, the characteristic of the
+    /// first one is returned; the others can be found by their
+    /// [handles](Peripheral::characteristic_by_handle).
+    fn characteristic(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) -> Result<Characteristic> {
+        self.services()
+            .into_iter()
+            .find(|service| service.uuid == service_uuid)
+            .ok_or(Error::NoSuchService)?
+            .characteristics
+            .into_iter()
+            .find(|characteristic| characteristic.uuid == characteristic_uuid)
+            .ok_or(Error::NoSuchCharacteristic)
+    }
+
+    /// Looks up a discovered descriptor by its UUID and the UUIDs of the service and
+    /// characteristic it belongs to. Returns [`Error::NoSuchService`],
+    /// [`Error::NoSuchCharacteristic`] or [`Error::NoSuchDescriptor`] if any of them wasn't
+    /// discovered.
+    fn descriptor(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        descriptor_uuid: Uuid,
+    ) -> Result<Descriptor> {
+        self.characteristic(service_uuid, characteristic_uuid)?
+            .descriptors
+            .into_iter()
+            .find(|descriptor| descriptor.uuid == descriptor_uuid)
+            .ok_or(Error::NoSuchDescriptor)
+    }
+
+    /// Looks up a discovered characteristic by the handle of its value. Returns
+    /// [`Error::NoSuchCharacteristic`] if none has it, as is always the case on platforms which
+    /// don't report [handles](Characteristic::handle).
+    fn characteristic_by_handle(&self, handle: u16) -> Result<Characteristic> {
+        self.characteristics()
+            .into_iter()
+            .find(|characteristic| characteristic.handle == Some(handle))
+            .ok_or(Error::NoSuchCharacteristic)
+    }
+
+    /// Looks up a discovered descriptor by its handle. Returns [`Error::NoSuchDescriptor`] if none
+    /// has it, as is always the case on platforms which don't report
+    /// [handles](Descriptor::handle).
+    fn descriptor_by_handle(&self, handle: u16) -> Result<Descriptor> {
+        self.characteristics()
+            .into_iter()
+            .flat_map(|characteristic| characteristic.descriptors)
+            .find(|descriptor| descriptor.handle == Some(handle))
+            .ok_or(Error::NoSuchDescriptor)
+    }
+
     /// Returns true iff we are currently connected to the device.
     async fn is_connected(&self) -> Result<bool>;
 
+    /// Returns whether the device is paired (bonded) with this host, looked up in the bond
+    /// database of the platform. Unlike [`PeripheralProperties::is_paired`] this is always
+    /// current, so it can decide whether to connect directly or to pair first.
+    ///
+    /// CoreBluetooth doesn't expose bonds, and returns
+    /// [`Error::NotSupported`](crate::Error::NotSupported).
+    async fn is_paired(&self) -> Result<bool> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::PairingState,
+        })
+    }
+
+    /// Answers the pending [`CentralEvent::PairingPinRequested`] or
+    /// [`CentralEvent::PairingConfirmationRequested`] for this device, failing if there's none.
+    ///
+    /// Android only lets system apps confirm or reject pairings, so anything but
+    /// [`PairingResponse::Pin`] fails with
+    /// [`Error::PermissionDenied`](crate::Error::PermissionDenied) there, leaving it to the user.
+    async fn respond_to_pairing(&self, _response: PairingResponse) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::PairingRequests,
+        })
+    }
+
     /// Returns the negotiated ATT MTU for this connection when available.
     /// For CoreBluetooth, passing characteristics can influence the MTU calculation.
     async fn mtu(&self, characteristics: Option<&[Characteristic]>) -> Result<u16>;
 
+    /// Returns the largest value [`write`](Peripheral::write) accepts with `write_type` on the
+    /// current connection, for splitting up larger payloads. A write without response has to fit
+    /// in a single packet, so it can take the MTU minus the 3 byte ATT header. A write with response
+    /// is split up by the platform if needed, up to the 512 bytes an attribute value can hold.
+    async fn max_write_len(&self, write_type: WriteType) -> Result<usize> {
+        match write_type {
+            WriteType::WithResponse => Ok(MAX_ATTRIBUTE_VALUE_LEN),
+            WriteType::WithoutResponse => {
+                let mtu = self.mtu(None).await?;
+                Ok(usize::from(mtu).saturating_sub(ATT_WRITE_HEADER_LEN))
+            }
+            WriteType::Signed => {
+                let mtu = self.mtu(None).await?;
+                Ok(usize::from(mtu).saturating_sub(ATT_WRITE_HEADER_LEN + ATT_SIGNATURE_LEN))
+            }
+        }
+    }
+
+    /// Sets whether values too long for a single packet are read in chunks, which is off by
+    /// default.
+    ///
+    /// BlueZ reads long values with Read Blob requests by itself, but some devices cut values off
+    /// at the MTU instead. With chunking on, [`read`](Peripheral::read) goes on reading from the
+    /// end of the value while the responses fill the MTU. This costs an extra read for values
+    /// filling the last packet exactly, which devices may answer with an error failing the whole
+    /// read, so only turn it on for devices which truncate. Writes are never split up: BlueZ
+    /// writes long values with a single atomic prepared write. Android, CoreBluetooth and WinRT
+    /// always do long reads by themselves, and return
+    /// [`Error::NotSupported`](crate::Error::NotSupported).
+    async fn set_long_value_chunking(&self, _enabled: bool) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::LongValueChunking,
+        })
+    }
+
+    /// Returns the parameters currently negotiated for the connection to the device, or `None`
+    /// if the platform hasn't reported them yet.
+    ///
+    /// This is only supported on Android, where the parameters are reported whenever the
+    /// connection is updated. BlueZ, CoreBluetooth and WinRT don't expose them, and return
+    /// [`Error::NotSupported`](crate::Error::NotSupported).
+    async fn connection_info(&self) -> Result<Option<ConnectionInfo>> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::ConnectionInfo,
+        })
+    }
+
+    /// Asks for different parameters for the connection to the device, e.g. a short connection
+    /// interval for a firmware update. `ConnectionPriority::High.into()` requests one of the
+    /// presets.
+    ///
+    /// This is supported on Android, which only takes the presets, and on Windows 11 and later,
+    /// where the request holds until the next one or until the device disconnects. BlueZ and
+    /// CoreBluetooth negotiate the parameters by themselves, and return
+    /// [`Error::NotSupported`](crate::Error::NotSupported).
+    async fn update_connection_parameters(&self, _params: ConnectionParams) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::ConnectionParameters,
+        })
+    }
+
+    /// Asks for the connection to send on one of the PHYs in `tx` and receive on one of those in
+    /// `rx`, e.g. [`Phy::Le2M`] for throughput or [`Phy::LeCoded`] for range. The controllers of
+    /// both sides agree on the PHYs, so they may not follow the request; [`read_phy`] tells which
+    /// PHYs are in use. `coding` is only used if the LE Coded PHY is chosen.
+    ///
+    /// Only supported on Android 8.0 and later.
+    ///
+    /// [`read_phy`]: Peripheral::read_phy
+    async fn set_preferred_phy(&self, _tx: &[Phy], _rx: &[Phy], _coding: PhyCoding) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::PhySelection,
+        })
+    }
+
+    /// Reads the PHYs the connection to the device uses. Only supported on Android 8.0 and later.
+    async fn read_phy(&self) -> Result<ConnectionPhy> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::PhySelection,
+        })
+    }
+
     /// Creates a connection to the device. If this method returns Ok there has been successful
     /// connection. Note that peripherals allow only one connection at a time. Operations that
     /// attempt to communicate with a device will fail until it is connected.
     async fn connect(&self) -> Result<()>;
 
+    /// Connects like [`connect`](Peripheral::connect), with `options` overriding the platform's
+    /// defaults. Fails with [`Error::NotSupported`](crate::Error::NotSupported) if the platform
+    /// can't honour an option, rather than connecting without it.
+    ///
+    /// A connection attempt which is cancelled by dropping the returned future may still complete
+    /// in the background; use [`ConnectOptions::timeout`] to give up on it cleanly.
+    async fn connect_with(&self, options: ConnectOptions) -> Result<()> {
+        if !matches!(options.transport, None | Some(ConnectTransport::Auto)) {
+            return Err(crate::Error::NotSupported {
+                feature: Feature::ConnectTransport,
+            });
+        }
+        if options.auto_connect == Some(true) {
+            return Err(crate::Error::NotSupported {
+                feature: Feature::AutoConnect,
+            });
+        }
+        if options.phy.is_some() {
+            return Err(crate::Error::NotSupported {
+                feature: Feature::ConnectPhy,
+            });
+        }
+        crate::common::connect::with_timeout(self, options.timeout, self.connect()).await
+    }
+
     /// Terminates a connection to the device.
     async fn disconnect(&self) -> Result<()>;
 
@@ -274,20 +1355,156 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
         write_type: WriteType,
     ) -> Result<()>;
 
+    /// Writes the characteristic or descriptor with the given handle, see
+    /// [`read_by_handle`](Peripheral::read_by_handle). `write_type` is ignored for descriptors.
+    /// Returns [`Error::NoSuchCharacteristic`] if no discovered attribute has the handle.
+    async fn write_by_handle(&self, handle: u16, data: &[u8], write_type: WriteType) -> Result<()> {
+        if let Ok(characteristic) = self.characteristic_by_handle(handle) {
+            return self.write(&characteristic, data, write_type).await;
+        }
+        match self.descriptor_by_handle(handle) {
+            Ok(descriptor) => self.write_descriptor(&descriptor, data).await,
+            Err(_) => Err(Error::NoSuchCharacteristic),
+        }
+    }
+
+    /// Starts a reliable write transaction, which writes several characteristics at once: the
+    /// peripheral keeps the values queued with [`ReliableWrite::queue_write`] until
+    /// [`ReliableWrite::execute`] applies them, or [`ReliableWrite::abort`] discards them.
+    ///
+    /// Only supported on Android. BlueZ has no transactions spanning several characteristics, so
+    /// this returns [`Error::NotSupported`] on Linux.
+    async fn begin_reliable_write(&self) -> Result<Box<dyn ReliableWrite>> {
+        Err(Error::NotSupported {
+            feature: Feature::ReliableWrite,
+        })
+    }
+
+    /// Writes like [`write`](Peripheral::write), but retries after
+    /// [transient](Error::is_transient) errors according to `policy`. Each attempt completes
+    /// before the next one is queued, and the connection is checked between attempts: if the
+    /// device disconnected, [`Error::NotConnected`] is returned instead of retrying. Other errors
+    /// are returned immediately, as is the error of the last attempt.
+    ///
+    /// A write which timed out may still have reached the device, so it may be applied twice.
+    async fn write_with_retry(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+        policy: &RetryPolicy,
+    ) -> Result<()> {
+        policy
+            .retry(
+                format!("Write to {}", characteristic.uuid),
+                || self.write(characteristic, data, write_type),
+                || self.is_connected(),
+            )
+            .await
+    }
+
     /// Sends a read request to the device. Returns either an error if the request was not accepted
     /// or the response from the device.
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
 
+    /// Reads the characteristic or descriptor with the given handle. Unlike the UUIDs, handles
+    /// tell apart characteristics which share a UUID, such as those of several instances of a
+    /// service. Returns [`Error::NoSuchCharacteristic`] if no discovered attribute has the handle.
+    async fn read_by_handle(&self, handle: u16) -> Result<Vec<u8>> {
+        if let Ok(characteristic) = self.characteristic_by_handle(handle) {
+            return self.read(&characteristic).await;
+        }
+        match self.descriptor_by_handle(handle) {
+            Ok(descriptor) => self.read_descriptor(&descriptor).await,
+            Err(_) => Err(Error::NoSuchCharacteristic),
+        }
+    }
+
+    /// Returns the last value seen for the characteristic, either from a read or a notification,
+    /// if it is no older than `max_age`. Otherwise sends a read request to the device like
+    /// [`read`](Peripheral::read) does.
+    ///
+    /// The values are shared by all the peripherals returned for the same device. Notified values
+    /// are only seen while a stream from [`notifications`](Peripheral::notifications) is polled.
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>>;
+
+    /// Returns a stream of the values of a characteristic which can be read but not subscribed to,
+    /// by reading it every `interval`. The first value read is yielded, then every value which
+    /// differs from the previous one. Failed reads are retried at the next interval, and the
+    /// stream ends once the device disconnects. Fails with [`Error::NotConnected`] if it isn't
+    /// connected yet.
+    ///
+    /// Reads happen while the stream is polled, and stop when it's dropped. Prefer
+    /// [`subscribe`](Peripheral::subscribe) for characteristics which support it, which doesn't
+    /// keep the radio busy and doesn't miss changes between reads.
+    async fn poll_characteristic(
+        &self,
+        characteristic: &Characteristic,
+        interval: Duration,
+    ) -> Result<Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>>
+    where
+        Self: 'static,
+    {
+        if !self.is_connected().await? {
+            return Err(Error::NotConnected);
+        }
+        Ok(poll::poll_values(
+            self.clone(),
+            characteristic.clone(),
+            interval,
+        ))
+    }
+
+    /// Reads the name of the device from the GAP Device Name characteristic, which is the name the
+    /// device was given rather than the possibly shortened one it advertises. The name becomes the
+    /// [`local_name`](PeripheralProperties::local_name) of the peripheral, with
+    /// [`NameSource::DeviceName`] as its source, until a newer name is received. The services
+    /// must have been discovered; to refresh the name on each connection, call this after
+    /// [`discover_services`](Peripheral::discover_services).
+    ///
+    /// CoreBluetooth hides the GAP service, so this fails with [`Error::NoSuchService`] there.
+    /// BlueZ hides it too, but reads the characteristic itself when connecting, so there the name
+    /// BlueZ got from it is returned.
+    async fn read_device_name(&self) -> Result<String> {
+        read_gap_device_name(self).await
+    }
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
+    /// Subscribes to every discovered characteristic which supports notify or indicate,
+    /// optionally only those belonging to the service with the given UUID. Returns the
+    /// characteristics which were successfully subscribed to; failures on individual
+    /// characteristics are logged and skipped, unless the device is no longer connected.
+    async fn subscribe_all(&self, service_uuid: Option<Uuid>) -> Result<Vec<Characteristic>> {
+        let mut subscribed = Vec::new();
+        let characteristics = self.characteristics().into_iter().filter(|characteristic| {
+            characteristic
+                .properties
+                .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+                && service_uuid.is_none_or(|uuid| characteristic.service_uuid == uuid)
+        });
+        for characteristic in characteristics {
+            match self.subscribe(&characteristic).await {
+                Ok(()) => subscribed.push(characteristic),
+                Err(Error::NotConnected) => return Err(Error::NotConnected),
+                Err(e) => log::warn!("Failed to subscribe to {}: {}", characteristic, e),
+            }
+        }
+        Ok(subscribed)
+    }
+
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
     /// Returns a stream of notifications for characteristic value updates. The stream will receive
     /// a notification when a value notification or indication is received from the device.
-    /// The stream will remain valid across connections and can be queried before any connection
-    /// is made.
+    /// The stream can be queried before any connection is made, and ends when the device
+    /// disconnects; call this again after reconnecting to keep receiving notifications.
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>>;
 
     /// Write some data to the descriptor. Returns an error if the write couldn't be sent or (in
@@ -297,6 +1514,92 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Sends a read descriptor request to the device. Returns either an error if the request
     /// was not accepted or the response from the device.
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+
+    /// Registers an interceptor run around the GATT operations of this peripheral, after those
+    /// registered on its central. See the [`interceptor`](crate::interceptor) module.
+    fn add_interceptor(&self, _interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        Err(Error::NotSupported {
+            feature: Feature::GattInterceptors,
+        })
+    }
+
+    /// Removes an interceptor registered with [`add_interceptor`](Peripheral::add_interceptor),
+    /// returning whether it was registered.
+    fn remove_interceptor(&self, _interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Err(Error::NotSupported {
+            feature: Feature::GattInterceptors,
+        })
+    }
+}
+
+/// A reliable write transaction, started with [`Peripheral::begin_reliable_write`].
+///
+/// Only one transaction may be open for a peripheral at a time, and other operations shouldn't be
+/// done on it until the transaction is executed or aborted. Dropping the transaction aborts it.
+#[async_trait]
+pub trait ReliableWrite: Send + Sync + Debug {
+    /// Queues a write of `data` to `characteristic`, which must support writes with response.
+    async fn queue_write(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()>;
+
+    /// Has the peripheral apply the queued writes.
+    async fn execute(self: Box<Self>) -> Result<()>;
+
+    /// Has the peripheral discard the queued writes.
+    async fn abort(self: Box<Self>) -> Result<()>;
+}
+
+/// The platform Bluetooth stacks btleplug has backends for.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BackendKind {
+    /// CoreBluetooth, on macOS and iOS.
+    CoreBluetooth,
+    /// The Windows Runtime Bluetooth APIs.
+    WinRt,
+    /// BlueZ, on Linux.
+    BlueZ,
+    /// The Android Bluetooth APIs.
+    Android,
+}
+
+impl Display for BackendKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BackendKind::CoreBluetooth => "CoreBluetooth",
+            BackendKind::WinRt => "WinRT",
+            BackendKind::BlueZ => "BlueZ",
+            BackendKind::Android => "Android",
+        })
+    }
+}
+
+/// The backend of a [`Central`], returned by [`Central::backend`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Backend {
+    /// The platform Bluetooth API the backend is built on, which is fixed for each target.
+    pub kind: BackendKind,
+    /// The version of the operating system the backend runs on, if it could be found out. For
+    /// BlueZ this is the version of the Linux kernel.
+    pub version: Option<String>,
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} ({})", self.kind, version),
+            None => write!(f, "{}", self.kind),
+        }
+    }
 }
 
 #[cfg_attr(
@@ -312,6 +1615,32 @@ pub enum CentralState {
     PoweredOff = 2,
 }
 
+/// Whether a peripheral is bonded with this host, see [`CentralEvent::BondStateChanged`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BondState {
+    NotBonded,
+    /// Pairing is in progress. Only reported on Android.
+    Bonding,
+    Bonded,
+}
+
+/// The answer to a pairing request, given with [`Peripheral::respond_to_pairing`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PairingResponse {
+    /// Enters the PIN code or passkey asked for by [`CentralEvent::PairingPinRequested`]. A
+    /// passkey is given as its decimal digits.
+    Pin(String),
+    /// Accepts the pairing, for [`CentralEvent::PairingConfirmationRequested`].
+    Confirm,
+    /// Rejects the pairing, for either request.
+    Reject,
+}
+
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
@@ -320,9 +1649,37 @@ pub enum CentralState {
 #[derive(Debug, Clone)]
 pub enum CentralEvent {
     DeviceDiscovered(PeripheralId),
+    /// Emitted when the properties of a device changed, e.g. because a new advertisement with a
+    /// different RSSI was received.
     DeviceUpdated(PeripheralId),
     DeviceConnected(PeripheralId),
     DeviceDisconnected(PeripheralId),
+    /// Emitted when a connection attempt to a device has started. Currently only emitted on
+    /// macOS/iOS.
+    DeviceConnecting(PeripheralId),
+    /// Emitted when a connection attempt to a device failed, with the reason given by the OS.
+    /// Currently only emitted on macOS/iOS.
+    DeviceConnectionFailed {
+        id: PeripheralId,
+        reason: String,
+    },
+    /// Emitted when disconnecting from a device has started. Currently only emitted on macOS/iOS.
+    DeviceDisconnecting(PeripheralId),
+    /// Emitted when the OS has finished resolving the GATT services of a connected device, so
+    /// [`Peripheral::discover_services`] won't have to wait for them. Currently only emitted on
+    /// Linux.
+    ServicesResolved(PeripheralId),
+    /// Emitted when the GATT services of a connected device changed, e.g. after a firmware
+    /// update. The platforms subscribe to the Service Changed characteristic of the device by
+    /// themselves.
+    ///
+    /// On Linux and macOS/iOS the services are discovered again first, so
+    /// [`Peripheral::services`] returns the new services by then. BlueZ reports each service
+    /// added or removed, which are coalesced into one event per change. On Android (from
+    /// Android 12) and Windows the services discovered before are dropped instead, and
+    /// [`Peripheral::discover_services`] has to be called again. Not emitted with the `bluer`
+    /// backend.
+    ServicesChanged(PeripheralId),
     /// Emitted when a Manufacturer Data advertisement has been received from a device
     ManufacturerDataAdvertisement {
         id: PeripheralId,
@@ -339,6 +1696,55 @@ pub enum CentralEvent {
         services: Vec<Uuid>,
     },
     StateUpdate(CentralState),
+    /// Emitted when a scan was stopped by the OS rather than by [`Central::stop_scan`], e.g.
+    /// because the Bluetooth radio was reset. `restarting` is whether btleplug is going to restart
+    /// the scan by itself; otherwise it stays stopped until [`Central::start_scan`] is called
+    /// again. Currently only emitted on Windows.
+    ScanStopped {
+        reason: String,
+        restarting: bool,
+    },
+    /// Emitted in [`ParseMode::Strict`] when an advertisement from a device contained malformed
+    /// AD structures. The well-formed parts of the advertisement are still reported as usual.
+    MalformedAdvertisement {
+        id: PeripheralId,
+        errors: Vec<AdParseError>,
+    },
+    /// Emitted when a device was bonded with this host, or its bond was removed. Only emitted on
+    /// Linux and Android, see [`Capabilities::BOND_STATE_EVENTS`].
+    BondStateChanged {
+        id: PeripheralId,
+        state: BondState,
+    },
+    /// Emitted when pairing with a device needs a PIN code or passkey to be entered, which is
+    /// given with [`Peripheral::respond_to_pairing`]. Only emitted on Linux and Android, see
+    /// [`Central::handle_pairing_requests`].
+    PairingPinRequested {
+        id: PeripheralId,
+    },
+    /// Emitted when pairing with a device needs to be confirmed, with
+    /// [`Peripheral::respond_to_pairing`]. `passkey` is the number to compare with the one shown
+    /// by the device, if any. On Linux, it is also emitted when a device asks to connect to one
+    /// of our services. Only emitted on Linux and Android, see
+    /// [`Central::handle_pairing_requests`].
+    PairingConfirmationRequested {
+        id: PeripheralId,
+        passkey: Option<u32>,
+    },
+}
+
+impl CentralEvent {
+    /// Returns the peripheral the event was caused by, if it was caused by an advertisement.
+    pub(crate) fn advertisement_peripheral_id(&self) -> Option<&PeripheralId> {
+        match self {
+            CentralEvent::DeviceDiscovered(id)
+            | CentralEvent::DeviceUpdated(id)
+            | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+            | CentralEvent::ServiceDataAdvertisement { id, .. }
+            | CentralEvent::ServicesAdvertisement { id, .. } => Some(id),
+            _ => None,
+        }
+    }
 }
 
 /// Central is the "client" of BLE. It's able to scan for and establish connections to peripherals.
@@ -360,19 +1766,99 @@ pub trait Central: Send + Sync + Clone {
     /// a filter, but must be able to handle devices, which do not fit into the filter.
     async fn start_scan(&self, filter: ScanFilter) -> Result<()>;
 
+    /// Starts a scan like [`start_scan`](Central::start_scan), with additional settings for how the
+    /// scan is performed. Backends ignore the settings they don't support.
+    async fn start_scan_with_settings(
+        &self,
+        filter: ScanFilter,
+        _settings: ScanSettings,
+    ) -> Result<()> {
+        self.start_scan(filter).await
+    }
+
     /// Stops scanning for BLE devices.
     async fn stop_scan(&self) -> Result<()>;
 
+    /// Starts a scan shared with the other sessions of this central: scanning only stops once all
+    /// sessions are stopped or dropped. This lets independent components scan with the same
+    /// central without stopping each other's scans. See the
+    /// [`scan_session`](crate::scan_session) module.
+    async fn start_scan_session(&self, _filter: ScanFilter) -> Result<ScanSession> {
+        Err(Error::NotSupported {
+            feature: Feature::ScanSessions,
+        })
+    }
+
+    /// Starts a scan like [`start_scan`](Central::start_scan) and returns a stream tracking which
+    /// peripherals are present: a peripheral is [`Found`](WatchEvent::Found) when it's first seen,
+    /// [`Updated`](WatchEvent::Updated) on every further advertisement, and
+    /// [`Lost`](WatchEvent::Lost) once nothing was received from it for `lost_after`. A lost
+    /// peripheral is found again when it shows up again.
+    ///
+    /// Dropping the stream doesn't stop the scan, call [`stop_scan`](Central::stop_scan) for that.
+    /// Note that most peripherals stop advertising while they're connected, so they get lost.
+    async fn watch(
+        &self,
+        filter: ScanFilter,
+        lost_after: Duration,
+    ) -> Result<Pin<Box<dyn Stream<Item = WatchEvent> + Send>>> {
+        let events = self.events().await?;
+        self.start_scan(filter).await?;
+        Ok(watch::watch_events(events, lost_after))
+    }
+
+    /// Starts scanning in windows: on for [`DutyCycle::on`], off for [`DutyCycle::off`], over and
+    /// over until the returned [`DutyCycledScan`] is stopped or dropped. See the
+    /// [`duty_cycle`](crate::duty_cycle) module.
+    async fn start_duty_cycled_scan(
+        &self,
+        filter: ScanFilter,
+        duty_cycle: DutyCycle,
+    ) -> Result<DutyCycledScan>
+    where
+        Self: 'static,
+    {
+        Ok(DutyCycledScan::start(self.clone(), filter, duty_cycle))
+    }
+
     /// Returns the list of [`Peripheral`]s that have been discovered so far. Note that this list
     /// may contain peripherals that are no longer available.
     async fn peripherals(&self) -> Result<Vec<Self::Peripheral>>;
 
+    /// Returns the list of [`Peripheral`]s that have been discovered so far, each paired with its
+    /// properties. This is equivalent to calling [`Peripheral::properties`] on every result of
+    /// [`peripherals`](Central::peripherals), but backends which have to query the OS for
+    /// properties fetch them all at once.
+    async fn peripherals_with_properties(
+        &self,
+    ) -> Result<Vec<(Self::Peripheral, Option<Arc<PeripheralProperties>>)>> {
+        let peripherals = self.peripherals().await?;
+        let mut result = Vec::with_capacity(peripherals.len());
+        for peripheral in peripherals {
+            let properties = peripheral.properties().await?;
+            result.push((peripheral, properties));
+        }
+        Ok(result)
+    }
+
     /// Returns a particular [`Peripheral`] by its address if it has been discovered.
     async fn peripheral(&self, id: &PeripheralId) -> Result<Self::Peripheral>;
 
     /// Add a [`Peripheral`] from a MAC address without a scan result. Not supported on all Bluetooth systems.
     async fn add_peripheral(&self, address: &PeripheralId) -> Result<Self::Peripheral>;
 
+    /// Returns the [`Peripheral`]s paired with this host, whether or not they are currently
+    /// advertising, and makes them available via [`peripherals`](Central::peripherals). Paired
+    /// devices can be connected to without scanning for them first.
+    ///
+    /// Currently only supported on Windows; elsewhere this returns
+    /// [`Error::NotSupported`](crate::Error::NotSupported).
+    async fn paired_peripherals(&self) -> Result<Vec<Self::Peripheral>> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::PairedPeripherals,
+        })
+    }
+
     /// Get information about the Bluetooth adapter being used, such as the model or type.
     ///
     /// The details of this are platform-specific andyou should not attempt to parse it, but it may
@@ -381,6 +1867,108 @@ pub trait Central: Send + Sync + Clone {
 
     /// Get information about the Bluetooth adapter state.
     async fn adapter_state(&self) -> Result<CentralState>;
+
+    /// Returns the optional features supported by this central's backend. Features which aren't
+    /// included either return [`Error::NotSupported`](crate::Error::NotSupported) or, for settings
+    /// and hints, are ignored.
+    async fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::empty())
+    }
+
+    /// Returns which platform backend this central uses, and the version of the platform. Meant for
+    /// logs and bug reports, and for working around quirks of particular platform versions.
+    fn backend(&self) -> Backend;
+
+    /// Sets how malformed advertising data is handled. Defaults to [`ParseMode::Lenient`].
+    ///
+    /// [`ParseMode::Strict`] is only supported on platforms where btleplug parses raw advertising
+    /// data itself (currently Android); elsewhere the OS discards malformed data before btleplug
+    /// sees it and this returns [`Error::NotSupported`](crate::Error::NotSupported).
+    async fn set_advertisement_parse_mode(&self, mode: ParseMode) -> Result<()> {
+        match mode {
+            ParseMode::Lenient => Ok(()),
+            ParseMode::Strict => Err(crate::Error::NotSupported {
+                feature: Feature::StrictAdvertisementParsing,
+            }),
+        }
+    }
+
+    /// Sets how many of the most recent events are replayed to each new stream returned by
+    /// [`events`](Central::events), before the live events. This lets a consumer which subscribes
+    /// after scanning started, e.g. a UI, see the devices discovered so far. Defaults to 0, no
+    /// replay.
+    ///
+    /// Not supported on Linux, where [`events`](Central::events) starts with a `DeviceDiscovered`
+    /// (and `DeviceConnected`) event for every device BlueZ already knows instead.
+    async fn set_event_replay_capacity(&self, _capacity: usize) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::EventReplay,
+        })
+    }
+
+    /// Registers an interceptor run around the GATT operations of every peripheral of this
+    /// central, including the peripherals returned before. See the
+    /// [`interceptor`](crate::interceptor) module.
+    fn add_interceptor(&self, _interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::GattInterceptors,
+        })
+    }
+
+    /// Removes an interceptor registered with [`add_interceptor`](Central::add_interceptor),
+    /// returning whether it was registered.
+    fn remove_interceptor(&self, _interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::GattInterceptors,
+        })
+    }
+
+    /// Opens a local GATT server on this adapter, so that remote centrals can connect to this
+    /// device and use the services published on it. See the [`server`] module.
+    async fn gatt_server(&self) -> Result<Box<dyn GattServer>> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::GattServer,
+        })
+    }
+
+    /// Starts advertising this device, replacing the advertisement started before if there is one.
+    /// Advertising goes on until [`stop_advertising`](Central::stop_advertising) is called.
+    async fn start_advertising(&self, _data: AdvertisementData) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::Advertising,
+        })
+    }
+
+    /// Stops the advertisement started with [`start_advertising`](Central::start_advertising). Does
+    /// nothing if this device isn't advertising.
+    async fn stop_advertising(&self) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::Advertising,
+        })
+    }
+
+    /// Routes the pairing requests of the platform to this application, which is then sent
+    /// [`CentralEvent::PairingPinRequested`] and [`CentralEvent::PairingConfirmationRequested`]
+    /// events and has to answer them with [`Peripheral::respond_to_pairing`].
+    ///
+    /// On Linux this makes btleplug BlueZ's default agent, taking over from the pairing UI of the
+    /// desktop until the [`Manager`] is dropped. Android always reports pairing requests while
+    /// showing its own pairing dialog, so this does nothing there.
+    async fn handle_pairing_requests(&self) -> Result<()> {
+        Err(crate::Error::NotSupported {
+            feature: Feature::PairingRequests,
+        })
+    }
+}
+
+/// The events generated by a [`Manager`] when the set of Bluetooth adapters changes.
+#[derive(Debug, Clone)]
+pub enum ManagerEvent<A> {
+    /// An adapter was plugged in or enabled.
+    AdapterAdded(A),
+    /// An adapter was removed. It can't be used anymore, but compares equal to the adapter
+    /// previously returned for it.
+    AdapterRemoved(A),
 }
 
 /// The Manager is the entry point to the library, providing access to all the Bluetooth adapters on
@@ -408,4 +1996,274 @@ pub trait Manager {
 
     /// Get a list of all Bluetooth adapters on the system. Each adapter implements [`Central`].
     async fn adapters(&self) -> Result<Vec<Self::Adapter>>;
+
+    /// Retrieve a stream of [`ManagerEvent`]s, which are emitted when adapters are plugged in or
+    /// removed while the stream is alive. [`adapters`](Manager::adapters) always returns the
+    /// current set of adapters.
+    ///
+    /// Currently only supported on Linux; elsewhere this returns
+    /// [`Error::NotSupported`](crate::Error::NotSupported).
+    async fn events(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = ManagerEvent<Self::Adapter>> + Send>>>
+    where
+        Self: Sync,
+    {
+        Err(crate::Error::NotSupported {
+            feature: Feature::ManagerEvents,
+        })
+    }
+
+    /// Stops scanning, disconnects the peripherals connected through btleplug and releases the
+    /// background tasks and platform resources of this manager, so that an application hosting
+    /// btleplug (e.g. as a plugin) can unload it cleanly. The manager, and the adapters and
+    /// peripherals it returned, shouldn't be used afterwards.
+    ///
+    /// Once the manager and everything it returned are dropped, backends do the same on a best
+    /// effort basis, as far as they can without an async context. Errors are only logged then.
+    async fn shutdown(&self) -> Result<()>
+    where
+        Self: Sync,
+    {
+        let mut result = Ok(());
+        for adapter in self.adapters().await? {
+            result = result.and(shutdown_central(&adapter).await);
+        }
+        result
+    }
+}
+
+/// Reads the GAP Device Name characteristic of `peripheral`.
+pub(crate) async fn read_gap_device_name<P: Peripheral>(peripheral: &P) -> Result<String> {
+    let characteristic =
+        peripheral.characteristic(GENERIC_ACCESS_SERVICE, DEVICE_NAME_CHARACTERISTIC)?;
+    let value = peripheral.read(&characteristic).await?;
+    Ok(advertisement::local_name(&value))
+}
+
+/// Stops scanning on `central` and disconnects its connected peripherals. Carries on when something
+/// fails, and returns the first error.
+pub(crate) async fn shutdown_central<C: Central>(central: &C) -> Result<()> {
+    // This fails if no scan was running, which is fine.
+    if let Err(e) = central.stop_scan().await {
+        log::debug!("Stopping the scan on shutdown failed: {}", e);
+    }
+    let mut result = Ok(());
+    for peripheral in central.peripherals().await? {
+        if peripheral.is_connected().await.unwrap_or(false) {
+            result = result.and(peripheral.disconnect().await);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_params_round_trip_priorities() {
+        for priority in [
+            ConnectionPriority::Balanced,
+            ConnectionPriority::High,
+            ConnectionPriority::LowPower,
+        ] {
+            assert_eq!(ConnectionParams::from(priority).priority(), priority);
+        }
+        let params = ConnectionParams {
+            min_interval: Duration::from_micros(7_500),
+            max_interval: Duration::from_millis(30),
+            latency: 0,
+            supervision_timeout: Duration::from_secs(2),
+        };
+        assert_eq!(params.priority(), ConnectionPriority::Balanced);
+    }
+
+    #[test]
+    fn retry_backoff_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        assert_eq!(policy.backoff(40), Duration::from_millis(350));
+    }
+
+    /// A policy which retries without waiting, and the number of attempts it made.
+    fn retry_test_policy() -> (RetryPolicy, std::sync::atomic::AtomicU32) {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        };
+        (policy, std::sync::atomic::AtomicU32::new(0))
+    }
+
+    #[tokio::test]
+    async fn retry_repeats_transient_errors() {
+        use std::sync::atomic::Ordering;
+        let (policy, attempts) = retry_test_policy();
+        let result = policy
+            .retry(
+                "Test",
+                || async {
+                    if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                        Err(Error::TimedOut(Duration::from_secs(1)))
+                    } else {
+                        Ok(42)
+                    }
+                },
+                || async { Ok(true) },
+            )
+            .await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let (policy, attempts) = retry_test_policy();
+        let result: Result<()> = policy
+            .retry(
+                "Test",
+                || async {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    Err(Error::Unreachable)
+                },
+                || async { Ok(true) },
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Unreachable)));
+        assert_eq!(attempts.load(Ordering::SeqCst), policy.max_attempts);
+    }
+
+    #[tokio::test]
+    async fn retry_returns_permanent_errors_immediately() {
+        use std::sync::atomic::Ordering;
+        let permanent: [fn() -> Error; 3] = [
+            || Error::PermissionDenied,
+            || Error::RuntimeError("GATT error".into()),
+            || Error::Other("org.bluez.Error.Failed".into()),
+        ];
+        for error in permanent {
+            let (policy, attempts) = retry_test_policy();
+            let result: Result<()> = policy
+                .retry(
+                    "Test",
+                    || async {
+                        attempts.fetch_add(1, Ordering::SeqCst);
+                        Err(error())
+                    },
+                    || async { Ok(true) },
+                )
+                .await;
+            assert!(result.is_err());
+            assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_stops_when_disconnected() {
+        use std::sync::atomic::Ordering;
+        let (policy, attempts) = retry_test_policy();
+        let result: Result<()> = policy
+            .retry(
+                "Test",
+                || async {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    Err(Error::TimedOut(Duration::from_secs(1)))
+                },
+                || async { Ok(false) },
+            )
+            .await;
+        assert!(matches!(result, Err(Error::NotConnected)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn scan_filter_matches_services_and_appearances() {
+        let heart_rate = Uuid::from_u128(0x180d);
+        let battery = Uuid::from_u128(0x180f);
+        let properties = PeripheralProperties {
+            services: vec![heart_rate],
+            service_data: [(battery, vec![100])].into_iter().collect(),
+            appearance: Some(0x0340),
+            ..Default::default()
+        };
+        assert!(ScanFilter::default().matches(&properties));
+        let filter = |services: Vec<Uuid>, appearances: Vec<u16>| ScanFilter {
+            services,
+            appearances,
+            ..Default::default()
+        };
+        assert!(filter(vec![heart_rate], vec![]).matches(&properties));
+        assert!(filter(vec![battery], vec![0x0340]).matches(&properties));
+        assert!(!filter(vec![Uuid::nil()], vec![]).matches(&properties));
+        assert!(!filter(vec![heart_rate], vec![0x0341]).matches(&properties));
+    }
+
+    #[test]
+    fn scan_filter_matches_manufacturer_data_names_and_addresses() {
+        let address = BDAddr::from([1, 2, 3, 4, 5, 6]);
+        let properties = PeripheralProperties {
+            address,
+            local_name: Some("Thermometer 42".to_string()),
+            manufacturer_data: [(0x004c, vec![0x02, 0x15, 0xaa])].into_iter().collect(),
+            ..Default::default()
+        };
+        let manufacturer = |company_id, data: &[u8], mask: &[u8]| ScanFilter {
+            manufacturer_data: vec![ManufacturerDataFilter {
+                company_id,
+                data: data.to_vec(),
+                mask: mask.to_vec(),
+            }],
+            ..Default::default()
+        };
+        assert!(manufacturer(0x004c, &[], &[]).matches(&properties));
+        assert!(manufacturer(0x004c, &[0x02, 0x15], &[]).matches(&properties));
+        assert!(manufacturer(0x004c, &[0x02, 0x10], &[0xff, 0xf0]).matches(&properties));
+        assert!(!manufacturer(0x004c, &[0x02, 0x10], &[]).matches(&properties));
+        assert!(!manufacturer(0x004c, &[0x02, 0x15, 0xaa, 0x00], &[]).matches(&properties));
+        assert!(!manufacturer(0x0059, &[], &[]).matches(&properties));
+
+        let names = |prefixes: &[&str]| ScanFilter {
+            name_prefixes: prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+            ..Default::default()
+        };
+        assert!(names(&["Heart", "Thermo"]).matches(&properties));
+        assert!(!names(&["Heart"]).matches(&properties));
+        assert!(!names(&["Thermo"]).matches(&PeripheralProperties::default()));
+
+        let addresses = |addresses: Vec<BDAddr>| ScanFilter {
+            addresses,
+            ..Default::default()
+        };
+        assert!(addresses(vec![BDAddr::default(), address]).matches(&properties));
+        assert!(!addresses(vec![BDAddr::default()]).matches(&properties));
+    }
+
+    #[test]
+    fn packet_of_prefers_advertisement() {
+        let structure = |ad_type| AdStructure {
+            ad_type,
+            data: vec![0],
+        };
+        let properties = PeripheralProperties {
+            advertisement_data: Some(vec![structure(advertisement::COMPLETE_LOCAL_NAME)]),
+            scan_response_data: Some(vec![
+                structure(advertisement::COMPLETE_LOCAL_NAME),
+                structure(advertisement::MANUFACTURER_SPECIFIC_DATA),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            properties.packet_of(advertisement::COMPLETE_LOCAL_NAME),
+            Some(AdvertisingPacket::Advertisement)
+        );
+        assert_eq!(
+            properties.packet_of(advertisement::MANUFACTURER_SPECIFIC_DATA),
+            Some(AdvertisingPacket::ScanResponse)
+        );
+        assert_eq!(properties.packet_of(advertisement::APPEARANCE), None);
+    }
 }
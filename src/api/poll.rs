@@ -0,0 +1,169 @@
+//! Watching characteristics which can't be subscribed to, see
+//! [`Peripheral::poll_characteristic`](super::Peripheral::poll_characteristic).
+
+use super::{Characteristic, Peripheral};
+use crate::Error;
+use futures::stream::{self, Stream};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+struct PollState<P> {
+    peripheral: P,
+    characteristic: Characteristic,
+    ticks: Interval,
+    last: Option<Vec<u8>>,
+}
+
+/// Reads `characteristic` every `interval`, yielding the first value and then every value which
+/// differs from the previous one. The stream ends once the peripheral is disconnected.
+pub(crate) fn poll_values<P: Peripheral + 'static>(
+    peripheral: P,
+    characteristic: Characteristic,
+    interval: Duration,
+) -> Pin<Box<dyn Stream<Item = Vec<u8>> + Send>> {
+    let mut ticks = time::interval(interval);
+    // A slow read postpones the following ones, rather than being followed by a burst.
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let state = PollState {
+        peripheral,
+        characteristic,
+        ticks,
+        last: None,
+    };
+    Box::pin(stream::unfold(state, |mut state| async move {
+        loop {
+            state.ticks.tick().await;
+            match state.peripheral.read(&state.characteristic).await {
+                Ok(value) => {
+                    if state.last.as_ref() != Some(&value) {
+                        state.last = Some(value.clone());
+                        return Some((value, state));
+                    }
+                }
+                Err(Error::NotConnected) => return None,
+                Err(e) => {
+                    if !state.peripheral.is_connected().await.unwrap_or(false) {
+                        return None;
+                    }
+                    log::debug!(
+                        "Polling {} failed, trying again: {}",
+                        state.characteristic.uuid,
+                        e
+                    );
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        BDAddr, CharPropFlags, Descriptor, PeripheralProperties, Service, ValueNotification,
+        WriteType,
+    };
+    use crate::platform::PeripheralId;
+    use crate::Result;
+    use async_trait::async_trait;
+    use futures::stream::StreamExt;
+    use std::collections::{BTreeSet, VecDeque};
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    /// A peripheral whose reads return queued results, and which disconnects once they run out.
+    #[derive(Clone, Debug)]
+    struct Reads(Arc<Mutex<VecDeque<Result<Vec<u8>>>>>);
+
+    #[async_trait]
+    impl Peripheral for Reads {
+        fn id(&self) -> PeripheralId {
+            unimplemented!()
+        }
+        fn address(&self) -> BDAddr {
+            BDAddr::default()
+        }
+        async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+            Ok(None)
+        }
+        fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+            None
+        }
+        fn services(&self) -> BTreeSet<Service> {
+            BTreeSet::new()
+        }
+        async fn is_connected(&self) -> Result<bool> {
+            Ok(!self.0.lock().unwrap().is_empty())
+        }
+        async fn mtu(&self, _characteristics: Option<&[Characteristic]>) -> Result<u16> {
+            Ok(23)
+        }
+        async fn connect(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn disconnect(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn discover_services(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn write(&self, _: &Characteristic, _: &[u8], _: WriteType) -> Result<()> {
+            Ok(())
+        }
+        async fn read(&self, _characteristic: &Characteristic) -> Result<Vec<u8>> {
+            self.0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or(Err(Error::NotConnected))
+        }
+        async fn read_cached(
+            &self,
+            characteristic: &Characteristic,
+            _: Duration,
+        ) -> Result<Vec<u8>> {
+            self.read(characteristic).await
+        }
+        async fn subscribe(&self, _characteristic: &Characteristic) -> Result<()> {
+            Ok(())
+        }
+        async fn unsubscribe(&self, _characteristic: &Characteristic) -> Result<()> {
+            Ok(())
+        }
+        async fn notifications(
+            &self,
+        ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+            Ok(Box::pin(stream::empty()))
+        }
+        async fn write_descriptor(&self, _descriptor: &Descriptor, _data: &[u8]) -> Result<()> {
+            Ok(())
+        }
+        async fn read_descriptor(&self, _descriptor: &Descriptor) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn yields_changed_values_until_disconnected() {
+        let reads = Reads(Arc::new(Mutex::new(VecDeque::from([
+            Ok(vec![1]),
+            Ok(vec![1]),
+            Err(Error::TimedOut(Duration::from_secs(1))),
+            Ok(vec![2]),
+            Ok(vec![2]),
+            Ok(vec![1]),
+        ]))));
+        let characteristic = Characteristic {
+            uuid: Uuid::nil(),
+            service_uuid: Uuid::nil(),
+            properties: CharPropFlags::READ,
+            descriptors: BTreeSet::new(),
+            handle: None,
+        };
+        let values: Vec<_> = poll_values(reads, characteristic, Duration::from_millis(1))
+            .collect()
+            .await;
+        assert_eq!(values, [vec![1], vec![2], vec![1]]);
+    }
+}
@@ -0,0 +1,287 @@
+//! Acting as a peripheral: advertising this device with
+//! [`Central::start_advertising`](super::Central::start_advertising), and publishing local GATT
+//! services which remote centrals connect to, see
+//! [`Central::gatt_server`](super::Central::gatt_server).
+
+use super::{bleuuid::uuid_from_u16, BDAddr, CharPropFlags, WriteType};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The data to advertise with [`Central::start_advertising`](super::Central::start_advertising).
+///
+/// Legacy advertisements carry at most 31 bytes, which a 128-bit service UUID and a short name
+/// already fill, so platforms fail to start advertising data which doesn't fit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvertisementData {
+    /// The name to advertise. Android advertises the name of the adapter instead, as apps can't
+    /// choose it, and Windows doesn't allow advertising a name at all.
+    pub local_name: Option<String>,
+    pub service_uuids: Vec<Uuid>,
+    /// Manufacturer specific data, keyed by company identifier.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// Whether remote centrals may connect, to use the services of a [`GattServer`]. Windows only
+    /// sends non-connectable advertisements.
+    pub connectable: bool,
+}
+
+/// A service to publish with [`GattServer::add_service`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalService {
+    pub uuid: Uuid,
+    /// Whether this is a primary service.
+    pub primary: bool,
+    pub characteristics: Vec<LocalCharacteristic>,
+}
+
+/// The UUID of the Client Characteristic Configuration descriptor, which the platforms add to
+/// characteristics which notify or indicate.
+const CLIENT_CHARACTERISTIC_CONFIGURATION: Uuid = uuid_from_u16(0x2902);
+
+impl LocalService {
+    /// Checks that the service can be published: each characteristic must allow remote centrals
+    /// some operation, must have a UUID no other characteristic of the service has, as requests
+    /// and notifications identify characteristics by UUID, and mustn't list a Client
+    /// Characteristic Configuration descriptor, which the platform adds itself.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let invalid = |characteristic: &LocalCharacteristic, reason| {
+            Err(Error::Other(
+                format!("Characteristic {} {}", characteristic.uuid, reason).into(),
+            ))
+        };
+        let mut uuids = HashSet::new();
+        for characteristic in &self.characteristics {
+            if !characteristic.properties.intersects(
+                CharPropFlags::READ
+                    | CharPropFlags::WRITE
+                    | CharPropFlags::WRITE_WITHOUT_RESPONSE
+                    | CharPropFlags::NOTIFY
+                    | CharPropFlags::INDICATE,
+            ) {
+                return invalid(characteristic, "can't be read, written or subscribed to");
+            }
+            if !uuids.insert(characteristic.uuid) {
+                return invalid(characteristic, "is in the service more than once");
+            }
+            if characteristic
+                .descriptors
+                .iter()
+                .any(|descriptor| descriptor.uuid == CLIENT_CHARACTERISTIC_CONFIGURATION)
+            {
+                return invalid(
+                    characteristic,
+                    "lists a Client Characteristic Configuration descriptor",
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A characteristic of a [`LocalService`]. Its value isn't stored by btleplug: reads and writes of
+/// remote centrals are passed to the [`RequestHandler`] of the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalCharacteristic {
+    pub uuid: Uuid,
+    /// The operations remote centrals may do. Characteristics which notify or indicate get a
+    /// Client Characteristic Configuration descriptor without it being listed in `descriptors`.
+    pub properties: CharPropFlags,
+    pub descriptors: Vec<LocalDescriptor>,
+}
+
+/// A descriptor of a [`LocalCharacteristic`], which remote centrals may read but not write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalDescriptor {
+    pub uuid: Uuid,
+    pub value: Vec<u8>,
+}
+
+/// A remote central reading a local characteristic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadRequest {
+    /// The address of the central, if the platform tells it.
+    pub client: Option<BDAddr>,
+    pub service_uuid: Uuid,
+    pub characteristic_uuid: Uuid,
+    /// The offset into the value at which to start, for values longer than fit into one response.
+    pub offset: u16,
+}
+
+/// A remote central writing a local characteristic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteRequest {
+    /// The address of the central, if the platform tells it.
+    pub client: Option<BDAddr>,
+    pub service_uuid: Uuid,
+    pub characteristic_uuid: Uuid,
+    /// The offset into the value at which to write `value`.
+    pub offset: u16,
+    pub value: Vec<u8>,
+    /// Whether the central waits for a response. The result of [`RequestHandler::write`] is only
+    /// sent to it for [`WriteType::WithResponse`].
+    pub write_type: WriteType,
+}
+
+/// The ATT error to answer a request of a remote central with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttError {
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InsufficientAuthentication,
+    RequestNotSupported,
+    InvalidOffset,
+    InvalidAttributeValueLength,
+    UnlikelyError,
+    /// An error defined by the application, from 0x80 to 0x9F.
+    Application(u8),
+}
+
+impl AttError {
+    /// The ATT error code.
+    pub fn code(self) -> u8 {
+        match self {
+            AttError::ReadNotPermitted => 0x02,
+            AttError::WriteNotPermitted => 0x03,
+            AttError::InsufficientAuthentication => 0x05,
+            AttError::RequestNotSupported => 0x06,
+            AttError::InvalidOffset => 0x07,
+            AttError::InvalidAttributeValueLength => 0x0D,
+            AttError::UnlikelyError => 0x0E,
+            AttError::Application(code) => code,
+        }
+    }
+}
+
+impl Display for AttError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ATT error {:#04x}", self.code())
+    }
+}
+
+/// Answers the requests of remote centrals for the characteristics of a [`LocalService`].
+///
+/// The methods are called from the thread on which the platform delivers the requests, and the
+/// remote central waits for their result, so they should return quickly: keep the values to serve
+/// at hand rather than fetching them on demand.
+pub trait RequestHandler: Send + Sync {
+    /// Returns the value of a characteristic, starting at `request.offset`. By default reads are
+    /// rejected.
+    fn read(&self, _request: &ReadRequest) -> std::result::Result<Vec<u8>, AttError> {
+        Err(AttError::ReadNotPermitted)
+    }
+
+    /// Handles a write of a characteristic. By default writes are rejected.
+    fn write(&self, _request: &WriteRequest) -> std::result::Result<(), AttError> {
+        Err(AttError::WriteNotPermitted)
+    }
+
+    /// Called when the first remote central subscribes to notifications or indications of a
+    /// characteristic (`subscribed` is true), and when the last one unsubscribes.
+    fn subscription_changed(
+        &self,
+        _service_uuid: Uuid,
+        _characteristic_uuid: Uuid,
+        _subscribed: bool,
+    ) {
+    }
+}
+
+/// A local GATT server, through which this device acts as a peripheral. Get one with
+/// [`Central::gatt_server`](super::Central::gatt_server).
+///
+/// Services stay published until they are removed, or the server is dropped.
+#[async_trait]
+pub trait GattServer: Send + Sync + Debug {
+    /// Publishes a service, whose reads and writes are answered by `handler`. Fails without
+    /// publishing anything if some characteristic of the service allows no operation, has the UUID
+    /// of another one, or lists a Client Characteristic Configuration descriptor.
+    async fn add_service(
+        &self,
+        service: LocalService,
+        handler: Arc<dyn RequestHandler>,
+    ) -> Result<()>;
+
+    /// Stops publishing the service with the given UUID. Returns
+    /// [`Error::NoSuchService`](crate::Error::NoSuchService) if it wasn't published.
+    async fn remove_service(&self, uuid: Uuid) -> Result<()>;
+
+    /// Sends a new value of a characteristic to the remote centrals which subscribed to it, as a
+    /// notification or, for characteristics which only indicate, as an indication.
+    async fn notify(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        value: &[u8],
+    ) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn characteristic(uuid: u16, properties: CharPropFlags) -> LocalCharacteristic {
+        LocalCharacteristic {
+            uuid: uuid_from_u16(uuid),
+            properties,
+            descriptors: Vec::new(),
+        }
+    }
+
+    fn service(characteristics: Vec<LocalCharacteristic>) -> LocalService {
+        LocalService {
+            uuid: uuid_from_u16(0x180F),
+            primary: true,
+            characteristics,
+        }
+    }
+
+    #[test]
+    fn valid_services_pass() {
+        let mut described = characteristic(0x2A19, CharPropFlags::READ | CharPropFlags::NOTIFY);
+        described.descriptors.push(LocalDescriptor {
+            uuid: uuid_from_u16(0x2901),
+            value: b"Battery".to_vec(),
+        });
+        let service = service(vec![
+            described,
+            characteristic(0x2A1A, CharPropFlags::WRITE_WITHOUT_RESPONSE),
+            characteristic(0x2A1B, CharPropFlags::INDICATE),
+        ]);
+        assert!(service.validate().is_ok());
+        assert!(self::service(Vec::new()).validate().is_ok());
+    }
+
+    #[test]
+    fn characteristics_without_operations_are_rejected() {
+        for properties in [
+            CharPropFlags::empty(),
+            CharPropFlags::BROADCAST | CharPropFlags::EXTENDED_PROPERTIES,
+        ] {
+            let service = service(vec![characteristic(0x2A19, properties)]);
+            assert!(service.validate().is_err(), "{:?}", properties);
+        }
+    }
+
+    #[test]
+    fn duplicate_characteristics_are_rejected() {
+        let service = service(vec![
+            characteristic(0x2A19, CharPropFlags::READ),
+            characteristic(0x2A19, CharPropFlags::WRITE),
+        ]);
+        assert!(service.validate().is_err());
+    }
+
+    #[test]
+    fn configuration_descriptors_are_rejected() {
+        let mut characteristic = characteristic(0x2A19, CharPropFlags::NOTIFY);
+        characteristic.descriptors.push(LocalDescriptor {
+            uuid: CLIENT_CHARACTERISTIC_CONFIGURATION,
+            value: vec![0, 0],
+        });
+        assert!(service(vec![characteristic]).validate().is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Presence tracking on top of [`CentralEvent`]s, see [`Central::watch`](super::Central::watch).
+
+use super::CentralEvent;
+use crate::platform::PeripheralId;
+use futures::future::{self, Either};
+use futures::stream::{self, Stream, StreamExt};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// The events generated by [`Central::watch`](super::Central::watch).
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// A peripheral was seen for the first time, or for the first time since it was lost.
+    Found(PeripheralId),
+    /// An advertisement was received from a peripheral which is already present.
+    Updated(PeripheralId),
+    /// Nothing was received from a peripheral for the absence timeout given to `watch`.
+    Lost(PeripheralId),
+}
+
+/// Keeps track of when each present peripheral was last seen.
+#[derive(Debug)]
+struct Tracker<K> {
+    lost_after: Duration,
+    last_seen: HashMap<K, Instant>,
+}
+
+impl<K: Clone + Eq + Hash> Tracker<K> {
+    fn new(lost_after: Duration) -> Self {
+        Self {
+            lost_after,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Records that a peripheral was seen, returning whether it wasn't present before.
+    fn seen(&mut self, id: K, now: Instant) -> bool {
+        self.last_seen.insert(id, now).is_none()
+    }
+
+    /// Forgets and returns the peripherals which weren't seen for `lost_after`.
+    fn expire(&mut self, now: Instant) -> Vec<K> {
+        let lost: Vec<K> = self
+            .last_seen
+            .iter()
+            .filter(|(_, &last_seen)| now.saturating_duration_since(last_seen) >= self.lost_after)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &lost {
+            self.last_seen.remove(id);
+        }
+        lost
+    }
+
+    /// Returns when the next peripheral is going to be lost, if any are present.
+    fn next_expiry(&self) -> Option<Instant> {
+        self.last_seen
+            .values()
+            .min()
+            .map(|last_seen| *last_seen + self.lost_after)
+    }
+}
+
+/// Turns a stream of central events into a stream of watch events, losing peripherals after
+/// `lost_after` without an advertisement.
+pub(crate) fn watch_events(
+    events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+    lost_after: Duration,
+) -> Pin<Box<dyn Stream<Item = WatchEvent> + Send>> {
+    Box::pin(stream::unfold(
+        (events, Tracker::new(lost_after), VecDeque::new()),
+        |(mut events, mut tracker, mut pending)| async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((event, (events, tracker, pending)));
+                }
+                let event = match tracker.next_expiry() {
+                    Some(expiry) => {
+                        match future::select(events.next(), Box::pin(time::sleep_until(expiry)))
+                            .await
+                        {
+                            Either::Left((event, _)) => event,
+                            Either::Right(_) => {
+                                pending.extend(
+                                    tracker
+                                        .expire(Instant::now())
+                                        .into_iter()
+                                        .map(WatchEvent::Lost),
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                    None => events.next().await,
+                }?;
+                if let Some(id) = event.advertisement_peripheral_id() {
+                    pending.push_back(match tracker.seen(id.clone(), Instant::now()) {
+                        true => WatchEvent::Found(id.clone()),
+                        false => WatchEvent::Updated(id.clone()),
+                    });
+                }
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn found_then_updated() {
+        let mut tracker = Tracker::new(Duration::from_secs(5));
+        let now = Instant::now();
+        assert!(tracker.seen(1, now));
+        assert!(!tracker.seen(1, now));
+        assert!(tracker.seen(2, now));
+    }
+
+    #[test]
+    fn lost_after_timeout() {
+        let mut tracker = Tracker::new(Duration::from_secs(5));
+        let start = Instant::now();
+        tracker.seen(1, start);
+        tracker.seen(2, start + Duration::from_secs(3));
+        assert_eq!(tracker.next_expiry(), Some(start + Duration::from_secs(5)));
+
+        assert!(tracker.expire(start + Duration::from_secs(4)).is_empty());
+        assert_eq!(tracker.expire(start + Duration::from_secs(5)), vec![1]);
+        assert_eq!(tracker.next_expiry(), Some(start + Duration::from_secs(8)));
+
+        // Found again after being lost.
+        assert!(tracker.seen(1, start + Duration::from_secs(6)));
+    }
+}
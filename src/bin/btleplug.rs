@@ -0,0 +1,295 @@
+//! Command line GATT explorer built on the public btleplug API. Enabled with the `cli` feature:
+//!
+//! ```sh
+//! cargo run --features cli -- scan
+//! ```
+//!
+//! See the "macOS permissions note" in README.md before running this on macOS Big Sur or later.
+
+use btleplug::api::{
+    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, ValueNotification,
+    WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::stream::StreamExt;
+use serde_json::json;
+use std::env;
+use std::error::Error;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use uuid::Uuid;
+
+const USAGE: &str = "\
+Usage: btleplug [OPTIONS] <COMMAND>
+
+Commands:
+  scan [SECONDS]                          Scan and list the peripherals found (default 5s)
+  connect <PERIPHERAL>                    Connect and show the connection state and MTU
+  services <PERIPHERAL>                   Connect and dump services, characteristics and descriptors
+  read <PERIPHERAL> <CHARACTERISTIC>      Read a characteristic and print its value as hex
+  write <PERIPHERAL> <CHARACTERISTIC> <HEX> [--no-response | --signed]
+                                          Write a hex encoded value to a characteristic
+  subscribe <PERIPHERAL> <CHARACTERISTIC> [COUNT]
+                                          Print notifications until COUNT are received or Ctrl-C
+
+PERIPHERAL is the address or platform ID of a peripheral, CHARACTERISTIC its UUID.
+
+Options:
+  --adapter <INDEX>   Use the adapter with this index instead of the first one
+  --json              Print results as JSON, one object per line
+  -h, --help          Print this help";
+
+/// How long to scan for a peripheral given on the command line before giving up.
+const FIND_TIMEOUT: Duration = Duration::from_secs(10);
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+struct Options {
+    adapter: usize,
+    json: bool,
+    command: Vec<String>,
+}
+
+fn parse_options() -> CliResult<Option<Options>> {
+    let mut options = Options {
+        adapter: 0,
+        json: false,
+        command: vec![],
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--json" => options.json = true,
+            "--adapter" => {
+                options.adapter = args.next().ok_or("--adapter needs a value")?.parse()?;
+            }
+            _ => options.command.push(arg),
+        }
+    }
+    Ok((!options.command.is_empty()).then_some(options))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_options() {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(options: &Options) -> CliResult<()> {
+    let manager = Manager::new().await?;
+    let adapter = manager
+        .adapters()
+        .await?
+        .into_iter()
+        .nth(options.adapter)
+        .ok_or("No such Bluetooth adapter")?;
+    let args: Vec<&str> = options.command.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["scan"] => scan(&adapter, Duration::from_secs(5), options.json).await,
+        ["scan", seconds] => {
+            scan(
+                &adapter,
+                Duration::from_secs(seconds.parse()?),
+                options.json,
+            )
+            .await
+        }
+        ["connect", peripheral] => {
+            let peripheral = connect(&adapter, peripheral).await?;
+            let mtu = peripheral.mtu(None).await.ok();
+            if options.json {
+                println!(
+                    "{}",
+                    json!({
+                        "id": peripheral.id().to_string(),
+                        "address": peripheral.address().to_string(),
+                        "connected": peripheral.is_connected().await?,
+                        "mtu": mtu,
+                    })
+                );
+            } else {
+                println!("Connected to {}", peripheral.address());
+                if let Some(mtu) = mtu {
+                    println!("MTU: {}", mtu);
+                }
+            }
+            Ok(())
+        }
+        ["services", peripheral] => {
+            let peripheral = connect(&adapter, peripheral).await?;
+            for service in peripheral.services() {
+                if options.json {
+                    println!("{}", serde_json::to_string(&service)?);
+                    continue;
+                }
+                let kind = if service.primary {
+                    "primary"
+                } else {
+                    "secondary"
+                };
+                println!("Service {} ({})", service.uuid, kind);
+                for characteristic in &service.characteristics {
+                    println!(
+                        "  Characteristic {} {:?}",
+                        characteristic.uuid, characteristic.properties
+                    );
+                    for descriptor in &characteristic.descriptors {
+                        println!("    Descriptor {}", descriptor.uuid);
+                    }
+                }
+            }
+            Ok(())
+        }
+        ["read", peripheral, characteristic] => {
+            let peripheral = connect(&adapter, peripheral).await?;
+            let characteristic = find_characteristic(&peripheral, characteristic)?;
+            let value = peripheral.read(&characteristic).await?;
+            print_value(characteristic.uuid, &value, options.json);
+            Ok(())
+        }
+        ["write", peripheral, characteristic, value, rest @ ..] => {
+            let write_type = match rest {
+                [] => WriteType::WithResponse,
+                ["--no-response"] => WriteType::WithoutResponse,
+                ["--signed"] => WriteType::Signed,
+                _ => return Err(format!("Unexpected arguments: {}", rest.join(" ")).into()),
+            };
+            let value = parse_hex(value)?;
+            let peripheral = connect(&adapter, peripheral).await?;
+            let characteristic = find_characteristic(&peripheral, characteristic)?;
+            peripheral
+                .write(&characteristic, &value, write_type)
+                .await?;
+            Ok(())
+        }
+        ["subscribe", peripheral, characteristic, rest @ ..] => {
+            let count = match rest {
+                [] => usize::MAX,
+                [count] => count.parse()?,
+                _ => return Err(format!("Unexpected arguments: {}", rest.join(" ")).into()),
+            };
+            let peripheral = connect(&adapter, peripheral).await?;
+            let characteristic = find_characteristic(&peripheral, characteristic)?;
+            let mut notifications = peripheral
+                .notifications()
+                .await?
+                .filter(|notification| {
+                    futures::future::ready(notification.uuid == characteristic.uuid)
+                })
+                .take(count);
+            peripheral.subscribe(&characteristic).await?;
+            while let Some(ValueNotification { uuid, value, .. }) = notifications.next().await {
+                print_value(uuid, &value, options.json);
+            }
+            peripheral.unsubscribe(&characteristic).await?;
+            Ok(())
+        }
+        _ => Err(format!("Unknown command: {}\n\n{}", args.join(" "), USAGE).into()),
+    }
+}
+
+async fn scan(adapter: &Adapter, duration: Duration, json: bool) -> CliResult<()> {
+    adapter.start_scan(ScanFilter::default()).await?;
+    time::sleep(duration).await;
+    adapter.stop_scan().await?;
+    for peripheral in adapter.peripherals().await? {
+        let Some(properties) = peripheral.properties().await? else {
+            continue;
+        };
+        if json {
+            println!(
+                "{}",
+                json!({ "id": peripheral.id().to_string(), "properties": *properties })
+            );
+        } else {
+            println!(
+                "{} {:>5} {}",
+                properties.address,
+                properties
+                    .rssi
+                    .map_or_else(|| "-".to_string(), |rssi| rssi.to_string()),
+                properties.local_name.as_deref().unwrap_or("(unknown)")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Finds the peripheral with the given address or ID, scanning for it if the adapter doesn't know
+/// it yet, then connects and discovers its services.
+async fn connect(adapter: &Adapter, name: &str) -> CliResult<Peripheral> {
+    let matches = |peripheral: &Peripheral| {
+        peripheral.address().to_string().eq_ignore_ascii_case(name)
+            || peripheral.id().to_string().eq_ignore_ascii_case(name)
+    };
+    let deadline = Instant::now() + FIND_TIMEOUT;
+    let mut scanning = false;
+    let peripheral = loop {
+        if let Some(peripheral) = adapter.peripherals().await?.into_iter().find(matches) {
+            break peripheral;
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("Peripheral {} not found", name).into());
+        }
+        if !scanning {
+            adapter.start_scan(ScanFilter::default()).await?;
+            scanning = true;
+        }
+        time::sleep(Duration::from_millis(500)).await;
+    };
+    if scanning {
+        adapter.stop_scan().await?;
+    }
+    if !peripheral.is_connected().await? {
+        peripheral.connect().await?;
+    }
+    peripheral.discover_services().await?;
+    Ok(peripheral)
+}
+
+fn find_characteristic(peripheral: &Peripheral, uuid: &str) -> CliResult<Characteristic> {
+    let uuid = Uuid::parse_str(uuid)?;
+    peripheral
+        .characteristics()
+        .into_iter()
+        .find(|characteristic| characteristic.uuid == uuid)
+        .ok_or_else(|| format!("Characteristic {} not found", uuid).into())
+}
+
+fn parse_hex(value: &str) -> CliResult<Vec<u8>> {
+    let value = value.trim_start_matches("0x");
+    if !value.is_ascii() || !value.len().is_multiple_of(2) {
+        return Err("Hex value needs an even number of digits".into());
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&value[i..i + 2], 16)?))
+        .collect()
+}
+
+fn print_value(uuid: Uuid, value: &[u8], json: bool) {
+    let hex: String = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+    if json {
+        println!("{}", json!({ "uuid": uuid, "value": hex }));
+    } else {
+        println!("{} {}", uuid, hex);
+    }
+}
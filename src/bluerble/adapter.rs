@@ -0,0 +1,263 @@
+use super::peripheral::{Peripheral, PeripheralId};
+use crate::api::{
+    BDAddr, Backend, BackendKind, Capabilities, Central, CentralEvent, CentralState, Feature,
+    Peripheral as _, ScanFilter, ScanSettings, ScanTransport,
+};
+use crate::common::adapter_manager::AdapterManager;
+use crate::interceptor::GattInterceptor;
+use crate::scan_session::ScanSession;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use bluer::{AdapterEvent, AdapterProperty, Address, DiscoveryFilter, DiscoveryTransport};
+use futures::stream::{Stream, StreamExt};
+use std::fmt::{self, Debug, Formatter};
+use std::fs;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::task::AbortHandle;
+
+/// Implementation of [api::Central](crate::api::Central).
+#[derive(Clone)]
+pub struct Adapter {
+    adapter: bluer::Adapter,
+    manager: Arc<AdapterManager<Peripheral>>,
+    // BlueZ keeps discovering for as long as the discovery stream returned by bluer is alive, so
+    // it's held by a task which is aborted to stop scanning.
+    discovery: Arc<Mutex<Option<AbortHandle>>>,
+}
+
+fn get_central_state(powered: bool) -> CentralState {
+    match powered {
+        true => CentralState::PoweredOn,
+        false => CentralState::PoweredOff,
+    }
+}
+
+impl Adapter {
+    pub(crate) async fn new(adapter: bluer::Adapter) -> Result<Self> {
+        let manager = Arc::new(AdapterManager::default());
+        // Subscribe before listing the known devices, so none can be missed in between.
+        let events = adapter.events().await?;
+        for address in adapter.device_addresses().await? {
+            let peripheral = Peripheral::new(&adapter, Arc::downgrade(&manager), address).await?;
+            manager.add_peripheral(peripheral);
+        }
+        tokio::spawn(handle_adapter_events(
+            adapter.clone(),
+            Arc::downgrade(&manager),
+            Box::pin(events),
+        ));
+        Ok(Self {
+            adapter,
+            manager,
+            discovery: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Returns the peripheral for the given address, looking it up in BlueZ if it's not known
+    /// (anymore).
+    async fn peripheral_for(&self, address: BDAddr) -> Result<Peripheral> {
+        if let Some(peripheral) = self.manager.peripheral(&address.into()) {
+            return Ok(peripheral);
+        }
+        let peripheral =
+            Peripheral::new(&self.adapter, Arc::downgrade(&self.manager), address.into()).await?;
+        if self.manager.peripheral(&address.into()).is_none() {
+            self.manager.add_peripheral(peripheral.clone());
+        }
+        Ok(peripheral)
+    }
+}
+
+async fn handle_adapter_events(
+    adapter: bluer::Adapter,
+    manager: Weak<AdapterManager<Peripheral>>,
+    mut events: Pin<Box<dyn Stream<Item = AdapterEvent> + Send>>,
+) {
+    while let Some(event) = events.next().await {
+        let Some(manager) = manager.upgrade() else {
+            break;
+        };
+        match event {
+            AdapterEvent::DeviceAdded(address) => {
+                let id = PeripheralId::from(BDAddr::from(address));
+                let peripheral = match manager.peripheral(&id) {
+                    Some(peripheral) => peripheral,
+                    None => {
+                        match Peripheral::new(&adapter, Arc::downgrade(&manager), address).await {
+                            Ok(peripheral) => {
+                                manager.add_peripheral(peripheral.clone());
+                                peripheral
+                            }
+                            Err(e) => {
+                                log::debug!("Failed to add device {}: {:?}", address, e);
+                                continue;
+                            }
+                        }
+                    }
+                };
+                manager.emit_with_properties(
+                    CentralEvent::DeviceDiscovered(id),
+                    peripheral.cached_properties().as_deref(),
+                );
+            }
+            AdapterEvent::PropertyChanged(AdapterProperty::Powered(powered)) => {
+                manager.emit(CentralEvent::StateUpdate(get_central_state(powered)));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Debug for Adapter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Adapter")
+            .field("name", &self.adapter.name())
+            .field("manager", &self.manager)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl Central for Adapter {
+    type Peripheral = Peripheral;
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(self.manager.event_stream())
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.start_scan_with_settings(filter, ScanSettings::default())
+            .await
+    }
+
+    async fn start_scan_with_settings(
+        &self,
+        filter: ScanFilter,
+        settings: ScanSettings,
+    ) -> Result<()> {
+        self.manager.set_scan_filter(filter.clone());
+        let discovery_filter = DiscoveryFilter {
+            uuids: filter.services.into_iter().collect(),
+            duplicate_data: settings.duplicate_data.unwrap_or(true),
+            transport: match settings.transport.unwrap_or(ScanTransport::Auto) {
+                ScanTransport::Auto => DiscoveryTransport::Auto,
+                ScanTransport::Le => DiscoveryTransport::Le,
+                ScanTransport::BrEdr => DiscoveryTransport::BrEdr,
+            },
+            discoverable: settings.discoverable.unwrap_or(false),
+            pattern: settings.pattern,
+            ..Default::default()
+        };
+        self.adapter.set_discovery_filter(discovery_filter).await?;
+        // Devices are picked up from the adapter events, the discovery stream only needs to be
+        // kept alive.
+        let discovery = self.adapter.discover_devices().await?;
+        let task = tokio::spawn(async move {
+            let mut discovery = Box::pin(discovery);
+            while discovery.next().await.is_some() {}
+        });
+        if let Some(previous) = self
+            .discovery
+            .lock()
+            .map_err(Into::<Error>::into)?
+            .replace(task.abort_handle())
+        {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        if let Some(discovery) = self.discovery.lock().map_err(Into::<Error>::into)?.take() {
+            discovery.abort();
+        }
+        self.manager.set_scan_filter(ScanFilter::default());
+        Ok(())
+    }
+
+    async fn start_scan_session(&self, filter: ScanFilter) -> Result<ScanSession> {
+        self.manager
+            .scan_sessions()
+            .start(self.clone(), filter)
+            .await
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Peripheral>> {
+        Ok(self.manager.peripherals())
+    }
+
+    async fn peripheral(&self, id: &PeripheralId) -> Result<Peripheral> {
+        self.peripheral_for(id.0).await
+    }
+
+    async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
+        Err(Error::NotSupported {
+            feature: Feature::AddPeripheral,
+        })
+    }
+
+    async fn paired_peripherals(&self) -> Result<Vec<Peripheral>> {
+        let mut peripherals = vec![];
+        for address in self.adapter.device_addresses().await? {
+            let peripheral = self.peripheral_for(address.into()).await?;
+            if peripheral.is_paired().await? {
+                peripherals.push(peripheral);
+            }
+        }
+        Ok(peripherals)
+    }
+
+    async fn adapter_info(&self) -> Result<String> {
+        let address = self.adapter.address().await?;
+        Ok(format!("{} ({})", self.adapter.name(), address))
+    }
+
+    async fn adapter_state(&self) -> Result<CentralState> {
+        Ok(get_central_state(
+            self.adapter.is_powered().await.unwrap_or(false),
+        ))
+    }
+
+    async fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::BACKGROUND_SCAN
+            | Capabilities::SCAN_SETTINGS
+            | Capabilities::PAIRED_PERIPHERALS
+            | Capabilities::EVENT_REPLAY
+            | Capabilities::MTU
+            | Capabilities::SCAN_SESSIONS
+            | Capabilities::SIGNED_WRITE
+            | Capabilities::PAIRING_STATE
+            | Capabilities::GATT_INTERCEPTORS
+            | Capabilities::LONG_VALUE_CHUNKING)
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.manager.interceptors().add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self.manager.interceptors().remove(interceptor))
+    }
+
+    fn backend(&self) -> Backend {
+        Backend {
+            kind: BackendKind::BlueZ,
+            version: fs::read_to_string("/proc/sys/kernel/osrelease")
+                .ok()
+                .map(|release| release.trim().to_string()),
+        }
+    }
+
+    async fn set_event_replay_capacity(&self, capacity: usize) -> Result<()> {
+        self.manager.set_replay_capacity(capacity);
+        Ok(())
+    }
+}
+
+impl From<Address> for PeripheralId {
+    fn from(address: Address) -> Self {
+        PeripheralId(address.into())
+    }
+}
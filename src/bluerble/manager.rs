@@ -0,0 +1,81 @@
+use super::adapter::Adapter;
+use crate::api::{self, Central};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use bluer::Session;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Implementation of [api::Manager](crate::api::Manager).
+#[derive(Clone)]
+pub struct Manager {
+    session: Session,
+    // Adapters keep track of their peripherals, so hand out the same adapter for each name.
+    adapters: Arc<Mutex<HashMap<String, Adapter>>>,
+}
+
+impl Manager {
+    pub async fn new() -> Result<Self> {
+        Ok(Self {
+            session: Session::new().await?,
+            adapters: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+}
+
+impl Debug for Manager {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Manager").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl api::Manager for Manager {
+    type Adapter = Adapter;
+
+    async fn adapters(&self) -> Result<Vec<Adapter>> {
+        let mut result = vec![];
+        for name in self.session.adapter_names().await? {
+            let known = self
+                .adapters
+                .lock()
+                .map_err(Into::<Error>::into)?
+                .get(&name)
+                .cloned();
+            let adapter = match known {
+                Some(adapter) => adapter,
+                None => {
+                    let adapter = Adapter::new(self.session.adapter(&name)?).await?;
+                    self.adapters
+                        .lock()
+                        .map_err(Into::<Error>::into)?
+                        .insert(name, adapter.clone());
+                    adapter
+                }
+            };
+            result.push(adapter);
+        }
+        Ok(result)
+    }
+
+    /// Stops scanning, and disconnects the devices connected through btleplug. Devices connected
+    /// by other applications stay connected.
+    async fn shutdown(&self) -> Result<()> {
+        let adapters: Vec<Adapter> = self
+            .adapters
+            .lock()
+            .map_err(Into::<Error>::into)?
+            .drain()
+            .map(|(_, adapter)| adapter)
+            .collect();
+        let mut result = Ok(());
+        for adapter in adapters {
+            adapter.stop_scan().await?;
+            for peripheral in adapter.peripherals().await? {
+                result = result.and(peripheral.shutdown().await);
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,43 @@
+//! Linux backend built on [bluer](https://crates.io/crates/bluer), the official Rust bindings for
+//! BlueZ. Enabled with the `bluer` feature in place of the default `bluez` feature.
+
+pub mod adapter;
+pub mod manager;
+pub mod peripheral;
+
+use crate::{api::BDAddr, Error};
+use bluer::{Address, ErrorKind};
+
+impl From<bluer::Error> for Error {
+    fn from(error: bluer::Error) -> Self {
+        match error.kind {
+            ErrorKind::NotAuthorized | ErrorKind::NotPermitted => Error::PermissionDenied,
+            ErrorKind::DoesNotExist => Error::DeviceNotFound,
+            ErrorKind::ConnectionAttemptFailed => Error::ConnectionFailed(error.message),
+            _ => Error::Other(Box::new(error)),
+        }
+    }
+}
+
+/// Whether a platform error wrapped in [`Error::Other`] may go away when retried.
+pub(crate) fn is_transient_error(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<bluer::Error>(),
+        Some(bluer::Error {
+            kind: ErrorKind::InProgress,
+            ..
+        })
+    )
+}
+
+impl From<Address> for BDAddr {
+    fn from(address: Address) -> Self {
+        BDAddr::from(address.0)
+    }
+}
+
+impl From<BDAddr> for Address {
+    fn from(address: BDAddr) -> Self {
+        Address(address.into_inner())
+    }
+}
@@ -0,0 +1,841 @@
+use crate::api::{
+    self,
+    advertisement::{self, AdStructure},
+    AddressType, BDAddr, CentralEvent, CharPropFlags, Characteristic, Descriptor, Feature,
+    NameSource, PeripheralProperties, Service, ValueNotification, WriteType,
+};
+use crate::common::{
+    adapter_manager::AdapterManager,
+    long_value,
+    util::notifications_stream_from_broadcast_receiver,
+    value_cache::{CharacteristicKey, ValueCache},
+};
+use crate::gatt_log::{self, GattOperation};
+use crate::interceptor::{GattInterceptor, GattRequest, InterceptorList, Interceptors};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use bluer::gatt::remote::{
+    Characteristic as BluerCharacteristic, CharacteristicReadRequest, CharacteristicWriteRequest,
+    Descriptor as BluerDescriptor, Service as BluerService,
+};
+use bluer::gatt::{CharacteristicFlags, WriteOp};
+use bluer::{Address, Device, DeviceEvent, DeviceProperty};
+use futures::stream::{Stream, StreamExt};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::AbortHandle;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+/// How long [`Peripheral::discover_services`] waits for BlueZ to resolve the services of a device.
+const SERVICES_RESOLVED_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct PeripheralId(pub(super) BDAddr);
+
+impl Display for PeripheralId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl From<BDAddr> for PeripheralId {
+    fn from(address: BDAddr) -> Self {
+        PeripheralId(address)
+    }
+}
+
+struct CharacteristicInternal {
+    uuid: Uuid,
+    characteristic: BluerCharacteristic,
+    properties: CharPropFlags,
+    descriptors: Vec<(Uuid, BluerDescriptor)>,
+}
+
+impl CharacteristicInternal {
+    /// The handle of the value of the characteristic. BlueZ identifies characteristics by the
+    /// handle of their declaration, which the value always follows.
+    fn handle(&self) -> Option<u16> {
+        self.characteristic.id().checked_add(1)
+    }
+}
+
+struct ServiceInternal {
+    uuid: Uuid,
+    /// The handle of the service declaration, by which BlueZ identifies the service.
+    handle: u16,
+    primary: bool,
+    // A service may have several characteristics with the same UUID, which only their handles
+    // tell apart.
+    characteristics: Vec<CharacteristicInternal>,
+}
+
+/// Implementation of [api::Peripheral](crate::api::Peripheral).
+#[derive(Clone)]
+pub struct Peripheral {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    device: Device,
+    adapter: Weak<AdapterManager<Peripheral>>,
+    address: BDAddr,
+    properties: Mutex<Arc<PeripheralProperties>>,
+    // The GAP device name read through btleplug, along with the name BlueZ had for the device at
+    // the time.
+    device_name: Mutex<Option<(String, Option<String>)>>,
+    // A device may have several instances of a service, with the same UUID.
+    services: Mutex<Vec<ServiceInternal>>,
+    notifications_channel: broadcast::Sender<Option<ValueNotification>>,
+    // The tasks forwarding notifications of subscribed characteristics. BlueZ stops notifying
+    // when the stream returned by bluer is dropped, which happens when the task is aborted. They
+    // are keyed by the identifier bluer gives the characteristic, which tells apart instances.
+    subscriptions: Mutex<HashMap<u16, AbortHandle>>,
+    value_cache: ValueCache,
+    interceptors: Arc<InterceptorList>,
+    // BlueZ connections are system wide, so keep track of whether btleplug connected the device:
+    // only those connections are closed on shutdown.
+    connected: AtomicBool,
+    long_value_chunking: AtomicBool,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        for subscription in self.subscriptions.get_mut().unwrap().values() {
+            subscription.abort();
+        }
+        if *self.connected.get_mut() {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                let device = self.device.clone();
+                runtime.spawn(async move {
+                    if let Err(e) = device.disconnect().await {
+                        log::warn!("Failed to disconnect {} on drop: {}", device.address(), e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl Peripheral {
+    pub(crate) async fn new(
+        adapter: &bluer::Adapter,
+        manager: Weak<AdapterManager<Peripheral>>,
+        address: Address,
+    ) -> Result<Self> {
+        let device = adapter.device(address)?;
+        let (notifications_channel, _) = broadcast::channel(16);
+        let peripheral = Peripheral {
+            shared: Arc::new(Shared {
+                properties: Mutex::new(Arc::new(PeripheralProperties {
+                    address: address.into(),
+                    ..Default::default()
+                })),
+                device_name: Mutex::new(None),
+                device,
+                adapter: manager,
+                address: address.into(),
+                services: Mutex::new(Vec::new()),
+                notifications_channel,
+                subscriptions: Mutex::new(HashMap::new()),
+                value_cache: ValueCache::new(),
+                interceptors: Arc::new(InterceptorList::default()),
+                connected: AtomicBool::new(false),
+                long_value_chunking: AtomicBool::new(false),
+            }),
+        };
+        // Subscribe before reading the properties, so no change can be missed in between.
+        let events = peripheral.shared.device.events().await?;
+        api::Peripheral::properties(&peripheral).await?;
+        tokio::spawn(handle_device_events(
+            Arc::downgrade(&peripheral.shared),
+            Box::pin(events),
+        ));
+        Ok(peripheral)
+    }
+
+    /// Stops the notification subscriptions, and disconnects the device if btleplug connected it.
+    pub(crate) async fn shutdown(&self) -> Result<()> {
+        for (_, subscription) in self.shared.subscriptions.lock().unwrap().drain() {
+            subscription.abort();
+        }
+        if self.shared.connected.swap(false, Ordering::Relaxed) {
+            self.shared.device.disconnect().await?;
+        }
+        Ok(())
+    }
+
+    fn interceptors(&self) -> Interceptors {
+        Interceptors::new(
+            self.shared
+                .adapter
+                .upgrade()
+                .map(|manager| manager.interceptors().clone()),
+            self.shared.interceptors.clone(),
+        )
+    }
+
+    /// Looks up the instances of a characteristic by its UUID, and by its handle if it has one, in
+    /// every instance of the service. Returns the first value `f` gives for one of them.
+    fn characteristic_internal<T>(
+        &self,
+        service_uuid: &Uuid,
+        characteristic_uuid: &Uuid,
+        handle: Option<u16>,
+        f: impl FnMut(&CharacteristicInternal) -> Option<T>,
+    ) -> Result<T> {
+        let services = self.shared.services.lock().map_err(Into::<Error>::into)?;
+        let mut instances = services
+            .iter()
+            .filter(|service| service.uuid == *service_uuid)
+            .peekable();
+        if instances.peek().is_none() {
+            return Err(Error::NoSuchService);
+        }
+        // A descriptor may be under any instance of the characteristic.
+        let mut characteristics = instances
+            .flat_map(|service| service.characteristics.iter())
+            .filter(|internal| {
+                internal.uuid == *characteristic_uuid
+                    && (handle.is_none() || internal.handle() == handle)
+            })
+            .peekable();
+        if characteristics.peek().is_none() {
+            return Err(Error::NoSuchCharacteristic);
+        }
+        characteristics.find_map(f).ok_or(Error::NoSuchDescriptor)
+    }
+
+    async fn read_characteristic_value(
+        &self,
+        characteristic: &Characteristic,
+        bluer_characteristic: &BluerCharacteristic,
+    ) -> Result<Vec<u8>> {
+        let Some(mtu) = self.chunking_mtu(characteristic).await else {
+            return Ok(bluer_characteristic.read().await?);
+        };
+        long_value::read_long(long_value::read_chunk_len(mtu), |offset| async move {
+            let request = CharacteristicReadRequest {
+                offset: offset as u16,
+                ..Default::default()
+            };
+            Ok(bluer_characteristic.read_ext(&request).await?)
+        })
+        .await
+    }
+
+    /// The MTU to split long values of `characteristic` up by, if chunking was turned on and
+    /// BlueZ knows the MTU.
+    async fn chunking_mtu(&self, characteristic: &Characteristic) -> Option<u16> {
+        if !self.shared.long_value_chunking.load(Ordering::Relaxed) {
+            return None;
+        }
+        api::Peripheral::mtu(self, Some(std::slice::from_ref(characteristic)))
+            .await
+            .ok()
+    }
+
+    fn bluer_characteristic(&self, characteristic: &Characteristic) -> Result<BluerCharacteristic> {
+        self.characteristic_internal(
+            &characteristic.service_uuid,
+            &characteristic.uuid,
+            characteristic.handle,
+            |internal| Some(internal.characteristic.clone()),
+        )
+    }
+
+    fn bluer_descriptor(&self, descriptor: &Descriptor) -> Result<BluerDescriptor> {
+        self.characteristic_internal(
+            &descriptor.service_uuid,
+            &descriptor.characteristic_uuid,
+            None,
+            |internal| {
+                internal
+                    .descriptors
+                    .iter()
+                    .find(|(uuid, bluer_descriptor)| {
+                        *uuid == descriptor.uuid
+                            && (descriptor.handle.is_none()
+                                || Some(bluer_descriptor.id()) == descriptor.handle)
+                    })
+                    .map(|(_, bluer_descriptor)| bluer_descriptor.clone())
+            },
+        )
+    }
+
+    /// Waits for BlueZ to signal that it has resolved the services of the device, if it's
+    /// connected and hasn't done so already.
+    async fn await_services_resolved(&self) -> Result<()> {
+        let device = &self.shared.device;
+        // Subscribe before checking the current state, so the signal can't be missed in between.
+        let mut events = Box::pin(device.events().await?);
+        if !device.is_connected().await? || device.is_services_resolved().await? {
+            return Ok(());
+        }
+        timeout(SERVICES_RESOLVED_TIMEOUT, async {
+            while let Some(DeviceEvent::PropertyChanged(property)) = events.next().await {
+                match property {
+                    DeviceProperty::ServicesResolved(true) => return Ok(()),
+                    DeviceProperty::Connected(false) => return Err(Error::NotConnected),
+                    _ => {}
+                }
+            }
+            Err(Error::NotConnected)
+        })
+        .await
+        .unwrap_or(Err(Error::TimedOut(SERVICES_RESOLVED_TIMEOUT)))
+    }
+
+    async fn discover_service(service: &BluerService) -> Result<ServiceInternal> {
+        let mut characteristics = Vec::new();
+        for characteristic in service.characteristics().await? {
+            let mut descriptors = Vec::new();
+            for descriptor in characteristic.descriptors().await.unwrap_or_default() {
+                if let Ok(descriptor_uuid) = descriptor.uuid().await {
+                    descriptors.push((descriptor_uuid, descriptor));
+                }
+            }
+            characteristics.push(CharacteristicInternal {
+                uuid: characteristic.uuid().await?,
+                properties: characteristic.flags().await?.into(),
+                characteristic,
+                descriptors,
+            });
+        }
+        Ok(ServiceInternal {
+            uuid: service.uuid().await?,
+            handle: service.id(),
+            primary: service.primary().await?,
+            characteristics,
+        })
+    }
+
+    fn emit_event(&self, event: CentralEvent) {
+        self.shared.emit_event(event)
+    }
+}
+
+impl Shared {
+    /// Replaces the name from BlueZ with the device name read through btleplug, unless BlueZ got
+    /// a newer name since.
+    fn apply_device_name(&self, properties: &mut PeripheralProperties) {
+        let mut device_name = self.device_name.lock().unwrap();
+        match &*device_name {
+            Some((name, bluez_name)) if *bluez_name == properties.local_name => {
+                properties.local_name = Some(name.clone());
+                properties.local_name_source = Some(NameSource::DeviceName);
+            }
+            Some(_) => *device_name = None,
+            None => {}
+        }
+    }
+
+    fn emit_event(&self, event: CentralEvent) {
+        if let Some(manager) = self.adapter.upgrade() {
+            let properties = self.properties.lock().unwrap().clone();
+            manager.emit_with_properties(event, Some(&properties));
+        } else {
+            log::trace!("Could not emit an event. AdapterManager has been dropped");
+        }
+    }
+}
+
+/// Keeps the cached properties of a peripheral up to date, and turns the changes into central
+/// events. Runs until the peripheral is dropped.
+async fn handle_device_events(
+    shared: Weak<Shared>,
+    mut events: Pin<Box<dyn Stream<Item = DeviceEvent> + Send>>,
+) {
+    while let Some(DeviceEvent::PropertyChanged(property)) = events.next().await {
+        let Some(shared) = shared.upgrade() else {
+            break;
+        };
+        let id = PeripheralId(shared.address);
+        let event = {
+            let mut guard = shared.properties.lock().unwrap();
+            let properties = Arc::make_mut(&mut guard);
+            match property {
+                DeviceProperty::Rssi(rssi) => {
+                    properties.rssi = Some(rssi);
+                    Some(CentralEvent::DeviceUpdated(id))
+                }
+                DeviceProperty::ManufacturerData(manufacturer_data) => {
+                    properties.manufacturer_data = manufacturer_data.clone();
+                    Some(CentralEvent::ManufacturerDataAdvertisement {
+                        id,
+                        manufacturer_data,
+                    })
+                }
+                DeviceProperty::ServiceData(service_data) => {
+                    properties.service_data = service_data.clone();
+                    Some(CentralEvent::ServiceDataAdvertisement { id, service_data })
+                }
+                DeviceProperty::Uuids(services) => {
+                    properties.services = services.into_iter().collect();
+                    Some(CentralEvent::ServicesAdvertisement {
+                        id,
+                        services: properties.services.clone(),
+                    })
+                }
+                DeviceProperty::Connected(true) => Some(CentralEvent::DeviceConnected(id)),
+                DeviceProperty::Connected(false) => {
+                    // End all notification streams.
+                    let _ = shared.notifications_channel.send(None);
+                    for (_, subscription) in shared.subscriptions.lock().unwrap().drain() {
+                        subscription.abort();
+                    }
+                    Some(CentralEvent::DeviceDisconnected(id))
+                }
+                DeviceProperty::ServicesResolved(true) => Some(CentralEvent::ServicesResolved(id)),
+                DeviceProperty::Name(name) => {
+                    properties.local_name = Some(name);
+                    properties.local_name_source = Some(NameSource::Platform);
+                    shared.apply_device_name(properties);
+                    None
+                }
+                property => {
+                    apply_property(properties, property);
+                    None
+                }
+            }
+        };
+        let Some(event) = event else {
+            continue;
+        };
+        if let Some(manager) = shared.adapter.upgrade() {
+            // The AdapterManager forgets peripherals when they disconnect, so bring it back once
+            // BlueZ reports on it again.
+            if !matches!(event, CentralEvent::DeviceDisconnected(_))
+                && manager.peripheral(&PeripheralId(shared.address)).is_none()
+            {
+                manager.add_peripheral(Peripheral {
+                    shared: shared.clone(),
+                });
+            }
+        }
+        shared.emit_event(event);
+    }
+}
+
+fn apply_property(properties: &mut PeripheralProperties, property: DeviceProperty) {
+    match property {
+        DeviceProperty::AddressType(address_type) => {
+            properties.address_type = match address_type {
+                bluer::AddressType::LePublic => Some(AddressType::Public),
+                bluer::AddressType::LeRandom => Some(AddressType::Random),
+                _ => None,
+            };
+        }
+        DeviceProperty::Name(name) => {
+            properties.local_name = Some(name);
+            properties.local_name_source = Some(NameSource::Platform);
+        }
+        DeviceProperty::TxPower(tx_power_level) => properties.tx_power_level = Some(tx_power_level),
+        DeviceProperty::Rssi(rssi) => properties.rssi = Some(rssi),
+        DeviceProperty::ManufacturerData(manufacturer_data) => {
+            properties.manufacturer_data = manufacturer_data
+        }
+        DeviceProperty::ServiceData(service_data) => properties.service_data = service_data,
+        DeviceProperty::Uuids(services) => properties.services = services.into_iter().collect(),
+        DeviceProperty::Class(class) => properties.class = Some(class),
+        DeviceProperty::Appearance(appearance) => properties.appearance = Some(appearance),
+        DeviceProperty::Paired(paired) => properties.is_paired = Some(paired),
+        DeviceProperty::AdvertisingData(advertising_data) => {
+            // BlueZ parses the common AD types itself, and only hands over the others.
+            let advertisement = advertisement::parse_structures(
+                advertising_data
+                    .into_iter()
+                    .map(|(ad_type, data)| AdStructure { ad_type, data }),
+            );
+            properties.uri = advertisement.uri;
+            properties.le_supported_features = advertisement.le_supported_features;
+            properties.advertised_interval = advertisement.advertising_interval;
+            properties.public_target_addresses = advertisement.public_target_addresses;
+        }
+        _ => {}
+    }
+}
+
+impl Debug for Peripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Peripheral")
+            .field("address", &self.shared.address)
+            .field("properties", &self.shared.properties.lock().unwrap())
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl api::Peripheral for Peripheral {
+    fn id(&self) -> PeripheralId {
+        PeripheralId(self.shared.address)
+    }
+
+    fn address(&self) -> BDAddr {
+        self.shared.address
+    }
+
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+        let mut properties = PeripheralProperties {
+            address: self.shared.address,
+            ..Default::default()
+        };
+        for property in self.shared.device.all_properties().await? {
+            apply_property(&mut properties, property);
+        }
+        self.shared.apply_device_name(&mut properties);
+        let properties = Arc::new(properties);
+        *self.shared.properties.lock().map_err(Into::<Error>::into)? = properties.clone();
+        Ok(Some(properties))
+    }
+
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        Some(self.shared.properties.lock().unwrap().clone())
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.shared
+            .services
+            .lock()
+            .unwrap()
+            .iter()
+            .map(make_service)
+            .collect()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.shared.device.is_connected().await?)
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        Ok(self.shared.device.is_paired().await?)
+    }
+
+    async fn mtu(&self, characteristics: Option<&[Characteristic]>) -> Result<u16> {
+        if self
+            .shared
+            .services
+            .lock()
+            .map_err(Into::<Error>::into)?
+            .is_empty()
+        {
+            self.discover_services().await?;
+        }
+        let bluer_characteristics = match characteristics {
+            Some(characteristics) => characteristics
+                .iter()
+                .map(|characteristic| self.bluer_characteristic(characteristic))
+                .collect::<Result<Vec<_>>>()?,
+            None => self
+                .shared
+                .services
+                .lock()
+                .map_err(Into::<Error>::into)?
+                .iter()
+                .flat_map(|service| service.characteristics.iter())
+                .map(|internal| internal.characteristic.clone())
+                .collect(),
+        };
+        // The MTU is the same for the whole connection, so without characteristics to ask the
+        // first one which reports it will do.
+        let mut max_mtu = None;
+        for characteristic in bluer_characteristics {
+            if let Ok(mtu) = characteristic.mtu().await {
+                let mtu = u16::try_from(mtu).unwrap_or(u16::MAX);
+                max_mtu = Some(max_mtu.map_or(mtu, |current: u16| current.max(mtu)));
+                if characteristics.is_none() {
+                    break;
+                }
+            }
+        }
+        max_mtu.ok_or(Error::NotSupported {
+            feature: Feature::Mtu,
+        })
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.shared.device.connect().await?;
+        self.shared.connected.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.shared.device.disconnect().await?;
+        self.shared.connected.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.await_services_resolved().await?;
+        let mut services = Vec::new();
+        for service in self.shared.device.services().await? {
+            services.push(Self::discover_service(&service).await?);
+        }
+        *self.shared.services.lock().map_err(Into::<Error>::into)? = services;
+        Ok(())
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        let op_type = match write_type {
+            WriteType::WithResponse => WriteOp::Request,
+            WriteType::WithoutResponse => WriteOp::Command,
+            // BlueZ signs Write Commands to characteristics with the authenticated signed writes
+            // property by itself, when the link isn't encrypted.
+            WriteType::Signed => WriteOp::Command,
+        };
+        let bluer_characteristic = self.bluer_characteristic(characteristic)?;
+        let id = self.id();
+        let request = GattRequest::characteristic(&id, characteristic, write_type.into());
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                gatt_log::log_traffic(&self.id(), characteristic.uuid, write_type.into(), &data);
+                // BlueZ does a long write by itself when the value doesn't fit in the MTU.
+                let request = CharacteristicWriteRequest {
+                    op_type,
+                    ..Default::default()
+                };
+                Ok(bluer_characteristic.write_ext(&data, &request).await?)
+            })
+            .await
+    }
+
+    async fn set_long_value_chunking(&self, enabled: bool) -> Result<()> {
+        self.shared
+            .long_value_chunking
+            .store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let bluer_characteristic = self.bluer_characteristic(characteristic)?;
+        let id = self.id();
+        let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+        self.interceptors()
+            .read(&request, async {
+                let value = self
+                    .read_characteristic_value(characteristic, &bluer_characteristic)
+                    .await?;
+                gatt_log::log_traffic(&id, characteristic.uuid, GattOperation::Read, &value);
+                self.shared
+                    .value_cache
+                    .insert(characteristic, value.clone());
+                Ok(value)
+            })
+            .await
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        if let Some(value) = self.shared.value_cache.get(characteristic, max_age) {
+            // The cache holds values as received, so they go through the interceptors again.
+            let id = self.id();
+            let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+            return self
+                .interceptors()
+                .read(&request, async { Ok(value) })
+                .await;
+        }
+        self.read(characteristic).await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        let device = &self.shared.device;
+        let name = match api::read_gap_device_name(self).await {
+            Ok(name) => name,
+            Err(Error::NoSuchService) => {
+                // BlueZ keeps the GAP service to itself, and sets the name of the device from the
+                // Device Name characteristic when connecting.
+                if !device.is_connected().await? {
+                    return Err(Error::NotConnected);
+                }
+                device.name().await?.ok_or(Error::NoSuchService)?
+            }
+            Err(e) => return Err(e),
+        };
+        *self.shared.device_name.lock().unwrap() = Some((name.clone(), device.name().await?));
+        api::Peripheral::properties(self).await?;
+        Ok(name)
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let bluer_characteristic = self.bluer_characteristic(characteristic)?;
+        let is_indication = characteristic.properties.subscribes_to_indications();
+        let values = bluer_characteristic.notify().await?;
+        let uuid = characteristic.uuid;
+        let service_uuid = characteristic.service_uuid;
+        let handle = characteristic.handle;
+        let key = CharacteristicKey::from(characteristic);
+        let shared = Arc::downgrade(&self.shared);
+        let task = tokio::spawn(async move {
+            let mut values = Box::pin(values);
+            while let Some(value) = values.next().await {
+                let Some(shared) = shared.upgrade() else {
+                    break;
+                };
+                gatt_log::log_traffic(
+                    &PeripheralId(shared.address),
+                    uuid,
+                    GattOperation::Notification,
+                    &value,
+                );
+                shared.value_cache.insert(key, value.clone());
+                let _ = shared.notifications_channel.send(Some(ValueNotification {
+                    uuid,
+                    service_uuid,
+                    handle,
+                    value,
+                    is_indication,
+                }));
+            }
+        });
+        if let Some(previous) = self
+            .shared
+            .subscriptions
+            .lock()
+            .map_err(Into::<Error>::into)?
+            .insert(bluer_characteristic.id(), task.abort_handle())
+        {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let Ok(bluer_characteristic) = self.bluer_characteristic(characteristic) else {
+            return Ok(());
+        };
+        if let Some(subscription) = self
+            .shared
+            .subscriptions
+            .lock()
+            .map_err(Into::<Error>::into)?
+            .remove(&bluer_characteristic.id())
+        {
+            subscription.abort();
+        }
+        Ok(())
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        let receiver = self.shared.notifications_channel.subscribe();
+        Ok(self.interceptors().notifications(
+            self.id(),
+            notifications_stream_from_broadcast_receiver(receiver),
+        ))
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let bluer_descriptor = self.bluer_descriptor(descriptor)?;
+        let id = self.id();
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::WriteDescriptor);
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                gatt_log::log_traffic(
+                    &self.id(),
+                    descriptor.uuid,
+                    GattOperation::WriteDescriptor,
+                    &data,
+                );
+                Ok(bluer_descriptor.write(&data).await?)
+            })
+            .await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let bluer_descriptor = self.bluer_descriptor(descriptor)?;
+        let id = self.id();
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::ReadDescriptor);
+        self.interceptors()
+            .read(&request, async {
+                let value = bluer_descriptor.read().await?;
+                gatt_log::log_traffic(&id, descriptor.uuid, GattOperation::ReadDescriptor, &value);
+                Ok(value)
+            })
+            .await
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.shared.interceptors.add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self.shared.interceptors.remove(interceptor))
+    }
+}
+
+fn make_service(service: &ServiceInternal) -> Service {
+    let uuid = service.uuid;
+    Service {
+        uuid,
+        handle: Some(service.handle),
+        primary: service.primary,
+        characteristics: service
+            .characteristics
+            .iter()
+            .map(|characteristic| Characteristic {
+                uuid: characteristic.uuid,
+                service_uuid: uuid,
+                properties: characteristic.properties,
+                descriptors: characteristic
+                    .descriptors
+                    .iter()
+                    .map(|(descriptor_uuid, descriptor)| Descriptor {
+                        uuid: *descriptor_uuid,
+                        service_uuid: uuid,
+                        characteristic_uuid: characteristic.uuid,
+                        handle: Some(descriptor.id()),
+                        permissions: None,
+                    })
+                    .collect(),
+                handle: characteristic.handle(),
+            })
+            .collect(),
+    }
+}
+
+impl From<CharacteristicFlags> for CharPropFlags {
+    fn from(flags: CharacteristicFlags) -> Self {
+        let mut result = CharPropFlags::default();
+        result.set(CharPropFlags::BROADCAST, flags.broadcast);
+        result.set(CharPropFlags::READ, flags.read);
+        result.set(
+            CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            flags.write_without_response,
+        );
+        result.set(CharPropFlags::WRITE, flags.write);
+        result.set(CharPropFlags::NOTIFY, flags.notify);
+        result.set(CharPropFlags::INDICATE, flags.indicate);
+        result.set(
+            CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+            flags.authenticated_signed_writes,
+        );
+        result.set(
+            CharPropFlags::EXTENDED_PROPERTIES,
+            flags.extended_properties,
+        );
+        result
+    }
+}
@@ -1,27 +1,285 @@
+use super::advertising::Advertisement;
+use super::agent::{Agent, AgentEvent};
+use super::manager::{MessageStream, Shared};
 use super::peripheral::{Peripheral, PeripheralId};
-use crate::api::{Central, CentralEvent, CentralState, ScanFilter};
+use super::server;
+use crate::api::{
+    AdvertisementData, Backend, BackendKind, BondState, Capabilities, Central, CentralEvent,
+    CentralState, Feature, GattServer, PeripheralProperties, ScanFilter, ScanSettings,
+    ScanTransport,
+};
+use crate::common::scan_filter;
+use crate::interceptor::GattInterceptor;
+use crate::scan_session::ScanSession;
 use crate::{Error, Result};
 use async_trait::async_trait;
 use bluez_async::{
     AdapterEvent, AdapterId, BluetoothError, BluetoothEvent, BluetoothSession, DeviceEvent,
-    DiscoveryFilter, Transport,
+    DeviceId, DiscoveryFilter, Transport,
+};
+use dbus::arg::cast;
+use dbus::message::{MatchRule, MessageType, SignalArgs};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::{
+    ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved, PropertiesPropertiesChanged,
 };
-use futures::stream::{self, Stream, StreamExt};
+use dbus::{Message, Path};
+use futures::future::ready;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::wrappers::BroadcastStream;
+
+const DEVICE_INTERFACE: &str = "org.bluez.Device1";
+const GATT_SERVICE_INTERFACE: &str = "org.bluez.GattService1";
+/// How long to wait for more services of a device to be added or removed before reporting the
+/// change, if BlueZ doesn't resolve the services of the device again before.
+const SERVICES_CHANGED_DELAY: Duration = Duration::from_millis(500);
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone, Debug)]
 pub struct Adapter {
+    shared: Arc<Shared>,
     session: BluetoothSession,
     adapter: AdapterId,
+    scan_filter: Arc<Mutex<ScanFilter>>,
+    advertisement: Arc<tokio::sync::Mutex<Option<Advertisement>>>,
 }
 
+// Adapters are identified by their D-Bus object, not by their current scan filter.
+impl PartialEq for Adapter {
+    fn eq(&self, other: &Self) -> bool {
+        self.adapter == other.adapter
+    }
+}
+
+impl Eq for Adapter {}
+
 impl Adapter {
-    pub(crate) fn new(session: BluetoothSession, adapter: AdapterId) -> Self {
-        Self { session, adapter }
+    pub(crate) fn new(shared: Arc<Shared>, adapter: AdapterId) -> Self {
+        Self {
+            session: shared.session.clone(),
+            scan_filter: shared.scan_filter(&adapter),
+            shared,
+            adapter,
+            advertisement: Arc::new(tokio::sync::Mutex::new(None)),
+        }
+    }
+
+    /// Bond state changes and pairing requests of the devices on this adapter, which bluez-async
+    /// doesn't report.
+    async fn security_events(&self) -> Result<BoxStream<'static, CentralEvent>> {
+        let adapter_path: Path<'static> = self.adapter.clone().into();
+        let rule = PropertiesPropertiesChanged::match_rule(Some(&"org.bluez".into()), None)
+            .static_clone()
+            .with_namespaced_path(adapter_path.clone());
+        let session = self.session.clone();
+        let adapter = self.adapter.clone();
+        let bond_events = MessageStream::new(&self.shared.connection()?, rule)
+            .await?
+            .filter_map(move |message| {
+                let session = session.clone();
+                let adapter = adapter.clone();
+                async move {
+                    let signal = PropertiesPropertiesChanged::from_message(&message)?;
+                    if signal.interface_name != DEVICE_INTERFACE {
+                        return None;
+                    }
+                    let paired = *cast::<bool>(&signal.changed_properties.get("Paired")?.0)?;
+                    let id = device_id(&session, &adapter, &message.path()?).await?;
+                    Some(CentralEvent::BondStateChanged {
+                        id: id.into(),
+                        state: if paired {
+                            BondState::Bonded
+                        } else {
+                            BondState::NotBonded
+                        },
+                    })
+                }
+            });
+
+        let session = self.session.clone();
+        let adapter = self.adapter.clone();
+        let pairing_requests = BroadcastStream::new(self.shared.agent_events.subscribe())
+            .filter_map(|event| ready(event.ok()))
+            .filter_map(move |event| {
+                let session = session.clone();
+                let adapter = adapter.clone();
+                async move {
+                    let id = device_id(&session, &adapter, event.device()).await?.into();
+                    Some(match event {
+                        AgentEvent::PinRequested(_) => CentralEvent::PairingPinRequested { id },
+                        AgentEvent::ConfirmationRequested(_, passkey) => {
+                            CentralEvent::PairingConfirmationRequested { id, passkey }
+                        }
+                    })
+                }
+            });
+        Ok(stream::select(bond_events, pairing_requests).boxed())
+    }
+
+    /// `ServicesChanged` events for the devices on this adapter, after discovering their services
+    /// again. BlueZ subscribes to the Service Changed characteristic by itself, and replaces the
+    /// service objects of the device when it's indicated, which bluez-async doesn't report. The
+    /// services added and removed by a change are coalesced into one event per device.
+    async fn services_changed_events(&self) -> Result<BoxStream<'static, CentralEvent>> {
+        // A single match for all the signals of BlueZ, so the signals for the services and for the
+        // ServicesResolved property arrive in the order they were sent: services exported before
+        // a device is first resolved aren't a change.
+        let rule = MatchRule::new()
+            .with_sender("org.bluez")
+            .with_type(MessageType::Signal);
+        let messages = MessageStream::new(&self.shared.connection()?, rule).await?;
+        let changes = ServicesChanges {
+            resolved: self
+                .session
+                .get_devices_on_adapter(&self.adapter)
+                .await?
+                .into_iter()
+                .filter(|device| device.services_resolved)
+                .map(|device| device.id.into())
+                .collect(),
+            pending: HashMap::new(),
+        };
+        let changed_devices = stream::unfold(
+            (messages, changes),
+            |(mut messages, mut changes)| async move {
+                loop {
+                    let message = match changes.next_deadline() {
+                        Some(deadline) => {
+                            match tokio::time::timeout_at(deadline, messages.next()).await {
+                                Ok(message) => message?,
+                                Err(_) => {
+                                    let due = changes.take_due(Instant::now());
+                                    if due.is_empty() {
+                                        continue;
+                                    }
+                                    return Some((stream::iter(due), (messages, changes)));
+                                }
+                            }
+                        }
+                        None => messages.next().await?,
+                    };
+                    if let Some(device) = changes.handle(&message, Instant::now()) {
+                        return Some((stream::iter(vec![device]), (messages, changes)));
+                    }
+                }
+            },
+        )
+        .flatten();
+
+        let adapter_path = format!("{}/", Path::from(self.adapter.clone()));
+        let session = self.session.clone();
+        let adapter = self.adapter.clone();
+        let shared = self.shared.clone();
+        Ok(changed_devices
+            .filter(move |path| ready(path.starts_with(&adapter_path)))
+            .filter_map(move |path| {
+                let session = session.clone();
+                let adapter = adapter.clone();
+                let shared = shared.clone();
+                async move {
+                    let id = device_id(&session, &adapter, &path).await?;
+                    let device = session.get_device_info(&id).await.ok()?;
+                    let peripheral = Peripheral::new(shared, device);
+                    if let Err(e) = crate::api::Peripheral::discover_services(&peripheral).await {
+                        log::debug!("Discovering the changed services of {} failed: {}", id, e);
+                    }
+                    Some(CentralEvent::ServicesChanged(id.into()))
+                }
+            })
+            .boxed())
     }
 }
 
+/// Coalesces the services BlueZ adds to and removes from devices into one change per device.
+#[derive(Debug, Default)]
+struct ServicesChanges {
+    /// The devices which have their services resolved, by path.
+    resolved: HashSet<Path<'static>>,
+    /// The devices with changes which haven't been reported yet, and when to report them unless
+    /// BlueZ resolves their services first.
+    pending: HashMap<Path<'static>, Instant>,
+}
+
+impl ServicesChanges {
+    /// Keeps track of a BlueZ signal. Returns the path of a device with pending changes if the
+    /// signal says its services are resolved, which completes the change.
+    fn handle(&mut self, message: &Message, now: Instant) -> Option<Path<'static>> {
+        if let Some(signal) = PropertiesPropertiesChanged::from_message(message) {
+            if signal.interface_name == DEVICE_INTERFACE {
+                if let Some(services_resolved) = signal.changed_properties.get("ServicesResolved") {
+                    let path = message.path()?.into_static();
+                    if *cast::<bool>(&services_resolved.0)? {
+                        self.resolved.insert(path.clone());
+                        return self.pending.remove(&path).map(|_| path);
+                    }
+                    self.resolved.remove(&path);
+                }
+            }
+            return None;
+        }
+        let service = if let Some(signal) = ObjectManagerInterfacesAdded::from_message(message) {
+            signal
+                .interfaces
+                .contains_key(GATT_SERVICE_INTERFACE)
+                .then_some(signal.object)?
+        } else {
+            let signal = ObjectManagerInterfacesRemoved::from_message(message)?;
+            signal
+                .interfaces
+                .iter()
+                .any(|interface| interface == GATT_SERVICE_INTERFACE)
+                .then_some(signal.object)?
+        };
+        // Services are children of their device.
+        let (device, _) = service.rsplit_once('/')?;
+        let device = Path::new(device.to_owned()).ok()?;
+        if self.resolved.contains(&device) {
+            self.pending.insert(device, now + SERVICES_CHANGED_DELAY);
+        }
+        None
+    }
+
+    /// When the next pending change is due.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().min().copied()
+    }
+
+    /// Removes the changes due by `now`, and returns the devices they're reported for. Changes of
+    /// devices which are no longer resolved, e.g. because they disconnected, are dropped.
+    fn take_due(&mut self, now: Instant) -> Vec<Path<'static>> {
+        let mut due = Vec::new();
+        self.pending.retain(|device, deadline| {
+            if *deadline > now {
+                return true;
+            }
+            due.push(device.clone());
+            false
+        });
+        due.retain(|device| self.resolved.contains(device));
+        due
+    }
+}
+
+/// Looks up the ID of the device at `path`, if it's on `adapter`.
+async fn device_id(
+    session: &BluetoothSession,
+    adapter: &AdapterId,
+    path: &Path<'_>,
+) -> Option<DeviceId> {
+    session
+        .get_devices_on_adapter(adapter)
+        .await
+        .ok()?
+        .into_iter()
+        .map(|device| device.id)
+        .find(|id| Path::from(id.clone()) == *path)
+}
+
 fn get_central_state(powered: bool) -> CentralState {
     match powered {
         true => CentralState::PoweredOn,
@@ -59,15 +317,78 @@ impl Central for Adapter {
         let adapter_id = self.adapter.clone();
         let events = events
             .filter_map(move |event| central_event(event, session.clone(), adapter_id.clone()));
+        let events = stream::select(
+            events,
+            stream::select(
+                self.security_events().await?,
+                self.services_changed_events().await?,
+            ),
+        );
 
-        Ok(Box::pin(initial_events.chain(events)))
+        // BlueZ only filters on services and a name pattern, so check advertisement events
+        // against the rest of the scan filter here.
+        let session = self.session.clone();
+        let shared = self.shared.clone();
+        let scan_filter = self.scan_filter.clone();
+        let withheld = Arc::new(Mutex::new(HashSet::new()));
+        let events = initial_events
+            .chain(events)
+            .then(move |event| {
+                let id = event.advertisement_peripheral_id().cloned();
+                let filter = scan_filter.lock().unwrap().clone();
+                let session = session.clone();
+                let shared = shared.clone();
+                let withheld = withheld.clone();
+                async move {
+                    let properties = match id {
+                        Some(id) if filter.filters_beyond_services() => session
+                            .get_device_info(&id.0)
+                            .await
+                            .ok()
+                            .map(|device| shared.properties(device)),
+                        _ => None,
+                    };
+                    let mut withheld = withheld.lock().unwrap();
+                    stream::iter(scan_filter::filter_event(
+                        &filter,
+                        &mut withheld,
+                        event,
+                        properties.as_ref(),
+                    ))
+                }
+            })
+            .flatten();
+
+        Ok(Box::pin(events))
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.start_scan_with_settings(filter, ScanSettings::default())
+            .await
+    }
+
+    async fn start_scan_with_settings(
+        &self,
+        filter: ScanFilter,
+        settings: ScanSettings,
+    ) -> Result<()> {
+        *self.scan_filter.lock().map_err(Into::<Error>::into)? = filter.clone();
+        // The pattern also matches addresses, which doesn't matter as names are checked again
+        // when events are emitted.
+        let pattern = match (settings.pattern, filter.name_prefixes.as_slice()) {
+            (None, [prefix]) => Some(prefix.clone()),
+            (pattern, _) => pattern,
+        };
         let filter = DiscoveryFilter {
             service_uuids: filter.services,
-            duplicate_data: Some(true),
-            transport: Some(Transport::Auto),
+            duplicate_data: Some(settings.duplicate_data.unwrap_or(true)),
+            transport: Some(match settings.transport.unwrap_or(ScanTransport::Auto) {
+                ScanTransport::Auto => Transport::Auto,
+                ScanTransport::Le => Transport::Le,
+                ScanTransport::BrEdr => Transport::BrEdr,
+            }),
+            discoverable: settings.discoverable,
+            pattern,
             ..Default::default()
         };
         self.session
@@ -80,14 +401,40 @@ impl Central for Adapter {
         self.session
             .stop_discovery_on_adapter(&self.adapter)
             .await?;
+        *self.scan_filter.lock().map_err(Into::<Error>::into)? = ScanFilter::default();
         Ok(())
     }
 
+    async fn start_scan_session(&self, filter: ScanFilter) -> Result<ScanSession> {
+        self.shared
+            .scan_sessions(&self.adapter)
+            .start(self.clone(), filter)
+            .await
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         let devices = self.session.get_devices_on_adapter(&self.adapter).await?;
         Ok(devices
             .into_iter()
-            .map(|device| Peripheral::new(self.session.clone(), device))
+            .map(|device| Peripheral::new(self.shared.clone(), device))
+            .collect())
+    }
+
+    async fn peripherals_with_properties(
+        &self,
+    ) -> Result<Vec<(Peripheral, Option<Arc<PeripheralProperties>>)>> {
+        // A single D-Bus call returns the properties of every device, so there is no need to query
+        // each peripheral separately.
+        let devices = self.session.get_devices_on_adapter(&self.adapter).await?;
+        Ok(devices
+            .into_iter()
+            .map(|device| {
+                let properties = Arc::new(self.shared.properties(device.clone()));
+                (
+                    Peripheral::new(self.shared.clone(), device),
+                    Some(properties),
+                )
+            })
             .collect())
     }
 
@@ -99,13 +446,13 @@ impl Central for Adapter {
                 e.into()
             }
         })?;
-        Ok(Peripheral::new(self.session.clone(), device))
+        Ok(Peripheral::new(self.shared.clone(), device))
     }
 
     async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
-        Err(Error::NotSupported(
-            "Can't add a Peripheral from a PeripheralId".to_string(),
-        ))
+        Err(Error::NotSupported {
+            feature: Feature::AddPeripheral,
+        })
     }
 
     async fn adapter_info(&self) -> Result<String> {
@@ -120,6 +467,77 @@ impl Central for Adapter {
         }
         Ok(get_central_state(powered))
     }
+
+    async fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::BACKGROUND_SCAN
+            | Capabilities::SCAN_SETTINGS
+            | Capabilities::ADD_PERIPHERAL
+            | Capabilities::MANAGER_EVENTS
+            | Capabilities::MTU
+            | Capabilities::SCAN_SESSIONS
+            | Capabilities::SIGNED_WRITE
+            | Capabilities::PAIRING_STATE
+            | Capabilities::GATT_INTERCEPTORS
+            | Capabilities::GATT_SERVER
+            | Capabilities::ADVERTISING
+            | Capabilities::AUTO_CONNECT
+            | Capabilities::BOND_STATE_EVENTS
+            | Capabilities::PAIRING_REQUESTS
+            | Capabilities::LONG_VALUE_CHUNKING)
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.shared
+            .adapter_interceptors(&self.adapter)
+            .add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self
+            .shared
+            .adapter_interceptors(&self.adapter)
+            .remove(interceptor))
+    }
+
+    async fn gatt_server(&self) -> Result<Box<dyn GattServer>> {
+        Ok(Box::new(
+            server::GattServer::new(self.adapter.clone().into()).await?,
+        ))
+    }
+
+    async fn start_advertising(&self, data: AdvertisementData) -> Result<()> {
+        let mut advertisement = self.advertisement.lock().await;
+        if let Some(previous) = advertisement.take() {
+            previous.unregister().await?;
+        }
+        *advertisement = Some(Advertisement::register(self.adapter.clone().into(), data).await?);
+        Ok(())
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        match self.advertisement.lock().await.take() {
+            Some(advertisement) => advertisement.unregister().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn handle_pairing_requests(&self) -> Result<()> {
+        let mut agent = self.shared.agent.lock().await;
+        if agent.is_none() {
+            *agent = Some(Agent::register(self.shared.agent_events.clone()).await?);
+        }
+        Ok(())
+    }
+
+    fn backend(&self) -> Backend {
+        Backend {
+            kind: BackendKind::BlueZ,
+            version: fs::read_to_string("/proc/sys/kernel/osrelease")
+                .ok()
+                .map(|release| release.trim().to_string()),
+        }
+    }
 }
 
 impl From<BluetoothError> for Error {
@@ -150,9 +568,16 @@ async fn central_event(
                     Some(CentralEvent::DeviceDisconnected(device.id.into()))
                 }
             }
-            DeviceEvent::Rssi { rssi: _ } => {
+            DeviceEvent::ServicesResolved => {
                 let device = session.get_device_info(&id).await.ok()?;
-                Some(CentralEvent::DeviceUpdated(device.id.into()))
+                Some(CentralEvent::ServicesResolved(device.id.into()))
+            }
+            DeviceEvent::Rssi { rssi } => {
+                // BlueZ signals every RSSI change while discovering, so skip the device lookup the
+                // other events do: the signal already carries the new value, which is what
+                // `properties()` returns from now on.
+                log::trace!("RSSI of {} is now {}", id, rssi);
+                Some(CentralEvent::DeviceUpdated(id.into()))
             }
             DeviceEvent::ManufacturerData { manufacturer_data } => {
                 let device = session.get_device_info(&id).await.ok()?;
@@ -190,3 +615,104 @@ async fn central_event(
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::arg::{PropMap, Variant};
+    use std::collections::HashMap;
+
+    const DEVICE: &str = "/org/bluez/hci0/dev_00_11_22_33_44_55";
+    const SERVICE: &str = "/org/bluez/hci0/dev_00_11_22_33_44_55/service0010";
+
+    fn services_resolved(resolved: bool) -> Message {
+        let mut changed_properties = PropMap::new();
+        changed_properties.insert("ServicesResolved".to_string(), Variant(Box::new(resolved)));
+        PropertiesPropertiesChanged {
+            interface_name: DEVICE_INTERFACE.to_string(),
+            changed_properties,
+            invalidated_properties: vec![],
+        }
+        .to_emit_message(&DEVICE.into())
+    }
+
+    fn service_added() -> Message {
+        ObjectManagerInterfacesAdded {
+            object: SERVICE.into(),
+            interfaces: HashMap::from([(GATT_SERVICE_INTERFACE.to_string(), PropMap::new())]),
+        }
+        .to_emit_message(&"/".into())
+    }
+
+    fn service_removed() -> Message {
+        ObjectManagerInterfacesRemoved {
+            object: SERVICE.into(),
+            interfaces: vec![GATT_SERVICE_INTERFACE.to_string()],
+        }
+        .to_emit_message(&"/".into())
+    }
+
+    #[test]
+    fn services_changed_only_once_resolved() {
+        let mut changes = ServicesChanges::default();
+        let now = Instant::now();
+        // Services are exported before the device is first resolved.
+        assert_eq!(changes.handle(&service_added(), now), None);
+        assert_eq!(changes.handle(&services_resolved(true), now), None);
+        assert_eq!(changes.next_deadline(), None);
+        assert_eq!(changes.handle(&service_removed(), now), None);
+        assert_eq!(changes.next_deadline(), Some(now + SERVICES_CHANGED_DELAY));
+        // And removed after it disconnected, which drops the pending change.
+        assert_eq!(changes.handle(&services_resolved(false), now), None);
+        assert_eq!(changes.handle(&service_removed(), now), None);
+        assert!(changes.take_due(now + SERVICES_CHANGED_DELAY).is_empty());
+        assert_eq!(changes.next_deadline(), None);
+    }
+
+    #[test]
+    fn services_changes_are_coalesced() {
+        let mut changes = ServicesChanges::default();
+        let start = Instant::now();
+        changes.handle(&services_resolved(true), start);
+        for offset in 0..4 {
+            let now = start + Duration::from_millis(offset * 100);
+            assert_eq!(changes.handle(&service_removed(), now), None);
+            assert_eq!(changes.handle(&service_added(), now), None);
+        }
+        // Each change pushes the report back.
+        let last = start + Duration::from_millis(300);
+        assert!(changes.take_due(start + SERVICES_CHANGED_DELAY).is_empty());
+        assert_eq!(
+            changes.take_due(last + SERVICES_CHANGED_DELAY),
+            vec![Path::from(DEVICE)]
+        );
+        assert_eq!(changes.next_deadline(), None);
+
+        // BlueZ reporting the services as resolved completes the change right away.
+        changes.handle(&service_added(), last);
+        assert_eq!(
+            changes.handle(&services_resolved(true), last),
+            Some(DEVICE.into())
+        );
+        assert_eq!(changes.next_deadline(), None);
+    }
+
+    #[tokio::test]
+    async fn scan_filters_are_shared_between_handles() {
+        let shared = Shared::for_tests().await;
+        let id: AdapterId =
+            serde_json::from_value(serde_json::json!({ "object_path": "/org/bluez/hci0" }))
+                .unwrap();
+        let first = Adapter::new(shared.clone(), id.clone());
+        let second = Adapter::new(shared, id);
+        let filter = ScanFilter {
+            name_prefixes: vec!["Sensor".to_string()],
+            ..Default::default()
+        };
+
+        first.start_scan(filter.clone()).await.unwrap();
+        assert_eq!(*second.scan_filter.lock().unwrap(), filter);
+        second.stop_scan().await.unwrap();
+        assert_eq!(*first.scan_filter.lock().unwrap(), ScanFilter::default());
+    }
+}
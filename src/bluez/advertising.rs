@@ -0,0 +1,241 @@
+//! Advertising through BlueZ's `org.bluez.LEAdvertisingManager1`.
+//!
+//! The advertisement is an `org.bluez.LEAdvertisement1` object, exported on a connection of its own
+//! like the applications of the [`GattServer`](super::server::GattServer). BlueZ stops advertising
+//! once that connection closes, which happens when the advertisement is dropped.
+
+use super::server::{
+    invalid_args, unknown_method, variant, MethodError, DBUS_TIMEOUT, OBJECT_MANAGER_INTERFACE,
+    PROPERTIES_INTERFACE,
+};
+use crate::api::AdvertisementData;
+use crate::{Error, Result};
+use dbus::arg::PropMap;
+use dbus::channel::{MatchingReceiver, Sender, Token};
+use dbus::message::MatchRule;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{Message, Path};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use tokio::task::AbortHandle;
+
+const ADVERTISING_MANAGER_INTERFACE: &str = "org.bluez.LEAdvertisingManager1";
+const ADVERTISEMENT_INTERFACE: &str = "org.bluez.LEAdvertisement1";
+
+/// Each advertisement has a connection of its own, so they can all use the same path.
+const ADVERTISEMENT_PATH: &str = "/org/btleplug/advertisement";
+
+/// An advertisement registered with BlueZ.
+pub(crate) struct Advertisement {
+    connection: Arc<SyncConnection>,
+    adapter: Path<'static>,
+    token: Token,
+    connection_task: AbortHandle,
+}
+
+impl Advertisement {
+    /// Registers an advertisement of `data` with the adapter at `adapter`.
+    pub(crate) async fn register(adapter: Path<'static>, data: AdvertisementData) -> Result<Self> {
+        let (resource, connection) =
+            dbus_tokio::connection::new_system_sync().map_err(|e| Error::Other(Box::new(e)))?;
+        let connection_task = tokio::spawn(async {
+            let err = resource.await;
+            log::error!("Advertisement lost its connection to D-Bus: {}", err);
+        });
+        let token = connection.start_receive(
+            MatchRule::new_method_call().with_path(ADVERTISEMENT_PATH),
+            Box::new(move |message, connection| {
+                let _ = connection.send(handle(&data, &message));
+                true
+            }),
+        );
+        let advertisement = Self {
+            connection,
+            adapter,
+            token,
+            connection_task: connection_task.abort_handle(),
+        };
+        // BlueZ reads the properties of the advertisement before this returns.
+        advertisement
+            .advertising_manager()
+            .method_call::<(), _, _, _>(
+                ADVERTISING_MANAGER_INTERFACE,
+                "RegisterAdvertisement",
+                (Path::from(ADVERTISEMENT_PATH), PropMap::new()),
+            )
+            .await
+            .map_err(|e| Error::Other(Box::new(e)))?;
+        Ok(advertisement)
+    }
+
+    /// Stops advertising. Dropping the advertisement does too, but doesn't tell whether it worked.
+    pub(crate) async fn unregister(self) -> Result<()> {
+        self.advertising_manager()
+            .method_call::<(), _, _, _>(
+                ADVERTISING_MANAGER_INTERFACE,
+                "UnregisterAdvertisement",
+                (Path::from(ADVERTISEMENT_PATH),),
+            )
+            .await
+            .map_err(|e| Error::Other(Box::new(e)))
+    }
+
+    fn advertising_manager(&self) -> Proxy<'_, Arc<SyncConnection>> {
+        Proxy::new(
+            "org.bluez",
+            &self.adapter,
+            DBUS_TIMEOUT,
+            self.connection.clone(),
+        )
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        self.connection.stop_receive(self.token);
+        self.connection_task.abort();
+    }
+}
+
+impl Debug for Advertisement {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Advertisement")
+            .field("adapter", &self.adapter)
+            .finish()
+    }
+}
+
+/// The properties of the `org.bluez.LEAdvertisement1` object for `data`.
+fn properties(data: &AdvertisementData) -> PropMap {
+    let kind = if data.connectable {
+        "peripheral"
+    } else {
+        "broadcast"
+    };
+    let service_uuids: Vec<String> = data
+        .service_uuids
+        .iter()
+        .map(|uuid| uuid.to_string())
+        .collect();
+    let manufacturer_data: HashMap<u16, _> = data
+        .manufacturer_data
+        .iter()
+        .map(|(&company, value)| (company, variant(value.clone())))
+        .collect();
+    let service_data: HashMap<String, _> = data
+        .service_data
+        .iter()
+        .map(|(uuid, value)| (uuid.to_string(), variant(value.clone())))
+        .collect();
+    let mut properties = PropMap::from([
+        ("Type".to_string(), variant(kind.to_string())),
+        ("ServiceUUIDs".to_string(), variant(service_uuids)),
+        ("ManufacturerData".to_string(), variant(manufacturer_data)),
+        ("ServiceData".to_string(), variant(service_data)),
+    ]);
+    if data.connectable {
+        properties.insert("Discoverable".to_string(), variant(true));
+    }
+    if let Some(name) = &data.local_name {
+        properties.insert("LocalName".to_string(), variant(name.clone()));
+    }
+    properties
+}
+
+/// Answers a method call to the advertisement object.
+fn handle(data: &AdvertisementData, message: &Message) -> Message {
+    let interface = message
+        .interface()
+        .map(|interface| interface.to_string())
+        .unwrap_or_default();
+    let member = message
+        .member()
+        .map(|member| member.to_string())
+        .unwrap_or_default();
+    let reply: std::result::Result<Message, MethodError> =
+        match (interface.as_str(), member.as_str()) {
+            (OBJECT_MANAGER_INTERFACE, "GetManagedObjects") => {
+                let objects = HashMap::from([(
+                    Path::from(ADVERTISEMENT_PATH),
+                    HashMap::from([(ADVERTISEMENT_INTERFACE.to_string(), properties(data))]),
+                )]);
+                Ok(message.method_return().append1(objects))
+            }
+            (PROPERTIES_INTERFACE, "GetAll") => message
+                .read1::<String>()
+                .map_err(invalid_args)
+                .and_then(|interface| match interface.as_str() {
+                    ADVERTISEMENT_INTERFACE => {
+                        Ok(message.method_return().append1(properties(data)))
+                    }
+                    _ => Err(unknown_method()),
+                }),
+            (PROPERTIES_INTERFACE, "Get") => message
+                .read2::<String, String>()
+                .map_err(invalid_args)
+                .and_then(|(interface, name)| {
+                    let value = match interface.as_str() {
+                        ADVERTISEMENT_INTERFACE => properties(data).remove(&name),
+                        _ => None,
+                    };
+                    value
+                        .map(|value| message.method_return().append1(value))
+                        .ok_or_else(|| {
+                            (
+                                "org.freedesktop.DBus.Error.UnknownProperty",
+                                format!("No property {}", name),
+                            )
+                        })
+                }),
+            (ADVERTISEMENT_INTERFACE, "Release") => {
+                log::debug!("BlueZ released the advertisement");
+                Ok(message.method_return())
+            }
+            _ => Err(unknown_method()),
+        };
+    reply.unwrap_or_else(|(name, text)| {
+        message.error(&name.into(), &CString::new(text).unwrap_or_default())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::arg::prop_cast;
+    use uuid::Uuid;
+
+    #[test]
+    fn advertisement_properties() {
+        let data = AdvertisementData {
+            local_name: Some("btleplug".to_string()),
+            service_uuids: vec![Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb)],
+            manufacturer_data: HashMap::from([(0x004c, vec![0x02, 0x15])]),
+            connectable: true,
+            ..Default::default()
+        };
+        let advertised = properties(&data);
+        assert_eq!(
+            prop_cast::<String>(&advertised, "Type").map(String::as_str),
+            Some("peripheral")
+        );
+        assert_eq!(
+            prop_cast::<String>(&advertised, "LocalName").map(String::as_str),
+            Some("btleplug")
+        );
+        assert_eq!(
+            prop_cast::<Vec<String>>(&advertised, "ServiceUUIDs"),
+            Some(&vec!["0000180d-0000-1000-8000-00805f9b34fb".to_string()])
+        );
+        assert_eq!(prop_cast::<bool>(&advertised, "Discoverable"), Some(&true));
+
+        let advertised = properties(&AdvertisementData::default());
+        assert_eq!(
+            prop_cast::<String>(&advertised, "Type").map(String::as_str),
+            Some("broadcast")
+        );
+        assert!(!advertised.contains_key("LocalName"));
+        assert!(!advertised.contains_key("Discoverable"));
+    }
+}
@@ -0,0 +1,342 @@
+//! Pairing through BlueZ's `org.bluez.AgentManager1`.
+//!
+//! The agent is an `org.bluez.Agent1` object, exported on a connection of its own like the
+//! [`Advertisement`](super::advertising::Advertisement). It registers as BlueZ's default agent, so
+//! it is asked about all pairings, and BlueZ falls back to the previous default agent once its
+//! connection closes, which happens when it is dropped.
+//!
+//! Requests for a PIN code, a passkey or a confirmation are kept until the application answers
+//! them. Connections of devices to a service are authorized the same way as a confirmation, so
+//! they are rejected unless the application confirms them. Passkeys BlueZ asks the agent to
+//! display are only logged, as there's no event for them.
+
+use super::server::{invalid_args, unknown_method, MethodError, DBUS_TIMEOUT};
+use crate::api::PairingResponse;
+use crate::{Error, Result};
+use dbus::channel::{MatchingReceiver, Sender, Token};
+use dbus::message::MatchRule;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{Message, Path};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::task::AbortHandle;
+
+const AGENT_MANAGER_INTERFACE: &str = "org.bluez.AgentManager1";
+const AGENT_INTERFACE: &str = "org.bluez.Agent1";
+
+const AGENT_PATH: &str = "/org/btleplug/agent";
+
+/// The IO capability the agent registers with, which makes BlueZ ask it for whatever a pairing
+/// needs.
+const CAPABILITY: &str = "KeyboardDisplay";
+
+/// A request of BlueZ to the agent, for the application to answer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum AgentEvent {
+    PinRequested(Path<'static>),
+    ConfirmationRequested(Path<'static>, Option<u32>),
+}
+
+impl AgentEvent {
+    /// The object path of the device the request is about.
+    pub(crate) fn device(&self) -> &Path<'static> {
+        match self {
+            AgentEvent::PinRequested(device) | AgentEvent::ConfirmationRequested(device, _) => {
+                device
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum RequestKind {
+    PinCode,
+    Passkey,
+    Confirmation,
+}
+
+/// A method call of BlueZ which is waiting for the application to answer it.
+#[derive(Debug)]
+struct PendingRequest {
+    kind: RequestKind,
+    message: Message,
+}
+
+type PendingRequests = Arc<Mutex<HashMap<Path<'static>, PendingRequest>>>;
+
+/// The pairing agent registered with BlueZ.
+pub(crate) struct Agent {
+    connection: Arc<SyncConnection>,
+    token: Token,
+    connection_task: AbortHandle,
+    pending: PendingRequests,
+}
+
+impl Agent {
+    /// Registers an agent as BlueZ's default agent, which sends the requests it gets to `events`.
+    pub(crate) async fn register(events: broadcast::Sender<AgentEvent>) -> Result<Self> {
+        let (resource, connection) =
+            dbus_tokio::connection::new_system_sync().map_err(|e| Error::Other(Box::new(e)))?;
+        let connection_task = tokio::spawn(async {
+            let err = resource.await;
+            log::error!("Pairing agent lost its connection to D-Bus: {}", err);
+        });
+        let pending = PendingRequests::default();
+        let token = connection.start_receive(MatchRule::new_method_call().with_path(AGENT_PATH), {
+            let pending = pending.clone();
+            Box::new(move |message, connection| {
+                if let Some(reply) = handle(&pending, &events, message) {
+                    let _ = connection.send(reply);
+                }
+                true
+            })
+        });
+        let agent = Self {
+            connection,
+            token,
+            connection_task: connection_task.abort_handle(),
+            pending,
+        };
+        let agent_manager = Proxy::new(
+            "org.bluez",
+            "/org/bluez",
+            DBUS_TIMEOUT,
+            agent.connection.clone(),
+        );
+        agent_manager
+            .method_call::<(), _, _, _>(
+                AGENT_MANAGER_INTERFACE,
+                "RegisterAgent",
+                (Path::from(AGENT_PATH), CAPABILITY),
+            )
+            .await
+            .map_err(|e| Error::Other(Box::new(e)))?;
+        agent_manager
+            .method_call::<(), _, _, _>(
+                AGENT_MANAGER_INTERFACE,
+                "RequestDefaultAgent",
+                (Path::from(AGENT_PATH),),
+            )
+            .await
+            .map_err(|e| Error::Other(Box::new(e)))?;
+        Ok(agent)
+    }
+
+    /// Answers the pending request about the device at `device`.
+    pub(crate) fn respond(&self, device: &Path<'static>, response: PairingResponse) -> Result<()> {
+        let mut pending = self.pending.lock().map_err(Into::<Error>::into)?;
+        let request = pending
+            .remove(device)
+            .ok_or_else(|| Error::Other("No pairing request is pending".into()))?;
+        let reply = match reply(&request, &response) {
+            Ok(reply) => reply,
+            Err(e) => {
+                // Let the application try again with a fitting response.
+                pending.insert(device.clone(), request);
+                return Err(e);
+            }
+        };
+        self.connection
+            .send(reply)
+            .map(|_| ())
+            .map_err(|_| Error::Other("Unable to answer the pairing request".into()))
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        self.connection.stop_receive(self.token);
+        self.connection_task.abort();
+    }
+}
+
+impl Debug for Agent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Agent").finish_non_exhaustive()
+    }
+}
+
+/// The reply to `request` for the application's `response`.
+fn reply(request: &PendingRequest, response: &PairingResponse) -> Result<Message> {
+    match (request.kind, response) {
+        (_, PairingResponse::Reject) => Ok(error(
+            &request.message,
+            ("org.bluez.Error.Rejected", "Rejected".to_string()),
+        )),
+        (RequestKind::PinCode, PairingResponse::Pin(pin)) => {
+            Ok(request.message.method_return().append1(pin.as_str()))
+        }
+        (RequestKind::Passkey, PairingResponse::Pin(pin)) => {
+            let passkey = pin
+                .parse::<u32>()
+                .ok()
+                .filter(|&passkey| passkey <= 999999)
+                .ok_or_else(|| Error::Other(format!("Invalid passkey {:?}", pin).into()))?;
+            Ok(request.message.method_return().append1(passkey))
+        }
+        (RequestKind::Confirmation, PairingResponse::Confirm) => {
+            Ok(request.message.method_return())
+        }
+        (kind, response) => Err(Error::Other(
+            format!("{:?} doesn't answer a {:?} request", response, kind).into(),
+        )),
+    }
+}
+
+/// Handles a method call to the agent object. Returns the reply to send right away, if any.
+fn handle(
+    pending: &PendingRequests,
+    events: &broadcast::Sender<AgentEvent>,
+    message: Message,
+) -> Option<Message> {
+    let interface = message
+        .interface()
+        .map(|interface| interface.to_string())
+        .unwrap_or_default();
+    let member = message
+        .member()
+        .map(|member| member.to_string())
+        .unwrap_or_default();
+    if interface != AGENT_INTERFACE {
+        return Some(error(&message, unknown_method()));
+    }
+    let request = match member.as_str() {
+        "RequestPinCode" => message
+            .read1::<Path>()
+            .map(|device| (device, RequestKind::PinCode, None)),
+        "RequestPasskey" => message
+            .read1::<Path>()
+            .map(|device| (device, RequestKind::Passkey, None)),
+        "RequestConfirmation" => message
+            .read2::<Path, u32>()
+            .map(|(device, passkey)| (device, RequestKind::Confirmation, Some(passkey))),
+        "RequestAuthorization" => message
+            .read1::<Path>()
+            .map(|device| (device, RequestKind::Confirmation, None)),
+        "AuthorizeService" => message.read2::<Path, &str>().map(|(device, uuid)| {
+            log::debug!("{} asks to use service {}", device, uuid);
+            (device, RequestKind::Confirmation, None)
+        }),
+        "DisplayPinCode" => {
+            if let Ok((device, pin)) = message.read2::<Path, &str>() {
+                log::info!("Enter PIN code {} on {}", pin, device);
+            }
+            return Some(message.method_return());
+        }
+        "DisplayPasskey" => {
+            if let Ok((device, passkey)) = message.read2::<Path, u32>() {
+                log::info!("Enter passkey {:06} on {}", passkey, device);
+            }
+            return Some(message.method_return());
+        }
+        "Release" => return Some(message.method_return()),
+        "Cancel" => {
+            // BlueZ doesn't say which request it cancelled, and doesn't wait for a reply to any.
+            pending.lock().unwrap().clear();
+            return Some(message.method_return());
+        }
+        _ => return Some(error(&message, unknown_method())),
+    };
+    let (device, kind, passkey) = match request {
+        Ok(request) => request,
+        Err(e) => return Some(error(&message, invalid_args(e))),
+    };
+    let device = device.into_static();
+    let event = match kind {
+        RequestKind::PinCode | RequestKind::Passkey => AgentEvent::PinRequested(device.clone()),
+        RequestKind::Confirmation => AgentEvent::ConfirmationRequested(device.clone(), passkey),
+    };
+    let replaced = pending
+        .lock()
+        .unwrap()
+        .insert(device, PendingRequest { kind, message });
+    if events.send(event).is_err() {
+        log::debug!("No one is listening for pairing requests");
+    }
+    // BlueZ only asks one thing about a device at a time, so an earlier request is stale.
+    replaced.map(|request| {
+        error(
+            &request.message,
+            ("org.bluez.Error.Canceled", "Canceled".to_string()),
+        )
+    })
+}
+
+fn error(message: &Message, (name, text): MethodError) -> Message {
+    message.error(&name.into(), &CString::new(text).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(kind: RequestKind) -> PendingRequest {
+        let mut message =
+            Message::new_method_call("org.bluez", AGENT_PATH, AGENT_INTERFACE, "Test").unwrap();
+        // Replies refer to the serial of the call, which is normally set when it's sent.
+        message.set_serial(1);
+        PendingRequest { kind, message }
+    }
+
+    #[test]
+    fn pairing_replies() {
+        let pin = reply(
+            &request(RequestKind::PinCode),
+            &PairingResponse::Pin("0000".to_string()),
+        )
+        .unwrap();
+        assert_eq!(pin.read1::<&str>().unwrap(), "0000");
+
+        let passkey = reply(
+            &request(RequestKind::Passkey),
+            &PairingResponse::Pin("012345".to_string()),
+        )
+        .unwrap();
+        assert_eq!(passkey.read1::<u32>().unwrap(), 12345);
+        assert!(reply(
+            &request(RequestKind::Passkey),
+            &PairingResponse::Pin("1234567".to_string()),
+        )
+        .is_err());
+
+        let mut rejected = reply(
+            &request(RequestKind::Confirmation),
+            &PairingResponse::Reject,
+        )
+        .unwrap();
+        assert_eq!(
+            rejected.as_result().unwrap_err().name(),
+            Some("org.bluez.Error.Rejected")
+        );
+        assert!(reply(
+            &request(RequestKind::Confirmation),
+            &PairingResponse::Confirm
+        )
+        .is_ok());
+        assert!(reply(&request(RequestKind::PinCode), &PairingResponse::Confirm).is_err());
+    }
+
+    #[test]
+    fn service_authorization_is_asked_to_the_application() {
+        let pending = PendingRequests::default();
+        let (events, mut receiver) = broadcast::channel(1);
+        let device = Path::from("/org/bluez/hci0/dev_00_11_22_33_44_55");
+        let message =
+            Message::new_method_call("org.bluez", AGENT_PATH, AGENT_INTERFACE, "AuthorizeService")
+                .unwrap()
+                .append2(&device, "0000180d-0000-1000-8000-00805f9b34fb");
+
+        assert!(handle(&pending, &events, message).is_none());
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            AgentEvent::ConfirmationRequested(device.clone(), None)
+        );
+        assert_eq!(
+            pending.lock().unwrap()[&device].kind,
+            RequestKind::Confirmation
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! A fake BlueZ on a private D-Bus bus, for the tests of the code which talks to BlueZ.
+//!
+//! The bus is a `dbus-daemon` started once for all the tests, which `DBUS_SYSTEM_BUS_ADDRESS`
+//! points to, so [`BluetoothSession`](bluez_async::BluetoothSession) and our own connections use
+//! it. The fake only answers reads of the characteristics the tests gave a value to, and accepts
+//! starting and stopping scans.
+
+use super::server::{unknown_method, MethodError};
+use dbus::blocking::SyncConnection;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
+use dbus::Message;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Mutex, Once};
+use std::time::Duration;
+
+const ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+const CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// A characteristic of the fake BlueZ, by object path.
+#[derive(Debug, Default)]
+struct FakeCharacteristic {
+    value: Vec<u8>,
+    reads: usize,
+}
+
+static CHARACTERISTICS: Mutex<BTreeMap<String, FakeCharacteristic>> = Mutex::new(BTreeMap::new());
+
+static STARTED: Once = Once::new();
+
+/// Starts the bus and the fake BlueZ on it, unless a test already did.
+pub(super) fn start() {
+    STARTED.call_once(|| {
+        // The shell stops the bus once its standard input is closed, which happens when the test
+        // process exits.
+        let mut daemon = Command::new("sh")
+            .args([
+                "-c",
+                "dbus-daemon --session --nofork --print-address=1 & read _; kill $!",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start dbus-daemon");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        assert!(!address.is_empty(), "dbus-daemon didn't start");
+        std::mem::forget(daemon.stdin.take());
+        std::thread::spawn(move || daemon.wait());
+        std::env::set_var("DBUS_SYSTEM_BUS_ADDRESS", address.trim());
+
+        let (started, wait_started) = mpsc::channel();
+        std::thread::spawn(move || {
+            let connection = SyncConnection::new_system().unwrap();
+            connection
+                .request_name("org.bluez", false, true, false)
+                .unwrap();
+            connection.start_receive(
+                MatchRule::new_method_call(),
+                Box::new(|message, connection| {
+                    let _ = connection.send(reply(&message));
+                    true
+                }),
+            );
+            started.send(()).unwrap();
+            loop {
+                connection.process(Duration::from_secs(1)).unwrap();
+            }
+        });
+        wait_started.recv().unwrap();
+    });
+}
+
+fn reply(message: &Message) -> Message {
+    let (Some(interface), Some(member), Some(path)) =
+        (message.interface(), message.member(), message.path())
+    else {
+        return error(message, unknown_method());
+    };
+    match (&*interface, &*member) {
+        (CHARACTERISTIC_INTERFACE, "ReadValue") => {
+            match CHARACTERISTICS.lock().unwrap().get_mut(&*path) {
+                Some(characteristic) => {
+                    characteristic.reads += 1;
+                    message
+                        .method_return()
+                        .append1(characteristic.value.clone())
+                }
+                None => error(message, unknown_method()),
+            }
+        }
+        // Scanning only needs to be accepted.
+        (PROPERTIES_INTERFACE, "Set")
+        | (ADAPTER_INTERFACE, "SetDiscoveryFilter" | "StartDiscovery" | "StopDiscovery") => {
+            message.method_return()
+        }
+        _ => error(message, unknown_method()),
+    }
+}
+
+fn error(message: &Message, (name, text): MethodError) -> Message {
+    message.error(&name.into(), &CString::new(text).unwrap_or_default())
+}
+
+/// Gives the characteristic at `path` a value, which reads of it return.
+pub(super) fn set_characteristic_value(path: &str, value: &[u8]) {
+    CHARACTERISTICS
+        .lock()
+        .unwrap()
+        .entry(path.to_string())
+        .or_default()
+        .value = value.to_vec();
+}
+
+/// How many times the characteristic at `path` was read.
+pub(super) fn characteristic_reads(path: &str) -> usize {
+    CHARACTERISTICS
+        .lock()
+        .unwrap()
+        .get(path)
+        .map_or(0, |characteristic| characteristic.reads)
+}
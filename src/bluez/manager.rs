@@ -1,21 +1,327 @@
 use super::adapter::Adapter;
-use crate::{api, Result};
+use super::agent::{Agent, AgentEvent};
+use super::peripheral::Services;
+use crate::api::{self, Central, ManagerEvent, NameSource, PeripheralProperties, ScanFilter};
+use crate::common::value_cache::ValueCache;
+use crate::interceptor::{InterceptorList, Interceptors};
+use crate::scan_session::ScanSessions;
+use crate::{Error, Result};
 use async_trait::async_trait;
-use bluez_async::BluetoothSession;
+use bluez_async::{AdapterId, BluetoothSession, DeviceId, DeviceInfo};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::{
+    ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved,
+};
+use dbus::nonblock::{MsgMatch, SyncConnection};
+use dbus::{Message, Path};
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Formatter};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::broadcast;
+use tokio::task::AbortHandle;
+
+const ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+
+/// State shared by a [`Manager`] and the adapters and peripherals it returned.
+pub(crate) struct Shared {
+    pub session: BluetoothSession,
+    // Our own connection to D-Bus, for the signals bluez-async doesn't report. It is only opened
+    // once events are asked for.
+    connection: Mutex<Option<Arc<SyncConnection>>>,
+    // The pairing agent, once an adapter was asked to handle pairing requests. BlueZ has a single
+    // agent per application, whichever adapter the device is on.
+    pub agent: tokio::sync::Mutex<Option<Agent>>,
+    pub agent_events: broadcast::Sender<AgentEvent>,
+    // BlueZ connections are system wide, so keep track of the devices connected through btleplug:
+    // those are the ones to disconnect on shutdown.
+    connected: Mutex<HashSet<DeviceId>>,
+    // Adapters are created anew for every call to `adapters`, so the scan sessions are kept here.
+    scan_sessions: Mutex<HashMap<AdapterId, Arc<ScanSessions>>>,
+    // The filters of the scans running on adapters, which the events of all the adapters created
+    // for the same adapter are checked against.
+    scan_filters: Mutex<HashMap<AdapterId, Arc<Mutex<ScanFilter>>>>,
+    // The GAP device names read through btleplug, along with the name BlueZ had for the device at
+    // the time. Peripherals are created anew for every call to `peripherals` too.
+    device_names: Mutex<HashMap<DeviceId, (String, Option<String>)>>,
+    // The interceptors registered on adapters and devices, which are created anew on every lookup.
+    adapter_interceptors: Mutex<HashMap<AdapterId, Arc<InterceptorList>>>,
+    device_interceptors: Mutex<HashMap<DeviceId, Arc<InterceptorList>>>,
+    // The services discovered on devices, which all the peripherals created for a device share, so
+    // they all see the services discovered again after a change.
+    device_services: Mutex<HashMap<DeviceId, Services>>,
+    // The last values read from or notified by devices, shared the same way.
+    device_values: Mutex<HashMap<DeviceId, Arc<ValueCache>>>,
+    // The devices long value chunking was turned on for.
+    chunked_devices: Mutex<HashSet<DeviceId>>,
+    // The tasks driving our own D-Bus connections. bluez-async doesn't give a way to stop the one
+    // of the session, it ends with the runtime.
+    tasks: Mutex<Vec<AbortHandle>>,
+    shut_down: AtomicBool,
+}
+
+impl Shared {
+    fn new(session: BluetoothSession) -> Self {
+        Self {
+            session,
+            connection: Mutex::new(None),
+            agent: tokio::sync::Mutex::new(None),
+            agent_events: broadcast::channel(16).0,
+            connected: Mutex::new(HashSet::new()),
+            scan_sessions: Mutex::new(HashMap::new()),
+            scan_filters: Mutex::new(HashMap::new()),
+            device_names: Mutex::new(HashMap::new()),
+            adapter_interceptors: Mutex::new(HashMap::new()),
+            device_interceptors: Mutex::new(HashMap::new()),
+            device_services: Mutex::new(HashMap::new()),
+            device_values: Mutex::new(HashMap::new()),
+            chunked_devices: Mutex::new(HashSet::new()),
+            tasks: Mutex::new(Vec::new()),
+            shut_down: AtomicBool::new(false),
+        }
+    }
+
+    /// State for tests, talking to the fake BlueZ of [`fake_bluez`](super::fake_bluez).
+    #[cfg(test)]
+    pub(super) async fn for_tests() -> Arc<Self> {
+        super::fake_bluez::start();
+        let (_, session) = BluetoothSession::new().await.unwrap();
+        Arc::new(Self::new(session))
+    }
+
+    /// Our own connection to D-Bus, opening it if this is the first time it's needed.
+    pub fn connection(&self) -> Result<Arc<SyncConnection>> {
+        let mut connection = self.connection.lock().unwrap();
+        if let Some(connection) = &*connection {
+            return Ok(connection.clone());
+        }
+        let (resource, new_connection) =
+            dbus_tokio::connection::new_system_sync().map_err(|e| Error::Other(Box::new(e)))?;
+        new_connection.set_signal_match_mode(true);
+        let connection_task = tokio::spawn(async {
+            let err = resource.await;
+            log::error!("Lost connection to D-Bus: {}", err);
+        });
+        self.tasks
+            .lock()
+            .unwrap()
+            .push(connection_task.abort_handle());
+        *connection = Some(new_connection.clone());
+        Ok(new_connection)
+    }
+
+    pub fn connected(&self, device: &DeviceId) {
+        self.connected.lock().unwrap().insert(device.clone());
+    }
+
+    pub fn disconnected(&self, device: &DeviceId) {
+        self.connected.lock().unwrap().remove(device);
+    }
+
+    pub fn scan_sessions(&self, adapter: &AdapterId) -> Arc<ScanSessions> {
+        self.scan_sessions
+            .lock()
+            .unwrap()
+            .entry(adapter.clone())
+            .or_default()
+            .clone()
+    }
+
+    pub fn scan_filter(&self, adapter: &AdapterId) -> Arc<Mutex<ScanFilter>> {
+        self.scan_filters
+            .lock()
+            .unwrap()
+            .entry(adapter.clone())
+            .or_default()
+            .clone()
+    }
+
+    pub fn adapter_interceptors(&self, adapter: &AdapterId) -> Arc<InterceptorList> {
+        self.adapter_interceptors
+            .lock()
+            .unwrap()
+            .entry(adapter.clone())
+            .or_default()
+            .clone()
+    }
+
+    pub fn device_interceptors(&self, device: &DeviceId) -> Arc<InterceptorList> {
+        self.device_interceptors
+            .lock()
+            .unwrap()
+            .entry(device.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// The interceptors applying to a device: those of its adapter, then its own.
+    pub fn interceptors(&self, device: &DeviceId) -> Interceptors {
+        Interceptors::new(
+            Some(self.adapter_interceptors(&device.adapter())),
+            self.device_interceptors(device),
+        )
+    }
+
+    pub(super) fn device_services(&self, device: &DeviceId) -> Services {
+        self.device_services
+            .lock()
+            .unwrap()
+            .entry(device.clone())
+            .or_default()
+            .clone()
+    }
+
+    pub(super) fn value_cache(&self, device: &DeviceId) -> Arc<ValueCache> {
+        self.device_values
+            .lock()
+            .unwrap()
+            .entry(device.clone())
+            .or_insert_with(|| Arc::new(ValueCache::new()))
+            .clone()
+    }
+
+    pub fn set_long_value_chunking(&self, device: &DeviceId, enabled: bool) {
+        let mut chunked_devices = self.chunked_devices.lock().unwrap();
+        if enabled {
+            chunked_devices.insert(device.clone());
+        } else {
+            chunked_devices.remove(device);
+        }
+    }
+
+    pub fn long_value_chunking(&self, device: &DeviceId) -> bool {
+        self.chunked_devices.lock().unwrap().contains(device)
+    }
+
+    pub fn set_device_name(&self, device: &DeviceId, name: String, bluez_name: Option<String>) {
+        self.device_names
+            .lock()
+            .unwrap()
+            .insert(device.clone(), (name, bluez_name));
+    }
+
+    /// Converts the info of a device to its properties, with the device name read for it if BlueZ
+    /// hasn't got a newer name since.
+    pub fn properties(&self, device: DeviceInfo) -> PeripheralProperties {
+        let mut device_names = self.device_names.lock().unwrap();
+        let device_name = match device_names.get(&device.id) {
+            Some((name, bluez_name)) if *bluez_name == device.name => Some(name.clone()),
+            Some(_) => {
+                device_names.remove(&device.id);
+                None
+            }
+            None => None,
+        };
+        let mut properties = PeripheralProperties::from(device);
+        if let Some(name) = device_name {
+            properties.local_name = Some(name);
+            properties.local_name_source = Some(NameSource::DeviceName);
+        }
+        properties
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.shut_down.store(true, Ordering::Relaxed);
+        let result = disconnect_all(&self.session, self.take_connected()).await;
+        for task in self.tasks.lock().unwrap().iter() {
+            task.abort();
+        }
+        result
+    }
+
+    fn take_connected(&self) -> Vec<DeviceId> {
+        self.connected.lock().unwrap().drain().collect()
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        if self.shut_down.load(Ordering::Relaxed) {
+            return;
+        }
+        let devices = self.take_connected();
+        let tasks = std::mem::take(self.tasks.get_mut().unwrap());
+        match tokio::runtime::Handle::try_current() {
+            // The disconnections need the D-Bus connection, so only stop it afterwards.
+            Ok(runtime) if !devices.is_empty() => {
+                let session = self.session.clone();
+                runtime.spawn(async move {
+                    if let Err(e) = disconnect_all(&session, devices).await {
+                        log::warn!("Failed to disconnect on drop: {}", e);
+                    }
+                    tasks.iter().for_each(AbortHandle::abort);
+                });
+            }
+            _ => tasks.iter().for_each(AbortHandle::abort),
+        }
+    }
+}
+
+impl Debug for Shared {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Shared")
+            .field("connected", &self.connected)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Disconnects all the given devices, returning the first error.
+async fn disconnect_all(session: &BluetoothSession, devices: Vec<DeviceId>) -> Result<()> {
+    let mut result = Ok(());
+    for device in devices {
+        result = result.and(session.disconnect(&device).await.map_err(Error::from));
+    }
+    result
+}
 
 /// Implementation of [api::Manager](crate::api::Manager).
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Manager {
+    shared: Arc<Shared>,
     session: BluetoothSession,
 }
 
 impl Manager {
+    /// Connects to BlueZ on the D-Bus system bus.
+    ///
+    /// The bus can't be chosen here: bluez-async, which btleplug uses to talk to BlueZ, always
+    /// opens its own connection to the system bus. libdbus finds that bus through the
+    /// `DBUS_SYSTEM_BUS_ADDRESS` environment variable though, so setting it before calling this
+    /// connects to another bus, e.g. the host's bus from a container or a mock BlueZ in tests.
     pub async fn new() -> Result<Self> {
         let (_, session) = BluetoothSession::new().await?;
-        Ok(Self { session })
+        let shared = Arc::new(Shared::new(session.clone()));
+        Ok(Self { shared, session })
+    }
+
+    async fn adapter_ids(&self) -> Result<HashMap<Path<'static>, AdapterId>> {
+        Ok(self
+            .session
+            .get_adapters()
+            .await?
+            .into_iter()
+            .map(|adapter| (adapter.id.clone().into(), adapter.id))
+            .collect())
     }
 }
 
+impl Debug for Manager {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Manager")
+            .field("session", &self.session)
+            .finish()
+    }
+}
+
+enum AdapterSignal {
+    Added(Path<'static>),
+    Removed(Path<'static>),
+}
+
 #[async_trait]
 impl api::Manager for Manager {
     type Adapter = Adapter;
@@ -24,7 +330,135 @@ impl api::Manager for Manager {
         let adapters = self.session.get_adapters().await?;
         Ok(adapters
             .into_iter()
-            .map(|adapter| Adapter::new(self.session.clone(), adapter.id))
+            .map(|adapter| Adapter::new(self.shared.clone(), adapter.id))
             .collect())
     }
+
+    /// Stops scanning on all the adapters and disconnects the devices connected through btleplug.
+    /// Devices connected by other applications stay connected.
+    async fn shutdown(&self) -> Result<()> {
+        for adapter in self.adapters().await? {
+            if let Err(e) = adapter.stop_scan().await {
+                log::debug!("Stopping the scan on shutdown failed: {}", e);
+            }
+        }
+        self.shared.shutdown().await
+    }
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = ManagerEvent<Adapter>> + Send>>> {
+        // bluez-async doesn't report adapters being added or removed, so listen for the
+        // ObjectManager signals on a connection of our own.
+        let connection = self.shared.connection()?;
+        let bus_name = "org.bluez".into();
+        // Subscribe before looking up the current adapters, so none can be missed in between.
+        let added = MessageStream::new(
+            &connection,
+            ObjectManagerInterfacesAdded::match_rule(Some(&bus_name), None).static_clone(),
+        )
+        .await?
+        .filter_map(|message| async move {
+            let signal = ObjectManagerInterfacesAdded::from_message(&message)?;
+            signal
+                .interfaces
+                .contains_key(ADAPTER_INTERFACE)
+                .then_some(AdapterSignal::Added(signal.object))
+        });
+        let removed = MessageStream::new(
+            &connection,
+            ObjectManagerInterfacesRemoved::match_rule(Some(&bus_name), None).static_clone(),
+        )
+        .await?
+        .filter_map(|message| async move {
+            let signal = ObjectManagerInterfacesRemoved::from_message(&message)?;
+            signal
+                .interfaces
+                .iter()
+                .any(|interface| interface == ADAPTER_INTERFACE)
+                .then_some(AdapterSignal::Removed(signal.object))
+        });
+        let signals = stream::select(added, removed).boxed();
+
+        let known_adapters = self.adapter_ids().await?;
+        let manager = self.clone();
+        Ok(Box::pin(stream::unfold(
+            (signals, known_adapters),
+            move |(mut signals, mut known_adapters)| {
+                let manager = manager.clone();
+                async move {
+                    loop {
+                        let event = match signals.next().await? {
+                            AdapterSignal::Added(path) => {
+                                // The signal only has the object path, get the ID from BlueZ.
+                                let Ok(mut adapter_ids) = manager.adapter_ids().await else {
+                                    continue;
+                                };
+                                let Some(id) = adapter_ids.remove(&path) else {
+                                    continue;
+                                };
+                                known_adapters.insert(path, id.clone());
+                                ManagerEvent::AdapterAdded(Adapter::new(manager.shared.clone(), id))
+                            }
+                            AdapterSignal::Removed(path) => {
+                                let Some(id) = known_adapters.remove(&path) else {
+                                    continue;
+                                };
+                                ManagerEvent::AdapterRemoved(Adapter::new(
+                                    manager.shared.clone(),
+                                    id,
+                                ))
+                            }
+                        };
+                        return Some((event, (signals, known_adapters)));
+                    }
+                }
+            },
+        )))
+    }
+}
+
+/// A stream of D-Bus messages matching a rule, which removes the match from the connection when
+/// it is dropped.
+pub(super) struct MessageStream {
+    msg_match: Option<MsgMatch>,
+    messages: UnboundedReceiver<Message>,
+    connection: Arc<SyncConnection>,
+}
+
+impl MessageStream {
+    pub(super) async fn new(
+        connection: &Arc<SyncConnection>,
+        rule: MatchRule<'static>,
+    ) -> Result<Self> {
+        let (msg_match, messages) = connection
+            .add_match(rule)
+            .await
+            .map_err(|e| Error::Other(Box::new(e)))?
+            .msg_stream();
+        Ok(Self {
+            msg_match: Some(msg_match),
+            messages,
+            connection: connection.clone(),
+        })
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        Pin::new(&mut self.messages).poll_next(cx)
+    }
+}
+
+impl Drop for MessageStream {
+    fn drop(&mut self) {
+        if let Some(msg_match) = self.msg_match.take() {
+            let connection = self.connection.clone();
+            tokio::spawn(async move {
+                if let Err(e) = connection.remove_match(msg_match.token()).await {
+                    log::debug!("Failed to remove D-Bus match: {}", e);
+                }
+            });
+        }
+    }
 }
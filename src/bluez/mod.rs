@@ -1,3 +1,29 @@
 pub mod adapter;
+mod advertising;
+mod agent;
+#[cfg(test)]
+mod fake_bluez;
 pub mod manager;
 pub mod peripheral;
+pub mod server;
+
+use bluez_async::BluetoothError;
+
+/// D-Bus errors BlueZ returns while it's busy, which go away once it's done.
+const TRANSIENT_DBUS_ERRORS: [&str; 3] = [
+    "org.bluez.Error.InProgress",
+    "org.bluez.Error.NotReady",
+    "org.freedesktop.DBus.Error.NoReply",
+];
+
+/// Whether a platform error wrapped in [`Error::Other`](crate::Error::Other) may go away when
+/// retried.
+pub(crate) fn is_transient_error(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    match error.downcast_ref::<BluetoothError>() {
+        Some(BluetoothError::ServiceDiscoveryTimedOut) => true,
+        Some(BluetoothError::DbusError(e)) => e
+            .name()
+            .is_some_and(|name| TRANSIENT_DBUS_ERRORS.contains(&name)),
+        _ => false,
+    }
+}
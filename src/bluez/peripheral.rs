@@ -1,8 +1,9 @@
+use super::manager::Shared;
 use async_trait::async_trait;
 use bluez_async::{
-    BluetoothEvent, BluetoothSession, CharacteristicEvent, CharacteristicFlags, CharacteristicId,
-    CharacteristicInfo, DescriptorInfo, DeviceId, DeviceInfo, MacAddress, ServiceInfo,
-    WriteOptions,
+    BluetoothError, BluetoothEvent, BluetoothSession, CharacteristicEvent, CharacteristicFlags,
+    CharacteristicId, CharacteristicInfo, DescriptorInfo, DeviceEvent, DeviceId, DeviceInfo,
+    MacAddress, ServiceInfo, WriteOptions,
 };
 use futures::future::{join_all, ready};
 use futures::stream::{Stream, StreamExt};
@@ -10,36 +11,67 @@ use futures::stream::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
 use serde_cr as serde;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::BTreeSet;
 use std::fmt::{self, Display, Formatter};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::timeout;
 use uuid::Uuid;
 
 use crate::api::{
-    self, AddressType, BDAddr, CharPropFlags, Characteristic, Descriptor, PeripheralProperties,
-    Service, ValueNotification, WriteType,
+    self, AddressType, BDAddr, CharPropFlags, Characteristic, Descriptor, Feature, NameSource,
+    PeripheralProperties, Service, ValueNotification, WriteType,
 };
+use crate::common::{long_value, value_cache::ValueCache};
+use crate::gatt_log::{self, GattOperation};
+use crate::interceptor::{GattInterceptor, GattRequest, Interceptors};
 use crate::{Error, Result};
 
+/// How long [`Peripheral::discover_services`] waits for BlueZ to resolve the services of a device.
+const SERVICES_RESOLVED_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait between connection attempts with [`api::ConnectOptions::auto_connect`].
+const AUTO_CONNECT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Whether `error` means the device couldn't be reached, rather than that connecting to it can't
+/// work at all.
+fn is_connect_attempt_failure(error: &BluetoothError) -> bool {
+    match error {
+        BluetoothError::DbusError(e) => matches!(
+            e.name(),
+            Some("org.bluez.Error.Failed")
+                | Some("org.bluez.Error.InProgress")
+                | Some("org.freedesktop.DBus.Error.NoReply")
+        ),
+        _ => false,
+    }
+}
+
 #[derive(Clone, Debug)]
 struct CharacteristicInternal {
     info: CharacteristicInfo,
-    descriptors: HashMap<Uuid, DescriptorInfo>,
+    descriptors: Vec<DescriptorInfo>,
 }
 
 impl CharacteristicInternal {
-    fn new(info: CharacteristicInfo, descriptors: HashMap<Uuid, DescriptorInfo>) -> Self {
+    fn new(info: CharacteristicInfo, descriptors: Vec<DescriptorInfo>) -> Self {
         Self { info, descriptors }
     }
 }
 
 #[derive(Clone, Debug)]
-struct ServiceInternal {
+pub(super) struct ServiceInternal {
     info: ServiceInfo,
-    characteristics: HashMap<Uuid, CharacteristicInternal>,
+    // A service may have several characteristics with the same UUID, which only their handles
+    // tell apart.
+    characteristics: Vec<CharacteristicInternal>,
 }
 
+/// The services discovered on a device. A device may have several instances of a service, with
+/// the same UUID.
+pub(super) type Services = Arc<Mutex<Vec<ServiceInternal>>>;
+
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
@@ -57,24 +89,38 @@ impl Display for PeripheralId {
 /// Implementation of [api::Peripheral](crate::api::Peripheral).
 #[derive(Clone, Debug)]
 pub struct Peripheral {
+    shared: Arc<Shared>,
     session: BluetoothSession,
     device: DeviceId,
     mac_address: BDAddr,
-    services: Arc<Mutex<HashMap<Uuid, ServiceInternal>>>,
+    services: Services,
+    properties: Arc<Mutex<Arc<PeripheralProperties>>>,
+    value_cache: Arc<ValueCache>,
 }
 
+/// Looks up a characteristic by its UUID, and by its handle if it has one.
+/// Without a handle, the first instance of the service which has the characteristic is used.
 fn get_characteristic<'a>(
-    services: &'a HashMap<Uuid, ServiceInternal>,
+    services: &'a [ServiceInternal],
     service_uuid: &Uuid,
     characteristic_uuid: &Uuid,
+    handle: Option<u16>,
 ) -> Result<&'a CharacteristicInternal> {
-    services
-        .get(service_uuid)
-        .ok_or_else(|| {
-            Error::Other(format!("Service with UUID {} not found.", service_uuid).into())
-        })?
-        .characteristics
-        .get(characteristic_uuid)
+    let mut instances = services
+        .iter()
+        .filter(|service| service.info.uuid == *service_uuid)
+        .peekable();
+    if instances.peek().is_none() {
+        return Err(Error::Other(
+            format!("Service with UUID {} not found.", service_uuid).into(),
+        ));
+    }
+    instances
+        .flat_map(|service| service.characteristics.iter())
+        .find(|characteristic| {
+            characteristic.info.uuid == *characteristic_uuid
+                && (handle.is_none() || characteristic_handle(&characteristic.info.id) == handle)
+        })
         .ok_or_else(|| {
             Error::Other(
                 format!(
@@ -86,13 +132,47 @@ fn get_characteristic<'a>(
         })
 }
 
+/// Looks up a descriptor by its UUID and those of its characteristic and service, and by its handle
+/// if it has one. Without a handle, the first instance of the characteristic which has the
+/// descriptor is used.
+fn get_descriptor<'a>(
+    services: &'a [ServiceInternal],
+    descriptor: &Descriptor,
+) -> Result<&'a DescriptorInfo> {
+    // Fail like a lookup of the characteristic if there's no instance of it at all.
+    get_characteristic(
+        services,
+        &descriptor.service_uuid,
+        &descriptor.characteristic_uuid,
+        None,
+    )?;
+    services
+        .iter()
+        .filter(|service| service.info.uuid == descriptor.service_uuid)
+        .flat_map(|service| service.characteristics.iter())
+        .filter(|characteristic| characteristic.info.uuid == descriptor.characteristic_uuid)
+        .flat_map(|characteristic| characteristic.descriptors.iter())
+        .find(|info| {
+            info.uuid == descriptor.uuid
+                && (descriptor.handle.is_none() || descriptor_handle(info) == descriptor.handle)
+        })
+        .ok_or_else(|| {
+            Error::Other(format!("Descriptor with UUID {} not found.", descriptor.uuid).into())
+        })
+}
+
 impl Peripheral {
-    pub(crate) fn new(session: BluetoothSession, device: DeviceInfo) -> Self {
+    pub(crate) fn new(shared: Arc<Shared>, device: DeviceInfo) -> Self {
+        let services = shared.device_services(&device.id);
+        let value_cache = shared.value_cache(&device.id);
         Peripheral {
-            session,
-            device: device.id,
+            session: shared.session.clone(),
+            device: device.id.clone(),
             mac_address: device.mac_address.into(),
-            services: Arc::new(Mutex::new(HashMap::new())),
+            properties: Arc::new(Mutex::new(Arc::new(shared.properties(device)))),
+            shared,
+            services,
+            value_cache,
         }
     }
 
@@ -102,6 +182,7 @@ impl Peripheral {
             &services,
             &characteristic.service_uuid,
             &characteristic.uuid,
+            characteristic.handle,
         )
         .map(|c| &c.info)
         .cloned()
@@ -109,23 +190,85 @@ impl Peripheral {
 
     fn descriptor_info(&self, descriptor: &Descriptor) -> Result<DescriptorInfo> {
         let services = self.services.lock().map_err(Into::<Error>::into)?;
-        let characteristic = get_characteristic(
-            &services,
-            &descriptor.service_uuid,
-            &descriptor.characteristic_uuid,
-        )?;
-        characteristic
-            .descriptors
-            .get(&descriptor.uuid)
-            .ok_or_else(|| {
-                Error::Other(format!("Descriptor with UUID {} not found.", descriptor.uuid).into())
-            })
-            .cloned()
+        get_descriptor(&services, descriptor).cloned()
+    }
+
+    fn update_characteristic_info(&self, info: CharacteristicInfo) -> Result<()> {
+        let mut services = self.services.lock().map_err(Into::<Error>::into)?;
+        if let Some(characteristic) = services
+            .iter_mut()
+            .flat_map(|service| service.characteristics.iter_mut())
+            .find(|characteristic| characteristic.info.id == info.id)
+        {
+            characteristic.info = info;
+        }
+        Ok(())
+    }
+
+    async fn read_characteristic_value(
+        &self,
+        characteristic: &Characteristic,
+        id: &CharacteristicId,
+    ) -> Result<Vec<u8>> {
+        let Some(mtu) = self.chunking_mtu(characteristic).await else {
+            return Ok(self.session.read_characteristic_value(id).await?);
+        };
+        long_value::read_long(long_value::read_chunk_len(mtu), |offset| async move {
+            Ok(self
+                .session
+                .read_characteristic_value_with_offset(id, offset)
+                .await?)
+        })
+        .await
+    }
+
+    /// The MTU to split long values of `characteristic` up by, if chunking was turned on and
+    /// BlueZ knows the MTU.
+    async fn chunking_mtu(&self, characteristic: &Characteristic) -> Option<u16> {
+        if !self.shared.long_value_chunking(&self.device) {
+            return None;
+        }
+        api::Peripheral::mtu(self, Some(std::slice::from_ref(characteristic)))
+            .await
+            .ok()
+    }
+
+    fn interceptors(&self) -> Interceptors {
+        self.shared.interceptors(&self.device)
     }
 
     async fn device_info(&self) -> Result<DeviceInfo> {
         Ok(self.session.get_device_info(&self.device).await?)
     }
+
+    /// Waits for BlueZ to signal that it has resolved the services of the device, if it's
+    /// connected and hasn't done so already.
+    async fn await_services_resolved(&self) -> Result<()> {
+        // Subscribe before checking the current state, so the signal can't be missed in between.
+        let mut events = self.session.device_event_stream(&self.device).await?;
+        let device_info = self.device_info().await?;
+        if !device_info.connected || device_info.services_resolved {
+            return Ok(());
+        }
+        timeout(SERVICES_RESOLVED_TIMEOUT, async {
+            while let Some(event) = events.next().await {
+                match event {
+                    BluetoothEvent::Device {
+                        event: DeviceEvent::ServicesResolved,
+                        ..
+                    } => return Ok(()),
+                    BluetoothEvent::Device {
+                        event: DeviceEvent::Connected { connected: false },
+                        ..
+                    } => return Err(Error::NotConnected),
+                    _ => {}
+                }
+            }
+            Err(Error::NotConnected)
+        })
+        .await
+        .unwrap_or(Err(Error::TimedOut(SERVICES_RESOLVED_TIMEOUT)))
+    }
 }
 
 #[async_trait]
@@ -138,26 +281,21 @@ impl api::Peripheral for Peripheral {
         self.mac_address
     }
 
-    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
-        let device_info = self.device_info().await?;
-        Ok(Some(PeripheralProperties {
-            address: device_info.mac_address.into(),
-            address_type: Some(device_info.address_type.into()),
-            local_name: device_info.name,
-            tx_power_level: device_info.tx_power,
-            rssi: device_info.rssi,
-            manufacturer_data: device_info.manufacturer_data,
-            service_data: device_info.service_data,
-            services: device_info.services,
-            class: device_info.class,
-        }))
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+        let properties = Arc::new(self.shared.properties(self.device_info().await?));
+        *self.properties.lock().map_err(Into::<Error>::into)? = properties.clone();
+        Ok(Some(properties))
+    }
+
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        Some(self.properties.lock().unwrap().clone())
     }
 
     fn services(&self) -> BTreeSet<Service> {
         self.services
             .lock()
             .unwrap()
-            .values()
+            .iter()
             .map(|service| service.into())
             .collect()
     }
@@ -167,76 +305,129 @@ impl api::Peripheral for Peripheral {
         Ok(device_info.connected)
     }
 
-    async fn mtu(&self, _characteristics: Option<&[Characteristic]>) -> Result<u16> {
+    async fn is_paired(&self) -> Result<bool> {
+        let device_info = self.device_info().await?;
+        Ok(device_info.paired)
+    }
+
+    async fn mtu(&self, characteristics: Option<&[Characteristic]>) -> Result<u16> {
         if self.services.lock().map_err(Into::<Error>::into)?.is_empty() {
             self.discover_services().await?;
         }
-        let services = self.services.lock().map_err(Into::<Error>::into)?;
+        let characteristic_ids = match characteristics {
+            Some(characteristics) => characteristics
+                .iter()
+                .map(|characteristic| Ok(self.characteristic_info(characteristic)?.id))
+                .collect::<Result<Vec<_>>>()?,
+            None => self
+                .services
+                .lock()
+                .map_err(Into::<Error>::into)?
+                .iter()
+                .flat_map(|service| service.characteristics.iter())
+                .map(|characteristic| characteristic.info.id.clone())
+                .collect(),
+        };
+        // BlueZ updates the MTU property when the MTU is exchanged, and when a characteristic is
+        // acquired with AcquireWrite or AcquireNotify, so read it again rather than relying on
+        // the value seen at discovery time. The MTU is the same for the whole connection, so
+        // without characteristics to ask the first one which reports it will do, rather than
+        // making a D-Bus call for every characteristic of the device.
         let mut max_mtu = None;
-        for service in services.values() {
-            for characteristic in service.characteristics.values() {
-                if let Some(mtu) = characteristic.info.mtu {
-                    max_mtu = Some(max_mtu.map_or(mtu, |current| current.max(mtu)));
+        for id in characteristic_ids {
+            let info = self.session.get_characteristic_info(&id).await?;
+            let mtu = info.mtu;
+            self.update_characteristic_info(info)?;
+            if let Some(mtu) = mtu {
+                max_mtu = Some(max_mtu.map_or(mtu, |current: u16| current.max(mtu)));
+                if characteristics.is_none() {
+                    break;
                 }
             }
         }
-        max_mtu.ok_or_else(|| Error::NotSupported("MTU not available".to_string()))
+        max_mtu.ok_or(Error::NotSupported {
+            feature: Feature::Mtu,
+        })
     }
 
     async fn connect(&self) -> Result<()> {
         self.session.connect(&self.device).await?;
+        self.shared.connected(&self.device);
         Ok(())
     }
 
+    async fn respond_to_pairing(&self, response: api::PairingResponse) -> Result<()> {
+        match &*self.shared.agent.lock().await {
+            Some(agent) => agent.respond(&self.device.clone().into(), response),
+            None => Err(Error::Other("No pairing request is pending".into())),
+        }
+    }
+
+    async fn connect_with(&self, options: api::ConnectOptions) -> Result<()> {
+        // BlueZ picks the transport of dual-mode devices and the PHY of LE connections by itself.
+        if !matches!(options.transport, None | Some(api::ConnectTransport::Auto)) {
+            return Err(Error::NotSupported {
+                feature: Feature::ConnectTransport,
+            });
+        }
+        if options.phy.is_some() {
+            return Err(Error::NotSupported {
+                feature: Feature::ConnectPhy,
+            });
+        }
+        let auto_connect = options.auto_connect.unwrap_or(false);
+        let connect = async {
+            loop {
+                let result = match options.timeout {
+                    // Keep the D-Bus call from timing out before the connection attempt does.
+                    Some(timeout) => {
+                        self.session
+                            .connect_with_timeout(&self.device, timeout)
+                            .await
+                    }
+                    None => self.session.connect(&self.device).await,
+                };
+                match result {
+                    Ok(()) => break,
+                    Err(e) if auto_connect && is_connect_attempt_failure(&e) => {
+                        log::debug!("Connecting to {} failed, retrying: {}", self.device, e);
+                        tokio::time::sleep(AUTO_CONNECT_RETRY_DELAY).await;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            self.shared.connected(&self.device);
+            Ok(())
+        };
+        crate::common::connect::with_timeout(self, options.timeout, connect).await
+    }
+
     async fn disconnect(&self) -> Result<()> {
         self.session.disconnect(&self.device).await?;
+        self.shared.disconnected(&self.device);
         Ok(())
     }
 
     async fn discover_services(&self) -> Result<()> {
-        let mut services_internal = HashMap::new();
+        self.await_services_resolved().await?;
+        let mut services_internal = Vec::new();
         let services = self.session.get_services(&self.device).await?;
         for service in services {
             let characteristics = self.session.get_characteristics(&service.id).await?;
-            let characteristics = join_all(
-                characteristics
-                    .into_iter()
-                    .fold(
-                        // Only consider the first characteristic of each UUID
-                        // This "should" be unique, but of course it's not enforced
-                        HashMap::<Uuid, CharacteristicInfo>::new(),
-                        |mut map, characteristic| {
-                            if !map.contains_key(&characteristic.uuid) {
-                                map.insert(characteristic.uuid, characteristic);
-                            }
-                            map
-                        },
-                    )
-                    .into_iter()
-                    .map(|mapped_characteristic| async {
-                        let characteristic = mapped_characteristic.1;
-                        let descriptors = self
-                            .session
-                            .get_descriptors(&characteristic.id)
-                            .await
-                            .unwrap_or(Vec::new())
-                            .into_iter()
-                            .map(|descriptor| (descriptor.uuid, descriptor))
-                            .collect();
-                        CharacteristicInternal::new(characteristic, descriptors)
-                    }),
-            )
-            .await;
-            services_internal.insert(
-                service.uuid,
-                ServiceInternal {
-                    info: service,
-                    characteristics: characteristics
-                        .into_iter()
-                        .map(|characteristic| (characteristic.info.uuid, characteristic))
-                        .collect(),
-                },
-            );
+            let characteristics =
+                join_all(characteristics.into_iter().map(|characteristic| async {
+                    let descriptors = self
+                        .session
+                        .get_descriptors(&characteristic.id)
+                        .await
+                        .unwrap_or(Vec::new());
+                    CharacteristicInternal::new(characteristic, descriptors)
+                }))
+                .await;
+            services_internal.push(ServiceInternal {
+                info: service,
+                characteristics,
+            });
         }
         *(self.services.lock().map_err(Into::<Error>::into)?) = services_internal;
         Ok(())
@@ -249,22 +440,81 @@ impl api::Peripheral for Peripheral {
         write_type: WriteType,
     ) -> Result<()> {
         let characteristic_info = self.characteristic_info(characteristic)?;
-        let options = WriteOptions {
-            write_type: Some(write_type.into()),
-            ..Default::default()
-        };
-        Ok(self
-            .session
-            .write_characteristic_value_with_options(&characteristic_info.id, data, options)
-            .await?)
+        let id = self.id();
+        let request = GattRequest::characteristic(&id, characteristic, write_type.into());
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                gatt_log::log_traffic(&self.id(), characteristic.uuid, write_type.into(), &data);
+                // BlueZ does a long write by itself when the value doesn't fit in the MTU.
+                let options = WriteOptions {
+                    write_type: Some(write_type.into()),
+                    ..Default::default()
+                };
+                Ok(self
+                    .session
+                    .write_characteristic_value_with_options(&characteristic_info.id, data, options)
+                    .await?)
+            })
+            .await
+    }
+
+    async fn set_long_value_chunking(&self, enabled: bool) -> Result<()> {
+        self.shared.set_long_value_chunking(&self.device, enabled);
+        Ok(())
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
         let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self
-            .session
-            .read_characteristic_value(&characteristic_info.id)
-            .await?)
+        let id = self.id();
+        let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+        self.interceptors()
+            .read(&request, async {
+                let value = self
+                    .read_characteristic_value(characteristic, &characteristic_info.id)
+                    .await?;
+                gatt_log::log_traffic(&id, characteristic.uuid, GattOperation::Read, &value);
+                self.value_cache.insert(characteristic, value.clone());
+                Ok(value)
+            })
+            .await
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        if let Some(value) = self.value_cache.get(characteristic, max_age) {
+            // The cache holds values as received, so they go through the interceptors again.
+            let id = self.id();
+            let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+            return self
+                .interceptors()
+                .read(&request, async { Ok(value) })
+                .await;
+        }
+        self.read(characteristic).await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        let name = match api::read_gap_device_name(self).await {
+            Ok(name) => name,
+            Err(Error::NoSuchService) => {
+                // BlueZ keeps the GAP service to itself, and sets the name of the device from the
+                // Device Name characteristic when connecting.
+                let device_info = self.device_info().await?;
+                if !device_info.connected {
+                    return Err(Error::NotConnected);
+                }
+                device_info.name.ok_or(Error::NoSuchService)?
+            }
+            Err(e) => return Err(e),
+        };
+        let bluez_name = self.device_info().await?.name;
+        self.shared
+            .set_device_name(&self.device, name.clone(), bluez_name);
+        api::Peripheral::properties(self).await?;
+        Ok(name)
     }
 
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
@@ -281,32 +531,87 @@ impl api::Peripheral for Peripheral {
         let device_id = self.device.clone();
         let events = self.session.device_event_stream(&device_id).await?;
         let services = self.services.clone();
-        Ok(Box::pin(events.filter_map(move |event| {
-            ready(value_notification(event, &device_id, services.clone()))
-        })))
+        let value_cache = self.value_cache.clone();
+        let events = events.take_while(|event| {
+            ready(!matches!(
+                event,
+                BluetoothEvent::Device {
+                    event: DeviceEvent::Connected { connected: false },
+                    ..
+                }
+            ))
+        });
+        let notifications = Box::pin(events.filter_map(move |event| {
+            let notification = value_notification(event, &device_id, services.clone());
+            if let Some(notification) = &notification {
+                gatt_log::log_traffic(
+                    &PeripheralId(device_id.clone()),
+                    notification.uuid,
+                    GattOperation::Notification,
+                    &notification.value,
+                );
+                value_cache.insert(notification, notification.value.clone());
+            }
+            ready(notification)
+        }));
+        Ok(self.interceptors().notifications(self.id(), notifications))
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
         let descriptor_info = self.descriptor_info(descriptor)?;
-        Ok(self
-            .session
-            .write_descriptor_value(&descriptor_info.id, data)
-            .await?)
+        let id = self.id();
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::WriteDescriptor);
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                gatt_log::log_traffic(
+                    &self.id(),
+                    descriptor.uuid,
+                    GattOperation::WriteDescriptor,
+                    &data,
+                );
+                Ok(self
+                    .session
+                    .write_descriptor_value(&descriptor_info.id, data)
+                    .await?)
+            })
+            .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
         let descriptor_info = self.descriptor_info(descriptor)?;
+        let id = self.id();
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::ReadDescriptor);
+        self.interceptors()
+            .read(&request, async {
+                let value = self
+                    .session
+                    .read_descriptor_value(&descriptor_info.id)
+                    .await?;
+                gatt_log::log_traffic(&id, descriptor.uuid, GattOperation::ReadDescriptor, &value);
+                Ok(value)
+            })
+            .await
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.shared
+            .device_interceptors(&self.device)
+            .add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
         Ok(self
-            .session
-            .read_descriptor_value(&descriptor_info.id)
-            .await?)
+            .shared
+            .device_interceptors(&self.device)
+            .remove(interceptor))
     }
 }
 
 fn value_notification(
     event: BluetoothEvent,
     device_id: &DeviceId,
-    services: Arc<Mutex<HashMap<Uuid, ServiceInternal>>>,
+    services: Arc<Mutex<Vec<ServiceInternal>>>,
 ) -> Option<ValueNotification> {
     match event {
         BluetoothEvent::Characteristic {
@@ -314,21 +619,28 @@ fn value_notification(
             event: CharacteristicEvent::Value { value },
         } if id.service().device() == *device_id => {
             let services = services.lock().unwrap();
-            let uuid = find_characteristic_by_id(&services, id)?.uuid;
-            Some(ValueNotification { uuid, value })
+            let (service_uuid, characteristic) = find_characteristic_by_id(&services, id)?;
+            Some(ValueNotification {
+                uuid: characteristic.uuid,
+                service_uuid,
+                handle: characteristic_handle(&characteristic.id),
+                value,
+                is_indication: CharPropFlags::from(characteristic.flags)
+                    .subscribes_to_indications(),
+            })
         }
         _ => None,
     }
 }
 
 fn find_characteristic_by_id(
-    services: &HashMap<Uuid, ServiceInternal>,
+    services: &[ServiceInternal],
     characteristic_id: CharacteristicId,
-) -> Option<&CharacteristicInfo> {
-    for service in services.values() {
-        for characteristic in service.characteristics.values() {
+) -> Option<(Uuid, &CharacteristicInfo)> {
+    for service in services {
+        for characteristic in service.characteristics.iter() {
             if characteristic.info.id == characteristic_id {
-                return Some(&characteristic.info);
+                return Some((service.info.uuid, &characteristic.info));
             }
         }
     }
@@ -340,6 +652,9 @@ impl From<WriteType> for bluez_async::WriteType {
         match write_type {
             WriteType::WithoutResponse => bluez_async::WriteType::WithoutResponse,
             WriteType::WithResponse => bluez_async::WriteType::WithResponse,
+            // BlueZ signs Write Commands to characteristics with the authenticated signed writes
+            // property by itself, when the link isn't encrypted.
+            WriteType::Signed => bluez_async::WriteType::WithoutResponse,
         }
     }
 }
@@ -356,6 +671,36 @@ impl From<DeviceId> for PeripheralId {
     }
 }
 
+impl From<DeviceInfo> for PeripheralProperties {
+    fn from(device_info: DeviceInfo) -> Self {
+        PeripheralProperties {
+            address: device_info.mac_address.into(),
+            address_type: Some(device_info.address_type.into()),
+            local_name_source: device_info.name.as_ref().map(|_| NameSource::Platform),
+            local_name: device_info.name,
+            tx_power_level: device_info.tx_power,
+            rssi: device_info.rssi,
+            manufacturer_data: device_info.manufacturer_data,
+            service_data: device_info.service_data,
+            services: device_info.services,
+            class: device_info.class,
+            appearance: device_info.appearance,
+            is_paired: Some(device_info.paired),
+            advertising_interval: None,
+            uri: None,
+            le_supported_features: None,
+            advertised_interval: None,
+            public_target_addresses: Vec::new(),
+            advertisement_data: None,
+            scan_response_data: None,
+            raw_data: None,
+            secondary_phy: None,
+            advertising_sid: None,
+            periodic_advertising_interval: None,
+        }
+    }
+}
+
 impl From<bluez_async::AddressType> for AddressType {
     fn from(address_type: bluez_async::AddressType) -> Self {
         match address_type {
@@ -365,6 +710,22 @@ impl From<bluez_async::AddressType> for AddressType {
     }
 }
 
+/// The handle BlueZ puts at the end of the object path of an attribute, e.g. `char000b`.
+fn handle_from_path(path: &str, prefix: &str) -> Option<u16> {
+    let name = path.rsplit('/').next()?;
+    u16::from_str_radix(name.strip_prefix(prefix)?, 16).ok()
+}
+
+/// The handle of the value of a characteristic. BlueZ names characteristics after the handle of
+/// their declaration, which the value always follows.
+fn characteristic_handle(id: &CharacteristicId) -> Option<u16> {
+    handle_from_path(&id.to_string(), "char")?.checked_add(1)
+}
+
+fn descriptor_handle(info: &DescriptorInfo) -> Option<u16> {
+    handle_from_path(&info.id.to_string(), "desc")
+}
+
 fn make_descriptor(
     info: &DescriptorInfo,
     characteristic_uuid: Uuid,
@@ -374,6 +735,8 @@ fn make_descriptor(
         uuid: info.uuid,
         characteristic_uuid,
         service_uuid,
+        handle: descriptor_handle(info),
+        permissions: None,
     }
 }
 
@@ -387,9 +750,10 @@ fn make_characteristic(
         properties: info.flags.into(),
         descriptors: descriptors
             .iter()
-            .map(|(_, descriptor)| make_descriptor(descriptor, info.uuid, service_uuid))
+            .map(|descriptor| make_descriptor(descriptor, info.uuid, service_uuid))
             .collect(),
         service_uuid,
+        handle: characteristic_handle(&info.id),
     }
 }
 
@@ -398,9 +762,10 @@ impl From<&ServiceInternal> for Service {
         Service {
             uuid: service.info.uuid,
             primary: service.info.primary,
+            handle: handle_from_path(&service.info.id.to_string(), "service"),
             characteristics: service
                 .characteristics
-                .values()
+                .iter()
                 .map(|characteristic| make_characteristic(characteristic, service.info.uuid))
                 .collect(),
         }
@@ -437,3 +802,165 @@ impl From<CharacteristicFlags> for CharPropFlags {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::fake_bluez;
+    use super::*;
+    use crate::api::Peripheral as _;
+
+    #[test]
+    fn handles_from_paths() {
+        let service = "/org/bluez/hci0/dev_11_22_33_44_55_66/service000a";
+        assert_eq!(
+            handle_from_path(&format!("{}/char000b", service), "char"),
+            Some(0x000b)
+        );
+        assert_eq!(
+            handle_from_path(&format!("{}/char000b/desc000d", service), "desc"),
+            Some(0x000d)
+        );
+        assert_eq!(handle_from_path(service, "service"), Some(0x000a));
+        assert_eq!(handle_from_path(service, "char"), None);
+        assert_eq!(handle_from_path("hci0/dev_11_22_33_44_55_66", "desc"), None);
+    }
+
+    #[test]
+    fn descriptors_of_every_instance_are_found() {
+        let path = |path: String| serde_json::json!({ "object_path": path });
+        let service_uuid = Uuid::from_u128(0x180f);
+        let characteristic_uuid = Uuid::from_u128(0x2a19);
+        let descriptor_uuid = Uuid::from_u128(0x2902);
+        // Two instances of the service, the second one with two instances of the characteristic.
+        let services = [(0x0010, vec![0x0011]), (0x0020, vec![0x0021, 0x0024])]
+            .into_iter()
+            .map(|(service, characteristics): (u16, Vec<u16>)| {
+                let service_path = format!("{}/service{:04x}", DEVICE_PATH, service);
+                ServiceInternal {
+                    info: ServiceInfo {
+                        id: serde_json::from_value(path(service_path.clone())).unwrap(),
+                        uuid: service_uuid,
+                        primary: true,
+                    },
+                    characteristics: characteristics
+                        .into_iter()
+                        .map(|characteristic| {
+                            let characteristic_path =
+                                format!("{}/char{:04x}", service_path, characteristic);
+                            let descriptor = DescriptorInfo {
+                                id: serde_json::from_value(path(format!(
+                                    "{}/desc{:04x}",
+                                    characteristic_path,
+                                    characteristic + 2
+                                )))
+                                .unwrap(),
+                                uuid: descriptor_uuid,
+                            };
+                            let info = CharacteristicInfo {
+                                id: serde_json::from_value(path(characteristic_path)).unwrap(),
+                                uuid: characteristic_uuid,
+                                flags: CharacteristicFlags::NOTIFY,
+                                mtu: None,
+                            };
+                            CharacteristicInternal::new(info, vec![descriptor])
+                        })
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let descriptor = |handle| Descriptor {
+            uuid: descriptor_uuid,
+            service_uuid,
+            characteristic_uuid,
+            handle,
+            permissions: None,
+        };
+
+        for handle in [0x0013, 0x0023, 0x0026] {
+            let info = get_descriptor(&services, &descriptor(Some(handle))).unwrap();
+            assert_eq!(descriptor_handle(info), Some(handle));
+        }
+        let first = get_descriptor(&services, &descriptor(None)).unwrap();
+        assert_eq!(descriptor_handle(first), Some(0x0013));
+        assert!(get_descriptor(&services, &descriptor(Some(0x0030))).is_err());
+    }
+
+    const DEVICE_PATH: &str = "/org/bluez/hci0/dev_11_22_33_44_55_66";
+
+    fn device_info() -> DeviceInfo {
+        DeviceInfo {
+            id: serde_json::from_value(serde_json::json!({ "object_path": DEVICE_PATH })).unwrap(),
+            mac_address: "11:22:33:44:55:66".parse().unwrap(),
+            address_type: bluez_async::AddressType::Public,
+            name: None,
+            appearance: None,
+            services: vec![],
+            paired: false,
+            connected: true,
+            rssi: None,
+            tx_power: None,
+            manufacturer_data: Default::default(),
+            service_data: Default::default(),
+            services_resolved: true,
+            alias: None,
+            class: None,
+            bonded: false,
+            icon: None,
+            trusted: false,
+            blocked: false,
+            legacy_pairing: false,
+            modalias: None,
+            wake_allowed: false,
+        }
+    }
+
+    /// Adds a service with a single readable characteristic to the services of the device,
+    /// returning the characteristic.
+    fn add_service(shared: &Shared, service: u16, characteristic: u16) -> Characteristic {
+        let service_path = format!("{}/service{:04x}", DEVICE_PATH, service);
+        let characteristic_path = format!("{}/char{:04x}", service_path, characteristic);
+        let info = CharacteristicInfo {
+            id: serde_json::from_value(serde_json::json!({ "object_path": characteristic_path }))
+                .unwrap(),
+            uuid: Uuid::from_u128(0x2a19),
+            flags: CharacteristicFlags::READ,
+            mtu: None,
+        };
+        let service = ServiceInternal {
+            info: ServiceInfo {
+                id: serde_json::from_value(serde_json::json!({ "object_path": service_path }))
+                    .unwrap(),
+                uuid: Uuid::from_u128(0x180f),
+                primary: true,
+            },
+            characteristics: vec![CharacteristicInternal::new(info, vec![])],
+        };
+        let characteristic = make_characteristic(&service.characteristics[0], service.info.uuid);
+        shared
+            .device_services(&device_info().id)
+            .lock()
+            .unwrap()
+            .push(service);
+        characteristic
+    }
+
+    #[tokio::test]
+    async fn cached_values_are_shared_between_handles() {
+        let shared = Shared::for_tests().await;
+        let characteristic = add_service(&shared, 0x0010, 0x0011);
+        let path = format!("{}/service0010/char0011", DEVICE_PATH);
+        fake_bluez::set_characteristic_value(&path, &[42]);
+
+        let first = Peripheral::new(shared.clone(), device_info());
+        let second = Peripheral::new(shared, device_info());
+        assert_eq!(first.read(&characteristic).await.unwrap(), [42]);
+        assert_eq!(
+            second
+                .read_cached(&characteristic, Duration::from_secs(60))
+                .await
+                .unwrap(),
+            [42]
+        );
+        assert_eq!(fake_bluez::characteristic_reads(&path), 1);
+    }
+}
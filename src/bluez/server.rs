@@ -0,0 +1,621 @@
+//! A GATT server published through BlueZ's `org.bluez.GattManager1`.
+//!
+//! Each service is exported as an application of its own, a tree of D-Bus objects on the server's
+//! connection, so that services can be added and removed one at a time. BlueZ drops the
+//! applications of a connection once it closes, which happens when the server is dropped.
+
+use crate::api::server::{AttError, LocalService, ReadRequest, RequestHandler, WriteRequest};
+use crate::api::{self, BDAddr, CharPropFlags, WriteType};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use dbus::arg::{prop_cast, PropMap, RefArg, Variant};
+use dbus::channel::{MatchingReceiver, Sender, Token};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{Message, Path};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt::{self, Debug, Formatter};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+const GATT_MANAGER_INTERFACE: &str = "org.bluez.GattManager1";
+const SERVICE_INTERFACE: &str = "org.bluez.GattService1";
+const CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+const DESCRIPTOR_INTERFACE: &str = "org.bluez.GattDescriptor1";
+pub(super) const OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+pub(super) const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// The applications are exported below this path.
+const ROOT_PATH: &str = "/org/btleplug/gatt";
+
+pub(super) const DBUS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A D-Bus error name and message to reply to a method call with.
+pub(super) type MethodError = (&'static str, String);
+
+/// Implementation of [api::GattServer](crate::api::GattServer).
+pub struct GattServer {
+    connection: Arc<SyncConnection>,
+    adapter: Path<'static>,
+    applications: Mutex<Vec<Registered>>,
+    next_id: AtomicUsize,
+    connection_task: AbortHandle,
+}
+
+struct Registered {
+    application: Arc<Application>,
+    token: Token,
+}
+
+/// The D-Bus objects of one published service. The service is at `{path}/service`, its
+/// characteristics at `{path}/service/char{i}` and their descriptors at
+/// `{path}/service/char{i}/desc{j}`, indexed like in the `LocalService`.
+struct Application {
+    path: Path<'static>,
+    service: LocalService,
+    handler: Arc<dyn RequestHandler>,
+}
+
+impl GattServer {
+    /// Opens a connection of its own to the system bus, whose closing unregisters the services.
+    pub(crate) async fn new(adapter: Path<'static>) -> Result<Self> {
+        let (resource, connection) =
+            dbus_tokio::connection::new_system_sync().map_err(|e| Error::Other(Box::new(e)))?;
+        let connection_task = tokio::spawn(async {
+            let err = resource.await;
+            log::error!("GATT server lost its connection to D-Bus: {}", err);
+        });
+        Ok(Self {
+            connection,
+            adapter,
+            applications: Mutex::new(Vec::new()),
+            next_id: AtomicUsize::new(0),
+            connection_task: connection_task.abort_handle(),
+        })
+    }
+
+    fn gatt_manager(&self) -> Proxy<'_, Arc<SyncConnection>> {
+        Proxy::new(
+            "org.bluez",
+            &self.adapter,
+            DBUS_TIMEOUT,
+            self.connection.clone(),
+        )
+    }
+}
+
+impl Drop for GattServer {
+    fn drop(&mut self) {
+        self.connection_task.abort();
+    }
+}
+
+impl Debug for GattServer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let services: Vec<Uuid> = self
+            .applications
+            .lock()
+            .map(|applications| {
+                applications
+                    .iter()
+                    .map(|registered| registered.application.service.uuid)
+                    .collect()
+            })
+            .unwrap_or_default();
+        f.debug_struct("GattServer")
+            .field("adapter", &self.adapter)
+            .field("services", &services)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl api::GattServer for GattServer {
+    async fn add_service(
+        &self,
+        service: LocalService,
+        handler: Arc<dyn RequestHandler>,
+    ) -> Result<()> {
+        service.validate()?;
+        if self
+            .applications
+            .lock()?
+            .iter()
+            .any(|registered| registered.application.service.uuid == service.uuid)
+        {
+            return Err(Error::Other(
+                format!("Service {} is already published", service.uuid).into(),
+            ));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let path = Path::from(format!("{}/app{}", ROOT_PATH, id));
+        let application = Arc::new(Application {
+            path: path.clone(),
+            service,
+            handler,
+        });
+        let receiver = application.clone();
+        let token = self.connection.start_receive(
+            MatchRule::new_method_call().with_namespaced_path(path.clone()),
+            Box::new(move |message, connection| {
+                let _ = connection.send(receiver.handle(&message));
+                true
+            }),
+        );
+        // BlueZ reads the objects of the application before this returns.
+        let registered: std::result::Result<(), _> = self
+            .gatt_manager()
+            .method_call(
+                GATT_MANAGER_INTERFACE,
+                "RegisterApplication",
+                (path, PropMap::new()),
+            )
+            .await;
+        if let Err(e) = registered {
+            self.connection.stop_receive(token);
+            return Err(Error::Other(Box::new(e)));
+        }
+        self.applications
+            .lock()?
+            .push(Registered { application, token });
+        Ok(())
+    }
+
+    async fn remove_service(&self, uuid: Uuid) -> Result<()> {
+        let registered = {
+            let mut applications = self.applications.lock()?;
+            let index = applications
+                .iter()
+                .position(|registered| registered.application.service.uuid == uuid)
+                .ok_or(Error::NoSuchService)?;
+            applications.remove(index)
+        };
+        let unregistered: std::result::Result<(), _> = self
+            .gatt_manager()
+            .method_call(
+                GATT_MANAGER_INTERFACE,
+                "UnregisterApplication",
+                (registered.application.path.clone(),),
+            )
+            .await;
+        self.connection.stop_receive(registered.token);
+        unregistered.map_err(|e| Error::Other(Box::new(e)))
+    }
+
+    async fn notify(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        value: &[u8],
+    ) -> Result<()> {
+        let path = {
+            let applications = self.applications.lock()?;
+            let application = &applications
+                .iter()
+                .find(|registered| registered.application.service.uuid == service_uuid)
+                .ok_or(Error::NoSuchService)?
+                .application;
+            let index = application
+                .service
+                .characteristics
+                .iter()
+                .position(|characteristic| characteristic.uuid == characteristic_uuid)
+                .ok_or(Error::NoSuchCharacteristic)?;
+            application.characteristic_path(index)
+        };
+        // BlueZ sends a changed value to the centrals which subscribed to it.
+        let signal = PropertiesPropertiesChanged {
+            interface_name: CHARACTERISTIC_INTERFACE.to_string(),
+            changed_properties: PropMap::from([("Value".to_string(), variant(value.to_vec()))]),
+            invalidated_properties: Vec::new(),
+        };
+        self.connection
+            .send(signal.to_emit_message(&path))
+            .map_err(|()| Error::Other("Failed to send the new value to D-Bus".into()))?;
+        Ok(())
+    }
+}
+
+impl Debug for Registered {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.application.fmt(f)
+    }
+}
+
+impl Debug for Application {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Application")
+            .field("path", &self.path)
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+impl Application {
+    fn service_path(&self) -> Path<'static> {
+        Path::from(format!("{}/service", self.path))
+    }
+
+    fn characteristic_path(&self, characteristic: usize) -> Path<'static> {
+        Path::from(format!("{}/service/char{}", self.path, characteristic))
+    }
+
+    fn descriptor_path(&self, characteristic: usize, descriptor: usize) -> Path<'static> {
+        Path::from(format!(
+            "{}/service/char{}/desc{}",
+            self.path, characteristic, descriptor
+        ))
+    }
+
+    /// The objects of the application, with their interfaces and properties, as returned by
+    /// `GetManagedObjects`.
+    fn objects(&self) -> HashMap<Path<'static>, HashMap<String, PropMap>> {
+        let mut objects = HashMap::new();
+        let service_path = self.service_path();
+        objects.insert(
+            service_path.clone(),
+            HashMap::from([(
+                SERVICE_INTERFACE.to_string(),
+                PropMap::from([
+                    ("UUID".to_string(), variant(self.service.uuid.to_string())),
+                    ("Primary".to_string(), variant(self.service.primary)),
+                ]),
+            )]),
+        );
+        for (i, characteristic) in self.service.characteristics.iter().enumerate() {
+            let characteristic_path = self.characteristic_path(i);
+            objects.insert(
+                characteristic_path.clone(),
+                HashMap::from([(
+                    CHARACTERISTIC_INTERFACE.to_string(),
+                    PropMap::from([
+                        ("UUID".to_string(), variant(characteristic.uuid.to_string())),
+                        ("Service".to_string(), variant(service_path.clone())),
+                        (
+                            "Flags".to_string(),
+                            variant(characteristic_flags(characteristic.properties)),
+                        ),
+                    ]),
+                )]),
+            );
+            for (j, descriptor) in characteristic.descriptors.iter().enumerate() {
+                objects.insert(
+                    self.descriptor_path(i, j),
+                    HashMap::from([(
+                        DESCRIPTOR_INTERFACE.to_string(),
+                        PropMap::from([
+                            ("UUID".to_string(), variant(descriptor.uuid.to_string())),
+                            (
+                                "Characteristic".to_string(),
+                                variant(characteristic_path.clone()),
+                            ),
+                            ("Flags".to_string(), variant(vec!["read".to_string()])),
+                        ]),
+                    )]),
+                );
+            }
+        }
+        objects
+    }
+
+    /// Finds the indices of the characteristic, and descriptor if any, at an object path.
+    fn attribute(&self, path: &str) -> Option<(usize, Option<usize>)> {
+        let rest = path.strip_prefix(&format!("{}/service/char", self.path))?;
+        let (characteristic, descriptor) = match rest.split_once("/desc") {
+            Some((characteristic, descriptor)) => (characteristic, Some(descriptor.parse().ok()?)),
+            None => (rest, None),
+        };
+        let characteristic: usize = characteristic.parse().ok()?;
+        let descriptors = &self
+            .service
+            .characteristics
+            .get(characteristic)?
+            .descriptors;
+        match descriptor {
+            Some(descriptor) if descriptor >= descriptors.len() => None,
+            _ => Some((characteristic, descriptor)),
+        }
+    }
+
+    /// Answers a method call to one of the objects of the application.
+    fn handle(&self, message: &Message) -> Message {
+        let path = message
+            .path()
+            .map(|path| path.to_string())
+            .unwrap_or_default();
+        let interface = message
+            .interface()
+            .map(|interface| interface.to_string())
+            .unwrap_or_default();
+        let member = message
+            .member()
+            .map(|member| member.to_string())
+            .unwrap_or_default();
+        let reply = match (interface.as_str(), member.as_str()) {
+            (OBJECT_MANAGER_INTERFACE, "GetManagedObjects") if path == *self.path => {
+                Ok(message.method_return().append1(self.objects()))
+            }
+            (PROPERTIES_INTERFACE, "GetAll") => self.get_all_properties(&path, message),
+            (PROPERTIES_INTERFACE, "Get") => self.get_property(&path, message),
+            (CHARACTERISTIC_INTERFACE, "ReadValue") => self.read_characteristic(&path, message),
+            (CHARACTERISTIC_INTERFACE, "WriteValue") => self.write_characteristic(&path, message),
+            (CHARACTERISTIC_INTERFACE, "StartNotify") => self.set_notifying(&path, message, true),
+            (CHARACTERISTIC_INTERFACE, "StopNotify") => self.set_notifying(&path, message, false),
+            (DESCRIPTOR_INTERFACE, "ReadValue") => self.read_descriptor(&path, message),
+            _ => Err(unknown_method()),
+        };
+        reply.unwrap_or_else(|(name, text)| {
+            message.error(&name.into(), &CString::new(text).unwrap_or_default())
+        })
+    }
+
+    fn properties(&self, path: &str, interface: &str) -> std::result::Result<PropMap, MethodError> {
+        self.objects()
+            .remove(&Path::from(path))
+            .and_then(|mut interfaces| interfaces.remove(interface))
+            .ok_or_else(unknown_method)
+    }
+
+    fn get_all_properties(
+        &self,
+        path: &str,
+        message: &Message,
+    ) -> std::result::Result<Message, MethodError> {
+        let interface: String = message.read1().map_err(invalid_args)?;
+        let properties = self.properties(path, &interface)?;
+        Ok(message.method_return().append1(properties))
+    }
+
+    fn get_property(
+        &self,
+        path: &str,
+        message: &Message,
+    ) -> std::result::Result<Message, MethodError> {
+        let (interface, name): (String, String) = message.read2().map_err(invalid_args)?;
+        let value = self
+            .properties(path, &interface)?
+            .remove(&name)
+            .ok_or_else(|| {
+                (
+                    "org.freedesktop.DBus.Error.UnknownProperty",
+                    format!("No property {}", name),
+                )
+            })?;
+        Ok(message.method_return().append1(value))
+    }
+
+    fn read_characteristic(
+        &self,
+        path: &str,
+        message: &Message,
+    ) -> std::result::Result<Message, MethodError> {
+        let Some((characteristic, None)) = self.attribute(path) else {
+            return Err(unknown_method());
+        };
+        let options: PropMap = message.read1().map_err(invalid_args)?;
+        let request = ReadRequest {
+            client: client(&options),
+            service_uuid: self.service.uuid,
+            characteristic_uuid: self.service.characteristics[characteristic].uuid,
+            offset: prop_cast::<u16>(&options, "offset").copied().unwrap_or(0),
+        };
+        let value = self.handler.read(&request).map_err(att_error)?;
+        Ok(message.method_return().append1(value))
+    }
+
+    fn write_characteristic(
+        &self,
+        path: &str,
+        message: &Message,
+    ) -> std::result::Result<Message, MethodError> {
+        let Some((characteristic, None)) = self.attribute(path) else {
+            return Err(unknown_method());
+        };
+        let (value, options): (Vec<u8>, PropMap) = message.read2().map_err(invalid_args)?;
+        let write_type = match prop_cast::<String>(&options, "type").map(String::as_str) {
+            Some("command") => WriteType::WithoutResponse,
+            _ => WriteType::WithResponse,
+        };
+        let request = WriteRequest {
+            client: client(&options),
+            service_uuid: self.service.uuid,
+            characteristic_uuid: self.service.characteristics[characteristic].uuid,
+            offset: prop_cast::<u16>(&options, "offset").copied().unwrap_or(0),
+            value,
+            write_type,
+        };
+        self.handler.write(&request).map_err(att_error)?;
+        Ok(message.method_return())
+    }
+
+    /// BlueZ calls `StartNotify` when the first central subscribes, and `StopNotify` when the last
+    /// one unsubscribes.
+    fn set_notifying(
+        &self,
+        path: &str,
+        message: &Message,
+        notifying: bool,
+    ) -> std::result::Result<Message, MethodError> {
+        let Some((characteristic, None)) = self.attribute(path) else {
+            return Err(unknown_method());
+        };
+        self.handler.subscription_changed(
+            self.service.uuid,
+            self.service.characteristics[characteristic].uuid,
+            notifying,
+        );
+        Ok(message.method_return())
+    }
+
+    fn read_descriptor(
+        &self,
+        path: &str,
+        message: &Message,
+    ) -> std::result::Result<Message, MethodError> {
+        let Some((characteristic, Some(descriptor))) = self.attribute(path) else {
+            return Err(unknown_method());
+        };
+        let options: PropMap = message.read1().map_err(invalid_args)?;
+        let offset = prop_cast::<u16>(&options, "offset").copied().unwrap_or(0);
+        let value = &self.service.characteristics[characteristic].descriptors[descriptor].value;
+        let value = value
+            .get(usize::from(offset)..)
+            .ok_or_else(|| att_error(AttError::InvalidOffset))?;
+        Ok(message.method_return().append1(value))
+    }
+}
+
+pub(super) fn variant<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
+}
+
+/// The `Flags` of a characteristic, which BlueZ uses for both its properties and permissions.
+fn characteristic_flags(properties: CharPropFlags) -> Vec<String> {
+    [
+        (CharPropFlags::BROADCAST, "broadcast"),
+        (CharPropFlags::READ, "read"),
+        (
+            CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            "write-without-response",
+        ),
+        (CharPropFlags::WRITE, "write"),
+        (CharPropFlags::NOTIFY, "notify"),
+        (CharPropFlags::INDICATE, "indicate"),
+        (
+            CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+            "authenticated-signed-writes",
+        ),
+        (CharPropFlags::EXTENDED_PROPERTIES, "extended-properties"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| properties.contains(*flag))
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+/// The address of the central from the `device` option of a request, an object path like
+/// `/org/bluez/hci0/dev_00_11_22_33_44_55`.
+fn client(options: &PropMap) -> Option<BDAddr> {
+    let device = prop_cast::<Path>(options, "device")?;
+    let address = device.rsplit('/').next()?.strip_prefix("dev_")?;
+    BDAddr::from_str(&address.replace('_', ":")).ok()
+}
+
+/// The BlueZ error for an ATT error. BlueZ passes the code of `org.bluez.Error.Failed` errors whose
+/// message is an application error code on to the central.
+fn att_error(error: AttError) -> MethodError {
+    match error {
+        AttError::ReadNotPermitted | AttError::WriteNotPermitted => {
+            ("org.bluez.Error.NotPermitted", error.to_string())
+        }
+        AttError::InsufficientAuthentication => {
+            ("org.bluez.Error.NotAuthorized", error.to_string())
+        }
+        AttError::RequestNotSupported => ("org.bluez.Error.NotSupported", error.to_string()),
+        AttError::InvalidOffset => ("org.bluez.Error.InvalidOffset", error.to_string()),
+        AttError::InvalidAttributeValueLength => {
+            ("org.bluez.Error.InvalidValueLength", error.to_string())
+        }
+        AttError::UnlikelyError => ("org.bluez.Error.Failed", error.to_string()),
+        AttError::Application(code) => ("org.bluez.Error.Failed", format!("{:#04x}", code)),
+    }
+}
+
+pub(super) fn unknown_method() -> MethodError {
+    (
+        "org.freedesktop.DBus.Error.UnknownMethod",
+        "Unknown object, interface or method".to_string(),
+    )
+}
+
+pub(super) fn invalid_args(e: dbus::arg::TypeMismatchError) -> MethodError {
+    ("org.freedesktop.DBus.Error.InvalidArgs", e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::server::{LocalCharacteristic, LocalDescriptor};
+
+    struct Rejecting;
+
+    impl RequestHandler for Rejecting {}
+
+    #[test]
+    fn attribute_paths() {
+        let characteristic = LocalCharacteristic {
+            uuid: Uuid::nil(),
+            properties: CharPropFlags::READ | CharPropFlags::NOTIFY,
+            descriptors: vec![LocalDescriptor {
+                uuid: Uuid::nil(),
+                value: Vec::new(),
+            }],
+        };
+        let application = Application {
+            path: Path::from("/org/btleplug/gatt/app3"),
+            service: LocalService {
+                uuid: Uuid::nil(),
+                primary: true,
+                characteristics: vec![characteristic.clone(), characteristic],
+            },
+            handler: Arc::new(Rejecting),
+        };
+        let path = application.descriptor_path(1, 0);
+        assert_eq!(&*path, "/org/btleplug/gatt/app3/service/char1/desc0");
+        assert_eq!(application.attribute(&path), Some((1, Some(0))));
+        assert_eq!(
+            application.attribute(&application.characteristic_path(0)),
+            Some((0, None))
+        );
+        assert_eq!(
+            application.attribute(&application.characteristic_path(2)),
+            None
+        );
+        assert_eq!(
+            application.attribute(&application.descriptor_path(0, 1)),
+            None
+        );
+        assert_eq!(application.attribute(&application.service_path()), None);
+        assert_eq!(application.objects().len(), 5);
+
+        let mut objects = application.objects();
+        let descriptor = &objects.remove(&path).unwrap()[DESCRIPTOR_INTERFACE];
+        assert_eq!(
+            prop_cast::<Path>(descriptor, "Characteristic"),
+            Some(&application.characteristic_path(1))
+        );
+        let characteristic =
+            &objects.remove(&application.characteristic_path(1)).unwrap()[CHARACTERISTIC_INTERFACE];
+        assert_eq!(
+            prop_cast::<Path>(characteristic, "Service"),
+            Some(&application.service_path())
+        );
+        assert_eq!(
+            prop_cast::<Vec<String>>(characteristic, "Flags").unwrap(),
+            &["read", "notify"]
+        );
+    }
+
+    #[test]
+    fn client_address() {
+        let options = PropMap::from([(
+            "device".to_string(),
+            variant(Path::from("/org/bluez/hci0/dev_00_11_22_33_44_55")),
+        )]);
+        assert_eq!(
+            client(&options),
+            Some(BDAddr::from([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]))
+        );
+        assert_eq!(client(&PropMap::new()), None);
+        assert_eq!(
+            characteristic_flags(CharPropFlags::READ | CharPropFlags::WRITE_WITHOUT_RESPONSE),
+            ["read", "write-without-response"]
+        );
+    }
+}
@@ -0,0 +1,938 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `capture` module records the Bluetooth traffic that goes through btleplug into a
+//! [btsnoop](https://fte.com/webhelpII/HSU/Content/Technical_Information/BT_Snoop_File_Format.htm)
+//! file, which can be opened with Wireshark and attached to bug reports.
+//!
+//! btleplug never sees raw HCI traffic, so the capture is synthesized from what the crate does
+//! see:
+//!
+//! - Advertisements are written as LE Advertising Report events built from the
+//!   [`PeripheralProperties`] of the device.
+//! - Connections and disconnections are written as LE Connection Complete and Disconnection
+//!   Complete events. Each peripheral gets its own connection handle.
+//! - GATT operations are written as ATT PDUs, with the handles the platform reports for
+//!   characteristics and descriptors. Those it doesn't report a handle for are given a made-up one
+//!   the first time they are used, counting down from `0xFFFF`.
+//!
+//! GATT traffic is recorded by wrapping a peripheral in a [`CapturingPeripheral`]:
+//!
+//! ```no_run
+//! # use btleplug::api::{Central, CentralEvent, Peripheral as _};
+//! # use btleplug::platform::Adapter;
+//! # use futures::stream::StreamExt;
+//! # use std::sync::Arc;
+//! use btleplug::capture::{Capture, CapturingPeripheral};
+//!
+//! # async fn example(central: Adapter) -> btleplug::Result<()> {
+//! let capture = Arc::new(Capture::create("btleplug.btsnoop")?);
+//! let mut events = central.events().await?;
+//! while let Some(event) = events.next().await {
+//!     capture.record_central_event(&central, &event).await;
+//!     if let CentralEvent::DeviceDiscovered(id) = event {
+//!         let peripheral = CapturingPeripheral::new(central.peripheral(&id).await?, capture.clone());
+//!         peripheral.connect().await?;
+//!         // Everything done through `peripheral` now ends up in the capture.
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::api::{
+    AddressType, BDAddr, Central, CentralEvent, Characteristic, ConnectOptions, ConnectionInfo,
+    ConnectionParams, ConnectionPhy, Descriptor, PairingResponse, Peripheral, PeripheralProperties,
+    Phy, PhyCoding, ReliableWrite, Service, ValueNotification, WriteType,
+};
+use crate::interceptor::GattInterceptor;
+use crate::platform::PeripheralId;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use futures::stream::{Stream, StreamExt};
+use log::warn;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{self, Debug, Formatter},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// btsnoop timestamps count microseconds since midnight, January 1st 0 AD.
+const BTSNOOP_EPOCH_DELTA: u64 = 0x00dc_ddb3_0f2f_8000;
+/// Datalink type for HCI UART (H4) framing.
+const BTSNOOP_DATALINK_H4: u32 = 1002;
+
+const H4_ACL: u8 = 0x02;
+const H4_EVENT: u8 = 0x04;
+
+const FLAG_RECEIVED: u32 = 0x01;
+const FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+
+const ATT_CID: u16 = 0x0004;
+const ATT_ERROR_RSP: u8 = 0x01;
+const ATT_READ_REQ: u8 = 0x0A;
+const ATT_READ_RSP: u8 = 0x0B;
+const ATT_WRITE_REQ: u8 = 0x12;
+const ATT_WRITE_RSP: u8 = 0x13;
+const ATT_WRITE_CMD: u8 = 0x52;
+const ATT_SIGNED_WRITE_CMD: u8 = 0xD2;
+const ATT_HANDLE_VALUE_NTF: u8 = 0x1B;
+const ATT_HANDLE_VALUE_IND: u8 = 0x1D;
+/// "Unlikely Error", used for operations which failed for reasons btleplug can't map to ATT.
+const ATT_ERROR_UNLIKELY: u8 = 0x0E;
+
+/// The key of a characteristic or descriptor in the made-up attribute handle table.
+type AttributeKey = (Uuid, Uuid, Option<Uuid>);
+
+/// A characteristic or descriptor, with its handle if the platform reports it.
+struct Attribute {
+    key: AttributeKey,
+    handle: Option<u16>,
+}
+
+struct CaptureState {
+    writer: Box<dyn Write + Send>,
+    connection_handles: HashMap<PeripheralId, u16>,
+    attribute_handles: HashMap<AttributeKey, u16>,
+}
+
+impl CaptureState {
+    fn connection_handle(&mut self, id: &PeripheralId) -> u16 {
+        let next = self.connection_handles.len() as u16 + 1;
+        *self.connection_handles.entry(id.clone()).or_insert(next)
+    }
+
+    /// The handle of `attribute`, or a made-up one if it has none. Real handles count up from 1,
+    /// so the made-up ones count down from the top to keep clear of them.
+    fn attribute_handle(&mut self, attribute: Attribute) -> u16 {
+        if let Some(handle) = attribute.handle {
+            return handle;
+        }
+        let next = u16::MAX - self.attribute_handles.len() as u16;
+        *self.attribute_handles.entry(attribute.key).or_insert(next)
+    }
+
+    fn write_packet(&mut self, flags: u32, packet: &[u8]) -> std::io::Result<()> {
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        self.writer
+            .write_all(&btsnoop_record(flags, micros + BTSNOOP_EPOCH_DELTA, packet))?;
+        self.writer.flush()
+    }
+}
+
+/// A btsnoop capture file that btleplug traffic can be recorded into. See the
+/// [module documentation](self) for details.
+pub struct Capture {
+    state: Mutex<CaptureState>,
+}
+
+impl Debug for Capture {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Capture").finish_non_exhaustive()
+    }
+}
+
+impl Capture {
+    /// Creates a new capture file at `path`, replacing any existing file.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::create(path).map_err(|e| Error::Other(Box::new(e)))?;
+        Self::new(BufWriter::new(file))
+    }
+
+    /// Starts a capture which is written to `writer`.
+    pub fn new(writer: impl Write + Send + 'static) -> Result<Self> {
+        let mut writer: Box<dyn Write + Send> = Box::new(writer);
+        writer
+            .write_all(&btsnoop_header())
+            .and_then(|_| writer.flush())
+            .map_err(|e| Error::Other(Box::new(e)))?;
+        Ok(Capture {
+            state: Mutex::new(CaptureState {
+                writer,
+                connection_handles: HashMap::new(),
+                attribute_handles: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Records an advertisement with the given properties.
+    pub fn record_advertisement(&self, properties: &PeripheralProperties) {
+        self.record(FLAG_RECEIVED | FLAG_COMMAND_OR_EVENT, |_| {
+            advertising_report(properties)
+        });
+    }
+
+    /// Records `event` if it carries advertisement data, looking up the properties of the
+    /// peripheral it refers to on `central`.
+    pub async fn record_central_event<C: Central>(&self, central: &C, event: &CentralEvent) {
+        let id = match event {
+            CentralEvent::DeviceDiscovered(id)
+            | CentralEvent::DeviceUpdated(id)
+            | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+            | CentralEvent::ServiceDataAdvertisement { id, .. }
+            | CentralEvent::ServicesAdvertisement { id, .. } => id,
+            CentralEvent::DeviceConnected(id) => {
+                if let Ok(peripheral) = central.peripheral(id).await {
+                    let address_type = peripheral
+                        .cached_properties()
+                        .and_then(|properties| properties.address_type);
+                    self.record_connected(id, peripheral.address(), address_type);
+                }
+                return;
+            }
+            CentralEvent::DeviceDisconnected(id) => {
+                self.record_disconnected(id);
+                return;
+            }
+            _ => return,
+        };
+        if let Ok(peripheral) = central.peripheral(id).await {
+            if let Ok(Some(properties)) = peripheral.properties().await {
+                self.record_advertisement(&properties);
+            }
+        }
+    }
+
+    /// Records that a connection to the peripheral was established.
+    pub fn record_connected(
+        &self,
+        id: &PeripheralId,
+        address: BDAddr,
+        address_type: Option<AddressType>,
+    ) {
+        self.record(FLAG_RECEIVED | FLAG_COMMAND_OR_EVENT, |state| {
+            connection_complete(state.connection_handle(id), address, address_type)
+        });
+    }
+
+    /// Records that the connection to the peripheral was terminated.
+    pub fn record_disconnected(&self, id: &PeripheralId) {
+        self.record(FLAG_RECEIVED | FLAG_COMMAND_OR_EVENT, |state| {
+            disconnection_complete(state.connection_handle(id))
+        });
+    }
+
+    /// Records a characteristic read and its outcome.
+    pub fn record_read(
+        &self,
+        id: &PeripheralId,
+        characteristic: &Characteristic,
+        result: &Result<Vec<u8>>,
+    ) {
+        self.record_att_read(id, characteristic_attribute(characteristic), result);
+    }
+
+    /// Records a characteristic write and its outcome.
+    pub fn record_write(
+        &self,
+        id: &PeripheralId,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+        result: &Result<()>,
+    ) {
+        let attribute = characteristic_attribute(characteristic);
+        self.record_att_write(id, attribute, data, write_type, result);
+    }
+
+    /// Records a descriptor read and its outcome.
+    pub fn record_read_descriptor(
+        &self,
+        id: &PeripheralId,
+        descriptor: &Descriptor,
+        result: &Result<Vec<u8>>,
+    ) {
+        self.record_att_read(id, descriptor_attribute(descriptor), result);
+    }
+
+    /// Records a descriptor write and its outcome.
+    pub fn record_write_descriptor(
+        &self,
+        id: &PeripheralId,
+        descriptor: &Descriptor,
+        data: &[u8],
+        result: &Result<()>,
+    ) {
+        let attribute = descriptor_attribute(descriptor);
+        self.record_att_write(id, attribute, data, WriteType::WithResponse, result);
+    }
+
+    /// Records a notification or indication received from the peripheral.
+    pub fn record_notification(&self, id: &PeripheralId, notification: &ValueNotification) {
+        self.record(FLAG_RECEIVED, |state| {
+            let handle = state.attribute_handle(Attribute {
+                key: (notification.service_uuid, notification.uuid, None),
+                handle: notification.handle,
+            });
+            let opcode = if notification.is_indication {
+                ATT_HANDLE_VALUE_IND
+            } else {
+                ATT_HANDLE_VALUE_NTF
+            };
+            att_packet(
+                state.connection_handle(id),
+                &att_pdu(opcode, handle, &notification.value),
+            )
+        });
+    }
+
+    fn record_att_read(&self, id: &PeripheralId, attribute: Attribute, result: &Result<Vec<u8>>) {
+        let mut response = None;
+        self.record(0, |state| {
+            let connection = state.connection_handle(id);
+            let handle = state.attribute_handle(attribute);
+            response = Some(match result {
+                Ok(value) => att_packet(connection, &[&[ATT_READ_RSP], value.as_slice()].concat()),
+                Err(_) => att_packet(connection, &att_error(ATT_READ_REQ, handle)),
+            });
+            att_packet(connection, &att_pdu(ATT_READ_REQ, handle, &[]))
+        });
+        if let Some(response) = response {
+            self.record(FLAG_RECEIVED, |_| response);
+        }
+    }
+
+    fn record_att_write(
+        &self,
+        id: &PeripheralId,
+        attribute: Attribute,
+        data: &[u8],
+        write_type: WriteType,
+        result: &Result<()>,
+    ) {
+        let opcode = match write_type {
+            WriteType::WithResponse => ATT_WRITE_REQ,
+            WriteType::WithoutResponse => ATT_WRITE_CMD,
+            WriteType::Signed => ATT_SIGNED_WRITE_CMD,
+        };
+        let mut response = None;
+        self.record(0, |state| {
+            let connection = state.connection_handle(id);
+            let handle = state.attribute_handle(attribute);
+            response = match (write_type, result) {
+                (WriteType::WithResponse, Ok(())) => Some(att_packet(connection, &[ATT_WRITE_RSP])),
+                (WriteType::WithResponse, Err(_)) => {
+                    Some(att_packet(connection, &att_error(opcode, handle)))
+                }
+                // Commands get no response, even when they fail to be sent.
+                _ => None,
+            };
+            let pdu = match write_type {
+                // The platform adds the signature, which isn't known here. Pad with a blank one so
+                // that dissectors don't take the end of the value for it.
+                WriteType::Signed => att_pdu(opcode, handle, &[data, &[0; 12]].concat()),
+                _ => att_pdu(opcode, handle, data),
+            };
+            att_packet(connection, &pdu)
+        });
+        if let Some(response) = response {
+            self.record(FLAG_RECEIVED, |_| response);
+        }
+    }
+
+    fn record(&self, flags: u32, packet: impl FnOnce(&mut CaptureState) -> Vec<u8>) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let packet = packet(&mut state);
+        if let Err(e) = state.write_packet(flags, &packet) {
+            warn!("Failed to write capture record: {}", e);
+        }
+    }
+}
+
+/// A [`Peripheral`] wrapper which records all GATT operations into a [`Capture`].
+#[derive(Clone, Debug)]
+pub struct CapturingPeripheral<P> {
+    peripheral: P,
+    capture: Arc<Capture>,
+}
+
+impl<P: Peripheral> CapturingPeripheral<P> {
+    pub fn new(peripheral: P, capture: Arc<Capture>) -> Self {
+        CapturingPeripheral {
+            peripheral,
+            capture,
+        }
+    }
+
+    /// Returns the wrapped peripheral.
+    pub fn into_inner(self) -> P {
+        self.peripheral
+    }
+
+    fn record_connected(&self) {
+        let address_type = self
+            .peripheral
+            .cached_properties()
+            .and_then(|properties| properties.address_type);
+        self.capture.record_connected(
+            &self.peripheral.id(),
+            self.peripheral.address(),
+            address_type,
+        );
+    }
+}
+
+#[async_trait]
+impl<P: Peripheral> Peripheral for CapturingPeripheral<P> {
+    fn id(&self) -> PeripheralId {
+        self.peripheral.id()
+    }
+
+    fn address(&self) -> BDAddr {
+        self.peripheral.address()
+    }
+
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+        self.peripheral.properties().await
+    }
+
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        self.peripheral.cached_properties()
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.peripheral.services()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        self.peripheral.is_connected().await
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        self.peripheral.is_paired().await
+    }
+
+    async fn respond_to_pairing(&self, response: PairingResponse) -> Result<()> {
+        self.peripheral.respond_to_pairing(response).await
+    }
+
+    async fn mtu(&self, characteristics: Option<&[Characteristic]>) -> Result<u16> {
+        self.peripheral.mtu(characteristics).await
+    }
+
+    async fn set_long_value_chunking(&self, enabled: bool) -> Result<()> {
+        self.peripheral.set_long_value_chunking(enabled).await
+    }
+
+    async fn connection_info(&self) -> Result<Option<ConnectionInfo>> {
+        self.peripheral.connection_info().await
+    }
+
+    async fn update_connection_parameters(&self, params: ConnectionParams) -> Result<()> {
+        self.peripheral.update_connection_parameters(params).await
+    }
+
+    async fn set_preferred_phy(&self, tx: &[Phy], rx: &[Phy], coding: PhyCoding) -> Result<()> {
+        self.peripheral.set_preferred_phy(tx, rx, coding).await
+    }
+
+    async fn read_phy(&self) -> Result<ConnectionPhy> {
+        self.peripheral.read_phy().await
+    }
+
+    async fn begin_reliable_write(&self) -> Result<Box<dyn ReliableWrite>> {
+        self.peripheral.begin_reliable_write().await
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.peripheral.connect().await?;
+        self.record_connected();
+        Ok(())
+    }
+
+    async fn connect_with(&self, options: ConnectOptions) -> Result<()> {
+        self.peripheral.connect_with(options).await?;
+        self.record_connected();
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.peripheral.disconnect().await?;
+        self.capture.record_disconnected(&self.peripheral.id());
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.peripheral.discover_services().await
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        let result = self
+            .peripheral
+            .write(characteristic, data, write_type)
+            .await;
+        self.capture.record_write(
+            &self.peripheral.id(),
+            characteristic,
+            data,
+            write_type,
+            &result,
+        );
+        result
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let result = self.peripheral.read(characteristic).await;
+        self.capture
+            .record_read(&self.peripheral.id(), characteristic, &result);
+        result
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        // Values served from the cache never hit the air, so only record live reads.
+        self.peripheral.read_cached(characteristic, max_age).await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        // Forwarded so the backend records the name, at the cost of the read not being captured.
+        self.peripheral.read_device_name().await
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.peripheral.subscribe(characteristic).await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.peripheral.unsubscribe(characteristic).await
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        let id = self.peripheral.id();
+        let capture = self.capture.clone();
+        let notifications = self.peripheral.notifications().await?;
+        Ok(Box::pin(notifications.map(move |notification| {
+            capture.record_notification(&id, &notification);
+            notification
+        })))
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let result = self.peripheral.write_descriptor(descriptor, data).await;
+        self.capture
+            .record_write_descriptor(&self.peripheral.id(), descriptor, data, &result);
+        result
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let result = self.peripheral.read_descriptor(descriptor).await;
+        self.capture
+            .record_read_descriptor(&self.peripheral.id(), descriptor, &result);
+        result
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.peripheral.add_interceptor(interceptor)
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        self.peripheral.remove_interceptor(interceptor)
+    }
+}
+
+fn characteristic_attribute(characteristic: &Characteristic) -> Attribute {
+    Attribute {
+        key: (characteristic.service_uuid, characteristic.uuid, None),
+        handle: characteristic.handle,
+    }
+}
+
+fn descriptor_attribute(descriptor: &Descriptor) -> Attribute {
+    Attribute {
+        key: (
+            descriptor.service_uuid,
+            descriptor.characteristic_uuid,
+            Some(descriptor.uuid),
+        ),
+        handle: descriptor.handle,
+    }
+}
+
+fn btsnoop_header() -> Vec<u8> {
+    let mut header = b"btsnoop\0".to_vec();
+    header.extend_from_slice(&1u32.to_be_bytes());
+    header.extend_from_slice(&BTSNOOP_DATALINK_H4.to_be_bytes());
+    header
+}
+
+fn btsnoop_record(flags: u32, timestamp: u64, packet: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(24 + packet.len());
+    let length = packet.len() as u32;
+    record.extend_from_slice(&length.to_be_bytes());
+    record.extend_from_slice(&length.to_be_bytes());
+    record.extend_from_slice(&flags.to_be_bytes());
+    record.extend_from_slice(&0u32.to_be_bytes());
+    record.extend_from_slice(&timestamp.to_be_bytes());
+    record.extend_from_slice(packet);
+    record
+}
+
+fn hci_event(code: u8, parameters: &[u8]) -> Vec<u8> {
+    let mut packet = vec![H4_EVENT, code, parameters.len().min(255) as u8];
+    packet.extend_from_slice(&parameters[..parameters.len().min(255)]);
+    packet
+}
+
+fn address_bytes(address: BDAddr) -> [u8; 6] {
+    // HCI transmits addresses least significant byte first.
+    let mut bytes = address.into_inner();
+    bytes.reverse();
+    bytes
+}
+
+fn address_type_byte(address_type: Option<AddressType>) -> u8 {
+    match address_type {
+        Some(AddressType::Random) => 0x01,
+        _ => 0x00,
+    }
+}
+
+/// The most advertising data an LE Advertising Report with a single report can carry within the
+/// 255 bytes of event parameters.
+const MAX_ADVERTISING_DATA_LEN: usize = 255 - 12;
+
+/// Appends an AD structure to `data` if it fits in `max_len` bytes altogether. Structures which
+/// don't fit are left out whole, so the data stays well-formed.
+fn push_ad_structure(data: &mut Vec<u8>, max_len: usize, ad_type: u8, value: &[u8]) {
+    let Some(length) = value
+        .len()
+        .checked_add(1)
+        .and_then(|length| u8::try_from(length).ok())
+    else {
+        return;
+    };
+    if data.len() + 1 + usize::from(length) > max_len {
+        return;
+    }
+    data.push(length);
+    data.push(ad_type);
+    data.extend_from_slice(value);
+}
+
+fn uuid_le_bytes(uuid: &Uuid) -> Vec<u8> {
+    let mut bytes = uuid.as_bytes().to_vec();
+    bytes.reverse();
+    bytes
+}
+
+/// Rebuilds the advertising data (AD structures) that would have produced `properties`, leaving
+/// out those which don't fit in `max_len` bytes.
+fn advertising_data(properties: &PeripheralProperties, max_len: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    if let Some(name) = &properties.local_name {
+        push_ad_structure(&mut data, max_len, 0x09, name.as_bytes());
+    }
+    if let Some(tx_power_level) = properties.tx_power_level {
+        push_ad_structure(&mut data, max_len, 0x0A, &[tx_power_level as i8 as u8]);
+    }
+    if !properties.services.is_empty() {
+        let services: Vec<u8> = properties.services.iter().flat_map(uuid_le_bytes).collect();
+        push_ad_structure(&mut data, max_len, 0x07, &services);
+    }
+    for (uuid, value) in &properties.service_data {
+        let mut service_data = uuid_le_bytes(uuid);
+        service_data.extend_from_slice(value);
+        push_ad_structure(&mut data, max_len, 0x21, &service_data);
+    }
+    for (company, value) in &properties.manufacturer_data {
+        let mut manufacturer_data = company.to_le_bytes().to_vec();
+        manufacturer_data.extend_from_slice(value);
+        push_ad_structure(&mut data, max_len, 0xFF, &manufacturer_data);
+    }
+    data
+}
+
+fn advertising_report(properties: &PeripheralProperties) -> Vec<u8> {
+    let data = advertising_data(properties, MAX_ADVERTISING_DATA_LEN);
+    let mut parameters = vec![0x02, 0x01, 0x00, address_type_byte(properties.address_type)];
+    parameters.extend_from_slice(&address_bytes(properties.address));
+    parameters.push(data.len() as u8);
+    parameters.extend_from_slice(&data);
+    parameters.push(properties.rssi.map_or(0x7F, |rssi| rssi as i8 as u8));
+    hci_event(0x3E, &parameters)
+}
+
+fn connection_complete(
+    connection: u16,
+    address: BDAddr,
+    address_type: Option<AddressType>,
+) -> Vec<u8> {
+    let mut parameters = vec![0x01, 0x00];
+    parameters.extend_from_slice(&connection.to_le_bytes());
+    // Central role.
+    parameters.push(0x00);
+    parameters.push(address_type_byte(address_type));
+    parameters.extend_from_slice(&address_bytes(address));
+    // Connection interval, latency, supervision timeout and clock accuracy are not known.
+    parameters.extend_from_slice(&[0x00; 7]);
+    hci_event(0x3E, &parameters)
+}
+
+fn disconnection_complete(connection: u16) -> Vec<u8> {
+    let mut parameters = vec![0x00];
+    parameters.extend_from_slice(&connection.to_le_bytes());
+    // Remote user terminated connection.
+    parameters.push(0x13);
+    hci_event(0x05, &parameters)
+}
+
+fn att_pdu(opcode: u8, handle: u16, value: &[u8]) -> Vec<u8> {
+    let mut pdu = vec![opcode];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.extend_from_slice(value);
+    pdu
+}
+
+fn att_error(request_opcode: u8, handle: u16) -> Vec<u8> {
+    let mut pdu = vec![ATT_ERROR_RSP, request_opcode];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.push(ATT_ERROR_UNLIKELY);
+    pdu
+}
+
+fn att_packet(connection: u16, pdu: &[u8]) -> Vec<u8> {
+    let mut packet = vec![H4_ACL];
+    // Packet boundary flag: first automatically flushable packet.
+    packet.extend_from_slice(&(connection | 0x2000).to_le_bytes());
+    packet.extend_from_slice(&(pdu.len() as u16 + 4).to_le_bytes());
+    packet.extend_from_slice(&(pdu.len() as u16).to_le_bytes());
+    packet.extend_from_slice(&ATT_CID.to_le_bytes());
+    packet.extend_from_slice(pdu);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::fake::peripheral_id;
+
+    #[test]
+    fn header_is_btsnoop_v1_h4() {
+        assert_eq!(
+            btsnoop_header(),
+            [b'b', b't', b's', b'n', b'o', b'o', b'p', 0, 0, 0, 0, 1, 0, 0, 0x03, 0xEA]
+        );
+    }
+
+    #[test]
+    fn record_has_lengths_and_timestamp() {
+        let record = btsnoop_record(FLAG_RECEIVED, 0x0102030405060708, &[0xAA, 0xBB]);
+        assert_eq!(
+            record,
+            [0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 0xAA, 0xBB]
+        );
+    }
+
+    #[test]
+    fn att_packet_is_wrapped_in_l2cap() {
+        let packet = att_packet(0x0001, &att_pdu(ATT_READ_REQ, 0x0003, &[]));
+        assert_eq!(
+            packet,
+            [
+                H4_ACL,
+                0x01,
+                0x20,
+                0x07,
+                0x00,
+                0x03,
+                0x00,
+                0x04,
+                0x00,
+                ATT_READ_REQ,
+                0x03,
+                0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn advertising_report_contains_name_and_manufacturer_data() {
+        let properties = PeripheralProperties {
+            address: BDAddr::from([1, 2, 3, 4, 5, 6]),
+            local_name: Some("ab".to_string()),
+            rssi: Some(-60),
+            manufacturer_data: [(0x004C, vec![0x10])].into_iter().collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            advertising_report(&properties),
+            [
+                H4_EVENT, 0x3E, 0x15, 0x02, 0x01, 0x00, 0x00, 6, 5, 4, 3, 2, 1, 0x09, 0x03, 0x09,
+                b'a', b'b', 0x04, 0xFF, 0x4C, 0x00, 0x10, 0xC4
+            ]
+        );
+    }
+
+    /// Walks the AD structures of `data`, returning their types, or panics if they're malformed.
+    fn ad_types(data: &[u8]) -> Vec<u8> {
+        let mut types = Vec::new();
+        let mut rest = data;
+        while let Some((&length, tail)) = rest.split_first() {
+            let length = usize::from(length);
+            assert!(length > 0 && length <= tail.len(), "malformed AD structure");
+            types.push(tail[0]);
+            rest = &tail[length..];
+        }
+        types
+    }
+
+    #[test]
+    fn oversized_values_are_left_out() {
+        let properties = PeripheralProperties {
+            local_name: Some("n".repeat(300)),
+            manufacturer_data: [(0x004C, vec![0; 255]), (0x004D, vec![1])]
+                .into_iter()
+                .collect(),
+            service_data: [(Uuid::nil(), vec![0; 200])].into_iter().collect(),
+            ..Default::default()
+        };
+        let data = advertising_data(&properties, 1000);
+        assert_eq!(ad_types(&data), [0x21, 0xFF]);
+        assert_eq!(data.len(), 218 + 5);
+    }
+
+    #[test]
+    fn advertising_report_keeps_whole_structures() {
+        let properties = PeripheralProperties {
+            local_name: Some("n".repeat(200)),
+            manufacturer_data: [(0x004C, vec![0; 60]), (0x004D, vec![1])]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let report = advertising_report(&properties);
+        assert_eq!(usize::from(report[2]), report.len() - 3);
+        let data_len = usize::from(report[13]);
+        let data = &report[14..14 + data_len];
+        assert!(data_len <= MAX_ADVERTISING_DATA_LEN);
+        // The 62 bytes of manufacturer data don't fit after the name, the next structure does.
+        assert_eq!(ad_types(data), [0x09, 0xFF]);
+        assert_eq!(data_len, 202 + 5);
+    }
+
+    /// A writer whose output stays readable after the capture took it.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        /// The packets of the records written so far.
+        fn packets(&self) -> Vec<Vec<u8>> {
+            let data = self.0.lock().unwrap();
+            let mut rest = &data[btsnoop_header().len()..];
+            let mut packets = Vec::new();
+            while !rest.is_empty() {
+                let length = u32::from_be_bytes(rest[4..8].try_into().unwrap()) as usize;
+                packets.push(rest[24..24 + length].to_vec());
+                rest = &rest[24 + length..];
+            }
+            packets
+        }
+    }
+
+    fn capture() -> (Capture, Buffer, PeripheralId) {
+        let buffer = Buffer::default();
+        let capture = Capture::new(buffer.clone()).unwrap();
+        (capture, buffer, peripheral_id(BDAddr::default()))
+    }
+
+    fn characteristic(uuid: u128, handle: Option<u16>) -> Characteristic {
+        Characteristic {
+            uuid: Uuid::from_u128(uuid),
+            service_uuid: Uuid::nil(),
+            properties: Default::default(),
+            descriptors: BTreeSet::new(),
+            handle,
+        }
+    }
+
+    #[test]
+    fn real_handles_are_used_when_known() {
+        let (capture, buffer, id) = capture();
+        capture.record_read(&id, &characteristic(1, Some(0x0010)), &Ok(vec![]));
+        capture.record_read(&id, &characteristic(2, None), &Ok(vec![]));
+        capture.record_read(&id, &characteristic(3, None), &Ok(vec![]));
+        capture.record_read(&id, &characteristic(2, None), &Ok(vec![]));
+
+        let handles: Vec<_> = buffer
+            .packets()
+            .iter()
+            .filter(|packet| packet[9] == ATT_READ_REQ)
+            .map(|packet| u16::from_le_bytes([packet[10], packet[11]]))
+            .collect();
+        assert_eq!(handles, [0x0010, 0xFFFF, 0xFFFE, 0xFFFF]);
+    }
+
+    #[test]
+    fn connection_complete_has_peer_address_type() {
+        let address = BDAddr::from([1, 2, 3, 4, 5, 6]);
+        let (capture, buffer, id) = capture();
+        capture.record_connected(&id, address, Some(AddressType::Random));
+        capture.record_connected(&id, address, None);
+
+        let packets = buffer.packets();
+        // H4 type, event code, length, subevent, status and connection handle come first.
+        assert_eq!(packets[0][7..9], [0x00, 0x01]);
+        assert_eq!(packets[1][7..9], [0x00, 0x00]);
+        assert_eq!(packets[0][9..15], [6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn failed_commands_get_no_error_response() {
+        let (capture, buffer, id) = capture();
+        let characteristic = characteristic(1, Some(0x0010));
+        let failed = Err(Error::NotConnected);
+        for write_type in [
+            WriteType::WithoutResponse,
+            WriteType::Signed,
+            WriteType::WithResponse,
+        ] {
+            capture.record_write(&id, &characteristic, &[1], write_type, &failed);
+        }
+
+        let opcodes: Vec<_> = buffer.packets().iter().map(|packet| packet[9]).collect();
+        assert_eq!(
+            opcodes,
+            [
+                ATT_WRITE_CMD,
+                ATT_SIGNED_WRITE_CMD,
+                ATT_WRITE_REQ,
+                ATT_ERROR_RSP
+            ]
+        );
+    }
+}
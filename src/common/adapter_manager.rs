@@ -11,12 +11,19 @@
 // following copyright:
 //
 // Copyright (c) 2014 The Rust Project Developers
-use crate::api::{CentralEvent, Peripheral};
+use super::advertising_interval::IntervalEstimator;
+use super::scan_filter;
+use crate::api::{AdvertisingInterval, CentralEvent, Peripheral, PeripheralProperties, ScanFilter};
+use crate::interceptor::InterceptorList;
 use crate::platform::PeripheralId;
+use crate::scan_session::ScanSessions;
 use dashmap::{mapref::one::RefMut, DashMap};
-use futures::stream::{Stream, StreamExt};
+use futures::stream::{self, Stream, StreamExt};
 use log::trace;
+use std::collections::{HashSet, VecDeque};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use tokio::sync::broadcast;
 use tokio_stream::wrappers::BroadcastStream;
 
@@ -27,6 +34,20 @@ where
 {
     peripherals: DashMap<PeripheralId, PeripheralType>,
     events_channel: broadcast::Sender<CentralEvent>,
+    scan_filter: RwLock<ScanFilter>,
+    // The peripherals whose DeviceDiscovered event was dropped by the scan filter.
+    withheld_discoveries: Mutex<HashSet<PeripheralId>>,
+    replay: Mutex<EventReplay>,
+    scan_sessions: Arc<ScanSessions>,
+    advertising_intervals: DashMap<PeripheralId, IntervalEstimator>,
+    interceptors: Arc<InterceptorList>,
+}
+
+/// The most recent events, handed to new subscribers before the live events.
+#[derive(Debug, Default)]
+struct EventReplay {
+    capacity: usize,
+    events: VecDeque<CentralEvent>,
 }
 
 impl<PeripheralType: Peripheral + 'static> Default for AdapterManager<PeripheralType> {
@@ -35,6 +56,12 @@ impl<PeripheralType: Peripheral + 'static> Default for AdapterManager<Peripheral
         AdapterManager {
             peripherals: DashMap::new(),
             events_channel: broadcast_sender,
+            scan_filter: RwLock::new(ScanFilter::default()),
+            withheld_discoveries: Mutex::new(HashSet::new()),
+            replay: Mutex::new(EventReplay::default()),
+            scan_sessions: Arc::new(ScanSessions::default()),
+            advertising_intervals: DashMap::new(),
+            interceptors: Arc::new(InterceptorList::default()),
         }
     }
 }
@@ -43,19 +70,97 @@ impl<PeripheralType> AdapterManager<PeripheralType>
 where
     PeripheralType: Peripheral + 'static,
 {
+    /// Sets the filter applied to advertisement events by
+    /// [`emit_with_properties`](Self::emit_with_properties), for the parts of [`ScanFilter`] which
+    /// the platform can't apply itself. Backends reset it to the default when the scan stops.
+    pub fn set_scan_filter(&self, filter: ScanFilter) {
+        *self.scan_filter.write().unwrap() = filter;
+    }
+
+    pub fn scan_sessions(&self) -> &Arc<ScanSessions> {
+        &self.scan_sessions
+    }
+
+    /// The interceptors registered on the central, which apply to all its peripherals.
+    pub fn interceptors(&self) -> &Arc<InterceptorList> {
+        &self.interceptors
+    }
+
+    /// Sets how many of the most recent events are replayed to new subscribers of
+    /// [`event_stream`](Self::event_stream). 0 disables the replay.
+    pub fn set_replay_capacity(&self, capacity: usize) {
+        let mut replay = self.replay.lock().unwrap();
+        replay.capacity = capacity;
+        let excess = replay.events.len().saturating_sub(capacity);
+        replay.events.drain(..excess);
+    }
+
+    /// Emits an event which wasn't caused by an advertisement. Advertisement events go through
+    /// [`emit_with_properties`](Self::emit_with_properties), so the scan filter applies to them.
     pub fn emit(&self, event: CentralEvent) {
+        debug_assert!(
+            event.advertisement_peripheral_id().is_none(),
+            "advertisement event emitted without properties: {:?}",
+            event
+        );
+        self.send(event);
+    }
+
+    /// Emits `event`, applying the scan filter to it if it was caused by an advertisement of a
+    /// peripheral with `properties`. The properties are passed in rather than looked up, so this
+    /// may be called while holding a reference into the peripheral map.
+    pub fn emit_with_properties(
+        &self,
+        event: CentralEvent,
+        properties: Option<&PeripheralProperties>,
+    ) {
+        let events = {
+            let filter = self.scan_filter.read().unwrap();
+            let mut withheld = self.withheld_discoveries.lock().unwrap();
+            scan_filter::filter_event(&filter, &mut withheld, event, properties)
+        };
+        for event in events {
+            self.send(event);
+        }
+    }
+
+    fn send(&self, event: CentralEvent) {
         if let CentralEvent::DeviceDisconnected(ref id) = event {
             self.peripherals.remove(id);
+            self.advertising_intervals.remove(id);
+            // The peripheral is discovered anew when it shows up again.
+            self.withheld_discoveries.lock().unwrap().remove(id);
         }
 
+        // Hold the lock while sending, so a new subscriber gets each event either replayed or live.
+        let mut replay = self.replay.lock().unwrap();
+        if replay.capacity > 0 {
+            if replay.events.len() == replay.capacity {
+                replay.events.pop_front();
+            }
+            replay.events.push_back(event.clone());
+        }
         if let Err(lost) = self.events_channel.send(event) {
             trace!("Lost central event, while nothing subscribed: {:?}", lost);
         }
     }
 
+    /// Records that an advertisement of the peripheral was received just now, returning the
+    /// estimated advertising interval of the peripheral. Backends call this once for each
+    /// advertising report, before updating the properties of the peripheral.
+    pub fn record_advertisement(&self, id: &PeripheralId) -> Option<AdvertisingInterval> {
+        let mut estimator = self.advertising_intervals.entry(id.clone()).or_default();
+        estimator.record(Instant::now());
+        estimator.estimate()
+    }
+
     pub fn event_stream(&self) -> Pin<Box<dyn Stream<Item = CentralEvent> + Send>> {
+        let replay = self.replay.lock().unwrap();
         let receiver = self.events_channel.subscribe();
-        Box::pin(BroadcastStream::new(receiver).filter_map(|x| async move { x.ok() }))
+        let replayed = stream::iter(replay.events.clone());
+        Box::pin(
+            replayed.chain(BroadcastStream::new(receiver).filter_map(|x| async move { x.ok() })),
+        )
     }
 
     pub fn add_peripheral(&self, peripheral: PeripheralType) {
@@ -66,6 +171,29 @@ where
         self.peripherals.insert(peripheral.id(), peripheral);
     }
 
+    /// Returns the peripheral with the given ID, adding the one made by `make` if there is none
+    /// yet. Unlike [`add_peripheral`](Self::add_peripheral) this is safe to call when a scan may
+    /// add the same peripheral concurrently. The flag tells whether the peripheral was added.
+    // Only used on windows, so turn off deadcode so we don't get warnings on other platforms.
+    #[allow(dead_code)]
+    pub fn peripheral_or_insert_with(
+        &self,
+        id: &PeripheralId,
+        make: impl FnOnce() -> PeripheralType,
+    ) -> (PeripheralType, bool) {
+        let mut added = false;
+        let peripheral = self
+            .peripherals
+            .entry(id.clone())
+            .or_insert_with(|| {
+                added = true;
+                make()
+            })
+            .value()
+            .clone();
+        (peripheral, added)
+    }
+
     pub fn peripherals(&self) -> Vec<PeripheralType> {
         self.peripherals
             .iter()
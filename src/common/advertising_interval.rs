@@ -0,0 +1,149 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Estimates the advertising interval of a device from the times its advertisements were
+//! received.
+//!
+//! A scanner doesn't receive every advertisement: it only listens on one channel at a time and
+//! may not be scanning all the time, so missed advertisements show up as gaps which are multiples
+//! of the interval. The interval is therefore taken from the shortest gaps, and the confidence
+//! reflects how well the other gaps fit it.
+
+use crate::api::AdvertisingInterval;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The shortest interval a legacy advertiser may use. Reports closer together belong to the same
+/// advertising event, e.g. an advertisement and its scan response.
+const MIN_INTERVAL: Duration = Duration::from_millis(20);
+/// The largest random delay an advertiser adds to each advertising event.
+const MAX_ADV_DELAY: Duration = Duration::from_millis(10);
+/// The number of gaps kept, so the estimate follows devices changing their interval.
+const MAX_GAPS: usize = 32;
+/// The number of gaps needed for an estimate.
+const MIN_GAPS: usize = 3;
+/// The number of gaps from which the confidence isn't scaled down anymore.
+const FULL_CONFIDENCE_GAPS: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct IntervalEstimator {
+    last: Option<Instant>,
+    gaps: VecDeque<Duration>,
+}
+
+impl IntervalEstimator {
+    /// Records an advertisement received at `at`.
+    pub fn record(&mut self, at: Instant) {
+        if let Some(last) = self.last {
+            let gap = at.saturating_duration_since(last);
+            if gap < MIN_INTERVAL {
+                // Part of the advertising event already recorded.
+                return;
+            }
+            if self.gaps.len() == MAX_GAPS {
+                self.gaps.pop_front();
+            }
+            self.gaps.push_back(gap);
+        }
+        self.last = Some(at);
+    }
+
+    /// Estimates the interval from the recorded advertisements, or returns `None` if there are
+    /// too few of them.
+    pub fn estimate(&self) -> Option<AdvertisingInterval> {
+        if self.gaps.len() < MIN_GAPS {
+            return None;
+        }
+        let mut sorted: Vec<_> = self.gaps.iter().copied().collect();
+        sorted.sort();
+        // The shortest gap alone is skewed by the random delay, so take the median of the gaps
+        // close to it.
+        let limit = sorted[0] + sorted[0] / 2;
+        let shortest = sorted.partition_point(|&gap| gap <= limit);
+        let interval = sorted[(shortest - 1) / 2];
+
+        let tolerance = (interval / 5).max(MAX_ADV_DELAY);
+        let consistent = sorted
+            .iter()
+            .filter(|&&gap| {
+                let multiple = (gap.as_secs_f64() / interval.as_secs_f64())
+                    .round()
+                    .max(1.0);
+                let expected = interval.mul_f64(multiple);
+                gap.abs_diff(expected) <= tolerance.mul_f64(multiple.sqrt())
+            })
+            .count();
+        let fit = consistent as f32 / sorted.len() as f32;
+        let samples = sorted.len().min(FULL_CONFIDENCE_GAPS) as f32 / FULL_CONFIDENCE_GAPS as f32;
+        Some(AdvertisingInterval {
+            interval,
+            confidence: fit * samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimate(gaps_ms: &[u64]) -> Option<AdvertisingInterval> {
+        let mut estimator = IntervalEstimator::default();
+        let mut at = Instant::now();
+        estimator.record(at);
+        for &gap in gaps_ms {
+            at += Duration::from_millis(gap);
+            estimator.record(at);
+        }
+        estimator.estimate()
+    }
+
+    #[test]
+    fn too_few_advertisements() {
+        assert_eq!(estimate(&[100, 100]), None);
+    }
+
+    #[test]
+    fn regular_advertisements() {
+        let estimate = estimate(&[104; 16]).unwrap();
+        assert_eq!(estimate.interval, Duration::from_millis(104));
+        assert_eq!(estimate.confidence, 1.0);
+    }
+
+    #[test]
+    fn few_advertisements_lower_confidence() {
+        let estimate = estimate(&[104; 4]).unwrap();
+        assert_eq!(estimate.interval, Duration::from_millis(104));
+        assert_eq!(estimate.confidence, 0.25);
+    }
+
+    #[test]
+    fn missed_advertisements() {
+        let estimate = estimate(&[
+            102, 208, 105, 310, 101, 103, 207, 106, 104, 102, 415, 103, 101, 209, 105, 104,
+        ])
+        .unwrap();
+        assert_eq!(estimate.interval, Duration::from_millis(103));
+        assert_eq!(estimate.confidence, 1.0);
+    }
+
+    #[test]
+    fn scan_responses_ignored() {
+        // Each advertisement is followed by a scan response a few milliseconds later.
+        let estimate = estimate(&[3, 97, 2, 98, 4, 96, 3, 97]).unwrap();
+        assert_eq!(estimate.interval, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn irregular_advertisements_lower_confidence() {
+        let estimate = estimate(&[
+            100, 100, 100, 100, 100, 100, 100, 100, 160, 250, 170, 260, 180, 240, 150, 270,
+        ])
+        .unwrap();
+        assert_eq!(estimate.interval, Duration::from_millis(100));
+        assert!(estimate.confidence < 0.7);
+    }
+}
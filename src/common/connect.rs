@@ -0,0 +1,35 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::api::Peripheral;
+use crate::{Error, Result};
+use log::debug;
+use std::future::Future;
+use std::time::Duration;
+
+/// Runs `connect`, a connection attempt to `peripheral`, for at most `timeout`.
+///
+/// When the attempt times out it is cancelled by disconnecting, as dropping the future doesn't stop
+/// the platform from connecting later on.
+pub async fn with_timeout<P: Peripheral>(
+    peripheral: &P,
+    timeout: Option<Duration>,
+    connect: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let Some(timeout) = timeout else {
+        return connect.await;
+    };
+    match tokio::time::timeout(timeout, connect).await {
+        Ok(result) => result,
+        Err(_) => {
+            if let Err(e) = peripheral.disconnect().await {
+                debug!("Failed to cancel a timed out connection attempt: {}", e);
+            }
+            Err(Error::TimedOut(timeout))
+        }
+    }
+}
@@ -0,0 +1,291 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Fakes of the API traits, for the tests of the code built on top of them.
+
+use crate::api::{
+    BDAddr, Backend, BackendKind, Central, CentralEvent, CentralState, Characteristic, Descriptor,
+    Feature, Peripheral, PeripheralProperties, ScanFilter, Service, ValueNotification, WriteType,
+};
+use crate::platform::PeripheralId;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use futures::stream::{self, Stream};
+use std::collections::{BTreeSet, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The id the platform gives the device with `address`.
+pub(crate) fn peripheral_id(address: BDAddr) -> PeripheralId {
+    #[cfg(all(target_os = "linux", feature = "bluez"))]
+    {
+        let object_path = format!(
+            "/org/bluez/hci0/dev_{}",
+            address.to_string().replace(':', "_")
+        );
+        let device_id: bluez_async::DeviceId =
+            serde_json::from_value(serde_json::json!({ "object_path": object_path })).unwrap();
+        device_id.into()
+    }
+    #[cfg(target_vendor = "apple")]
+    {
+        uuid::Uuid::from_u128(u64::from(address).into()).into()
+    }
+    #[cfg(not(any(all(target_os = "linux", feature = "bluez"), target_vendor = "apple")))]
+    {
+        address.into()
+    }
+}
+
+/// An operation a [`FakePeripheral`] was asked for.
+#[derive(Clone, Debug)]
+pub(crate) struct Call {
+    pub operation: &'static str,
+    /// The value written, for writes.
+    pub data: Vec<u8>,
+    pub started: Instant,
+    pub ended: Instant,
+}
+
+#[derive(Debug)]
+struct State {
+    properties: PeripheralProperties,
+    services: BTreeSet<Service>,
+    connected: bool,
+    value: Vec<u8>,
+    // The errors the next operations fail with, one each.
+    failures: VecDeque<Error>,
+    calls: Vec<Call>,
+}
+
+/// A peripheral which answers reads with a fixed value and records the operations it's asked for.
+/// Clones share their state.
+#[derive(Clone, Debug)]
+pub(crate) struct FakePeripheral {
+    id: PeripheralId,
+    // How long each operation takes.
+    duration: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakePeripheral {
+    /// A connected peripheral without services.
+    pub fn new(address: BDAddr) -> Self {
+        FakePeripheral {
+            id: peripheral_id(address),
+            duration: Duration::ZERO,
+            state: Arc::new(Mutex::new(State {
+                properties: PeripheralProperties {
+                    address,
+                    ..Default::default()
+                },
+                services: BTreeSet::new(),
+                connected: true,
+                value: Vec::new(),
+                failures: VecDeque::new(),
+                calls: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn with_value(self, value: &[u8]) -> Self {
+        self.state.lock().unwrap().value = value.to_vec();
+        self
+    }
+
+    pub fn with_name(self, name: &str) -> Self {
+        self.state.lock().unwrap().properties.local_name = Some(name.to_string());
+        self
+    }
+
+    pub fn with_services(self, services: BTreeSet<Service>) -> Self {
+        self.state.lock().unwrap().services = services;
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.state.lock().unwrap().connected = connected;
+    }
+
+    /// Makes the next operations fail with `errors`, one each.
+    pub fn fail_next(&self, errors: impl IntoIterator<Item = Error>) {
+        self.state.lock().unwrap().failures.extend(errors);
+    }
+
+    /// The operations asked for so far.
+    pub fn calls(&self) -> Vec<Call> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    async fn call(&self, operation: &'static str, data: &[u8]) -> Result<()> {
+        let started = Instant::now();
+        tokio::time::sleep(self.duration).await;
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(Call {
+            operation,
+            data: data.to_vec(),
+            started,
+            ended: Instant::now(),
+        });
+        match state.failures.pop_front() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Peripheral for FakePeripheral {
+    fn id(&self) -> PeripheralId {
+        self.id.clone()
+    }
+
+    fn address(&self) -> BDAddr {
+        self.state.lock().unwrap().properties.address
+    }
+
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+        Ok(self.cached_properties())
+    }
+
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        Some(Arc::new(self.state.lock().unwrap().properties.clone()))
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.state.lock().unwrap().services.clone()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.state.lock().unwrap().connected)
+    }
+
+    async fn mtu(&self, _characteristics: Option<&[Characteristic]>) -> Result<u16> {
+        Ok(23)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.call("connect", &[]).await?;
+        self.set_connected(true);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.call("disconnect", &[]).await?;
+        self.set_connected(false);
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.call("discover services", &[]).await
+    }
+
+    async fn write(&self, _: &Characteristic, data: &[u8], _: WriteType) -> Result<()> {
+        self.call("write", data).await
+    }
+
+    async fn read(&self, _characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.call("read", &[]).await?;
+        Ok(self.state.lock().unwrap().value.clone())
+    }
+
+    async fn read_cached(&self, characteristic: &Characteristic, _: Duration) -> Result<Vec<u8>> {
+        self.read(characteristic).await
+    }
+
+    async fn subscribe(&self, _characteristic: &Characteristic) -> Result<()> {
+        self.call("subscribe", &[]).await
+    }
+
+    async fn unsubscribe(&self, _characteristic: &Characteristic) -> Result<()> {
+        self.call("unsubscribe", &[]).await
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        Ok(Box::pin(stream::empty()))
+    }
+
+    async fn write_descriptor(&self, _descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.call("write descriptor", data).await
+    }
+
+    async fn read_descriptor(&self, _descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.call("read descriptor", &[]).await?;
+        Ok(self.state.lock().unwrap().value.clone())
+    }
+}
+
+/// A central which knows a fixed set of peripherals. It adds peripherals by id, without any
+/// services, only if `can_add` is set.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FakeCentral {
+    pub peripherals: Vec<FakePeripheral>,
+    pub can_add: bool,
+}
+
+#[async_trait]
+impl Central for FakeCentral {
+    type Peripheral = FakePeripheral;
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(Box::pin(stream::empty()))
+    }
+
+    async fn start_scan(&self, _filter: ScanFilter) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn peripherals(&self) -> Result<Vec<FakePeripheral>> {
+        Ok(self.peripherals.clone())
+    }
+
+    async fn peripheral(&self, id: &PeripheralId) -> Result<FakePeripheral> {
+        self.peripherals
+            .iter()
+            .find(|peripheral| peripheral.id == *id)
+            .cloned()
+            .ok_or(Error::DeviceNotFound)
+    }
+
+    async fn add_peripheral(&self, id: &PeripheralId) -> Result<FakePeripheral> {
+        if !self.can_add {
+            return Err(Error::NotSupported {
+                feature: Feature::AddPeripheral,
+            });
+        }
+        Ok(FakePeripheral {
+            id: id.clone(),
+            ..FakePeripheral::new(BDAddr::default())
+        })
+    }
+
+    async fn adapter_info(&self) -> Result<String> {
+        Ok(String::new())
+    }
+
+    async fn adapter_state(&self) -> Result<CentralState> {
+        Ok(CentralState::PoweredOn)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend {
+            kind: BackendKind::BlueZ,
+            version: None,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Reads of characteristic values which don't fit in a single ATT packet, split into chunks at
+//! increasing offsets.
+
+use crate::api::MAX_ATTRIBUTE_VALUE_LEN;
+use crate::Result;
+use std::future::Future;
+
+/// The size of the header of an ATT Read Response or Read Blob Response: just the opcode.
+const READ_RESPONSE_HEADER_LEN: usize = 1;
+
+/// The most value bytes a single read response carries with `mtu`.
+pub fn read_chunk_len(mtu: u16) -> usize {
+    usize::from(mtu).saturating_sub(READ_RESPONSE_HEADER_LEN)
+}
+
+/// Reads a whole value with `read_at`, which reads the value from the given offset.
+///
+/// A response filling the packet may have been cut off, so reading goes on at the end of what was
+/// read so far until a response comes back short, or the value reaches the largest length an
+/// attribute can have.
+pub async fn read_long<F, Fut>(chunk_len: usize, mut read_at: F) -> Result<Vec<u8>>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>>>,
+{
+    let mut value = read_at(0).await?;
+    if chunk_len == 0 {
+        return Ok(value);
+    }
+    let mut last_len = value.len();
+    while last_len >= chunk_len && value.len() < MAX_ATTRIBUTE_VALUE_LEN {
+        let chunk = read_at(value.len()).await?;
+        last_len = chunk.len();
+        value.extend(chunk);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// A value read from the given offset, cut off at `chunk_len` like a stack which doesn't do
+    /// long reads by itself.
+    fn chunk_at(value: &[u8], offset: usize, chunk_len: usize) -> Vec<u8> {
+        value[offset.min(value.len())..]
+            .iter()
+            .take(chunk_len)
+            .copied()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn short_value_is_read_once() {
+        let reads = Mutex::new(Vec::new());
+        let value = read_long(22, |offset| {
+            reads.lock().unwrap().push(offset);
+            async move { Ok(chunk_at(&[1, 2, 3], offset, 22)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(value, vec![1, 2, 3]);
+        assert_eq!(*reads.lock().unwrap(), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn truncated_value_is_read_in_chunks() {
+        let full: Vec<u8> = (0..50).collect();
+        let reads = Mutex::new(Vec::new());
+        let value = read_long(22, |offset| {
+            reads.lock().unwrap().push(offset);
+            let chunk = chunk_at(&full, offset, 22);
+            async move { Ok(chunk) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(value, full);
+        assert_eq!(*reads.lock().unwrap(), vec![0, 22, 44]);
+    }
+
+    #[tokio::test]
+    async fn value_of_whole_chunks_ends_with_empty_read() {
+        let full: Vec<u8> = (0..44).collect();
+        let value = read_long(22, |offset| {
+            let chunk = chunk_at(&full, offset, 22);
+            async move { Ok(chunk) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(value, full);
+    }
+
+    #[tokio::test]
+    async fn read_stops_at_max_attribute_len() {
+        let reads = Mutex::new(0);
+        let value = read_long(100, |_| {
+            *reads.lock().unwrap() += 1;
+            async { Ok(vec![0; 100]) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(value.len(), 600);
+        assert_eq!(*reads.lock().unwrap(), 6);
+    }
+
+    #[test]
+    fn chunk_length() {
+        assert_eq!(read_chunk_len(23), 22);
+        assert_eq!(read_chunk_len(0), 0);
+    }
+}
@@ -1,2 +1,13 @@
+#[cfg(any(not(target_os = "linux"), feature = "bluer"))]
 pub mod adapter_manager;
+#[cfg(any(not(target_os = "linux"), feature = "bluer", test))]
+pub mod advertising_interval;
+pub mod connect;
+#[cfg(test)]
+pub mod fake;
+#[cfg(any(target_os = "linux", test))]
+pub mod long_value;
+pub mod scan_filter;
+#[cfg(any(not(target_os = "linux"), feature = "bluer"))]
 pub mod util;
+pub mod value_cache;
@@ -0,0 +1,210 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Applies the parts of a [`ScanFilter`] which platforms can't apply themselves to central events.
+
+use crate::api::{CentralEvent, PeripheralProperties, ScanFilter};
+use crate::platform::PeripheralId;
+use log::trace;
+use std::collections::HashSet;
+
+/// Returns the events to emit for `event`, which is dropped if it was caused by an advertisement
+/// of a peripheral whose `properties` don't pass `filter`.
+///
+/// A peripheral may only pass once later advertisements filled in its properties, so dropped
+/// `DeviceDiscovered` events are remembered in `withheld`, and emitted ahead of the first event of
+/// the peripheral which passes.
+pub(crate) fn filter_event(
+    filter: &ScanFilter,
+    withheld: &mut HashSet<PeripheralId>,
+    event: CentralEvent,
+    properties: Option<&PeripheralProperties>,
+) -> Vec<CentralEvent> {
+    let Some(id) = event.advertisement_peripheral_id().cloned() else {
+        return vec![event];
+    };
+    let passes = !filter.filters_beyond_services()
+        || properties.is_some_and(|properties| filter.matches_except_services(properties));
+    if !passes {
+        trace!(
+            "Dropping central event filtered by scan filter: {:?}",
+            event
+        );
+        if matches!(event, CentralEvent::DeviceDiscovered(_)) {
+            withheld.insert(id);
+        }
+        return Vec::new();
+    }
+    match event {
+        CentralEvent::DeviceDiscovered(_) => {
+            withheld.remove(&id);
+            vec![event]
+        }
+        _ if withheld.remove(&id) => vec![CentralEvent::DeviceDiscovered(id), event],
+        _ => vec![event],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{BDAddr, ManufacturerDataFilter};
+    use crate::common::fake::peripheral_id;
+    use std::collections::HashMap;
+
+    const APPLE: u16 = 0x004c;
+
+    /// An event given to the filter, the properties of the peripheral at the time, and the events
+    /// expected back.
+    type Step<'a> = (
+        CentralEvent,
+        Option<&'a PeripheralProperties>,
+        Vec<CentralEvent>,
+    );
+
+    fn id() -> PeripheralId {
+        peripheral_id(BDAddr::from([1, 2, 3, 4, 5, 6]))
+    }
+
+    fn properties(name: Option<&str>, manufacturer_data: &[(u16, &[u8])]) -> PeripheralProperties {
+        PeripheralProperties {
+            local_name: name.map(str::to_string),
+            manufacturer_data: manufacturer_data
+                .iter()
+                .map(|(company, data)| (*company, data.to_vec()))
+                .collect(),
+            rssi: Some(-50),
+            ..Default::default()
+        }
+    }
+
+    fn discovered() -> CentralEvent {
+        CentralEvent::DeviceDiscovered(id())
+    }
+
+    fn updated() -> CentralEvent {
+        CentralEvent::DeviceUpdated(id())
+    }
+
+    fn manufacturer_data(data: &[u8]) -> CentralEvent {
+        CentralEvent::ManufacturerDataAdvertisement {
+            id: id(),
+            manufacturer_data: HashMap::from([(APPLE, data.to_vec())]),
+        }
+    }
+
+    #[test]
+    fn filter_events() {
+        let no_filter = ScanFilter {
+            services: vec![uuid::Uuid::from_u128(0x180f)],
+            ..Default::default()
+        };
+        let names = ScanFilter {
+            name_prefixes: vec!["Sensor".to_string()],
+            ..Default::default()
+        };
+        let ibeacons = ScanFilter {
+            manufacturer_data: vec![ManufacturerDataFilter {
+                company_id: APPLE,
+                data: vec![0x02, 0x15],
+                mask: vec![],
+            }],
+            ..Default::default()
+        };
+        let sensor = properties(Some("Sensor 1"), &[]);
+        let other = properties(Some("Other"), &[]);
+        let ibeacon = properties(None, &[(APPLE, &[0x02, 0x15, 0xff])]);
+        let not_ibeacon = properties(None, &[(APPLE, &[0x10, 0x05])]);
+        let nothing = properties(None, &[]);
+
+        // Each case is a filter and the steps it goes through in turn.
+        let cases: Vec<(&str, &ScanFilter, Vec<Step>)> = vec![
+            (
+                "services only are applied by the platform",
+                &no_filter,
+                vec![(discovered(), None, vec![discovered()])],
+            ),
+            (
+                "matching name",
+                &names,
+                vec![
+                    (discovered(), Some(&sensor), vec![discovered()]),
+                    (updated(), Some(&sensor), vec![updated()]),
+                ],
+            ),
+            (
+                "unknown properties",
+                &names,
+                vec![(discovered(), None, vec![])],
+            ),
+            (
+                "name advertised later",
+                &names,
+                vec![
+                    (discovered(), Some(&nothing), vec![]),
+                    (updated(), Some(&nothing), vec![]),
+                    (updated(), Some(&sensor), vec![discovered(), updated()]),
+                    (updated(), Some(&sensor), vec![updated()]),
+                ],
+            ),
+            (
+                "name not matching, whatever the RSSI",
+                &names,
+                vec![
+                    (discovered(), Some(&other), vec![]),
+                    (updated(), Some(&other), vec![]),
+                ],
+            ),
+            (
+                "matching manufacturer data advertised later",
+                &ibeacons,
+                vec![
+                    (discovered(), Some(&not_ibeacon), vec![]),
+                    (
+                        manufacturer_data(&[0x02, 0x15, 0xff]),
+                        Some(&ibeacon),
+                        vec![discovered(), manufacturer_data(&[0x02, 0x15, 0xff])],
+                    ),
+                ],
+            ),
+            (
+                "manufacturer data not matching",
+                &ibeacons,
+                vec![
+                    (discovered(), Some(&not_ibeacon), vec![]),
+                    (manufacturer_data(&[0x10, 0x05]), Some(&not_ibeacon), vec![]),
+                ],
+            ),
+            (
+                "events not caused by advertisements",
+                &names,
+                vec![
+                    (discovered(), Some(&other), vec![]),
+                    (
+                        CentralEvent::DeviceConnected(id()),
+                        Some(&other),
+                        vec![CentralEvent::DeviceConnected(id())],
+                    ),
+                ],
+            ),
+        ];
+
+        for (name, filter, steps) in cases {
+            let mut withheld = HashSet::new();
+            for (i, (event, properties, expected)) in steps.into_iter().enumerate() {
+                let events = filter_event(filter, &mut withheld, event, properties);
+                assert_eq!(
+                    format!("{:?}", events),
+                    format!("{:?}", expected),
+                    "{}, event {}",
+                    name,
+                    i
+                );
+            }
+        }
+    }
+}
@@ -6,13 +6,21 @@
 // for full license information.
 
 use crate::api::ValueNotification;
+use futures::future::ready;
 use futures::stream::{Stream, StreamExt};
 use std::pin::Pin;
 use tokio::sync::broadcast::Receiver;
 use tokio_stream::wrappers::BroadcastStream;
 
+/// Turns a receiver of a peripheral's notification channel into a notification stream. `None` is
+/// sent on the channel when the peripheral disconnects, and ends the stream.
 pub fn notifications_stream_from_broadcast_receiver(
-    receiver: Receiver<ValueNotification>,
+    receiver: Receiver<Option<ValueNotification>>,
 ) -> Pin<Box<dyn Stream<Item = ValueNotification> + Send>> {
-    Box::pin(BroadcastStream::new(receiver).filter_map(|x| async move { x.ok() }))
+    Box::pin(
+        BroadcastStream::new(receiver)
+            .filter_map(|x| ready(x.ok()))
+            .take_while(|x| ready(x.is_some()))
+            .filter_map(ready),
+    )
 }
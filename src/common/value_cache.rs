@@ -0,0 +1,132 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::api::{Characteristic, ValueNotification};
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Identifies a characteristic in a [`ValueCache`]. A peripheral may have characteristics with
+/// the same UUID in different services, or even in the same service, so the service UUID and the
+/// handle, where the platform reports it, are part of the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CharacteristicKey {
+    pub service_uuid: Uuid,
+    pub uuid: Uuid,
+    pub handle: Option<u16>,
+}
+
+impl From<&Characteristic> for CharacteristicKey {
+    fn from(characteristic: &Characteristic) -> Self {
+        Self {
+            service_uuid: characteristic.service_uuid,
+            uuid: characteristic.uuid,
+            handle: characteristic.handle,
+        }
+    }
+}
+
+impl From<&ValueNotification> for CharacteristicKey {
+    fn from(notification: &ValueNotification) -> Self {
+        Self {
+            service_uuid: notification.service_uuid,
+            uuid: notification.uuid,
+            handle: notification.handle,
+        }
+    }
+}
+
+/// Last known value of each characteristic of a peripheral, along with the time it was seen.
+#[derive(Debug, Default)]
+pub struct ValueCache {
+    values: DashMap<CharacteristicKey, (Instant, Vec<u8>)>,
+}
+
+impl ValueCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a value received from a read or a notification.
+    pub fn insert(&self, characteristic: impl Into<CharacteristicKey>, value: Vec<u8>) {
+        self.values
+            .insert(characteristic.into(), (Instant::now(), value));
+    }
+
+    /// Returns the cached value for the characteristic if it was seen no longer than `max_age` ago.
+    pub fn get(
+        &self,
+        characteristic: impl Into<CharacteristicKey>,
+        max_age: Duration,
+    ) -> Option<Vec<u8>> {
+        self.values
+            .get(&characteristic.into())
+            .filter(|entry| entry.0.elapsed() <= max_age)
+            .map(|entry| entry.1.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: CharacteristicKey = CharacteristicKey {
+        service_uuid: Uuid::from_u128(0x180f),
+        uuid: Uuid::from_u128(0x2a19),
+        handle: None,
+    };
+
+    #[test]
+    fn fresh_value_is_returned() {
+        let cache = ValueCache::new();
+        cache.insert(UUID, vec![1, 2, 3]);
+        assert_eq!(
+            cache.get(UUID, Duration::from_secs(60)),
+            Some(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn stale_value_is_not_returned() {
+        let cache = ValueCache::new();
+        cache.insert(UUID, vec![1]);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.get(UUID, Duration::from_millis(1)), None);
+    }
+
+    #[test]
+    fn missing_value_is_not_returned() {
+        let cache = ValueCache::new();
+        cache.insert(
+            CharacteristicKey {
+                uuid: Uuid::from_u128(0x2a1a),
+                ..UUID
+            },
+            vec![1],
+        );
+        assert_eq!(cache.get(UUID, Duration::MAX), None);
+    }
+
+    #[test]
+    fn characteristics_with_the_same_uuid_are_kept_apart() {
+        let cache = ValueCache::new();
+        let other_service = CharacteristicKey {
+            service_uuid: Uuid::from_u128(0x1234),
+            ..UUID
+        };
+        let other_handle = CharacteristicKey {
+            handle: Some(0x0010),
+            ..UUID
+        };
+        cache.insert(UUID, vec![1]);
+        cache.insert(other_service, vec![2]);
+        cache.insert(other_handle, vec![3]);
+        assert_eq!(cache.get(UUID, Duration::MAX), Some(vec![1]));
+        assert_eq!(cache.get(other_service, Duration::MAX), Some(vec![2]));
+        assert_eq!(cache.get(other_handle, Duration::MAX), Some(vec![3]));
+    }
+}
@@ -2,16 +2,24 @@ use super::internal::{
     run_corebluetooth_thread, CoreBluetoothEvent, CoreBluetoothMessage, CoreBluetoothReply,
     CoreBluetoothReplyFuture,
 };
+use super::manager::ManagerOptions;
 use super::peripheral::{Peripheral, PeripheralId};
-use crate::api::{Central, CentralEvent, CentralState, ScanFilter};
+use crate::api::{
+    Backend, BackendKind, Capabilities, Central, CentralEvent, CentralState, Feature,
+    Peripheral as _, ScanFilter,
+};
 use crate::common::adapter_manager::AdapterManager;
+use crate::interceptor::GattInterceptor;
+use crate::scan_session::ScanSession;
 use crate::{Error, Result};
 use async_trait::async_trait;
 use futures::channel::mpsc::{self, Sender};
 use futures::sink::SinkExt;
 use futures::stream::{Stream, StreamExt};
 use log::*;
+use objc2::{class, msg_send_id, rc::Retained};
 use objc2_core_bluetooth::CBManagerState;
+use objc2_foundation::{NSObject, NSString};
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::task;
@@ -21,6 +29,25 @@ use tokio::task;
 pub struct Adapter {
     manager: Arc<AdapterManager<Peripheral>>,
     sender: Sender<CoreBluetoothMessage>,
+    _thread: Arc<ThreadGuard>,
+}
+
+/// Ends the CoreBluetooth thread when the last clone of the adapter is dropped. The thread and the
+/// task forwarding its events keep each other alive otherwise.
+#[derive(Debug)]
+struct ThreadGuard {
+    sender: Sender<CoreBluetoothMessage>,
+}
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self
+            .sender
+            .try_send(CoreBluetoothMessage::Shutdown { future: None })
+        {
+            debug!("Failed to shut down the CoreBluetooth thread: {}", e);
+        }
+    }
 }
 
 fn get_central_state(state: CBManagerState) -> CentralState {
@@ -32,9 +59,9 @@ fn get_central_state(state: CBManagerState) -> CentralState {
 }
 
 impl Adapter {
-    pub(crate) async fn new() -> Result<Self> {
+    pub(crate) async fn new(options: ManagerOptions) -> Result<Self> {
         let (sender, mut receiver) = mpsc::channel(256);
-        let adapter_sender = run_corebluetooth_thread(sender)?;
+        let adapter_sender = run_corebluetooth_thread(sender, options)?;
         // Since init currently blocked until the state update, we know the
         // receiver is dropped after that. We can pick it up here and make it
         // part of our event loop to update our peripherals.
@@ -60,20 +87,38 @@ impl Adapter {
                         name,
                         event_receiver,
                     } => {
-                        manager_clone.add_peripheral(Peripheral::new(
+                        let peripheral = Peripheral::new(
                             uuid,
                             name,
                             Arc::downgrade(&manager_clone),
                             event_receiver,
                             adapter_sender_clone.clone(),
-                        ));
-                        manager_clone.emit(CentralEvent::DeviceDiscovered(uuid.into()));
+                        );
+                        let properties = peripheral.cached_properties();
+                        manager_clone.add_peripheral(peripheral);
+                        manager_clone.record_advertisement(&uuid.into());
+                        manager_clone.emit_with_properties(
+                            CentralEvent::DeviceDiscovered(uuid.into()),
+                            properties.as_deref(),
+                        );
                     }
                     CoreBluetoothEvent::DeviceUpdated { uuid, name } => {
                         let id = uuid.into();
+                        let advertising_interval = manager_clone.record_advertisement(&id);
                         if let Some(entry) = manager_clone.peripheral_mut(&id) {
-                            entry.value().update_name(&name);
-                            manager_clone.emit(CentralEvent::DeviceUpdated(id));
+                            entry
+                                .value()
+                                .update_advertising_interval(advertising_interval);
+                            // Only advertisements with a name are reported as updates.
+                            let Some((name, source)) = name else {
+                                continue;
+                            };
+                            entry.value().update_name(name, source);
+                            let properties = entry.value().cached_properties();
+                            manager_clone.emit_with_properties(
+                                CentralEvent::DeviceUpdated(id),
+                                properties.as_deref(),
+                            );
                         }
                     }
                     CoreBluetoothEvent::DeviceDisconnected { uuid } => {
@@ -89,9 +134,28 @@ impl Adapter {
 
         Ok(Adapter {
             manager,
+            _thread: Arc::new(ThreadGuard {
+                sender: adapter_sender.clone(),
+            }),
             sender: adapter_sender,
         })
     }
+
+    /// Stops scanning and cancels the connections of all peripherals, then ends the CoreBluetooth
+    /// thread. The adapter can't be used anymore afterwards.
+    pub(crate) async fn shutdown(&self) -> Result<()> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::Shutdown {
+                future: Some(fut.get_state_clone()),
+            })
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => Ok(()),
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+    }
 }
 
 #[async_trait]
@@ -103,6 +167,7 @@ impl Central for Adapter {
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.manager.set_scan_filter(filter.clone());
         self.sender
             .to_owned()
             .send(CoreBluetoothMessage::StartScanning { filter })
@@ -115,9 +180,17 @@ impl Central for Adapter {
             .to_owned()
             .send(CoreBluetoothMessage::StopScanning)
             .await?;
+        self.manager.set_scan_filter(ScanFilter::default());
         Ok(())
     }
 
+    async fn start_scan_session(&self, filter: ScanFilter) -> Result<ScanSession> {
+        self.manager
+            .scan_sessions()
+            .start(self.clone(), filter)
+            .await
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         Ok(self.manager.peripherals())
     }
@@ -127,9 +200,9 @@ impl Central for Adapter {
     }
 
     async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
-        Err(Error::NotSupported(
-            "Can't add a Peripheral from a PeripheralId".to_string(),
-        ))
+        Err(Error::NotSupported {
+            feature: Feature::AddPeripheral,
+        })
     }
 
     async fn adapter_info(&self) -> Result<String> {
@@ -154,4 +227,38 @@ impl Central for Adapter {
             _ => panic!("Shouldn't get anything but a AdapterState!"),
         }
     }
+
+    async fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::BACKGROUND_SCAN
+            | Capabilities::EVENT_REPLAY
+            | Capabilities::MTU
+            | Capabilities::SCAN_SESSIONS
+            | Capabilities::GATT_INTERCEPTORS
+            | Capabilities::AUTO_CONNECT)
+    }
+
+    fn backend(&self) -> Backend {
+        let process_info: Retained<NSObject> =
+            unsafe { msg_send_id![class!(NSProcessInfo), processInfo] };
+        let version: Retained<NSString> =
+            unsafe { msg_send_id![&process_info, operatingSystemVersionString] };
+        Backend {
+            kind: BackendKind::CoreBluetooth,
+            version: Some(version.to_string()),
+        }
+    }
+
+    async fn set_event_replay_capacity(&self, capacity: usize) -> Result<()> {
+        self.manager.set_replay_capacity(capacity);
+        Ok(())
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.manager.interceptors().add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self.manager.interceptors().remove(interceptor))
+    }
 }
@@ -18,8 +18,7 @@
 
 use super::utils::nsstring_to_string;
 use super::utils::{core_bluetooth::cbuuid_to_uuid, nsuuid_to_uuid};
-use futures::channel::mpsc::Sender;
-use futures::sink::SinkExt;
+use futures::channel::mpsc::UnboundedSender;
 use log::{error, trace};
 use objc2::runtime::{AnyObject, ProtocolObject};
 use objc2::{declare_class, msg_send_id, mutability, rc::Retained, ClassType, DeclaredClass};
@@ -91,6 +90,10 @@ pub enum CentralDelegateEvent {
     DisconnectedDevice {
         peripheral_uuid: Uuid,
     },
+    ModifiedServices {
+        peripheral_uuid: Uuid,
+        invalidated_services: Vec<Uuid>,
+    },
     CharacteristicSubscribed {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
@@ -279,6 +282,14 @@ impl Debug for CentralDelegateEvent {
                 .field("descriptor_uuid", descriptor_uuid)
                 .field("data", data)
                 .finish(),
+            CentralDelegateEvent::ModifiedServices {
+                peripheral_uuid,
+                invalidated_services,
+            } => f
+                .debug_struct("ModifiedServices")
+                .field("peripheral_uuid", peripheral_uuid)
+                .field("invalidated_services", invalidated_services)
+                .finish(),
             CentralDelegateEvent::DescriptorWritten {
                 peripheral_uuid,
                 service_uuid,
@@ -306,7 +317,7 @@ declare_class!(
     }
 
     impl DeclaredClass for CentralDelegate {
-        type Ivars = Sender<CentralDelegateEvent>;
+        type Ivars = UnboundedSender<CentralDelegateEvent>;
     }
 
     unsafe impl NSObjectProtocol for CentralDelegate {}
@@ -319,10 +330,16 @@ declare_class!(
             self.send_event(CentralDelegateEvent::DidUpdateState { state });
         }
 
-        // #[method(centralManager:willRestoreState:)]
-        // fn delegate_centralmanager_willrestorestate(&self, _central: &CBCentralManager, _dict: &NSDictionary<NSString, AnyObject>) {
-        //     trace!("delegate_centralmanager_willrestorestate");
-        // }
+        // Required by CoreBluetooth when a restore identifier is set. Restored peripherals show up
+        // again through discovery, so there is nothing to do with the state here.
+        #[method(centralManager:willRestoreState:)]
+        fn delegate_centralmanager_willrestorestate(
+            &self,
+            _central: &CBCentralManager,
+            _dict: &NSDictionary<NSString, AnyObject>,
+        ) {
+            trace!("delegate_centralmanager_willrestorestate");
+        }
 
         #[method(centralManager:didConnectPeripheral:)]
         fn delegate_centralmanager_didconnectperipheral(
@@ -656,6 +673,30 @@ declare_class!(
             }
         }
 
+        #[method(peripheral:didModifyServices:)]
+        fn delegate_peripheral_didmodifyservices(
+            &self,
+            peripheral: &CBPeripheral,
+            invalidated_services: &NSArray<CBService>,
+        ) {
+            trace!(
+                "delegate_peripheral_didmodifyservices {}",
+                peripheral_debug(peripheral)
+            );
+            let invalidated_services = invalidated_services
+                .iter()
+                .map(|service| cbuuid_to_uuid(unsafe { &service.UUID() }))
+                .collect();
+            let peripheral_uuid = nsuuid_to_uuid(unsafe { &peripheral.identifier() });
+            self.send_event(CentralDelegateEvent::ModifiedServices {
+                peripheral_uuid,
+                invalidated_services,
+            });
+            // Discover everything again, the results are handled like those of the initial
+            // discovery on connection.
+            unsafe { peripheral.discoverServices(None) }
+        }
+
         #[method(peripheral:didReadRSSI:error:)]
         fn delegate_peripheral_didreadrssi_error(
             &self,
@@ -725,18 +766,18 @@ declare_class!(
 );
 
 impl CentralDelegate {
-    pub fn new(sender: Sender<CentralDelegateEvent>) -> Retained<Self> {
+    pub fn new(sender: UnboundedSender<CentralDelegateEvent>) -> Retained<Self> {
         let this = CentralDelegate::alloc().set_ivars(sender);
         unsafe { msg_send_id![super(this), init] }
     }
 
+    /// Forwards an event to the CoreBluetooth thread. This runs on the dispatch queue of the
+    /// central manager, so it must not block: the channel is unbounded, and the thread on the other
+    /// end only ever waits for these events.
     fn send_event(&self, event: CentralDelegateEvent) {
-        let mut sender = self.ivars().clone();
-        futures::executor::block_on(async {
-            if let Err(e) = sender.send(event).await {
-                error!("Error sending delegate event: {}", e);
-            }
-        });
+        if let Err(e) = self.ivars().unbounded_send(event) {
+            error!("Error sending delegate event: {}", e);
+        }
     }
 }
 
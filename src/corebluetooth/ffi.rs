@@ -1,17 +1,31 @@
 #![allow(non_camel_case_types)]
-use std::os::raw::{c_char, c_void};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
 
 pub type dispatch_object_s = c_void;
 pub type dispatch_queue_t = *mut dispatch_object_s;
 pub type dispatch_queue_attr_t = *const dispatch_object_s;
 
+pub type qos_class_t = c_uint;
+
 pub const DISPATCH_QUEUE_SERIAL: dispatch_queue_attr_t = 0 as dispatch_queue_attr_t;
 
+pub const QOS_CLASS_USER_INTERACTIVE: qos_class_t = 0x21;
+pub const QOS_CLASS_USER_INITIATED: qos_class_t = 0x19;
+pub const QOS_CLASS_DEFAULT: qos_class_t = 0x15;
+pub const QOS_CLASS_UTILITY: qos_class_t = 0x11;
+pub const QOS_CLASS_BACKGROUND: qos_class_t = 0x09;
+
 extern "C" {
     pub fn dispatch_queue_create(
         label: *const c_char,
         attr: dispatch_queue_attr_t,
     ) -> dispatch_queue_t;
+
+    pub fn dispatch_queue_attr_make_with_qos_class(
+        attr: dispatch_queue_attr_t,
+        qos_class: qos_class_t,
+        relative_priority: c_int,
+    ) -> dispatch_queue_attr_t;
 }
 
 // TODO: Do we need to link to AppKit here?
@@ -12,14 +12,17 @@ use super::{
     central_delegate::{CentralDelegate, CentralDelegateEvent},
     ffi,
     future::{BtlePlugFuture, BtlePlugFutureStateShared},
+    manager::{ManagerOptions, QueueQos},
     utils::{
         core_bluetooth::{cbuuid_to_uuid, uuid_to_cbuuid},
         nsuuid_to_uuid,
     },
 };
-use crate::api::{CharPropFlags, Characteristic, Descriptor, ScanFilter, Service, WriteType};
+use crate::api::{
+    CharPropFlags, Characteristic, Descriptor, NameSource, ScanFilter, Service, WriteType,
+};
 use crate::Error;
-use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::channel::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
 use futures::select;
 use futures::sink::SinkExt;
 use futures::stream::{Fuse, StreamExt};
@@ -27,11 +30,13 @@ use log::{error, trace, warn};
 use objc2::{msg_send_id, ClassType};
 use objc2::{rc::Retained, runtime::AnyObject};
 use objc2_core_bluetooth::{
-    CBCentralManager, CBCentralManagerScanOptionAllowDuplicatesKey, CBCharacteristic,
-    CBCharacteristicProperties, CBCharacteristicWriteType, CBDescriptor, CBManager,
-    CBManagerAuthorization, CBManagerState, CBPeripheral, CBPeripheralState, CBService, CBUUID,
+    CBCentralManager, CBCentralManagerOptionRestoreIdentifierKey,
+    CBCentralManagerOptionShowPowerAlertKey, CBCentralManagerScanOptionAllowDuplicatesKey,
+    CBCharacteristic, CBCharacteristicProperties, CBCharacteristicWriteType, CBDescriptor,
+    CBManager, CBManagerAuthorization, CBManagerState, CBPeripheral, CBPeripheralState, CBService,
+    CBUUID,
 };
-use objc2_foundation::{NSArray, NSData, NSMutableDictionary, NSNumber};
+use objc2_foundation::{NSArray, NSData, NSMutableDictionary, NSNumber, NSString};
 use std::{
     collections::{BTreeSet, HashMap, VecDeque},
     ffi::CString,
@@ -151,6 +156,7 @@ pub enum CoreBluetoothReply {
     Connected(BTreeSet<Service>),
     State(CBPeripheralState),
     Mtu(u16),
+    MaxWriteLen(usize),
     Ok,
     NotFound,
     Err(String),
@@ -159,10 +165,12 @@ pub enum CoreBluetoothReply {
 #[derive(Debug)]
 pub enum PeripheralEventInternal {
     Disconnected,
-    Notification(Uuid, Vec<u8>),
+    /// The UUIDs of the service and characteristic, and the value.
+    Notification(Uuid, Uuid, Vec<u8>),
     ManufacturerData(u16, Vec<u8>, i16),
     ServiceData(HashMap<Uuid, Vec<u8>>, i16),
     Services(Vec<Uuid>, i16),
+    ServicesChanged(BTreeSet<Service>),
 }
 
 pub type CoreBluetoothReplyStateShared = BtlePlugFutureStateShared<CoreBluetoothReply>;
@@ -177,7 +185,7 @@ struct ServiceInternal {
 struct PeripheralInternal {
     pub peripheral: Retained<CBPeripheral>,
     services: HashMap<Uuid, ServiceInternal>,
-    pub event_sender: Sender<PeripheralEventInternal>,
+    pub event_sender: UnboundedSender<PeripheralEventInternal>,
     pub disconnected_future_state: Option<CoreBluetoothReplyStateShared>,
     pub connected_future_state: Option<CoreBluetoothReplyStateShared>,
 }
@@ -203,7 +211,7 @@ impl Debug for PeripheralInternal {
 impl PeripheralInternal {
     pub fn new(
         peripheral: Retained<CBPeripheral>,
-        event_sender: Sender<PeripheralEventInternal>,
+        event_sender: UnboundedSender<PeripheralEventInternal>,
     ) -> Self {
         Self {
             peripheral,
@@ -287,14 +295,12 @@ impl PeripheralInternal {
         // back a Connected reply to the waiting future with all of the
         // characteristic info in it.
         if !self.services.values().any(|service| !service.discovered) {
-            if self.connected_future_state.is_none() {
-                panic!("We should still have a future at this point!");
-            }
             let services = self
                 .services
                 .iter()
                 .map(|(&service_uuid, service)| Service {
                     uuid: service_uuid,
+                    handle: None,
                     primary: unsafe { service.cbservice.isPrimary() },
                     characteristics: service
                         .characteristics
@@ -307,6 +313,8 @@ impl PeripheralInternal {
                                     uuid: descriptor_uuid,
                                     service_uuid,
                                     characteristic_uuid,
+                                    handle: None,
+                                    permissions: None,
                                 })
                                 .collect();
                             Characteristic {
@@ -314,17 +322,27 @@ impl PeripheralInternal {
                                 service_uuid,
                                 descriptors,
                                 properties: characteristic.properties,
+                                handle: None,
                             }
                         })
                         .collect(),
                 })
                 .collect();
-            self.connected_future_state
-                .take()
-                .unwrap()
-                .lock()
-                .unwrap()
-                .set_reply(CoreBluetoothReply::Connected(services));
+            match self.connected_future_state.take() {
+                Some(future) => future
+                    .lock()
+                    .unwrap()
+                    .set_reply(CoreBluetoothReply::Connected(services)),
+                // Services were discovered again after the peripheral modified them.
+                None => {
+                    if let Err(e) = self
+                        .event_sender
+                        .unbounded_send(PeripheralEventInternal::ServicesChanged(services))
+                    {
+                        error!("Error sending services changed event: {}", e);
+                    }
+                }
+            }
         }
     }
 
@@ -372,11 +390,15 @@ struct CoreBluetoothInternal {
     delegate: Retained<CentralDelegate>,
     // Map of identifiers to object pointers
     peripherals: HashMap<Uuid, PeripheralInternal>,
-    delegate_receiver: Fuse<Receiver<CentralDelegateEvent>>,
+    delegate_receiver: Fuse<UnboundedReceiver<CentralDelegateEvent>>,
     // Out in the world beyond CoreBluetooth, we'll be async, so just
     // task::block this when sending even though it'll never actually block.
     event_sender: Sender<CoreBluetoothEvent>,
     message_receiver: Fuse<Receiver<CoreBluetoothMessage>>,
+    wait_for_powered_on: bool,
+    // Scan requested before the central was powered on, started once it is.
+    pending_scan: Option<ScanFilter>,
+    shut_down: bool,
 }
 
 impl Debug for CoreBluetoothInternal {
@@ -459,6 +481,15 @@ pub enum CoreBluetoothMessage {
         characteristics: Option<Vec<Characteristic>>,
         future: CoreBluetoothReplyStateShared,
     },
+    GetMaxWriteLen {
+        peripheral_uuid: Uuid,
+        write_type: WriteType,
+        future: CoreBluetoothReplyStateShared,
+    },
+    /// Stops scanning, cancels all the connections and ends the thread.
+    Shutdown {
+        future: Option<CoreBluetoothReplyStateShared>,
+    },
 }
 
 #[derive(Debug)]
@@ -468,12 +499,12 @@ pub enum CoreBluetoothEvent {
     },
     DeviceDiscovered {
         uuid: Uuid,
-        name: Option<String>,
-        event_receiver: Receiver<PeripheralEventInternal>,
+        name: Option<(String, NameSource)>,
+        event_receiver: UnboundedReceiver<PeripheralEventInternal>,
     },
     DeviceUpdated {
         uuid: Uuid,
-        name: String,
+        name: Option<(String, NameSource)>,
     },
     DeviceDisconnected {
         uuid: Uuid,
@@ -484,18 +515,48 @@ impl CoreBluetoothInternal {
     pub fn new(
         message_receiver: Receiver<CoreBluetoothMessage>,
         event_sender: Sender<CoreBluetoothEvent>,
+        options: ManagerOptions,
     ) -> Self {
-        // Pretty sure these come preallocated?
-        let (sender, receiver) = mpsc::channel::<CentralDelegateEvent>(256);
+        let (sender, receiver) = mpsc::unbounded::<CentralDelegateEvent>();
         let delegate = CentralDelegate::new(sender);
 
         let label = CString::new("CBqueue").unwrap();
-        let queue =
-            unsafe { ffi::dispatch_queue_create(label.as_ptr(), ffi::DISPATCH_QUEUE_SERIAL) };
+        let attr = match options.queue_qos {
+            Some(qos) => unsafe {
+                ffi::dispatch_queue_attr_make_with_qos_class(
+                    ffi::DISPATCH_QUEUE_SERIAL,
+                    qos_class(qos),
+                    0,
+                )
+            },
+            None => ffi::DISPATCH_QUEUE_SERIAL,
+        };
+        let queue = unsafe { ffi::dispatch_queue_create(label.as_ptr(), attr) };
         let queue: *mut AnyObject = queue.cast();
 
+        let mut manager_options = NSMutableDictionary::new();
+        if let Some(show_power_alert) = options.show_power_alert {
+            manager_options.insert_id(
+                unsafe { CBCentralManagerOptionShowPowerAlertKey },
+                Retained::into_super(Retained::into_super(Retained::into_super(
+                    NSNumber::new_bool(show_power_alert),
+                ))),
+            );
+        }
+        if let Some(restore_identifier) = &options.restore_identifier {
+            manager_options.insert_id(
+                unsafe { CBCentralManagerOptionRestoreIdentifierKey },
+                Retained::into_super(Retained::into_super(NSString::from_str(restore_identifier))),
+            );
+        }
+
         let manager = unsafe {
-            msg_send_id![CBCentralManager::alloc(), initWithDelegate: &*delegate, queue: queue]
+            msg_send_id![
+                CBCentralManager::alloc(),
+                initWithDelegate: &*delegate,
+                queue: queue,
+                options: &*manager_options
+            ]
         };
 
         Self {
@@ -505,6 +566,9 @@ impl CoreBluetoothInternal {
             event_sender,
             message_receiver: message_receiver.fuse(),
             delegate,
+            wait_for_powered_on: options.wait_for_powered_on.unwrap_or(true),
+            pending_scan: None,
+            shut_down: false,
         }
     }
 
@@ -528,14 +592,13 @@ impl CoreBluetoothInternal {
             manufacturer_data
         );
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Err(e) = p
-                .event_sender
-                .send(PeripheralEventInternal::ManufacturerData(
-                    manufacturer_id,
-                    manufacturer_data,
-                    rssi,
-                ))
-                .await
+            if let Err(e) =
+                p.event_sender
+                    .unbounded_send(PeripheralEventInternal::ManufacturerData(
+                        manufacturer_id,
+                        manufacturer_data,
+                        rssi,
+                    ))
             {
                 error!("Error sending notification event: {}", e);
             }
@@ -552,8 +615,7 @@ impl CoreBluetoothInternal {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
             if let Err(e) = p
                 .event_sender
-                .send(PeripheralEventInternal::ServiceData(service_data, rssi))
-                .await
+                .unbounded_send(PeripheralEventInternal::ServiceData(service_data, rssi))
             {
                 error!("Error sending notification event: {}", e);
             }
@@ -565,8 +627,7 @@ impl CoreBluetoothInternal {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
             if let Err(e) = p
                 .event_sender
-                .send(PeripheralEventInternal::Services(services, rssi))
-                .await
+                .unbounded_send(PeripheralEventInternal::Services(services, rssi))
             {
                 error!("Error sending notification event: {}", e);
             }
@@ -583,30 +644,24 @@ impl CoreBluetoothInternal {
 
         let name = match (peripheral_name.map(|n| n.to_string()), local_name) {
             (Some(p_name), Some(l_name)) if p_name != l_name => {
-                Some(format!("{p_name} [{l_name}]"))
+                Some((format!("{p_name} [{l_name}]"), NameSource::Platform))
             }
-            (Some(p_name), Some(_)) => Some(p_name),
-            (Some(p_name), None) => Some(p_name),
-            (None, Some(l_name)) => Some(l_name),
+            (Some(p_name), _) => Some((p_name, NameSource::Platform)),
+            (None, Some(l_name)) => Some((l_name, NameSource::Advertisement)),
             (None, None) => None,
         };
 
         if self.peripherals.contains_key(&uuid) {
-            if let Some(name) = name {
-                self.dispatch_event(CoreBluetoothEvent::DeviceUpdated {
-                    uuid,
-                    name: name.to_string(),
-                })
+            self.dispatch_event(CoreBluetoothEvent::DeviceUpdated { uuid, name })
                 .await;
-            }
         } else {
             // Create our channels
-            let (event_sender, event_receiver) = mpsc::channel(256);
+            let (event_sender, event_receiver) = mpsc::unbounded();
             self.peripherals
                 .insert(uuid, PeripheralInternal::new(peripheral, event_sender));
             self.dispatch_event(CoreBluetoothEvent::DeviceDiscovered {
                 uuid,
-                name: name.map(|name| name.to_string()),
+                name,
                 event_receiver,
             })
             .await;
@@ -640,6 +695,17 @@ impl CoreBluetoothInternal {
         }
     }
 
+    fn on_modified_services(&mut self, peripheral_uuid: Uuid, invalidated_services: Vec<Uuid>) {
+        trace!("Services modified: {:?}", invalidated_services);
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            // Operations on the invalidated services fail with NotFound until they are discovered
+            // again.
+            for service_uuid in invalidated_services {
+                p.services.remove(&service_uuid);
+            }
+        }
+    }
+
     fn on_discovered_characteristics(
         &mut self,
         peripheral_uuid: Uuid,
@@ -715,8 +781,7 @@ impl CoreBluetoothInternal {
                 .get_mut(&peripheral_uuid)
                 .expect("If we're here we should have an ID")
                 .event_sender
-                .send(PeripheralEventInternal::Disconnected)
-                .await
+                .unbounded_send(PeripheralEventInternal::Disconnected)
             {
                 error!("Error sending notification event: {}", e);
             }
@@ -806,11 +871,6 @@ impl CoreBluetoothInternal {
                 if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
                 {
                     trace!("Got read event!");
-
-                    let mut data_clone = Vec::new();
-                    for byte in data.iter() {
-                        data_clone.push(*byte);
-                    }
                     // Reads and notifications both return the same callback. If
                     // we're trying to do a read, we'll have a future we can
                     // fulfill. Otherwise, just treat the returned value as a
@@ -820,15 +880,14 @@ impl CoreBluetoothInternal {
                         state
                             .lock()
                             .unwrap()
-                            .set_reply(CoreBluetoothReply::ReadResult(data_clone));
-                    } else if let Err(e) = peripheral
-                        .event_sender
-                        .send(PeripheralEventInternal::Notification(
+                            .set_reply(CoreBluetoothReply::ReadResult(data));
+                    } else if let Err(e) = peripheral.event_sender.unbounded_send(
+                        PeripheralEventInternal::Notification(
+                            service_uuid,
                             characteristic_uuid,
                             data,
-                        ))
-                        .await
-                    {
+                        ),
+                    ) {
                         error!("Error sending notification event: {}", e);
                     }
                 }
@@ -899,18 +958,17 @@ impl CoreBluetoothInternal {
                 if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
                 {
                     trace!("Writing value! With kind {:?}", kind);
+                    let Some(write_type) = cb_write_type(kind) else {
+                        fut.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+                            "Signed writes are not supported".to_string(),
+                        ));
+                        return;
+                    };
                     unsafe {
                         peripheral.peripheral.writeValue_forCharacteristic_type(
                             &NSData::from_vec(data),
                             &characteristic.characteristic,
-                            match kind {
-                                WriteType::WithResponse => {
-                                    CBCharacteristicWriteType::CBCharacteristicWriteWithResponse
-                                }
-                                WriteType::WithoutResponse => {
-                                    CBCharacteristicWriteType::CBCharacteristicWriteWithoutResponse
-                                }
-                            },
+                            write_type,
                         );
                     }
                     // WriteWithoutResponse does not call the corebluetooth
@@ -1076,15 +1134,11 @@ impl CoreBluetoothInternal {
                     if let Some(descriptor) = characteristic.descriptors.get_mut(&descriptor_uuid) {
                         trace!("Got read event!");
 
-                        let mut data_clone = Vec::new();
-                        for byte in data.iter() {
-                            data_clone.push(*byte);
-                        }
                         let state = descriptor.read_future_state.pop_back().unwrap();
                         state
                             .lock()
                             .unwrap()
-                            .set_reply(CoreBluetoothReply::ReadResult(data_clone));
+                            .set_reply(CoreBluetoothReply::ReadResult(data));
                     }
                 }
             }
@@ -1118,6 +1172,11 @@ impl CoreBluetoothInternal {
                     // "ready" variable in our adapter that will cause scans/etc
                     // to fail if this hasn't updated.
                     CentralDelegateEvent::DidUpdateState{state} => {
+                        if state == CBManagerState::PoweredOn {
+                            if let Some(filter) = self.pending_scan.take() {
+                                self.start_discovery(filter);
+                            }
+                        }
                         self.dispatch_event(CoreBluetoothEvent::DidUpdateState{state}).await
                     }
                     CentralDelegateEvent::DiscoveredPeripheral{cbperipheral, local_name} => {
@@ -1141,6 +1200,9 @@ impl CoreBluetoothInternal {
                     CentralDelegateEvent::DisconnectedDevice{peripheral_uuid} => {
                         self.on_peripheral_disconnect(peripheral_uuid).await
                     }
+                    CentralDelegateEvent::ModifiedServices{peripheral_uuid, invalidated_services} => {
+                        self.on_modified_services(peripheral_uuid, invalidated_services)
+                    }
                     CentralDelegateEvent::CharacteristicSubscribed{
                         peripheral_uuid,
                         service_uuid,
@@ -1237,6 +1299,12 @@ impl CoreBluetoothInternal {
                     } => {
                         self.get_mtu(peripheral_uuid, characteristics, future);
                     }
+                    CoreBluetoothMessage::GetMaxWriteLen {
+                        peripheral_uuid,
+                        write_type,
+                        future,
+                    } => self.get_max_write_len(peripheral_uuid, write_type, future),
+                    CoreBluetoothMessage::Shutdown { future } => self.shutdown(future),
                 };
             }
         }
@@ -1249,6 +1317,32 @@ impl CoreBluetoothInternal {
             .set_reply(CoreBluetoothReply::AdapterState(state))
     }
 
+    fn get_max_write_len(
+        &mut self,
+        peripheral_uuid: Uuid,
+        write_type: WriteType,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        let Some(peripheral) = self.peripherals.get(&peripheral_uuid) else {
+            fut.lock().unwrap().set_reply(CoreBluetoothReply::NotFound);
+            return;
+        };
+        let Some(write_type) = cb_write_type(write_type) else {
+            fut.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+                "Signed writes are not supported".to_string(),
+            ));
+            return;
+        };
+        let max_len = unsafe {
+            peripheral
+                .peripheral
+                .maximumWriteValueLengthForType(write_type)
+        };
+        fut.lock()
+            .unwrap()
+            .set_reply(CoreBluetoothReply::MaxWriteLen(max_len));
+    }
+
     fn get_mtu(
         &mut self,
         peripheral_uuid: Uuid,
@@ -1306,6 +1400,12 @@ impl CoreBluetoothInternal {
 
     fn start_discovery(&mut self, filter: ScanFilter) {
         trace!("BluetoothAdapter::start_discovery");
+        if self.wait_for_powered_on && unsafe { self.manager.state() } != CBManagerState::PoweredOn
+        {
+            trace!("Central not powered on yet, deferring scan");
+            self.pending_scan = Some(filter);
+            return;
+        }
         let service_uuids = scan_filter_to_service_uuids(filter);
         let mut options = NSMutableDictionary::new();
         // NOTE: If duplicates are not allowed then a peripheral will not show
@@ -1324,8 +1424,23 @@ impl CoreBluetoothInternal {
 
     fn stop_discovery(&mut self) {
         trace!("BluetoothAdapter::stop_discovery");
+        self.pending_scan = None;
         unsafe { self.manager.stopScan() };
     }
+
+    fn shutdown(&mut self, fut: Option<CoreBluetoothReplyStateShared>) {
+        trace!("BluetoothAdapter::shutdown");
+        self.stop_discovery();
+        // Dropping the peripherals closes their event channels, which ends the tasks listening to
+        // them.
+        for (_, p) in self.peripherals.drain() {
+            unsafe { self.manager.cancelPeripheralConnection(&p.peripheral) };
+        }
+        self.shut_down = true;
+        if let Some(fut) = fut {
+            fut.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+        }
+    }
 }
 
 /// Convert a `ScanFilter` to the appropriate `NSArray<CBUUID *> *` to use for discovery. If the
@@ -1351,8 +1466,33 @@ impl Drop for CoreBluetoothInternal {
     }
 }
 
+/// Returns the CoreBluetooth write type for `write_type`, or `None` for signed writes, which
+/// CoreBluetooth has no API for.
+fn cb_write_type(write_type: WriteType) -> Option<CBCharacteristicWriteType> {
+    match write_type {
+        WriteType::WithResponse => {
+            Some(CBCharacteristicWriteType::CBCharacteristicWriteWithResponse)
+        }
+        WriteType::WithoutResponse => {
+            Some(CBCharacteristicWriteType::CBCharacteristicWriteWithoutResponse)
+        }
+        WriteType::Signed => None,
+    }
+}
+
+fn qos_class(qos: QueueQos) -> ffi::qos_class_t {
+    match qos {
+        QueueQos::UserInteractive => ffi::QOS_CLASS_USER_INTERACTIVE,
+        QueueQos::UserInitiated => ffi::QOS_CLASS_USER_INITIATED,
+        QueueQos::Default => ffi::QOS_CLASS_DEFAULT,
+        QueueQos::Utility => ffi::QOS_CLASS_UTILITY,
+        QueueQos::Background => ffi::QOS_CLASS_BACKGROUND,
+    }
+}
+
 pub fn run_corebluetooth_thread(
     event_sender: Sender<CoreBluetoothEvent>,
+    options: ManagerOptions,
 ) -> Result<Sender<CoreBluetoothMessage>, Error> {
     let authorization = unsafe { CBManager::authorization_class() };
     if authorization != CBManagerAuthorization::AllowedAlways
@@ -1368,8 +1508,8 @@ pub fn run_corebluetooth_thread(
     thread::spawn(move || {
         let runtime = runtime::Builder::new_current_thread().build().unwrap();
         runtime.block_on(async move {
-            let mut cbi = CoreBluetoothInternal::new(receiver, event_sender);
-            loop {
+            let mut cbi = CoreBluetoothInternal::new(receiver, event_sender, options);
+            while !cbi.shut_down {
                 cbi.wait_for_message().await;
             }
         })
@@ -8,14 +8,101 @@
 use super::adapter::Adapter;
 use crate::{api, Result};
 use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Quality of service class of the dispatch queue CoreBluetooth delivers its callbacks on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QueueQos {
+    UserInteractive,
+    UserInitiated,
+    Default,
+    Utility,
+    Background,
+}
+
+/// Options passed to `CBCentralManager` when an adapter is created.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ManagerOptions {
+    pub show_power_alert: Option<bool>,
+    pub restore_identifier: Option<String>,
+    pub queue_qos: Option<QueueQos>,
+    pub wait_for_powered_on: Option<bool>,
+}
+
+/// Builds a [`Manager`] with CoreBluetooth specific options. Options which are not set keep the
+/// system defaults.
+///
+/// ```no_run
+/// # #[cfg(target_vendor = "apple")]
+/// # async fn example() -> btleplug::Result<()> {
+/// use btleplug::platform::{ManagerBuilder, QueueQos};
+///
+/// let manager = ManagerBuilder::new()
+///     .show_power_alert(false)
+///     .queue_qos(QueueQos::UserInitiated)
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ManagerBuilder {
+    options: ManagerOptions,
+}
+
+impl ManagerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the system should warn the user if Bluetooth is powered off when an adapter is
+    /// created (`CBCentralManagerOptionShowPowerAlertKey`).
+    pub fn show_power_alert(mut self, show: bool) -> Self {
+        self.options.show_power_alert = Some(show);
+        self
+    }
+
+    /// Opts into state preservation and restoration under the given identifier
+    /// (`CBCentralManagerOptionRestoreIdentifierKey`). Only has an effect on iOS.
+    pub fn restore_identifier(mut self, identifier: impl Into<String>) -> Self {
+        self.options.restore_identifier = Some(identifier.into());
+        self
+    }
+
+    /// The quality of service class of the queue CoreBluetooth callbacks are delivered on.
+    pub fn queue_qos(mut self, qos: QueueQos) -> Self {
+        self.options.queue_qos = Some(qos);
+        self
+    }
+
+    /// Whether scans started before the central is powered on are held back until it is, instead
+    /// of being dropped by CoreBluetooth. Right after an adapter is created the central is still
+    /// in the unknown state, so this defaults to `true`.
+    pub fn wait_for_powered_on(mut self, wait: bool) -> Self {
+        self.options.wait_for_powered_on = Some(wait);
+        self
+    }
+
+    pub async fn build(self) -> Result<Manager> {
+        Ok(Manager {
+            options: self.options,
+            adapter: Arc::new(Mutex::new(None)),
+        })
+    }
+}
 
 /// Implementation of [api::Manager](crate::api::Manager).
 #[derive(Clone, Debug)]
-pub struct Manager {}
+pub struct Manager {
+    options: ManagerOptions,
+    // Each adapter runs its own CBCentralManager on a thread, so hand out the same one every time.
+    adapter: Arc<Mutex<Option<Adapter>>>,
+}
 
 impl Manager {
     pub async fn new() -> Result<Self> {
-        Ok(Self {})
+        ManagerBuilder::new().build().await
     }
 }
 
@@ -24,8 +111,21 @@ impl api::Manager for Manager {
     type Adapter = Adapter;
 
     async fn adapters(&self) -> Result<Vec<Adapter>> {
-        Ok(vec![Adapter::new().await?])
+        let mut adapter = self.adapter.lock().await;
+        if adapter.is_none() {
+            *adapter = Some(Adapter::new(self.options.clone()).await?);
+        }
+        Ok(adapter.iter().cloned().collect())
         // TODO What do we do if there is no bluetooth adapter, like on an older
         // macbook pro? Will BluetoothAdapter::init() fail?
     }
+
+    /// Stops scanning, cancels the connections of all peripherals and ends the CoreBluetooth
+    /// thread. Calling `adapters` afterwards creates a new adapter.
+    async fn shutdown(&self) -> Result<()> {
+        match self.adapter.lock().await.take() {
+            Some(adapter) => adapter.shutdown().await,
+            None => Ok(()),
+        }
+    }
 }
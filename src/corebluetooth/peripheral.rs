@@ -10,14 +10,19 @@ use super::internal::{
 };
 use crate::{
     api::{
-        self, BDAddr, CentralEvent, CharPropFlags, Characteristic, Descriptor,
-        PeripheralProperties, Service, ValueNotification, WriteType,
+        self, AdvertisingInterval, BDAddr, CentralEvent, CharPropFlags, Characteristic, Descriptor,
+        Feature, NameSource, PeripheralProperties, Service, ValueNotification, WriteType,
     },
-    common::{adapter_manager::AdapterManager, util::notifications_stream_from_broadcast_receiver},
+    common::{
+        adapter_manager::AdapterManager, util::notifications_stream_from_broadcast_receiver,
+        value_cache::ValueCache,
+    },
+    gatt_log::{self, GattOperation},
+    interceptor::{GattInterceptor, GattRequest, InterceptorList, Interceptors},
     Error, Result,
 };
 use async_trait::async_trait;
-use futures::channel::mpsc::{Receiver, SendError, Sender};
+use futures::channel::mpsc::{SendError, Sender, UnboundedReceiver};
 use futures::sink::SinkExt;
 use futures::stream::{Stream, StreamExt};
 use log::*;
@@ -31,23 +36,103 @@ use std::{
     collections::{BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
+    str::FromStr,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 use tokio::sync::broadcast;
 use tokio::task;
 use uuid::Uuid;
 
-#[cfg_attr(
-    feature = "serde",
-    derive(Serialize, Deserialize),
-    serde(crate = "serde_cr")
-)]
+/// The identifier CoreBluetooth assigns to a peripheral (`CBPeripheral.identifier`).
+///
+/// The identifier is stable for a given peripheral on a given host, so it can be persisted to
+/// find the same device again in a later run. It is not the same across different hosts, and the
+/// OS may assign a new one after the device is unpaired or Bluetooth settings are reset.
+///
+/// The [`Display`], [`FromStr`] and (with the `serde` feature) serialized representation is the
+/// lowercase hyphenated UUID, e.g. `5f1c4a0e-9e2b-4b5b-8f6e-3c1d2a0b9e7f`, in every serde format.
+/// Parsing also accepts the other textual forms understood by [`Uuid::parse_str`], and
+/// deserializing the 16 bytes earlier versions used in formats which aren't human readable.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct PeripheralId(Uuid);
 
+impl PeripheralId {
+    /// Returns the underlying `CBPeripheral.identifier`.
+    pub fn uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
 impl Display for PeripheralId {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        Display::fmt(&self.0, f)
+        Display::fmt(&self.0.hyphenated(), f)
+    }
+}
+
+impl FromStr for PeripheralId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Uuid::parse_str(s).map(PeripheralId)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for PeripheralId {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for PeripheralId {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // Earlier versions serialized the identifier like a `Uuid`: as a string in human readable
+        // formats, but as 16 bytes in the others, which are still accepted.
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(PeripheralIdVisitor)
+        } else {
+            deserializer.deserialize_bytes(PeripheralIdVisitor)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+struct PeripheralIdVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for PeripheralIdVisitor {
+    type Value = PeripheralId;
+
+    fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "a UUID as a string, or as 16 bytes")
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        // No textual form of a UUID is 16 characters long.
+        if v.len() == 16 {
+            return Uuid::from_slice(v).map(PeripheralId).map_err(E::custom);
+        }
+        std::str::from_utf8(v)
+            .map_err(E::custom)
+            .and_then(|v| self.visit_str(v))
     }
 }
 
@@ -58,11 +143,15 @@ pub struct Peripheral {
 }
 
 struct Shared {
-    notifications_channel: broadcast::Sender<ValueNotification>,
+    notifications_channel: broadcast::Sender<Option<ValueNotification>>,
     manager: Weak<AdapterManager<Peripheral>>,
     uuid: Uuid,
     services: Mutex<BTreeSet<Service>>,
-    properties: Mutex<PeripheralProperties>,
+    // Behind an Arc so handing the properties out doesn't clone them. Updates copy them only if
+    // a previously returned Arc is still alive.
+    properties: Mutex<Arc<PeripheralProperties>>,
+    value_cache: ValueCache,
+    interceptors: Arc<InterceptorList>,
     message_sender: Sender<CoreBluetoothMessage>,
     // We're not actually holding a peripheral object here, that's held out in
     // the objc thread. We'll just communicate with it through our
@@ -72,7 +161,8 @@ struct Shared {
 impl Shared {
     fn emit_event(&self, event: CentralEvent) {
         if let Some(manager) = self.manager.upgrade() {
-            manager.emit(event);
+            let properties = self.properties.lock().unwrap().clone();
+            manager.emit_with_properties(event, Some(&properties));
         } else {
             trace!("Could not emit an event. AdapterManager has been dropped");
         }
@@ -83,24 +173,38 @@ impl Peripheral {
     // This calls tokio::task::spawn, so it must be called from the context of a Tokio Runtime.
     pub(crate) fn new(
         uuid: Uuid,
-        local_name: Option<String>,
+        name: Option<(String, NameSource)>,
         manager: Weak<AdapterManager<Self>>,
-        event_receiver: Receiver<PeripheralEventInternal>,
+        event_receiver: UnboundedReceiver<PeripheralEventInternal>,
         message_sender: Sender<CoreBluetoothMessage>,
     ) -> Self {
         // Since we're building the object, we have an active advertisement.
         // Build properties now.
-        let properties = Mutex::from(PeripheralProperties {
+        let properties = Mutex::from(Arc::new(PeripheralProperties {
             address: BDAddr::default(),
             address_type: None,
-            local_name,
+            local_name_source: name.as_ref().map(|(_, source)| *source),
+            local_name: name.map(|(name, _)| name),
             tx_power_level: None,
             rssi: None,
             manufacturer_data: HashMap::new(),
             service_data: HashMap::new(),
             services: Vec::new(),
             class: None,
-        });
+            appearance: None,
+            is_paired: None,
+            advertising_interval: None,
+            uri: None,
+            le_supported_features: None,
+            advertised_interval: None,
+            public_target_addresses: Vec::new(),
+            advertisement_data: None,
+            scan_response_data: None,
+            raw_data: None,
+            secondary_phy: None,
+            advertising_sid: None,
+            periodic_advertising_interval: None,
+        }));
         let (notifications_channel, _) = broadcast::channel(16);
 
         let shared = Arc::new(Shared {
@@ -108,6 +212,8 @@ impl Peripheral {
             manager,
             services: Mutex::new(BTreeSet::new()),
             notifications_channel,
+            value_cache: ValueCache::new(),
+            interceptors: Arc::new(InterceptorList::default()),
             uuid,
             message_sender,
         });
@@ -118,44 +224,78 @@ impl Peripheral {
 
             loop {
                 match event_receiver.next().await {
-                    Some(PeripheralEventInternal::Notification(uuid, data)) => {
-                        let notification = ValueNotification { uuid, value: data };
+                    Some(PeripheralEventInternal::Notification(service_uuid, uuid, data)) => {
+                        gatt_log::log_traffic(
+                            &PeripheralId(shared.uuid),
+                            uuid,
+                            GattOperation::Notification,
+                            &data,
+                        );
+                        // CoreBluetooth enables notifications if the characteristic supports both.
+                        let is_indication = shared
+                            .services
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .filter(|service| service.uuid == service_uuid)
+                            .flat_map(|service| service.characteristics.iter())
+                            .find(|characteristic| characteristic.uuid == uuid)
+                            .is_some_and(|characteristic| {
+                                characteristic.properties.subscribes_to_indications()
+                            });
+                        let notification = ValueNotification {
+                            uuid,
+                            service_uuid,
+                            handle: None,
+                            value: data,
+                            is_indication,
+                        };
+                        shared
+                            .value_cache
+                            .insert(&notification, notification.value.clone());
 
                         // Note: we ignore send errors here which may happen while there are no
                         // receivers...
-                        let _ = shared.notifications_channel.send(notification);
+                        let _ = shared.notifications_channel.send(Some(notification));
                     }
                     Some(PeripheralEventInternal::ManufacturerData(
                         manufacturer_id,
                         data,
                         rssi,
                     )) => {
-                        let mut properties = shared.properties.lock().unwrap();
-                        properties.rssi = Some(rssi);
-
-                        match properties.manufacturer_data.get(&manufacturer_id) {
-                            Some(old) => {
-                                if old.len() < data.len() {
+                        let manufacturer_data = {
+                            let mut guard = shared.properties.lock().unwrap();
+                            let properties = Arc::make_mut(&mut guard);
+                            properties.rssi = Some(rssi);
+
+                            match properties.manufacturer_data.get(&manufacturer_id) {
+                                Some(old) => {
+                                    if old.len() < data.len() {
+                                        properties
+                                            .manufacturer_data
+                                            .insert(manufacturer_id, data.clone());
+                                    }
+                                }
+                                _ => {
                                     properties
                                         .manufacturer_data
                                         .insert(manufacturer_id, data.clone());
                                 }
                             }
-                            _ => {
-                                properties
-                                    .manufacturer_data
-                                    .insert(manufacturer_id, data.clone());
-                            }
-                        }
+                            properties.manufacturer_data.clone()
+                        };
                         shared.emit_event(CentralEvent::ManufacturerDataAdvertisement {
                             id: shared.uuid.into(),
-                            manufacturer_data: properties.manufacturer_data.clone(),
+                            manufacturer_data,
                         });
                     }
                     Some(PeripheralEventInternal::ServiceData(service_data, rssi)) => {
-                        let mut properties = shared.properties.lock().unwrap();
-                        properties.rssi = Some(rssi);
-                        properties.service_data.extend(service_data.clone());
+                        {
+                            let mut guard = shared.properties.lock().unwrap();
+                            let properties = Arc::make_mut(&mut guard);
+                            properties.rssi = Some(rssi);
+                            properties.service_data.extend(service_data.clone());
+                        }
 
                         shared.emit_event(CentralEvent::ServiceDataAdvertisement {
                             id: shared.uuid.into(),
@@ -163,16 +303,26 @@ impl Peripheral {
                         });
                     }
                     Some(PeripheralEventInternal::Services(services, rssi)) => {
-                        let mut properties = shared.properties.lock().unwrap();
-                        properties.rssi = Some(rssi);
-                        properties.services = services.clone();
+                        {
+                            let mut guard = shared.properties.lock().unwrap();
+                            let properties = Arc::make_mut(&mut guard);
+                            properties.rssi = Some(rssi);
+                            properties.services = services.clone();
+                        }
 
                         shared.emit_event(CentralEvent::ServicesAdvertisement {
                             id: shared.uuid.into(),
                             services,
                         });
                     }
-                    Some(PeripheralEventInternal::Disconnected) => (),
+                    Some(PeripheralEventInternal::ServicesChanged(services)) => {
+                        *shared.services.lock().unwrap() = services;
+                        shared.emit_event(CentralEvent::ServicesChanged(shared.uuid.into()));
+                    }
+                    Some(PeripheralEventInternal::Disconnected) => {
+                        // End all notification streams.
+                        let _ = shared.notifications_channel.send(None);
+                    }
                     None => {
                         info!("Event receiver died, breaking out of corebluetooth device loop.");
                         break;
@@ -183,8 +333,167 @@ impl Peripheral {
         Self { shared: shared }
     }
 
-    pub(super) fn update_name(&self, name: &str) {
-        self.shared.properties.lock().unwrap().local_name = Some(name.to_string());
+    fn interceptors(&self) -> Interceptors {
+        Interceptors::new(
+            self.shared
+                .manager
+                .upgrade()
+                .map(|manager| manager.interceptors().clone()),
+            self.shared.interceptors.clone(),
+        )
+    }
+
+    async fn write_characteristic(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        mut write_type: WriteType,
+    ) -> Result<()> {
+        if write_type == WriteType::Signed {
+            return Err(signed_write_not_supported());
+        }
+        let fut = CoreBluetoothReplyFuture::default();
+        // If we get WriteWithoutResponse for a characteristic that only
+        // supports WriteWithResponse, slam the type to WriteWithResponse.
+        // Otherwise we won't handle the future correctly.
+        if write_type == WriteType::WithoutResponse
+            && !characteristic
+                .properties
+                .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+        {
+            write_type = WriteType::WithResponse
+        }
+        gatt_log::log_traffic(
+            &PeripheralId(self.shared.uuid),
+            characteristic.uuid,
+            write_type.into(),
+            data,
+        );
+        self.shared
+            .message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::WriteValue {
+                peripheral_uuid: self.shared.uuid,
+                service_uuid: characteristic.service_uuid,
+                characteristic_uuid: characteristic.uuid,
+                data: Vec::from(data),
+                write_type,
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => {}
+            CoreBluetoothReply::NotFound => return Err(Error::DeviceNotFound),
+            CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+        Ok(())
+    }
+
+    async fn read_characteristic(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.shared
+            .message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::ReadValue {
+                peripheral_uuid: self.shared.uuid,
+                service_uuid: characteristic.service_uuid,
+                characteristic_uuid: characteristic.uuid,
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::ReadResult(chars) => {
+                gatt_log::log_traffic(
+                    &PeripheralId(self.shared.uuid),
+                    characteristic.uuid,
+                    GattOperation::Read,
+                    &chars,
+                );
+                self.shared
+                    .value_cache
+                    .insert(characteristic, chars.clone());
+                Ok(chars)
+            }
+            CoreBluetoothReply::NotFound => Err(Error::DeviceNotFound),
+            CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
+            _ => {
+                panic!("Shouldn't get anything but read result!");
+            }
+        }
+    }
+
+    async fn write_descriptor_value(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let fut = CoreBluetoothReplyFuture::default();
+        gatt_log::log_traffic(
+            &PeripheralId(self.shared.uuid),
+            descriptor.uuid,
+            GattOperation::WriteDescriptor,
+            data,
+        );
+        self.shared
+            .message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::WriteDescriptorValue {
+                peripheral_uuid: self.shared.uuid,
+                service_uuid: descriptor.service_uuid,
+                characteristic_uuid: descriptor.characteristic_uuid,
+                descriptor_uuid: descriptor.uuid,
+                data: Vec::from(data),
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => {}
+            CoreBluetoothReply::NotFound => return Err(Error::DeviceNotFound),
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+        Ok(())
+    }
+
+    async fn read_descriptor_value(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.shared
+            .message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::ReadDescriptorValue {
+                peripheral_uuid: self.shared.uuid,
+                service_uuid: descriptor.service_uuid,
+                characteristic_uuid: descriptor.characteristic_uuid,
+                descriptor_uuid: descriptor.uuid,
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::ReadResult(chars) => {
+                gatt_log::log_traffic(
+                    &PeripheralId(self.shared.uuid),
+                    descriptor.uuid,
+                    GattOperation::ReadDescriptor,
+                    &chars,
+                );
+                Ok(chars)
+            }
+            CoreBluetoothReply::NotFound => Err(Error::DeviceNotFound),
+            _ => {
+                panic!("Shouldn't get anything but read result!");
+            }
+        }
+    }
+
+    pub(super) fn update_name(&self, name: String, source: NameSource) {
+        let mut properties = self.shared.properties.lock().unwrap();
+        let properties = Arc::make_mut(&mut properties);
+        properties.local_name = Some(name);
+        properties.local_name_source = Some(source);
+    }
+
+    pub(super) fn update_advertising_interval(
+        &self,
+        advertising_interval: Option<AdvertisingInterval>,
+    ) {
+        Arc::make_mut(&mut self.shared.properties.lock().unwrap()).advertising_interval =
+            advertising_interval;
     }
 }
 
@@ -209,6 +518,13 @@ impl Debug for Peripheral {
     }
 }
 
+/// CoreBluetooth can't sign writes.
+fn signed_write_not_supported() -> Error {
+    Error::NotSupported {
+        feature: Feature::SignedWrite,
+    }
+}
+
 #[async_trait]
 impl api::Peripheral for Peripheral {
     fn id(&self) -> PeripheralId {
@@ -219,7 +535,7 @@ impl api::Peripheral for Peripheral {
         BDAddr::default()
     }
 
-    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
         Ok(Some(
             self.shared
                 .properties
@@ -229,6 +545,10 @@ impl api::Peripheral for Peripheral {
         ))
     }
 
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        Some(self.shared.properties.lock().unwrap().clone())
+    }
+
     fn services(&self) -> BTreeSet<Service> {
         self.shared.services.lock().unwrap().clone()
     }
@@ -273,8 +593,31 @@ impl api::Peripheral for Peripheral {
         }
     }
 
+    async fn max_write_len(&self, write_type: WriteType) -> Result<usize> {
+        if write_type == WriteType::Signed {
+            return Err(signed_write_not_supported());
+        }
+        let fut = CoreBluetoothReplyFuture::default();
+        self.shared
+            .message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::GetMaxWriteLen {
+                peripheral_uuid: self.shared.uuid,
+                write_type,
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::MaxWriteLen(max_len) => Ok(max_len),
+            CoreBluetoothReply::NotFound => Err(Error::DeviceNotFound),
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+    }
+
     async fn connect(&self) -> Result<()> {
         let fut = CoreBluetoothReplyFuture::default();
+        self.shared
+            .emit_event(CentralEvent::DeviceConnecting(self.shared.uuid.into()));
         self.shared
             .message_sender
             .to_owned()
@@ -290,15 +633,41 @@ impl api::Peripheral for Peripheral {
                     .emit_event(CentralEvent::DeviceConnected(self.shared.uuid.into()));
             }
             CoreBluetoothReply::NotFound => return Err(Error::DeviceNotFound),
-            CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
+            CoreBluetoothReply::Err(msg) => {
+                self.shared
+                    .emit_event(CentralEvent::DeviceConnectionFailed {
+                        id: self.shared.uuid.into(),
+                        reason: msg.clone(),
+                    });
+                return Err(Error::ConnectionFailed(msg));
+            }
             _ => panic!("Shouldn't get anything but connected or err!"),
         }
         trace!("Device connected!");
         Ok(())
     }
 
+    async fn connect_with(&self, options: api::ConnectOptions) -> Result<()> {
+        // CoreBluetooth only connects to peripherals over LE, and leaves the PHY to the system.
+        if options.transport == Some(api::ConnectTransport::BrEdr) {
+            return Err(Error::NotSupported {
+                feature: Feature::ConnectTransport,
+            });
+        }
+        if options.phy.is_some() {
+            return Err(Error::NotSupported {
+                feature: Feature::ConnectPhy,
+            });
+        }
+        // Connection requests never time out, so CoreBluetooth always waits for the peripheral
+        // to come into range, whatever `auto_connect` says.
+        crate::common::connect::with_timeout(self, options.timeout, self.connect()).await
+    }
+
     async fn disconnect(&self) -> Result<()> {
         let fut = CoreBluetoothReplyFuture::default();
+        self.shared
+            .emit_event(CentralEvent::DeviceDisconnecting(self.shared.uuid.into()));
         self.shared
             .message_sender
             .to_owned()
@@ -328,60 +697,41 @@ impl api::Peripheral for Peripheral {
         &self,
         characteristic: &Characteristic,
         data: &[u8],
-        mut write_type: WriteType,
+        write_type: WriteType,
     ) -> Result<()> {
-        let fut = CoreBluetoothReplyFuture::default();
-        // If we get WriteWithoutResponse for a characteristic that only
-        // supports WriteWithResponse, slam the type to WriteWithResponse.
-        // Otherwise we won't handle the future correctly.
-        if write_type == WriteType::WithoutResponse
-            && !characteristic
-                .properties
-                .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
-        {
-            write_type = WriteType::WithResponse
-        }
-        self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::WriteValue {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: characteristic.service_uuid,
-                characteristic_uuid: characteristic.uuid,
-                data: Vec::from(data),
-                write_type,
-                future: fut.get_state_clone(),
+        let id = PeripheralId(self.shared.uuid);
+        let request = GattRequest::characteristic(&id, characteristic, write_type.into());
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                self.write_characteristic(characteristic, &data, write_type)
+                    .await
             })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::Ok => {}
-            CoreBluetoothReply::NotFound => return Err(Error::DeviceNotFound),
-            CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
-            reply => panic!("Unexpected reply: {:?}", reply),
-        }
-        Ok(())
+            .await
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        let fut = CoreBluetoothReplyFuture::default();
-        self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::ReadValue {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: characteristic.service_uuid,
-                characteristic_uuid: characteristic.uuid,
-                future: fut.get_state_clone(),
-            })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::ReadResult(chars) => Ok(chars),
-            CoreBluetoothReply::NotFound => Err(Error::DeviceNotFound),
-            CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
-            _ => {
-                panic!("Shouldn't get anything but read result!");
-            }
+        let id = PeripheralId(self.shared.uuid);
+        let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+        self.interceptors()
+            .read(&request, self.read_characteristic(characteristic))
+            .await
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        if let Some(value) = self.shared.value_cache.get(characteristic, max_age) {
+            // The cache holds values as received, so they go through the interceptors again.
+            let id = PeripheralId(self.shared.uuid);
+            let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+            return self
+                .interceptors()
+                .read(&request, async { Ok(value) })
+                .await;
         }
+        self.read(characteristic).await
     }
 
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
@@ -428,51 +778,37 @@ impl api::Peripheral for Peripheral {
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
         let receiver = self.shared.notifications_channel.subscribe();
-        Ok(notifications_stream_from_broadcast_receiver(receiver))
+        Ok(self.interceptors().notifications(
+            PeripheralId(self.shared.uuid),
+            notifications_stream_from_broadcast_receiver(receiver),
+        ))
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
-        let fut = CoreBluetoothReplyFuture::default();
-        self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::WriteDescriptorValue {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: descriptor.service_uuid,
-                characteristic_uuid: descriptor.characteristic_uuid,
-                descriptor_uuid: descriptor.uuid,
-                data: Vec::from(data),
-                future: fut.get_state_clone(),
+        let id = PeripheralId(self.shared.uuid);
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::WriteDescriptor);
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                self.write_descriptor_value(descriptor, &data).await
             })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::Ok => {}
-            CoreBluetoothReply::NotFound => return Err(Error::DeviceNotFound),
-            reply => panic!("Unexpected reply: {:?}", reply),
-        }
-        Ok(())
+            .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
-        let fut = CoreBluetoothReplyFuture::default();
-        self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::ReadDescriptorValue {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: descriptor.service_uuid,
-                characteristic_uuid: descriptor.characteristic_uuid,
-                descriptor_uuid: descriptor.uuid,
-                future: fut.get_state_clone(),
-            })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::ReadResult(chars) => Ok(chars),
-            CoreBluetoothReply::NotFound => Err(Error::DeviceNotFound),
-            _ => {
-                panic!("Shouldn't get anything but read result!");
-            }
-        }
+        let id = PeripheralId(self.shared.uuid);
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::ReadDescriptor);
+        self.interceptors()
+            .read(&request, self.read_descriptor_value(descriptor))
+            .await
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.shared.interceptors.add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self.shared.interceptors.remove(interceptor))
     }
 }
 
@@ -482,8 +818,46 @@ impl From<Uuid> for PeripheralId {
     }
 }
 
+impl From<PeripheralId> for Uuid {
+    fn from(id: PeripheralId) -> Self {
+        id.0
+    }
+}
+
 impl From<SendError> for Error {
     fn from(_: SendError) -> Self {
         Error::Other("Channel closed".to_string().into())
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use serde::de::value::{BytesDeserializer, Error as ValueError};
+
+    const ID: &str = "5f1c4a0e-9e2b-4b5b-8f6e-3c1d2a0b9e7f";
+
+    #[test]
+    fn peripheral_id_serializes_as_string() {
+        let id: PeripheralId = ID.parse().unwrap();
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, format!("\"{}\"", ID));
+        assert_eq!(serde_json::from_str::<PeripheralId>(&json).unwrap(), id);
+        let bytes = BytesDeserializer::<ValueError>::new(ID.as_bytes());
+        assert_eq!(PeripheralId::deserialize(bytes).unwrap(), id);
+    }
+
+    #[test]
+    fn peripheral_id_deserializes_old_format() {
+        let uuid = Uuid::parse_str(ID).unwrap();
+        // Human readable formats had the UUID's string form.
+        let json = serde_json::to_string(&uuid).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PeripheralId>(&json).unwrap().uuid(),
+            uuid
+        );
+        // The others its bytes.
+        let bytes = BytesDeserializer::<ValueError>::new(uuid.as_bytes());
+        assert_eq!(PeripheralId::deserialize(bytes).unwrap().uuid(), uuid);
+    }
+}
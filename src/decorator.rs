@@ -0,0 +1,875 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `decorator` module contains wrappers which implement [`Peripheral`] over another
+//! peripheral, adding behaviour around its operations:
+//!
+//! - [`LoggingPeripheral`] logs every operation with how long it took and how it ended.
+//! - [`RetryingPeripheral`] retries operations which failed with a transient error.
+//! - [`ThrottlingPeripheral`] runs GATT operations one at a time, with a minimum delay between
+//!   them, for devices which can't keep up with back to back requests.
+//!
+//! As each of them is a `Peripheral` they can be stacked, the outermost wrapper seeing operations
+//! first:
+//!
+//! ```no_run
+//! # use btleplug::api::{Peripheral as _, RetryPolicy};
+//! # use btleplug::platform::Peripheral;
+//! # use std::time::Duration;
+//! use btleplug::decorator::{LoggingPeripheral, RetryingPeripheral, ThrottlingPeripheral};
+//!
+//! # async fn example(peripheral: Peripheral) -> btleplug::Result<()> {
+//! // Each attempt is logged and throttled.
+//! let peripheral = RetryingPeripheral::new(
+//!     LoggingPeripheral::new(ThrottlingPeripheral::new(peripheral, Duration::from_millis(50))),
+//!     RetryPolicy::default(),
+//! );
+//! peripheral.connect().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The wrappers only use the public API of btleplug, so they can also serve as a starting point
+//! for implementing `Peripheral` outside of it. Like
+//! [`CapturingPeripheral`](crate::capture::CapturingPeripheral) they forward every method a backend
+//! may implement itself: the required ones, the optional ones which fail with
+//! [`NotSupported`](crate::Error::NotSupported) by default, and those some backends override, like
+//! [`characteristics`](Peripheral::characteristics) and
+//! [`max_write_len`](Peripheral::max_write_len). The provided methods which are only built on top
+//! of others, like [`poll_characteristic`](Peripheral::poll_characteristic), are left to their
+//! default implementation, so they go through the wrapper too. The exception is
+//! [`write_with_retry`](Peripheral::write_with_retry) on a [`RetryingPeripheral`], which is
+//! forwarded so the writes aren't retried twice over.
+
+use crate::api::{
+    BDAddr, Characteristic, ConnectOptions, ConnectionInfo, ConnectionParams, ConnectionPhy,
+    Descriptor, PairingResponse, Peripheral, PeripheralProperties, Phy, PhyCoding, ReliableWrite,
+    RetryPolicy, Service, ValueNotification, WriteType,
+};
+use crate::gatt_log::GattOperation;
+use crate::interceptor::GattInterceptor;
+use crate::platform::PeripheralId;
+use crate::Result;
+use async_trait::async_trait;
+use futures::stream::{Stream, StreamExt};
+use log::Level;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// A [`Peripheral`] wrapper which logs every operation, with how long it took and its outcome.
+/// Payloads aren't logged, see the [`gatt_log`](crate::gatt_log) module for that.
+#[derive(Clone, Debug)]
+pub struct LoggingPeripheral<P> {
+    peripheral: P,
+    level: Level,
+}
+
+impl<P: Peripheral> LoggingPeripheral<P> {
+    /// Wraps `peripheral`, logging at debug level.
+    pub fn new(peripheral: P) -> Self {
+        LoggingPeripheral {
+            peripheral,
+            level: Level::Debug,
+        }
+    }
+
+    /// Sets the level operations are logged at.
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Returns the wrapped peripheral.
+    pub fn into_inner(self) -> P {
+        self.peripheral
+    }
+
+    async fn logged<T, F>(&self, operation: impl Display + Send, f: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        if !log::log_enabled!(self.level) {
+            return f.await;
+        }
+        let started = Instant::now();
+        let result = f.await;
+        let elapsed = started.elapsed();
+        let id = self.peripheral.id();
+        match &result {
+            Ok(_) => log::log!(self.level, "{} {}: done in {:?}", id, operation, elapsed),
+            Err(e) => log::log!(
+                self.level,
+                "{} {}: failed after {:?}: {}",
+                id,
+                operation,
+                elapsed,
+                e
+            ),
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<P: Peripheral> Peripheral for LoggingPeripheral<P> {
+    fn id(&self) -> PeripheralId {
+        self.peripheral.id()
+    }
+
+    fn address(&self) -> BDAddr {
+        self.peripheral.address()
+    }
+
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+        self.peripheral.properties().await
+    }
+
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        self.peripheral.cached_properties()
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.peripheral.services()
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.peripheral.characteristics()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        self.peripheral.is_connected().await
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        self.peripheral.is_paired().await
+    }
+
+    async fn respond_to_pairing(&self, response: PairingResponse) -> Result<()> {
+        self.peripheral.respond_to_pairing(response).await
+    }
+
+    async fn mtu(&self, characteristics: Option<&[Characteristic]>) -> Result<u16> {
+        self.peripheral.mtu(characteristics).await
+    }
+
+    async fn max_write_len(&self, write_type: WriteType) -> Result<usize> {
+        self.peripheral.max_write_len(write_type).await
+    }
+
+    async fn set_long_value_chunking(&self, enabled: bool) -> Result<()> {
+        self.peripheral.set_long_value_chunking(enabled).await
+    }
+
+    async fn connection_info(&self) -> Result<Option<ConnectionInfo>> {
+        self.peripheral.connection_info().await
+    }
+
+    async fn update_connection_parameters(&self, params: ConnectionParams) -> Result<()> {
+        self.peripheral.update_connection_parameters(params).await
+    }
+
+    async fn set_preferred_phy(&self, tx: &[Phy], rx: &[Phy], coding: PhyCoding) -> Result<()> {
+        self.peripheral.set_preferred_phy(tx, rx, coding).await
+    }
+
+    async fn read_phy(&self) -> Result<ConnectionPhy> {
+        self.peripheral.read_phy().await
+    }
+
+    async fn begin_reliable_write(&self) -> Result<Box<dyn ReliableWrite>> {
+        self.peripheral.begin_reliable_write().await
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.logged("connect", self.peripheral.connect()).await
+    }
+
+    async fn connect_with(&self, options: ConnectOptions) -> Result<()> {
+        self.logged("connect", self.peripheral.connect_with(options))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.logged("disconnect", self.peripheral.disconnect())
+            .await
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.logged("discover services", self.peripheral.discover_services())
+            .await
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.logged(
+            format!(
+                "{} {} ({} bytes)",
+                GattOperation::from(write_type),
+                characteristic.uuid,
+                data.len()
+            ),
+            self.peripheral.write(characteristic, data, write_type),
+        )
+        .await
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.logged(
+            format!("{} {}", GattOperation::Read, characteristic.uuid),
+            self.peripheral.read(characteristic),
+        )
+        .await
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        self.logged(
+            format!("cached {} {}", GattOperation::Read, characteristic.uuid),
+            self.peripheral.read_cached(characteristic, max_age),
+        )
+        .await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        self.logged("read device name", self.peripheral.read_device_name())
+            .await
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.logged(
+            format!("subscribe {}", characteristic.uuid),
+            self.peripheral.subscribe(characteristic),
+        )
+        .await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.logged(
+            format!("unsubscribe {}", characteristic.uuid),
+            self.peripheral.unsubscribe(characteristic),
+        )
+        .await
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        let id = self.peripheral.id();
+        let level = self.level;
+        let notifications = self.peripheral.notifications().await?;
+        Ok(Box::pin(notifications.inspect(move |notification| {
+            log::log!(
+                level,
+                "{} {} {} ({} bytes)",
+                id,
+                GattOperation::Notification,
+                notification.uuid,
+                notification.value.len()
+            );
+        })))
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.logged(
+            format!(
+                "{} {} ({} bytes)",
+                GattOperation::WriteDescriptor,
+                descriptor.uuid,
+                data.len()
+            ),
+            self.peripheral.write_descriptor(descriptor, data),
+        )
+        .await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.logged(
+            format!("{} {}", GattOperation::ReadDescriptor, descriptor.uuid),
+            self.peripheral.read_descriptor(descriptor),
+        )
+        .await
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.peripheral.add_interceptor(interceptor)
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        self.peripheral.remove_interceptor(interceptor)
+    }
+}
+
+/// A [`Peripheral`] wrapper which retries connecting, service discovery and GATT operations after
+/// [transient](crate::Error::is_transient) errors, according to a [`RetryPolicy`]. Like
+/// [`write_with_retry`](Peripheral::write_with_retry), GATT operations aren't retried once the
+/// device disconnected, and fail with [`Error::NotConnected`](crate::Error::NotConnected) instead.
+///
+/// Writes are retried too, so a write whose response got lost may reach the device twice. Wrap
+/// only peripherals whose writes can safely be repeated.
+///
+/// [`write_with_retry`](Peripheral::write_with_retry) retries with the policy it is given
+/// instead of this one, rather than retrying each of its attempts again.
+#[derive(Clone, Debug)]
+pub struct RetryingPeripheral<P> {
+    peripheral: P,
+    policy: RetryPolicy,
+}
+
+impl<P: Peripheral> RetryingPeripheral<P> {
+    pub fn new(peripheral: P, policy: RetryPolicy) -> Self {
+        RetryingPeripheral { peripheral, policy }
+    }
+
+    /// Returns the wrapped peripheral.
+    pub fn into_inner(self) -> P {
+        self.peripheral
+    }
+
+    /// Runs the operation returned by `f` with the retry policy. If `connected` is set the device
+    /// must still be connected for the operation to be retried.
+    async fn retry<T, F, Fut>(
+        &self,
+        operation: impl Display + Send,
+        connected: bool,
+        f: F,
+    ) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.policy
+            .retry(operation, f, || async move {
+                if connected {
+                    self.peripheral.is_connected().await
+                } else {
+                    Ok(true)
+                }
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl<P: Peripheral> Peripheral for RetryingPeripheral<P> {
+    fn id(&self) -> PeripheralId {
+        self.peripheral.id()
+    }
+
+    fn address(&self) -> BDAddr {
+        self.peripheral.address()
+    }
+
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+        self.peripheral.properties().await
+    }
+
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        self.peripheral.cached_properties()
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.peripheral.services()
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.peripheral.characteristics()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        self.peripheral.is_connected().await
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        self.peripheral.is_paired().await
+    }
+
+    async fn respond_to_pairing(&self, response: PairingResponse) -> Result<()> {
+        self.peripheral.respond_to_pairing(response).await
+    }
+
+    async fn mtu(&self, characteristics: Option<&[Characteristic]>) -> Result<u16> {
+        self.peripheral.mtu(characteristics).await
+    }
+
+    async fn max_write_len(&self, write_type: WriteType) -> Result<usize> {
+        self.peripheral.max_write_len(write_type).await
+    }
+
+    async fn set_long_value_chunking(&self, enabled: bool) -> Result<()> {
+        self.peripheral.set_long_value_chunking(enabled).await
+    }
+
+    async fn connection_info(&self) -> Result<Option<ConnectionInfo>> {
+        self.peripheral.connection_info().await
+    }
+
+    async fn update_connection_parameters(&self, params: ConnectionParams) -> Result<()> {
+        self.peripheral.update_connection_parameters(params).await
+    }
+
+    async fn set_preferred_phy(&self, tx: &[Phy], rx: &[Phy], coding: PhyCoding) -> Result<()> {
+        self.peripheral.set_preferred_phy(tx, rx, coding).await
+    }
+
+    async fn read_phy(&self) -> Result<ConnectionPhy> {
+        self.peripheral.read_phy().await
+    }
+
+    async fn begin_reliable_write(&self) -> Result<Box<dyn ReliableWrite>> {
+        self.peripheral.begin_reliable_write().await
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.retry("Connect", false, || self.peripheral.connect())
+            .await
+    }
+
+    async fn connect_with(&self, options: ConnectOptions) -> Result<()> {
+        self.retry("Connect", false, || {
+            self.peripheral.connect_with(options.clone())
+        })
+        .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.peripheral.disconnect().await
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.retry("Service discovery", true, || {
+            self.peripheral.discover_services()
+        })
+        .await
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.retry(format!("Write to {}", characteristic.uuid), true, || {
+            self.peripheral.write(characteristic, data, write_type)
+        })
+        .await
+    }
+
+    async fn write_with_retry(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+        policy: &RetryPolicy,
+    ) -> Result<()> {
+        self.peripheral
+            .write_with_retry(characteristic, data, write_type, policy)
+            .await
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.retry(format!("Read of {}", characteristic.uuid), true, || {
+            self.peripheral.read(characteristic)
+        })
+        .await
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        self.retry(format!("Read of {}", characteristic.uuid), true, || {
+            self.peripheral.read_cached(characteristic, max_age)
+        })
+        .await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        self.retry("Read of the device name", true, || {
+            self.peripheral.read_device_name()
+        })
+        .await
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.retry(
+            format!("Subscribing to {}", characteristic.uuid),
+            true,
+            || self.peripheral.subscribe(characteristic),
+        )
+        .await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.retry(
+            format!("Unsubscribing from {}", characteristic.uuid),
+            true,
+            || self.peripheral.unsubscribe(characteristic),
+        )
+        .await
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        self.peripheral.notifications().await
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.retry(
+            format!("Write to descriptor {}", descriptor.uuid),
+            true,
+            || self.peripheral.write_descriptor(descriptor, data),
+        )
+        .await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.retry(
+            format!("Read of descriptor {}", descriptor.uuid),
+            true,
+            || self.peripheral.read_descriptor(descriptor),
+        )
+        .await
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.peripheral.add_interceptor(interceptor)
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        self.peripheral.remove_interceptor(interceptor)
+    }
+}
+
+/// A [`Peripheral`] wrapper which runs GATT operations one at a time, waiting at least a given
+/// interval between the end of one operation and the start of the next. Clones share the
+/// throttle, so it holds for all of them.
+///
+/// Reads served from the cache by [`read_cached`](Peripheral::read_cached) are throttled too, as
+/// whether they go to the device isn't known beforehand.
+#[derive(Clone, Debug)]
+pub struct ThrottlingPeripheral<P> {
+    peripheral: P,
+    interval: Duration,
+    // The end of the last operation, locked while an operation runs.
+    last: Arc<Mutex<Option<tokio::time::Instant>>>,
+}
+
+impl<P: Peripheral> ThrottlingPeripheral<P> {
+    pub fn new(peripheral: P, interval: Duration) -> Self {
+        ThrottlingPeripheral {
+            peripheral,
+            interval,
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the wrapped peripheral.
+    pub fn into_inner(self) -> P {
+        self.peripheral
+    }
+
+    async fn throttled<T>(&self, f: impl Future<Output = Result<T>>) -> Result<T> {
+        let mut last = self.last.lock().await;
+        if let Some(last) = *last {
+            tokio::time::sleep_until(last + self.interval).await;
+        }
+        let result = f.await;
+        *last = Some(tokio::time::Instant::now());
+        result
+    }
+}
+
+#[async_trait]
+impl<P: Peripheral> Peripheral for ThrottlingPeripheral<P> {
+    fn id(&self) -> PeripheralId {
+        self.peripheral.id()
+    }
+
+    fn address(&self) -> BDAddr {
+        self.peripheral.address()
+    }
+
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+        self.peripheral.properties().await
+    }
+
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        self.peripheral.cached_properties()
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.peripheral.services()
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.peripheral.characteristics()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        self.peripheral.is_connected().await
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        self.peripheral.is_paired().await
+    }
+
+    async fn respond_to_pairing(&self, response: PairingResponse) -> Result<()> {
+        self.peripheral.respond_to_pairing(response).await
+    }
+
+    async fn mtu(&self, characteristics: Option<&[Characteristic]>) -> Result<u16> {
+        self.peripheral.mtu(characteristics).await
+    }
+
+    async fn max_write_len(&self, write_type: WriteType) -> Result<usize> {
+        self.peripheral.max_write_len(write_type).await
+    }
+
+    async fn set_long_value_chunking(&self, enabled: bool) -> Result<()> {
+        self.peripheral.set_long_value_chunking(enabled).await
+    }
+
+    async fn connection_info(&self) -> Result<Option<ConnectionInfo>> {
+        self.peripheral.connection_info().await
+    }
+
+    async fn update_connection_parameters(&self, params: ConnectionParams) -> Result<()> {
+        self.peripheral.update_connection_parameters(params).await
+    }
+
+    async fn set_preferred_phy(&self, tx: &[Phy], rx: &[Phy], coding: PhyCoding) -> Result<()> {
+        self.peripheral.set_preferred_phy(tx, rx, coding).await
+    }
+
+    async fn read_phy(&self) -> Result<ConnectionPhy> {
+        self.peripheral.read_phy().await
+    }
+
+    async fn begin_reliable_write(&self) -> Result<Box<dyn ReliableWrite>> {
+        self.peripheral.begin_reliable_write().await
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.peripheral.connect().await
+    }
+
+    async fn connect_with(&self, options: ConnectOptions) -> Result<()> {
+        self.peripheral.connect_with(options).await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.peripheral.disconnect().await
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.throttled(self.peripheral.discover_services()).await
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.throttled(self.peripheral.write(characteristic, data, write_type))
+            .await
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.throttled(self.peripheral.read(characteristic)).await
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        self.throttled(self.peripheral.read_cached(characteristic, max_age))
+            .await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        self.throttled(self.peripheral.read_device_name()).await
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.throttled(self.peripheral.subscribe(characteristic))
+            .await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.throttled(self.peripheral.unsubscribe(characteristic))
+            .await
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        self.peripheral.notifications().await
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.throttled(self.peripheral.write_descriptor(descriptor, data))
+            .await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.throttled(self.peripheral.read_descriptor(descriptor))
+            .await
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.peripheral.add_interceptor(interceptor)
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        self.peripheral.remove_interceptor(interceptor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::CharPropFlags;
+    use crate::common::fake::FakePeripheral;
+    use crate::Error;
+    use uuid::Uuid;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn characteristic() -> Characteristic {
+        Characteristic {
+            uuid: Uuid::from_u128(0x2a19),
+            service_uuid: Uuid::from_u128(0x180f),
+            properties: CharPropFlags::READ | CharPropFlags::WRITE,
+            descriptors: BTreeSet::new(),
+            handle: None,
+        }
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(150),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_transient_errors_with_backoff() {
+        let fake = FakePeripheral::new(BDAddr::default()).with_value(&[1]);
+        fake.fail_next([Error::TimedOut(TIMEOUT), Error::TimedOut(TIMEOUT)]);
+        let peripheral = RetryingPeripheral::new(fake.clone(), policy(3));
+        assert_eq!(peripheral.read(&characteristic()).await.unwrap(), [1]);
+
+        let calls = fake.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(
+            calls[1].started - calls[0].ended,
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            calls[2].started - calls[1].ended,
+            Duration::from_millis(150)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_retrying_after_max_attempts() {
+        let fake = FakePeripheral::new(BDAddr::default());
+        fake.fail_next((0..5).map(|_| Error::TimedOut(TIMEOUT)));
+        let peripheral = RetryingPeripheral::new(fake.clone(), policy(3));
+        assert!(matches!(
+            peripheral.read(&characteristic()).await,
+            Err(Error::TimedOut(_))
+        ));
+        assert_eq!(fake.calls().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_permanent_errors_or_after_disconnection() {
+        let fake = FakePeripheral::new(BDAddr::default());
+        let peripheral = RetryingPeripheral::new(fake.clone(), policy(3));
+        fake.fail_next([Error::NotSupported {
+            feature: crate::api::Feature::Mtu,
+        }]);
+        assert!(peripheral.read(&characteristic()).await.is_err());
+        assert_eq!(fake.calls().len(), 1);
+
+        fake.fail_next([Error::TimedOut(TIMEOUT)]);
+        fake.set_connected(false);
+        assert!(matches!(
+            peripheral.read(&characteristic()).await,
+            Err(Error::NotConnected)
+        ));
+        assert_eq!(fake.calls().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn write_with_retry_is_not_retried_twice() {
+        let fake = FakePeripheral::new(BDAddr::default());
+        fake.fail_next((0..10).map(|_| Error::TimedOut(TIMEOUT)));
+        let peripheral = RetryingPeripheral::new(fake.clone(), policy(3));
+        assert!(peripheral
+            .write_with_retry(&characteristic(), &[1], WriteType::WithResponse, &policy(2))
+            .await
+            .is_err());
+        assert_eq!(fake.calls().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttles_operations() {
+        let interval = Duration::from_millis(50);
+        let fake = FakePeripheral::new(BDAddr::default()).with_duration(Duration::from_millis(10));
+        let peripheral = ThrottlingPeripheral::new(fake.clone(), interval);
+        let started = tokio::time::Instant::now();
+        let characteristic = characteristic();
+        let clone = peripheral.clone();
+        let (read, write, clone_read) = tokio::join!(
+            peripheral.read(&characteristic),
+            peripheral.write(&characteristic, &[1], WriteType::WithResponse),
+            clone.read(&characteristic),
+        );
+        read.unwrap();
+        write.unwrap();
+        clone_read.unwrap();
+
+        let calls = fake.calls();
+        assert_eq!(calls.len(), 3);
+        // The first operation isn't delayed, the others start an interval after the previous one
+        // ended.
+        assert_eq!(calls[0].started, started);
+        for pair in calls.windows(2) {
+            assert_eq!(pair[1].started - pair[0].ended, interval);
+        }
+    }
+
+    #[tokio::test]
+    async fn logging_passes_results_through() {
+        let fake = FakePeripheral::new(BDAddr::default()).with_value(&[1]);
+        let peripheral = LoggingPeripheral::new(fake.clone()).with_level(Level::Error);
+        assert_eq!(peripheral.read(&characteristic()).await.unwrap(), [1]);
+        fake.fail_next([Error::NotConnected]);
+        assert!(matches!(
+            peripheral
+                .write(&characteristic(), &[2], WriteType::WithoutResponse)
+                .await,
+            Err(Error::NotConnected)
+        ));
+        let calls = fake.calls();
+        assert_eq!(calls[1].operation, "write");
+        assert_eq!(calls[1].data, [2]);
+    }
+}
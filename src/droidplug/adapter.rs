@@ -1,13 +1,20 @@
 use super::{
     jni::{
-        global_jvm,
-        objects::{JScanFilter, JScanResult},
+        jni_env,
+        objects::{JAdvertiseData, JScanFilter, JScanResult, ScanReport},
     },
-    peripheral::{Peripheral, PeripheralId},
+    peripheral::{get_poll_result, poll_result_from_future, Peripheral, PeripheralId},
+    server,
 };
 use crate::{
-    api::{BDAddr, Central, CentralEvent, CentralState, PeripheralProperties, ScanFilter},
+    api::{
+        advertisement::ParseMode, shutdown_central, AdvertisementData, BDAddr, Backend,
+        BackendKind, BondState, Capabilities, Central, CentralEvent, CentralState, GattServer,
+        PeripheralProperties, ScanFilter, ScanMode, ScanSettings,
+    },
     common::adapter_manager::AdapterManager,
+    interceptor::GattInterceptor,
+    scan_session::ScanSession,
     Error, Result,
 };
 use async_trait::async_trait;
@@ -15,20 +22,23 @@ use futures::stream::Stream;
 use jni::{
     objects::{GlobalRef, JObject, JString, JValue},
     strings::JavaStr,
-    sys::jboolean,
+    sys::{jboolean, jint},
     JNIEnv,
 };
+use jni_utils::future::{JFuture, JSendFuture};
 use std::{
+    convert::TryFrom,
     fmt::{Debug, Formatter},
     pin::Pin,
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 #[derive(Clone)]
 pub struct Adapter {
     manager: Arc<AdapterManager<Peripheral>>,
     internal: GlobalRef,
+    parse_mode: Arc<Mutex<ParseMode>>,
 }
 
 impl Debug for Adapter {
@@ -41,7 +51,7 @@ impl Debug for Adapter {
 
 impl Adapter {
     pub(crate) fn new() -> Result<Self> {
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
 
         let obj = env.new_object(
             "com/nonpolynomial/btleplug/android/impl/Adapter",
@@ -52,6 +62,7 @@ impl Adapter {
         let adapter = Self {
             manager: Arc::new(AdapterManager::default()),
             internal,
+            parse_mode: Arc::new(Mutex::new(ParseMode::default())),
         };
         unsafe {
             env.set_rust_field(&obj, "handle", adapter.clone())?;
@@ -63,66 +74,79 @@ impl Adapter {
     pub fn report_scan_result(&self, scan_result: JObject) -> Result<Peripheral> {
         use std::convert::TryInto;
 
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
         let scan_result = JScanResult::from_env(&mut env, scan_result)?;
+        let report: ScanReport = scan_result.try_into()?;
+        let id = PeripheralId(report.address);
 
-        let (addr, properties): (BDAddr, Option<PeripheralProperties>) = scan_result.try_into()?;
-
-        match self.manager.peripheral(&PeripheralId(addr)) {
-            Some(p) => match properties {
-                Some(properties) => {
-                    self.report_properties(&p, properties, false);
-                    Ok(p)
-                }
-                None => {
-                    //self.manager.emit(CentralEvent::DeviceDisconnected(addr));
-                    Err(Error::DeviceNotFound)
-                }
-            },
-            None => match properties {
-                Some(properties) => {
-                    let p = self.add(addr)?;
-                    self.report_properties(&p, properties, true);
-                    Ok(p)
-                }
-                None => Err(Error::DeviceNotFound),
-            },
+        let (peripheral, new) = match self.manager.peripheral(&id) {
+            Some(p) => (p, false),
+            None if report.advertising_data.is_some() => (self.add(report.address)?, true),
+            None => return Err(Error::DeviceNotFound),
+        };
+        let advertising_interval = self.manager.record_advertisement(&id);
+        let Some((properties, ad_errors)) = peripheral.report_scan(report, advertising_interval)
+        else {
+            //self.manager.emit(CentralEvent::DeviceDisconnected(addr));
+            return Err(Error::DeviceNotFound);
+        };
+
+        if !ad_errors.is_empty() && *self.parse_mode.lock()? == ParseMode::Strict {
+            self.manager.emit(CentralEvent::MalformedAdvertisement {
+                id: id.clone(),
+                errors: ad_errors,
+            });
+        }
+        self.report_properties(id, &properties, new);
+        Ok(peripheral)
+    }
+
+    /// Stops scanning, disconnects and closes all the peripherals, and detaches the adapter from its
+    /// Java object, which then stops reporting to it.
+    pub(crate) async fn shutdown(&self) -> Result<()> {
+        let mut result = shutdown_central(self).await;
+        for peripheral in self.manager.peripherals() {
+            result = result.and(peripheral.close().await);
+        }
+        let mut env = jni_env()?;
+        // The Java object holds a clone of the adapter, which would keep it alive otherwise.
+        unsafe {
+            env.take_rust_field::<_, _, Adapter>(self.internal.as_obj(), "handle")?;
         }
+        result
     }
 
     fn add(&self, address: BDAddr) -> Result<Peripheral> {
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
         let adapter_obj = env.new_local_ref(self.internal.as_obj())?;
         let peripheral = Peripheral::new(&mut env, adapter_obj, address, Arc::downgrade(&self.manager))?;
         self.manager.add_peripheral(peripheral.clone());
         Ok(peripheral)
     }
 
-    fn report_properties(
-        &self,
-        peripheral: &Peripheral,
-        properties: PeripheralProperties,
-        new: bool,
-    ) {
-        peripheral.report_properties(properties.clone());
-        self.manager.emit(if new {
-            CentralEvent::DeviceDiscovered(PeripheralId(properties.address))
-        } else {
-            CentralEvent::DeviceUpdated(PeripheralId(properties.address))
-        });
-        self.manager
-            .emit(CentralEvent::ManufacturerDataAdvertisement {
-                id: PeripheralId(properties.address),
-                manufacturer_data: properties.manufacturer_data,
-            });
-        self.manager.emit(CentralEvent::ServiceDataAdvertisement {
-            id: PeripheralId(properties.address),
-            service_data: properties.service_data,
-        });
-        self.manager.emit(CentralEvent::ServicesAdvertisement {
-            id: PeripheralId(properties.address),
-            services: properties.services,
-        });
+    fn report_properties(&self, id: PeripheralId, properties: &PeripheralProperties, new: bool) {
+        let events = [
+            if new {
+                CentralEvent::DeviceDiscovered(id.clone())
+            } else {
+                CentralEvent::DeviceUpdated(id.clone())
+            },
+            CentralEvent::ManufacturerDataAdvertisement {
+                id: id.clone(),
+                manufacturer_data: properties.manufacturer_data.clone(),
+            },
+            CentralEvent::ServiceDataAdvertisement {
+                id: id.clone(),
+                service_data: properties.service_data.clone(),
+            },
+            CentralEvent::ServicesAdvertisement {
+                id,
+                services: properties.services.clone(),
+            },
+        ];
+        for event in events {
+            self.manager.emit_with_properties(event, Some(properties));
+        }
     }
 }
 
@@ -140,24 +164,59 @@ impl Central for Adapter {
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
-        let mut env = global_jvm().get_env()?;
+        self.start_scan_with_settings(filter, ScanSettings::default())
+            .await
+    }
+
+    async fn start_scan_with_settings(
+        &self,
+        filter: ScanFilter,
+        settings: ScanSettings,
+    ) -> Result<()> {
+        self.manager.set_scan_filter(filter.clone());
+        let mut env = jni_env()?;
         let filter = JScanFilter::new(&mut env, filter)?;
         let filter_obj: JObject = filter.into();
+        let scan_mode = match settings.mode.unwrap_or(ScanMode::LowPower) {
+            ScanMode::LowPower => SCAN_MODE_LOW_POWER,
+            ScanMode::Balanced => SCAN_MODE_BALANCED,
+            ScanMode::LowLatency => SCAN_MODE_LOW_LATENCY,
+        };
+        let report_delay = settings.report_delay.unwrap_or_default().as_millis() as i64;
+        let legacy = !settings.extended_advertising.unwrap_or(true);
+        let phy = match settings.coded_phy {
+            Some(false) => PHY_LE_1M,
+            _ => PHY_LE_ALL_SUPPORTED,
+        };
         env.call_method(
             &self.internal,
             "startScan",
-            "(Lcom/nonpolynomial/btleplug/android/impl/ScanFilter;)V",
-            &[JValue::from(&filter_obj)],
+            "(Lcom/nonpolynomial/btleplug/android/impl/ScanFilter;IJZI)V",
+            &[
+                JValue::from(&filter_obj),
+                JValue::from(scan_mode),
+                JValue::from(report_delay),
+                JValue::from(legacy),
+                JValue::from(phy),
+            ],
         )?;
         Ok(())
     }
 
     async fn stop_scan(&self) -> Result<()> {
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
         env.call_method(&self.internal, "stopScan", "()V", &[])?;
+        self.manager.set_scan_filter(ScanFilter::default());
         Ok(())
     }
 
+    async fn start_scan_session(&self, filter: ScanFilter) -> Result<ScanSession> {
+        self.manager
+            .scan_sessions()
+            .start(self.clone(), filter)
+            .await
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         Ok(self.manager.peripherals())
     }
@@ -175,6 +234,110 @@ impl Central for Adapter {
     async fn adapter_state(&self) -> Result<CentralState> {
         Ok(CentralState::Unknown)
     }
+
+    async fn capabilities(&self) -> Result<Capabilities> {
+        let mut capabilities = Capabilities::STRICT_ADVERTISEMENT_PARSING
+            | Capabilities::EVENT_REPLAY
+            | Capabilities::CONNECTION_INFO
+            | Capabilities::ADD_PERIPHERAL
+            | Capabilities::MTU
+            | Capabilities::SCAN_SESSIONS
+            | Capabilities::SIGNED_WRITE
+            | Capabilities::CONNECT_TRANSPORT
+            | Capabilities::PAIRING_STATE
+            | Capabilities::GATT_INTERCEPTORS
+            | Capabilities::GATT_SERVER
+            | Capabilities::ADVERTISING
+            | Capabilities::CONNECTION_PARAMETERS
+            | Capabilities::AUTO_CONNECT
+            | Capabilities::BOND_STATE_EVENTS
+            | Capabilities::PAIRING_REQUESTS
+            | Capabilities::SCAN_SETTINGS
+            | Capabilities::RELIABLE_WRITE;
+        if sdk_int()? >= 26 {
+            capabilities |= Capabilities::CONNECT_PHY | Capabilities::PHY_SELECTION;
+        }
+        Ok(capabilities)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend {
+            kind: BackendKind::Android,
+            version: android_version().ok(),
+        }
+    }
+
+    async fn set_advertisement_parse_mode(&self, mode: ParseMode) -> Result<()> {
+        *self.parse_mode.lock()? = mode;
+        Ok(())
+    }
+
+    async fn set_event_replay_capacity(&self, capacity: usize) -> Result<()> {
+        self.manager.set_replay_capacity(capacity);
+        Ok(())
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.manager.interceptors().add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self.manager.interceptors().remove(interceptor))
+    }
+
+    async fn gatt_server(&self) -> Result<Box<dyn GattServer>> {
+        Ok(Box::new(server::GattServer::new()?))
+    }
+
+    async fn start_advertising(&self, data: AdvertisementData) -> Result<()> {
+        let future = {
+            let mut env = jni_env()?;
+            let data_obj: JObject = JAdvertiseData::new(&mut env, &data)?.into();
+            let future = env
+                .call_method(
+                    &self.internal,
+                    "startAdvertising",
+                    "(Landroid/bluetooth/le/AdvertiseData;Z)Lio/github/gedgygedgy/rust/future/Future;",
+                    &[JValue::from(&data_obj), JValue::from(data.connectable)],
+                )?
+                .l()?;
+            JSendFuture::try_from(JFuture::from_env(&mut env, future)?)?
+        };
+        let result_ref = future.await?;
+        let mut env = jni_env()?;
+        let result = poll_result_from_future(&mut env, &result_ref)?;
+        get_poll_result(&mut env, result).map(|_| {})
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        let mut env = jni_env()?;
+        env.call_method(&self.internal, "stopAdvertising", "()V", &[])?;
+        Ok(())
+    }
+
+    async fn handle_pairing_requests(&self) -> Result<()> {
+        // Pairing requests are always reported, see `Adapter.Receiver` on the Java side.
+        Ok(())
+    }
+}
+
+/// Returns the Android release and API level, e.g. "Android 14 (API 34)".
+fn android_version() -> Result<String> {
+    let mut env = jni_env()?;
+    let release = env
+        .get_static_field("android/os/Build$VERSION", "RELEASE", "Ljava/lang/String;")?
+        .l()?;
+    let release: String = env.get_string(&JString::from(release))?.into();
+    Ok(format!("Android {} (API {})", release, sdk_int()?))
+}
+
+/// Returns the Android API level.
+pub(super) fn sdk_int() -> Result<jint> {
+    let mut env = jni_env()?;
+    Ok(env
+        .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")?
+        .i()?)
 }
 
 pub(crate) fn adapter_report_scan_result_internal(
@@ -204,3 +367,86 @@ pub(crate) fn adapter_on_connection_state_changed_internal(
     });
     Ok(())
 }
+
+pub(crate) fn adapter_on_services_changed_internal(
+    env: &mut JNIEnv,
+    obj: JObject,
+    addr: JString,
+) -> crate::Result<()> {
+    let addr_str = JavaStr::from_env(env, &addr)?;
+    let addr_str = addr_str.to_str().map_err(|e| Error::Other(e.into()))?;
+    let adapter = unsafe { env.get_rust_field::<_, _, Adapter>(obj, "handle")? };
+    let id = PeripheralId(BDAddr::from_str(addr_str)?);
+    if let Some(peripheral) = adapter.manager.peripheral(&id) {
+        peripheral.clear_services()?;
+    }
+    adapter.manager.emit(CentralEvent::ServicesChanged(id));
+    Ok(())
+}
+
+// The `ScanSettings.SCAN_MODE_*` constants.
+const SCAN_MODE_LOW_POWER: jint = 0;
+const SCAN_MODE_BALANCED: jint = 1;
+const SCAN_MODE_LOW_LATENCY: jint = 2;
+
+// The `BluetoothDevice.PHY_LE_1M` and `ScanSettings.PHY_LE_ALL_SUPPORTED` constants.
+const PHY_LE_1M: jint = 1;
+const PHY_LE_ALL_SUPPORTED: jint = 255;
+
+// The `BluetoothDevice.BOND_*` constants.
+const BOND_NONE: jint = 10;
+const BOND_BONDING: jint = 11;
+const BOND_BONDED: jint = 12;
+
+// The `BluetoothDevice.PAIRING_VARIANT_*` constants, some of which are hidden.
+const PAIRING_VARIANT_PIN: jint = 0;
+const PAIRING_VARIANT_PASSKEY: jint = 1;
+const PAIRING_VARIANT_PASSKEY_CONFIRMATION: jint = 2;
+const PAIRING_VARIANT_CONSENT: jint = 3;
+
+pub(crate) fn adapter_on_bond_state_changed_internal(
+    env: &mut JNIEnv,
+    obj: JObject,
+    addr: JString,
+    state: jint,
+) -> crate::Result<()> {
+    let state = match state {
+        BOND_NONE => BondState::NotBonded,
+        BOND_BONDING => BondState::Bonding,
+        BOND_BONDED => BondState::Bonded,
+        _ => return Ok(()),
+    };
+    let addr_str = JavaStr::from_env(env, &addr)?;
+    let addr_str = addr_str.to_str().map_err(|e| Error::Other(e.into()))?;
+    let adapter = unsafe { env.get_rust_field::<_, _, Adapter>(obj, "handle")? };
+    let id = PeripheralId(BDAddr::from_str(addr_str)?);
+    adapter
+        .manager
+        .emit(CentralEvent::BondStateChanged { id, state });
+    Ok(())
+}
+
+pub(crate) fn adapter_on_pairing_request_internal(
+    env: &mut JNIEnv,
+    obj: JObject,
+    addr: JString,
+    variant: jint,
+    key: jint,
+) -> crate::Result<()> {
+    let addr_str = JavaStr::from_env(env, &addr)?;
+    let addr_str = addr_str.to_str().map_err(|e| Error::Other(e.into()))?;
+    let adapter = unsafe { env.get_rust_field::<_, _, Adapter>(obj, "handle")? };
+    let id = PeripheralId(BDAddr::from_str(addr_str)?);
+    let event = match variant {
+        PAIRING_VARIANT_PIN | PAIRING_VARIANT_PASSKEY => CentralEvent::PairingPinRequested { id },
+        PAIRING_VARIANT_PASSKEY_CONFIRMATION => CentralEvent::PairingConfirmationRequested {
+            id,
+            passkey: u32::try_from(key).ok(),
+        },
+        PAIRING_VARIANT_CONSENT => CentralEvent::PairingConfirmationRequested { id, passkey: None },
+        // The passkey or PIN is shown on this device, and entered on the peripheral.
+        _ => return Ok(()),
+    };
+    adapter.manager.emit(event);
+    Ok(())
+}
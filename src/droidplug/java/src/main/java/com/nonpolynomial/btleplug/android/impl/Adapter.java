@@ -2,51 +2,71 @@ package com.nonpolynomial.btleplug.android.impl;
 
 import android.annotation.SuppressLint;
 import android.bluetooth.BluetoothAdapter;
+import android.bluetooth.BluetoothDevice;
 import android.bluetooth.BluetoothManager;
+import android.bluetooth.le.AdvertiseCallback;
+import android.bluetooth.le.AdvertiseData;
+import android.bluetooth.le.AdvertiseSettings;
+import android.bluetooth.le.BluetoothLeAdvertiser;
 import android.bluetooth.le.BluetoothLeScanner;
 import android.bluetooth.le.ScanCallback;
-import android.bluetooth.le.ScanFilter.Builder;
 import android.bluetooth.le.ScanResult;
 import android.bluetooth.le.ScanSettings;
+import android.content.BroadcastReceiver;
+import android.content.Context;
+import android.content.Intent;
+import android.content.IntentFilter;
 import android.os.Build;
-import android.os.ParcelUuid;
+import android.util.Log;
 
-import java.util.ArrayList;
 import java.util.List;
 
+import io.github.gedgygedgy.rust.future.Future;
+import io.github.gedgygedgy.rust.future.SimpleFuture;
+
 @SuppressWarnings("unused") // Native code uses this class.
 class Adapter {
+    private static final String TAG = "btleplug-Adapter";
+
     private long handle;
     private final Callback callback = new Callback();
+    /** The callback of the running advertisement, which also identifies it to stop it. */
+    private AdvertiseCallback advertiseCallback;
+
+    public Adapter() {
+        IntentFilter filter = new IntentFilter();
+        filter.addAction(BluetoothDevice.ACTION_BOND_STATE_CHANGED);
+        filter.addAction(BluetoothDevice.ACTION_PAIRING_REQUEST);
+        try {
+            applicationContext().registerReceiver(new Receiver(), filter);
+        } catch (ReflectiveOperationException | RuntimeException ex) {
+            Log.w(TAG, "Unable to listen for bonding and pairing", ex);
+        }
+    }
 
-    public Adapter() {}
+    /** The context of the application, which native code doesn't have at hand. */
+    static Context applicationContext() throws ReflectiveOperationException {
+        return (Context) Class.forName("android.app.ActivityThread")
+                .getMethod("currentApplication")
+                .invoke(null);
+    }
 
     @SuppressLint("MissingPermission")
-    public void startScan(ScanFilter filter) {
+    public void startScan(ScanFilter filter, int scanMode, long reportDelayMillis, boolean legacy, int phy) {
         BluetoothAdapter bluetoothAdapter = BluetoothAdapter.getDefaultAdapter();
         if (bluetoothAdapter == null) {
           throw new RuntimeException("No bluetooth adapter available.");
         }
 
-        ArrayList<android.bluetooth.le.ScanFilter> filters = null;
-        String[] uuids = filter.getUuids();
-        if (uuids.length > 0) {
-            filters = new ArrayList<>();
-            for (String uuid : uuids) {
-                filters.add(new Builder().setServiceUuid(ParcelUuid.fromString(uuid)).build());
-            }
-        }
-        ScanSettings settings;
+        List<android.bluetooth.le.ScanFilter> filters = filter.toScanFilters();
+        ScanSettings.Builder builder = new ScanSettings.Builder()
+                .setCallbackType(ScanSettings.CALLBACK_TYPE_ALL_MATCHES)
+                .setScanMode(scanMode)
+                .setReportDelay(reportDelayMillis);
         if (Build.VERSION.SDK_INT >= 26) {
-            settings = new ScanSettings.Builder()
-                    .setCallbackType(ScanSettings.CALLBACK_TYPE_ALL_MATCHES)
-                    .setLegacy(false)
-                    .build();
-        } else {
-            settings = new ScanSettings.Builder()
-                    .setCallbackType(ScanSettings.CALLBACK_TYPE_ALL_MATCHES)
-                    .build();
+            builder.setLegacy(legacy).setPhy(phy);
         }
+        ScanSettings settings = builder.build();
         BluetoothLeScanner scanner = bluetoothAdapter.getBluetoothLeScanner();
         if (scanner == null) {
           throw new RuntimeException("No bluetooth scanner available for adapter");
@@ -65,14 +85,108 @@ class Adapter {
         }
     }
 
+    @SuppressLint("MissingPermission")
+    public synchronized Future<Void> startAdvertising(AdvertiseData data, boolean connectable) {
+        SimpleFuture<Void> future = new SimpleFuture<>();
+        BluetoothAdapter bluetoothAdapter = BluetoothAdapter.getDefaultAdapter();
+        BluetoothLeAdvertiser advertiser = bluetoothAdapter != null ? bluetoothAdapter.getBluetoothLeAdvertiser() : null;
+        if (advertiser == null) {
+            future.wakeWithThrowable(new RuntimeException("No bluetooth advertiser available for adapter"));
+            return future;
+        }
+        this.stopAdvertising();
+        AdvertiseSettings settings = new AdvertiseSettings.Builder()
+                .setConnectable(connectable)
+                .setTimeout(0)
+                .build();
+        AdvertiseCallback callback = new AdvertiseCallback() {
+            @Override
+            public void onStartSuccess(AdvertiseSettings settingsInEffect) {
+                future.wake(null);
+            }
+
+            @Override
+            public void onStartFailure(int errorCode) {
+                synchronized (Adapter.this) {
+                    if (Adapter.this.advertiseCallback == this) {
+                        Adapter.this.advertiseCallback = null;
+                    }
+                }
+                future.wakeWithThrowable(new RuntimeException("Unable to start advertising: error=" + errorCode));
+            }
+        };
+        try {
+            advertiser.startAdvertising(settings, data, callback);
+        } catch (SecurityException ex) {
+            future.wakeWithThrowable(new PermissionDeniedException(ex));
+            return future;
+        }
+        this.advertiseCallback = callback;
+        return future;
+    }
+
+    @SuppressLint("MissingPermission")
+    public synchronized void stopAdvertising() {
+        if (this.advertiseCallback == null) {
+            return;
+        }
+        BluetoothAdapter bluetoothAdapter = BluetoothAdapter.getDefaultAdapter();
+        BluetoothLeAdvertiser advertiser = bluetoothAdapter != null ? bluetoothAdapter.getBluetoothLeAdvertiser() : null;
+        if (advertiser != null) {
+            advertiser.stopAdvertising(this.advertiseCallback);
+        }
+        this.advertiseCallback = null;
+    }
+
     private native void reportScanResult(ScanResult result);
 
     public native void onConnectionStateChanged(String address, boolean connected);
 
+    public native void onServicesChanged(String address);
+
+    private native void onBondStateChanged(String address, int state);
+
+    private native void onPairingRequest(String address, int variant, int key);
+
     private class Callback extends ScanCallback {
         @Override
         public void onScanResult(int callbackType, ScanResult result) {
             Adapter.this.reportScanResult(result);
         }
+
+        @Override
+        public void onBatchScanResults(List<ScanResult> results) {
+            for (ScanResult result : results) {
+                Adapter.this.reportScanResult(result);
+            }
+        }
+
+        @Override
+        public void onScanFailed(int errorCode) {
+            Log.e(TAG, "Scan failed with error " + errorCode);
+        }
+    }
+
+    private class Receiver extends BroadcastReceiver {
+        @Override
+        @SuppressWarnings("deprecation")
+        public void onReceive(Context context, Intent intent) {
+            BluetoothDevice device = intent.getParcelableExtra(BluetoothDevice.EXTRA_DEVICE);
+            if (device == null) {
+                return;
+            }
+            try {
+                if (BluetoothDevice.ACTION_BOND_STATE_CHANGED.equals(intent.getAction())) {
+                    Adapter.this.onBondStateChanged(device.getAddress(),
+                            intent.getIntExtra(BluetoothDevice.EXTRA_BOND_STATE, BluetoothDevice.ERROR));
+                } else if (BluetoothDevice.ACTION_PAIRING_REQUEST.equals(intent.getAction())) {
+                    Adapter.this.onPairingRequest(device.getAddress(),
+                            intent.getIntExtra(BluetoothDevice.EXTRA_PAIRING_VARIANT, BluetoothDevice.ERROR),
+                            intent.getIntExtra(BluetoothDevice.EXTRA_PAIRING_KEY, BluetoothDevice.ERROR));
+                }
+            } catch (RuntimeException ex) {
+                Log.w(TAG, "Unable to report " + intent.getAction(), ex);
+            }
+        }
     }
 }
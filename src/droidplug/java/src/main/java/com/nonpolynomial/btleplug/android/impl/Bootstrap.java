@@ -0,0 +1,13 @@
+package com.nonpolynomial.btleplug.android.impl;
+
+/**
+ * Entry point used when the support classes are loaded from the dex embedded in the Rust crate.
+ * Class lookups done by nativeInit() resolve through the class loader of this class.
+ */
+class Bootstrap {
+    private static native boolean nativeInit();
+
+    static boolean init() {
+        return nativeInit();
+    }
+}
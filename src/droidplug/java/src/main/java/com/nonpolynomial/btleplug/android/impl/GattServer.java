@@ -0,0 +1,257 @@
+package com.nonpolynomial.btleplug.android.impl;
+
+import android.annotation.SuppressLint;
+import android.bluetooth.BluetoothDevice;
+import android.bluetooth.BluetoothGatt;
+import android.bluetooth.BluetoothGattCharacteristic;
+import android.bluetooth.BluetoothGattDescriptor;
+import android.bluetooth.BluetoothGattServer;
+import android.bluetooth.BluetoothGattServerCallback;
+import android.bluetooth.BluetoothGattService;
+import android.bluetooth.BluetoothManager;
+import android.bluetooth.BluetoothProfile;
+import android.content.Context;
+import android.os.Build;
+import android.util.Log;
+
+import java.util.Arrays;
+import java.util.HashMap;
+import java.util.HashSet;
+import java.util.Map;
+import java.util.Set;
+import java.util.UUID;
+
+import io.github.gedgygedgy.rust.future.Future;
+import io.github.gedgygedgy.rust.future.SimpleFuture;
+
+@SuppressWarnings("unused") // Native code uses this class.
+class GattServer {
+    private static final String TAG = "btleplug-GattServer";
+    private static final UUID CLIENT_CHARACTERISTIC_CONFIGURATION_DESCRIPTOR = new UUID(0x00002902_0000_1000L, 0x8000_00805f9b34fbL);
+
+    private long handle;
+    private final BluetoothGattServer server;
+    private final Callback callback = new Callback();
+    /** Completed by onServiceAdded. Android adds one service at a time, so there's at most one. */
+    private SimpleFuture<Void> addServiceFuture;
+    /** The centrals which enabled notifications or indications, by characteristic. */
+    private final Map<BluetoothGattCharacteristic, Set<BluetoothDevice>> subscribers = new HashMap<>();
+
+    @SuppressLint("MissingPermission")
+    public GattServer() throws ReflectiveOperationException {
+        Context context = Adapter.applicationContext();
+        BluetoothManager manager = (BluetoothManager) context.getSystemService(Context.BLUETOOTH_SERVICE);
+        try {
+            this.server = manager.openGattServer(context, this.callback);
+        } catch (SecurityException ex) {
+            throw new PermissionDeniedException(ex);
+        }
+        if (this.server == null) {
+            throw new RuntimeException("Unable to open a GATT server");
+        }
+    }
+
+    @SuppressLint("MissingPermission")
+    public Future<Void> addService(BluetoothGattService service) {
+        SimpleFuture<Void> future = new SimpleFuture<>();
+        for (BluetoothGattCharacteristic characteristic : service.getCharacteristics()) {
+            int notifyOrIndicate = BluetoothGattCharacteristic.PROPERTY_NOTIFY | BluetoothGattCharacteristic.PROPERTY_INDICATE;
+            if ((characteristic.getProperties() & notifyOrIndicate) != 0
+                    && characteristic.getDescriptor(CLIENT_CHARACTERISTIC_CONFIGURATION_DESCRIPTOR) == null) {
+                characteristic.addDescriptor(new BluetoothGattDescriptor(
+                        CLIENT_CHARACTERISTIC_CONFIGURATION_DESCRIPTOR,
+                        BluetoothGattDescriptor.PERMISSION_READ | BluetoothGattDescriptor.PERMISSION_WRITE));
+            }
+        }
+        synchronized (this) {
+            if (this.addServiceFuture != null) {
+                future.wakeWithThrowable(new RuntimeException("Another service is being added"));
+                return future;
+            }
+            this.addServiceFuture = future;
+            try {
+                if (!this.server.addService(service)) {
+                    this.addServiceFuture = null;
+                    future.wakeWithThrowable(new RuntimeException("Unable to add service"));
+                }
+            } catch (SecurityException ex) {
+                this.addServiceFuture = null;
+                future.wakeWithThrowable(new PermissionDeniedException(ex));
+            }
+        }
+        return future;
+    }
+
+    @SuppressLint("MissingPermission")
+    public synchronized boolean removeService(String uuid) {
+        BluetoothGattService service = this.server.getService(UUID.fromString(uuid));
+        if (service == null) {
+            return false;
+        }
+        for (BluetoothGattCharacteristic characteristic : service.getCharacteristics()) {
+            this.subscribers.remove(characteristic);
+        }
+        return this.server.removeService(service);
+    }
+
+    /**
+     * Sends a new value of a characteristic to the centrals which subscribed to it. Returns false
+     * if there's no such characteristic.
+     */
+    @SuppressLint("MissingPermission")
+    @SuppressWarnings("deprecation")
+    public synchronized boolean notify(String serviceUuid, String characteristicUuid, byte[] value) {
+        BluetoothGattService service = this.server.getService(UUID.fromString(serviceUuid));
+        if (service == null) {
+            return false;
+        }
+        BluetoothGattCharacteristic characteristic = service.getCharacteristic(UUID.fromString(characteristicUuid));
+        if (characteristic == null) {
+            return false;
+        }
+        Set<BluetoothDevice> devices = this.subscribers.get(characteristic);
+        if (devices == null) {
+            return true;
+        }
+        boolean confirm = (characteristic.getProperties() & BluetoothGattCharacteristic.PROPERTY_NOTIFY) == 0;
+        for (BluetoothDevice device : devices) {
+            if (Build.VERSION.SDK_INT >= 33) {
+                this.server.notifyCharacteristicChanged(device, characteristic, confirm, value);
+            } else {
+                characteristic.setValue(value);
+                this.server.notifyCharacteristicChanged(device, characteristic, confirm);
+            }
+        }
+        return true;
+    }
+
+    @SuppressLint("MissingPermission")
+    public void sendResponse(BluetoothDevice device, int requestId, int status, int offset, byte[] value) {
+        this.server.sendResponse(device, requestId, status, offset, value);
+    }
+
+    @SuppressLint("MissingPermission")
+    public synchronized void close() {
+        this.subscribers.clear();
+        this.server.close();
+    }
+
+    private native void onReadRequest(BluetoothDevice device, String address, int requestId, String serviceUuid, String characteristicUuid, int offset);
+
+    private native void onWriteRequest(BluetoothDevice device, String address, int requestId, String serviceUuid, String characteristicUuid, int offset, byte[] value, boolean responseNeeded);
+
+    private native void onSubscriptionChanged(String serviceUuid, String characteristicUuid, boolean subscribed);
+
+    private class Callback extends BluetoothGattServerCallback {
+        @Override
+        public void onServiceAdded(int status, BluetoothGattService service) {
+            SimpleFuture<Void> future;
+            synchronized (GattServer.this) {
+                future = GattServer.this.addServiceFuture;
+                GattServer.this.addServiceFuture = null;
+            }
+            if (future == null) {
+                return;
+            }
+            if (status == BluetoothGatt.GATT_SUCCESS) {
+                future.wake(null);
+            } else {
+                future.wakeWithThrowable(new RuntimeException("Unable to add service: status=" + status));
+            }
+        }
+
+        @Override
+        public void onConnectionStateChange(BluetoothDevice device, int status, int newState) {
+            if (newState == BluetoothProfile.STATE_DISCONNECTED) {
+                synchronized (GattServer.this) {
+                    for (Map.Entry<BluetoothGattCharacteristic, Set<BluetoothDevice>> entry : GattServer.this.subscribers.entrySet()) {
+                        if (entry.getValue().remove(device) && entry.getValue().isEmpty()) {
+                            GattServer.this.subscriptionChanged(entry.getKey(), false);
+                        }
+                    }
+                }
+            }
+        }
+
+        @Override
+        public void onCharacteristicReadRequest(BluetoothDevice device, int requestId, int offset, BluetoothGattCharacteristic characteristic) {
+            GattServer.this.onReadRequest(device, device.getAddress(), requestId,
+                    characteristic.getService().getUuid().toString(), characteristic.getUuid().toString(), offset);
+        }
+
+        @Override
+        public void onCharacteristicWriteRequest(BluetoothDevice device, int requestId, BluetoothGattCharacteristic characteristic, boolean preparedWrite, boolean responseNeeded, int offset, byte[] value) {
+            if (preparedWrite) {
+                // Reliable and long writes aren't supported.
+                if (responseNeeded) {
+                    GattServer.this.sendResponse(device, requestId, BluetoothGatt.GATT_REQUEST_NOT_SUPPORTED, offset, null);
+                }
+                return;
+            }
+            GattServer.this.onWriteRequest(device, device.getAddress(), requestId,
+                    characteristic.getService().getUuid().toString(), characteristic.getUuid().toString(), offset, value, responseNeeded);
+        }
+
+        @Override
+        public void onDescriptorReadRequest(BluetoothDevice device, int requestId, int offset, BluetoothGattDescriptor descriptor) {
+            byte[] value;
+            if (descriptor.getUuid().equals(CLIENT_CHARACTERISTIC_CONFIGURATION_DESCRIPTOR)) {
+                boolean subscribed;
+                synchronized (GattServer.this) {
+                    Set<BluetoothDevice> devices = GattServer.this.subscribers.get(descriptor.getCharacteristic());
+                    subscribed = devices != null && devices.contains(device);
+                }
+                value = subscribed ? BluetoothGattDescriptor.ENABLE_NOTIFICATION_VALUE : BluetoothGattDescriptor.DISABLE_NOTIFICATION_VALUE;
+            } else {
+                value = descriptor.getValue() != null ? descriptor.getValue() : new byte[0];
+            }
+            if (offset > value.length) {
+                GattServer.this.sendResponse(device, requestId, BluetoothGatt.GATT_INVALID_OFFSET, offset, null);
+                return;
+            }
+            GattServer.this.sendResponse(device, requestId, BluetoothGatt.GATT_SUCCESS, offset, Arrays.copyOfRange(value, offset, value.length));
+        }
+
+        @Override
+        public void onDescriptorWriteRequest(BluetoothDevice device, int requestId, BluetoothGattDescriptor descriptor, boolean preparedWrite, boolean responseNeeded, int offset, byte[] value) {
+            int status = BluetoothGatt.GATT_WRITE_NOT_PERMITTED;
+            if (descriptor.getUuid().equals(CLIENT_CHARACTERISTIC_CONFIGURATION_DESCRIPTOR) && !preparedWrite) {
+                if (value == null || value.length != 2 || offset != 0) {
+                    status = BluetoothGatt.GATT_INVALID_ATTRIBUTE_LENGTH;
+                } else {
+                    boolean subscribe = value[0] != 0 || value[1] != 0;
+                    BluetoothGattCharacteristic characteristic = descriptor.getCharacteristic();
+                    synchronized (GattServer.this) {
+                        Set<BluetoothDevice> devices = GattServer.this.subscribers.get(characteristic);
+                        if (devices == null) {
+                            devices = new HashSet<>();
+                            GattServer.this.subscribers.put(characteristic, devices);
+                        }
+                        boolean wasEmpty = devices.isEmpty();
+                        if (subscribe) {
+                            devices.add(device);
+                        } else {
+                            devices.remove(device);
+                        }
+                        if (wasEmpty != devices.isEmpty()) {
+                            GattServer.this.subscriptionChanged(characteristic, subscribe);
+                        }
+                    }
+                    status = BluetoothGatt.GATT_SUCCESS;
+                }
+            }
+            if (responseNeeded) {
+                GattServer.this.sendResponse(device, requestId, status, offset, null);
+            }
+        }
+    }
+
+    private void subscriptionChanged(BluetoothGattCharacteristic characteristic, boolean subscribed) {
+        try {
+            this.onSubscriptionChanged(characteristic.getService().getUuid().toString(),
+                    characteristic.getUuid().toString(), subscribed);
+        } catch (RuntimeException ex) {
+            Log.w(TAG, "onSubscriptionChanged failed", ex);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+package com.nonpolynomial.btleplug.android.impl;
+
+import android.bluetooth.BluetoothGattCharacteristic;
+
+// A value a peripheral notified or indicated. The characteristic is the one of the discovered
+// services, which tells the service and instance ID, while the value is copied, as the one of the
+// characteristic changes with the next notification.
+public class Notification {
+    private final BluetoothGattCharacteristic characteristic;
+    private final byte[] value;
+
+    Notification(BluetoothGattCharacteristic characteristic, byte[] value) {
+        this.characteristic = characteristic;
+        this.value = value;
+    }
+
+    public BluetoothGattCharacteristic getCharacteristic() {
+        return this.characteristic;
+    }
+
+    public byte[] getValue() {
+        return this.value;
+    }
+}
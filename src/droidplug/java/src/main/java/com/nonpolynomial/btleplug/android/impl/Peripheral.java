@@ -2,15 +2,20 @@ package com.nonpolynomial.btleplug.android.impl;
 
 import android.annotation.SuppressLint;
 import android.bluetooth.BluetoothAdapter;
+import android.bluetooth.BluetoothClass;
 import android.bluetooth.BluetoothDevice;
 import android.bluetooth.BluetoothGatt;
 import android.bluetooth.BluetoothGattCallback;
 import android.bluetooth.BluetoothGattCharacteristic;
 import android.bluetooth.BluetoothGattDescriptor;
 import android.bluetooth.BluetoothGattService;
+import android.os.Build;
+import android.os.Handler;
+import android.os.Looper;
 import android.util.Log;
 
 import java.lang.ref.WeakReference;
+import java.nio.charset.StandardCharsets;
 import java.util.ArrayList;
 import java.util.LinkedList;
 import java.util.List;
@@ -25,20 +30,35 @@ import io.github.gedgygedgy.rust.stream.Stream;
 @SuppressWarnings("unused") // Native code uses this class.
 class Peripheral {
     private static final String TAG = "btleplug-Peripheral";
+    /** Passed instead of an instance ID to operate on the first attribute with a matching UUID. */
+    private static final int NO_INSTANCE_ID = -1;
+    /** Returned by getClassOfDevice if the class of a device isn't known. */
+    private static final int NO_CLASS_OF_DEVICE = -1;
+    /** Returned by getBondState if the BLUETOOTH_CONNECT permission is missing. */
+    private static final int BOND_STATE_UNKNOWN = -1;
     private static final UUID CLIENT_CHARACTERISTIC_CONFIGURATION_DESCRIPTOR = new UUID(0x00002902_0000_1000L, 0x8000_00805f9b34fbL);
     private static final int DEFAULT_MTU = 23;
     private static final int REQUESTED_MTU = 517;
+    // Undocumented statuses reported for many spurious connection failures.
+    private static final int GATT_ERROR = 133;
+    private static final int GATT_FAILURE = 257;
+    private static final Handler RETRY_HANDLER = new Handler(Looper.getMainLooper());
 
     private final BluetoothDevice device;
     private final Adapter adapter;
     private BluetoothGatt gatt;
+    /** The transport {@link #gatt} was connected over, one of the {@code BluetoothDevice.TRANSPORT_*} constants. */
+    private int gattTransport = BluetoothDevice.TRANSPORT_AUTO;
+    /** The {@code BluetoothDevice.PHY_LE_*_MASK} bits {@link #gatt} was connected with, or 0 for the default. */
+    private int gattPhy = 0;
     private final Callback callback;
     private boolean connected = false;
     private int negotiatedMtu = DEFAULT_MTU;
     private boolean mtuRequestPending = false;
+    private int[] connectionParameters = null;
 
     private final Queue<Runnable> commandQueue = new LinkedList<>();
-    private final LinkedList<WeakReference<QueueStream<BluetoothGattCharacteristic>>> notificationStreams = new LinkedList<>();
+    private final LinkedList<WeakReference<QueueStream<Notification>>> notificationStreams = new LinkedList<>();
     private boolean executingCommand = false;
     private CommandCallback commandCallback;
 
@@ -49,18 +69,39 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<Void> connect() {
+    public Future<Void> connect(int maxAttempts, long initialBackoffMillis, long maxBackoffMillis, boolean toggleAutoConnect, int transport, boolean autoConnect, int phy) {
         SimpleFuture<Void> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
                 this.asyncWithFuture(future, () -> {
                     CommandCallback callback = new CommandCallback() {
+                        private int attempt = 1;
+
                         @Override
                         public void onConnectionStateChange(BluetoothGatt gatt, int status, int newState) {
                             Peripheral.this.asyncWithFuture(future, () -> {
                                 Log.d(TAG, "connect callback: status=" + status + " newState=" + newState);
+                                if ((status == GATT_ERROR || status == GATT_FAILURE) && this.attempt < maxAttempts) {
+                                    long backoff = Math.min(initialBackoffMillis << Math.min(this.attempt - 1, 30), maxBackoffMillis);
+                                    boolean retryAutoConnect = autoConnect || (toggleAutoConnect && this.attempt % 2 == 1);
+                                    Log.w(TAG, "connect failed: status=" + status + ", retrying in " + backoff + "ms with autoConnect=" + retryAutoConnect);
+                                    this.attempt++;
+                                    // A GATT client which failed this way can't be reused.
+                                    gatt.close();
+                                    Peripheral.this.gatt = null;
+                                    RETRY_HANDLER.postDelayed(() -> {
+                                        synchronized (Peripheral.this) {
+                                            Peripheral.this.asyncWithFuture(future, () -> {
+                                                Peripheral.this.gatt = Peripheral.this.connectGatt(retryAutoConnect, transport, phy);
+                                            });
+                                        }
+                                    }, backoff);
+                                    return;
+                                }
                                 if (status != BluetoothGatt.GATT_SUCCESS) {
                                     Log.w(TAG, "connect failed: status=" + status);
+                                    gatt.close();
+                                    Peripheral.this.gatt = null;
                                     throw new NotConnectedException();
                                 }
 
@@ -73,11 +114,20 @@ class Peripheral {
 
                     if (this.connected) {
                         Peripheral.this.wakeCommand(future, null);
-                    } else if (this.gatt == null) {
+                        return;
+                    }
+                    if (this.gatt != null && (this.gattTransport != transport || this.gattPhy != phy)) {
+                        // A GATT client stays bound to the transport and PHYs it was created for.
+                        this.gatt.close();
+                        this.gatt = null;
+                    }
+                    if (this.gatt == null) {
                         try {
                             this.negotiatedMtu = DEFAULT_MTU;
                             this.setCommandCallback(callback);
-                            this.gatt = this.device.connectGatt(null, false, this.callback);
+                            this.gattTransport = transport;
+                            this.gattPhy = phy;
+                            this.gatt = this.connectGatt(autoConnect, transport, phy);
                         } catch (SecurityException ex) {
                             throw new PermissionDeniedException(ex);
                         }
@@ -94,6 +144,15 @@ class Peripheral {
         return future;
     }
 
+    /** Creates a GATT client for the device. {@code phy} of 0 leaves the PHYs to Android. */
+    @SuppressLint("MissingPermission")
+    private BluetoothGatt connectGatt(boolean autoConnect, int transport, int phy) {
+        if (phy != 0 && Build.VERSION.SDK_INT >= 26) {
+            return this.device.connectGatt(null, autoConnect, this.callback, transport, phy, null);
+        }
+        return this.device.connectGatt(null, autoConnect, this.callback, transport);
+    }
+
     @SuppressLint("MissingPermission")
     public Future<Void> disconnect() {
         SimpleFuture<Void> future = new SimpleFuture<>();
@@ -112,8 +171,8 @@ class Peripheral {
                                     }
 
                                     if (newState == BluetoothGatt.STATE_DISCONNECTED) {
-                                        Peripheral.this.gatt.close();
-                                        Peripheral.this.gatt = null;
+                                        // The GATT client is kept for a quicker reconnection,
+                                        // close() releases it.
                                         Peripheral.this.wakeCommand(future, null);
                                     }
                                 });
@@ -127,10 +186,39 @@ class Peripheral {
         return future;
     }
 
+    // Releases the GATT client, dropping the connection if there is one. Android only allows a
+    // limited number of clients per app.
+    @SuppressLint("MissingPermission")
+    public Future<Void> close() {
+        SimpleFuture<Void> future = new SimpleFuture<>();
+        synchronized (this) {
+            this.queueCommand(() -> {
+                this.asyncWithFuture(future, () -> {
+                    boolean wasConnected = this.connected;
+                    if (this.gatt != null) {
+                        this.gatt.close();
+                        this.gatt = null;
+                    }
+                    // No more callbacks arrive after close(), so clean up as if disconnected.
+                    this.resetConnectionState();
+                    if (wasConnected) {
+                        this.adapter.onConnectionStateChanged(this.device.getAddress(), false);
+                    }
+                    this.wakeCommand(future, null);
+                });
+            });
+        }
+        return future;
+    }
+
     public boolean isConnected() {
         return this.connected;
     }
 
+    public int getBondState() {
+        return getBondState(this.device);
+    }
+
     public int getMtu() {
         if (!this.connected || this.gatt == null) {
             throw new NotConnectedException();
@@ -138,6 +226,176 @@ class Peripheral {
         return this.negotiatedMtu - 3;
     }
 
+    // Returns {interval, latency, timeout} in HCI units, or null if no connection update was
+    // reported yet.
+    public synchronized int[] getConnectionParameters() {
+        if (!this.connected || this.gatt == null) {
+            throw new NotConnectedException();
+        }
+        return this.connectionParameters == null ? null : this.connectionParameters.clone();
+    }
+
+    // Returns whether Android accepted the request. priority is one of the
+    // BluetoothGatt.CONNECTION_PRIORITY_* constants.
+    @SuppressLint("MissingPermission")
+    public synchronized boolean requestConnectionPriority(int priority) {
+        if (!this.connected || this.gatt == null) {
+            throw new NotConnectedException();
+        }
+        try {
+            return this.gatt.requestConnectionPriority(priority);
+        } catch (SecurityException ex) {
+            throw new PermissionDeniedException(ex);
+        }
+    }
+
+    // txPhy and rxPhy are masks of the BluetoothDevice.PHY_LE_*_MASK constants, phyOptions one of
+    // the BluetoothDevice.PHY_OPTION_* constants. Android reports the PHY it picks to onPhyUpdate.
+    @SuppressLint("MissingPermission")
+    public synchronized void setPreferredPhy(int txPhy, int rxPhy, int phyOptions) {
+        if (!this.connected || this.gatt == null) {
+            throw new NotConnectedException();
+        }
+        try {
+            this.gatt.setPreferredPhy(txPhy, rxPhy, phyOptions);
+        } catch (SecurityException ex) {
+            throw new PermissionDeniedException(ex);
+        }
+    }
+
+    // Resolves to {txPhy, rxPhy}, as BluetoothDevice.PHY_LE_* constants.
+    @SuppressLint("MissingPermission")
+    public Future<int[]> readPhy() {
+        SimpleFuture<int[]> future = new SimpleFuture<>();
+        synchronized (this) {
+            this.queueCommand(() -> {
+                this.asyncWithFuture(future, () -> {
+                    if (!this.connected || this.gatt == null) {
+                        throw new NotConnectedException();
+                    }
+
+                    this.setCommandCallback(new CommandCallback() {
+                        @Override
+                        public void onPhyRead(BluetoothGatt gatt, int txPhy, int rxPhy, int status) {
+                            Peripheral.this.asyncWithFuture(future, () -> {
+                                if (status != BluetoothGatt.GATT_SUCCESS) {
+                                    throw new RuntimeException("Unable to read PHY, status " + status);
+                                }
+
+                                Peripheral.this.wakeCommand(future, new int[] {txPhy, rxPhy});
+                            });
+                        }
+                    });
+                    try {
+                        this.gatt.readPhy();
+                    } catch (SecurityException ex) {
+                        throw new PermissionDeniedException(ex);
+                    }
+                });
+            });
+        }
+        return future;
+    }
+
+    // Writes queued after this resolves are kept by the peripheral until the reliable write is
+    // executed or aborted.
+    @SuppressLint("MissingPermission")
+    public Future<Void> beginReliableWrite() {
+        SimpleFuture<Void> future = new SimpleFuture<>();
+        synchronized (this) {
+            this.queueCommand(() -> {
+                this.asyncWithFuture(future, () -> {
+                    if (!this.connected || this.gatt == null) {
+                        throw new NotConnectedException();
+                    }
+
+                    try {
+                        if (!this.gatt.beginReliableWrite()) {
+                            throw new RuntimeException("Unable to begin reliable write");
+                        }
+                    } catch (SecurityException ex) {
+                        throw new PermissionDeniedException(ex);
+                    }
+                    this.wakeCommand(future, null);
+                });
+            });
+        }
+        return future;
+    }
+
+    @SuppressLint("MissingPermission")
+    public Future<Void> executeReliableWrite() {
+        SimpleFuture<Void> future = new SimpleFuture<>();
+        synchronized (this) {
+            this.queueCommand(() -> {
+                this.asyncWithFuture(future, () -> {
+                    if (!this.connected || this.gatt == null) {
+                        throw new NotConnectedException();
+                    }
+
+                    this.setCommandCallback(new CommandCallback() {
+                        @Override
+                        public void onReliableWriteCompleted(BluetoothGatt gatt, int status) {
+                            Peripheral.this.asyncWithFuture(future, () -> {
+                                if (status != BluetoothGatt.GATT_SUCCESS) {
+                                    throw new RuntimeException("Unable to execute reliable write, status " + status);
+                                }
+
+                                Peripheral.this.wakeCommand(future, null);
+                            });
+                        }
+                    });
+                    try {
+                        if (!this.gatt.executeReliableWrite()) {
+                            throw new RuntimeException("Unable to execute reliable write");
+                        }
+                    } catch (SecurityException ex) {
+                        throw new PermissionDeniedException(ex);
+                    }
+                });
+            });
+        }
+        return future;
+    }
+
+    // Doesn't wait for the peripheral to confirm: Android only reports the outcome of aborting
+    // when writes were already prepared.
+    @SuppressLint("MissingPermission")
+    public Future<Void> abortReliableWrite() {
+        SimpleFuture<Void> future = new SimpleFuture<>();
+        synchronized (this) {
+            this.queueCommand(() -> {
+                this.asyncWithFuture(future, () -> {
+                    if (!this.connected || this.gatt == null) {
+                        throw new NotConnectedException();
+                    }
+
+                    try {
+                        this.gatt.abortReliableWrite();
+                    } catch (SecurityException ex) {
+                        throw new PermissionDeniedException(ex);
+                    }
+                    this.wakeCommand(future, null);
+                });
+            });
+        }
+        return future;
+    }
+
+    // Answers a pairing request: enters pin if it isn't null, otherwise confirms or rejects the
+    // pairing. Returns whether Android accepted the answer.
+    @SuppressLint("MissingPermission")
+    public boolean respondToPairing(String pin, boolean confirm) {
+        try {
+            if (pin != null) {
+                return this.device.setPin(pin.getBytes(StandardCharsets.UTF_8));
+            }
+            return this.device.setPairingConfirmation(confirm);
+        } catch (SecurityException ex) {
+            throw new PermissionDeniedException(ex);
+        }
+    }
+
     private boolean requestMtu(BluetoothGatt gatt) {
         try {
             Object started = BluetoothGatt.class.getMethod("requestMtu", int.class).invoke(gatt, REQUESTED_MTU);
@@ -149,7 +407,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<byte[]> read(UUID uuid) {
+    public Future<byte[]> read(UUID uuid, int instanceId) {
         SimpleFuture<byte[]> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -158,7 +416,7 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid);
+                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid, instanceId);
                     this.setCommandCallback(new CommandCallback() {
                         @Override
                         public void onCharacteristicRead(BluetoothGatt gatt, BluetoothGattCharacteristic characteristic, int status) {
@@ -195,7 +453,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<Void> write(UUID uuid, byte[] data, int writeType) {
+    public Future<Void> write(UUID uuid, int instanceId, byte[] data, int writeType) {
         SimpleFuture<Void> future = new SimpleFuture<>();
         synchronized (this) {
             if (!this.connected) {
@@ -204,7 +462,7 @@ class Peripheral {
             }
             this.queueCommand(() -> {
                 this.asyncWithFuture(future, () -> {
-                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid);
+                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid, instanceId);
                     characteristic.setValue(data);
                     characteristic.setWriteType(writeType);
                     this.setCommandCallback(new CommandCallback() {
@@ -267,23 +525,20 @@ class Peripheral {
 
                         @Override
                         public void onServicesDiscovered(BluetoothGatt gatt, int status) {
-                            if (status != BluetoothGatt.GATT_SUCCESS) {
-                                throw new RuntimeException("Unable to discover services");
-                            }
+                            Peripheral.this.asyncWithFuture(future, () -> {
+                                if (status != BluetoothGatt.GATT_SUCCESS) {
+                                    Log.w(TAG, "service discovery failed: status=" + status);
+                                    throw new ServiceDiscoveryException(status);
+                                }
 
-                            Peripheral.this.wakeCommand(future, gatt.getServices());
+                                Peripheral.this.wakeCommand(future, gatt.getServices());
+                            });
                         }
                         @Override
                         public void onConnectionStateChange(BluetoothGatt gatt, int status, int newState) {
                             Peripheral.this.asyncWithFuture(future, () -> {
-                                if (status != BluetoothGatt.GATT_SUCCESS) {
-                                    throw new RuntimeException("Disconnected while discovering services");
-                                }
-
                                 if (newState == BluetoothGatt.STATE_DISCONNECTED) {
-                                    Peripheral.this.gatt.close();
-                                    Peripheral.this.gatt = null;
-                                    Peripheral.this.wakeCommand(future, null);
+                                    throw new NotConnectedException();
                                 }
                             });
                         }
@@ -310,7 +565,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<Void> setCharacteristicNotification(UUID uuid, boolean enable) {
+    public Future<Void> setCharacteristicNotification(UUID uuid, int instanceId, boolean enable) {
         SimpleFuture<Void> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -319,13 +574,19 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid);
+                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid, instanceId);
                     if (!this.gatt.setCharacteristicNotification(characteristic, enable)) {
                         throw new RuntimeException("Unable to set characteristic notification");
                     }
 
                     BluetoothGattDescriptor descriptor = characteristic.getDescriptor(CLIENT_CHARACTERISTIC_CONFIGURATION_DESCRIPTOR);
-                    descriptor.setValue(enable ? BluetoothGattDescriptor.ENABLE_NOTIFICATION_VALUE : BluetoothGattDescriptor.DISABLE_NOTIFICATION_VALUE);
+                    byte[] enableValue = BluetoothGattDescriptor.ENABLE_NOTIFICATION_VALUE;
+                    int properties = characteristic.getProperties();
+                    if ((properties & BluetoothGattCharacteristic.PROPERTY_NOTIFY) == 0
+                            && (properties & BluetoothGattCharacteristic.PROPERTY_INDICATE) != 0) {
+                        enableValue = BluetoothGattDescriptor.ENABLE_INDICATION_VALUE;
+                    }
+                    descriptor.setValue(enable ? enableValue : BluetoothGattDescriptor.DISABLE_NOTIFICATION_VALUE);
                     if (!this.gatt.writeDescriptor(descriptor)) {
                         throw new RuntimeException("Unable to write client characteristic configuration descriptor");
                     }
@@ -366,8 +627,8 @@ class Peripheral {
         return future;
     }
 
-    public Stream<BluetoothGattCharacteristic> getNotifications() {
-        QueueStream<BluetoothGattCharacteristic> stream = new QueueStream<>();
+    public Stream<Notification> getNotifications() {
+        QueueStream<Notification> stream = new QueueStream<>();
         synchronized (this) {
             this.notificationStreams.add(new WeakReference<>(stream));
         }
@@ -375,7 +636,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<byte[]> readDescriptor(UUID characteristic, UUID uuid) {
+    public Future<byte[]> readDescriptor(UUID characteristic, UUID uuid, int instanceId) {
         SimpleFuture<byte[]> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -384,7 +645,7 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattDescriptor descriptor = this.getDescriptorByUuid(characteristic, uuid);
+                    BluetoothGattDescriptor descriptor = this.getDescriptorByUuid(characteristic, uuid, instanceId);
                     this.setCommandCallback(new CommandCallback() {
                         @Override
                         public void onDescriptorRead(BluetoothGatt gatt, BluetoothGattDescriptor descriptor, int status) {
@@ -407,7 +668,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<Void> writeDescriptor(UUID characteristic, UUID uuid, byte[] data, int writeType) {
+    public Future<Void> writeDescriptor(UUID characteristic, UUID uuid, int instanceId, byte[] data) {
         SimpleFuture<Void> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -416,7 +677,7 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattDescriptor descriptor = this.getDescriptorByUuid(characteristic, uuid);
+                    BluetoothGattDescriptor descriptor = this.getDescriptorByUuid(characteristic, uuid, instanceId);
                     descriptor.setValue(data);
                     this.setCommandCallback(new CommandCallback() {
                         @Override
@@ -451,9 +712,10 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    private BluetoothGattCharacteristic getCharacteristicByUuid(UUID uuid) {
+    private BluetoothGattCharacteristic getCharacteristicByUuid(UUID uuid, int instanceId) {
         for (BluetoothGattCharacteristic characteristic : this.getCharacteristics()) {
-            if (characteristic.getUuid().equals(uuid)) {
+            if (characteristic.getUuid().equals(uuid)
+                    && (instanceId == NO_INSTANCE_ID || characteristic.getInstanceId() == instanceId)) {
                 return characteristic;
             }
         }
@@ -462,8 +724,22 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    private BluetoothGattDescriptor getDescriptorByUuid(UUID characteristicUuid, UUID uuid) {
-        BluetoothGattCharacteristic characteristic = getCharacteristicByUuid(characteristicUuid);
+    private BluetoothGattDescriptor getDescriptorByUuid(UUID characteristicUuid, UUID uuid, int instanceId) {
+        if (instanceId != NO_INSTANCE_ID) {
+            // Descriptor instance IDs are unique within the whole GATT database, so this also finds
+            // descriptors of characteristics which share their UUID with another characteristic.
+            for (BluetoothGattCharacteristic characteristic : this.getCharacteristics()) {
+                for (BluetoothGattDescriptor descriptor : characteristic.getDescriptors()) {
+                    if (descriptor.getUuid().equals(uuid) && getDescriptorInstanceId(descriptor) == instanceId) {
+                        return descriptor;
+                    }
+                }
+            }
+
+            throw new NoSuchCharacteristicException();
+        }
+
+        BluetoothGattCharacteristic characteristic = getCharacteristicByUuid(characteristicUuid, NO_INSTANCE_ID);
         for (BluetoothGattDescriptor descriptor : characteristic.getDescriptors()) {
             if (descriptor.getUuid().equals(uuid)) {
                 return descriptor;
@@ -473,6 +749,60 @@ class Peripheral {
         throw new NoSuchCharacteristicException();
     }
 
+    /**
+     * BluetoothGattDescriptor.getInstanceId() is hidden from the SDK, so it is called through
+     * reflection. Returns NO_INSTANCE_ID if it isn't accessible.
+     */
+    static int getDescriptorInstanceId(BluetoothGattDescriptor descriptor) {
+        try {
+            return (Integer) BluetoothGattDescriptor.class.getMethod("getInstanceId").invoke(descriptor);
+        } catch (ReflectiveOperationException | ClassCastException ex) {
+            return NO_INSTANCE_ID;
+        }
+    }
+
+    /**
+     * Returns the raw class of device Android cached for a device, or NO_CLASS_OF_DEVICE if it isn't
+     * known or the BLUETOOTH_CONNECT permission is missing. BluetoothClass has no getter for the raw
+     * value, but hashCode() returns it.
+     */
+    @SuppressLint("MissingPermission")
+    static int getClassOfDevice(BluetoothDevice device) {
+        try {
+            BluetoothClass bluetoothClass = device.getBluetoothClass();
+            return bluetoothClass == null ? NO_CLASS_OF_DEVICE : bluetoothClass.hashCode();
+        } catch (SecurityException ex) {
+            return NO_CLASS_OF_DEVICE;
+        }
+    }
+
+    /**
+     * Returns the bond state of a device, or BOND_STATE_UNKNOWN if the BLUETOOTH_CONNECT permission
+     * is missing.
+     */
+    @SuppressLint("MissingPermission")
+    static int getBondState(BluetoothDevice device) {
+        try {
+            return device.getBondState();
+        } catch (SecurityException ex) {
+            return BOND_STATE_UNKNOWN;
+        }
+    }
+
+    private void resetConnectionState() {
+        this.connected = false;
+        this.negotiatedMtu = DEFAULT_MTU;
+        this.mtuRequestPending = false;
+        this.connectionParameters = null;
+        for (WeakReference<QueueStream<Notification>> ref : this.notificationStreams) {
+            QueueStream<Notification> stream = ref.get();
+            if (stream != null) {
+                stream.finish();
+            }
+        }
+        this.notificationStreams.clear();
+    }
+
     private void queueCommand(Runnable callback) {
         if (this.executingCommand) {
             this.commandQueue.add(callback);
@@ -522,9 +852,7 @@ class Peripheral {
                         Peripheral.this.connected = true;
                         break;
                     case BluetoothGatt.STATE_DISCONNECTED:
-                        Peripheral.this.connected = false;
-                        Peripheral.this.negotiatedMtu = DEFAULT_MTU;
-                        Peripheral.this.mtuRequestPending = false;
+                        Peripheral.this.resetConnectionState();
                         break;
                 }
                 if (Peripheral.this.commandCallback != null) {
@@ -570,6 +898,44 @@ class Peripheral {
             }
         }
 
+        @Override
+        public void onPhyRead(BluetoothGatt gatt, int txPhy, int rxPhy, int status) {
+            Log.d(TAG, "gatt callback: onPhyRead txPhy=" + txPhy + " rxPhy=" + rxPhy + " status=" + status);
+            synchronized (Peripheral.this) {
+                if (Peripheral.this.commandCallback != null) {
+                    Peripheral.this.commandCallback.onPhyRead(gatt, txPhy, rxPhy, status);
+                }
+            }
+        }
+
+        // Added in API level 31. Android subscribes to the Service Changed characteristic by itself,
+        // and drops the services it discovered before calling this.
+        @Override
+        public void onServiceChanged(BluetoothGatt gatt) {
+            Log.d(TAG, "gatt callback: onServiceChanged");
+            Peripheral.this.adapter.onServicesChanged(Peripheral.this.device.getAddress());
+        }
+
+        @Override
+        public void onReliableWriteCompleted(BluetoothGatt gatt, int status) {
+            Log.d(TAG, "gatt callback: onReliableWriteCompleted status=" + status);
+            synchronized (Peripheral.this) {
+                if (Peripheral.this.commandCallback != null) {
+                    Peripheral.this.commandCallback.onReliableWriteCompleted(gatt, status);
+                }
+            }
+        }
+
+        // Hidden API (added in API level 26), so it can't be marked @Override.
+        public void onConnectionUpdated(BluetoothGatt gatt, int interval, int latency, int timeout, int status) {
+            Log.d(TAG, "gatt callback: onConnectionUpdated interval=" + interval + " latency=" + latency + " timeout=" + timeout + " status=" + status);
+            if (status == BluetoothGatt.GATT_SUCCESS) {
+                synchronized (Peripheral.this) {
+                    Peripheral.this.connectionParameters = new int[] {interval, latency, timeout};
+                }
+            }
+        }
+
         @Override
         public void onCharacteristicWrite(BluetoothGatt gatt, BluetoothGattCharacteristic characteristic, int status) {
             synchronized (Peripheral.this) {
@@ -590,13 +956,13 @@ class Peripheral {
 
         @Override
         public void onCharacteristicChanged(BluetoothGatt gatt, BluetoothGattCharacteristic characteristic) {
-            BluetoothGattCharacteristic characteristic2 = new BluetoothGattCharacteristic(characteristic.getUuid(), characteristic.getProperties(), characteristic.getPermissions());
-            characteristic2.setValue(characteristic.getValue());
+            byte[] value = characteristic.getValue();
+            Notification notification = new Notification(characteristic, value == null ? new byte[0] : value.clone());
             synchronized (Peripheral.this) {
-                for (WeakReference<QueueStream<BluetoothGattCharacteristic>> ref : Peripheral.this.notificationStreams) {
-                    QueueStream<BluetoothGattCharacteristic> stream = ref.get();
+                for (WeakReference<QueueStream<Notification>> ref : Peripheral.this.notificationStreams) {
+                    QueueStream<Notification> stream = ref.get();
                     if (stream != null) {
-                        stream.add(characteristic2);
+                        stream.add(notification);
                     }
                 }
             }
@@ -1,21 +1,60 @@
 package com.nonpolynomial.btleplug.android.impl;
 
+import android.bluetooth.le.ScanFilter.Builder;
+import android.os.ParcelUuid;
+
+import java.util.ArrayList;
 import java.util.Arrays;
+import java.util.List;
 
 public class ScanFilter {
     private final String[] uuids;
+    private final int[] manufacturerIds;
+    private final byte[][] manufacturerData;
+    private final byte[][] manufacturerMasks;
+    private final String[] addresses;
 
-    public ScanFilter(String uuids[]) {
-        if (uuids == null) {
-            this.uuids = new String[0];
-        } else {
-            int len = uuids.length;
-            this.uuids = Arrays.copyOf(uuids, len);
-        }
+    public ScanFilter(String uuids[], int manufacturerIds[], byte[][] manufacturerData, byte[][] manufacturerMasks, String addresses[]) {
+        this.uuids = uuids == null ? new String[0] : Arrays.copyOf(uuids, uuids.length);
+        this.manufacturerIds = manufacturerIds == null ? new int[0] : Arrays.copyOf(manufacturerIds, manufacturerIds.length);
+        this.manufacturerData = manufacturerData == null ? new byte[0][] : Arrays.copyOf(manufacturerData, manufacturerData.length);
+        this.manufacturerMasks = manufacturerMasks == null ? new byte[0][] : Arrays.copyOf(manufacturerMasks, manufacturerMasks.length);
+        this.addresses = addresses == null ? new String[0] : Arrays.copyOf(addresses, addresses.length);
     }
 
     public String[] getUuids() {
         int len = uuids.length;
         return Arrays.copyOf(uuids, len);
     }
+
+    /**
+     * The Android filters to scan with, or null to scan for all devices.
+     *
+     * Android passes devices which match any of the filters, and each filter matches on one
+     * service, manufacturer data and address at most, so there is one for each combination.
+     */
+    public List<android.bluetooth.le.ScanFilter> toScanFilters() {
+        if (uuids.length == 0 && manufacturerIds.length == 0 && addresses.length == 0) {
+            return null;
+        }
+        List<android.bluetooth.le.ScanFilter> filters = new ArrayList<>();
+        for (int u = 0; u < Math.max(uuids.length, 1); u++) {
+            for (int m = 0; m < Math.max(manufacturerIds.length, 1); m++) {
+                for (int a = 0; a < Math.max(addresses.length, 1); a++) {
+                    Builder builder = new Builder();
+                    if (uuids.length > 0) {
+                        builder.setServiceUuid(ParcelUuid.fromString(uuids[u]));
+                    }
+                    if (manufacturerIds.length > 0) {
+                        builder.setManufacturerData(manufacturerIds[m], manufacturerData[m], manufacturerMasks[m]);
+                    }
+                    if (addresses.length > 0) {
+                        builder.setDeviceAddress(addresses[a]);
+                    }
+                    filters.add(builder.build());
+                }
+            }
+        }
+        return filters;
+    }
 }
@@ -0,0 +1,14 @@
+package com.nonpolynomial.btleplug.android.impl;
+
+class ServiceDiscoveryException extends BluetoothException {
+    private final int status;
+
+    public ServiceDiscoveryException(int status) {
+        super();
+        this.status = status;
+    }
+
+    public int getStatus() {
+        return this.status;
+    }
+}
@@ -0,0 +1,87 @@
+//! Loading of the Java support classes from a dex file embedded in the crate.
+//!
+//! With the `android-embedded-dex` feature, the classes btleplug needs on the Java side (its own
+//! and those of jni-utils) are compiled into `btleplug.dex`, which is included in the library and
+//! loaded at init with an `InMemoryDexClassLoader`. Apps then don't need a Gradle build of the
+//! Java sources. `InMemoryDexClassLoader` requires Android 8.0 (API level 26).
+//!
+//! Classes defined by that loader aren't visible to `FindClass` from arbitrary native code, which
+//! both btleplug and jni-utils use to look up their classes. To get around that, the lookups are
+//! done from within `Bootstrap.nativeInit()`: while a native method runs, `FindClass` resolves
+//! classes through the class loader of the class declaring the method, which is the dex loader.
+
+use super::{load_classes, native_method};
+use crate::{Error, Result};
+use jni::{
+    objects::{JClass, JValue},
+    sys::{jboolean, JNI_FALSE, JNI_TRUE},
+    JNIEnv, NativeMethod,
+};
+use std::ffi::c_void;
+
+/// Built from the Java sources with `./gradlew embeddedDex` in `src/droidplug/java`, and copied
+/// into `OUT_DIR` by the build script.
+static DEX: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/btleplug.dex"));
+
+const BOOTSTRAP_CLASS: &str = "com.nonpolynomial.btleplug.android.impl.Bootstrap";
+
+pub(super) fn load(env: &mut JNIEnv) -> Result<()> {
+    // The loader copies the dex out of the buffer, and never writes to it.
+    let buffer = unsafe { env.new_direct_byte_buffer(DEX.as_ptr() as *mut u8, DEX.len())? };
+    let parent = env
+        .call_static_method(
+            "java/lang/ClassLoader",
+            "getSystemClassLoader",
+            "()Ljava/lang/ClassLoader;",
+            &[],
+        )?
+        .l()?;
+    let loader = env.new_object(
+        "dalvik/system/InMemoryDexClassLoader",
+        "(Ljava/nio/ByteBuffer;Ljava/lang/ClassLoader;)V",
+        &[JValue::from(&buffer), JValue::from(&parent)],
+    )?;
+    let name = env.new_string(BOOTSTRAP_CLASS)?;
+    let bootstrap: JClass = env
+        .call_method(
+            &loader,
+            "loadClass",
+            "(Ljava/lang/String;)Ljava/lang/Class;",
+            &[JValue::from(&name)],
+        )?
+        .l()?
+        .into();
+    env.register_native_methods(
+        &bootstrap,
+        &[NativeMethod {
+            name: "nativeInit".into(),
+            sig: "()Z".into(),
+            fn_ptr: bootstrap_native_init as *mut c_void,
+        }],
+    )?;
+    if env
+        .call_static_method(&bootstrap, "init", "()Z", &[])?
+        .z()?
+    {
+        Ok(())
+    } else {
+        Err(Error::RuntimeError(
+            "Failed to load the embedded Java classes".to_string(),
+        ))
+    }
+}
+
+extern "C" fn bootstrap_native_init(mut env: JNIEnv, _class: JClass) -> jboolean {
+    native_method(
+        &mut env,
+        "nativeInit",
+        JNI_FALSE,
+        |env| match load_classes(env) {
+            Ok(()) => Ok(JNI_TRUE),
+            Err(e) => {
+                log::error!("Failed to load the embedded Java classes: {}", e);
+                Ok(JNI_FALSE)
+            }
+        },
+    )
+}
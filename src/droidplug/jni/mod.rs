@@ -1,90 +1,300 @@
+#[cfg(feature = "android-embedded-dex")]
+mod dex;
 pub mod objects;
 
 use ::jni::{objects::JObject, JNIEnv, JavaVM, NativeMethod};
-use jni::{objects::JString, sys::jboolean};
+use jni::{
+    objects::{JByteArray, JString},
+    sys::{jboolean, jint},
+};
 use once_cell::sync::OnceCell;
 use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
 
 static GLOBAL_JVM: OnceCell<JavaVM> = OnceCell::new();
 
 pub fn init(env: &mut JNIEnv) -> crate::Result<()> {
     if let Ok(()) = GLOBAL_JVM.set(env.get_java_vm()?) {
-        jni_utils::init(env)?;
-        env.register_native_methods(
-            "com/nonpolynomial/btleplug/android/impl/Adapter",
-            &[
-                NativeMethod {
-                    name: "reportScanResult".into(),
-                    sig: "(Landroid/bluetooth/le/ScanResult;)V".into(),
-                    fn_ptr: adapter_report_scan_result as *mut c_void,
-                },
-                NativeMethod {
-                    name: "onConnectionStateChanged".into(),
-                    sig: "(Ljava/lang/String;Z)V".into(),
-                    fn_ptr: adapter_on_connection_state_changed as *mut c_void,
-                },
-            ],
-        )?;
-        jni_utils::classcache::find_add_class(
-            env,
-            "com/nonpolynomial/btleplug/android/impl/Peripheral",
-        )?;
-        jni_utils::classcache::find_add_class(
-            env,
-            "com/nonpolynomial/btleplug/android/impl/ScanFilter",
-        )?;
-        jni_utils::classcache::find_add_class(
-            env,
-            "com/nonpolynomial/btleplug/android/impl/NotConnectedException",
-        )?;
-        jni_utils::classcache::find_add_class(
-            env,
-            "com/nonpolynomial/btleplug/android/impl/PermissionDeniedException",
-        )?;
-        jni_utils::classcache::find_add_class(
-            env,
-            "com/nonpolynomial/btleplug/android/impl/UnexpectedCallbackException",
-        )?;
-        jni_utils::classcache::find_add_class(
-            env,
-            "com/nonpolynomial/btleplug/android/impl/UnexpectedCharacteristicException",
-        )?;
-        jni_utils::classcache::find_add_class(
-            env,
-            "com/nonpolynomial/btleplug/android/impl/NoSuchCharacteristicException",
-        )?;
+        #[cfg(feature = "android-embedded-dex")]
+        dex::load(env)?;
+        #[cfg(not(feature = "android-embedded-dex"))]
+        load_classes(env)?;
     }
     Ok(())
 }
 
+/// Registers the native methods of the Java support classes and caches the classes. The classes
+/// are looked up with `FindClass`, so this must run where they are visible: either in an app which
+/// bundles them, or from within a native method of a class defined by the embedded dex.
+fn load_classes(env: &mut JNIEnv) -> crate::Result<()> {
+    jni_utils::init(env)?;
+    env.register_native_methods(
+        "com/nonpolynomial/btleplug/android/impl/Adapter",
+        &[
+            NativeMethod {
+                name: "reportScanResult".into(),
+                sig: "(Landroid/bluetooth/le/ScanResult;)V".into(),
+                fn_ptr: adapter_report_scan_result as *mut c_void,
+            },
+            NativeMethod {
+                name: "onConnectionStateChanged".into(),
+                sig: "(Ljava/lang/String;Z)V".into(),
+                fn_ptr: adapter_on_connection_state_changed as *mut c_void,
+            },
+            NativeMethod {
+                name: "onServicesChanged".into(),
+                sig: "(Ljava/lang/String;)V".into(),
+                fn_ptr: adapter_on_services_changed as *mut c_void,
+            },
+            NativeMethod {
+                name: "onBondStateChanged".into(),
+                sig: "(Ljava/lang/String;I)V".into(),
+                fn_ptr: adapter_on_bond_state_changed as *mut c_void,
+            },
+            NativeMethod {
+                name: "onPairingRequest".into(),
+                sig: "(Ljava/lang/String;II)V".into(),
+                fn_ptr: adapter_on_pairing_request as *mut c_void,
+            },
+        ],
+    )?;
+    env.register_native_methods(
+        "com/nonpolynomial/btleplug/android/impl/GattServer",
+        &[
+            NativeMethod {
+                name: "onReadRequest".into(),
+                sig: "(Landroid/bluetooth/BluetoothDevice;Ljava/lang/String;ILjava/lang/String;Ljava/lang/String;I)V".into(),
+                fn_ptr: gatt_server_on_read_request as *mut c_void,
+            },
+            NativeMethod {
+                name: "onWriteRequest".into(),
+                sig: "(Landroid/bluetooth/BluetoothDevice;Ljava/lang/String;ILjava/lang/String;Ljava/lang/String;I[BZ)V".into(),
+                fn_ptr: gatt_server_on_write_request as *mut c_void,
+            },
+            NativeMethod {
+                name: "onSubscriptionChanged".into(),
+                sig: "(Ljava/lang/String;Ljava/lang/String;Z)V".into(),
+                fn_ptr: gatt_server_on_subscription_changed as *mut c_void,
+            },
+        ],
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/GattServer",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/Peripheral",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/ScanFilter",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/Notification",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/NotConnectedException",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/PermissionDeniedException",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/UnexpectedCallbackException",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/UnexpectedCharacteristicException",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/NoSuchCharacteristicException",
+    )?;
+    jni_utils::classcache::find_add_class(
+        env,
+        "com/nonpolynomial/btleplug/android/impl/ServiceDiscoveryException",
+    )?;
+    Ok(())
+}
+
 pub fn global_jvm() -> &'static JavaVM {
     GLOBAL_JVM.get().expect(
         "Droidplug has not been initialized. Please initialize it with btleplug::platform::init().",
     )
 }
 
+/// Returns the JNI environment of the current thread, attaching the thread to the JVM first if
+/// needed. Threads stay attached until they exit, so threads which call into Java repeatedly (like
+/// the ones polling notification streams) don't pay for attaching and detaching on every call.
+pub(crate) fn jni_env() -> ::jni::errors::Result<JNIEnv<'static>> {
+    global_jvm().attach_current_thread_permanently()
+}
+
 impl From<::jni::errors::Error> for crate::Error {
     fn from(err: ::jni::errors::Error) -> Self {
         Self::Other(Box::new(err))
     }
 }
 
-extern "C" fn adapter_report_scan_result(env: JNIEnv, obj: JObject, scan_result: JObject) {
-    let mut env = env;
-    let _ = super::adapter::adapter_report_scan_result_internal(&mut env, obj, scan_result);
+/// Runs `f`, turning a panic into [`Error::Panicked`](crate::Error::Panicked).
+pub(crate) fn catch_panic<T>(f: impl FnOnce() -> crate::Result<T>) -> crate::Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(crate::Error::Panicked(message))
+    })
+}
+
+/// Runs the body of a native method. A panic unwinding out of an `extern "C"` function aborts the
+/// whole app, so panics are caught, logged and thrown to the Java caller as a
+/// `java.lang.RuntimeException` instead. Errors are logged, and `default` is returned for both.
+pub(crate) fn native_method<T>(
+    env: &mut JNIEnv,
+    name: &str,
+    default: T,
+    f: impl FnOnce(&mut JNIEnv) -> crate::Result<T>,
+) -> T {
+    match catch_panic(|| f(env)) {
+        Ok(value) => value,
+        Err(crate::Error::Panicked(message)) => {
+            log::error!("{} panicked: {}", name, message);
+            // Don't replace an exception a failed Java call left pending.
+            if !env.exception_check().unwrap_or(true) {
+                let _ = env.throw_new(
+                    "java/lang/RuntimeException",
+                    format!("btleplug panicked in {}: {}", name, message),
+                );
+            }
+            default
+        }
+        Err(e) => {
+            log::debug!("{} failed: {}", name, e);
+            default
+        }
+    }
+}
+
+extern "C" fn adapter_report_scan_result(mut env: JNIEnv, obj: JObject, scan_result: JObject) {
+    native_method(&mut env, "reportScanResult", (), |env| {
+        super::adapter::adapter_report_scan_result_internal(env, obj, scan_result)
+    })
 }
 
 extern "C" fn adapter_on_connection_state_changed(
-    env: JNIEnv,
+    mut env: JNIEnv,
     obj: JObject,
     addr: JString,
     connected: jboolean,
 ) {
-    let mut env = env;
-    let _ = super::adapter::adapter_on_connection_state_changed_internal(
-        &mut env,
-        obj,
-        addr,
-        connected,
-    );
+    native_method(&mut env, "onConnectionStateChanged", (), |env| {
+        super::adapter::adapter_on_connection_state_changed_internal(env, obj, addr, connected)
+    })
+}
+
+extern "C" fn adapter_on_services_changed(mut env: JNIEnv, obj: JObject, addr: JString) {
+    native_method(&mut env, "onServicesChanged", (), |env| {
+        super::adapter::adapter_on_services_changed_internal(env, obj, addr)
+    })
+}
+
+extern "C" fn adapter_on_bond_state_changed(
+    mut env: JNIEnv,
+    obj: JObject,
+    addr: JString,
+    state: jint,
+) {
+    native_method(&mut env, "onBondStateChanged", (), |env| {
+        super::adapter::adapter_on_bond_state_changed_internal(env, obj, addr, state)
+    })
+}
+
+extern "C" fn adapter_on_pairing_request(
+    mut env: JNIEnv,
+    obj: JObject,
+    addr: JString,
+    variant: jint,
+    key: jint,
+) {
+    native_method(&mut env, "onPairingRequest", (), |env| {
+        super::adapter::adapter_on_pairing_request_internal(env, obj, addr, variant, key)
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn gatt_server_on_read_request(
+    mut env: JNIEnv,
+    obj: JObject,
+    device: JObject,
+    address: JString,
+    request_id: jint,
+    service_uuid: JString,
+    characteristic_uuid: JString,
+    offset: jint,
+) {
+    native_method(&mut env, "onReadRequest", (), |env| {
+        super::server::gatt_server_on_read_request_internal(
+            env,
+            obj,
+            device,
+            address,
+            request_id,
+            service_uuid,
+            characteristic_uuid,
+            offset,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn gatt_server_on_write_request(
+    mut env: JNIEnv,
+    obj: JObject,
+    device: JObject,
+    address: JString,
+    request_id: jint,
+    service_uuid: JString,
+    characteristic_uuid: JString,
+    offset: jint,
+    value: JByteArray,
+    response_needed: jboolean,
+) {
+    native_method(&mut env, "onWriteRequest", (), |env| {
+        super::server::gatt_server_on_write_request_internal(
+            env,
+            obj,
+            device,
+            address,
+            request_id,
+            service_uuid,
+            characteristic_uuid,
+            offset,
+            value,
+            response_needed,
+        )
+    })
+}
+
+extern "C" fn gatt_server_on_subscription_changed(
+    mut env: JNIEnv,
+    obj: JObject,
+    service_uuid: JString,
+    characteristic_uuid: JString,
+    subscribed: jboolean,
+) {
+    native_method(&mut env, "onSubscriptionChanged", (), |env| {
+        super::server::gatt_server_on_subscription_changed_internal(
+            env,
+            obj,
+            service_uuid,
+            characteristic_uuid,
+            subscribed,
+        )
+    })
 }
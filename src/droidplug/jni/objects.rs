@@ -1,21 +1,25 @@
 use jni::{
     errors::Result,
-    objects::{JByteArray, JClass, JList, JMap, JMethodID, JObject, JString, JValue},
+    objects::{JByteArray, JClass, JIntArray, JList, JMethodID, JObject, JString, JValue},
     signature::{Primitive, ReturnType},
     strings::JavaStr,
-    sys::jint,
+    sys::{jint, jlong},
     JNIEnv,
 };
 use jni_utils::{future::JFuture, stream::JStream, uuid::JUuid};
-use std::{collections::HashMap, convert::TryFrom, iter::Iterator};
+use std::{convert::TryFrom, iter::Iterator, time::Duration};
 use uuid::Uuid;
 
-use crate::api::{BDAddr, CharPropFlags, PeripheralProperties, ScanFilter};
+use crate::api::{
+    AdvertisementData, BDAddr, CharPropFlags, DescriptorPermissions, Phy, ScanFilter,
+};
+use crate::droidplug::adapter::sdk_int;
 
 pub struct JPeripheral<'a> {
     internal: JObject<'a>,
     connect: JMethodID,
     disconnect: JMethodID,
+    close: JMethodID,
     is_connected: JMethodID,
     discover_services: JMethodID,
     read: JMethodID,
@@ -25,6 +29,15 @@ pub struct JPeripheral<'a> {
     read_descriptor: JMethodID,
     write_descriptor: JMethodID,
     get_mtu: JMethodID,
+    get_bond_state: JMethodID,
+    get_connection_parameters: JMethodID,
+    request_connection_priority: JMethodID,
+    set_preferred_phy: JMethodID,
+    read_phy: JMethodID,
+    begin_reliable_write: JMethodID,
+    execute_reliable_write: JMethodID,
+    abort_reliable_write: JMethodID,
+    respond_to_pairing: JMethodID,
     env: JNIEnv<'a>,
 }
 
@@ -60,15 +73,48 @@ impl<'a> JPeripheral<'a> {
         let connect = env.get_method_id(
             class,
             "connect",
-            "()Lio/github/gedgygedgy/rust/future/Future;",
+            "(IJJZIZI)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let disconnect = env.get_method_id(
             class,
             "disconnect",
             "()Lio/github/gedgygedgy/rust/future/Future;",
         )?;
+        let close = env.get_method_id(
+            class,
+            "close",
+            "()Lio/github/gedgygedgy/rust/future/Future;",
+        )?;
         let is_connected = env.get_method_id(class, "isConnected", "()Z")?;
         let get_mtu = env.get_method_id(class, "getMtu", "()I")?;
+        let get_bond_state = env.get_method_id(class, "getBondState", "()I")?;
+        let get_connection_parameters =
+            env.get_method_id(class, "getConnectionParameters", "()[I")?;
+        let request_connection_priority =
+            env.get_method_id(class, "requestConnectionPriority", "(I)Z")?;
+        let set_preferred_phy = env.get_method_id(class, "setPreferredPhy", "(III)V")?;
+        let read_phy = env.get_method_id(
+            class,
+            "readPhy",
+            "()Lio/github/gedgygedgy/rust/future/Future;",
+        )?;
+        let begin_reliable_write = env.get_method_id(
+            class,
+            "beginReliableWrite",
+            "()Lio/github/gedgygedgy/rust/future/Future;",
+        )?;
+        let execute_reliable_write = env.get_method_id(
+            class,
+            "executeReliableWrite",
+            "()Lio/github/gedgygedgy/rust/future/Future;",
+        )?;
+        let abort_reliable_write = env.get_method_id(
+            class,
+            "abortReliableWrite",
+            "()Lio/github/gedgygedgy/rust/future/Future;",
+        )?;
+        let respond_to_pairing =
+            env.get_method_id(class, "respondToPairing", "(Ljava/lang/String;Z)Z")?;
         let discover_services = env.get_method_id(
             class,
             "discoverServices",
@@ -77,17 +123,17 @@ impl<'a> JPeripheral<'a> {
         let read = env.get_method_id(
             class,
             "read",
-            "(Ljava/util/UUID;)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;I)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let write = env.get_method_id(
             class,
             "write",
-            "(Ljava/util/UUID;[BI)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;I[BI)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let set_characteristic_notification = env.get_method_id(
             class,
             "setCharacteristicNotification",
-            "(Ljava/util/UUID;Z)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;IZ)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let get_notifications = env.get_method_id(
             class,
@@ -97,17 +143,18 @@ impl<'a> JPeripheral<'a> {
         let read_descriptor = env.get_method_id(
             class,
             "readDescriptor",
-            "(Ljava/util/UUID;Ljava/util/UUID;)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;Ljava/util/UUID;I)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let write_descriptor = env.get_method_id(
             class,
             "writeDescriptor",
-            "(Ljava/util/UUID;Ljava/util/UUID;[BI)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;Ljava/util/UUID;I[B)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         Ok(Self {
             internal: obj,
             connect,
             disconnect,
+            close,
             is_connected,
             discover_services,
             read,
@@ -117,6 +164,15 @@ impl<'a> JPeripheral<'a> {
             read_descriptor,
             write_descriptor,
             get_mtu,
+            get_bond_state,
+            get_connection_parameters,
+            request_connection_priority,
+            set_preferred_phy,
+            read_phy,
+            begin_reliable_write,
+            execute_reliable_write,
+            abort_reliable_write,
+            respond_to_pairing,
             env: unsafe { env.unsafe_clone() },
         })
     }
@@ -140,10 +196,28 @@ impl<'a> JPeripheral<'a> {
         Self::from_env_impl(env, obj)
     }
 
-    pub fn connect(&self) -> Result<JFuture<'a>> {
+    pub fn connect(
+        &self,
+        max_attempts: jint,
+        initial_backoff_millis: jlong,
+        max_backoff_millis: jlong,
+        toggle_auto_connect: bool,
+        transport: jint,
+        auto_connect: bool,
+        phy: jint,
+    ) -> Result<JFuture<'a>> {
         let mut env = unsafe { self.env.unsafe_clone() };
+        let args = [
+            JValue::from(max_attempts).as_jni(),
+            JValue::from(initial_backoff_millis).as_jni(),
+            JValue::from(max_backoff_millis).as_jni(),
+            JValue::from(toggle_auto_connect).as_jni(),
+            JValue::from(transport).as_jni(),
+            JValue::from(auto_connect).as_jni(),
+            JValue::from(phy).as_jni(),
+        ];
         let future_obj = unsafe {
-            env.call_method_unchecked(&self.internal, self.connect, ReturnType::Object, &[])
+            env.call_method_unchecked(&self.internal, self.connect, ReturnType::Object, &args)
         }?
         .l()?;
         JFuture::from_env(&mut env, future_obj)
@@ -159,6 +233,15 @@ impl<'a> JPeripheral<'a> {
         JFuture::from_env(&mut env, future_obj)
     }
 
+    pub fn close(&self) -> Result<JFuture<'a>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let future_obj = unsafe {
+            env.call_method_unchecked(&self.internal, self.close, ReturnType::Object, &[])
+        }?
+        .l()?;
+        JFuture::from_env(&mut env, future_obj)
+    }
+
     pub fn is_connected(&self) -> Result<bool> {
         let mut env = unsafe { self.env.unsafe_clone() };
         unsafe {
@@ -185,6 +268,124 @@ impl<'a> JPeripheral<'a> {
         .i()
     }
 
+    /// Returns whether the device is bonded, or `None` if the BLUETOOTH_CONNECT permission is
+    /// missing.
+    pub fn is_bonded(&self) -> Result<Option<bool>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let bond_state = unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.get_bond_state,
+                ReturnType::Primitive(Primitive::Int),
+                &[],
+            )
+        }?
+        .i()?;
+        Ok(is_bonded(bond_state))
+    }
+
+    /// Returns the connection interval, latency and supervision timeout in HCI units.
+    pub fn get_connection_parameters(&self) -> Result<Option<[jint; 3]>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let obj = unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.get_connection_parameters,
+                ReturnType::Array,
+                &[],
+            )
+        }?
+        .l()?;
+        if env.is_same_object(&obj, JObject::null())? {
+            return Ok(None);
+        }
+        let mut parameters = [0; 3];
+        env.get_int_array_region(JIntArray::from(obj), 0, &mut parameters)?;
+        Ok(Some(parameters))
+    }
+
+    /// Returns whether Android accepted the request.
+    pub fn request_connection_priority(&self, priority: jint) -> Result<bool> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.request_connection_priority,
+                ReturnType::Primitive(Primitive::Boolean),
+                &[JValue::from(priority).as_jni()],
+            )
+        }?
+        .z()
+    }
+
+    /// `tx` and `rx` are masks of the `PHY_LE_*_MASK` constants, `options` a `PHY_OPTION_*`.
+    pub fn set_preferred_phy(&self, tx: jint, rx: jint, options: jint) -> Result<()> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let args = [
+            JValue::from(tx).as_jni(),
+            JValue::from(rx).as_jni(),
+            JValue::from(options).as_jni(),
+        ];
+        unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.set_preferred_phy,
+                ReturnType::Primitive(Primitive::Void),
+                &args,
+            )
+        }?
+        .v()
+    }
+
+    /// The future resolves to the transmitter and receiver PHY, as `PHY_LE_*` constants.
+    pub fn read_phy(&self) -> Result<JFuture<'a>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let future_obj = unsafe {
+            env.call_method_unchecked(&self.internal, self.read_phy, ReturnType::Object, &[])
+        }?
+        .l()?;
+        JFuture::from_env(&mut env, future_obj)
+    }
+
+    pub fn begin_reliable_write(&self) -> Result<JFuture<'a>> {
+        self.reliable_write_call(self.begin_reliable_write)
+    }
+
+    pub fn execute_reliable_write(&self) -> Result<JFuture<'a>> {
+        self.reliable_write_call(self.execute_reliable_write)
+    }
+
+    pub fn abort_reliable_write(&self) -> Result<JFuture<'a>> {
+        self.reliable_write_call(self.abort_reliable_write)
+    }
+
+    fn reliable_write_call(&self, method: JMethodID) -> Result<JFuture<'a>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let future_obj =
+            unsafe { env.call_method_unchecked(&self.internal, method, ReturnType::Object, &[]) }?
+                .l()?;
+        JFuture::from_env(&mut env, future_obj)
+    }
+
+    /// Enters `pin` if it is given, otherwise confirms or rejects the pairing. Returns whether
+    /// Android accepted the answer.
+    pub fn respond_to_pairing(&self, pin: Option<&str>, confirm: bool) -> Result<bool> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let pin = match pin {
+            Some(pin) => JObject::from(env.new_string(pin)?),
+            None => JObject::null(),
+        };
+        unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.respond_to_pairing,
+                ReturnType::Primitive(Primitive::Boolean),
+                &[JValue::from(&pin).as_jni(), JValue::from(confirm).as_jni()],
+            )
+        }?
+        .z()
+    }
+
     pub fn discover_services(&self) -> Result<JFuture<'a>> {
         let mut env = unsafe { self.env.unsafe_clone() };
         let future_obj = unsafe {
@@ -199,10 +400,13 @@ impl<'a> JPeripheral<'a> {
         JFuture::from_env(&mut env, future_obj)
     }
 
-    pub fn read(&self, uuid: JUuid<'a>) -> Result<JFuture<'a>> {
+    pub fn read(&self, uuid: JUuid<'a>, instance_id: jint) -> Result<JFuture<'a>> {
         let mut env = unsafe { self.env.unsafe_clone() };
         let uuid_obj: JObject = uuid.into();
-        let args = [JValue::from(&uuid_obj).as_jni()];
+        let args = [
+            JValue::from(&uuid_obj).as_jni(),
+            JValue::from(instance_id).as_jni(),
+        ];
         let future_obj = unsafe {
             env.call_method_unchecked(&self.internal, self.read, ReturnType::Object, &args)
         }?
@@ -213,6 +417,7 @@ impl<'a> JPeripheral<'a> {
     pub fn write(
         &self,
         uuid: JUuid<'a>,
+        instance_id: jint,
         data: JObject<'a>,
         write_type: jint,
     ) -> Result<JFuture<'a>> {
@@ -220,6 +425,7 @@ impl<'a> JPeripheral<'a> {
         let uuid_obj: JObject = uuid.into();
         let args = [
             JValue::from(&uuid_obj).as_jni(),
+            JValue::from(instance_id).as_jni(),
             JValue::from(&data).as_jni(),
             JValue::from(write_type).as_jni(),
         ];
@@ -233,12 +439,14 @@ impl<'a> JPeripheral<'a> {
     pub fn set_characteristic_notification(
         &self,
         uuid: JUuid<'a>,
+        instance_id: jint,
         enable: bool,
     ) -> Result<JFuture<'a>> {
         let mut env = unsafe { self.env.unsafe_clone() };
         let uuid_obj: JObject = uuid.into();
         let args = [
             JValue::from(&uuid_obj).as_jni(),
+            JValue::from(instance_id).as_jni(),
             JValue::from(enable).as_jni(),
         ];
         let future_obj = unsafe {
@@ -271,6 +479,7 @@ impl<'a> JPeripheral<'a> {
         &self,
         characteristic: JUuid<'a>,
         uuid: JUuid<'a>,
+        instance_id: jint,
     ) -> Result<JFuture<'a>> {
         let mut env = unsafe { self.env.unsafe_clone() };
         let characteristic_obj: JObject = characteristic.into();
@@ -278,6 +487,7 @@ impl<'a> JPeripheral<'a> {
         let args = [
             JValue::from(&characteristic_obj).as_jni(),
             JValue::from(&uuid_obj).as_jni(),
+            JValue::from(instance_id).as_jni(),
         ];
         let future_obj = unsafe {
             env.call_method_unchecked(
@@ -295,6 +505,7 @@ impl<'a> JPeripheral<'a> {
         &self,
         characteristic: JUuid<'a>,
         uuid: JUuid<'a>,
+        instance_id: jint,
         data: JObject<'a>,
     ) -> Result<JFuture<'a>> {
         let mut env = unsafe { self.env.unsafe_clone() };
@@ -303,6 +514,7 @@ impl<'a> JPeripheral<'a> {
         let args = [
             JValue::from(&characteristic_obj).as_jni(),
             JValue::from(&uuid_obj).as_jni(),
+            JValue::from(instance_id).as_jni(),
             JValue::from(&data).as_jni(),
         ];
         let future_obj = unsafe {
@@ -321,41 +533,64 @@ impl<'a> JPeripheral<'a> {
 pub struct JBluetoothGattService<'a> {
     internal: JObject<'a>,
     get_uuid: JMethodID,
-    //is_primary: JMethodID<'a>,
+    get_type: JMethodID,
+    get_instance_id: JMethodID,
     get_characteristics: JMethodID,
+    get_included_services: JMethodID,
     env: JNIEnv<'a>,
 }
 
 impl<'a> JBluetoothGattService<'a> {
+    /// `BluetoothGattService.SERVICE_TYPE_PRIMARY`
+    const SERVICE_TYPE_PRIMARY: i32 = 0;
+
     pub fn from_env(env: &mut JNIEnv<'a>, obj: JObject<'a>) -> Result<Self> {
         let class = env.find_class("android/bluetooth/BluetoothGattService")?;
         let class = env.auto_local(class);
 
         let get_uuid = env.get_method_id(&class, "getUuid", "()Ljava/util/UUID;")?;
-        //let is_primary = env.get_method_id(&class, "isPrimary", "()Z;")?;
+        let get_type = env.get_method_id(&class, "getType", "()I")?;
+        let get_instance_id = env.get_method_id(&class, "getInstanceId", "()I")?;
         let get_characteristics =
             env.get_method_id(&class, "getCharacteristics", "()Ljava/util/List;")?;
+        let get_included_services =
+            env.get_method_id(&class, "getIncludedServices", "()Ljava/util/List;")?;
         Ok(Self {
             internal: obj,
             get_uuid,
-            //is_primary,
+            get_type,
+            get_instance_id,
             get_characteristics,
+            get_included_services,
             env: unsafe { env.unsafe_clone() },
         })
     }
 
     pub fn is_primary(&self) -> Result<bool> {
-        /*
-        self.env
-        .call_method_unchecked(
-            self.internal,
-            self.is_primary,
-            JavaType::Primitive(Primitive::Boolean),
-            &[],
-        )?
-        .z()
-        */
-        Ok(true)
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let service_type = unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.get_type,
+                ReturnType::Primitive(Primitive::Int),
+                &[],
+            )
+        }?
+        .i()?;
+        Ok(service_type == Self::SERVICE_TYPE_PRIMARY)
+    }
+
+    pub fn get_instance_id(&self) -> Result<jint> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.get_instance_id,
+                ReturnType::Primitive(Primitive::Int),
+                &[],
+            )
+        }?
+        .i()
     }
 
     pub fn get_uuid(&self) -> Result<Uuid> {
@@ -387,11 +622,33 @@ impl<'a> JBluetoothGattService<'a> {
         }
         Ok(chr_vec)
     }
+
+    pub fn get_included_services(&self) -> Result<Vec<JBluetoothGattService<'_>>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let obj = unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.get_included_services,
+                ReturnType::Object,
+                &[],
+            )
+        }?
+        .l()?;
+        let service_list = JList::from_env(&mut env, &obj)?;
+        let mut service_vec = vec![];
+        let mut iter = service_list.iter(&mut env)?;
+        while let Some(service) = iter.next(&mut env)? {
+            service_vec.push(JBluetoothGattService::from_env(&mut env, service)?);
+        }
+        Ok(service_vec)
+    }
 }
 
 pub struct JBluetoothGattCharacteristic<'a> {
     internal: JObject<'a>,
+    get_service: JMethodID,
     get_uuid: JMethodID,
+    get_instance_id: JMethodID,
     get_properties: JMethodID,
     get_value: JMethodID,
     get_descriptors: JMethodID,
@@ -403,13 +660,21 @@ impl<'a> JBluetoothGattCharacteristic<'a> {
         let class = env.find_class("android/bluetooth/BluetoothGattCharacteristic")?;
         let class = env.auto_local(class);
 
+        let get_service = env.get_method_id(
+            &class,
+            "getService",
+            "()Landroid/bluetooth/BluetoothGattService;",
+        )?;
         let get_uuid = env.get_method_id(&class, "getUuid", "()Ljava/util/UUID;")?;
+        let get_instance_id = env.get_method_id(&class, "getInstanceId", "()I")?;
         let get_properties = env.get_method_id(&class, "getProperties", "()I")?;
         let get_descriptors = env.get_method_id(&class, "getDescriptors", "()Ljava/util/List;")?;
         let get_value = env.get_method_id(&class, "getValue", "()[B")?;
         Ok(Self {
             internal: obj,
+            get_service,
             get_uuid,
+            get_instance_id,
             get_properties,
             get_value,
             get_descriptors,
@@ -427,6 +692,29 @@ impl<'a> JBluetoothGattCharacteristic<'a> {
         Ok(uuid_obj.as_uuid()?)
     }
 
+    pub fn get_instance_id(&self) -> Result<jint> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.get_instance_id,
+                ReturnType::Primitive(Primitive::Int),
+                &[],
+            )
+        }?
+        .i()
+    }
+
+    /// The UUID of the service the characteristic belongs to.
+    pub fn get_service_uuid(&self) -> Result<Uuid> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let obj = unsafe {
+            env.call_method_unchecked(&self.internal, self.get_service, ReturnType::Object, &[])
+        }?
+        .l()?;
+        JBluetoothGattService::from_env(&mut env, obj)?.get_uuid()
+    }
+
     pub fn get_properties(&self) -> Result<CharPropFlags> {
         let mut env = unsafe { self.env.unsafe_clone() };
         let flags = unsafe {
@@ -471,9 +759,64 @@ impl<'a> JBluetoothGattCharacteristic<'a> {
     }
 }
 
+/// A value notified or indicated by a peripheral, with the characteristic it belongs to.
+pub struct JNotification<'a> {
+    internal: JObject<'a>,
+    get_characteristic: JMethodID,
+    get_value: JMethodID,
+    env: JNIEnv<'a>,
+}
+
+impl<'a> JNotification<'a> {
+    pub fn from_env(env: &mut JNIEnv<'a>, obj: JObject<'a>) -> Result<Self> {
+        let class_static = jni_utils::classcache::get_class(
+            "com/nonpolynomial/btleplug/android/impl/Notification",
+        )
+        .unwrap();
+        let class = <&JClass>::from(class_static.as_obj());
+
+        let get_characteristic = env.get_method_id(
+            class,
+            "getCharacteristic",
+            "()Landroid/bluetooth/BluetoothGattCharacteristic;",
+        )?;
+        let get_value = env.get_method_id(class, "getValue", "()[B")?;
+        Ok(Self {
+            internal: obj,
+            get_characteristic,
+            get_value,
+            env: unsafe { env.unsafe_clone() },
+        })
+    }
+
+    pub fn get_characteristic(&self) -> Result<JBluetoothGattCharacteristic<'a>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let obj = unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.get_characteristic,
+                ReturnType::Object,
+                &[],
+            )
+        }?
+        .l()?;
+        JBluetoothGattCharacteristic::from_env(&mut env, obj)
+    }
+
+    pub fn get_value(&self) -> Result<Vec<u8>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let value = unsafe {
+            env.call_method_unchecked(&self.internal, self.get_value, ReturnType::Array, &[])
+        }?
+        .l()?;
+        jni_utils::arrays::byte_array_to_vec(&mut env, JByteArray::from(value))
+    }
+}
+
 pub struct JBluetoothGattDescriptor<'a> {
     internal: JObject<'a>,
     get_uuid: JMethodID,
+    get_permissions: JMethodID,
     env: JNIEnv<'a>,
 }
 
@@ -483,9 +826,11 @@ impl<'a> JBluetoothGattDescriptor<'a> {
         let class = env.auto_local(class);
 
         let get_uuid = env.get_method_id(&class, "getUuid", "()Ljava/util/UUID;")?;
+        let get_permissions = env.get_method_id(&class, "getPermissions", "()I")?;
         Ok(Self {
             internal: obj,
             get_uuid,
+            get_permissions,
             env: unsafe { env.unsafe_clone() },
         })
     }
@@ -499,6 +844,38 @@ impl<'a> JBluetoothGattDescriptor<'a> {
         let uuid_obj = JUuid::from_env(&mut env, obj)?;
         Ok(uuid_obj.as_uuid()?)
     }
+
+    pub fn get_permissions(&self) -> Result<DescriptorPermissions> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let permissions = unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                self.get_permissions,
+                ReturnType::Primitive(Primitive::Int),
+                &[],
+            )
+        }?
+        .i()?;
+        Ok(DescriptorPermissions::from_bits_truncate(
+            permissions as u16,
+        ))
+    }
+
+    /// Returns the instance ID, or -1 if the hidden `getInstanceId()` can't be called.
+    pub fn get_instance_id(&self) -> Result<jint> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let class_static =
+            jni_utils::classcache::get_class("com/nonpolynomial/btleplug/android/impl/Peripheral")
+                .unwrap();
+        let class = <&JClass>::from(class_static.as_obj());
+        env.call_static_method(
+            class,
+            "getDescriptorInstanceId",
+            "(Landroid/bluetooth/BluetoothGattDescriptor;)I",
+            &[JValue::from(&self.internal)],
+        )?
+        .i()
+    }
 }
 
 pub struct JBluetoothDevice<'a> {
@@ -528,6 +905,55 @@ impl<'a> JBluetoothDevice<'a> {
         .l()?;
         Ok(obj.into())
     }
+
+    /// Returns whether the device is bonded, or `None` if the BLUETOOTH_CONNECT permission is
+    /// missing.
+    pub fn is_bonded(&self) -> Result<Option<bool>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let class_static =
+            jni_utils::classcache::get_class("com/nonpolynomial/btleplug/android/impl/Peripheral")
+                .unwrap();
+        let class = <&JClass>::from(class_static.as_obj());
+        let bond_state = env
+            .call_static_method(
+                class,
+                "getBondState",
+                "(Landroid/bluetooth/BluetoothDevice;)I",
+                &[JValue::from(&self.internal)],
+            )?
+            .i()?;
+        Ok(is_bonded(bond_state))
+    }
+
+    /// Returns the class of device Android cached for the device, if it's known.
+    pub fn get_class_of_device(&self) -> Result<Option<u32>> {
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let class_static =
+            jni_utils::classcache::get_class("com/nonpolynomial/btleplug/android/impl/Peripheral")
+                .unwrap();
+        let class = <&JClass>::from(class_static.as_obj());
+        let class_of_device = env
+            .call_static_method(
+                class,
+                "getClassOfDevice",
+                "(Landroid/bluetooth/BluetoothDevice;)I",
+                &[JValue::from(&self.internal)],
+            )?
+            .i()?;
+        // LE-only devices are reported with an empty class rather than none.
+        Ok(u32::try_from(class_of_device)
+            .ok()
+            .filter(|&class| class != 0))
+    }
+}
+
+/// `BluetoothDevice.BOND_BONDED`.
+const BOND_BONDED: jint = 12;
+
+/// Whether a bond state returned by the Java side means the device is bonded, or `None` if the
+/// state couldn't be read.
+fn is_bonded(bond_state: jint) -> Option<bool> {
+    (bond_state >= 0).then_some(bond_state == BOND_BONDED)
 }
 
 pub struct JScanFilter<'a> {
@@ -538,11 +964,41 @@ impl<'a> JScanFilter<'a> {
     pub fn new(env: &mut JNIEnv<'a>, filter: ScanFilter) -> Result<Self> {
         let string_class = env.find_class("java/lang/String")?;
         let uuids =
-            env.new_object_array(filter.services.len() as i32, string_class, JObject::null())?;
+            env.new_object_array(filter.services.len() as i32, &string_class, JObject::null())?;
         for (idx, uuid) in filter.services.into_iter().enumerate() {
             let uuid_str = env.new_string(uuid.to_string())?;
             env.set_object_array_element(&uuids, idx as i32, uuid_str)?;
         }
+        let count = filter.manufacturer_data.len() as i32;
+        let byte_array_class = env.find_class("[B")?;
+        let manufacturer_ids = env.new_int_array(count)?;
+        let manufacturer_data = env.new_object_array(count, &byte_array_class, JObject::null())?;
+        let manufacturer_masks = env.new_object_array(count, &byte_array_class, JObject::null())?;
+        for (idx, manufacturer) in filter.manufacturer_data.into_iter().enumerate() {
+            env.set_int_array_region(
+                &manufacturer_ids,
+                idx as i32,
+                &[manufacturer.company_id.into()],
+            )?;
+            let data = jni_utils::arrays::slice_to_byte_array(env, &manufacturer.data)?;
+            env.set_object_array_element(&manufacturer_data, idx as i32, data)?;
+            // Android wants the mask to be as long as the data, or no mask at all.
+            if !manufacturer.mask.is_empty() {
+                let mut mask = manufacturer.mask;
+                mask.resize(manufacturer.data.len(), 0xff);
+                let mask = jni_utils::arrays::slice_to_byte_array(env, &mask)?;
+                env.set_object_array_element(&manufacturer_masks, idx as i32, mask)?;
+            }
+        }
+        let addresses = env.new_object_array(
+            filter.addresses.len() as i32,
+            &string_class,
+            JObject::null(),
+        )?;
+        for (idx, address) in filter.addresses.into_iter().enumerate() {
+            let address = env.new_string(format!("{:X}", address))?;
+            env.set_object_array_element(&addresses, idx as i32, address)?;
+        }
         let obj = env.new_object(
             <&JClass>::from(
                 jni_utils::classcache::get_class(
@@ -552,8 +1008,14 @@ impl<'a> JScanFilter<'a> {
                 .as_obj(),
             ),
             //class.as_obj(),
-            "([Ljava/lang/String;)V",
-            &[JValue::from(&uuids)],
+            "([Ljava/lang/String;[I[[B[[B[Ljava/lang/String;)V",
+            &[
+                JValue::from(&uuids),
+                JValue::from(&manufacturer_ids),
+                JValue::from(&manufacturer_data),
+                JValue::from(&manufacturer_masks),
+                JValue::from(&addresses),
+            ],
         )?;
         Ok(Self { internal: obj })
     }
@@ -565,15 +1027,96 @@ impl<'a> From<JScanFilter<'a>> for JObject<'a> {
     }
 }
 
+/// An `android.bluetooth.le.AdvertiseData`.
+pub struct JAdvertiseData<'a> {
+    internal: JObject<'a>,
+}
+
+impl<'a> JAdvertiseData<'a> {
+    const BUILDER: &'static str = "Landroid/bluetooth/le/AdvertiseData$Builder;";
+
+    pub fn new(env: &mut JNIEnv<'a>, data: &AdvertisementData) -> Result<Self> {
+        let builder = env.new_object("android/bluetooth/le/AdvertiseData$Builder", "()V", &[])?;
+        // Apps can't choose the name, only whether the one of the adapter is included.
+        env.call_method(
+            &builder,
+            "setIncludeDeviceName",
+            format!("(Z){}", Self::BUILDER),
+            &[JValue::from(data.local_name.is_some())],
+        )?;
+        for uuid in &data.service_uuids {
+            let uuid = parcel_uuid(env, *uuid)?;
+            env.call_method(
+                &builder,
+                "addServiceUuid",
+                format!("(Landroid/os/ParcelUuid;){}", Self::BUILDER),
+                &[JValue::from(&uuid)],
+            )?;
+        }
+        for (&company, value) in &data.manufacturer_data {
+            let value = jni_utils::arrays::slice_to_byte_array(env, value)?;
+            env.call_method(
+                &builder,
+                "addManufacturerData",
+                format!("(I[B){}", Self::BUILDER),
+                &[JValue::from(jint::from(company)), JValue::from(&value)],
+            )?;
+        }
+        for (uuid, value) in &data.service_data {
+            let uuid = parcel_uuid(env, *uuid)?;
+            let value = jni_utils::arrays::slice_to_byte_array(env, value)?;
+            env.call_method(
+                &builder,
+                "addServiceData",
+                format!("(Landroid/os/ParcelUuid;[B){}", Self::BUILDER),
+                &[JValue::from(&uuid), JValue::from(&value)],
+            )?;
+        }
+        let internal = env
+            .call_method(
+                &builder,
+                "build",
+                "()Landroid/bluetooth/le/AdvertiseData;",
+                &[],
+            )?
+            .l()?;
+        Ok(Self { internal })
+    }
+}
+
+impl<'a> From<JAdvertiseData<'a>> for JObject<'a> {
+    fn from(value: JAdvertiseData<'a>) -> Self {
+        value.internal
+    }
+}
+
+fn parcel_uuid<'a>(env: &mut JNIEnv<'a>, uuid: Uuid) -> Result<JObject<'a>> {
+    let uuid: JObject = JUuid::new(env, uuid)?.into();
+    env.new_object(
+        "android/os/ParcelUuid",
+        "(Ljava/util/UUID;)V",
+        &[JValue::from(&uuid)],
+    )
+}
+
 pub struct JScanResult<'a> {
     internal: JObject<'a>,
     get_device: JMethodID,
     get_scan_record: JMethodID,
     get_tx_power: JMethodID,
     get_rssi: JMethodID,
+    extended: Option<ExtendedScanResultMethods>,
     env: JNIEnv<'a>,
 }
 
+/// The methods of `ScanResult` about extended advertisements, which were added in API level 26.
+struct ExtendedScanResultMethods {
+    is_legacy: JMethodID,
+    get_secondary_phy: JMethodID,
+    get_advertising_sid: JMethodID,
+    get_periodic_advertising_interval: JMethodID,
+}
+
 impl<'a> JScanResult<'a> {
     pub fn from_env(env: &mut JNIEnv<'a>, obj: JObject<'a>) -> Result<Self> {
         let class = env.find_class("android/bluetooth/le/ScanResult")?;
@@ -588,12 +1131,27 @@ impl<'a> JScanResult<'a> {
         )?;
         let get_tx_power = env.get_method_id(&class, "getTxPower", "()I")?;
         let get_rssi = env.get_method_id(&class, "getRssi", "()I")?;
+        let extended = if sdk_int()? >= 26 {
+            Some(ExtendedScanResultMethods {
+                is_legacy: env.get_method_id(&class, "isLegacy", "()Z")?,
+                get_secondary_phy: env.get_method_id(&class, "getSecondaryPhy", "()I")?,
+                get_advertising_sid: env.get_method_id(&class, "getAdvertisingSid", "()I")?,
+                get_periodic_advertising_interval: env.get_method_id(
+                    &class,
+                    "getPeriodicAdvertisingInterval",
+                    "()I",
+                )?,
+            })
+        } else {
+            None
+        };
         Ok(Self {
             internal: obj,
             get_device,
             get_scan_record,
             get_tx_power,
             get_rssi,
+            extended,
             env: unsafe { env.unsafe_clone() },
         })
     }
@@ -635,11 +1193,41 @@ impl<'a> JScanResult<'a> {
     }
 
     pub fn get_rssi(&self) -> Result<jint> {
+        self.call_int_method(self.get_rssi)
+    }
+
+    /// Returns the secondary PHY, advertising SID and periodic advertising interval of an extended
+    /// advertisement, or `None` for legacy advertisements and before API level 26.
+    pub fn get_extended_advertising(&self) -> Result<Option<(jint, jint, jint)>> {
+        let Some(methods) = &self.extended else {
+            return Ok(None);
+        };
+        let mut env = unsafe { self.env.unsafe_clone() };
+        let is_legacy = unsafe {
+            env.call_method_unchecked(
+                &self.internal,
+                methods.is_legacy,
+                ReturnType::Primitive(Primitive::Boolean),
+                &[],
+            )
+        }?
+        .z()?;
+        if is_legacy {
+            return Ok(None);
+        }
+        Ok(Some((
+            self.call_int_method(methods.get_secondary_phy)?,
+            self.call_int_method(methods.get_advertising_sid)?,
+            self.call_int_method(methods.get_periodic_advertising_interval)?,
+        )))
+    }
+
+    fn call_int_method(&self, method: JMethodID) -> Result<jint> {
         let mut env = unsafe { self.env.unsafe_clone() };
         unsafe {
             env.call_method_unchecked(
                 &self.internal,
-                self.get_rssi,
+                method,
                 ReturnType::Primitive(Primitive::Int),
                 &[],
             )
@@ -648,7 +1236,22 @@ impl<'a> JScanResult<'a> {
     }
 }
 
-impl<'a> TryFrom<JScanResult<'a>> for (BDAddr, Option<PeripheralProperties>) {
+/// The parts of a scan result btleplug needs, read without walking any of the Java collections of
+/// the scan record. The raw advertising data is parsed on the Rust side, and only when it changed.
+pub struct ScanReport {
+    pub address: BDAddr,
+    pub rssi: i16,
+    pub tx_power_level: Option<i16>,
+    pub class: Option<u32>,
+    pub is_paired: Option<bool>,
+    /// The raw advertising data, or `None` if the result has no scan record.
+    pub advertising_data: Option<Vec<u8>>,
+    pub secondary_phy: Option<Phy>,
+    pub advertising_sid: Option<u8>,
+    pub periodic_advertising_interval: Option<Duration>,
+}
+
+impl<'a> TryFrom<JScanResult<'a>> for ScanReport {
     type Error = crate::Error;
 
     fn try_from(result: JScanResult<'a>) -> std::result::Result<Self, Self::Error> {
@@ -658,152 +1261,62 @@ impl<'a> TryFrom<JScanResult<'a>> for (BDAddr, Option<PeripheralProperties>) {
 
         let addr_obj = device.get_address()?;
         let addr_str = JavaStr::from_env(&result.env, &addr_obj)?;
-        let addr = BDAddr::from_str(
+        let address = BDAddr::from_str(
             addr_str
                 .to_str()
                 .map_err(|e| Self::Error::Other(e.into()))?,
         )?;
 
+        let tx_power_level = result.get_tx_power()?;
+        const TX_POWER_NOT_PRESENT: jint = 127; // from ScanResult documentation
+        let tx_power_level = if tx_power_level == TX_POWER_NOT_PRESENT {
+            None
+        } else {
+            Some(tx_power_level as i16)
+        };
+
         let record = result.get_scan_record()?;
         let record_obj: &JObject = &record;
-        let properties = if result.env.is_same_object(record_obj, JObject::null())? {
+        let advertising_data = if result.env.is_same_object(record_obj, JObject::null())? {
             None
         } else {
-            let device_name_obj = record.get_device_name()?;
-            let device_name = if result
-                .env
-                .is_same_object(&device_name_obj, JObject::null())?
-            {
-                None
-            } else {
-                let device_name_str = JavaStr::from_env(&result.env, &device_name_obj)?;
-                // On Android, there is a chance that a device name may not actually be valid UTF-8.
-                // We're given the full buffer, regardless of if it's just UTF-8 characters,
-                // possibly c str with null characters, or whatever. We should try UTF-8 first, if
-                // that doesn't work out, see if there's a null termination character in it and try
-                // parsing that.
-                Some(
-                    String::from_utf8_lossy(device_name_str.to_bytes())
-                        .chars()
-                        .filter(|&c| c != '\u{fffd}')
-                        .collect(),
-                )
-            };
-
-            let tx_power_level = result.get_tx_power()?;
-            const TX_POWER_NOT_PRESENT: jint = 127; // from ScanResult documentation
-            let tx_power_level = if tx_power_level == TX_POWER_NOT_PRESENT {
-                None
-            } else {
-                Some(tx_power_level as i16)
-            };
+            let arr = record.get_bytes()?;
+            Some(result.env.convert_byte_array(arr)?)
+        };
 
-            let rssi = Some(result.get_rssi()? as i16);
-            let raw_bytes = {
-                let arr = record.get_bytes()?;
-                result.env.convert_byte_array(arr)?
+        // From the ScanResult and BluetoothDevice documentation.
+        const PHY_UNUSED: jint = 0;
+        const SID_NOT_PRESENT: jint = 0xff;
+        const PERIODIC_INTERVAL_NOT_PRESENT: jint = 0;
+        let (secondary_phy, advertising_sid, periodic_advertising_interval) =
+            match result.get_extended_advertising()? {
+                Some((phy, sid, interval)) => (
+                    match phy {
+                        PHY_UNUSED => None,
+                        1 => Some(Phy::Le1M),
+                        2 => Some(Phy::Le2M),
+                        3 => Some(Phy::LeCoded),
+                        _ => None,
+                    },
+                    (sid != SID_NOT_PRESENT).then_some(sid as u8),
+                    // The interval is in units of 1.25ms.
+                    (interval != PERIODIC_INTERVAL_NOT_PRESENT)
+                        .then(|| Duration::from_micros(interval as u64 * 1250)),
+                ),
+                None => (None, None, None),
             };
-            // parse AD structure here if needed
-            let mut index = 0;
-            let mut manufacturer_data: HashMap<u16, Vec<u8>> = HashMap::new();
-
-            while index < raw_bytes.len() {
-                let length = raw_bytes[index] as usize;
-                if length == 0 {
-                    break;
-                }
-
-                if index + length >= raw_bytes.len() {
-                    break;
-                }
-
-                let ad_type = raw_bytes[index + 1] as u8;
-                if ad_type == 0xFF {
-                    // Manufacturer Specific Data
-                    let company_id =
-                        ((raw_bytes[index + 3] as u16) << 8) | (raw_bytes[index + 2] as u16);
-
-                    let data_start = index + 4;
-                    let data_end = index + 1 + length;
-                    if data_end <= raw_bytes.len() {
-                        let data = raw_bytes[data_start..data_end].to_vec();
-
-                        manufacturer_data
-                            .entry(company_id)
-                            .and_modify(|v| v.extend_from_slice(&data))
-                            .or_insert(data);
-                    }
-                }
-
-                index += length + 1;
-            }
 
-            // let manufacturer_specific_data_array = record.get_manufacturer_specific_data()?;
-            // let manufacturer_specific_data_obj: &JObject = &manufacturer_specific_data_array;
-            // let mut manufacturer_data = HashMap::new();
-            // if !result
-            //     .env
-            //     .is_same_object(manufacturer_specific_data_obj.clone(), JObject::null())?
-            // {
-            //     for item in manufacturer_specific_data_array.iter() {
-            //         let (index, data) = item?;
-            //
-            //         let index = index as u16;
-            //         let data = jni_utils::arrays::byte_array_to_vec(result.env, data.into_inner())?;
-            //         manufacturer_data.insert(index, data);
-            //     }
-            // }
-
-            let service_data_obj = record.get_service_data()?;
-            let mut service_data = HashMap::new();
-            if !result
-                .env
-                .is_same_object(&service_data_obj, JObject::null())?
-            {
-                let mut iter_env = unsafe { result.env.unsafe_clone() };
-                let service_data_map = JMap::from_env(&mut iter_env, &service_data_obj)?;
-                let mut iter = service_data_map.iter(&mut iter_env)?;
-                while let Some((key, value)) = iter.next(&mut iter_env)? {
-                    let mut item_env = unsafe { result.env.unsafe_clone() };
-                    let uuid = JParcelUuid::from_env(&mut item_env, key)?
-                        .get_uuid()?
-                        .as_uuid()?;
-                    let data = jni_utils::arrays::byte_array_to_vec(
-                        &mut item_env,
-                        JByteArray::from(value),
-                    )?;
-                    service_data.insert(uuid, data);
-                }
-            }
-
-            let services_obj = record.get_service_uuids()?;
-            let mut services = Vec::new();
-            if !result.env.is_same_object(&services_obj, JObject::null())? {
-                let mut iter_env = unsafe { result.env.unsafe_clone() };
-                let services_list = JList::from_env(&mut iter_env, &services_obj)?;
-                let mut iter = services_list.iter(&mut iter_env)?;
-                while let Some(obj) = iter.next(&mut iter_env)? {
-                    let mut item_env = unsafe { result.env.unsafe_clone() };
-                    let uuid = JParcelUuid::from_env(&mut item_env, obj)?
-                        .get_uuid()?
-                        .as_uuid()?;
-                    services.push(uuid);
-                }
-            }
-
-            Some(PeripheralProperties {
-                address: addr,
-                address_type: None,
-                local_name: device_name,
-                tx_power_level,
-                manufacturer_data,
-                service_data,
-                services,
-                rssi,
-                class: None,
-            })
-        };
-        Ok((addr, properties))
+        Ok(ScanReport {
+            address,
+            rssi: result.get_rssi()? as i16,
+            tx_power_level,
+            class: device.get_class_of_device()?,
+            is_paired: device.is_bonded()?,
+            advertising_data,
+            secondary_phy,
+            advertising_sid,
+            periodic_advertising_interval,
+        })
     }
 }
 
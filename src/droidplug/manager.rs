@@ -7,6 +7,8 @@ pub struct Manager;
 
 impl Manager {
     pub async fn new() -> Result<Manager> {
+        #[cfg(feature = "android-ndk-context")]
+        super::init_from_ndk_context()?;
         Ok(Manager)
     }
 }
@@ -16,6 +18,17 @@ impl api::Manager for Manager {
     type Adapter = Adapter;
 
     async fn adapters(&self) -> Result<Vec<Adapter>> {
-        Ok(vec![super::global_adapter().clone()])
+        Ok(vec![super::global_adapter()])
+    }
+
+    /// Stops scanning, disconnects all peripherals and releases their GATT clients, then releases
+    /// the adapter and the Java object behind it. The `JavaVM` and the cached Java classes are kept,
+    /// they're needed to initialize again: call [`init`](super::init) (or `Manager::new()` with the
+    /// `android-ndk-context` feature) before using btleplug again.
+    async fn shutdown(&self) -> Result<()> {
+        match super::take_global_adapter() {
+            Some(adapter) => adapter.shutdown().await,
+            None => Ok(()),
+        }
     }
 }
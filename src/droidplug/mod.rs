@@ -1,22 +1,50 @@
 pub mod adapter;
 pub mod manager;
 pub mod peripheral;
+pub mod server;
 
 use ::jni::JNIEnv;
-use once_cell::sync::OnceCell;
+use std::sync::Mutex;
 
 pub mod jni;
 
-static GLOBAL_ADAPTER: OnceCell<adapter::Adapter> = OnceCell::new();
+// Taken out by `Manager::shutdown`, after which `init` creates a new adapter.
+static GLOBAL_ADAPTER: Mutex<Option<adapter::Adapter>> = Mutex::new(None);
 
 pub fn init(env: &mut JNIEnv) -> crate::Result<()> {
     self::jni::init(env)?;
-    GLOBAL_ADAPTER.get_or_try_init(|| adapter::Adapter::new())?;
+    let mut adapter = GLOBAL_ADAPTER.lock()?;
+    if adapter.is_none() {
+        *adapter = Some(adapter::Adapter::new()?);
+    }
     Ok(())
 }
 
-pub fn global_adapter() -> &'static adapter::Adapter {
-    GLOBAL_ADAPTER.get().expect(
+/// Initializes droidplug from the JVM registered with `ndk-context`, unless it was already
+/// initialized through [`init`]. Called by `Manager::new()`, so apps built with ndk-glue or
+/// android-activity don't need to get hold of a `JNIEnv` themselves.
+///
+/// The calling thread is usually a native one, where `FindClass` can't see the classes of the app,
+/// so this is only useful together with the `android-embedded-dex` feature, or when called from a
+/// thread created by Java.
+#[cfg(feature = "android-ndk-context")]
+pub(crate) fn init_from_ndk_context() -> crate::Result<()> {
+    if GLOBAL_ADAPTER.lock()?.is_some() {
+        return Ok(());
+    }
+    let context = ndk_context::android_context();
+    let vm = unsafe { ::jni::JavaVM::from_raw(context.vm().cast()) }?;
+    let mut env = vm.attach_current_thread_permanently()?;
+    init(&mut env)
+}
+
+pub fn global_adapter() -> adapter::Adapter {
+    GLOBAL_ADAPTER.lock().unwrap().clone().expect(
         "Droidplug has not been initialized. Please initialize it with btleplug::platform::init().",
     )
 }
+
+/// Takes the adapter out, so that it's released once the last clone of it is dropped.
+pub(crate) fn take_global_adapter() -> Option<adapter::Adapter> {
+    GLOBAL_ADAPTER.lock().unwrap().take()
+}
@@ -1,15 +1,22 @@
 use crate::{
     api::{
-        self, BDAddr, Characteristic, Descriptor, PeripheralProperties, Service, ValueNotification,
-        WriteType,
+        self,
+        advertisement::{self, AdParseError},
+        AdvertisingInterval, BDAddr, Characteristic, ConnectOptions, ConnectTransport,
+        ConnectionInfo, ConnectionParams, ConnectionPhy, ConnectionPriority, Descriptor, Feature,
+        NameSource, PairingResponse, PeripheralProperties, Phy, PhyCoding, RetryPolicy, Service,
+        ValueNotification, WriteType,
     },
-    common::adapter_manager::AdapterManager,
+    common::{adapter_manager::AdapterManager, value_cache::ValueCache},
+    gatt_log::{self, GattOperation},
+    interceptor::{GattInterceptor, GattRequest, InterceptorList, Interceptors},
     Error, Result,
 };
 use async_trait::async_trait;
 use futures::stream::Stream;
 use jni::{
-    objects::{GlobalRef, JByteArray, JList, JObject, JString, JThrowable},
+    objects::{GlobalRef, JByteArray, JIntArray, JList, JObject, JString, JThrowable},
+    sys::{jint, jlong},
     JNIEnv,
 };
 use jni_utils::{
@@ -26,11 +33,15 @@ use std::{
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
     sync::{Arc, Mutex, Weak},
+    time::Duration,
 };
 
-use super::jni::{
-    global_jvm,
-    objects::{JBluetoothGattCharacteristic, JBluetoothGattService, JPeripheral},
+use super::{
+    adapter::sdk_int,
+    jni::{
+        jni_env,
+        objects::{JBluetoothGattService, JNotification, JPeripheral, ScanReport},
+    },
 };
 use jni::objects::JClass;
 #[cfg_attr(
@@ -46,6 +57,12 @@ impl Display for PeripheralId {
     }
 }
 
+impl From<BDAddr> for PeripheralId {
+    fn from(address: BDAddr) -> Self {
+        PeripheralId(address)
+    }
+}
+
 fn map_future_exception<'a>(
     env: &mut JNIEnv<'a>,
     ex: JThrowable<'a>,
@@ -109,6 +126,18 @@ fn map_future_exception<'a>(
         ),
     )? {
         Ok(Error::NoSuchCharacteristic)
+    } else if env.is_instance_of(
+        &cause,
+        <&JClass>::from(
+            jni_utils::classcache::get_class(
+                "com/nonpolynomial/btleplug/android/impl/ServiceDiscoveryException",
+            )
+            .unwrap()
+            .as_obj(),
+        ),
+    )? {
+        let status = env.call_method(&cause, "getStatus", "()I", &[])?.i()?;
+        Ok(Error::ServiceDiscoveryFailed(status))
     } else if env.is_instance_of(&cause, "java/lang/RuntimeException")? {
         let msg = env
             .call_method(&cause, "getMessage", "()Ljava/lang/String;", &[])?
@@ -143,7 +172,7 @@ fn check_pending_exception(env: &mut JNIEnv) -> Result<()> {
     Err(jni::errors::Error::JavaException.into())
 }
 
-fn poll_result_from_future<'a>(
+pub(super) fn poll_result_from_future<'a>(
     env: &mut JNIEnv<'a>,
     result_ref: &GlobalRef,
 ) -> Result<JPollResult<'a>> {
@@ -172,7 +201,10 @@ fn poll_result_from_future<'a>(
     Ok(JPollResult::from_env(env, result_obj)?)
 }
 
-fn get_poll_result<'a>(env: &mut JNIEnv<'a>, result: JPollResult<'a>) -> Result<JObject<'a>> {
+pub(super) fn get_poll_result<'a>(
+    env: &mut JNIEnv<'a>,
+    result: JPollResult<'a>,
+) -> Result<JObject<'a>> {
     match result.get() {
         Ok(obj) => Ok(obj),
         Err(jni::errors::Error::JavaException) => {
@@ -198,19 +230,62 @@ fn get_poll_result<'a>(env: &mut JNIEnv<'a>, result: JPollResult<'a>) -> Result<
     }
 }
 
+/// How [`connect`](api::Peripheral::connect) recovers from the GATT_ERROR (133) and GATT_FAILURE
+/// (257) statuses, which Android reports for many spurious connection failures. Before each retry
+/// the GATT client is closed and a fresh one is created.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ConnectRetryPolicy {
+    /// The number of connection attempts and the delay between them.
+    pub retry: RetryPolicy,
+    /// Alternate `autoConnect` between `false` and `true` on each retry. Some devices only
+    /// connect reliably with one or the other.
+    pub toggle_auto_connect: bool,
+}
+
 #[derive(Debug)]
 struct PeripheralShared {
     services: BTreeSet<Service>,
     characteristics: BTreeSet<Characteristic>,
-    properties: Option<PeripheralProperties>,
+    properties: Option<Arc<PeripheralProperties>>,
+    /// The raw advertising data `properties` was parsed from.
+    advertising_data: Option<Vec<u8>>,
+    /// The GAP device name read through btleplug, along with the name advertised at the time.
+    device_name: Option<(String, Option<String>)>,
+    connect_retry_policy: ConnectRetryPolicy,
+}
+
+/// Closes the GATT client once the last clone of a [`Peripheral`] is dropped.
+struct GattClientGuard(GlobalRef);
+
+impl Drop for GattClientGuard {
+    fn drop(&mut self) {
+        let result = jni_env().and_then(|mut env| {
+            let result = env.call_method(
+                self.0.as_obj(),
+                "close",
+                "()Lio/github/gedgygedgy/rust/future/Future;",
+                &[],
+            );
+            if env.exception_check()? {
+                env.exception_clear()?;
+            }
+            result.map(|_| ())
+        });
+        if let Err(e) = result {
+            log::warn!("Failed to close GATT client: {}", e);
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Peripheral {
     addr: BDAddr,
     internal: GlobalRef,
+    _gatt_client: Arc<GattClientGuard>,
     adapter: Weak<AdapterManager<Peripheral>>,
     shared: Arc<Mutex<PeripheralShared>>,
+    value_cache: Arc<ValueCache>,
+    interceptors: Arc<InterceptorList>,
 }
 
 impl Peripheral {
@@ -222,22 +297,248 @@ impl Peripheral {
     ) -> Result<Self> {
         let obj = JPeripheral::new(env, adapter, addr)?;
         let obj_ref: JObject = obj.into();
+        let internal = env.new_global_ref(&obj_ref)?;
         Ok(Self {
             addr,
-            internal: env.new_global_ref(&obj_ref)?,
+            _gatt_client: Arc::new(GattClientGuard(internal.clone())),
+            internal,
             adapter: manager,
             shared: Arc::new(Mutex::new(PeripheralShared {
                 services: BTreeSet::new(),
                 characteristics: BTreeSet::new(),
                 properties: None,
+                advertising_data: None,
+                device_name: None,
+                connect_retry_policy: ConnectRetryPolicy::default(),
             })),
+            value_cache: Arc::new(ValueCache::new()),
+            interceptors: Arc::new(InterceptorList::default()),
         })
     }
 
-    pub(crate) fn report_properties(&self, properties: PeripheralProperties) {
+    /// Releases the Android GATT client of this peripheral, dropping the connection if there is
+    /// one. Unlike [`disconnect`](api::Peripheral::disconnect), which keeps the client for a
+    /// quicker reconnection, this frees one of the limited (around 32) GATT clients an app may
+    /// have. The client is also released when the last clone of the peripheral is dropped.
+    pub async fn close(&self) -> Result<()> {
+        let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.close()?))?;
+        let result_ref = future.await?;
+        let mut env = jni_env()?;
+        let result = poll_result_from_future(&mut env, &result_ref)?;
+        get_poll_result(&mut env, result).map(|_| {})
+    }
+
+    /// Sets how connection attempts failing with status 133 or 257 are retried.
+    pub fn set_connect_retry_policy(&self, policy: ConnectRetryPolicy) {
+        self.shared.lock().unwrap().connect_retry_policy = policy;
+    }
+
+    /// Updates the properties from a scan result, returning them along with any errors found in
+    /// the advertising data. Returns `None` if the result has no advertising data. The data is only
+    /// parsed again if it differs from the last report; otherwise just the RSSI, TX power level,
+    /// class of device, pairing state, advertising interval and extended advertising fields are
+    /// updated.
+    pub(crate) fn report_scan(
+        &self,
+        report: ScanReport,
+        advertising_interval: Option<AdvertisingInterval>,
+    ) -> Option<(Arc<PeripheralProperties>, Vec<AdParseError>)> {
+        let advertising_data = report.advertising_data?;
         let mut guard = self.shared.lock().unwrap();
+        let guard = &mut *guard;
+        if let (Some(properties), Some(previous)) = (&mut guard.properties, &guard.advertising_data)
+        {
+            if *previous == advertising_data {
+                let properties_mut = Arc::make_mut(properties);
+                properties_mut.rssi = Some(report.rssi);
+                properties_mut.tx_power_level =
+                    report.tx_power_level.or(properties_mut.tx_power_level);
+                properties_mut.class = report.class.or(properties_mut.class);
+                properties_mut.is_paired = report.is_paired;
+                properties_mut.advertising_interval = advertising_interval;
+                properties_mut.secondary_phy = report.secondary_phy;
+                properties_mut.advertising_sid = report.advertising_sid;
+                properties_mut.periodic_advertising_interval = report.periodic_advertising_interval;
+                return Some((properties.clone(), vec![]));
+            }
+        }
+        let advertisement = advertisement::parse_advertisement(&advertising_data);
+        // Keep the device name read from the device, unless it advertises another name since.
+        let (local_name, local_name_source) = match guard.device_name.take() {
+            Some((name, advertised)) if advertised == advertisement.local_name => {
+                guard.device_name = Some((name.clone(), advertised));
+                (Some(name), Some(NameSource::DeviceName))
+            }
+            _ => (
+                advertisement.local_name.clone(),
+                advertisement
+                    .local_name
+                    .as_ref()
+                    .map(|_| NameSource::Advertisement),
+            ),
+        };
+        let properties = Arc::new(PeripheralProperties {
+            address: report.address,
+            address_type: None,
+            local_name,
+            local_name_source,
+            tx_power_level: report.tx_power_level.or(advertisement.tx_power_level),
+            manufacturer_data: advertisement.manufacturer_data,
+            service_data: advertisement.service_data,
+            services: advertisement.services,
+            rssi: Some(report.rssi),
+            class: report.class,
+            appearance: advertisement.appearance,
+            is_paired: report.is_paired,
+            advertising_interval,
+            uri: advertisement.uri,
+            le_supported_features: advertisement.le_supported_features,
+            advertised_interval: advertisement.advertising_interval,
+            public_target_addresses: advertisement.public_target_addresses,
+            advertisement_data: None,
+            scan_response_data: None,
+            raw_data: Some(advertising_data.clone()),
+            secondary_phy: report.secondary_phy,
+            advertising_sid: report.advertising_sid,
+            periodic_advertising_interval: report.periodic_advertising_interval,
+        });
+        guard.properties = Some(properties.clone());
+        guard.advertising_data = Some(advertising_data);
+        Some((properties, advertisement.errors))
+    }
 
-        guard.properties = Some(properties);
+    fn interceptors(&self) -> Interceptors {
+        Interceptors::new(
+            self.adapter
+                .upgrade()
+                .map(|manager| manager.interceptors().clone()),
+            self.interceptors.clone(),
+        )
+    }
+
+    async fn write_characteristic(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.ensure_available()?;
+        gatt_log::log_traffic(
+            &PeripheralId(self.addr),
+            characteristic.uuid,
+            write_type.into(),
+            data,
+        );
+        let future = self.with_obj(|env, obj| {
+            let mut local_env = unsafe { env.unsafe_clone() };
+            let uuid = JUuid::new(&mut local_env, characteristic.uuid)?;
+            let data_obj = jni_utils::arrays::slice_to_byte_array(&mut local_env, data)?;
+            let write_type = match write_type {
+                WriteType::WithResponse => 2,
+                WriteType::WithoutResponse => 1,
+                WriteType::Signed => 4,
+            };
+            JSendFuture::try_from(obj.write(
+                uuid,
+                instance_id(characteristic.handle),
+                data_obj.into(),
+                write_type,
+            )?)
+        })?;
+        let result_ref = future.await?;
+        let mut env = jni_env()?;
+        let result = poll_result_from_future(&mut env, &result_ref)?;
+        get_poll_result(&mut env, result).map(|_| {})
+    }
+
+    /// Drops the discovered services, after the device reported that its services changed.
+    pub(crate) fn clear_services(&self) -> Result<()> {
+        let mut guard = self.shared.lock().map_err(Into::<Error>::into)?;
+        guard.services.clear();
+        guard.characteristics.clear();
+        Ok(())
+    }
+
+    /// Waits for a Java future which resolves to nothing.
+    async fn await_void_future(&self, future: JSendFuture) -> Result<()> {
+        let result_ref = future.await?;
+        let mut env = jni_env()?;
+        let result = poll_result_from_future(&mut env, &result_ref)?;
+        get_poll_result(&mut env, result).map(|_| {})
+    }
+
+    async fn read_characteristic(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.ensure_available()?;
+        let future = self.with_obj(|env, obj| {
+            let uuid = JUuid::new(env, characteristic.uuid)?;
+            JSendFuture::try_from(obj.read(uuid, instance_id(characteristic.handle))?)
+        })?;
+        let result_ref = future.await?;
+        let mut env = jni_env()?;
+        let result = poll_result_from_future(&mut env, &result_ref)?;
+        let bytes = get_poll_result(&mut env, result)?;
+        let mut local_env = unsafe { env.unsafe_clone() };
+        let value = byte_array_to_vec(&mut local_env, JByteArray::from(bytes))?;
+        gatt_log::log_traffic(
+            &PeripheralId(self.addr),
+            characteristic.uuid,
+            GattOperation::Read,
+            &value,
+        );
+        self.value_cache.insert(characteristic, value.clone());
+        Ok(value)
+    }
+
+    async fn write_descriptor_value(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.ensure_available()?;
+        gatt_log::log_traffic(
+            &PeripheralId(self.addr),
+            descriptor.uuid,
+            GattOperation::WriteDescriptor,
+            data,
+        );
+        let future = self.with_obj(|env, obj| {
+            let mut local_env = unsafe { env.unsafe_clone() };
+            let characteristic = JUuid::new(&mut local_env, descriptor.characteristic_uuid)?;
+            let uuid = JUuid::new(&mut local_env, descriptor.uuid)?;
+            let data_obj = jni_utils::arrays::slice_to_byte_array(&mut local_env, data)?;
+            JSendFuture::try_from(obj.write_descriptor(
+                characteristic,
+                uuid,
+                instance_id(descriptor.handle),
+                data_obj.into(),
+            )?)
+        })?;
+        let result_ref = future.await?;
+        let mut env = jni_env()?;
+        let result = poll_result_from_future(&mut env, &result_ref)?;
+        get_poll_result(&mut env, result).map(|_| {})
+    }
+
+    async fn read_descriptor_value(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.ensure_available()?;
+        let future = self.with_obj(|env, obj| {
+            let characteristic = JUuid::new(env, descriptor.characteristic_uuid)?;
+            let uuid = JUuid::new(env, descriptor.uuid)?;
+            JSendFuture::try_from(obj.read_descriptor(
+                characteristic,
+                uuid,
+                instance_id(descriptor.handle),
+            )?)
+        })?;
+        let result_ref = future.await?;
+        let mut env = jni_env()?;
+        let result = poll_result_from_future(&mut env, &result_ref)?;
+        let bytes = get_poll_result(&mut env, result)?;
+        let mut local_env = unsafe { env.unsafe_clone() };
+        let value = byte_array_to_vec(&mut local_env, JByteArray::from(bytes))?;
+        gatt_log::log_traffic(
+            &PeripheralId(self.addr),
+            descriptor.uuid,
+            GattOperation::ReadDescriptor,
+            &value,
+        );
+        Ok(value)
     }
 
     fn with_obj<T, E>(
@@ -247,7 +548,7 @@ impl Peripheral {
     where
         E: From<::jni::errors::Error>,
     {
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
         if env.exception_check()? {
             env.exception_clear()?;
             return Err(::jni::errors::Error::JavaException.into());
@@ -269,10 +570,14 @@ impl Peripheral {
         self.ensure_available()?;
         let future = self.with_obj(|env, obj| {
             let uuid_obj = JUuid::new(env, characteristic.uuid)?;
-            JSendFuture::try_from(obj.set_characteristic_notification(uuid_obj, enable)?)
+            JSendFuture::try_from(obj.set_characteristic_notification(
+                uuid_obj,
+                instance_id(characteristic.handle),
+                enable,
+            )?)
         })?;
         let result_ref = future.await?;
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
         let result = poll_result_from_future(&mut env, &result_ref)?;
         get_poll_result(&mut env, result).map(|_| {})
     }
@@ -305,11 +610,15 @@ impl api::Peripheral for Peripheral {
         self.addr
     }
 
-    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
         let guard = self.shared.lock().map_err(Into::<Error>::into)?;
         Ok((&guard.properties).clone())
     }
 
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        self.shared.lock().unwrap().properties.clone()
+    }
+
     fn characteristics(&self) -> BTreeSet<Characteristic> {
         let guard = self.shared.lock().unwrap();
         (&guard.characteristics).clone()
@@ -320,6 +629,12 @@ impl api::Peripheral for Peripheral {
         self.with_obj(|_env, obj| Ok(obj.is_connected()?))
     }
 
+    async fn is_paired(&self) -> Result<bool> {
+        self.ensure_available()?;
+        self.with_obj(|_env, obj| obj.is_bonded())?
+            .ok_or(Error::PermissionDenied)
+    }
+
     async fn mtu(&self, _characteristics: Option<&[Characteristic]>) -> Result<u16> {
         self.ensure_available()?;
         self.with_obj(|env, obj| {
@@ -340,20 +655,218 @@ impl api::Peripheral for Peripheral {
         })
     }
 
-    async fn connect(&self) -> Result<()> {
+    async fn connection_info(&self) -> Result<Option<ConnectionInfo>> {
+        self.ensure_available()?;
+        self.with_obj(|env, obj| {
+            let result = try_block(env, |_env| Ok(Ok(obj.get_connection_parameters()?)))
+                .catch(
+                    <&JClass>::from(
+                        jni_utils::classcache::get_class(
+                            "com/nonpolynomial/btleplug/android/impl/NotConnectedException",
+                        )
+                        .unwrap()
+                        .as_obj(),
+                    ),
+                    |_env, _ex| Ok(Err(Error::NotConnected)),
+                )
+                .result()?
+                .map_err(Into::<Error>::into)?;
+            Ok(result.map(|[interval, latency, timeout]| {
+                ConnectionInfo::from_hci_units(interval as u16, latency as u16, timeout as u16)
+            }))
+        })
+    }
+
+    async fn respond_to_pairing(&self, response: PairingResponse) -> Result<()> {
+        let (pin, confirm) = match &response {
+            PairingResponse::Pin(pin) => (Some(pin.as_str()), true),
+            PairingResponse::Confirm => (None, true),
+            PairingResponse::Reject => (None, false),
+        };
+        let accepted = self.with_obj(|env, obj| {
+            try_block(env, |_env| Ok(Ok(obj.respond_to_pairing(pin, confirm)?)))
+                .catch(
+                    <&JClass>::from(
+                        jni_utils::classcache::get_class(
+                            "com/nonpolynomial/btleplug/android/impl/PermissionDeniedException",
+                        )
+                        .unwrap()
+                        .as_obj(),
+                    ),
+                    |_env, _ex| Ok(Err(Error::PermissionDenied)),
+                )
+                .result()?
+        })?;
+        if accepted {
+            Ok(())
+        } else {
+            Err(Error::Other("Android refused the pairing response".into()))
+        }
+    }
+
+    async fn update_connection_parameters(&self, params: ConnectionParams) -> Result<()> {
+        self.ensure_available()?;
+        let priority = match params.priority() {
+            ConnectionPriority::Balanced => CONNECTION_PRIORITY_BALANCED,
+            ConnectionPriority::High => CONNECTION_PRIORITY_HIGH,
+            ConnectionPriority::LowPower => CONNECTION_PRIORITY_LOW_POWER,
+        };
+        let accepted = self.with_obj(|env, obj| {
+            try_block(env, |_env| {
+                Ok(Ok(obj.request_connection_priority(priority)?))
+            })
+            .catch(
+                <&JClass>::from(
+                    jni_utils::classcache::get_class(
+                        "com/nonpolynomial/btleplug/android/impl/NotConnectedException",
+                    )
+                    .unwrap()
+                    .as_obj(),
+                ),
+                |_env, _ex| Ok(Err(Error::NotConnected)),
+            )
+            .catch(
+                <&JClass>::from(
+                    jni_utils::classcache::get_class(
+                        "com/nonpolynomial/btleplug/android/impl/PermissionDeniedException",
+                    )
+                    .unwrap()
+                    .as_obj(),
+                ),
+                |_env, _ex| Ok(Err(Error::PermissionDenied)),
+            )
+            .result()?
+        })?;
+        if accepted {
+            Ok(())
+        } else {
+            Err(Error::Other(
+                "Android refused the connection priority request".into(),
+            ))
+        }
+    }
+
+    async fn set_preferred_phy(&self, tx: &[Phy], rx: &[Phy], coding: PhyCoding) -> Result<()> {
         self.ensure_available()?;
-        let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.connect()?))?;
+        // Choosing the PHY was added in Android 8.
+        if sdk_int()? < 26 {
+            return Err(Error::NotSupported {
+                feature: Feature::PhySelection,
+            });
+        }
+        let options = match coding {
+            PhyCoding::NoPreference => PHY_OPTION_NO_PREFERRED,
+            PhyCoding::S2 => PHY_OPTION_S2,
+            PhyCoding::S8 => PHY_OPTION_S8,
+        };
+        let (tx, rx) = (phy_mask(tx), phy_mask(rx));
+        self.with_obj(|env, obj| {
+            try_block(env, |_env| Ok(Ok(obj.set_preferred_phy(tx, rx, options)?)))
+                .catch(
+                    <&JClass>::from(
+                        jni_utils::classcache::get_class(
+                            "com/nonpolynomial/btleplug/android/impl/NotConnectedException",
+                        )
+                        .unwrap()
+                        .as_obj(),
+                    ),
+                    |_env, _ex| Ok(Err(Error::NotConnected)),
+                )
+                .catch(
+                    <&JClass>::from(
+                        jni_utils::classcache::get_class(
+                            "com/nonpolynomial/btleplug/android/impl/PermissionDeniedException",
+                        )
+                        .unwrap()
+                        .as_obj(),
+                    ),
+                    |_env, _ex| Ok(Err(Error::PermissionDenied)),
+                )
+                .result()?
+        })
+    }
+
+    async fn read_phy(&self) -> Result<ConnectionPhy> {
+        self.ensure_available()?;
+        if sdk_int()? < 26 {
+            return Err(Error::NotSupported {
+                feature: Feature::PhySelection,
+            });
+        }
+        let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.read_phy()?))?;
         let result_ref = future.await?;
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
         let result = poll_result_from_future(&mut env, &result_ref)?;
-        get_poll_result(&mut env, result).map(|_| {})
+        let phys = get_poll_result(&mut env, result)?;
+        let mut values = [0; 2];
+        env.get_int_array_region(JIntArray::from(phys), 0, &mut values)?;
+        let [tx, rx] = values.map(phy_from_constant);
+        match (tx, rx) {
+            (Some(tx), Some(rx)) => Ok(ConnectionPhy { tx, rx }),
+            _ => Err(Error::Other(
+                format!("Android reported unknown PHYs {:?}", values).into(),
+            )),
+        }
+    }
+
+    async fn begin_reliable_write(&self) -> Result<Box<dyn api::ReliableWrite>> {
+        self.ensure_available()?;
+        let future =
+            self.with_obj(|_env, obj| JSendFuture::try_from(obj.begin_reliable_write()?))?;
+        self.await_void_future(future).await?;
+        Ok(Box::new(ReliableWrite {
+            peripheral: self.clone(),
+            finished: false,
+        }))
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.connect_with(ConnectOptions::default()).await
+    }
+
+    async fn connect_with(&self, options: ConnectOptions) -> Result<()> {
+        self.ensure_available()?;
+        let policy = self.shared.lock()?.connect_retry_policy;
+        let transport = match options.transport {
+            None | Some(ConnectTransport::Auto) => TRANSPORT_AUTO,
+            Some(ConnectTransport::Le) => TRANSPORT_LE,
+            Some(ConnectTransport::BrEdr) => TRANSPORT_BREDR,
+        };
+        let phy = match &options.phy {
+            // Choosing the PHYs to connect on was added in Android 8.
+            Some(_) if sdk_int()? < 26 => {
+                return Err(Error::NotSupported {
+                    feature: Feature::ConnectPhy,
+                })
+            }
+            Some(phys) => phy_mask(phys),
+            None => 0,
+        };
+        let connect = async {
+            let future = self.with_obj(|_env, obj| {
+                JSendFuture::try_from(obj.connect(
+                    policy.retry.max_attempts.max(1) as jint,
+                    policy.retry.initial_backoff.as_millis() as jlong,
+                    policy.retry.max_backoff.as_millis() as jlong,
+                    policy.toggle_auto_connect,
+                    transport,
+                    options.auto_connect.unwrap_or(false),
+                    phy,
+                )?)
+            })?;
+            let result_ref = future.await?;
+            let mut env = jni_env()?;
+            let result = poll_result_from_future(&mut env, &result_ref)?;
+            get_poll_result(&mut env, result).map(|_| {})
+        };
+        crate::common::connect::with_timeout(self, options.timeout, connect).await
     }
 
     async fn disconnect(&self) -> Result<()> {
         self.ensure_available()?;
         let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.disconnect()?))?;
         let result_ref = future.await?;
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
         let result = poll_result_from_future(&mut env, &result_ref)?;
         get_poll_result(&mut env, result).map(|_| {})
     }
@@ -369,7 +882,7 @@ impl api::Peripheral for Peripheral {
         self.ensure_available()?;
         let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.discover_services()?))?;
         let result_ref = future.await?;
-        let mut env = global_jvm().get_env()?;
+        let mut env = jni_env()?;
         use std::iter::FromIterator;
 
         let result = poll_result_from_future(&mut env, &result_ref)?;
@@ -381,39 +894,11 @@ impl api::Peripheral for Peripheral {
         let mut iter = list.iter(&mut env)?;
         while let Some(service) = iter.next(&mut env)? {
             let service = JBluetoothGattService::from_env(&mut env, service)?;
-            let mut characteristics = BTreeSet::<Characteristic>::new();
-            for characteristic in service.get_characteristics()? {
-                let mut descriptors = BTreeSet::new();
-                for descriptor in characteristic.get_descriptors()? {
-                    descriptors.insert(Descriptor {
-                        uuid: descriptor.get_uuid()?,
-                        service_uuid: service.get_uuid()?,
-                        characteristic_uuid: characteristic.get_uuid()?,
-                    });
-                }
-                let char = Characteristic {
-                    service_uuid: service.get_uuid()?,
-                    uuid: characteristic.get_uuid()?,
-                    properties: characteristic.get_properties()?,
-                    descriptors: descriptors.clone(),
-                };
-                // Only consider the first characteristic of each UUID
-                // This "should" be unique, but of course it's not enforced
-                if characteristics
-                    .iter()
-                    .filter(|c| c.service_uuid == char.service_uuid && c.uuid == char.uuid)
-                    .count()
-                    == 0
-                {
-                    characteristics.insert(char.clone());
-                    peripheral_characteristics.push(char.clone());
-                }
-            }
-            peripheral_services.push(Service {
-                uuid: service.get_uuid()?,
-                primary: service.is_primary()?,
-                characteristics,
-            })
+            collect_service(
+                &service,
+                &mut peripheral_services,
+                &mut peripheral_characteristics,
+            )?;
         }
         let mut guard = self.shared.lock().map_err(Into::<Error>::into)?;
         guard.services = BTreeSet::from_iter(peripheral_services.clone());
@@ -427,35 +912,61 @@ impl api::Peripheral for Peripheral {
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        self.ensure_available()?;
-        let future = self.with_obj(|env, obj| {
-            let mut local_env = unsafe { env.unsafe_clone() };
-            let uuid = JUuid::new(&mut local_env, characteristic.uuid)?;
-            let data_obj = jni_utils::arrays::slice_to_byte_array(&mut local_env, data)?;
-            let write_type = match write_type {
-                WriteType::WithResponse => 2,
-                WriteType::WithoutResponse => 1,
-            };
-            JSendFuture::try_from(obj.write(uuid, data_obj.into(), write_type)?)
-        })?;
-        let result_ref = future.await?;
-        let mut env = global_jvm().get_env()?;
-        let result = poll_result_from_future(&mut env, &result_ref)?;
-        get_poll_result(&mut env, result).map(|_| {})
+        let id = PeripheralId(self.addr);
+        let request = GattRequest::characteristic(&id, characteristic, write_type.into());
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                self.write_characteristic(characteristic, &data, write_type)
+                    .await
+            })
+            .await
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        self.ensure_available()?;
-        let future = self.with_obj(|env, obj| {
-            let uuid = JUuid::new(env, characteristic.uuid)?;
-            JSendFuture::try_from(obj.read(uuid)?)
-        })?;
-        let result_ref = future.await?;
-        let mut env = global_jvm().get_env()?;
-        let result = poll_result_from_future(&mut env, &result_ref)?;
-        let bytes = get_poll_result(&mut env, result)?;
-        let mut local_env = unsafe { env.unsafe_clone() };
-        Ok(byte_array_to_vec(&mut local_env, JByteArray::from(bytes))?)
+        let id = PeripheralId(self.addr);
+        let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+        self.interceptors()
+            .read(&request, self.read_characteristic(characteristic))
+            .await
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        if let Some(value) = self.value_cache.get(characteristic, max_age) {
+            // The cache holds values as received, so they go through the interceptors again.
+            let id = PeripheralId(self.addr);
+            let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+            return self
+                .interceptors()
+                .read(&request, async { Ok(value) })
+                .await;
+        }
+        self.read(characteristic).await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        let name = api::read_gap_device_name(self).await?;
+        let mut guard = self.shared.lock().map_err(Into::<Error>::into)?;
+        let guard = &mut *guard;
+        if let Some(properties) = &mut guard.properties {
+            let properties = Arc::make_mut(properties);
+            let advertised = match properties.local_name_source {
+                Some(NameSource::DeviceName) => guard
+                    .device_name
+                    .take()
+                    .and_then(|(_, advertised)| advertised),
+                _ => properties.local_name.clone(),
+            };
+            guard.device_name = Some((name.clone(), advertised));
+            properties.local_name = Some(name.clone());
+            properties.local_name_source = Some(NameSource::DeviceName);
+        } else {
+            guard.device_name = Some((name.clone(), None));
+        }
+        Ok(name)
     }
 
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
@@ -473,49 +984,221 @@ impl api::Peripheral for Peripheral {
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
         use futures::stream::StreamExt;
         let stream = self.with_obj(|_env, obj| JSendStream::try_from(obj.get_notifications()?))?;
+        let value_cache = self.value_cache.clone();
+        let id = PeripheralId(self.addr);
         let stream = stream
-            .map(|item| match item {
+            .map(move |item| match item {
                 Ok(item) => {
-                    let mut env = global_jvm().get_env()?;
+                    let mut env = jni_env()?;
                     let item = env.new_local_ref(item.as_obj())?;
-                    let characteristic = JBluetoothGattCharacteristic::from_env(&mut env, item)?;
+                    let notification = JNotification::from_env(&mut env, item)?;
+                    let characteristic = notification.get_characteristic()?;
                     let uuid = characteristic.get_uuid()?;
-                    let value = characteristic.get_value()?;
-                    Ok(ValueNotification { uuid, value })
+                    let value = notification.get_value()?;
+                    let is_indication =
+                        characteristic.get_properties()?.subscribes_to_indications();
+                    gatt_log::log_traffic(&id, uuid, GattOperation::Notification, &value);
+                    let notification = ValueNotification {
+                        uuid,
+                        service_uuid: characteristic.get_service_uuid()?,
+                        handle: handle_from_instance_id(characteristic.get_instance_id()?),
+                        value,
+                        is_indication,
+                    };
+                    value_cache.insert(&notification, notification.value.clone());
+                    Ok(notification)
                 }
                 Err(err) => Err(err),
             })
+            // The Java side finishes the stream when the device disconnects. Errors end it as
+            // well, rather than leaving the caller waiting for notifications which can't arrive.
+            .take_while(|item: &Result<ValueNotification>| {
+                if let Err(e) = item {
+                    log::warn!("Notification stream failed: {}", e);
+                }
+                futures::future::ready(item.is_ok())
+            })
             .filter_map(|item| async { item.ok() });
-        Ok(Box::pin(stream))
+        Ok(self
+            .interceptors()
+            .notifications(PeripheralId(self.addr), Box::pin(stream)))
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
-        self.ensure_available()?;
-        let future = self.with_obj(|env, obj| {
-            let mut local_env = unsafe { env.unsafe_clone() };
-            let characteristic = JUuid::new(&mut local_env, descriptor.characteristic_uuid)?;
-            let uuid = JUuid::new(&mut local_env, descriptor.uuid)?;
-            let data_obj = jni_utils::arrays::slice_to_byte_array(&mut local_env, data)?;
-            JSendFuture::try_from(obj.write_descriptor(characteristic, uuid, data_obj.into())?)
-        })?;
-        let result_ref = future.await?;
-        let mut env = global_jvm().get_env()?;
-        let result = poll_result_from_future(&mut env, &result_ref)?;
-        get_poll_result(&mut env, result).map(|_| {})
+        let id = PeripheralId(self.addr);
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::WriteDescriptor);
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                self.write_descriptor_value(descriptor, &data).await
+            })
+            .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
-        self.ensure_available()?;
-        let future = self.with_obj(|env, obj| {
-            let characteristic = JUuid::new(env, descriptor.characteristic_uuid)?;
-            let uuid = JUuid::new(env, descriptor.uuid)?;
-            JSendFuture::try_from(obj.read_descriptor(characteristic, uuid)?)
-        })?;
-        let result_ref = future.await?;
-        let mut env = global_jvm().get_env()?;
-        let result = poll_result_from_future(&mut env, &result_ref)?;
-        let bytes = get_poll_result(&mut env, result)?;
-        let mut local_env = unsafe { env.unsafe_clone() };
-        Ok(byte_array_to_vec(&mut local_env, JByteArray::from(bytes))?)
+        let id = PeripheralId(self.addr);
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::ReadDescriptor);
+        self.interceptors()
+            .read(&request, self.read_descriptor_value(descriptor))
+            .await
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.interceptors.add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self.interceptors.remove(interceptor))
+    }
+}
+
+/// A reliable write transaction, which Android keeps in the `BluetoothGatt` of the peripheral.
+#[derive(Debug)]
+struct ReliableWrite {
+    peripheral: Peripheral,
+    finished: bool,
+}
+
+#[async_trait]
+impl api::ReliableWrite for ReliableWrite {
+    async fn queue_write(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()> {
+        // Android turns writes with response into Prepare Write requests during the transaction.
+        api::Peripheral::write(
+            &self.peripheral,
+            characteristic,
+            data,
+            WriteType::WithResponse,
+        )
+        .await
+    }
+
+    async fn execute(mut self: Box<Self>) -> Result<()> {
+        self.finished = true;
+        let future = self
+            .peripheral
+            .with_obj(|_env, obj| JSendFuture::try_from(obj.execute_reliable_write()?))?;
+        self.peripheral.await_void_future(future).await
+    }
+
+    async fn abort(mut self: Box<Self>) -> Result<()> {
+        self.finished = true;
+        let future = self
+            .peripheral
+            .with_obj(|_env, obj| JSendFuture::try_from(obj.abort_reliable_write()?))?;
+        self.peripheral.await_void_future(future).await
+    }
+}
+
+impl Drop for ReliableWrite {
+    fn drop(&mut self) {
+        if !self.finished {
+            // The abort is queued behind any writes still running, so it doesn't need awaiting.
+            if let Err(e) = self
+                .peripheral
+                .with_obj(|_env, obj| obj.abort_reliable_write().map(|_| ()))
+            {
+                log::warn!("Failed to abort reliable write: {}", e);
+            }
+        }
+    }
+}
+
+/// Converts a discovered service into the cross-platform model, followed by the services it
+/// includes. Primary and secondary services are both collected.
+fn collect_service(
+    service: &JBluetoothGattService,
+    services: &mut Vec<Service>,
+    peripheral_characteristics: &mut Vec<Characteristic>,
+) -> Result<()> {
+    let mut characteristics = BTreeSet::<Characteristic>::new();
+    let mut service_characteristics = Vec::new();
+    for characteristic in service.get_characteristics()? {
+        let mut descriptors = BTreeSet::new();
+        for descriptor in characteristic.get_descriptors()? {
+            descriptors.insert(Descriptor {
+                uuid: descriptor.get_uuid()?,
+                service_uuid: service.get_uuid()?,
+                characteristic_uuid: characteristic.get_uuid()?,
+                handle: handle_from_instance_id(descriptor.get_instance_id()?),
+                permissions: Some(descriptor.get_permissions()?).filter(|p| !p.is_empty()),
+            });
+        }
+        let char = Characteristic {
+            service_uuid: service.get_uuid()?,
+            uuid: characteristic.get_uuid()?,
+            properties: characteristic.get_properties()?,
+            descriptors: descriptors.clone(),
+            handle: handle_from_instance_id(characteristic.get_instance_id()?),
+        };
+        characteristics.insert(char.clone());
+        service_characteristics.push(char);
     }
+    let service_model = Service {
+        uuid: service.get_uuid()?,
+        handle: handle_from_instance_id(service.get_instance_id()?),
+        primary: service.is_primary()?,
+        characteristics,
+    };
+    // Included services are normally listed by getServices() too, in which case they were already
+    // collected. This also stops on (invalid) include cycles.
+    if services.contains(&service_model) {
+        return Ok(());
+    }
+    services.push(service_model);
+    peripheral_characteristics.extend(service_characteristics);
+    for included in service.get_included_services()? {
+        collect_service(&included, services, peripheral_characteristics)?;
+    }
+    Ok(())
+}
+
+// The `BluetoothDevice.TRANSPORT_*` constants passed to `connectGatt`.
+const TRANSPORT_AUTO: jint = 0;
+const TRANSPORT_BREDR: jint = 1;
+const TRANSPORT_LE: jint = 2;
+
+// The `BluetoothDevice.PHY_LE_*_MASK` constants passed to `connectGatt`.
+const PHY_LE_1M_MASK: jint = 1;
+const PHY_LE_2M_MASK: jint = 2;
+const PHY_LE_CODED_MASK: jint = 4;
+
+fn phy_mask(phys: &[Phy]) -> jint {
+    phys.iter().fold(0, |mask, phy| {
+        mask | match phy {
+            Phy::Le1M => PHY_LE_1M_MASK,
+            Phy::Le2M => PHY_LE_2M_MASK,
+            Phy::LeCoded => PHY_LE_CODED_MASK,
+        }
+    })
+}
+
+// The `BluetoothDevice.PHY_OPTION_*` constants passed to `setPreferredPhy`.
+const PHY_OPTION_NO_PREFERRED: jint = 0;
+const PHY_OPTION_S2: jint = 1;
+const PHY_OPTION_S8: jint = 2;
+
+/// Maps the `BluetoothDevice.PHY_LE_*` constants `readPhy` reports.
+fn phy_from_constant(phy: jint) -> Option<Phy> {
+    match phy {
+        1 => Some(Phy::Le1M),
+        2 => Some(Phy::Le2M),
+        3 => Some(Phy::LeCoded),
+        _ => None,
+    }
+}
+
+// The `BluetoothGatt.CONNECTION_PRIORITY_*` constants.
+const CONNECTION_PRIORITY_BALANCED: jint = 0;
+const CONNECTION_PRIORITY_HIGH: jint = 1;
+const CONNECTION_PRIORITY_LOW_POWER: jint = 2;
+
+/// Instance ID passed to Java to operate on the first attribute with a matching UUID.
+const NO_INSTANCE_ID: jint = -1;
+
+fn instance_id(handle: Option<u16>) -> jint {
+    handle.map_or(NO_INSTANCE_ID, jint::from)
+}
+
+fn handle_from_instance_id(instance_id: jint) -> Option<u16> {
+    u16::try_from(instance_id).ok()
 }
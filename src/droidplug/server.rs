@@ -0,0 +1,434 @@
+use super::{
+    jni::jni_env,
+    peripheral::{get_poll_result, poll_result_from_future},
+};
+use crate::{
+    api::{
+        server::{self, AttError, LocalService, ReadRequest, RequestHandler, WriteRequest},
+        BDAddr, CharPropFlags, WriteType,
+    },
+    Error, Result,
+};
+use async_trait::async_trait;
+use jni::{
+    objects::{GlobalRef, JByteArray, JClass, JObject, JString, JValue},
+    strings::JavaStr,
+    sys::{jboolean, jint},
+    JNIEnv,
+};
+use jni_utils::{
+    arrays::{byte_array_to_vec, slice_to_byte_array},
+    future::{JFuture, JSendFuture},
+    uuid::JUuid,
+};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{self, Debug, Formatter},
+    str::FromStr,
+    sync::Arc,
+};
+use uuid::Uuid;
+
+/// The handlers of the published services, kept in the `handle` field of the Java object so the
+/// native callbacks can find them.
+type Handlers = HashMap<Uuid, Arc<dyn RequestHandler>>;
+
+const PERMISSION_READ: jint = 0x01;
+const PERMISSION_WRITE: jint = 0x10;
+const SERVICE_TYPE_PRIMARY: jint = 0;
+const SERVICE_TYPE_SECONDARY: jint = 1;
+
+/// A GATT server backed by `android.bluetooth.BluetoothGattServer`.
+pub struct GattServer {
+    internal: GlobalRef,
+    // Android adds one service at a time.
+    adding: tokio::sync::Mutex<()>,
+}
+
+impl Debug for GattServer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("GattServer").finish_non_exhaustive()
+    }
+}
+
+impl GattServer {
+    pub(crate) fn new() -> Result<Self> {
+        let mut env = jni_env()?;
+        // Looked up in the class cache, as `FindClass` can't see the classes of the app from the
+        // native threads this is usually called on.
+        let class =
+            jni_utils::classcache::get_class("com/nonpolynomial/btleplug/android/impl/GattServer")
+                .unwrap();
+        let obj = env.new_object(<&JClass>::from(class.as_obj()), "()V", &[]);
+        let obj = match obj {
+            Ok(obj) => obj,
+            Err(e) => return Err(clear_exception(&mut env, e)),
+        };
+        unsafe { env.set_rust_field(&obj, "handle", Handlers::new()) }?;
+        Ok(Self {
+            internal: env.new_global_ref(&obj)?,
+            adding: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    fn with_obj<T>(
+        &self,
+        f: impl for<'a> FnOnce(&mut JNIEnv<'a>, &JObject<'a>) -> jni::errors::Result<T>,
+    ) -> Result<T> {
+        let mut env = jni_env()?;
+        let obj = env.new_local_ref(self.internal.as_obj())?;
+        f(&mut env, &obj).map_err(|e| clear_exception(&mut env, e))
+    }
+
+    fn handlers<T>(&self, f: impl FnOnce(&mut Handlers) -> T) -> Result<T> {
+        let env = jni_env()?;
+        let mut handlers =
+            unsafe { env.get_rust_field::<_, _, Handlers>(self.internal.as_obj(), "handle")? };
+        Ok(f(&mut handlers))
+    }
+}
+
+impl Drop for GattServer {
+    fn drop(&mut self) {
+        let result = self.with_obj(|env, obj| {
+            env.call_method(obj, "close", "()V", &[])?;
+            unsafe { env.take_rust_field::<_, _, Handlers>(obj, "handle") }?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            log::warn!("Failed to close GATT server: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl server::GattServer for GattServer {
+    async fn add_service(
+        &self,
+        service: LocalService,
+        handler: Arc<dyn RequestHandler>,
+    ) -> Result<()> {
+        service.validate()?;
+        let _adding = self.adding.lock().await;
+        // Registered first, so that no request which arrives right after the service is added
+        // goes unanswered.
+        self.handlers(|handlers| handlers.insert(service.uuid, handler))?;
+        let future = self.with_obj(|env, obj| {
+            let service_obj = new_service(env, &service)?;
+            let future = env
+                .call_method(
+                    obj,
+                    "addService",
+                    "(Landroid/bluetooth/BluetoothGattService;)Lio/github/gedgygedgy/rust/future/Future;",
+                    &[JValue::from(&service_obj)],
+                )?
+                .l()?;
+            JSendFuture::try_from(JFuture::from_env(env, future)?)
+        });
+        let result = match future {
+            Ok(future) => match future.await {
+                Ok(result_ref) => {
+                    let mut env = jni_env()?;
+                    poll_result_from_future(&mut env, &result_ref)
+                        .and_then(|result| get_poll_result(&mut env, result))
+                        .map(|_| ())
+                }
+                Err(e) => Err(e.into()),
+            },
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.handlers(|handlers| handlers.remove(&service.uuid))?;
+        }
+        result
+    }
+
+    async fn remove_service(&self, uuid: Uuid) -> Result<()> {
+        let removed = self.with_obj(|env, obj| {
+            let uuid = env.new_string(uuid.to_string())?;
+            env.call_method(
+                obj,
+                "removeService",
+                "(Ljava/lang/String;)Z",
+                &[JValue::from(&uuid)],
+            )?
+            .z()
+        })?;
+        self.handlers(|handlers| handlers.remove(&uuid))?;
+        if removed {
+            Ok(())
+        } else {
+            Err(Error::NoSuchService)
+        }
+    }
+
+    async fn notify(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        value: &[u8],
+    ) -> Result<()> {
+        let found = self.with_obj(|env, obj| {
+            let service = env.new_string(service_uuid.to_string())?;
+            let characteristic = env.new_string(characteristic_uuid.to_string())?;
+            let value = slice_to_byte_array(env, value)?;
+            env.call_method(
+                obj,
+                "notify",
+                "(Ljava/lang/String;Ljava/lang/String;[B)Z",
+                &[
+                    JValue::from(&service),
+                    JValue::from(&characteristic),
+                    JValue::from(&value),
+                ],
+            )?
+            .z()
+        })?;
+        if found {
+            Ok(())
+        } else {
+            Err(Error::NoSuchCharacteristic)
+        }
+    }
+}
+
+/// Clears the exception a failed Java call left pending, so that later calls on this thread work.
+fn clear_exception(env: &mut JNIEnv, e: jni::errors::Error) -> Error {
+    if let jni::errors::Error::JavaException = e {
+        let _ = env.exception_describe();
+        let _ = env.exception_clear();
+    }
+    e.into()
+}
+
+/// Builds the `BluetoothGattService` to publish for `service`.
+fn new_service<'a>(
+    env: &mut JNIEnv<'a>,
+    service: &LocalService,
+) -> jni::errors::Result<JObject<'a>> {
+    let uuid: JObject = JUuid::new(env, service.uuid)?.into();
+    let service_obj = env.new_object(
+        "android/bluetooth/BluetoothGattService",
+        "(Ljava/util/UUID;I)V",
+        &[
+            JValue::from(&uuid),
+            JValue::from(service_type(service.primary)),
+        ],
+    )?;
+    for characteristic in &service.characteristics {
+        let uuid: JObject = JUuid::new(env, characteristic.uuid)?.into();
+        let characteristic_obj = env.new_object(
+            "android/bluetooth/BluetoothGattCharacteristic",
+            "(Ljava/util/UUID;II)V",
+            &[
+                JValue::from(&uuid),
+                // The property bits are the same as those of the ATT protocol.
+                JValue::from(jint::from(characteristic.properties.bits())),
+                JValue::from(permissions(characteristic.properties)),
+            ],
+        )?;
+        for descriptor in &characteristic.descriptors {
+            let uuid: JObject = JUuid::new(env, descriptor.uuid)?.into();
+            let descriptor_obj = env.new_object(
+                "android/bluetooth/BluetoothGattDescriptor",
+                "(Ljava/util/UUID;I)V",
+                &[JValue::from(&uuid), JValue::from(PERMISSION_READ)],
+            )?;
+            let value = slice_to_byte_array(env, &descriptor.value)?;
+            env.call_method(
+                &descriptor_obj,
+                "setValue",
+                "([B)Z",
+                &[JValue::from(&value)],
+            )?;
+            env.call_method(
+                &characteristic_obj,
+                "addDescriptor",
+                "(Landroid/bluetooth/BluetoothGattDescriptor;)Z",
+                &[JValue::from(&descriptor_obj)],
+            )?;
+        }
+        env.call_method(
+            &service_obj,
+            "addCharacteristic",
+            "(Landroid/bluetooth/BluetoothGattCharacteristic;)Z",
+            &[JValue::from(&characteristic_obj)],
+        )?;
+    }
+    Ok(service_obj)
+}
+
+fn service_type(primary: bool) -> jint {
+    if primary {
+        SERVICE_TYPE_PRIMARY
+    } else {
+        SERVICE_TYPE_SECONDARY
+    }
+}
+
+/// The permissions Android checks requests of remote centrals against, which it doesn't derive
+/// from the properties itself.
+fn permissions(properties: CharPropFlags) -> jint {
+    let mut permissions = 0;
+    if properties.contains(CharPropFlags::READ) {
+        permissions |= PERMISSION_READ;
+    }
+    if properties.intersects(CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE) {
+        permissions |= PERMISSION_WRITE;
+    }
+    permissions
+}
+
+fn string(env: &mut JNIEnv, string: &JString) -> Result<String> {
+    let string = JavaStr::from_env(env, string)?;
+    Ok(string
+        .to_str()
+        .map_err(|e| Error::Other(e.into()))?
+        .to_owned())
+}
+
+fn uuid(env: &mut JNIEnv, uuid: &JString) -> Result<Uuid> {
+    Uuid::from_str(&string(env, uuid)?).map_err(|e| Error::Other(e.into()))
+}
+
+/// Looks up the handler of a service. It may be gone if the service was just removed.
+fn handler(
+    env: &mut JNIEnv,
+    obj: &JObject,
+    service_uuid: Uuid,
+) -> Result<Option<Arc<dyn RequestHandler>>> {
+    let handlers = unsafe { env.get_rust_field::<_, _, Handlers>(obj, "handle")? };
+    Ok(handlers.get(&service_uuid).cloned())
+}
+
+fn send_response(
+    env: &mut JNIEnv,
+    obj: &JObject,
+    device: &JObject,
+    request_id: jint,
+    offset: jint,
+    result: std::result::Result<Option<Vec<u8>>, AttError>,
+) -> Result<()> {
+    let (status, value) = match result {
+        Ok(value) => (0, value),
+        Err(e) => (jint::from(e.code()), None),
+    };
+    let value = match value {
+        Some(value) => JObject::from(slice_to_byte_array(env, &value)?),
+        None => JObject::null(),
+    };
+    env.call_method(
+        obj,
+        "sendResponse",
+        "(Landroid/bluetooth/BluetoothDevice;III[B)V",
+        &[
+            JValue::from(device),
+            JValue::from(request_id),
+            JValue::from(status),
+            JValue::from(offset),
+            JValue::from(&value),
+        ],
+    )?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn gatt_server_on_read_request_internal(
+    env: &mut JNIEnv,
+    obj: JObject,
+    device: JObject,
+    address: JString,
+    request_id: jint,
+    service_uuid: JString,
+    characteristic_uuid: JString,
+    offset: jint,
+) -> Result<()> {
+    let request = ReadRequest {
+        client: BDAddr::from_str(&string(env, &address)?).ok(),
+        service_uuid: uuid(env, &service_uuid)?,
+        characteristic_uuid: uuid(env, &characteristic_uuid)?,
+        offset: offset as u16,
+    };
+    let result = match handler(env, &obj, request.service_uuid)? {
+        Some(handler) => handler.read(&request).map(Some),
+        None => Err(AttError::UnlikelyError),
+    };
+    send_response(env, &obj, &device, request_id, offset, result)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn gatt_server_on_write_request_internal(
+    env: &mut JNIEnv,
+    obj: JObject,
+    device: JObject,
+    address: JString,
+    request_id: jint,
+    service_uuid: JString,
+    characteristic_uuid: JString,
+    offset: jint,
+    value: JByteArray,
+    response_needed: jboolean,
+) -> Result<()> {
+    let request = WriteRequest {
+        client: BDAddr::from_str(&string(env, &address)?).ok(),
+        service_uuid: uuid(env, &service_uuid)?,
+        characteristic_uuid: uuid(env, &characteristic_uuid)?,
+        offset: offset as u16,
+        value: byte_array_to_vec(env, value)?,
+        write_type: if response_needed != 0 {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        },
+    };
+    let result = match handler(env, &obj, request.service_uuid)? {
+        Some(handler) => handler.write(&request).map(|()| None),
+        None => Err(AttError::UnlikelyError),
+    };
+    if response_needed != 0 {
+        send_response(env, &obj, &device, request_id, offset, result)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn gatt_server_on_subscription_changed_internal(
+    env: &mut JNIEnv,
+    obj: JObject,
+    service_uuid: JString,
+    characteristic_uuid: JString,
+    subscribed: jboolean,
+) -> Result<()> {
+    let service_uuid = uuid(env, &service_uuid)?;
+    let characteristic_uuid = uuid(env, &characteristic_uuid)?;
+    if let Some(handler) = handler(env, &obj, service_uuid)? {
+        handler.subscription_changed(service_uuid, characteristic_uuid, subscribed != 0);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_types() {
+        assert_eq!(service_type(true), SERVICE_TYPE_PRIMARY);
+        assert_eq!(service_type(false), SERVICE_TYPE_SECONDARY);
+    }
+
+    #[test]
+    fn permissions_follow_properties() {
+        assert_eq!(permissions(CharPropFlags::READ), PERMISSION_READ);
+        assert_eq!(permissions(CharPropFlags::WRITE), PERMISSION_WRITE);
+        assert_eq!(
+            permissions(CharPropFlags::READ | CharPropFlags::WRITE_WITHOUT_RESPONSE),
+            PERMISSION_READ | PERMISSION_WRITE
+        );
+        // Subscribing goes through the configuration descriptor, which has its own permissions.
+        assert_eq!(
+            permissions(CharPropFlags::NOTIFY | CharPropFlags::INDICATE),
+            0
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `duty_cycle` module scans in windows: the scan is on for a while, then off for a while, over
+//! and over. Scanning keeps the radio busy, so on battery powered devices this trades discovery
+//! latency for power.
+//!
+//! ```no_run
+//! # use btleplug::api::{Central, ScanFilter};
+//! # use btleplug::platform::Adapter;
+//! use btleplug::duty_cycle::DutyCycle;
+//! use std::time::Duration;
+//!
+//! # async fn example(central: Adapter) -> btleplug::Result<()> {
+//! // Scan for 2 seconds out of every 10, give or take half a second.
+//! let duty_cycle = DutyCycle::from_ratio(Duration::from_secs(10), 0.2)
+//!     .with_jitter(Duration::from_millis(500));
+//! let scan = central.start_duty_cycled_scan(ScanFilter::default(), duty_cycle).await?;
+//! // Discovered devices show up in `central.events()` as usual, during the on windows.
+//! scan.stop().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The jitter varies the length of every window randomly, so that gateways started at the same
+//! time don't all scan in lockstep, and advertisers with a fixed interval don't always fall into
+//! the off windows.
+
+use crate::api::{Central, ScanFilter};
+use crate::{Error, Result};
+use futures::future::{self, Either};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// How long the scan windows of a [`DutyCycledScan`] are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DutyCycle {
+    /// How long each scan window is.
+    pub on: Duration,
+    /// How long the pause between scan windows is.
+    pub off: Duration,
+    /// Up to how much each window is randomly made shorter or longer.
+    pub jitter: Duration,
+}
+
+impl DutyCycle {
+    pub fn new(on: Duration, off: Duration) -> Self {
+        Self {
+            on,
+            off,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Creates a duty cycle which scans for `ratio` (between 0 and 1) of every `period`.
+    pub fn from_ratio(period: Duration, ratio: f64) -> Self {
+        let on = period.mul_f64(ratio.clamp(0.0, 1.0));
+        Self::new(on, period - on)
+    }
+
+    pub fn with_jitter(self, jitter: Duration) -> Self {
+        Self { jitter, ..self }
+    }
+}
+
+/// Returns `base` made shorter or longer by up to `jitter`, picked with `random`.
+fn jittered(base: Duration, jitter: Duration, random: u64) -> Duration {
+    if jitter.is_zero() {
+        return base;
+    }
+    // Map the random value to an offset in [-jitter, jitter].
+    let span = jitter.as_nanos() * 2 + 1;
+    let offset = (u128::from(random) % span) as i128 - jitter.as_nanos() as i128;
+    let nanos = (base.as_nanos() as i128 + offset).max(0);
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+}
+
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// A scan running in a [`DutyCycle`], started with
+/// [`Central::start_duty_cycled_scan`](crate::api::Central::start_duty_cycled_scan). The scan
+/// stops when this is dropped, or with [`stop`](DutyCycledScan::stop).
+#[derive(Debug)]
+pub struct DutyCycledScan {
+    stop: Option<oneshot::Sender<()>>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl DutyCycledScan {
+    pub(crate) fn start<C: Central + 'static>(
+        central: C,
+        filter: ScanFilter,
+        duty_cycle: DutyCycle,
+    ) -> Self {
+        let (stop, mut stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                if let Err(e) = central.start_scan(filter.clone()).await {
+                    log::warn!("Failed to start duty cycled scan: {:?}", e);
+                }
+                let on = jittered(duty_cycle.on, duty_cycle.jitter, random());
+                if let Either::Right(_) =
+                    future::select(Box::pin(time::sleep(on)), &mut stopped).await
+                {
+                    break;
+                }
+                if let Err(e) = central.stop_scan().await {
+                    log::warn!("Failed to pause duty cycled scan: {:?}", e);
+                }
+                let off = jittered(duty_cycle.off, duty_cycle.jitter, random());
+                if let Either::Right(_) =
+                    future::select(Box::pin(time::sleep(off)), &mut stopped).await
+                {
+                    break;
+                }
+            }
+            central.stop_scan().await
+        });
+        Self {
+            stop: Some(stop),
+            task,
+        }
+    }
+
+    /// Stops scanning, waiting for the scan to be stopped.
+    pub async fn stop(mut self) -> Result<()> {
+        self.stop.take();
+        (&mut self.task)
+            .await
+            .map_err(|e| Error::RuntimeError(e.to_string()))?
+    }
+}
+
+impl Drop for DutyCycledScan {
+    fn drop(&mut self) {
+        // Dropping the sender wakes up the task, which then stops the scan by itself.
+        self.stop.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_ratio() {
+        let duty_cycle = DutyCycle::from_ratio(Duration::from_secs(10), 0.2);
+        assert_eq!(duty_cycle.on, Duration::from_secs(2));
+        assert_eq!(duty_cycle.off, Duration::from_secs(8));
+        assert_eq!(duty_cycle.jitter, Duration::ZERO);
+        let always_on = DutyCycle::from_ratio(Duration::from_secs(10), 1.5);
+        assert_eq!(always_on.off, Duration::ZERO);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let base = Duration::from_secs(2);
+        let jitter = Duration::from_millis(500);
+        assert_eq!(jittered(base, Duration::ZERO, 12345), base);
+        assert_eq!(jittered(base, jitter, 0), Duration::from_millis(1500));
+        assert_eq!(
+            jittered(base, jitter, 1_000_000_000),
+            Duration::from_millis(2500)
+        );
+        for _ in 0..100 {
+            let window = jittered(base, jitter, random());
+            assert!(window >= base - jitter && window <= base + jitter);
+        }
+        // Windows can't get negative.
+        assert_eq!(
+            jittered(Duration::from_millis(100), jitter, 0),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hex-dump logging of GATT traffic.
+//!
+//! With the `gatt-trace` feature enabled, every characteristic and descriptor read, write and
+//! notification is logged at trace level under the `btleplug::gatt` target, e.g.:
+//!
+//! ```text
+//! TRACE btleplug::gatt > hci0/dev_E3_A1_7B_C8_21_90 <- notification 00002a37-0000-1000-8000-00805f9b34fb: 16 4a 03
+//! ```
+//!
+//! Payloads which should not end up in logs (keys, personal data) can be masked with
+//! [`set_redactor`]. Without the feature nothing is logged and the redactor is never called.
+
+use crate::api::WriteType;
+use crate::platform::PeripheralId;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// The kind of GATT operation that was performed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GattOperation {
+    Read,
+    Write,
+    WriteWithoutResponse,
+    SignedWrite,
+    Notification,
+    ReadDescriptor,
+    WriteDescriptor,
+}
+
+impl GattOperation {
+    /// Whether the payload was sent to the peripheral (as opposed to received from it).
+    pub fn is_outgoing(&self) -> bool {
+        matches!(
+            self,
+            GattOperation::Write
+                | GattOperation::WriteWithoutResponse
+                | GattOperation::SignedWrite
+                | GattOperation::WriteDescriptor
+        )
+    }
+}
+
+impl From<WriteType> for GattOperation {
+    fn from(write_type: WriteType) -> Self {
+        match write_type {
+            WriteType::WithResponse => GattOperation::Write,
+            WriteType::WithoutResponse => GattOperation::WriteWithoutResponse,
+            WriteType::Signed => GattOperation::SignedWrite,
+        }
+    }
+}
+
+impl Display for GattOperation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            GattOperation::Read => "read",
+            GattOperation::Write => "write",
+            GattOperation::WriteWithoutResponse => "write without response",
+            GattOperation::SignedWrite => "signed write",
+            GattOperation::Notification => "notification",
+            GattOperation::ReadDescriptor => "read descriptor",
+            GattOperation::WriteDescriptor => "write descriptor",
+        })
+    }
+}
+
+/// A single logged GATT operation, as passed to the redactor.
+#[derive(Clone, Copy, Debug)]
+pub struct GattTraffic<'a> {
+    /// The peripheral the operation was performed on.
+    pub peripheral: &'a PeripheralId,
+    /// The UUID of the characteristic or descriptor.
+    pub uuid: Uuid,
+    pub operation: GattOperation,
+    /// The value that was read, written or notified.
+    pub payload: &'a [u8],
+}
+
+type Redactor = Arc<dyn Fn(&GattTraffic) -> Option<String> + Send + Sync>;
+
+static REDACTOR: RwLock<Option<Redactor>> = RwLock::new(None);
+
+/// Installs a function which can replace the hex dump of a payload before it is logged. Returning
+/// `Some(text)` logs `text` instead of the payload, returning `None` logs the payload as is.
+///
+/// ```
+/// use btleplug::gatt_log::set_redactor;
+/// use uuid::Uuid;
+///
+/// const PASSWORD: Uuid = Uuid::from_u128(0x6e400002_b534_f393_67a9_e50e24dcca9e);
+/// set_redactor(|traffic| {
+///     (traffic.uuid == PASSWORD).then(|| format!("<{} bytes redacted>", traffic.payload.len()))
+/// });
+/// ```
+pub fn set_redactor<F>(redactor: F)
+where
+    F: Fn(&GattTraffic) -> Option<String> + Send + Sync + 'static,
+{
+    *REDACTOR.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(redactor));
+}
+
+/// Removes the redactor installed with [`set_redactor`].
+pub fn clear_redactor() {
+    *REDACTOR.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+#[cfg(feature = "gatt-trace")]
+pub(crate) fn log_traffic(
+    peripheral: &PeripheralId,
+    uuid: Uuid,
+    operation: GattOperation,
+    payload: &[u8],
+) {
+    if !log::log_enabled!(target: "btleplug::gatt", log::Level::Trace) {
+        return;
+    }
+    let traffic = GattTraffic {
+        peripheral,
+        uuid,
+        operation,
+        payload,
+    };
+    let redactor = REDACTOR.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let payload = match redactor.and_then(|redactor| redactor(&traffic)) {
+        Some(redacted) => redacted,
+        None => hex_dump(payload),
+    };
+    let direction = if operation.is_outgoing() { "->" } else { "<-" };
+    log::trace!(
+        target: "btleplug::gatt",
+        "{} {} {} {}: {}",
+        peripheral,
+        direction,
+        operation,
+        uuid,
+        payload
+    );
+}
+
+#[cfg(not(feature = "gatt-trace"))]
+#[inline(always)]
+pub(crate) fn log_traffic(
+    _peripheral: &PeripheralId,
+    _uuid: Uuid,
+    _operation: GattOperation,
+    _payload: &[u8],
+) {
+}
+
+#[cfg_attr(not(feature = "gatt-trace"), allow(dead_code))]
+fn hex_dump(payload: &[u8]) -> String {
+    payload
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump_is_space_separated() {
+        assert_eq!(hex_dump(&[0x16, 0x4a, 0x03]), "16 4a 03");
+        assert_eq!(hex_dump(&[]), "");
+    }
+
+    #[test]
+    fn writes_are_outgoing() {
+        assert!(GattOperation::Write.is_outgoing());
+        assert!(GattOperation::WriteDescriptor.is_outgoing());
+        assert!(GattOperation::SignedWrite.is_outgoing());
+        assert!(!GattOperation::Notification.is_outgoing());
+        assert!(!GattOperation::Read.is_outgoing());
+    }
+}
@@ -0,0 +1,401 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `interceptor` module lets code run around every GATT operation of a peripheral, for
+//! concerns like auditing, policy enforcement or encrypting payloads at the application layer.
+//!
+//! A [`GattInterceptor`] is registered on a single peripheral with
+//! [`Peripheral::add_interceptor`](crate::api::Peripheral::add_interceptor), or on all the
+//! peripherals of a central with [`Central::add_interceptor`](crate::api::Central::add_interceptor).
+//! Reads, writes, descriptor reads and writes and notifications then go through
+//! [`before`](GattInterceptor::before), which can change the outgoing payload or reject the
+//! operation, and [`after`](GattInterceptor::after), which can change the incoming payload or the
+//! error. The interceptors of the central run before those of the peripheral, each in the order
+//! they were added, and `after` runs in the reverse order: the first interceptor added sees
+//! payloads closest to the application.
+//!
+//! ```
+//! use btleplug::interceptor::{GattInterceptor, GattRequest};
+//! use btleplug::{Error, Result};
+//!
+//! /// Rejects writes to a characteristic unless the application unlocked it.
+//! struct ReadOnly(uuid::Uuid);
+//!
+//! impl GattInterceptor for ReadOnly {
+//!     fn before(&self, request: &GattRequest, _payload: &mut Vec<u8>) -> Result<()> {
+//!         if request.uuid == self.0 && request.operation.is_outgoing() {
+//!             return Err(Error::Other("read only".into()));
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use crate::api::{Characteristic, Descriptor, ValueNotification};
+use crate::gatt_log::GattOperation;
+use crate::platform::PeripheralId;
+use crate::Result;
+use futures::stream::{Stream, StreamExt};
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// A GATT operation as seen by a [`GattInterceptor`].
+#[derive(Clone, Copy, Debug)]
+pub struct GattRequest<'a> {
+    /// The peripheral the operation is performed on.
+    pub peripheral: &'a PeripheralId,
+    pub operation: GattOperation,
+    /// The UUID of the characteristic or descriptor.
+    pub uuid: Uuid,
+    /// The UUID of the service.
+    pub service_uuid: Uuid,
+    /// The UUID of the characteristic a descriptor belongs to, for descriptor operations.
+    pub characteristic_uuid: Option<Uuid>,
+}
+
+impl<'a> GattRequest<'a> {
+    pub(crate) fn characteristic(
+        peripheral: &'a PeripheralId,
+        characteristic: &Characteristic,
+        operation: GattOperation,
+    ) -> Self {
+        Self {
+            peripheral,
+            operation,
+            uuid: characteristic.uuid,
+            service_uuid: characteristic.service_uuid,
+            characteristic_uuid: None,
+        }
+    }
+
+    pub(crate) fn descriptor(
+        peripheral: &'a PeripheralId,
+        descriptor: &Descriptor,
+        operation: GattOperation,
+    ) -> Self {
+        Self {
+            peripheral,
+            operation,
+            uuid: descriptor.uuid,
+            service_uuid: descriptor.service_uuid,
+            characteristic_uuid: Some(descriptor.characteristic_uuid),
+        }
+    }
+
+    fn notification(peripheral: &'a PeripheralId, notification: &ValueNotification) -> Self {
+        Self {
+            peripheral,
+            operation: GattOperation::Notification,
+            uuid: notification.uuid,
+            service_uuid: notification.service_uuid,
+            characteristic_uuid: None,
+        }
+    }
+}
+
+/// Code run around the GATT operations of a peripheral. Both methods do nothing by default.
+pub trait GattInterceptor: Send + Sync {
+    /// Called before an operation is sent to the peripheral. `payload` is the value to write,
+    /// which may be changed; for reads it's empty and changes are ignored. Returning an error
+    /// cancels the operation, which then fails with that error without `after` being called.
+    fn before(&self, _request: &GattRequest, _payload: &mut Vec<u8>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once an operation completed, or a notification was received. On success `result`
+    /// holds the value read or notified, which may be changed, or the value written. An error
+    /// for a notification drops it.
+    fn after(&self, _request: &GattRequest, _result: &mut Result<Vec<u8>>) {}
+}
+
+/// The interceptors registered on a central or peripheral.
+#[derive(Default)]
+pub(crate) struct InterceptorList {
+    interceptors: RwLock<Vec<Arc<dyn GattInterceptor>>>,
+}
+
+impl InterceptorList {
+    pub fn add(&self, interceptor: Arc<dyn GattInterceptor>) {
+        self.interceptors.write().unwrap().push(interceptor);
+    }
+
+    /// Removes an interceptor added before, returning whether it was found.
+    pub fn remove(&self, interceptor: &Arc<dyn GattInterceptor>) -> bool {
+        let mut interceptors = self.interceptors.write().unwrap();
+        let len = interceptors.len();
+        interceptors.retain(|added| !Arc::ptr_eq(added, interceptor));
+        interceptors.len() != len
+    }
+}
+
+impl Debug for InterceptorList {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("InterceptorList")
+            .field("len", &self.interceptors.read().unwrap().len())
+            .finish()
+    }
+}
+
+/// The interceptors applying to a peripheral: those of its central, then its own.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Interceptors {
+    central: Option<Arc<InterceptorList>>,
+    peripheral: Arc<InterceptorList>,
+}
+
+impl Interceptors {
+    pub fn new(central: Option<Arc<InterceptorList>>, peripheral: Arc<InterceptorList>) -> Self {
+        Self {
+            central,
+            peripheral,
+        }
+    }
+
+    /// The interceptors registered right now, in the order `before` is called.
+    fn chain(&self) -> Vec<Arc<dyn GattInterceptor>> {
+        let mut chain = Vec::new();
+        if let Some(central) = &self.central {
+            chain.extend(central.interceptors.read().unwrap().iter().cloned());
+        }
+        chain.extend(self.peripheral.interceptors.read().unwrap().iter().cloned());
+        chain
+    }
+
+    fn before(
+        chain: &[Arc<dyn GattInterceptor>],
+        request: &GattRequest,
+        payload: &mut Vec<u8>,
+    ) -> Result<()> {
+        chain
+            .iter()
+            .try_for_each(|interceptor| interceptor.before(request, payload))
+    }
+
+    fn after(
+        chain: &[Arc<dyn GattInterceptor>],
+        request: &GattRequest,
+        mut result: Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        for interceptor in chain.iter().rev() {
+            interceptor.after(request, &mut result);
+        }
+        result
+    }
+
+    /// Runs a read through the interceptors.
+    pub async fn read(
+        &self,
+        request: &GattRequest<'_>,
+        read: impl Future<Output = Result<Vec<u8>>>,
+    ) -> Result<Vec<u8>> {
+        let chain = self.chain();
+        if chain.is_empty() {
+            return read.await;
+        }
+        Self::before(&chain, request, &mut Vec::new())?;
+        Self::after(&chain, request, read.await)
+    }
+
+    /// Runs a write of `data` through the interceptors, which may change the data `write` is
+    /// called with.
+    pub async fn write<F, Fut>(
+        &self,
+        request: &GattRequest<'_>,
+        data: &[u8],
+        write: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(Vec<u8>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let chain = self.chain();
+        if chain.is_empty() {
+            return write(data.to_vec()).await;
+        }
+        let mut payload = data.to_vec();
+        Self::before(&chain, request, &mut payload)?;
+        let result = write(payload.clone()).await.map(|()| payload);
+        Self::after(&chain, request, result).map(|_| ())
+    }
+
+    /// Runs the notifications of a peripheral through the interceptors registered at the time
+    /// each one is received.
+    pub fn notifications(
+        self,
+        peripheral: PeripheralId,
+        notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+    ) -> Pin<Box<dyn Stream<Item = ValueNotification> + Send>> {
+        Box::pin(notifications.filter_map(move |mut notification| {
+            let chain = self.chain();
+            let result = if chain.is_empty() {
+                Some(notification)
+            } else {
+                let request = GattRequest::notification(&peripheral, &notification);
+                let value = std::mem::take(&mut notification.value);
+                match Self::after(&chain, &request, Ok(value)) {
+                    Ok(value) => {
+                        notification.value = value;
+                        Some(notification)
+                    }
+                    Err(e) => {
+                        log::debug!("Notification dropped by an interceptor: {}", e);
+                        None
+                    }
+                }
+            };
+            futures::future::ready(result)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{BDAddr, CharPropFlags};
+    use crate::common::fake::peripheral_id;
+    use crate::Error;
+    use futures::stream;
+    use std::collections::BTreeSet;
+    use std::sync::Mutex;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// Logs its calls, and appends its byte to the payloads it sees. Rejects operations in
+    /// `before` if `reject` is set.
+    struct Recorder {
+        byte: u8,
+        reject: bool,
+        log: Log,
+    }
+
+    impl GattInterceptor for Recorder {
+        fn before(&self, _request: &GattRequest, payload: &mut Vec<u8>) -> Result<()> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("before {}", self.byte));
+            if self.reject {
+                return Err(Error::PermissionDenied);
+            }
+            payload.push(self.byte);
+            Ok(())
+        }
+
+        fn after(&self, _request: &GattRequest, result: &mut Result<Vec<u8>>) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("after {}", self.byte));
+            if let Ok(value) = result {
+                value.push(self.byte);
+            }
+        }
+    }
+
+    fn list(log: &Log, bytes: &[u8], reject: Option<u8>) -> Arc<InterceptorList> {
+        let list = Arc::new(InterceptorList::default());
+        for &byte in bytes {
+            list.add(Arc::new(Recorder {
+                byte,
+                reject: reject == Some(byte),
+                log: log.clone(),
+            }));
+        }
+        list
+    }
+
+    fn characteristic() -> Characteristic {
+        Characteristic {
+            uuid: Uuid::from_u128(0x2a19),
+            service_uuid: Uuid::from_u128(0x180f),
+            properties: CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::NOTIFY,
+            descriptors: BTreeSet::new(),
+            handle: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn central_interceptors_run_first_and_after_in_reverse() {
+        let log = Log::default();
+        let interceptors =
+            Interceptors::new(Some(list(&log, &[1, 2], None)), list(&log, &[3], None));
+        let id = peripheral_id(BDAddr::default());
+        let request = GattRequest::characteristic(&id, &characteristic(), GattOperation::Read);
+        let value = interceptors
+            .read(&request, async {
+                log.lock().unwrap().push("read".to_string());
+                Ok(vec![0])
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["before 1", "before 2", "before 3", "read", "after 3", "after 2", "after 1"]
+        );
+        // The first interceptor added sees the value last, closest to the application.
+        assert_eq!(value, [0, 3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn rejections_cancel_the_operation() {
+        let log = Log::default();
+        let interceptors =
+            Interceptors::new(Some(list(&log, &[1, 2], Some(2))), list(&log, &[3], None));
+        let id = peripheral_id(BDAddr::default());
+        let request = GattRequest::characteristic(&id, &characteristic(), GattOperation::Write);
+        let result = interceptors
+            .write(&request, &[0], |_| async {
+                log.lock().unwrap().push("write".to_string());
+                Ok(())
+            })
+            .await;
+        assert!(matches!(result, Err(Error::PermissionDenied)));
+        assert_eq!(*log.lock().unwrap(), ["before 1", "before 2"]);
+    }
+
+    #[tokio::test]
+    async fn changed_payloads_are_written() {
+        let log = Log::default();
+        let interceptors = Interceptors::new(Some(list(&log, &[1], None)), list(&log, &[2], None));
+        let id = peripheral_id(BDAddr::default());
+        let request = GattRequest::characteristic(&id, &characteristic(), GattOperation::Write);
+        let written = Mutex::new(None);
+        interceptors
+            .write(&request, &[0], |data| async {
+                *written.lock().unwrap() = Some(data);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(written.into_inner().unwrap(), Some(vec![0, 1, 2]));
+    }
+
+    #[tokio::test]
+    async fn notifications_go_through_after() {
+        let log = Log::default();
+        let interceptors = Interceptors::new(None, list(&log, &[1, 2], None));
+        let id = peripheral_id(BDAddr::default());
+        let characteristic = characteristic();
+        let notification = ValueNotification {
+            uuid: characteristic.uuid,
+            service_uuid: characteristic.service_uuid,
+            handle: None,
+            value: vec![0],
+            is_indication: false,
+        };
+        let notifications: Vec<_> = interceptors
+            .notifications(id, Box::pin(stream::iter([notification])))
+            .collect()
+            .await;
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].value, [0, 2, 1]);
+        assert_eq!(*log.lock().unwrap(), ["after 2", "after 1"]);
+    }
+}
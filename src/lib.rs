@@ -90,20 +90,54 @@ use std::result;
 use std::time::Duration;
 
 pub mod api;
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "bluer", not(feature = "bluez")))]
+mod bluerble;
+#[cfg(all(target_os = "linux", feature = "bluez"))]
 mod bluez;
-#[cfg(not(target_os = "linux"))]
+pub mod capture;
 mod common;
 #[cfg(target_vendor = "apple")]
 mod corebluetooth;
+pub mod decorator;
 #[cfg(target_os = "android")]
 mod droidplug;
+pub mod duty_cycle;
+pub mod gatt_log;
+pub mod interceptor;
 pub mod platform;
+pub mod presentation_format;
+pub mod profiles;
+pub mod proximity;
+pub mod scan_session;
 #[cfg(feature = "serde")]
 pub mod serde;
+pub mod store;
 #[cfg(target_os = "windows")]
 mod winrtble;
 
+#[cfg(not(any(
+    target_os = "linux",
+    target_vendor = "apple",
+    target_os = "android",
+    target_os = "windows"
+)))]
+compile_error!(
+    "btleplug has no backend for this platform. Supported are Linux, Windows, macOS, iOS and Android."
+);
+
+#[cfg(all(target_os = "linux", feature = "bluez", feature = "bluer"))]
+compile_error!(
+    "The `bluez` and `bluer` features select different Linux backends and can't be enabled together. \
+     To use the bluer backend, depend on btleplug with `default-features = false` and make sure no \
+     other crate in the build enables its default features."
+);
+
+#[cfg(all(target_os = "linux", not(any(feature = "bluez", feature = "bluer"))))]
+compile_error!(
+    "btleplug needs a Linux backend. Enable either the `bluez` feature, which is on by default, or \
+     the `bluer` feature."
+);
+
 /// The main error type returned by most methods in btleplug.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -116,17 +150,46 @@ pub enum Error {
     #[error("Not connected")]
     NotConnected,
 
+    #[error("Connection failed: {}", _0)]
+    ConnectionFailed(String),
+
+    /// The device could not be reached, usually because it is out of range or turned off.
+    #[error("Device unreachable")]
+    Unreachable,
+
+    /// The device refused the operation, usually because it requires pairing or encryption.
+    #[error("Access denied by the device")]
+    AccessDenied,
+
+    /// The device answered with an ATT error, whose code is included if the platform reports it.
+    #[error(
+        "ATT protocol error{}",
+        .0.map(|code| format!(" {:#04x}", code)).unwrap_or_default()
+    )]
+    ProtocolError(Option<u8>),
+
     #[error("Unexpected callback")]
     UnexpectedCallback,
 
     #[error("Unexpected characteristic")]
     UnexpectedCharacteristic,
 
+    #[error("No such service")]
+    NoSuchService,
+
     #[error("No such characteristic")]
     NoSuchCharacteristic,
 
-    #[error("The operation is not supported: {}", _0)]
-    NotSupported(String),
+    #[error("No such descriptor")]
+    NoSuchDescriptor,
+
+    #[error("Service discovery failed with status {0}")]
+    ServiceDiscoveryFailed(i32),
+
+    /// The backend doesn't support this feature, see
+    /// [`Central::capabilities`](api::Central::capabilities).
+    #[error("The operation is not supported: {feature}")]
+    NotSupported { feature: api::Feature },
 
     #[error("Timed out after {:?}", _0)]
     TimedOut(Duration),
@@ -140,10 +203,30 @@ pub enum Error {
     #[error("Runtime Error: {}", _0)]
     RuntimeError(String),
 
+    /// Code btleplug runs on behalf of the platform panicked, with the panic message. The panic is
+    /// caught rather than unwinding into the platform. Currently only reported on Android, for the
+    /// callbacks Java makes into Rust.
+    #[error("Panicked: {}", _0)]
+    Panicked(String),
+
     #[error("{}", _0)]
     Other(Box<dyn std::error::Error + Send + Sync>),
 }
 
+impl Error {
+    /// Whether the error may go away by simply trying again: a timeout, an unreachable device, or
+    /// a platform error known to be temporary, like BlueZ reporting that another operation is in
+    /// progress. Errors the device or the platform will keep returning, including GATT errors,
+    /// aren't transient, and neither are platform errors btleplug doesn't know.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::TimedOut(_) | Error::Unreachable => true,
+            Error::Other(error) => platform::is_transient_error(error.as_ref()),
+            _ => false,
+        }
+    }
+}
+
 /// Convert [`PoisonError`] to [`Error`] for replace `unwrap` to `map_err`
 impl<T: std::fmt::Debug> From<std::sync::PoisonError<T>> for Error {
     fn from(e: std::sync::PoisonError<T>) -> Self {
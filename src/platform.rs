@@ -1,24 +1,45 @@
 //! The `platform` module contains the platform-specific implementations of the various [`api`]
 //! traits. Refer for the `api` module for how to use them.
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "bluer", not(feature = "bluez")))]
+pub use crate::bluerble::{
+    adapter::Adapter, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
+};
+#[cfg(all(target_os = "linux", feature = "bluez"))]
 pub use crate::bluez::{
     adapter::Adapter, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
 };
+#[cfg(all(target_os = "linux", feature = "bluer", not(feature = "bluez")))]
+pub(crate) use crate::bluerble::is_transient_error;
+#[cfg(all(target_os = "linux", feature = "bluez"))]
+pub(crate) use crate::bluez::is_transient_error;
 #[cfg(target_vendor = "apple")]
 pub use crate::corebluetooth::{
-    adapter::Adapter, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
+    adapter::Adapter,
+    manager::{Manager, ManagerBuilder, QueueQos},
+    peripheral::Peripheral,
+    peripheral::PeripheralId,
 };
 #[cfg(target_os = "android")]
 pub use crate::droidplug::{
-    adapter::Adapter, init, jni::global_jvm, manager::Manager, peripheral::Peripheral,
-    peripheral::PeripheralId,
+    adapter::Adapter, init, jni::global_jvm, manager::Manager, peripheral::ConnectRetryPolicy,
+    peripheral::Peripheral, peripheral::PeripheralId,
 };
 #[cfg(target_os = "windows")]
 pub use crate::winrtble::{
-    adapter::Adapter, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
+    adapter::Adapter,
+    manager::{Manager, ManagerBuilder},
+    peripheral::Peripheral,
+    peripheral::PeripheralId,
 };
 
+/// Whether a platform error wrapped in [`Error::Other`](crate::Error::Other) may go away when
+/// retried. This platform doesn't report any such errors.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn is_transient_error(_error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    false
+}
+
 use crate::api::{self, Central};
 use static_assertions::assert_impl_all;
 use std::{
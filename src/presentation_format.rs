@@ -0,0 +1,415 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `presentation_format` module decodes characteristic values with the help of their
+//! Characteristic Presentation Format descriptor, which tells how the value is encoded (e.g. a
+//! 16-bit signed integer or an IEEE 11073 SFLOAT), its decimal exponent and its unit.
+//!
+//! ```no_run
+//! # use btleplug::api::Characteristic;
+//! # use btleplug::platform::Peripheral;
+//! use btleplug::presentation_format::{self, Value};
+//!
+//! # async fn example(peripheral: Peripheral, temperature: Characteristic) -> btleplug::Result<()> {
+//! let reading = presentation_format::read_typed(&peripheral, &temperature).await?;
+//! if let Value::Float(celsius) = reading.value {
+//!     println!("{:.2} (unit {:#06x})", celsius, reading.unit);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Reading the descriptor takes a round trip, so when decoding many values of the same
+//! characteristic, read it once with [`read_presentation_format`] and decode each value with
+//! [`decode_value`].
+
+use crate::api::{bleuuid::uuid_from_u16, Characteristic, Peripheral};
+use crate::{Error, Result};
+use uuid::Uuid;
+
+/// The Characteristic Presentation Format descriptor.
+pub const CHARACTERISTIC_PRESENTATION_FORMAT: Uuid = uuid_from_u16(0x2904);
+
+/// How a characteristic value is encoded, from the Format Types in the Bluetooth Assigned Numbers.
+/// Multi-byte values are little endian.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Boolean,
+    UInt2,
+    UInt4,
+    UInt8,
+    UInt12,
+    UInt16,
+    UInt24,
+    UInt32,
+    UInt48,
+    UInt64,
+    UInt128,
+    SInt8,
+    SInt12,
+    SInt16,
+    SInt24,
+    SInt32,
+    SInt48,
+    SInt64,
+    SInt128,
+    /// IEEE 754 binary32.
+    Float32,
+    /// IEEE 754 binary64.
+    Float64,
+    /// IEEE 11073 16-bit SFLOAT: a 4-bit exponent and a 12-bit mantissa.
+    SFloat,
+    /// IEEE 11073 32-bit FLOAT: an 8-bit exponent and a 24-bit mantissa.
+    Float,
+    /// Two 16-bit unsigned integers.
+    DUInt16,
+    Utf8,
+    Utf16,
+    /// Opaque structure.
+    Struct,
+    /// A format this version of btleplug doesn't know.
+    Other(u8),
+}
+
+impl From<u8> for Format {
+    fn from(format: u8) -> Self {
+        match format {
+            0x01 => Format::Boolean,
+            0x02 => Format::UInt2,
+            0x03 => Format::UInt4,
+            0x04 => Format::UInt8,
+            0x05 => Format::UInt12,
+            0x06 => Format::UInt16,
+            0x07 => Format::UInt24,
+            0x08 => Format::UInt32,
+            0x09 => Format::UInt48,
+            0x0A => Format::UInt64,
+            0x0B => Format::UInt128,
+            0x0C => Format::SInt8,
+            0x0D => Format::SInt12,
+            0x0E => Format::SInt16,
+            0x0F => Format::SInt24,
+            0x10 => Format::SInt32,
+            0x11 => Format::SInt48,
+            0x12 => Format::SInt64,
+            0x13 => Format::SInt128,
+            0x14 => Format::Float32,
+            0x15 => Format::Float64,
+            0x16 => Format::SFloat,
+            0x17 => Format::Float,
+            0x18 => Format::DUInt16,
+            0x19 => Format::Utf8,
+            0x1A => Format::Utf16,
+            0x1B => Format::Struct,
+            other => Format::Other(other),
+        }
+    }
+}
+
+/// The content of a Characteristic Presentation Format descriptor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PresentationFormat {
+    pub format: Format,
+    /// The decimal exponent of integer values: the value represented is the integer times
+    /// 10<sup>exponent</sup>.
+    pub exponent: i8,
+    /// The unit of the value, as the 16-bit UUID assigned to it in the Bluetooth Assigned Numbers,
+    /// e.g. `0x272F` for degrees Celsius. `0x2700` means unitless.
+    pub unit: u16,
+    /// The organization which assigned [`description`](Self::description), `0x01` for the
+    /// Bluetooth SIG.
+    pub namespace: u8,
+    /// Tells apart characteristics with the same UUID, e.g. `0x0106` for "left" in the Bluetooth
+    /// SIG namespace.
+    pub description: u16,
+}
+
+impl PresentationFormat {
+    /// Parses the value of a Characteristic Presentation Format descriptor.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        match data {
+            [format, exponent, unit_0, unit_1, namespace, description_0, description_1, ..] => {
+                Ok(PresentationFormat {
+                    format: Format::from(*format),
+                    exponent: *exponent as i8,
+                    unit: u16::from_le_bytes([*unit_0, *unit_1]),
+                    namespace: *namespace,
+                    description: u16::from_le_bytes([*description_0, *description_1]),
+                })
+            }
+            _ => Err(Error::Other(
+                format!("Invalid Characteristic Presentation Format {:02x?}", data).into(),
+            )),
+        }
+    }
+}
+
+/// A decoded characteristic value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    /// An unsigned integer with an exponent of zero.
+    Unsigned(u64),
+    /// A signed integer with an exponent of zero.
+    Signed(i64),
+    /// A floating point value, or an integer with a non-zero exponent applied. The special values
+    /// of the IEEE 11073 formats map to NaN and the infinities.
+    Float(f64),
+    Text(String),
+    /// A value of a format which isn't decoded further: 128-bit integers, two 16-bit integers,
+    /// structures and unknown formats.
+    Bytes(Vec<u8>),
+}
+
+/// A characteristic value decoded according to its presentation format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypedValue {
+    pub value: Value,
+    /// The unit of the value, see [`PresentationFormat::unit`].
+    pub unit: u16,
+}
+
+/// Decodes a characteristic value according to its presentation format. Fails if the value is
+/// too short for the format; extra bytes are ignored.
+pub fn decode_value(format: &PresentationFormat, value: &[u8]) -> Result<TypedValue> {
+    let value = match format.format {
+        Format::Boolean => Value::Bool(unsigned(value, 1)? & 0x01 != 0),
+        Format::UInt2 => scaled_unsigned(unsigned(value, 1)? & 0x03, format.exponent),
+        Format::UInt4 => scaled_unsigned(unsigned(value, 1)? & 0x0F, format.exponent),
+        Format::UInt8 => scaled_unsigned(unsigned(value, 1)?, format.exponent),
+        Format::UInt12 => scaled_unsigned(unsigned(value, 2)? & 0x0FFF, format.exponent),
+        Format::UInt16 => scaled_unsigned(unsigned(value, 2)?, format.exponent),
+        Format::UInt24 => scaled_unsigned(unsigned(value, 3)?, format.exponent),
+        Format::UInt32 => scaled_unsigned(unsigned(value, 4)?, format.exponent),
+        Format::UInt48 => scaled_unsigned(unsigned(value, 6)?, format.exponent),
+        Format::UInt64 => scaled_unsigned(unsigned(value, 8)?, format.exponent),
+        Format::SInt8 => scaled_signed(signed(value, 1, 8)?, format.exponent),
+        Format::SInt12 => scaled_signed(signed(value, 2, 12)?, format.exponent),
+        Format::SInt16 => scaled_signed(signed(value, 2, 16)?, format.exponent),
+        Format::SInt24 => scaled_signed(signed(value, 3, 24)?, format.exponent),
+        Format::SInt32 => scaled_signed(signed(value, 4, 32)?, format.exponent),
+        Format::SInt48 => scaled_signed(signed(value, 6, 48)?, format.exponent),
+        Format::SInt64 => scaled_signed(signed(value, 8, 64)?, format.exponent),
+        Format::Float32 => Value::Float(f32::from_bits(unsigned(value, 4)? as u32) as f64),
+        Format::Float64 => Value::Float(f64::from_bits(unsigned(value, 8)?)),
+        Format::SFloat => Value::Float(decode_sfloat(unsigned(value, 2)? as u16)),
+        Format::Float => Value::Float(decode_float(unsigned(value, 4)? as u32)),
+        Format::Utf8 => Value::Text(String::from_utf8_lossy(value).into_owned()),
+        Format::Utf16 => {
+            let units: Vec<u16> = value
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+            Value::Text(String::from_utf16_lossy(&units))
+        }
+        Format::UInt128 | Format::SInt128 | Format::DUInt16 | Format::Struct | Format::Other(_) => {
+            Value::Bytes(value.to_vec())
+        }
+    };
+    Ok(TypedValue {
+        value,
+        unit: format.unit,
+    })
+}
+
+/// Reads and parses the Characteristic Presentation Format descriptor of a characteristic. Fails
+/// with [`Error::NoSuchDescriptor`] if the characteristic doesn't have one.
+pub async fn read_presentation_format<P: Peripheral>(
+    peripheral: &P,
+    characteristic: &Characteristic,
+) -> Result<PresentationFormat> {
+    let descriptor = characteristic
+        .descriptors
+        .iter()
+        .find(|descriptor| descriptor.uuid == CHARACTERISTIC_PRESENTATION_FORMAT)
+        .ok_or(Error::NoSuchDescriptor)?;
+    PresentationFormat::parse(&peripheral.read_descriptor(descriptor).await?)
+}
+
+/// Reads a characteristic and decodes its value according to its Characteristic Presentation
+/// Format descriptor, which is read first.
+pub async fn read_typed<P: Peripheral>(
+    peripheral: &P,
+    characteristic: &Characteristic,
+) -> Result<TypedValue> {
+    let format = read_presentation_format(peripheral, characteristic).await?;
+    decode_value(&format, &peripheral.read(characteristic).await?)
+}
+
+/// Reads the first `len` bytes of `value` as a little endian unsigned integer.
+fn unsigned(value: &[u8], len: usize) -> Result<u64> {
+    let bytes = value.get(..len).ok_or_else(|| {
+        Error::Other(format!("Value {:02x?} is shorter than {} bytes", value, len).into())
+    })?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |result, &byte| result << 8 | u64::from(byte)))
+}
+
+/// Reads the first `len` bytes of `value` as a little endian signed integer of `bits` bits.
+fn signed(value: &[u8], len: usize, bits: u32) -> Result<i64> {
+    let shift = 64 - bits;
+    Ok(((unsigned(value, len)? << shift) as i64) >> shift)
+}
+
+fn scaled_unsigned(value: u64, exponent: i8) -> Value {
+    match exponent {
+        0 => Value::Unsigned(value),
+        _ => Value::Float(scale(value as f64, exponent.into())),
+    }
+}
+
+fn scaled_signed(value: i64, exponent: i8) -> Value {
+    match exponent {
+        0 => Value::Signed(value),
+        _ => Value::Float(scale(value as f64, exponent.into())),
+    }
+}
+
+/// Returns `value` times 10 to the power of `exponent`. Negative exponents divide, so that e.g.
+/// 215 with an exponent of -1 gives exactly the closest `f64` to 21.5.
+fn scale(value: f64, exponent: i32) -> f64 {
+    if exponent < 0 {
+        value / 10f64.powi(-exponent)
+    } else {
+        value * 10f64.powi(exponent)
+    }
+}
+
+fn decode_sfloat(raw: u16) -> f64 {
+    match raw {
+        0x07FE => f64::INFINITY,
+        0x0802 => f64::NEG_INFINITY,
+        // NaN, NRes (not at this resolution) and the reserved value.
+        0x07FF..=0x0801 => f64::NAN,
+        _ => {
+            let mantissa = ((raw << 4) as i16) >> 4;
+            let exponent = (raw as i16) >> 12;
+            scale(f64::from(mantissa), exponent.into())
+        }
+    }
+}
+
+fn decode_float(raw: u32) -> f64 {
+    match raw {
+        0x007F_FFFE => f64::INFINITY,
+        0x0080_0002 => f64::NEG_INFINITY,
+        // NaN, NRes and the reserved value.
+        0x007F_FFFF..=0x0080_0001 => f64::NAN,
+        _ => {
+            let mantissa = ((raw << 8) as i32) >> 8;
+            let exponent = (raw as i32) >> 24;
+            scale(f64::from(mantissa), exponent)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: Format, exponent: i8) -> PresentationFormat {
+        PresentationFormat {
+            format,
+            exponent,
+            unit: 0x272F,
+            namespace: 0x01,
+            description: 0x0000,
+        }
+    }
+
+    fn decode(format: PresentationFormat, value: &[u8]) -> Value {
+        decode_value(&format, value).unwrap().value
+    }
+
+    #[test]
+    fn parse_descriptor() {
+        assert_eq!(
+            PresentationFormat::parse(&[0x0E, 0xFE, 0x2F, 0x27, 0x01, 0x06, 0x01]).unwrap(),
+            PresentationFormat {
+                format: Format::SInt16,
+                exponent: -2,
+                unit: 0x272F,
+                namespace: 0x01,
+                description: 0x0106,
+            }
+        );
+        assert!(PresentationFormat::parse(&[0x0E, 0xFE]).is_err());
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(
+            decode(format(Format::UInt16, 0), &[0x34, 0x12]),
+            Value::Unsigned(0x1234)
+        );
+        assert_eq!(
+            decode(format(Format::UInt24, 0), &[0x56, 0x34, 0x12]),
+            Value::Unsigned(0x123456)
+        );
+        assert_eq!(
+            decode(format(Format::SInt16, 0), &[0xFE, 0xFF]),
+            Value::Signed(-2)
+        );
+        assert_eq!(
+            decode(format(Format::SInt12, 0), &[0xFF, 0xFF]),
+            Value::Signed(-1)
+        );
+        assert_eq!(
+            decode(format(Format::UInt4, 0), &[0xF3]),
+            Value::Unsigned(3)
+        );
+        assert!(decode_value(&format(Format::UInt32, 0), &[0x01, 0x02]).is_err());
+    }
+
+    #[test]
+    fn exponent_is_applied() {
+        // 21.5 degrees Celsius as a sint16 with an exponent of -1.
+        assert_eq!(
+            decode(format(Format::SInt16, -1), &[0xD7, 0x00]),
+            Value::Float(21.5)
+        );
+        assert_eq!(
+            decode(format(Format::UInt8, 2), &[0x03]),
+            Value::Float(300.0)
+        );
+    }
+
+    #[test]
+    fn ieee_11073_floats() {
+        // Mantissa 375 with an exponent of -1.
+        assert_eq!(
+            decode(format(Format::SFloat, 0), &[0x77, 0xF1]),
+            Value::Float(37.5)
+        );
+        // Mantissa -5 with an exponent of 2.
+        assert_eq!(
+            decode(format(Format::Float, 0), &[0xFB, 0xFF, 0xFF, 0x02]),
+            Value::Float(-500.0)
+        );
+        assert_eq!(
+            decode(format(Format::SFloat, 0), &[0xFE, 0x07]),
+            Value::Float(f64::INFINITY)
+        );
+        assert!(
+            matches!(decode(format(Format::SFloat, 0), &[0xFF, 0x07]), Value::Float(nan) if nan.is_nan())
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            decode(format(Format::Utf8, 0), b"abc"),
+            Value::Text("abc".to_string())
+        );
+        assert_eq!(
+            decode(format(Format::Utf16, 0), &[b'h', 0, b'i', 0]),
+            Value::Text("hi".to_string())
+        );
+    }
+}
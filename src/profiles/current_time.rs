@@ -0,0 +1,248 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `current_time` module reads and sets the clock of a peripheral through the Current Time
+//! Service (CTS), which wearables and data loggers commonly expose so a phone or computer can keep
+//! their clock in sync.
+//!
+//! ```no_run
+//! # use btleplug::platform::Peripheral;
+//! use btleplug::profiles::current_time::{self, AdjustReason, CurrentTime};
+//! use std::time::{Duration, SystemTime};
+//!
+//! # async fn example(peripheral: Peripheral) -> btleplug::Result<()> {
+//! println!("{:?}", current_time::read_current_time(&peripheral).await?);
+//! // Peripherals usually show local time, so add the UTC offset (here UTC+2) first.
+//! let mut now = CurrentTime::from_system_time(SystemTime::now() + Duration::from_secs(2 * 3600));
+//! now.adjust_reason = AdjustReason::MANUAL_TIME_UPDATE;
+//! current_time::write_current_time(&peripheral, &now).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::api::{bleuuid::uuid_from_u16, CharPropFlags, Peripheral, WriteType};
+use crate::{Error, Result};
+use bitflags::bitflags;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// The Current Time service.
+pub const CURRENT_TIME_SERVICE: Uuid = uuid_from_u16(0x1805);
+
+/// The Current Time characteristic of the Current Time service.
+pub const CURRENT_TIME_CHARACTERISTIC: Uuid = uuid_from_u16(0x2A2B);
+
+bitflags! {
+    /// Why the clock of a peripheral was last adjusted.
+    #[derive(Default, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+    pub struct AdjustReason: u8 {
+        const MANUAL_TIME_UPDATE = 0x01;
+        const EXTERNAL_REFERENCE_TIME_UPDATE = 0x02;
+        const CHANGE_OF_TIME_ZONE = 0x04;
+        const CHANGE_OF_DST = 0x08;
+    }
+}
+
+/// A day of the week, as encoded by the Day of Week characteristic.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Weekday {
+    Monday = 1,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    fn from_u8(day: u8) -> Option<Self> {
+        Some(match day {
+            1 => Weekday::Monday,
+            2 => Weekday::Tuesday,
+            3 => Weekday::Wednesday,
+            4 => Weekday::Thursday,
+            5 => Weekday::Friday,
+            6 => Weekday::Saturday,
+            7 => Weekday::Sunday,
+            _ => return None,
+        })
+    }
+}
+
+/// The value of the Current Time characteristic. Fields which are 0 or `None` mean the peripheral
+/// doesn't know them.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CurrentTime {
+    /// 1582 to 9999.
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub day_of_week: Option<Weekday>,
+    /// Fractions of a second, in units of 1/256 s.
+    pub fractions256: u8,
+    pub adjust_reason: AdjustReason,
+}
+
+impl CurrentTime {
+    /// Converts a system time to a calendar date and time in UTC, with no adjust reason set.
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+            Err(e) => {
+                let before = e.duration();
+                match before.subsec_nanos() {
+                    0 => (-(before.as_secs() as i64), 0),
+                    nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+                }
+            }
+        };
+        let days = seconds.div_euclid(86400);
+        let time_of_day = seconds.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        CurrentTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hours: (time_of_day / 3600) as u8,
+            minutes: (time_of_day / 60 % 60) as u8,
+            seconds: (time_of_day % 60) as u8,
+            // The Unix epoch was a Thursday.
+            day_of_week: Weekday::from_u8((days + 3).rem_euclid(7) as u8 + 1),
+            fractions256: (u64::from(nanos) * 256 / 1_000_000_000) as u8,
+            adjust_reason: AdjustReason::empty(),
+        }
+    }
+
+    /// Parses the value of the Current Time characteristic.
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        match value {
+            [y0, y1, month, day, hours, minutes, seconds, day_of_week, fractions256, adjust_reason] => {
+                Ok(CurrentTime {
+                    year: u16::from_le_bytes([*y0, *y1]),
+                    month: *month,
+                    day: *day,
+                    hours: *hours,
+                    minutes: *minutes,
+                    seconds: *seconds,
+                    day_of_week: Weekday::from_u8(*day_of_week),
+                    fractions256: *fractions256,
+                    adjust_reason: AdjustReason::from_bits_truncate(*adjust_reason),
+                })
+            }
+            _ => Err(Error::Other(
+                format!("Invalid Current Time value {:02x?}", value).into(),
+            )),
+        }
+    }
+
+    /// Encodes the value of the Current Time characteristic.
+    pub fn to_bytes(&self) -> [u8; 10] {
+        let [y0, y1] = self.year.to_le_bytes();
+        [
+            y0,
+            y1,
+            self.month,
+            self.day,
+            self.hours,
+            self.minutes,
+            self.seconds,
+            self.day_of_week.map_or(0, |day| day as u8),
+            self.fractions256,
+            self.adjust_reason.bits(),
+        ]
+    }
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date in the proleptic Gregorian
+/// calendar, using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March, so that the leap day is at the end of the year.
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Reads the Current Time characteristic of the Current Time service.
+pub async fn read_current_time<P: Peripheral>(peripheral: &P) -> Result<CurrentTime> {
+    let characteristic =
+        peripheral.characteristic(CURRENT_TIME_SERVICE, CURRENT_TIME_CHARACTERISTIC)?;
+    CurrentTime::parse(&peripheral.read(&characteristic).await?)
+}
+
+/// Sets the clock of the peripheral through the Current Time characteristic. Writing it is
+/// optional, so this fails if the peripheral doesn't allow it.
+pub async fn write_current_time<P: Peripheral>(peripheral: &P, time: &CurrentTime) -> Result<()> {
+    let characteristic =
+        peripheral.characteristic(CURRENT_TIME_SERVICE, CURRENT_TIME_CHARACTERISTIC)?;
+    if !characteristic.properties.contains(CharPropFlags::WRITE) {
+        return Err(Error::Other(
+            "The Current Time characteristic of this peripheral is not writable".into(),
+        ));
+    }
+    peripheral
+        .write(&characteristic, &time.to_bytes(), WriteType::WithResponse)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_and_encode() {
+        let bytes = [0xE8, 0x07, 2, 29, 12, 34, 56, 4, 0x80, 0x03];
+        let time = CurrentTime::parse(&bytes).unwrap();
+        assert_eq!(
+            time,
+            CurrentTime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hours: 12,
+                minutes: 34,
+                seconds: 56,
+                day_of_week: Some(Weekday::Thursday),
+                fractions256: 0x80,
+                adjust_reason: AdjustReason::MANUAL_TIME_UPDATE
+                    | AdjustReason::EXTERNAL_REFERENCE_TIME_UPDATE,
+            }
+        );
+        assert_eq!(time.to_bytes(), bytes);
+        assert_eq!(CurrentTime::parse(&[0; 10]).unwrap().day_of_week, None);
+        assert!(CurrentTime::parse(&bytes[..9]).is_err());
+    }
+
+    #[test]
+    fn from_system_time() {
+        let time =
+            CurrentTime::from_system_time(UNIX_EPOCH + Duration::from_millis(1_709_210_096_500));
+        assert_eq!(
+            time.to_bytes(),
+            [0xE8, 0x07, 2, 29, 12, 34, 56, 4, 0x80, 0x00]
+        );
+        let time = CurrentTime::from_system_time(UNIX_EPOCH - Duration::from_millis(500));
+        assert_eq!(
+            time.to_bytes(),
+            [0xB1, 0x07, 12, 31, 23, 59, 59, 3, 0x80, 0x00]
+        );
+    }
+}
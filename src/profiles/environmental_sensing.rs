@@ -0,0 +1,377 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `environmental_sensing` module reads the Environmental Sensing Service (ESS), which weather
+//! stations and indoor climate sensors use to expose temperature, humidity, pressure and similar
+//! measurements.
+//!
+//! ```no_run
+//! # use btleplug::platform::Peripheral;
+//! # use futures::stream::StreamExt;
+//! use btleplug::profiles::environmental_sensing;
+//!
+//! # async fn example(peripheral: Peripheral) -> btleplug::Result<()> {
+//! for characteristic in environmental_sensing::characteristics(&peripheral) {
+//!     let reading = environmental_sensing::read(&peripheral, &characteristic).await?;
+//!     println!("{:?}: {:?}", reading.measurement, reading.value);
+//! }
+//! let mut readings = environmental_sensing::readings(&peripheral).await?;
+//! while let Some(reading) = readings.next().await {
+//!     println!("{:?}: {:?}", reading.measurement, reading.value);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A sensor may expose several instances of the same characteristic, e.g. an indoor and an outdoor
+//! temperature. Their ES Measurement descriptors tell them apart, see [`read_es_measurement`].
+
+use crate::api::{bleuuid::uuid_from_u16, CharPropFlags, Characteristic, Peripheral};
+use crate::{Error, Result};
+use futures::stream::{Stream, StreamExt};
+use std::pin::Pin;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The Environmental Sensing service.
+pub const ENVIRONMENTAL_SENSING_SERVICE: Uuid = uuid_from_u16(0x181A);
+
+/// The ES Measurement descriptor, which describes how a measurement was taken.
+pub const ES_MEASUREMENT_DESCRIPTOR: Uuid = uuid_from_u16(0x290C);
+
+/// A quantity measured by an Environmental Sensing characteristic. The doc comment of each variant
+/// gives the unit of [`Reading::value`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Measurement {
+    /// Degrees Celsius.
+    Temperature,
+    /// Relative humidity in percent.
+    Humidity,
+    /// Pascal.
+    Pressure,
+    /// Degrees Celsius.
+    DewPoint,
+    /// Degrees Celsius.
+    HeatIndex,
+    /// Degrees Celsius.
+    WindChill,
+    /// Metres.
+    Elevation,
+    /// UV index, without unit.
+    UvIndex,
+    /// Watts per square metre.
+    Irradiance,
+    /// Metres per second.
+    TrueWindSpeed,
+    /// Degrees, clockwise from true north.
+    TrueWindDirection,
+    /// Millimetres.
+    Rainfall,
+    /// Grains per cubic metre.
+    PollenConcentration,
+}
+
+impl Measurement {
+    const ALL: [Measurement; 13] = [
+        Measurement::Temperature,
+        Measurement::Humidity,
+        Measurement::Pressure,
+        Measurement::DewPoint,
+        Measurement::HeatIndex,
+        Measurement::WindChill,
+        Measurement::Elevation,
+        Measurement::UvIndex,
+        Measurement::Irradiance,
+        Measurement::TrueWindSpeed,
+        Measurement::TrueWindDirection,
+        Measurement::Rainfall,
+        Measurement::PollenConcentration,
+    ];
+
+    /// The UUID of the characteristic carrying this measurement.
+    pub fn uuid(self) -> Uuid {
+        uuid_from_u16(match self {
+            Measurement::Temperature => 0x2A6E,
+            Measurement::Humidity => 0x2A6F,
+            Measurement::Pressure => 0x2A6D,
+            Measurement::DewPoint => 0x2A7B,
+            Measurement::HeatIndex => 0x2A7A,
+            Measurement::WindChill => 0x2A79,
+            Measurement::Elevation => 0x2A6C,
+            Measurement::UvIndex => 0x2A76,
+            Measurement::Irradiance => 0x2A77,
+            Measurement::TrueWindSpeed => 0x2A70,
+            Measurement::TrueWindDirection => 0x2A71,
+            Measurement::Rainfall => 0x2A78,
+            Measurement::PollenConcentration => 0x2A75,
+        })
+    }
+
+    /// The measurement carried by the characteristic with the given UUID, if it is one this module
+    /// knows.
+    pub fn from_uuid(uuid: Uuid) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|measurement| measurement.uuid() == uuid)
+    }
+
+    /// Decodes a characteristic value. Returns `None` if the sensor reported that the value is not
+    /// known.
+    pub fn decode(self, value: &[u8]) -> Result<Option<f64>> {
+        // Length in bytes, signedness, divisor to get to the unit, and the "value is not known"
+        // marker if the characteristic defines one.
+        let (len, signed, divisor, unknown) = match self {
+            Measurement::Temperature => (2, true, 100.0, Some(0x8000)),
+            Measurement::Humidity => (2, false, 100.0, Some(0xFFFF)),
+            Measurement::Pressure => (4, false, 10.0, None),
+            Measurement::DewPoint | Measurement::HeatIndex | Measurement::WindChill => {
+                (1, true, 1.0, None)
+            }
+            Measurement::Elevation => (3, true, 100.0, None),
+            Measurement::UvIndex => (1, false, 1.0, None),
+            Measurement::Irradiance => (2, false, 10.0, None),
+            Measurement::TrueWindSpeed | Measurement::TrueWindDirection => (2, false, 100.0, None),
+            Measurement::Rainfall => (2, false, 1.0, None),
+            Measurement::PollenConcentration => (3, false, 1.0, None),
+        };
+        if value.len() != len {
+            return Err(Error::Other(
+                format!("Invalid {:?} value {:02x?}", self, value).into(),
+            ));
+        }
+        let raw = value
+            .iter()
+            .rev()
+            .fold(0u64, |raw, byte| raw << 8 | u64::from(*byte));
+        if Some(raw) == unknown {
+            return Ok(None);
+        }
+        let raw = if signed {
+            // Sign extend from the top bit of the value.
+            let shift = 64 - 8 * len as u32;
+            ((raw << shift) as i64 >> shift) as f64
+        } else {
+            raw as f64
+        };
+        // Dividing rather than multiplying with the resolution keeps e.g. 2150 / 100 at exactly
+        // 21.5.
+        Ok(Some(raw / divisor))
+    }
+}
+
+/// A decoded Environmental Sensing value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    pub measurement: Measurement,
+    /// The value in the unit of [`measurement`](Self::measurement), or `None` if the sensor
+    /// reported that it is not known.
+    pub value: Option<f64>,
+}
+
+/// How a sensor derives the value it reports from its samples.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SamplingFunction {
+    Unspecified,
+    Instantaneous,
+    ArithmeticMean,
+    Rms,
+    Maximum,
+    Minimum,
+    Accumulated,
+    Count,
+    /// A sampling function this version of btleplug doesn't know.
+    Other(u8),
+}
+
+impl From<u8> for SamplingFunction {
+    fn from(function: u8) -> Self {
+        match function {
+            0x00 => SamplingFunction::Unspecified,
+            0x01 => SamplingFunction::Instantaneous,
+            0x02 => SamplingFunction::ArithmeticMean,
+            0x03 => SamplingFunction::Rms,
+            0x04 => SamplingFunction::Maximum,
+            0x05 => SamplingFunction::Minimum,
+            0x06 => SamplingFunction::Accumulated,
+            0x07 => SamplingFunction::Count,
+            other => SamplingFunction::Other(other),
+        }
+    }
+}
+
+/// The ES Measurement descriptor of a characteristic, which describes how its value was taken.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EsMeasurement {
+    pub sampling_function: SamplingFunction,
+    /// The period over which the samples were taken, if the sensor reports one.
+    pub measurement_period: Option<Duration>,
+    /// How often the sensor updates the value, if it reports it.
+    pub update_interval: Option<Duration>,
+    /// Where or what was measured, from the Application values in the Bluetooth Assigned Numbers,
+    /// e.g. 0x01 for air, 0x13 for outdoor or 0x14 for indoor.
+    pub application: u8,
+    /// The uncertainty of the value in percent, if the sensor reports it.
+    pub uncertainty: Option<f64>,
+}
+
+impl EsMeasurement {
+    /// Parses the value of an ES Measurement descriptor.
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        match value {
+            [_flags, _, function, p0, p1, p2, i0, i1, i2, application, uncertainty] => {
+                let seconds =
+                    |bytes: [u8; 3]| match u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]) {
+                        0 => None,
+                        seconds => Some(Duration::from_secs(seconds.into())),
+                    };
+                Ok(EsMeasurement {
+                    sampling_function: (*function).into(),
+                    measurement_period: seconds([*p0, *p1, *p2]),
+                    update_interval: seconds([*i0, *i1, *i2]),
+                    application: *application,
+                    uncertainty: match uncertainty {
+                        0xFF => None,
+                        steps => Some(f64::from(*steps) / 2.0),
+                    },
+                })
+            }
+            _ => Err(Error::Other(
+                format!("Invalid ES Measurement descriptor {:02x?}", value).into(),
+            )),
+        }
+    }
+}
+
+/// Returns the discovered characteristics of the Environmental Sensing service which carry a
+/// measurement this module knows.
+pub fn characteristics<P: Peripheral>(peripheral: &P) -> Vec<Characteristic> {
+    peripheral
+        .services()
+        .into_iter()
+        .filter(|service| service.uuid == ENVIRONMENTAL_SENSING_SERVICE)
+        .flat_map(|service| service.characteristics)
+        .filter(|characteristic| Measurement::from_uuid(characteristic.uuid).is_some())
+        .collect()
+}
+
+/// Reads and decodes an Environmental Sensing characteristic.
+pub async fn read<P: Peripheral>(
+    peripheral: &P,
+    characteristic: &Characteristic,
+) -> Result<Reading> {
+    let measurement = Measurement::from_uuid(characteristic.uuid).ok_or_else(|| {
+        Error::Other(
+            format!(
+                "{} is not an Environmental Sensing measurement",
+                characteristic.uuid
+            )
+            .into(),
+        )
+    })?;
+    Ok(Reading {
+        measurement,
+        value: measurement.decode(&peripheral.read(characteristic).await?)?,
+    })
+}
+
+/// Reads the ES Measurement descriptor of an Environmental Sensing characteristic. Returns
+/// [`Error::NoSuchDescriptor`] if the characteristic doesn't have one.
+pub async fn read_es_measurement<P: Peripheral>(
+    peripheral: &P,
+    characteristic: &Characteristic,
+) -> Result<EsMeasurement> {
+    let descriptor = characteristic
+        .descriptors
+        .iter()
+        .find(|descriptor| descriptor.uuid == ES_MEASUREMENT_DESCRIPTOR)
+        .ok_or(Error::NoSuchDescriptor)?;
+    EsMeasurement::parse(&peripheral.read_descriptor(descriptor).await?)
+}
+
+/// Subscribes to every Environmental Sensing characteristic which supports notifications, and
+/// returns a stream of their decoded values. Values which fail to decode are logged and skipped.
+pub async fn readings<P: Peripheral>(
+    peripheral: &P,
+) -> Result<Pin<Box<dyn Stream<Item = Reading> + Send>>> {
+    let notifications = peripheral.notifications().await?;
+    for characteristic in characteristics(peripheral) {
+        if characteristic.properties.contains(CharPropFlags::NOTIFY) {
+            peripheral.subscribe(&characteristic).await?;
+        }
+    }
+    Ok(Box::pin(notifications.filter_map(
+        |notification| async move {
+            let measurement = Measurement::from_uuid(notification.uuid)?;
+            match measurement.decode(&notification.value) {
+                Ok(value) => Some(Reading { measurement, value }),
+                Err(e) => {
+                    log::debug!("Skipping notification: {}", e);
+                    None
+                }
+            }
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_measurements() {
+        let temperature = Measurement::Temperature;
+        assert_eq!(temperature.decode(&[0x66, 0x08]).unwrap(), Some(21.5));
+        assert_eq!(temperature.decode(&[0x06, 0xFF]).unwrap(), Some(-2.5));
+        assert_eq!(temperature.decode(&[0x00, 0x80]).unwrap(), None);
+        assert_eq!(Measurement::Humidity.decode(&[0xFF, 0xFF]).unwrap(), None);
+        assert_eq!(
+            Measurement::Pressure
+                .decode(&[0x04, 0x76, 0x0F, 0x00])
+                .unwrap(),
+            Some(101325.2)
+        );
+        assert_eq!(
+            Measurement::Elevation.decode(&[0x9C, 0xFF, 0xFF]).unwrap(),
+            Some(-1.0)
+        );
+        assert_eq!(Measurement::DewPoint.decode(&[0xFB]).unwrap(), Some(-5.0));
+        assert!(temperature.decode(&[0x66]).is_err());
+    }
+
+    #[test]
+    fn uuid_round_trip() {
+        for measurement in Measurement::ALL {
+            assert_eq!(
+                Measurement::from_uuid(measurement.uuid()),
+                Some(measurement)
+            );
+        }
+        assert_eq!(Measurement::from_uuid(uuid_from_u16(0x2A19)), None);
+    }
+
+    #[test]
+    fn parse_es_measurement() {
+        let measurement =
+            EsMeasurement::parse(&[0, 0, 0x02, 0x3C, 0, 0, 0x0A, 0, 0, 0x14, 0x03]).unwrap();
+        assert_eq!(
+            measurement,
+            EsMeasurement {
+                sampling_function: SamplingFunction::ArithmeticMean,
+                measurement_period: Some(Duration::from_secs(60)),
+                update_interval: Some(Duration::from_secs(10)),
+                application: 0x14,
+                uncertainty: Some(1.5),
+            }
+        );
+        let unknown = EsMeasurement::parse(&[0, 0, 0x09, 0, 0, 0, 0, 0, 0, 0, 0xFF]).unwrap();
+        assert_eq!(unknown.sampling_function, SamplingFunction::Other(0x09));
+        assert_eq!(unknown.measurement_period, None);
+        assert_eq!(unknown.update_interval, None);
+        assert_eq!(unknown.uncertainty, None);
+        assert!(EsMeasurement::parse(&[0; 10]).is_err());
+    }
+}
@@ -0,0 +1,759 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `ftms` module talks to smart trainers, treadmills and other fitness equipment through the
+//! Fitness Machine Service (FTMS).
+//!
+//! [`read_features`] tells what a machine measures and which targets it can be set to,
+//! [`machine_data`] streams its Indoor Bike or Treadmill Data, and [`ControlPoint`] runs the
+//! Fitness Machine Control Point procedures:
+//!
+//! ```no_run
+//! # use btleplug::platform::Peripheral;
+//! # use futures::stream::StreamExt;
+//! use btleplug::profiles::ftms::{self, ControlPoint, MachineData, Request};
+//!
+//! # async fn example(peripheral: Peripheral) -> btleplug::Result<()> {
+//! let control_point = ControlPoint::new(peripheral.clone()).await?;
+//! control_point.request(&Request::RequestControl).await?;
+//! control_point.request(&Request::SetTargetPower(200)).await?;
+//! control_point.request(&Request::StartOrResume).await?;
+//!
+//! let mut data = ftms::machine_data(&peripheral).await?;
+//! while let Some(MachineData::IndoorBike(bike)) = data.next().await {
+//!     println!("{:?} W at {:?} rpm", bike.instantaneous_power, bike.instantaneous_cadence);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::api::{
+    bleuuid::uuid_from_u16, Characteristic, Peripheral, ValueNotification, WriteType,
+};
+use crate::{Error, Result};
+use bitflags::bitflags;
+use futures::stream::{Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// The Fitness Machine service.
+pub const FITNESS_MACHINE_SERVICE: Uuid = uuid_from_u16(0x1826);
+
+/// The Fitness Machine Feature characteristic.
+pub const FITNESS_MACHINE_FEATURE: Uuid = uuid_from_u16(0x2ACC);
+
+/// The Treadmill Data characteristic.
+pub const TREADMILL_DATA: Uuid = uuid_from_u16(0x2ACD);
+
+/// The Indoor Bike Data characteristic.
+pub const INDOOR_BIKE_DATA: Uuid = uuid_from_u16(0x2AD2);
+
+/// The Fitness Machine Control Point characteristic.
+pub const FITNESS_MACHINE_CONTROL_POINT: Uuid = uuid_from_u16(0x2AD9);
+
+/// The Fitness Machine Status characteristic.
+pub const FITNESS_MACHINE_STATUS: Uuid = uuid_from_u16(0x2ADA);
+
+/// How long to wait for the response to a control point request, the ATT transaction timeout.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+bitflags! {
+    /// What a fitness machine measures.
+    #[derive(Default, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+    pub struct MachineFeatures: u32 {
+        const AVERAGE_SPEED = 1 << 0;
+        const CADENCE = 1 << 1;
+        const TOTAL_DISTANCE = 1 << 2;
+        const INCLINATION = 1 << 3;
+        const ELEVATION_GAIN = 1 << 4;
+        const PACE = 1 << 5;
+        const STEP_COUNT = 1 << 6;
+        const RESISTANCE_LEVEL = 1 << 7;
+        const STRIDE_COUNT = 1 << 8;
+        const EXPENDED_ENERGY = 1 << 9;
+        const HEART_RATE_MEASUREMENT = 1 << 10;
+        const METABOLIC_EQUIVALENT = 1 << 11;
+        const ELAPSED_TIME = 1 << 12;
+        const REMAINING_TIME = 1 << 13;
+        const POWER_MEASUREMENT = 1 << 14;
+        const FORCE_ON_BELT_AND_POWER_OUTPUT = 1 << 15;
+        const USER_DATA_RETENTION = 1 << 16;
+    }
+}
+
+bitflags! {
+    /// Which targets a fitness machine can be set to through its control point.
+    #[derive(Default, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+    pub struct TargetSettingFeatures: u32 {
+        const SPEED = 1 << 0;
+        const INCLINATION = 1 << 1;
+        const RESISTANCE = 1 << 2;
+        const POWER = 1 << 3;
+        const HEART_RATE = 1 << 4;
+        const EXPENDED_ENERGY = 1 << 5;
+        const STEP_NUMBER = 1 << 6;
+        const STRIDE_NUMBER = 1 << 7;
+        const DISTANCE = 1 << 8;
+        const TRAINING_TIME = 1 << 9;
+        const TIME_IN_TWO_HEART_RATE_ZONES = 1 << 10;
+        const TIME_IN_THREE_HEART_RATE_ZONES = 1 << 11;
+        const TIME_IN_FIVE_HEART_RATE_ZONES = 1 << 12;
+        const INDOOR_BIKE_SIMULATION = 1 << 13;
+        const WHEEL_CIRCUMFERENCE = 1 << 14;
+        const SPIN_DOWN_CONTROL = 1 << 15;
+        const TARGETED_CADENCE = 1 << 16;
+    }
+}
+
+/// The value of the Fitness Machine Feature characteristic.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Features {
+    pub machine: MachineFeatures,
+    pub target_setting: TargetSettingFeatures,
+}
+
+impl Features {
+    /// Parses the value of the Fitness Machine Feature characteristic.
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new("Fitness Machine Feature", value);
+        Ok(Features {
+            machine: MachineFeatures::from_bits_retain(reader.u32()?),
+            target_setting: TargetSettingFeatures::from_bits_retain(reader.u32()?),
+        })
+    }
+}
+
+/// Energy expended during a session. Machines which don't measure one of the fields set it to its
+/// maximum value.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ExpendedEnergy {
+    /// Kilocalories.
+    pub total: u16,
+    /// Kilocalories per hour.
+    pub per_hour: u16,
+    /// Kilocalories per minute.
+    pub per_minute: u8,
+}
+
+/// A notification of the Indoor Bike Data characteristic. Fields the machine didn't include are
+/// `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IndoorBikeData {
+    /// Kilometres per hour.
+    pub instantaneous_speed: Option<f64>,
+    /// Kilometres per hour.
+    pub average_speed: Option<f64>,
+    /// Revolutions per minute.
+    pub instantaneous_cadence: Option<f64>,
+    /// Revolutions per minute.
+    pub average_cadence: Option<f64>,
+    /// Metres.
+    pub total_distance: Option<u32>,
+    pub resistance_level: Option<i16>,
+    /// Watts.
+    pub instantaneous_power: Option<i16>,
+    /// Watts.
+    pub average_power: Option<i16>,
+    pub expended_energy: Option<ExpendedEnergy>,
+    /// Beats per minute.
+    pub heart_rate: Option<u8>,
+    pub metabolic_equivalent: Option<f64>,
+    pub elapsed_time: Option<Duration>,
+    pub remaining_time: Option<Duration>,
+}
+
+impl IndoorBikeData {
+    /// Parses a notification of the Indoor Bike Data characteristic.
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new("Indoor Bike Data", value);
+        let flags = reader.u16()?;
+        let has = |bit: u16| flags & (1 << bit) != 0;
+        // Bit 0 is "More Data": when a value is split across several notifications, only the last
+        // one clears it and carries the instantaneous speed.
+        Ok(IndoorBikeData {
+            instantaneous_speed: reader.field(!has(0), |r| Ok(f64::from(r.u16()?) / 100.0))?,
+            average_speed: reader.field(has(1), |r| Ok(f64::from(r.u16()?) / 100.0))?,
+            instantaneous_cadence: reader.field(has(2), |r| Ok(f64::from(r.u16()?) / 2.0))?,
+            average_cadence: reader.field(has(3), |r| Ok(f64::from(r.u16()?) / 2.0))?,
+            total_distance: reader.field(has(4), Reader::u24)?,
+            resistance_level: reader.field(has(5), Reader::i16)?,
+            instantaneous_power: reader.field(has(6), Reader::i16)?,
+            average_power: reader.field(has(7), Reader::i16)?,
+            expended_energy: reader.field(has(8), Reader::expended_energy)?,
+            heart_rate: reader.field(has(9), Reader::u8)?,
+            metabolic_equivalent: reader.field(has(10), |r| Ok(f64::from(r.u8()?) / 10.0))?,
+            elapsed_time: reader.field(has(11), Reader::seconds)?,
+            remaining_time: reader.field(has(12), Reader::seconds)?,
+        })
+    }
+}
+
+/// A notification of the Treadmill Data characteristic. Fields the machine didn't include are
+/// `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TreadmillData {
+    /// Kilometres per hour.
+    pub instantaneous_speed: Option<f64>,
+    /// Kilometres per hour.
+    pub average_speed: Option<f64>,
+    /// Metres.
+    pub total_distance: Option<u32>,
+    /// Percent.
+    pub inclination: Option<f64>,
+    /// Degrees.
+    pub ramp_angle: Option<f64>,
+    /// Metres.
+    pub positive_elevation_gain: Option<f64>,
+    /// Metres.
+    pub negative_elevation_gain: Option<f64>,
+    /// Kilometres per minute.
+    pub instantaneous_pace: Option<f64>,
+    /// Kilometres per minute.
+    pub average_pace: Option<f64>,
+    pub expended_energy: Option<ExpendedEnergy>,
+    /// Beats per minute.
+    pub heart_rate: Option<u8>,
+    pub metabolic_equivalent: Option<f64>,
+    pub elapsed_time: Option<Duration>,
+    pub remaining_time: Option<Duration>,
+    /// Newtons.
+    pub force_on_belt: Option<i16>,
+    /// Watts.
+    pub power_output: Option<i16>,
+}
+
+impl TreadmillData {
+    /// Parses a notification of the Treadmill Data characteristic.
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new("Treadmill Data", value);
+        let flags = reader.u16()?;
+        let has = |bit: u16| flags & (1 << bit) != 0;
+        let instantaneous_speed = reader.field(!has(0), |r| Ok(f64::from(r.u16()?) / 100.0))?;
+        let average_speed = reader.field(has(1), |r| Ok(f64::from(r.u16()?) / 100.0))?;
+        let total_distance = reader.field(has(2), Reader::u24)?;
+        let (inclination, ramp_angle) = reader
+            .field(has(3), |r| {
+                Ok((f64::from(r.i16()?) / 10.0, f64::from(r.i16()?) / 10.0))
+            })?
+            .unzip();
+        let (positive_elevation_gain, negative_elevation_gain) = reader
+            .field(has(4), |r| {
+                Ok((f64::from(r.u16()?) / 10.0, f64::from(r.u16()?) / 10.0))
+            })?
+            .unzip();
+        let instantaneous_pace = reader.field(has(5), |r| Ok(f64::from(r.u8()?) / 10.0))?;
+        let average_pace = reader.field(has(6), |r| Ok(f64::from(r.u8()?) / 10.0))?;
+        let expended_energy = reader.field(has(7), Reader::expended_energy)?;
+        let heart_rate = reader.field(has(8), Reader::u8)?;
+        let metabolic_equivalent = reader.field(has(9), |r| Ok(f64::from(r.u8()?) / 10.0))?;
+        let elapsed_time = reader.field(has(10), Reader::seconds)?;
+        let remaining_time = reader.field(has(11), Reader::seconds)?;
+        let (force_on_belt, power_output) =
+            reader.field(has(12), |r| Ok((r.i16()?, r.i16()?)))?.unzip();
+        Ok(TreadmillData {
+            instantaneous_speed,
+            average_speed,
+            total_distance,
+            inclination,
+            ramp_angle,
+            positive_elevation_gain,
+            negative_elevation_gain,
+            instantaneous_pace,
+            average_pace,
+            expended_energy,
+            heart_rate,
+            metabolic_equivalent,
+            elapsed_time,
+            remaining_time,
+            force_on_belt,
+            power_output,
+        })
+    }
+}
+
+/// A notification of one of the machine data characteristics, see [`machine_data`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MachineData {
+    IndoorBike(IndoorBikeData),
+    Treadmill(TreadmillData),
+}
+
+/// Reads the Fitness Machine Feature characteristic.
+pub async fn read_features<P: Peripheral>(peripheral: &P) -> Result<Features> {
+    let characteristic =
+        peripheral.characteristic(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_FEATURE)?;
+    Features::parse(&peripheral.read(&characteristic).await?)
+}
+
+/// Subscribes to the Indoor Bike Data and Treadmill Data characteristics, whichever the machine
+/// has, and returns a stream of their parsed notifications. Notifications which fail to parse are
+/// logged and skipped. Returns [`Error::NoSuchCharacteristic`] if the machine has neither.
+pub async fn machine_data<P: Peripheral>(
+    peripheral: &P,
+) -> Result<Pin<Box<dyn Stream<Item = MachineData> + Send>>> {
+    let notifications = peripheral.notifications().await?;
+    let mut subscribed = false;
+    for uuid in [INDOOR_BIKE_DATA, TREADMILL_DATA] {
+        match peripheral.characteristic(FITNESS_MACHINE_SERVICE, uuid) {
+            Ok(characteristic) => {
+                peripheral.subscribe(&characteristic).await?;
+                subscribed = true;
+            }
+            Err(Error::NoSuchCharacteristic) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if !subscribed {
+        return Err(Error::NoSuchCharacteristic);
+    }
+    Ok(Box::pin(notifications.filter_map(
+        |notification| async move {
+            let data = match notification.uuid {
+                INDOOR_BIKE_DATA => {
+                    IndoorBikeData::parse(&notification.value).map(MachineData::IndoorBike)
+                }
+                TREADMILL_DATA => {
+                    TreadmillData::parse(&notification.value).map(MachineData::Treadmill)
+                }
+                _ => return None,
+            };
+            data.map_err(|e| log::debug!("Skipping notification: {}", e))
+                .ok()
+        },
+    )))
+}
+
+/// A request to the Fitness Machine Control Point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    /// Asks for control of the machine, which every other request needs.
+    RequestControl,
+    /// Resets the machine, which also gives up control of it.
+    Reset,
+    /// Kilometres per hour.
+    SetTargetSpeed(f64),
+    /// Percent.
+    SetTargetInclination(f64),
+    /// Unitless, with a resolution of 0.1.
+    SetTargetResistance(f64),
+    /// Watts.
+    SetTargetPower(i16),
+    /// Beats per minute.
+    SetTargetHeartRate(u8),
+    StartOrResume,
+    Stop,
+    Pause,
+    /// Lets the machine simulate riding outdoors.
+    SetIndoorBikeSimulation {
+        /// Metres per second, positive for a head wind.
+        wind_speed: f64,
+        /// Percent.
+        grade: f64,
+        /// Coefficient of rolling resistance.
+        rolling_resistance: f64,
+        /// Wind resistance coefficient in kilograms per metre.
+        wind_resistance: f64,
+    },
+    /// Millimetres.
+    SetWheelCircumference(f64),
+}
+
+impl Request {
+    /// The op code of the request.
+    pub fn op_code(&self) -> u8 {
+        match self {
+            Request::RequestControl => 0x00,
+            Request::Reset => 0x01,
+            Request::SetTargetSpeed(_) => 0x02,
+            Request::SetTargetInclination(_) => 0x03,
+            Request::SetTargetResistance(_) => 0x04,
+            Request::SetTargetPower(_) => 0x05,
+            Request::SetTargetHeartRate(_) => 0x06,
+            Request::StartOrResume => 0x07,
+            Request::Stop | Request::Pause => 0x08,
+            Request::SetIndoorBikeSimulation { .. } => 0x11,
+            Request::SetWheelCircumference(_) => 0x12,
+        }
+    }
+
+    /// Encodes the request as written to the control point.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.op_code()];
+        match *self {
+            Request::RequestControl | Request::Reset | Request::StartOrResume => {}
+            Request::SetTargetSpeed(speed) => {
+                bytes.extend(((speed * 100.0).round() as u16).to_le_bytes())
+            }
+            Request::SetTargetInclination(inclination) => {
+                bytes.extend(((inclination * 10.0).round() as i16).to_le_bytes())
+            }
+            Request::SetTargetResistance(resistance) => {
+                bytes.push((resistance * 10.0).round() as u8)
+            }
+            Request::SetTargetPower(power) => bytes.extend(power.to_le_bytes()),
+            Request::SetTargetHeartRate(heart_rate) => bytes.push(heart_rate),
+            Request::Stop => bytes.push(0x01),
+            Request::Pause => bytes.push(0x02),
+            Request::SetIndoorBikeSimulation {
+                wind_speed,
+                grade,
+                rolling_resistance,
+                wind_resistance,
+            } => {
+                bytes.extend(((wind_speed * 1000.0).round() as i16).to_le_bytes());
+                bytes.extend(((grade * 100.0).round() as i16).to_le_bytes());
+                bytes.push((rolling_resistance * 10000.0).round() as u8);
+                bytes.push((wind_resistance * 100.0).round() as u8);
+            }
+            Request::SetWheelCircumference(circumference) => {
+                bytes.extend(((circumference * 10.0).round() as u16).to_le_bytes())
+            }
+        }
+        bytes
+    }
+}
+
+/// The result of a control point request.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ResultCode {
+    Success,
+    OpCodeNotSupported,
+    InvalidParameter,
+    OperationFailed,
+    ControlNotPermitted,
+    /// A result code this version of btleplug doesn't know.
+    Other(u8),
+}
+
+impl From<u8> for ResultCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => ResultCode::Success,
+            0x02 => ResultCode::OpCodeNotSupported,
+            0x03 => ResultCode::InvalidParameter,
+            0x04 => ResultCode::OperationFailed,
+            0x05 => ResultCode::ControlNotPermitted,
+            other => ResultCode::Other(other),
+        }
+    }
+}
+
+/// A response indicated by the control point.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Response {
+    /// The op code of the request this responds to.
+    pub request_op_code: u8,
+    pub result: ResultCode,
+    /// Parameters of the response, which only a few requests have.
+    pub parameters: Vec<u8>,
+}
+
+impl Response {
+    /// The op code of responses.
+    const OP_CODE: u8 = 0x80;
+
+    /// Parses an indication of the control point.
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        match value {
+            [Self::OP_CODE, request_op_code, result, parameters @ ..] => Ok(Response {
+                request_op_code: *request_op_code,
+                result: (*result).into(),
+                parameters: parameters.to_vec(),
+            }),
+            _ => Err(Error::Other(
+                format!(
+                    "Invalid Fitness Machine Control Point response {:02x?}",
+                    value
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+/// Runs Fitness Machine Control Point procedures, one at a time.
+///
+/// Each request is written to the control point, which indicates the response once the machine
+/// has handled it. The machine only accepts requests other than [`Request::RequestControl`] from
+/// the client in control, so `ControlPoint` keeps track of whether it is: it gains control when a
+/// `RequestControl` succeeds, and loses it on a successful [`Request::Reset`], when the machine
+/// answers [`ResultCode::ControlNotPermitted`], or when the Fitness Machine Status characteristic
+/// reports a reset or that control was taken away.
+pub struct ControlPoint<P> {
+    peripheral: P,
+    characteristic: Characteristic,
+    /// Indications of the control point and notifications of the status characteristic. Holding
+    /// the lock is what keeps procedures from overlapping.
+    responses: Mutex<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>>,
+    in_control: AtomicBool,
+}
+
+impl<P: Peripheral> ControlPoint<P> {
+    /// Subscribes to the control point of a connected fitness machine, and to its status if it has
+    /// that characteristic.
+    pub async fn new(peripheral: P) -> Result<Self> {
+        let characteristic =
+            peripheral.characteristic(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_CONTROL_POINT)?;
+        let responses = peripheral.notifications().await?.filter(|notification| {
+            let uuid = notification.uuid;
+            async move { uuid == FITNESS_MACHINE_CONTROL_POINT || uuid == FITNESS_MACHINE_STATUS }
+        });
+        peripheral.subscribe(&characteristic).await?;
+        match peripheral.characteristic(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_STATUS) {
+            Ok(status) => peripheral.subscribe(&status).await?,
+            Err(Error::NoSuchCharacteristic) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(ControlPoint {
+            peripheral,
+            characteristic,
+            responses: Mutex::new(Box::pin(responses)),
+            in_control: AtomicBool::new(false),
+        })
+    }
+
+    /// Whether this client is in control of the machine, as far as it knows.
+    pub fn in_control(&self) -> bool {
+        self.in_control.load(Ordering::SeqCst)
+    }
+
+    /// Sends a request and waits for its response, returning the parameters of the response.
+    /// Fails without sending the request if it needs control and this client doesn't have it, and
+    /// if the machine answers with any result other than [`ResultCode::Success`].
+    pub async fn request(&self, request: &Request) -> Result<Vec<u8>> {
+        let mut responses = self.responses.lock().await;
+        if *request != Request::RequestControl && !self.in_control() {
+            return Err(Error::Other(
+                format!("{:?} needs control of the fitness machine", request).into(),
+            ));
+        }
+        self.peripheral
+            .write(
+                &self.characteristic,
+                &request.to_bytes(),
+                WriteType::WithResponse,
+            )
+            .await?;
+        let response = tokio::time::timeout(RESPONSE_TIMEOUT, async {
+            while let Some(notification) = responses.next().await {
+                if notification.uuid == FITNESS_MACHINE_STATUS {
+                    self.handle_status(&notification.value);
+                    continue;
+                }
+                match Response::parse(&notification.value) {
+                    Ok(response) if response.request_op_code == request.op_code() => {
+                        return Ok(response)
+                    }
+                    // The late response to an earlier request which timed out.
+                    Ok(response) => log::debug!("Ignoring unexpected response {:?}", response),
+                    Err(e) => log::debug!("{}", e),
+                }
+            }
+            Err(Error::NotConnected)
+        })
+        .await
+        .map_err(|_| Error::TimedOut(RESPONSE_TIMEOUT))??;
+        match response.result {
+            ResultCode::Success => {
+                match request {
+                    Request::RequestControl => self.in_control.store(true, Ordering::SeqCst),
+                    Request::Reset => self.in_control.store(false, Ordering::SeqCst),
+                    _ => {}
+                }
+                Ok(response.parameters)
+            }
+            result => {
+                if result == ResultCode::ControlNotPermitted {
+                    self.in_control.store(false, Ordering::SeqCst);
+                }
+                Err(Error::Other(
+                    format!("Fitness machine answered {:?} to {:?}", result, request).into(),
+                ))
+            }
+        }
+    }
+
+    fn handle_status(&self, value: &[u8]) {
+        // 0x01 is "Reset", 0xFF "Control Permission Lost".
+        if let Some(0x01 | 0xFF) = value.first() {
+            self.in_control.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Reads the little endian fields of a characteristic value one after the other.
+struct Reader<'a> {
+    name: &'static str,
+    value: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(name: &'static str, value: &'a [u8]) -> Self {
+        Reader {
+            name,
+            value,
+            offset: 0,
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self
+            .value
+            .get(self.offset..self.offset + N)
+            .ok_or_else(|| {
+                Error::Other(format!("{} value {:02x?} is too short", self.name, self.value).into())
+            })?;
+        self.offset += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    /// Reads a field if its flag is set.
+    fn field<T>(
+        &mut self,
+        present: bool,
+        read: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<Option<T>> {
+        present.then(|| read(self)).transpose()
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        self.bytes().map(u16::from_le_bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        self.bytes().map(i16::from_le_bytes)
+    }
+
+    fn u24(&mut self) -> Result<u32> {
+        let [b0, b1, b2] = self.bytes()?;
+        Ok(u32::from_le_bytes([b0, b1, b2, 0]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn seconds(&mut self) -> Result<Duration> {
+        Ok(Duration::from_secs(self.u16()?.into()))
+    }
+
+    fn expended_energy(&mut self) -> Result<ExpendedEnergy> {
+        Ok(ExpendedEnergy {
+            total: self.u16()?,
+            per_hour: self.u16()?,
+            per_minute: self.u8()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_features() {
+        let features = Features::parse(&[0x02, 0x44, 0x00, 0x00, 0x0C, 0x20, 0x00, 0x00]).unwrap();
+        assert_eq!(
+            features.machine,
+            MachineFeatures::CADENCE
+                | MachineFeatures::HEART_RATE_MEASUREMENT
+                | MachineFeatures::POWER_MEASUREMENT
+        );
+        assert_eq!(
+            features.target_setting,
+            TargetSettingFeatures::RESISTANCE
+                | TargetSettingFeatures::POWER
+                | TargetSettingFeatures::INDOOR_BIKE_SIMULATION
+        );
+        assert!(Features::parse(&[0; 7]).is_err());
+    }
+
+    #[test]
+    fn parse_indoor_bike_data() {
+        // Instantaneous speed, cadence, power and heart rate.
+        let data =
+            IndoorBikeData::parse(&[0x44, 0x02, 0x9A, 0x0B, 0xB4, 0x00, 0xC8, 0x00, 0x8C]).unwrap();
+        assert_eq!(
+            data,
+            IndoorBikeData {
+                instantaneous_speed: Some(29.7),
+                instantaneous_cadence: Some(90.0),
+                instantaneous_power: Some(200),
+                heart_rate: Some(140),
+                ..Default::default()
+            }
+        );
+        // More Data set, so no instantaneous speed.
+        let data = IndoorBikeData::parse(&[0x01, 0x08, 0x3C, 0x00]).unwrap();
+        assert_eq!(data.instantaneous_speed, None);
+        assert_eq!(data.elapsed_time, Some(Duration::from_secs(60)));
+        assert!(IndoorBikeData::parse(&[0x44, 0x02, 0x9A, 0x0B]).is_err());
+    }
+
+    #[test]
+    fn parse_treadmill_data() {
+        // Instantaneous speed, total distance, inclination and ramp angle, and elapsed time.
+        let data = TreadmillData::parse(&[
+            0x0C, 0x04, 0xE8, 0x03, 0x10, 0x27, 0x00, 0x0F, 0x00, 0xF6, 0xFF, 0x2C, 0x01,
+        ])
+        .unwrap();
+        assert_eq!(
+            data,
+            TreadmillData {
+                instantaneous_speed: Some(10.0),
+                total_distance: Some(10000),
+                inclination: Some(1.5),
+                ramp_angle: Some(-1.0),
+                elapsed_time: Some(Duration::from_secs(300)),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn encode_requests() {
+        assert_eq!(Request::RequestControl.to_bytes(), [0x00]);
+        assert_eq!(Request::SetTargetPower(250).to_bytes(), [0x05, 0xFA, 0x00]);
+        assert_eq!(Request::SetTargetSpeed(12.5).to_bytes(), [0x02, 0xE2, 0x04]);
+        assert_eq!(Request::Pause.to_bytes(), [0x08, 0x02]);
+        assert_eq!(
+            Request::SetIndoorBikeSimulation {
+                wind_speed: 0.0,
+                grade: -2.5,
+                rolling_resistance: 0.004,
+                wind_resistance: 0.51,
+            }
+            .to_bytes(),
+            [0x11, 0x00, 0x00, 0x06, 0xFF, 0x28, 0x33]
+        );
+    }
+
+    #[test]
+    fn parse_response() {
+        assert_eq!(
+            Response::parse(&[0x80, 0x05, 0x01]).unwrap(),
+            Response {
+                request_op_code: 0x05,
+                result: ResultCode::Success,
+                parameters: Vec::new(),
+            }
+        );
+        assert_eq!(
+            Response::parse(&[0x80, 0x00, 0x05]).unwrap().result,
+            ResultCode::ControlNotPermitted
+        );
+        assert!(Response::parse(&[0x05, 0x00, 0x01]).is_err());
+    }
+}
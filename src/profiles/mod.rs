@@ -0,0 +1,13 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `profiles` module contains helpers for standard GATT services, which decode their
+//! characteristics into typed values so applications don't have to.
+
+pub mod current_time;
+pub mod environmental_sensing;
+pub mod ftms;
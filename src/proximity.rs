@@ -0,0 +1,433 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `proximity` module turns the RSSI of advertisements into zone events: it tells when a
+//! peripheral came close enough to the adapter, and when it went away again.
+//!
+//! The RSSI of single advertisements jumps around by 10 dB or more, so it is smoothed first, either
+//! with an exponentially weighted moving average or a one-dimensional Kalman filter (see
+//! [`Smoothing`]). The smoothed value is then compared against two thresholds: a peripheral enters
+//! the zone when it reaches [`enter_rssi`](ProximityConfig::enter_rssi), but only exits once it
+//! drops below the lower [`exit_rssi`](ProximityConfig::exit_rssi). The gap between the two keeps
+//! a peripheral at the edge of the zone from flapping in and out.
+//!
+//! ```no_run
+//! # use btleplug::api::{Central, ScanFilter};
+//! # use btleplug::platform::Adapter;
+//! # use futures::stream::StreamExt;
+//! use btleplug::proximity::{self, ProximityConfig, ProximityEvent};
+//!
+//! # async fn example(central: Adapter) -> btleplug::Result<()> {
+//! let mut events = proximity::proximity_events(&central, ProximityConfig::default()).await?;
+//! central.start_scan(ScanFilter::default()).await?;
+//! while let Some(event) = events.next().await {
+//!     match event {
+//!         ProximityEvent::Enter { id, rssi } => println!("{} is near ({:.0} dBm)", id, rssi),
+//!         ProximityEvent::Exit { id, rssi } => println!("{} went away ({:.0} dBm)", id, rssi),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! For beacon ranging, [`DistanceModel`] estimates the distance to a peripheral from its (smoothed)
+//! RSSI and advertised TX power. Peripherals which don't advertise their TX power may still report
+//! it through the Tx Power service once connected, see [`read_tx_power_level`].
+
+use crate::api::{bleuuid::uuid_from_u16, Central, Peripheral, PeripheralProperties};
+use crate::platform::PeripheralId;
+use crate::{Error, Result};
+use futures::stream::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// The Tx Power service.
+pub const TX_POWER_SERVICE: Uuid = uuid_from_u16(0x1804);
+
+/// The Tx Power Level characteristic of the Tx Power service.
+pub const TX_POWER_LEVEL_CHARACTERISTIC: Uuid = uuid_from_u16(0x2A07);
+
+/// How the RSSI of a peripheral is smoothed before comparing it against the zone thresholds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Exponentially weighted moving average. Each new reading is weighted with `alpha`, between 0
+    /// and 1: lower values smooth more, but react more slowly.
+    Ewma { alpha: f64 },
+    /// One-dimensional Kalman filter. `process_noise` is how much the real signal is expected to
+    /// change between readings, `measurement_noise` how noisy the readings are. A higher ratio of
+    /// measurement to process noise smooths more.
+    Kalman {
+        process_noise: f64,
+        measurement_noise: f64,
+    },
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing::Ewma { alpha: 0.3 }
+    }
+}
+
+/// Configuration of a [`ProximityMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProximityConfig {
+    pub smoothing: Smoothing,
+    /// Smoothed RSSI in dBm at or above which a peripheral enters the zone.
+    pub enter_rssi: f64,
+    /// Smoothed RSSI in dBm below which a peripheral exits the zone. Should be lower than
+    /// `enter_rssi`, the gap is the hysteresis.
+    pub exit_rssi: f64,
+}
+
+impl Default for ProximityConfig {
+    fn default() -> Self {
+        Self {
+            smoothing: Smoothing::default(),
+            enter_rssi: -60.0,
+            exit_rssi: -70.0,
+        }
+    }
+}
+
+/// A peripheral entered or exited the zone. `rssi` is the smoothed RSSI which triggered the event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProximityEvent {
+    Enter { id: PeripheralId, rssi: f64 },
+    Exit { id: PeripheralId, rssi: f64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Filter {
+    Ewma {
+        estimate: f64,
+    },
+    Kalman {
+        estimate: f64,
+        error_covariance: f64,
+    },
+}
+
+impl Filter {
+    fn new(smoothing: Smoothing, rssi: f64) -> Self {
+        match smoothing {
+            Smoothing::Ewma { .. } => Filter::Ewma { estimate: rssi },
+            Smoothing::Kalman {
+                measurement_noise, ..
+            } => Filter::Kalman {
+                estimate: rssi,
+                error_covariance: measurement_noise,
+            },
+        }
+    }
+
+    fn update(&mut self, smoothing: Smoothing, rssi: f64) -> f64 {
+        match (self, smoothing) {
+            (Filter::Ewma { estimate }, Smoothing::Ewma { alpha }) => {
+                *estimate += alpha * (rssi - *estimate);
+                *estimate
+            }
+            (
+                Filter::Kalman {
+                    estimate,
+                    error_covariance,
+                },
+                Smoothing::Kalman {
+                    process_noise,
+                    measurement_noise,
+                },
+            ) => {
+                let predicted_covariance = *error_covariance + process_noise;
+                let gain = predicted_covariance / (predicted_covariance + measurement_noise);
+                *estimate += gain * (rssi - *estimate);
+                *error_covariance = (1.0 - gain) * predicted_covariance;
+                *estimate
+            }
+            // The filter is always created from the same smoothing it's updated with.
+            (filter, smoothing) => {
+                *filter = Filter::new(smoothing, rssi);
+                rssi
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PeripheralState {
+    filter: Filter,
+    inside: bool,
+}
+
+/// Tracks the smoothed RSSI of peripherals and whether they are in the zone. Feed it RSSI readings
+/// with [`update`](ProximityMonitor::update), or use [`proximity_events`] to have it fed from the
+/// events of a [`Central`].
+#[derive(Debug)]
+pub struct ProximityMonitor<K = PeripheralId> {
+    config: ProximityConfig,
+    peripherals: HashMap<K, PeripheralState>,
+}
+
+impl<K: Clone + Eq + Hash> ProximityMonitor<K> {
+    pub fn new(config: ProximityConfig) -> Self {
+        Self {
+            config,
+            peripherals: HashMap::new(),
+        }
+    }
+
+    /// Adds an RSSI reading for a peripheral. Returns the smoothed RSSI, and whether the
+    /// peripheral entered (`Some(true)`) or exited (`Some(false)`) the zone with it.
+    fn update_state(&mut self, id: K, rssi: i16) -> (f64, Option<bool>) {
+        let smoothing = self.config.smoothing;
+        let rssi = f64::from(rssi);
+        let state = self
+            .peripherals
+            .entry(id)
+            .and_modify(|state| {
+                state.filter.update(smoothing, rssi);
+            })
+            .or_insert_with(|| PeripheralState {
+                filter: Filter::new(smoothing, rssi),
+                inside: false,
+            });
+        let smoothed = match state.filter {
+            Filter::Ewma { estimate } | Filter::Kalman { estimate, .. } => estimate,
+        };
+        let change = if !state.inside && smoothed >= self.config.enter_rssi {
+            Some(true)
+        } else if state.inside && smoothed < self.config.exit_rssi {
+            Some(false)
+        } else {
+            None
+        };
+        if let Some(inside) = change {
+            state.inside = inside;
+        }
+        (smoothed, change)
+    }
+
+    /// Returns the smoothed RSSI of a peripheral, if any readings were added for it.
+    pub fn smoothed_rssi(&self, id: &K) -> Option<f64> {
+        self.peripherals.get(id).map(|state| match state.filter {
+            Filter::Ewma { estimate } | Filter::Kalman { estimate, .. } => estimate,
+        })
+    }
+
+    /// Returns whether a peripheral is currently in the zone.
+    pub fn is_inside(&self, id: &K) -> bool {
+        self.peripherals.get(id).is_some_and(|state| state.inside)
+    }
+
+    /// Forgets a peripheral, e.g. because it stopped advertising. Its next reading starts from
+    /// scratch, outside the zone.
+    pub fn remove(&mut self, id: &K) {
+        self.peripherals.remove(id);
+    }
+}
+
+impl ProximityMonitor<PeripheralId> {
+    /// Adds an RSSI reading for a peripheral, returning an event if it entered or exited the zone
+    /// with it.
+    pub fn update(&mut self, id: &PeripheralId, rssi: i16) -> Option<ProximityEvent> {
+        let (rssi, change) = self.update_state(id.clone(), rssi);
+        change.map(|inside| match inside {
+            true => ProximityEvent::Enter {
+                id: id.clone(),
+                rssi,
+            },
+            false => ProximityEvent::Exit {
+                id: id.clone(),
+                rssi,
+            },
+        })
+    }
+}
+
+/// Returns a stream of zone events for the peripherals seen by `central`, taking the RSSI from
+/// their properties whenever an advertisement is received. Scanning has to be started separately.
+pub async fn proximity_events<C>(
+    central: &C,
+    config: ProximityConfig,
+) -> Result<Pin<Box<dyn Stream<Item = ProximityEvent> + Send>>>
+where
+    C: Central + 'static,
+{
+    let events = central.events().await?;
+    let central = central.clone();
+    let mut monitor = ProximityMonitor::new(config);
+    Ok(Box::pin(
+        events
+            .filter_map(move |event| {
+                let central = central.clone();
+                async move {
+                    let id = event.advertisement_peripheral_id()?.clone();
+                    let properties = central.peripheral(&id).await.ok()?.properties().await;
+                    let rssi = properties.ok()??.rssi?;
+                    Some((id, rssi))
+                }
+            })
+            .filter_map(move |(id, rssi)| futures::future::ready(monitor.update(&id, rssi))),
+    ))
+}
+
+/// Reads the TX power level in dBm from the Tx Power service of a connected peripheral, whose
+/// services have been discovered. Fails with [`Error::NoSuchService`] if the peripheral doesn't
+/// have the service.
+///
+/// This is the power the peripheral transmits with on the connection, which need not be the
+/// power it advertises with.
+pub async fn read_tx_power_level<P: Peripheral>(peripheral: &P) -> Result<i8> {
+    let characteristic =
+        peripheral.characteristic(TX_POWER_SERVICE, TX_POWER_LEVEL_CHARACTERISTIC)?;
+    let value = peripheral.read(&characteristic).await?;
+    decode_tx_power_level(&value)
+}
+
+fn decode_tx_power_level(value: &[u8]) -> Result<i8> {
+    match value {
+        [level] => Ok(*level as i8),
+        _ => Err(Error::Other(
+            format!("Invalid Tx Power Level value {:02x?}", value).into(),
+        )),
+    }
+}
+
+/// Estimates the distance to a peripheral with the log-distance path loss model:
+///
+/// ```text
+/// distance = 10 ^ ((tx_power - loss_at_one_meter - rssi) / (10 * path_loss_exponent))
+/// ```
+///
+/// `tx_power - loss_at_one_meter` is the RSSI expected at 1 m. The result is in meters, and only
+/// as good as the calibration: walls, bodies and the orientation of the antennas all change the
+/// RSSI by more than the model accounts for. Calibrate by measuring the RSSI at 1 m and at a larger
+/// known distance, then solving for the two constants.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DistanceModel {
+    /// How fast the signal falls off with distance: 2 in free space, typically 2.5 to 4 indoors.
+    pub path_loss_exponent: f64,
+    /// The path loss in dB over the first meter. 41 dB is the common value for 2.4 GHz.
+    pub loss_at_one_meter: f64,
+    /// TX power in dBm to assume for peripherals which don't advertise it.
+    pub default_tx_power: Option<i16>,
+}
+
+impl Default for DistanceModel {
+    fn default() -> Self {
+        Self {
+            path_loss_exponent: 2.0,
+            loss_at_one_meter: 41.0,
+            default_tx_power: None,
+        }
+    }
+}
+
+impl DistanceModel {
+    /// Returns the estimated distance in meters for an RSSI and TX power, both in dBm.
+    pub fn estimate(&self, rssi: f64, tx_power: i16) -> f64 {
+        let rssi_at_one_meter = f64::from(tx_power) - self.loss_at_one_meter;
+        10f64.powf((rssi_at_one_meter - rssi) / (10.0 * self.path_loss_exponent))
+    }
+
+    /// Returns the estimated distance in meters from the RSSI and TX power in the properties of a
+    /// peripheral, or `None` if either is unknown.
+    pub fn estimate_from_properties(&self, properties: &PeripheralProperties) -> Option<f64> {
+        let tx_power = properties.tx_power_level.or(self.default_tx_power)?;
+        Some(self.estimate(f64::from(properties.rssi?), tx_power))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(smoothing: Smoothing) -> ProximityMonitor<u8> {
+        ProximityMonitor::new(ProximityConfig {
+            smoothing,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn ewma_smooths_readings() {
+        let mut monitor = monitor(Smoothing::Ewma { alpha: 0.5 });
+        assert_eq!(monitor.update_state(1, -80).0, -80.0);
+        assert_eq!(monitor.update_state(1, -60).0, -70.0);
+        assert_eq!(monitor.update_state(1, -60).0, -65.0);
+        assert_eq!(monitor.smoothed_rssi(&1), Some(-65.0));
+        assert_eq!(monitor.smoothed_rssi(&2), None);
+    }
+
+    #[test]
+    fn kalman_converges() {
+        let mut monitor = monitor(Smoothing::Kalman {
+            process_noise: 0.01,
+            measurement_noise: 4.0,
+        });
+        monitor.update_state(1, -80);
+        let mut smoothed = 0.0;
+        for _ in 0..100 {
+            smoothed = monitor.update_state(1, -50).0;
+        }
+        assert!((smoothed + 50.0).abs() < 1.0, "{}", smoothed);
+        // A single outlier barely moves the estimate.
+        let after_outlier = monitor.update_state(1, -90).0;
+        assert!(after_outlier > -55.0, "{}", after_outlier);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut monitor = monitor(Smoothing::Ewma { alpha: 1.0 });
+        assert_eq!(monitor.update_state(1, -75).1, None);
+        assert_eq!(monitor.update_state(1, -60).1, Some(true));
+        assert!(monitor.is_inside(&1));
+        // Between the thresholds nothing changes.
+        assert_eq!(monitor.update_state(1, -65).1, None);
+        assert_eq!(monitor.update_state(1, -70).1, None);
+        assert_eq!(monitor.update_state(1, -71).1, Some(false));
+        assert!(!monitor.is_inside(&1));
+        assert_eq!(monitor.update_state(1, -65).1, None);
+    }
+
+    #[test]
+    fn tx_power_level() {
+        assert_eq!(decode_tx_power_level(&[0x04]).unwrap(), 4);
+        assert_eq!(decode_tx_power_level(&[0xEC]).unwrap(), -20);
+        assert!(decode_tx_power_level(&[]).is_err());
+        assert!(decode_tx_power_level(&[0x04, 0x00]).is_err());
+    }
+
+    #[test]
+    fn distance_estimate() {
+        let model = DistanceModel::default();
+        assert!((model.estimate(-41.0, 0) - 1.0).abs() < 1e-9);
+        assert!((model.estimate(-61.0, 0) - 10.0).abs() < 1e-9);
+        let indoor = DistanceModel {
+            path_loss_exponent: 4.0,
+            ..Default::default()
+        };
+        assert!((indoor.estimate(-81.0, 0) - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn distance_from_properties() {
+        let model = DistanceModel::default();
+        let mut properties = PeripheralProperties {
+            rssi: Some(-61),
+            ..Default::default()
+        };
+        assert_eq!(model.estimate_from_properties(&properties), None);
+        let with_default = DistanceModel {
+            default_tx_power: Some(0),
+            ..model
+        };
+        assert!((with_default.estimate_from_properties(&properties).unwrap() - 10.0).abs() < 1e-9);
+        properties.tx_power_level = Some(-20);
+        assert!((model.estimate_from_properties(&properties).unwrap() - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,291 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `scan_session` module lets independent components share the scan of one central. With
+//! [`start_scan`](crate::api::Central::start_scan) and [`stop_scan`](crate::api::Central::stop_scan)
+//! there is only one scan, so the first component to stop it stops it for everyone. Each
+//! [`ScanSession`] instead keeps the scan running until it's stopped or dropped, and the central
+//! only stops scanning once the last session ended.
+//!
+//! ```no_run
+//! # use btleplug::api::{Central, ScanFilter};
+//! # use btleplug::platform::Adapter;
+//! # use futures::stream::StreamExt;
+//! # use uuid::Uuid;
+//! # async fn example(central: Adapter, heart_rate: Uuid) -> btleplug::Result<()> {
+//! let everything = central.start_scan_session(ScanFilter::default()).await?;
+//! let heart_rate_monitors = central
+//!     .start_scan_session(ScanFilter {
+//!         services: vec![heart_rate],
+//!         ..Default::default()
+//!     })
+//!     .await?;
+//! // Only advertisements from heart rate monitors, even though all devices are scanned for.
+//! let mut events = heart_rate_monitors.events().await?;
+//! # let _ = events.next().await;
+//! // Still scanning for all devices.
+//! everything.stop().await?;
+//! // Now only scanning for heart rate monitors, until the session is dropped.
+//! drop(heart_rate_monitors);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! While sessions with different filters are running the central scans with the union of their
+//! filters, so the events of the central include devices which don't match the filter of each
+//! session. [`ScanSession::events`] only passes on the advertisements matching the filter of the
+//! session. Calling `stop_scan` directly still stops the scan, whatever sessions are running.
+
+use crate::api::{Central, CentralEvent, Peripheral, ScanFilter};
+use crate::{Error, Result};
+use futures::future::BoxFuture;
+use futures::stream::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+
+/// The running scan sessions of a central. Each backend adapter keeps one, shared by its clones.
+#[derive(Debug, Default)]
+pub(crate) struct ScanSessions {
+    // Held while the scan is started or stopped, so that changes are applied in order.
+    state: Mutex<SessionState>,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    next_id: u64,
+    filters: HashMap<u64, ScanFilter>,
+}
+
+impl SessionState {
+    /// The filter to scan with for the running sessions, or `None` if there are none.
+    fn merged_filter(&self) -> Option<ScanFilter> {
+        merge_filters(self.filters.values())
+    }
+}
+
+/// Merges filters into one which passes everything any of them passes.
+fn merge_filters<'a>(filters: impl IntoIterator<Item = &'a ScanFilter>) -> Option<ScanFilter> {
+    let filters: Vec<_> = filters.into_iter().collect();
+    if filters.is_empty() {
+        return None;
+    }
+    Some(ScanFilter {
+        services: merge_lists(&filters, |filter| &filter.services),
+        appearances: merge_lists(&filters, |filter| &filter.appearances),
+        manufacturer_data: merge_lists(&filters, |filter| &filter.manufacturer_data),
+        name_prefixes: merge_lists(&filters, |filter| &filter.name_prefixes),
+        addresses: merge_lists(&filters, |filter| &filter.addresses),
+    })
+}
+
+/// Merges one of the lists of each filter, without duplicates.
+fn merge_lists<T: Clone + Ord>(
+    filters: &[&ScanFilter],
+    list: impl Fn(&ScanFilter) -> &Vec<T>,
+) -> Vec<T> {
+    // An empty list doesn't filter at all, so it wins over everything else.
+    if filters.iter().any(|filter| list(filter).is_empty()) {
+        return Vec::new();
+    }
+    let mut merged: Vec<T> = filters
+        .iter()
+        .flat_map(|filter| list(filter).iter().cloned())
+        .collect();
+    merged.sort();
+    merged.dedup();
+    merged
+}
+
+impl ScanSessions {
+    /// Starts a session scanning with `filter`, restarting the scan of `central` if the filter
+    /// changes what it scans for.
+    pub async fn start<C: Central + 'static>(
+        self: &Arc<Self>,
+        central: C,
+        filter: ScanFilter,
+    ) -> Result<ScanSession> {
+        let id = {
+            let mut state = self.state.lock().await;
+            let previous = state.merged_filter();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.filters.insert(id, filter.clone());
+            let merged = state.merged_filter();
+            if merged != previous {
+                if let Err(e) = central.start_scan(merged.unwrap_or_default()).await {
+                    state.filters.remove(&id);
+                    return Err(e);
+                }
+            }
+            id
+        };
+        let events_central = central.clone();
+        let events: EventsFn = Box::new(move || {
+            let central = events_central.clone();
+            let filter = filter.clone();
+            Box::pin(async move {
+                let events = central.events().await?;
+                Ok(session_events(central, filter, events))
+            })
+        });
+        let (stop, stopped) = oneshot::channel::<()>();
+        let sessions = self.clone();
+        let task = tokio::spawn(async move {
+            // Resolves when the session is stopped or dropped, either way the sender goes away.
+            let _ = stopped.await;
+            sessions.end(&central, id).await
+        });
+        Ok(ScanSession {
+            stop: Some(stop),
+            task,
+            events,
+        })
+    }
+
+    async fn end<C: Central>(&self, central: &C, id: u64) -> Result<()> {
+        let mut state = self.state.lock().await;
+        let previous = state.merged_filter();
+        state.filters.remove(&id);
+        match state.merged_filter() {
+            None => central.stop_scan().await,
+            Some(merged) if Some(&merged) != previous.as_ref() => central.start_scan(merged).await,
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = CentralEvent> + Send>>;
+type EventsFn = Box<dyn Fn() -> BoxFuture<'static, Result<EventStream>> + Send + Sync>;
+
+/// Filters the events of `central` down to those relevant to a session scanning with `filter`:
+/// advertisements are only passed on if the peripheral matches the filter, other events always.
+fn session_events<C: Central + 'static>(
+    central: C,
+    filter: ScanFilter,
+    events: EventStream,
+) -> EventStream {
+    Box::pin(events.filter(move |event| {
+        let id = event.advertisement_peripheral_id().cloned();
+        let central = central.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(id) = id else {
+                return true;
+            };
+            if filter == ScanFilter::default() {
+                return true;
+            }
+            match central.peripheral(&id).await {
+                Ok(peripheral) => matches!(
+                    peripheral.properties().await,
+                    Ok(Some(properties)) if filter.matches(&properties)
+                ),
+                Err(_) => false,
+            }
+        }
+    }))
+}
+
+/// A scan shared with the other sessions of a central, started with
+/// [`Central::start_scan_session`](crate::api::Central::start_scan_session). The session ends when
+/// this is dropped, or with [`stop`](ScanSession::stop).
+pub struct ScanSession {
+    stop: Option<oneshot::Sender<()>>,
+    task: JoinHandle<Result<()>>,
+    events: EventsFn,
+}
+
+impl Debug for ScanSession {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ScanSession")
+            .field("stop", &self.stop)
+            .field("task", &self.task)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ScanSession {
+    /// Returns a stream of the events of the central, like [`Central::events`], but with only the
+    /// advertisements of the devices matching the filter of this session, as checked against
+    /// their [`properties`](crate::api::Peripheral::properties). Other events, like connections
+    /// or state changes, are all passed on. The stream keeps going after the session ended.
+    pub async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        (self.events)().await
+    }
+
+    /// Ends the session, waiting for the scan to be stopped or narrowed down to the filters of the
+    /// sessions still running.
+    pub async fn stop(mut self) -> Result<()> {
+        self.stop.take();
+        (&mut self.task)
+            .await
+            .map_err(|e| Error::RuntimeError(e.to_string()))?
+    }
+}
+
+impl Drop for ScanSession {
+    fn drop(&mut self) {
+        // Dropping the sender wakes up the task, which then ends the session by itself.
+        self.stop.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn filter(services: &[u128], appearances: &[u16]) -> ScanFilter {
+        ScanFilter {
+            services: services.iter().map(|&uuid| Uuid::from_u128(uuid)).collect(),
+            appearances: appearances.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_nothing() {
+        assert_eq!(merge_filters(&[]), None);
+    }
+
+    #[test]
+    fn merge_unions_filters() {
+        assert_eq!(
+            merge_filters(&[filter(&[2, 1], &[0x0340]), filter(&[1, 3], &[0x0341])]),
+            Some(filter(&[1, 2, 3], &[0x0340, 0x0341]))
+        );
+    }
+
+    #[test]
+    fn merge_with_unfiltered() {
+        // A session scanning for all devices makes the scan unfiltered, for each list separately.
+        assert_eq!(
+            merge_filters(&[filter(&[1], &[0x0340]), filter(&[], &[0x0341])]),
+            Some(filter(&[], &[0x0340, 0x0341]))
+        );
+    }
+
+    #[test]
+    fn merge_extended_filters() {
+        let names = |prefixes: &[&str]| ScanFilter {
+            name_prefixes: prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+            ..Default::default()
+        };
+        let merged = merge_filters(&[names(&["b", "a"]), names(&["a"])]).unwrap();
+        assert_eq!(merged.name_prefixes, ["a", "b"]);
+        assert!(merged.manufacturer_data.is_empty());
+        assert!(merged.addresses.is_empty());
+        assert_eq!(
+            merge_filters(&[names(&["a"]), names(&[])]),
+            Some(names(&[]))
+        );
+    }
+}
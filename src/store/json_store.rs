@@ -0,0 +1,79 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{store_error, DeviceStore, KnownDevice};
+use crate::platform::PeripheralId;
+use crate::Result;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// A [`DeviceStore`] which keeps all devices in a single JSON file.
+///
+/// The file is read on every [`load`](DeviceStore::load) and rewritten on every change, which is
+/// fine for the handful of devices an application usually remembers. A missing file is treated as
+/// an empty store.
+#[derive(Debug)]
+pub struct JsonDeviceStore {
+    path: PathBuf,
+    // Serializes read-modify-write cycles between threads of this process.
+    lock: Mutex<()>,
+}
+
+impl JsonDeviceStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonDeviceStore {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> Result<Vec<KnownDevice>> {
+        match fs::read(&self.path) {
+            Ok(contents) => serde_json::from_slice(&contents).map_err(store_error),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(store_error(e)),
+        }
+    }
+
+    fn write(&self, devices: &[KnownDevice]) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(devices).map_err(store_error)?;
+        // Write to a temporary file first so a crash can't leave a truncated store behind.
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, contents).map_err(store_error)?;
+        fs::rename(&temp_path, &self.path).map_err(store_error)
+    }
+}
+
+impl DeviceStore for JsonDeviceStore {
+    fn load(&self) -> Result<Vec<KnownDevice>> {
+        let _guard = self.lock.lock()?;
+        self.read()
+    }
+
+    fn save(&self, device: &KnownDevice) -> Result<()> {
+        let _guard = self.lock.lock()?;
+        let mut devices = self.read()?;
+        match devices.iter_mut().find(|known| known.id == device.id) {
+            Some(known) => *known = device.clone(),
+            None => devices.push(device.clone()),
+        }
+        self.write(&devices)
+    }
+
+    fn remove(&self, id: &PeripheralId) -> Result<()> {
+        let _guard = self.lock.lock()?;
+        let mut devices = self.read()?;
+        let count = devices.len();
+        devices.retain(|known| &known.id != id);
+        if devices.len() == count {
+            return Ok(());
+        }
+        self.write(&devices)
+    }
+}
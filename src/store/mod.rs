@@ -0,0 +1,281 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The `store` module allows known devices to be remembered across process restarts.
+//!
+//! A [`DeviceStore`] persists a [`KnownDevice`] record for each peripheral the application cares
+//! about: its identity, its name and the GATT services that were discovered on it. Two
+//! implementations are provided behind cargo features:
+//!
+//! - `json-store`: [`JsonDeviceStore`], which keeps all devices in a single JSON file.
+//! - `sled-store`: [`SledDeviceStore`], which keeps devices in a [sled](https://docs.rs/sled)
+//!   database.
+//!
+//! ```no_run
+//! # #[cfg(feature = "json-store")]
+//! # async fn example() -> btleplug::Result<()> {
+//! use btleplug::api::{Manager as _, Peripheral as _};
+//! use btleplug::platform::Manager;
+//! use btleplug::store::{self, JsonDeviceStore};
+//!
+//! let store = JsonDeviceStore::new("known_devices.json");
+//! let manager = Manager::new().await?;
+//! let central = manager.adapters().await?.remove(0);
+//!
+//! // Bring back the peripherals that were saved by a previous run.
+//! for device in store::restore(&store, &central).await? {
+//!     println!(
+//!         "Restored {}, last seen with {} services",
+//!         device.peripheral.id(),
+//!         device.snapshot.services.len()
+//!     );
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "json-store")]
+mod json_store;
+#[cfg(feature = "sled-store")]
+mod sled_store;
+
+#[cfg(feature = "json-store")]
+pub use self::json_store::JsonDeviceStore;
+#[cfg(feature = "sled-store")]
+pub use self::sled_store::SledDeviceStore;
+
+use crate::api::{BDAddr, Central, Peripheral, Service};
+use crate::platform::PeripheralId;
+use crate::{Error, Result};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::BTreeSet;
+
+/// Everything a [`DeviceStore`] remembers about a peripheral.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KnownDevice {
+    /// The platform specific identifier of the peripheral.
+    pub id: PeripheralId,
+    /// The MAC address of the peripheral, which may be unavailable on some platforms.
+    pub address: BDAddr,
+    /// The last local name the peripheral was seen with.
+    pub local_name: Option<String>,
+    /// The GATT services discovered on the peripheral. This is empty if services were never
+    /// discovered.
+    pub services: BTreeSet<Service>,
+}
+
+impl KnownDevice {
+    /// Builds a record from the current state of a peripheral.
+    pub async fn from_peripheral<P: Peripheral>(peripheral: &P) -> Result<Self> {
+        let local_name = peripheral
+            .properties()
+            .await?
+            .and_then(|properties| properties.local_name.clone());
+        Ok(KnownDevice {
+            id: peripheral.id(),
+            address: peripheral.address(),
+            local_name,
+            services: peripheral.services(),
+        })
+    }
+}
+
+/// Persistent storage of [`KnownDevice`] records, keyed by [`PeripheralId`].
+pub trait DeviceStore: Send + Sync {
+    /// Returns every device in the store.
+    fn load(&self) -> Result<Vec<KnownDevice>>;
+
+    /// Returns the device with the given id, if it is in the store.
+    fn get(&self, id: &PeripheralId) -> Result<Option<KnownDevice>> {
+        Ok(self.load()?.into_iter().find(|device| &device.id == id))
+    }
+
+    /// Inserts the device, replacing any previous record with the same id.
+    fn save(&self, device: &KnownDevice) -> Result<()>;
+
+    /// Removes the device with the given id. Removing an unknown device is not an error.
+    fn remove(&self, id: &PeripheralId) -> Result<()>;
+}
+
+/// A peripheral brought back by [`restore`], along with what the store remembered about it.
+#[derive(Clone, Debug)]
+pub struct RestoredDevice<P> {
+    pub peripheral: P,
+    /// A snapshot of the stored record, which is not applied to the peripheral. Its services are
+    /// the GATT database the peripheral had when it was remembered, which lets the application
+    /// check for the services it needs before connecting. The peripheral itself only reports
+    /// services once [`discover_services`](Peripheral::discover_services) ran on it.
+    pub snapshot: KnownDevice,
+}
+
+/// Saves the current state of `peripheral` into `store`. Call this after services have been
+/// discovered to also remember the GATT database.
+pub async fn remember<S, P>(store: &S, peripheral: &P) -> Result<()>
+where
+    S: DeviceStore + ?Sized,
+    P: Peripheral,
+{
+    store.save(&KnownDevice::from_peripheral(peripheral).await?)
+}
+
+/// Re-creates the peripherals in `store` on `central` using
+/// [`Central::add_peripheral`], and returns each with a snapshot of its stored record. Only the
+/// peripherals are restored: no platform lets btleplug seed their GATT database, so services have
+/// to be discovered again after connecting. Devices which the platform can not restore from their
+/// id alone are skipped; they will show up again once they are discovered by a scan.
+pub async fn restore<S, C>(store: &S, central: &C) -> Result<Vec<RestoredDevice<C::Peripheral>>>
+where
+    S: DeviceStore + ?Sized,
+    C: Central,
+{
+    let mut restored = Vec::new();
+    for device in store.load()? {
+        let peripheral = match central.peripheral(&device.id).await {
+            Ok(peripheral) => peripheral,
+            Err(_) => match central.add_peripheral(&device.id).await {
+                Ok(peripheral) => peripheral,
+                Err(Error::NotSupported { .. }) => continue,
+                Err(e) => return Err(e),
+            },
+        };
+        restored.push(RestoredDevice {
+            peripheral,
+            snapshot: device,
+        });
+    }
+    Ok(restored)
+}
+
+#[cfg(any(feature = "json-store", feature = "sled-store"))]
+fn store_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> Error {
+    Error::Other(Box::new(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{CharPropFlags, Characteristic};
+    use crate::common::fake::{peripheral_id, FakeCentral, FakePeripheral};
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    const ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    fn services() -> BTreeSet<Service> {
+        let service_uuid = Uuid::from_u128(0x180f);
+        let characteristic = Characteristic {
+            uuid: Uuid::from_u128(0x2a19),
+            service_uuid,
+            properties: CharPropFlags::READ | CharPropFlags::NOTIFY,
+            descriptors: BTreeSet::new(),
+            handle: Some(0x0012),
+        };
+        BTreeSet::from([Service {
+            uuid: service_uuid,
+            handle: Some(0x0010),
+            primary: true,
+            characteristics: BTreeSet::from([characteristic]),
+        }])
+    }
+
+    fn known_device() -> KnownDevice {
+        KnownDevice {
+            id: peripheral_id(ADDRESS),
+            address: ADDRESS,
+            local_name: Some("Sensor".to_string()),
+            services: services(),
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryStore(Mutex<Vec<KnownDevice>>);
+
+    impl DeviceStore for MemoryStore {
+        fn load(&self) -> Result<Vec<KnownDevice>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+        fn save(&self, device: &KnownDevice) -> Result<()> {
+            let mut devices = self.0.lock().unwrap();
+            devices.retain(|known| known.id != device.id);
+            devices.push(device.clone());
+            Ok(())
+        }
+        fn remove(&self, id: &PeripheralId) -> Result<()> {
+            self.0.lock().unwrap().retain(|known| &known.id != id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn remembered_devices_are_restored_with_their_snapshot() {
+        let store = MemoryStore::default();
+        let peripheral = FakePeripheral::new(ADDRESS)
+            .with_name("Sensor")
+            .with_services(services());
+        remember(&store, &peripheral).await.unwrap();
+
+        let central = FakeCentral {
+            can_add: true,
+            ..Default::default()
+        };
+        let restored = restore(&store, &central).await.unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].peripheral.id(), peripheral_id(ADDRESS));
+        assert_eq!(restored[0].snapshot, known_device());
+        // The snapshot isn't applied to the peripheral.
+        assert!(restored[0].peripheral.services().is_empty());
+
+        let restored = restore(&store, &FakeCentral::default()).await.unwrap();
+        assert!(restored.is_empty());
+    }
+
+    #[tokio::test]
+    async fn known_peripherals_are_not_added_again() {
+        let store = MemoryStore::default();
+        store.save(&known_device()).unwrap();
+        let peripheral = FakePeripheral::new(ADDRESS).with_services(services());
+        let central = FakeCentral {
+            peripherals: vec![peripheral],
+            can_add: false,
+        };
+
+        let restored = restore(&store, &central).await.unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].peripheral.services(), services());
+    }
+
+    #[cfg(feature = "json-store")]
+    #[test]
+    fn json_store_round_trip() {
+        let path = std::env::temp_dir().join(format!("btleplug-store-{}.json", std::process::id()));
+        let store = JsonDeviceStore::new(&path);
+        assert_eq!(store.load().unwrap(), []);
+
+        let device = known_device();
+        store.save(&device).unwrap();
+        let renamed = KnownDevice {
+            local_name: Some("Renamed".to_string()),
+            ..device.clone()
+        };
+        store.save(&renamed).unwrap();
+        let reopened = JsonDeviceStore::new(&path);
+        assert_eq!(reopened.get(&device.id).unwrap(), Some(renamed.clone()));
+        assert_eq!(reopened.load().unwrap(), [renamed]);
+
+        reopened.remove(&device.id).unwrap();
+        assert_eq!(JsonDeviceStore::new(&path).load().unwrap(), []);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{store_error, DeviceStore, KnownDevice};
+use crate::platform::PeripheralId;
+use crate::Result;
+use std::path::Path;
+
+/// A [`DeviceStore`] backed by a [sled](https://docs.rs/sled) database. Each device is stored as
+/// a JSON value under its JSON encoded [`PeripheralId`].
+#[derive(Clone, Debug)]
+pub struct SledDeviceStore {
+    db: sled::Db,
+}
+
+impl SledDeviceStore {
+    /// Opens (or creates) a database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::from_db(sled::open(path).map_err(store_error)?))
+    }
+
+    /// Uses the default tree of an already opened database.
+    pub fn from_db(db: sled::Db) -> Self {
+        SledDeviceStore { db }
+    }
+}
+
+impl DeviceStore for SledDeviceStore {
+    fn load(&self) -> Result<Vec<KnownDevice>> {
+        self.db
+            .iter()
+            .values()
+            .map(|value| serde_json::from_slice(&value.map_err(store_error)?).map_err(store_error))
+            .collect()
+    }
+
+    fn get(&self, id: &PeripheralId) -> Result<Option<KnownDevice>> {
+        let key = serde_json::to_vec(id).map_err(store_error)?;
+        match self.db.get(key).map_err(store_error)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value).map_err(store_error)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, device: &KnownDevice) -> Result<()> {
+        let key = serde_json::to_vec(&device.id).map_err(store_error)?;
+        let value = serde_json::to_vec(device).map_err(store_error)?;
+        self.db.insert(key, value).map_err(store_error)?;
+        self.db.flush().map_err(store_error)?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &PeripheralId) -> Result<()> {
+        let key = serde_json::to_vec(id).map_err(store_error)?;
+        self.db.remove(key).map_err(store_error)?;
+        self.db.flush().map_err(store_error)?;
+        Ok(())
+    }
+}
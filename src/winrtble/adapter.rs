@@ -11,21 +11,41 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::{ble::watcher::BLEWatcher, peripheral::Peripheral, peripheral::PeripheralId};
+use super::{
+    ble::device::{BLEDevice, DeviceProperties},
+    ble::publisher::BLEPublisher,
+    ble::watcher::BLEWatcher,
+    manager::ManagerOptions,
+    peripheral::Peripheral,
+    peripheral::PeripheralId,
+};
 use crate::{
-    api::{BDAddr, Central, CentralEvent, CentralState, ScanFilter},
+    api::{
+        AdvertisementData, BDAddr, Backend, BackendKind, Capabilities, Central, CentralEvent,
+        CentralState, Peripheral as _, ScanFilter, ScanSettings,
+    },
     common::adapter_manager::AdapterManager,
+    interceptor::GattInterceptor,
+    scan_session::ScanSession,
     Error, Result,
 };
 use async_trait::async_trait;
 use futures::stream::Stream;
+use log::warn;
 use std::convert::TryInto;
 use std::fmt::{self, Debug, Formatter};
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
 use windows::{
-    Devices::Radios::{Radio, RadioState},
+    Devices::{
+        Bluetooth::BluetoothError,
+        Radios::{Radio, RadioState},
+    },
     Foundation::TypedEventHandler,
+    System::Profile::AnalyticsInfo,
 };
 
 /// Implementation of [api::Central](crate::api::Central).
@@ -34,6 +54,9 @@ pub struct Adapter {
     watcher: Arc<Mutex<BLEWatcher>>,
     manager: Arc<AdapterManager<Peripheral>>,
     radio: Radio,
+    // Set while an aborted scan waits for the radio to be turned back on to be restarted.
+    restart_pending: Arc<AtomicBool>,
+    publisher: Arc<Mutex<Option<BLEPublisher>>>,
 }
 
 // https://github.com/microsoft/windows-rs/blob/master/crates/libs/windows/src/Windows/Devices/Radios/mod.rs
@@ -46,15 +69,77 @@ fn get_central_state(radio: &Radio) -> CentralState {
     }
 }
 
+fn bluetooth_error_reason(error: BluetoothError) -> String {
+    match error {
+        BluetoothError::RadioNotAvailable => "Radio not available".to_string(),
+        BluetoothError::ResourceInUse => "Resource in use".to_string(),
+        BluetoothError::DeviceNotConnected => "Device not connected".to_string(),
+        BluetoothError::OtherError => "Other error".to_string(),
+        BluetoothError::DisabledByPolicy => "Disabled by policy".to_string(),
+        BluetoothError::NotSupported => "Not supported".to_string(),
+        BluetoothError::DisabledByUser => "Disabled by user".to_string(),
+        BluetoothError::ConsentRequired => "Consent required".to_string(),
+        BluetoothError::TransportNotSupported => "Transport not supported".to_string(),
+        error => format!("Bluetooth error {}", error.0),
+    }
+}
+
 impl Adapter {
-    pub(crate) fn new(radio: Radio) -> Result<Self> {
+    pub(crate) fn new(radio: Radio, options: ManagerOptions) -> Result<Self> {
         let watcher = Arc::new(Mutex::new(BLEWatcher::new()?));
         let manager = Arc::new(AdapterManager::default());
+        let restart_pending = Arc::new(AtomicBool::new(false));
 
         let radio_clone = radio.clone();
         let manager_clone = manager.clone();
+        let restart_pending_clone = restart_pending.clone();
+        watcher
+            .lock()
+            .map_err(Into::<Error>::into)?
+            .on_aborted(Box::new(move |watcher, error| {
+                let restarting = if !options.restart_aborted_scans
+                    || matches!(
+                        error,
+                        BluetoothError::DisabledByPolicy | BluetoothError::NotSupported
+                    ) {
+                    false
+                } else if error == BluetoothError::RadioNotAvailable
+                    || error == BluetoothError::DisabledByUser
+                    || get_central_state(&radio_clone) != CentralState::PoweredOn
+                {
+                    // Restarting right away would just fail again, so wait for the radio.
+                    restart_pending_clone.store(true, Ordering::Relaxed);
+                    true
+                } else {
+                    match watcher.Start() {
+                        Ok(()) => true,
+                        Err(err) => {
+                            warn!("Failed to restart aborted scan: {:?}", err);
+                            false
+                        }
+                    }
+                };
+                manager_clone.emit(CentralEvent::ScanStopped {
+                    reason: bluetooth_error_reason(error),
+                    restarting,
+                });
+            }))?;
+
+        let radio_clone = radio.clone();
+        let manager_clone = manager.clone();
+        let watcher_clone = watcher.clone();
+        let restart_pending_clone = restart_pending.clone();
         let handler = TypedEventHandler::new(move |_sender, _args| {
             let state = get_central_state(&radio_clone);
+            if state == CentralState::PoweredOn
+                && restart_pending_clone.swap(false, Ordering::Relaxed)
+            {
+                if let Ok(watcher) = watcher_clone.lock() {
+                    if let Err(err) = watcher.resume() {
+                        warn!("Failed to restart aborted scan: {:?}", err);
+                    }
+                }
+            }
             manager_clone.emit(CentralEvent::StateUpdate(state.into()));
             Ok(())
         });
@@ -66,10 +151,29 @@ impl Adapter {
             watcher,
             manager,
             radio,
+            restart_pending,
+            publisher: Arc::new(Mutex::new(None)),
         })
     }
 }
 
+impl Adapter {
+    /// Adds a peripheral Windows knows about, or updates it if a scan has added it meanwhile.
+    fn add_known_peripheral(&self, address: BDAddr, properties: DeviceProperties) -> Peripheral {
+        let (peripheral, added) = self.manager.peripheral_or_insert_with(&address.into(), || {
+            Peripheral::new(Arc::downgrade(&self.manager), address)
+        });
+        peripheral.apply_device_properties(properties);
+        if added {
+            self.manager.emit_with_properties(
+                CentralEvent::DeviceDiscovered(address.into()),
+                peripheral.cached_properties().as_deref(),
+            );
+        }
+        peripheral
+    }
+}
+
 impl Debug for Adapter {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("Adapter")
@@ -87,33 +191,57 @@ impl Central for Adapter {
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.start_scan_with_settings(filter, ScanSettings::default())
+            .await
+    }
+
+    async fn start_scan_with_settings(
+        &self,
+        filter: ScanFilter,
+        settings: ScanSettings,
+    ) -> Result<()> {
+        self.restart_pending.store(false, Ordering::Relaxed);
         let watcher = self.watcher.lock().map_err(Into::<Error>::into)?;
         let manager = self.manager.clone();
+        manager.set_scan_filter(filter.clone());
         watcher.start(
             filter,
+            &settings,
             Box::new(move |args| {
                 let bluetooth_address = args.BluetoothAddress()?;
                 let address: BDAddr = bluetooth_address.try_into().unwrap();
-                if let Some(mut entry) = manager.peripheral_mut(&address.into()) {
-                    entry.value_mut().update_properties(args);
-                    manager.emit(CentralEvent::DeviceUpdated(address.into()));
+                let advertising_interval = manager.record_advertisement(&address.into());
+                let (peripheral, added) = manager
+                    .peripheral_or_insert_with(&address.into(), || {
+                        Peripheral::new(Arc::downgrade(&manager), address)
+                    });
+                peripheral.update_properties(args, advertising_interval);
+                let event = if added {
+                    CentralEvent::DeviceDiscovered(address.into())
                 } else {
-                    let peripheral = Peripheral::new(Arc::downgrade(&manager), address);
-                    peripheral.update_properties(args);
-                    manager.add_peripheral(peripheral);
-                    manager.emit(CentralEvent::DeviceDiscovered(address.into()));
-                }
+                    CentralEvent::DeviceUpdated(address.into())
+                };
+                manager.emit_with_properties(event, peripheral.cached_properties().as_deref());
                 Ok(())
             }),
         )
     }
 
     async fn stop_scan(&self) -> Result<()> {
+        self.restart_pending.store(false, Ordering::Relaxed);
         let watcher = self.watcher.lock().map_err(Into::<Error>::into)?;
         watcher.stop()?;
+        self.manager.set_scan_filter(ScanFilter::default());
         Ok(())
     }
 
+    async fn start_scan_session(&self, filter: ScanFilter) -> Result<ScanSession> {
+        self.manager
+            .scan_sessions()
+            .start(self.clone(), filter)
+            .await
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         Ok(self.manager.peripherals())
     }
@@ -122,10 +250,24 @@ impl Central for Adapter {
         self.manager.peripheral(id).ok_or(Error::DeviceNotFound)
     }
 
-    async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
-        Err(Error::NotSupported(
-            "Can't add a Peripheral from a BDAddr".to_string(),
-        ))
+    async fn add_peripheral(&self, id: &PeripheralId) -> Result<Peripheral> {
+        if let Some(peripheral) = self.manager.peripheral(id) {
+            return Ok(peripheral);
+        }
+        // Only devices Windows already knows about, e.g. because they're paired, can be found
+        // without a scan.
+        let address = id.0;
+        let properties = BLEDevice::device_properties(address).await?;
+        // A scan may have found the device while the properties were looked up.
+        Ok(self.add_known_peripheral(address, properties))
+    }
+
+    async fn paired_peripherals(&self) -> Result<Vec<Peripheral>> {
+        Ok(BLEDevice::paired_devices()
+            .await?
+            .into_iter()
+            .map(|(address, properties)| self.add_known_peripheral(address, properties))
+            .collect())
     }
 
     async fn adapter_info(&self) -> Result<String> {
@@ -136,4 +278,66 @@ impl Central for Adapter {
     async fn adapter_state(&self) -> Result<CentralState> {
         Ok(get_central_state(&self.radio))
     }
+
+    async fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::BACKGROUND_SCAN
+            | Capabilities::PAIRED_PERIPHERALS
+            | Capabilities::EVENT_REPLAY
+            | Capabilities::ADD_PERIPHERAL
+            | Capabilities::MTU
+            | Capabilities::SCAN_SESSIONS
+            | Capabilities::PAIRING_STATE
+            | Capabilities::GATT_INTERCEPTORS
+            | Capabilities::ADVERTISING
+            | Capabilities::CONNECTION_PARAMETERS
+            | Capabilities::SCAN_SETTINGS)
+    }
+
+    fn backend(&self) -> Backend {
+        // DeviceFamilyVersion is the four 16 bit parts of the version, packed into a u64.
+        let version = AnalyticsInfo::VersionInfo()
+            .and_then(|info| info.DeviceFamilyVersion())
+            .ok()
+            .and_then(|version| version.to_string().parse::<u64>().ok())
+            .map(|version| {
+                format!(
+                    "Windows {}.{}.{}.{}",
+                    version >> 48,
+                    (version >> 32) & 0xFFFF,
+                    (version >> 16) & 0xFFFF,
+                    version & 0xFFFF
+                )
+            });
+        Backend {
+            kind: BackendKind::WinRt,
+            version,
+        }
+    }
+
+    async fn set_event_replay_capacity(&self, capacity: usize) -> Result<()> {
+        self.manager.set_replay_capacity(capacity);
+        Ok(())
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.manager.interceptors().add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self.manager.interceptors().remove(interceptor))
+    }
+
+    async fn start_advertising(&self, data: AdvertisementData) -> Result<()> {
+        let mut publisher = self.publisher.lock().map_err(Into::<Error>::into)?;
+        // Stop the previous advertisement first, as Windows may not run two at once.
+        *publisher = None;
+        *publisher = Some(BLEPublisher::start(&data)?);
+        Ok(())
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        *self.publisher.lock().map_err(Into::<Error>::into)? = None;
+        Ok(())
+    }
 }
@@ -13,7 +13,7 @@
 
 use super::{super::utils::to_descriptor_value, descriptor::BLEDescriptor};
 use crate::{
-    api::{Characteristic, WriteType},
+    api::{Characteristic, Feature, WriteType},
     winrtble::utils,
     Error, Result,
 };
@@ -27,7 +27,7 @@ use windows::{
         BluetoothCacheMode,
         GenericAttributeProfile::{
             GattCharacteristic, GattClientCharacteristicConfigurationDescriptorValue,
-            GattCommunicationStatus, GattValueChangedEventArgs, GattWriteOption,
+            GattValueChangedEventArgs, GattWriteOption,
         },
     },
     Foundation::TypedEventHandler,
@@ -36,11 +36,17 @@ use windows::{
 
 pub type NotifiyEventHandler = Box<dyn Fn(Vec<u8>) + Send>;
 
-impl From<WriteType> for GattWriteOption {
-    fn from(val: WriteType) -> Self {
+impl TryFrom<WriteType> for GattWriteOption {
+    type Error = Error;
+
+    fn try_from(val: WriteType) -> Result<Self> {
         match val {
-            WriteType::WithoutResponse => GattWriteOption::WriteWithoutResponse,
-            WriteType::WithResponse => GattWriteOption::WriteWithResponse,
+            WriteType::WithoutResponse => Ok(GattWriteOption::WriteWithoutResponse),
+            WriteType::WithResponse => Ok(GattWriteOption::WriteWithResponse),
+            // WinRT has no way to ask for a signed write.
+            WriteType::Signed => Err(Error::NotSupported {
+                feature: Feature::SignedWrite,
+            }),
         }
     }
 }
@@ -64,40 +70,37 @@ impl BLECharacteristic {
         }
     }
 
-    pub async fn write_value(&self, data: &[u8], write_type: WriteType) -> Result<()> {
+    /// Returns the WinRT object of the characteristic. Cloning it only adds a reference, so it can
+    /// be used for an operation without keeping this borrowed.
+    pub fn gatt_characteristic(&self) -> GattCharacteristic {
+        self.characteristic.clone()
+    }
+
+    pub async fn write_value(
+        characteristic: &GattCharacteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
         let writer = DataWriter::new()?;
         writer.WriteBytes(data)?;
-        let operation = self
-            .characteristic
-            .WriteValueWithOptionAsync(&writer.DetachBuffer()?, write_type.into())?;
+        let operation = characteristic
+            .WriteValueWithResultAndOptionAsync(&writer.DetachBuffer()?, write_type.try_into()?)?;
         let result = operation.into_future().await?;
-        if result == GattCommunicationStatus::Success {
-            Ok(())
-        } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on write: {:?}", result).into(),
-            ))
-        }
+        utils::to_error(result.Status()?, result.ProtocolError())
     }
 
-    pub async fn read_value(&self) -> Result<Vec<u8>> {
-        let result = self
-            .characteristic
+    pub async fn read_value(characteristic: &GattCharacteristic) -> Result<Vec<u8>> {
+        let result = characteristic
             .ReadValueWithCacheModeAsync(BluetoothCacheMode::Uncached)?
             .into_future()
             .await?;
-        if result.Status()? == GattCommunicationStatus::Success {
-            let value = result.Value()?;
-            let reader = DataReader::FromBuffer(&value)?;
-            let len = reader.UnconsumedBufferLength()? as usize;
-            let mut input = vec![0u8; len];
-            reader.ReadBytes(&mut input[0..len])?;
-            Ok(input)
-        } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on read: {:?}", result).into(),
-            ))
-        }
+        utils::to_error(result.Status()?, result.ProtocolError())?;
+        let value = result.Value()?;
+        let reader = DataReader::FromBuffer(&value)?;
+        let len = reader.UnconsumedBufferLength()? as usize;
+        let mut input = vec![0u8; len];
+        reader.ReadBytes(&mut input[0..len])?;
+        Ok(input)
     }
 
     pub async fn subscribe(&mut self, on_value_changed: NotifiyEventHandler) -> Result<()> {
@@ -121,22 +124,19 @@ impl BLECharacteristic {
         }
         let config = to_descriptor_value(self.characteristic.CharacteristicProperties()?);
         if config == GattClientCharacteristicConfigurationDescriptorValue::None {
-            return Err(Error::NotSupported("Can not subscribe to attribute".into()));
+            return Err(Error::Other(
+                "Characteristic supports neither notifications nor indications".into(),
+            ));
         }
 
-        let status = self
+        let result = self
             .characteristic
-            .WriteClientCharacteristicConfigurationDescriptorAsync(config)?
+            .WriteClientCharacteristicConfigurationDescriptorWithResultAsync(config)?
             .into_future()
             .await?;
+        let status = result.Status()?;
         trace!("subscribe {:?}", status);
-        if status == GattCommunicationStatus::Success {
-            Ok(())
-        } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on subscribe: {:?}", status).into(),
-            ))
-        }
+        utils::to_error(status, result.ProtocolError())
     }
 
     pub async fn unsubscribe(&mut self) -> Result<()> {
@@ -145,19 +145,14 @@ impl BLECharacteristic {
         }
         self.notify_token = None;
         let config = GattClientCharacteristicConfigurationDescriptorValue::None;
-        let status = self
+        let result = self
             .characteristic
-            .WriteClientCharacteristicConfigurationDescriptorAsync(config)?
+            .WriteClientCharacteristicConfigurationDescriptorWithResultAsync(config)?
             .into_future()
             .await?;
+        let status = result.Status()?;
         trace!("unsubscribe {:?}", status);
-        if status == GattCommunicationStatus::Success {
-            Ok(())
-        } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on unsubscribe: {:?}", status).into(),
-            ))
-        }
+        utils::to_error(status, result.ProtocolError())
     }
 
     pub fn uuid(&self) -> Uuid {
@@ -178,6 +173,7 @@ impl BLECharacteristic {
             service_uuid,
             descriptors,
             properties,
+            handle: None,
         }
     }
 }
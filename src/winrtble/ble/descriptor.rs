@@ -12,18 +12,15 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 use super::super::utils;
-use crate::{api::Descriptor, Error, Result};
+use crate::{api::Descriptor, Result};
 use std::future::IntoFuture;
 use uuid::Uuid;
 use windows::{
-    Devices::Bluetooth::{
-        BluetoothCacheMode,
-        GenericAttributeProfile::{GattCommunicationStatus, GattDescriptor},
-    },
+    Devices::Bluetooth::{BluetoothCacheMode, GenericAttributeProfile::GattDescriptor},
     Storage::Streams::{DataReader, DataWriter},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BLEDescriptor {
     descriptor: GattDescriptor,
 }
@@ -43,21 +40,19 @@ impl BLEDescriptor {
             uuid,
             service_uuid,
             characteristic_uuid,
+            handle: None,
+            permissions: None,
         }
     }
 
     pub async fn write_value(&self, data: &[u8]) -> Result<()> {
         let writer = DataWriter::new()?;
         writer.WriteBytes(data)?;
-        let operation = self.descriptor.WriteValueAsync(&writer.DetachBuffer()?)?;
+        let operation = self
+            .descriptor
+            .WriteValueWithResultAsync(&writer.DetachBuffer()?)?;
         let result = operation.into_future().await?;
-        if result == GattCommunicationStatus::Success {
-            Ok(())
-        } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on write descriptor: {:?}", result).into(),
-            ))
-        }
+        utils::to_error(result.Status()?, result.ProtocolError())
     }
 
     pub async fn read_value(&self) -> Result<Vec<u8>> {
@@ -66,17 +61,12 @@ impl BLEDescriptor {
             .ReadValueWithCacheModeAsync(BluetoothCacheMode::Uncached)?
             .into_future()
             .await?;
-        if result.Status()? == GattCommunicationStatus::Success {
-            let value = result.Value()?;
-            let reader = DataReader::FromBuffer(&value)?;
-            let len = reader.UnconsumedBufferLength()? as usize;
-            let mut input = vec![0u8; len];
-            reader.ReadBytes(&mut input[0..len])?;
-            Ok(input)
-        } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on read: {:?}", result).into(),
-            ))
-        }
+        utils::to_error(result.Status()?, result.ProtocolError())?;
+        let value = result.Value()?;
+        let reader = DataReader::FromBuffer(&value)?;
+        let len = reader.UnconsumedBufferLength()? as usize;
+        let mut input = vec![0u8; len];
+        reader.ReadBytes(&mut input[0..len])?;
+        Ok(input)
     }
 }
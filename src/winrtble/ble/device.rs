@@ -11,33 +11,109 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::{api::BDAddr, winrtble::utils, Error, Result};
+use crate::{
+    api::{BDAddr, ConnectionPriority},
+    winrtble::utils,
+    Error, Result,
+};
 use log::{debug, trace};
-use std::future::IntoFuture;
+use std::{convert::TryInto, future::IntoFuture};
 use windows::{
     core::Ref,
-    Devices::Bluetooth::{
-        BluetoothCacheMode, BluetoothConnectionStatus, BluetoothLEDevice,
-        GenericAttributeProfile::{
-            GattCharacteristic, GattCommunicationStatus, GattDescriptor, GattDeviceService,
-            GattDeviceServicesResult, GattSession,
+    Devices::{
+        Bluetooth::{
+            BluetoothCacheMode, BluetoothConnectionStatus, BluetoothDevice, BluetoothLEDevice,
+            BluetoothLEPreferredConnectionParameters,
+            BluetoothLEPreferredConnectionParametersRequest,
+            BluetoothLEPreferredConnectionParametersRequestStatus,
+            GenericAttributeProfile::{
+                GattCharacteristic, GattCommunicationStatus, GattDescriptor, GattDeviceService,
+                GattDeviceServicesResult, GattSession,
+            },
         },
+        Enumeration::DeviceInformation,
     },
     Foundation::TypedEventHandler,
 };
 
 pub type ConnectedEventHandler = Box<dyn Fn(bool) + Send>;
+pub type ServicesChangedEventHandler = Box<dyn Fn() + Send>;
+
+/// Properties Windows knows about a device independently of its advertisements, e.g. because it
+/// was paired or connected before.
+#[derive(Debug, Default)]
+pub struct DeviceProperties {
+    pub name: Option<String>,
+    pub appearance: Option<u16>,
+    pub is_paired: Option<bool>,
+    pub class: Option<u32>,
+}
+
+impl DeviceProperties {
+    fn from_device(device: &BluetoothLEDevice) -> Self {
+        let device_information = device.DeviceInformation().ok();
+        // The DeviceInformation name is the one shown in the Windows settings, which includes any
+        // name given by the user, so prefer it over the GAP name.
+        let name = device_information
+            .as_ref()
+            .and_then(|info| info.Name().ok())
+            .filter(|name| !name.is_empty())
+            .or_else(|| device.Name().ok().filter(|name| !name.is_empty()))
+            .map(|name| name.to_string());
+        let appearance = device
+            .Appearance()
+            .and_then(|appearance| appearance.RawValue())
+            .ok()
+            .filter(|&appearance| appearance != 0);
+        let is_paired = device_information
+            .and_then(|info| info.Pairing().ok())
+            .and_then(|pairing| pairing.IsPaired().ok());
+        DeviceProperties {
+            name,
+            appearance,
+            is_paired,
+            class: None,
+        }
+    }
+
+    /// Looks up the class of device from the classic side of a dual-mode device, which Windows only
+    /// knows if the device was found by an inquiry or paired over BR/EDR.
+    async fn with_class_of_device(mut self, address: u64) -> Self {
+        let Ok(device) = BluetoothDevice::FromBluetoothAddressAsync(address) else {
+            return self;
+        };
+        match device.into_future().await {
+            Ok(device) => {
+                self.class = device
+                    .ClassOfDevice()
+                    .and_then(|class| class.RawValue())
+                    .ok()
+                    .filter(|&class| class != 0);
+                if let Err(err) = device.Close() {
+                    debug!("class_of_device:close {:?}", err);
+                }
+            }
+            Err(err) => trace!("No classic device for {:x}: {:?}", address, err),
+        }
+        self
+    }
+}
 
 pub struct BLEDevice {
     device: BluetoothLEDevice,
     connection_token: i64,
+    services_changed_token: i64,
     services: Vec<GattDeviceService>,
+    session: Option<GattSession>,
+    // Windows keeps to the requested parameters while the request is open.
+    connection_parameters_request: Option<BluetoothLEPreferredConnectionParametersRequest>,
 }
 
 impl BLEDevice {
     pub async fn new(
         address: BDAddr,
         connection_status_changed: ConnectedEventHandler,
+        services_changed: ServicesChangedEventHandler,
     ) -> Result<Self> {
         let async_op = BluetoothLEDevice::FromBluetoothAddressAsync(address.into())
             .map_err(|_| Error::DeviceNotFound)?;
@@ -61,14 +137,79 @@ impl BLEDevice {
         let connection_token = device
             .ConnectionStatusChanged(&connection_status_handler)
             .map_err(|_| Error::Other("Could not add connection status handler".into()))?;
+        // Windows subscribes to the Service Changed characteristic by itself, and raises this when
+        // the device indicates a change.
+        let services_changed_handler =
+            TypedEventHandler::new(move |_: Ref<BluetoothLEDevice>, _| {
+                services_changed();
+                Ok(())
+            });
+        let services_changed_token = device
+            .GattServicesChanged(&services_changed_handler)
+            .map_err(|_| Error::Other("Could not add services changed handler".into()))?;
 
         Ok(BLEDevice {
             device,
             connection_token,
+            services_changed_token,
             services: vec![],
+            session: None,
+            connection_parameters_request: None,
         })
     }
 
+    /// Looks up what Windows knows about the device with the given address, without connecting to
+    /// it.
+    pub async fn device_properties(address: BDAddr) -> Result<DeviceProperties> {
+        let device = BluetoothLEDevice::FromBluetoothAddressAsync(address.into())
+            .map_err(|_| Error::DeviceNotFound)?
+            .into_future()
+            .await
+            .map_err(|_| Error::DeviceNotFound)?;
+        let properties = DeviceProperties::from_device(&device);
+        if let Err(err) = device.Close() {
+            debug!("device_properties:close {:?}", err);
+        }
+        Ok(properties.with_class_of_device(address.into()).await)
+    }
+
+    /// Enumerates the Bluetooth LE devices paired with this host.
+    pub async fn paired_devices() -> Result<Vec<(BDAddr, DeviceProperties)>> {
+        let selector = BluetoothLEDevice::GetDeviceSelectorFromPairingState(true)?;
+        let device_infos = DeviceInformation::FindAllAsyncAqsFilter(&selector)?
+            .into_future()
+            .await?;
+        // Collect the IDs first, the collection isn't Send and so can't be held across the awaits
+        // below.
+        let ids: Vec<_> = device_infos
+            .into_iter()
+            .filter_map(|info| info.Id().ok())
+            .collect();
+        let mut devices = Vec::with_capacity(ids.len());
+        for id in ids {
+            let device = match BluetoothLEDevice::FromIdAsync(&id)?.into_future().await {
+                Ok(device) => device,
+                Err(err) => {
+                    debug!("paired_devices: could not open {}: {:?}", id, err);
+                    continue;
+                }
+            };
+            let raw_address = device.BluetoothAddress()?;
+            let properties = DeviceProperties::from_device(&device);
+            if let Err(err) = device.Close() {
+                debug!("paired_devices:close {:?}", err);
+            }
+            if let Ok(address) = raw_address.try_into() {
+                devices.push((address, properties.with_class_of_device(raw_address).await));
+            }
+        }
+        Ok(devices)
+    }
+
+    pub fn properties(&self) -> DeviceProperties {
+        DeviceProperties::from_device(&self.device)
+    }
+
     async fn get_gatt_services(
         &self,
         cache_mode: BluetoothCacheMode,
@@ -89,25 +230,63 @@ impl BLEDevice {
 
         let service_result = self.get_gatt_services(BluetoothCacheMode::Uncached).await?;
         let status = service_result.Status().map_err(|_| Error::DeviceNotFound)?;
-        utils::to_error(status)
+        utils::to_error(status, service_result.ProtocolError())
     }
 
-    pub async fn mtu(&self) -> Result<u16> {
-        let device_id = self
-            .device
-            .BluetoothDeviceId()
-            .map_err(|e| Error::Other(format!("{:?}", e).into()))?;
-        let session = GattSession::FromDeviceIdAsync(device_id)
-            .map_err(|e| Error::Other(format!("{:?}", e).into()))?
-            .into_future()
-            .await
-            .map_err(|e| Error::Other(format!("{:?}", e).into()))?;
-        let mtu = session
+    /// Returns the GATT session of the device, opening it the first time.
+    async fn session(&mut self) -> Result<&GattSession> {
+        if self.session.is_none() {
+            let device_id = self
+                .device
+                .BluetoothDeviceId()
+                .map_err(|e| Error::Other(format!("{:?}", e).into()))?;
+            let session = GattSession::FromDeviceIdAsync(device_id)
+                .map_err(|e| Error::Other(format!("{:?}", e).into()))?
+                .into_future()
+                .await
+                .map_err(|e| Error::Other(format!("{:?}", e).into()))?;
+            self.session = Some(session);
+        }
+        Ok(self.session.as_ref().unwrap())
+    }
+
+    pub async fn mtu(&mut self) -> Result<u16> {
+        let mtu = self
+            .session()
+            .await?
             .MaxPduSize()
             .map_err(|e| Error::Other(format!("{:?}", e).into()))?;
         Ok(mtu)
     }
 
+    /// Asks Windows for the connection parameters of a preset, replacing the previous request.
+    /// Only available on Windows 11 and later.
+    pub fn request_connection_priority(&mut self, priority: ConnectionPriority) -> Result<()> {
+        let parameters = match priority {
+            ConnectionPriority::Balanced => BluetoothLEPreferredConnectionParameters::Balanced(),
+            ConnectionPriority::High => {
+                BluetoothLEPreferredConnectionParameters::ThroughputOptimized()
+            }
+            ConnectionPriority::LowPower => {
+                BluetoothLEPreferredConnectionParameters::PowerOptimized()
+            }
+        }?;
+        let request = self
+            .device
+            .RequestPreferredConnectionParameters(&parameters)?;
+        let status = request.Status()?;
+        if status != BluetoothLEPreferredConnectionParametersRequestStatus::Success {
+            let _ = request.Close();
+            return Err(Error::Other(
+                format!("Connection parameters request failed: {:?}", status).into(),
+            ));
+        }
+        if let Some(previous) = self.connection_parameters_request.replace(request) {
+            let _ = previous.Close();
+        }
+        Ok(())
+    }
+
     async fn is_connected(&self) -> Result<bool> {
         let winrt_error = |e| Error::Other(format!("{:?}", e).into());
         let status = self.device.ConnectionStatus().map_err(winrt_error)?;
@@ -129,12 +308,11 @@ impl BLEDevice {
                 debug!("characteristics {:?}", results.Size());
                 Ok(results.into_iter().collect())
             }
-            Ok(GattCommunicationStatus::ProtocolError) => Err(Error::Other(
-                format!(
-                    "get_characteristics for {:?} encountered a protocol error",
-                    service
-                )
-                .into(),
+            Ok(GattCommunicationStatus::ProtocolError) => Err(Error::ProtocolError(
+                async_result
+                    .ProtocolError()
+                    .and_then(|code| code.Value())
+                    .ok(),
             )),
             Ok(status) => {
                 debug!("characteristic read failed due to {:?}", status);
@@ -153,20 +331,10 @@ impl BLEDevice {
             .GetDescriptorsWithCacheModeAsync(BluetoothCacheMode::Uncached)?
             .into_future()
             .await?;
-        let status = async_result.Status();
-        if status == Ok(GattCommunicationStatus::Success) {
-            let results = async_result.Descriptors()?;
-            debug!("descriptors {:?}", results.Size());
-            Ok(results.into_iter().collect())
-        } else {
-            Err(Error::Other(
-                format!(
-                    "get_characteristic_descriptors for {:?} failed: {:?}",
-                    characteristic, status
-                )
-                .into(),
-            ))
-        }
+        utils::to_error(async_result.Status()?, async_result.ProtocolError())?;
+        let results = async_result.Descriptors()?;
+        debug!("descriptors {:?}", results.Size());
+        Ok(results.into_iter().collect())
     }
 
     pub async fn discover_services(&mut self) -> Result<&[GattDeviceService]> {
@@ -196,6 +364,12 @@ impl Drop for BLEDevice {
         if let Err(err) = result {
             debug!("Drop:remove_connection_status_changed {:?}", err);
         }
+        if let Err(err) = self
+            .device
+            .RemoveGattServicesChanged(self.services_changed_token)
+        {
+            debug!("Drop:remove_gatt_services_changed {:?}", err);
+        }
 
         self.services.iter().for_each(|service| {
             if let Err(err) = service.Close() {
@@ -203,6 +377,18 @@ impl Drop for BLEDevice {
             }
         });
 
+        if let Some(session) = &self.session {
+            if let Err(err) = session.Close() {
+                debug!("Drop:close_gatt_session {:?}", err);
+            }
+        }
+
+        if let Some(request) = &self.connection_parameters_request {
+            if let Err(err) = request.Close() {
+                debug!("Drop:close_connection_parameters_request {:?}", err);
+            }
+        }
+
         let result = self.device.Close();
         if let Err(err) = result {
             debug!("Drop:close {:?}", err);
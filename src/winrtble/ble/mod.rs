@@ -14,5 +14,6 @@
 pub mod characteristic;
 pub mod descriptor;
 pub mod device;
+pub mod publisher;
 pub mod service;
 pub mod watcher;
@@ -0,0 +1,72 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+    api::{bleuuid::BleUuid, AdvertisementData},
+    winrtble::utils::to_buffer,
+    Error, Result,
+};
+use windows::{core::GUID, Devices::Bluetooth::Advertisement::*};
+
+/// The AD types of service data, by the length of the service UUID.
+const SERVICE_DATA_16: u8 = 0x16;
+const SERVICE_DATA_32: u8 = 0x20;
+const SERVICE_DATA_128: u8 = 0x21;
+
+/// A running advertisement. Windows only sends non-connectable advertisements from a publisher.
+#[derive(Debug)]
+pub struct BLEPublisher {
+    publisher: BluetoothLEAdvertisementPublisher,
+}
+
+impl BLEPublisher {
+    pub fn start(data: &AdvertisementData) -> Result<Self> {
+        if data.local_name.is_some() {
+            return Err(Error::Other(
+                "Windows doesn't allow advertising a local name".into(),
+            ));
+        }
+        let publisher = BluetoothLEAdvertisementPublisher::new()?;
+        let advertisement = publisher.Advertisement()?;
+        let service_uuids = advertisement.ServiceUuids()?;
+        for uuid in &data.service_uuids {
+            service_uuids.Append(GUID::from(uuid.as_u128()))?;
+        }
+        let manufacturer_data = advertisement.ManufacturerData()?;
+        for (&company, value) in &data.manufacturer_data {
+            manufacturer_data.Append(&BluetoothLEManufacturerData::Create(
+                company,
+                &to_buffer(value)?,
+            )?)?;
+        }
+        let data_sections = advertisement.DataSections()?;
+        for (uuid, value) in &data.service_data {
+            let (data_type, mut section) = if let Some(uuid) = uuid.to_ble_u16() {
+                (SERVICE_DATA_16, uuid.to_le_bytes().to_vec())
+            } else if let Some(uuid) = uuid.to_ble_u32() {
+                (SERVICE_DATA_32, uuid.to_le_bytes().to_vec())
+            } else {
+                (SERVICE_DATA_128, uuid.as_u128().to_le_bytes().to_vec())
+            };
+            section.extend_from_slice(value);
+            data_sections.Append(&BluetoothLEAdvertisementDataSection::Create(
+                data_type,
+                &to_buffer(&section)?,
+            )?)?;
+        }
+        publisher.Start()?;
+        Ok(BLEPublisher { publisher })
+    }
+}
+
+impl Drop for BLEPublisher {
+    fn drop(&mut self) {
+        if let Err(err) = self.publisher.Stop() {
+            log::warn!("Failed to stop advertising: {:?}", err);
+        }
+    }
+}
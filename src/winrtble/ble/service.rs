@@ -18,6 +18,7 @@ impl BLEService {
             .collect();
         Service {
             uuid: self.uuid,
+            handle: None,
             primary: true,
             characteristics,
         }
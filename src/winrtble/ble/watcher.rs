@@ -11,12 +11,27 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::{api::ScanFilter, Error, Result};
-use windows::{core::Ref, Devices::Bluetooth::Advertisement::*, Foundation::TypedEventHandler};
+use crate::{
+    api::{ManufacturerDataFilter, ScanFilter, ScanSettings},
+    winrtble::utils::to_buffer,
+    Error, Result,
+};
+use windows::{
+    core::Ref,
+    Devices::Bluetooth::{Advertisement::*, BluetoothError},
+    Foundation::TypedEventHandler,
+};
+
+/// The AD type of manufacturer specific data.
+const MANUFACTURER_SPECIFIC_DATA: u8 = 0xff;
 
 pub type AdvertisementEventHandler =
     Box<dyn Fn(&BluetoothLEAdvertisementReceivedEventArgs) -> windows::core::Result<()> + Send>;
 
+/// Called when the watcher was stopped because of an error rather than by [`BLEWatcher::stop`],
+/// with the watcher so it can be restarted.
+pub type AbortedEventHandler = Box<dyn Fn(&BluetoothLEAdvertisementWatcher, BluetoothError) + Send>;
+
 #[derive(Debug)]
 pub struct BLEWatcher {
     watcher: BluetoothLEAdvertisementWatcher,
@@ -35,17 +50,40 @@ impl BLEWatcher {
         Ok(BLEWatcher { watcher })
     }
 
-    pub fn start(&self, filter: ScanFilter, on_received: AdvertisementEventHandler) -> Result<()> {
-        let ScanFilter { services } = filter;
-        let ad = self.watcher.AdvertisementFilter()?.Advertisement()?;
-        let ad_services = ad.ServiceUuids()?;
+    pub fn start(
+        &self,
+        filter: ScanFilter,
+        settings: &ScanSettings,
+        on_received: AdvertisementEventHandler,
+    ) -> Result<()> {
+        let ScanFilter {
+            services,
+            manufacturer_data,
+            ..
+        } = filter;
+        let ad_filter = self.watcher.AdvertisementFilter()?;
+        let ad_services = ad_filter.Advertisement()?.ServiceUuids()?;
         ad_services.Clear()?;
         for service in services {
             ad_services.Append(windows::core::GUID::from(service.as_u128()))?;
         }
+        // A single manufacturer data filter is applied by Windows, as a byte pattern. Several are
+        // only applied by the AdapterManager, like the rest of the scan filter.
+        let byte_patterns = ad_filter.BytePatterns()?;
+        byte_patterns.Clear()?;
+        if let [manufacturer] = manufacturer_data.as_slice() {
+            byte_patterns.Append(&BluetoothLEAdvertisementBytePattern::Create(
+                MANUFACTURER_SPECIFIC_DATA,
+                0,
+                &to_buffer(&manufacturer_data_pattern(manufacturer))?,
+            )?)?;
+        }
         self.watcher
             .SetScanningMode(BluetoothLEScanningMode::Active)?;
-        let _ = self.watcher.SetAllowExtendedAdvertisements(true);
+        // Not available before Windows 10 2004.
+        let _ = self
+            .watcher
+            .SetAllowExtendedAdvertisements(settings.extended_advertising.unwrap_or(true));
         let handler: TypedEventHandler<
             BluetoothLEAdvertisementWatcher,
             BluetoothLEAdvertisementReceivedEventArgs,
@@ -63,8 +101,49 @@ impl BLEWatcher {
         Ok(())
     }
 
+    pub fn on_aborted(&self, on_aborted: AbortedEventHandler) -> Result<()> {
+        let handler: TypedEventHandler<
+            BluetoothLEAdvertisementWatcher,
+            BluetoothLEAdvertisementWatcherStoppedEventArgs,
+        > = TypedEventHandler::new(
+            move |sender: Ref<BluetoothLEAdvertisementWatcher>,
+                  args: Ref<BluetoothLEAdvertisementWatcherStoppedEventArgs>| {
+                let error = args.ok()?.Error()?;
+                if error != BluetoothError::Success {
+                    on_aborted(sender.ok()?, error);
+                }
+                Ok(())
+            },
+        );
+        self.watcher.Stopped(&handler)?;
+        Ok(())
+    }
+
+    /// Starts the watcher again with the filter and handler it was last started with.
+    pub fn resume(&self) -> Result<()> {
+        self.watcher.Start()?;
+        Ok(())
+    }
+
     pub fn stop(&self) -> Result<()> {
         self.watcher.Stop()?;
         Ok(())
     }
 }
+
+/// The bytes manufacturer specific data matching `filter` starts with: the company identifier,
+/// and the data up to the first byte which isn't compared fully.
+fn manufacturer_data_pattern(filter: &ManufacturerDataFilter) -> Vec<u8> {
+    let compared = filter
+        .data
+        .iter()
+        .enumerate()
+        .take_while(|&(i, _)| filter.mask.get(i).is_none_or(|&mask| mask == 0xff))
+        .map(|(_, &byte)| byte);
+    filter
+        .company_id
+        .to_le_bytes()
+        .into_iter()
+        .chain(compared)
+        .collect()
+}
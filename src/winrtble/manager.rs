@@ -12,18 +12,72 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 use super::adapter::Adapter;
-use crate::{api, Result};
+use crate::api::shutdown_central;
+use crate::{api, Error, Result};
 use async_trait::async_trait;
+use std::collections::HashMap;
 use std::future::IntoFuture;
+use std::sync::{Arc, Mutex};
 use windows::Devices::Radios::{Radio, RadioKind};
 
+/// Options applied to the adapters of a [`Manager`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ManagerOptions {
+    pub restart_aborted_scans: bool,
+}
+
+/// Builds a [`Manager`] with Windows specific options.
+///
+/// ```no_run
+/// # #[cfg(target_os = "windows")]
+/// # async fn example() -> btleplug::Result<()> {
+/// use btleplug::platform::ManagerBuilder;
+///
+/// let manager = ManagerBuilder::new()
+///     .restart_aborted_scans(true)
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ManagerBuilder {
+    options: ManagerOptions,
+}
+
+impl ManagerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether scans which Windows aborts, e.g. because the radio was reset, are restarted
+    /// automatically. If the radio went away the scan is restarted once it is back on. Either way
+    /// a [`CentralEvent::ScanStopped`](crate::api::CentralEvent::ScanStopped) event is emitted.
+    /// Defaults to `false`.
+    pub fn restart_aborted_scans(mut self, restart: bool) -> Self {
+        self.options.restart_aborted_scans = restart;
+        self
+    }
+
+    pub async fn build(self) -> Result<Manager> {
+        Ok(Manager {
+            options: self.options,
+            adapters: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+}
+
 /// Implementation of [api::Manager](crate::api::Manager).
 #[derive(Clone, Debug)]
-pub struct Manager {}
+pub struct Manager {
+    options: ManagerOptions,
+    // Adapters keep track of their peripherals, so hand out the same adapter for each radio.
+    adapters: Arc<Mutex<HashMap<String, Adapter>>>,
+}
 
 impl Manager {
     pub async fn new() -> Result<Self> {
-        Ok(Self {})
+        ManagerBuilder::new().build().await
     }
 }
 
@@ -33,10 +87,36 @@ impl api::Manager for Manager {
 
     async fn adapters(&self) -> Result<Vec<Adapter>> {
         let radios = Radio::GetRadiosAsync()?.into_future().await?;
+        let mut adapters = self.adapters.lock().map_err(Into::<Error>::into)?;
         radios
             .into_iter()
             .filter(|radio| radio.Kind() == Ok(RadioKind::Bluetooth))
-            .map(|radio| Adapter::new(radio))
+            .map(|radio| {
+                let name = radio.Name()?.to_string();
+                if let Some(adapter) = adapters.get(&name) {
+                    return Ok(adapter.clone());
+                }
+                let adapter = Adapter::new(radio, self.options.clone())?;
+                adapters.insert(name, adapter.clone());
+                Ok(adapter)
+            })
             .collect()
     }
+
+    /// Stops scanning and disconnects all connected peripherals. Calling `adapters` afterwards
+    /// creates new adapters.
+    async fn shutdown(&self) -> Result<()> {
+        let adapters: Vec<Adapter> = self
+            .adapters
+            .lock()
+            .map_err(Into::<Error>::into)?
+            .drain()
+            .map(|(_, adapter)| adapter)
+            .collect();
+        let mut result = Ok(());
+        for adapter in adapters {
+            result = result.and(shutdown_central(&adapter).await);
+        }
+        result
+    }
 }
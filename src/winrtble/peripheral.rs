@@ -12,16 +12,32 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 use super::{
-    advertisement_data_type, ble::characteristic::BLECharacteristic,
-    ble::descriptor::BLEDescriptor, ble::device::BLEDevice, ble::service::BLEService, utils,
+    advertisement_data_type,
+    ble::characteristic::BLECharacteristic,
+    ble::descriptor::BLEDescriptor,
+    ble::device::{BLEDevice, DeviceProperties},
+    ble::service::BLEService,
+    utils,
 };
 use crate::{
     api::{
+        advertisement::{
+            encode, parse_structures, AdStructure, ADVERTISING_INTERVAL, ADVERTISING_INTERVAL_LONG,
+            LE_SUPPORTED_FEATURES, PUBLIC_TARGET_ADDRESS, URI,
+        },
         bleuuid::{uuid_from_u16, uuid_from_u32},
-        AddressType, BDAddr, CentralEvent, Characteristic, Descriptor, Peripheral as ApiPeripheral,
-        PeripheralProperties, Service, ValueNotification, WriteType,
+        AddressType, AdvertisingInterval, AdvertisingPacket, BDAddr, CentralEvent, CharPropFlags,
+        Characteristic, ConnectOptions, ConnectTransport, ConnectionParams, Descriptor, Feature,
+        NameSource, Peripheral as ApiPeripheral, PeripheralProperties, Service, ValueNotification,
+        WriteType,
     },
-    common::{adapter_manager::AdapterManager, util::notifications_stream_from_broadcast_receiver},
+    common::{
+        adapter_manager::AdapterManager,
+        util::notifications_stream_from_broadcast_receiver,
+        value_cache::{CharacteristicKey, ValueCache},
+    },
+    gatt_log::{self, GattOperation},
+    interceptor::{GattInterceptor, GattRequest, InterceptorList, Interceptors},
     Error, Result,
 };
 use async_trait::async_trait;
@@ -38,7 +54,8 @@ use std::{
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
     sync::atomic::{AtomicBool, Ordering},
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
 };
 use tokio::sync::broadcast;
 use uuid::Uuid;
@@ -54,7 +71,7 @@ use windows::Devices::Bluetooth::{Advertisement::*, BluetoothAddressType};
     serde(crate = "serde_cr")
 )]
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct PeripheralId(BDAddr);
+pub struct PeripheralId(pub(super) BDAddr);
 
 impl Display for PeripheralId {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -74,17 +91,35 @@ struct Shared {
     address: BDAddr,
     connected: AtomicBool,
     ble_services: DashMap<Uuid, BLEService>,
-    notifications_channel: broadcast::Sender<ValueNotification>,
+    notifications_channel: broadcast::Sender<Option<ValueNotification>>,
+    value_cache: Arc<ValueCache>,
+    interceptors: Arc<InterceptorList>,
 
     // Mutable, advertised, state...
     address_type: RwLock<Option<AddressType>>,
     local_name: RwLock<Option<String>>,
+    local_name_source: RwLock<Option<NameSource>>,
+    // The GAP device name read through btleplug, along with the name advertised at the time.
+    device_name: RwLock<Option<(String, Option<String>)>>,
     last_tx_power_level: RwLock<Option<i16>>, // XXX: would be nice to avoid lock here!
     last_rssi: RwLock<Option<i16>>,           // XXX: would be nice to avoid lock here!
     latest_manufacturer_data: RwLock<HashMap<u16, Vec<u8>>>,
     latest_service_data: RwLock<HashMap<Uuid, Vec<u8>>>,
     services: RwLock<HashSet<Uuid>>,
     class: RwLock<Option<u32>>,
+    appearance: RwLock<Option<u16>>,
+    is_paired: RwLock<Option<bool>>,
+    advertising_interval: RwLock<Option<AdvertisingInterval>>,
+    uri: RwLock<Option<String>>,
+    le_supported_features: RwLock<Option<Vec<u8>>>,
+    advertised_interval: RwLock<Option<Duration>>,
+    public_target_addresses: RwLock<Vec<BDAddr>>,
+    advertisement_data: RwLock<Option<Vec<AdStructure>>>,
+    scan_response_data: RwLock<Option<Vec<AdStructure>>>,
+    // Whether the properties Windows keeps for known devices have been looked up yet.
+    device_properties_fetched: AtomicBool,
+    // The properties last derived from the fields above, cleared whenever they change.
+    properties_snapshot: Mutex<Option<Arc<PeripheralProperties>>>,
 }
 
 impl Peripheral {
@@ -98,25 +133,57 @@ impl Peripheral {
                 connected: AtomicBool::new(false),
                 ble_services: DashMap::new(),
                 notifications_channel: broadcast_sender,
+                value_cache: Arc::new(ValueCache::new()),
+                interceptors: Arc::new(InterceptorList::default()),
                 address_type: RwLock::new(None),
                 local_name: RwLock::new(None),
+                local_name_source: RwLock::new(None),
+                device_name: RwLock::new(None),
                 last_tx_power_level: RwLock::new(None),
                 last_rssi: RwLock::new(None),
                 latest_manufacturer_data: RwLock::new(HashMap::new()),
                 latest_service_data: RwLock::new(HashMap::new()),
                 services: RwLock::new(HashSet::new()),
                 class: RwLock::new(None),
+                appearance: RwLock::new(None),
+                is_paired: RwLock::new(None),
+                advertising_interval: RwLock::new(None),
+                uri: RwLock::new(None),
+                le_supported_features: RwLock::new(None),
+                advertised_interval: RwLock::new(None),
+                public_target_addresses: RwLock::new(Vec::new()),
+                advertisement_data: RwLock::new(None),
+                scan_response_data: RwLock::new(None),
+                device_properties_fetched: AtomicBool::new(false),
+                properties_snapshot: Mutex::new(None),
             }),
         }
     }
 
     // TODO: see if the other backends can also be similarly decoupled from PeripheralProperties
     // so it can potentially be replaced by individial state getters
-    fn derive_properties(&self) -> PeripheralProperties {
-        PeripheralProperties {
+    fn derive_properties(&self) -> Arc<PeripheralProperties> {
+        // Hold the lock while reading the fields, so a concurrent update can't clear the snapshot
+        // before a stale one is stored.
+        let mut snapshot = self.shared.properties_snapshot.lock().unwrap();
+        if let Some(properties) = &*snapshot {
+            return properties.clone();
+        }
+        let advertisement_data = self.shared.advertisement_data.read().unwrap().clone();
+        let scan_response_data = self.shared.scan_response_data.read().unwrap().clone();
+        let raw_data = (advertisement_data.is_some() || scan_response_data.is_some()).then(|| {
+            encode(
+                advertisement_data
+                    .iter()
+                    .chain(&scan_response_data)
+                    .flatten(),
+            )
+        });
+        let properties = Arc::new(PeripheralProperties {
             address: self.address(),
             address_type: *self.shared.address_type.read().unwrap(),
             local_name: self.shared.local_name.read().unwrap().clone(),
+            local_name_source: *self.shared.local_name_source.read().unwrap(),
             tx_power_level: *self.shared.last_tx_power_level.read().unwrap(),
             rssi: *self.shared.last_rssi.read().unwrap(),
             manufacturer_data: self.shared.latest_manufacturer_data.read().unwrap().clone(),
@@ -130,11 +197,71 @@ impl Peripheral {
                 .copied()
                 .collect(),
             class: *self.shared.class.read().unwrap(),
+            appearance: *self.shared.appearance.read().unwrap(),
+            is_paired: *self.shared.is_paired.read().unwrap(),
+            advertising_interval: *self.shared.advertising_interval.read().unwrap(),
+            uri: self.shared.uri.read().unwrap().clone(),
+            le_supported_features: self.shared.le_supported_features.read().unwrap().clone(),
+            advertised_interval: *self.shared.advertised_interval.read().unwrap(),
+            public_target_addresses: self.shared.public_target_addresses.read().unwrap().clone(),
+            advertisement_data,
+            scan_response_data,
+            raw_data,
+            secondary_phy: None,
+            advertising_sid: None,
+            periodic_advertising_interval: None,
+        });
+        *snapshot = Some(properties.clone());
+        properties
+    }
+
+    /// Clears the properties snapshot, to be called after changing any of the fields it's derived
+    /// from.
+    fn invalidate_properties(&self) {
+        *self.shared.properties_snapshot.lock().unwrap() = None;
+    }
+
+    pub(crate) fn apply_device_properties(&self, properties: DeviceProperties) {
+        self.shared
+            .device_properties_fetched
+            .store(true, Ordering::Relaxed);
+        if let Some(name) = properties.name {
+            // An advertised name is more recent than the one Windows has cached.
+            let mut local_name_guard = self.shared.local_name.write().unwrap();
+            if local_name_guard.is_none() {
+                *local_name_guard = Some(name);
+                *self.shared.local_name_source.write().unwrap() = Some(NameSource::Platform);
+            }
         }
+        if let Some(appearance) = properties.appearance {
+            let mut appearance_guard = self.shared.appearance.write().unwrap();
+            if appearance_guard.is_none() {
+                *appearance_guard = Some(appearance);
+            }
+        }
+        if properties.is_paired.is_some() {
+            *self.shared.is_paired.write().unwrap() = properties.is_paired;
+        }
+        if properties.class.is_some() {
+            *self.shared.class.write().unwrap() = properties.class;
+        }
+        self.invalidate_properties();
     }
 
-    pub(crate) fn update_properties(&self, args: &BluetoothLEAdvertisementReceivedEventArgs) {
+    pub(crate) fn update_properties(
+        &self,
+        args: &BluetoothLEAdvertisementReceivedEventArgs,
+        advertising_interval: Option<AdvertisingInterval>,
+    ) {
         let advertisement = args.Advertisement().unwrap();
+        // The events are emitted once the update is done, so they carry the new properties and
+        // no locks are held meanwhile.
+        let mut events = Vec::new();
+        *self.shared.advertising_interval.write().unwrap() = advertising_interval;
+        let packet = match args.AdvertisementType() {
+            Ok(BluetoothLEAdvertisementType::ScanResponse) => AdvertisingPacket::ScanResponse,
+            _ => AdvertisingPacket::Advertisement,
+        };
 
         // Advertisements are cumulative: set/replace data only if it's set
         if let Ok(name) = advertisement.LocalName() {
@@ -143,8 +270,22 @@ impl Peripheral {
                 // advertisement before and speculatively take a read lock
                 // to confirm that the name hasn't changed...
 
-                let mut local_name_guard = self.shared.local_name.write().unwrap();
-                *local_name_guard = Some(name.to_string());
+                let name = name.to_string();
+                let mut device_name_guard = self.shared.device_name.write().unwrap();
+                match &*device_name_guard {
+                    // Keep the device name read from the device while it advertises the same name.
+                    Some((_, advertised)) if advertised.as_ref() == Some(&name) => {}
+                    _ => {
+                        *device_name_guard = None;
+                        let source = match packet {
+                            AdvertisingPacket::Advertisement => NameSource::Advertisement,
+                            AdvertisingPacket::ScanResponse => NameSource::ScanResponse,
+                        };
+                        let mut local_name_guard = self.shared.local_name.write().unwrap();
+                        *local_name_guard = Some(name);
+                        *self.shared.local_name_source.write().unwrap() = Some(source);
+                    }
+                }
             }
         }
         if let Ok(manufacturer_data) = advertisement.ManufacturerData() {
@@ -162,7 +303,7 @@ impl Peripheral {
                     .collect();
 
                 // Emit event of newly received advertisement
-                self.emit_event(CentralEvent::ManufacturerDataAdvertisement {
+                events.push(CentralEvent::ManufacturerDataAdvertisement {
                     id: self.shared.address.into(),
                     manufacturer_data: manufacturer_data_guard.clone(),
                 });
@@ -172,6 +313,65 @@ impl Peripheral {
         // The Windows Runtime API (as of 19041) does not directly expose Service Data as a friendly API (like Manufacturer Data above)
         // Instead they provide data sections for access to raw advertising data. That is processed here.
         if let Ok(data_sections) = advertisement.DataSections() {
+            for section in &data_sections {
+                if section.DataType().unwrap() == advertisement_data_type::APPEARANCE {
+                    let data = utils::to_vec(&section.Data().unwrap());
+                    if let Some(appearance) = data.get(..2) {
+                        let mut appearance_guard = self.shared.appearance.write().unwrap();
+                        *appearance_guard =
+                            Some(u16::from_le_bytes(appearance.try_into().unwrap()));
+                    }
+                }
+            }
+
+            // The data sections are the AD structures of this packet alone, so they're kept apart
+            // from those of the other packet.
+            let all_structures: Vec<_> = (&data_sections)
+                .into_iter()
+                .filter_map(|section| {
+                    Some(AdStructure {
+                        ad_type: section.DataType().ok()?,
+                        data: utils::to_vec(&section.Data().ok()?),
+                    })
+                })
+                .collect();
+            let packet_data = match packet {
+                AdvertisingPacket::Advertisement => &self.shared.advertisement_data,
+                AdvertisingPacket::ScanResponse => &self.shared.scan_response_data,
+            };
+            *packet_data.write().unwrap() = Some(all_structures.clone());
+
+            // The other AD types Windows doesn't parse are handed to the common parser.
+            let structures: Vec<_> = all_structures
+                .into_iter()
+                .filter(|structure| {
+                    matches!(
+                        structure.ad_type,
+                        URI | LE_SUPPORTED_FEATURES
+                            | ADVERTISING_INTERVAL
+                            | ADVERTISING_INTERVAL_LONG
+                            | PUBLIC_TARGET_ADDRESS
+                    )
+                })
+                .collect();
+            if !structures.is_empty() {
+                let parsed = parse_structures(structures);
+                if parsed.uri.is_some() {
+                    *self.shared.uri.write().unwrap() = parsed.uri;
+                }
+                if parsed.le_supported_features.is_some() {
+                    *self.shared.le_supported_features.write().unwrap() =
+                        parsed.le_supported_features;
+                }
+                if parsed.advertising_interval.is_some() {
+                    *self.shared.advertised_interval.write().unwrap() = parsed.advertising_interval;
+                }
+                if !parsed.public_target_addresses.is_empty() {
+                    *self.shared.public_target_addresses.write().unwrap() =
+                        parsed.public_target_addresses;
+                }
+            }
+
             // See if we have any advertised service data before taking a lock to update...
             let mut found_service_data = false;
             for section in &data_sections {
@@ -217,7 +417,7 @@ impl Peripheral {
                     .collect();
 
                 // Emit event of newly received advertisement
-                self.emit_event(CentralEvent::ServiceDataAdvertisement {
+                events.push(CentralEvent::ServiceDataAdvertisement {
                     id: self.shared.address.into(),
                     service_data: service_data_guard.clone(),
                 });
@@ -253,7 +453,7 @@ impl Peripheral {
                     services_guard.insert(utils::to_uuid(&uuid));
                 }
 
-                self.emit_event(CentralEvent::ServicesAdvertisement {
+                events.push(CentralEvent::ServicesAdvertisement {
                     id: self.shared.address.into(),
                     services: services_guard.iter().copied().collect(),
                 });
@@ -283,11 +483,58 @@ impl Peripheral {
             let mut rssi_guard = self.shared.last_rssi.write().unwrap();
             *rssi_guard = Some(rssi);
         }
+        self.invalidate_properties();
+        for event in events {
+            self.emit_event(event);
+        }
+    }
+
+    /// Looks up the WinRT object of a characteristic discovered on the current connection. The
+    /// object is cloned out of the cache, so no lock on it is held while the operation is pending.
+    fn gatt_characteristic(&self, characteristic: &Characteristic) -> Result<GattCharacteristic> {
+        let ble_service = self
+            .shared
+            .ble_services
+            .get(&characteristic.service_uuid)
+            .ok_or(Error::NoSuchService)?;
+        ble_service
+            .characteristics
+            .get(&characteristic.uuid)
+            .map(BLECharacteristic::gatt_characteristic)
+            .ok_or(Error::NoSuchCharacteristic)
+    }
+
+    /// Like [`gatt_characteristic`](Self::gatt_characteristic), for descriptors.
+    fn ble_descriptor(&self, descriptor: &Descriptor) -> Result<BLEDescriptor> {
+        let ble_service = self
+            .shared
+            .ble_services
+            .get(&descriptor.service_uuid)
+            .ok_or(Error::NoSuchService)?;
+        let ble_characteristic = ble_service
+            .characteristics
+            .get(&descriptor.characteristic_uuid)
+            .ok_or(Error::NoSuchCharacteristic)?;
+        ble_characteristic
+            .descriptors
+            .get(&descriptor.uuid)
+            .cloned()
+            .ok_or(Error::NoSuchDescriptor)
+    }
+
+    fn interceptors(&self) -> Interceptors {
+        Interceptors::new(
+            self.shared
+                .adapter
+                .upgrade()
+                .map(|manager| manager.interceptors().clone()),
+            self.shared.interceptors.clone(),
+        )
     }
 
     fn emit_event(&self, event: CentralEvent) {
         if let Some(manager) = self.shared.adapter.upgrade() {
-            manager.emit(event);
+            manager.emit_with_properties(event, Some(&self.derive_properties()));
         } else {
             trace!("Could not emit an event. AdapterManager has been dropped");
         }
@@ -345,10 +592,28 @@ impl ApiPeripheral for Peripheral {
 
     /// Returns the set of properties associated with the peripheral. These may be updated over time
     /// as additional advertising reports are received.
-    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+    async fn properties(&self) -> Result<Option<Arc<PeripheralProperties>>> {
+        if !self
+            .shared
+            .device_properties_fetched
+            .swap(true, Ordering::Relaxed)
+        {
+            match BLEDevice::device_properties(self.shared.address).await {
+                Ok(properties) => self.apply_device_properties(properties),
+                Err(err) => trace!(
+                    "No device properties for {}: {:?}",
+                    self.shared.address,
+                    err
+                ),
+            }
+        }
         Ok(Some(self.derive_properties()))
     }
 
+    fn cached_properties(&self) -> Option<Arc<PeripheralProperties>> {
+        Some(self.derive_properties())
+    }
+
     fn services(&self) -> BTreeSet<Service> {
         self.shared
             .ble_services
@@ -362,19 +627,35 @@ impl ApiPeripheral for Peripheral {
         Ok(self.shared.connected.load(Ordering::Relaxed))
     }
 
+    async fn is_paired(&self) -> Result<bool> {
+        let properties = BLEDevice::device_properties(self.shared.address).await?;
+        let is_paired = properties.is_paired;
+        self.apply_device_properties(properties);
+        is_paired.ok_or_else(|| Error::Other("Windows didn't report the pairing state".into()))
+    }
+
     async fn mtu(&self, _characteristics: Option<&[Characteristic]>) -> Result<u16> {
-        let device = self.shared.device.lock().await;
-        if let Some(ref device) = *device {
+        let mut device = self.shared.device.lock().await;
+        if let Some(ref mut device) = *device {
             return device.mtu().await;
         }
         Err(Error::NotConnected)
     }
 
+    async fn update_connection_parameters(&self, params: ConnectionParams) -> Result<()> {
+        let mut device = self.shared.device.lock().await;
+        if let Some(ref mut device) = *device {
+            return device.request_connection_priority(params.priority());
+        }
+        Err(Error::NotConnected)
+    }
+
     /// Creates a connection to the device. This is a synchronous operation; if this method returns
     /// Ok there has been successful connection. Note that peripherals allow only one connection at
     /// a time. Operations that attempt to communicate with a device will fail until it is connected.
     async fn connect(&self) -> Result<()> {
         let shared_clone = Arc::downgrade(&self.shared);
+        let services_shared = shared_clone.clone();
         let adapter_clone = self.shared.adapter.clone();
         let address = self.shared.address;
         let device = BLEDevice::new(
@@ -382,6 +663,10 @@ impl ApiPeripheral for Peripheral {
             Box::new(move |is_connected| {
                 if let Some(shared) = shared_clone.upgrade() {
                     shared.connected.store(is_connected, Ordering::Relaxed);
+                    if !is_connected {
+                        // End all notification streams.
+                        let _ = shared.notifications_channel.send(None);
+                    }
                 }
 
                 if !is_connected {
@@ -390,17 +675,51 @@ impl ApiPeripheral for Peripheral {
                     }
                 }
             }),
+            Box::new(move || {
+                if let Some(shared) = services_shared.upgrade() {
+                    // The cached GATT objects may belong to services which are gone, so they're
+                    // looked up again by the next discover_services.
+                    shared.ble_services.clear();
+                    if let Some(adapter) = shared.adapter.upgrade() {
+                        adapter.emit(CentralEvent::ServicesChanged(address.into()));
+                    }
+                }
+            }),
         )
         .await?;
 
         device.connect().await?;
+        self.apply_device_properties(device.properties());
         let mut d = self.shared.device.lock().await;
+        // The cached GATT objects belong to the services of the device being replaced, which are
+        // closed when it is dropped.
+        self.shared.ble_services.clear();
         *d = Some(device);
         self.shared.connected.store(true, Ordering::Relaxed);
         self.emit_event(CentralEvent::DeviceConnected(self.shared.address.into()));
         Ok(())
     }
 
+    async fn connect_with(&self, options: ConnectOptions) -> Result<()> {
+        // A BluetoothLEDevice is only ever connected over LE, on the PHYs Windows picks.
+        if options.transport == Some(ConnectTransport::BrEdr) {
+            return Err(Error::NotSupported {
+                feature: Feature::ConnectTransport,
+            });
+        }
+        if options.auto_connect == Some(true) {
+            return Err(Error::NotSupported {
+                feature: Feature::AutoConnect,
+            });
+        }
+        if options.phy.is_some() {
+            return Err(Error::NotSupported {
+                feature: Feature::ConnectPhy,
+            });
+        }
+        crate::common::connect::with_timeout(self, options.timeout, self.connect()).await
+    }
+
     /// Terminates a connection to the device. This is a synchronous operation.
     async fn disconnect(&self) -> Result<()> {
         // We need to clear the services because if this device is re-connected,
@@ -409,6 +728,7 @@ impl ApiPeripheral for Peripheral {
         let mut device = self.shared.device.lock().await;
         *device = None;
         self.shared.connected.store(false, Ordering::Relaxed);
+        let _ = self.shared.notifications_channel.send(None);
         self.emit_event(CentralEvent::DeviceDisconnected(self.shared.address.into()));
         Ok(())
     }
@@ -491,16 +811,20 @@ impl ApiPeripheral for Peripheral {
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        let ble_service = &*self
-            .shared
-            .ble_services
-            .get(&characteristic.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for write".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get(&characteristic.uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for write".into()))?;
-        ble_characteristic.write_value(data, write_type).await
+        let gatt_characteristic = self.gatt_characteristic(characteristic)?;
+        let id = PeripheralId(self.shared.address);
+        let request = GattRequest::characteristic(&id, characteristic, write_type.into());
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                gatt_log::log_traffic(
+                    &PeripheralId(self.shared.address),
+                    characteristic.uuid,
+                    write_type.into(),
+                    &data,
+                );
+                BLECharacteristic::write_value(&gatt_characteristic, &data, write_type).await
+            })
+            .await
     }
 
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
@@ -510,19 +834,33 @@ impl ApiPeripheral for Peripheral {
             .shared
             .ble_services
             .get_mut(&characteristic.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for subscribe".into()))?;
+            .ok_or(Error::NoSuchService)?;
         let ble_characteristic = ble_service
             .characteristics
             .get_mut(&characteristic.uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for subscribe".into()))?;
+            .ok_or(Error::NoSuchCharacteristic)?;
         let notifications_sender = self.shared.notifications_channel.clone();
+        let value_cache = self.shared.value_cache.clone();
+        let id = PeripheralId(self.shared.address);
         let uuid = characteristic.uuid;
+        let service_uuid = characteristic.service_uuid;
+        let key = CharacteristicKey::from(characteristic);
+        // WinRT enables indications if the characteristic supports both.
+        let is_indication = characteristic.properties.contains(CharPropFlags::INDICATE);
         ble_characteristic
             .subscribe(Box::new(move |value| {
-                let notification = ValueNotification { uuid, value };
+                gatt_log::log_traffic(&id, uuid, GattOperation::Notification, &value);
+                value_cache.insert(key, value.clone());
+                let notification = ValueNotification {
+                    uuid,
+                    service_uuid,
+                    handle: None,
+                    value,
+                    is_indication,
+                };
                 // Note: we ignore send errors here which may happen while there are no
                 // receivers...
-                let _ = notifications_sender.send(notification);
+                let _ = notifications_sender.send(Some(notification));
             }))
             .await
     }
@@ -534,66 +872,112 @@ impl ApiPeripheral for Peripheral {
             .shared
             .ble_services
             .get_mut(&characteristic.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for unsubscribe".into()))?;
+            .ok_or(Error::NoSuchService)?;
         let ble_characteristic = ble_service
             .characteristics
             .get_mut(&characteristic.uuid)
-            .ok_or_else(|| {
-                Error::NotSupported("Characteristic not found for unsubscribe".into())
-            })?;
+            .ok_or(Error::NoSuchCharacteristic)?;
         ble_characteristic.unsubscribe().await
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        let ble_service = &*self
-            .shared
-            .ble_services
-            .get(&characteristic.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for read".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get(&characteristic.uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for read".into()))?;
-        ble_characteristic.read_value().await
+        let gatt_characteristic = self.gatt_characteristic(characteristic)?;
+        let id = PeripheralId(self.shared.address);
+        let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+        self.interceptors()
+            .read(&request, async {
+                let value = BLECharacteristic::read_value(&gatt_characteristic).await?;
+                gatt_log::log_traffic(&id, characteristic.uuid, GattOperation::Read, &value);
+                self.shared
+                    .value_cache
+                    .insert(characteristic, value.clone());
+                Ok(value)
+            })
+            .await
+    }
+
+    async fn read_cached(
+        &self,
+        characteristic: &Characteristic,
+        max_age: Duration,
+    ) -> Result<Vec<u8>> {
+        if let Some(value) = self.shared.value_cache.get(characteristic, max_age) {
+            // The cache holds values as received, so they go through the interceptors again.
+            let id = PeripheralId(self.shared.address);
+            let request = GattRequest::characteristic(&id, characteristic, GattOperation::Read);
+            return self
+                .interceptors()
+                .read(&request, async { Ok(value) })
+                .await;
+        }
+        self.read(characteristic).await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        let name = crate::api::read_gap_device_name(self).await?;
+        {
+            let mut device_name_guard = self.shared.device_name.write().unwrap();
+            let mut local_name_guard = self.shared.local_name.write().unwrap();
+            let mut source_guard = self.shared.local_name_source.write().unwrap();
+            let advertised = match *source_guard {
+                Some(NameSource::DeviceName) => device_name_guard
+                    .take()
+                    .and_then(|(_, advertised)| advertised),
+                _ => local_name_guard.clone(),
+            };
+            *device_name_guard = Some((name.clone(), advertised));
+            *local_name_guard = Some(name.clone());
+            *source_guard = Some(NameSource::DeviceName);
+        }
+        self.invalidate_properties();
+        Ok(name)
     }
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
         let receiver = self.shared.notifications_channel.subscribe();
-        Ok(notifications_stream_from_broadcast_receiver(receiver))
+        Ok(self.interceptors().notifications(
+            PeripheralId(self.shared.address),
+            notifications_stream_from_broadcast_receiver(receiver),
+        ))
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
-        let ble_service = &*self
-            .shared
-            .ble_services
-            .get(&descriptor.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for write".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get(&descriptor.characteristic_uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for write".into()))?;
-        let ble_descriptor = ble_characteristic
-            .descriptors
-            .get(&descriptor.uuid)
-            .ok_or_else(|| Error::NotSupported("Descriptor not found for write".into()))?;
-        ble_descriptor.write_value(data).await
+        let ble_descriptor = self.ble_descriptor(descriptor)?;
+        let id = PeripheralId(self.shared.address);
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::WriteDescriptor);
+        self.interceptors()
+            .write(&request, data, |data| async move {
+                gatt_log::log_traffic(
+                    &PeripheralId(self.shared.address),
+                    descriptor.uuid,
+                    GattOperation::WriteDescriptor,
+                    &data,
+                );
+                ble_descriptor.write_value(&data).await
+            })
+            .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
-        let ble_service = &*self
-            .shared
-            .ble_services
-            .get(&descriptor.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for read".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get(&descriptor.characteristic_uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for read".into()))?;
-        let ble_descriptor = ble_characteristic
-            .descriptors
-            .get(&descriptor.uuid)
-            .ok_or_else(|| Error::NotSupported("Descriptor not found for write".into()))?;
-        ble_descriptor.read_value().await
+        let ble_descriptor = self.ble_descriptor(descriptor)?;
+        let id = PeripheralId(self.shared.address);
+        let request = GattRequest::descriptor(&id, descriptor, GattOperation::ReadDescriptor);
+        self.interceptors()
+            .read(&request, async {
+                let value = ble_descriptor.read_value().await?;
+                gatt_log::log_traffic(&id, descriptor.uuid, GattOperation::ReadDescriptor, &value);
+                Ok(value)
+            })
+            .await
+    }
+
+    fn add_interceptor(&self, interceptor: Arc<dyn GattInterceptor>) -> Result<()> {
+        self.shared.interceptors.add(interceptor);
+        Ok(())
+    }
+
+    fn remove_interceptor(&self, interceptor: &Arc<dyn GattInterceptor>) -> Result<bool> {
+        Ok(self.shared.interceptors.remove(interceptor))
     }
 }
 
@@ -20,20 +20,30 @@ use windows::{
         GattCharacteristicProperties, GattClientCharacteristicConfigurationDescriptorValue,
         GattCommunicationStatus,
     },
-    Storage::Streams::{DataReader, IBuffer},
+    Foundation::IReference,
+    Storage::Streams::{DataReader, DataWriter, IBuffer},
 };
 
-pub fn to_error(status: GattCommunicationStatus) -> Result<()> {
+/// Converts the status of a GATT operation into a `Result`. `protocol_error` is the
+/// `ProtocolError` property of the operation's result, which holds the ATT error code.
+pub fn to_error(
+    status: GattCommunicationStatus,
+    protocol_error: windows::core::Result<IReference<u8>>,
+) -> Result<()> {
     if status == GattCommunicationStatus::AccessDenied {
-        Err(Error::PermissionDenied)
+        Err(Error::AccessDenied)
     } else if status == GattCommunicationStatus::Unreachable {
-        Err(Error::NotConnected)
+        Err(Error::Unreachable)
     } else if status == GattCommunicationStatus::Success {
         Ok(())
     } else if status == GattCommunicationStatus::ProtocolError {
-        Err(Error::NotSupported("ProtocolError".to_string()))
+        Err(Error::ProtocolError(
+            protocol_error.and_then(|code| code.Value()).ok(),
+        ))
     } else {
-        Err(Error::Other("Communication Error:".to_string().into()))
+        Err(Error::Other(
+            format!("Communication Error: {:?}", status).into(),
+        ))
     }
 }
 
@@ -64,6 +74,12 @@ pub fn to_vec(buffer: &IBuffer) -> Vec<u8> {
     data
 }
 
+pub fn to_buffer(data: &[u8]) -> Result<IBuffer> {
+    let writer = DataWriter::new()?;
+    writer.WriteBytes(data)?;
+    Ok(writer.DetachBuffer()?)
+}
+
 #[allow(dead_code)]
 pub fn to_guid(uuid: &Uuid) -> GUID {
     let (data1, data2, data3, data4) = uuid.as_fields();